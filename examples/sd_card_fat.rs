@@ -0,0 +1,127 @@
+//! This example demonstrates logging to a file on a FAT-formatted SD card, connected over SPI.
+//! It uses the `embedded-sdmmc` crate for the filesystem, through `block_device::SdmmcAdapter`,
+//! and timestamps files with the RTC. Requires the `embedded-sdmmc` feature.
+
+//! For project structure and debugging boilerplate, see the `synax_overview` example.
+
+#![no_main]
+#![no_std]
+
+use core::{cell::RefCell, fmt::Write};
+
+use cortex_m_rt::entry;
+
+use embedded_sdmmc::{Mode, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+
+use stm32_hal2::{
+    block_device::SdmmcAdapter,
+    clocks::Clocks,
+    delay,
+    gpio::{Pin, PinMode, Port},
+    pac,
+    rtc::{Rtc, RtcClockSource, RtcConfig},
+    sd_spi::SdSpi,
+    spi::{BaudRate, Spi, SpiConfig},
+};
+
+/// Timestamps files with the RTC's date and time. `TimeSource` takes `&self`, and reading the RTC
+/// takes `&mut self`, so we wrap it in a `RefCell`.
+struct RtcTime(RefCell<Rtc>);
+
+impl TimeSource for RtcTime {
+    fn get_timestamp(&self) -> Timestamp {
+        let mut rtc = self.0.borrow_mut();
+
+        Timestamp::from_calendar(
+            rtc.get_year(),
+            rtc.get_month(),
+            rtc.get_day(),
+            rtc.get_hours(),
+            rtc.get_minutes(),
+            rtc.get_seconds(),
+        )
+        // 1980-01-01, the earliest FAT timestamp, if the RTC hasn't been set.
+        .unwrap_or(Timestamp::from_fat(0, 0))
+    }
+}
+
+/// A fixed-size buffer for formatting a line of text.
+struct Line {
+    buf: [u8; 64],
+    len: usize,
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    // Set up microcontroller peripherals
+    let dp = pac::Peripherals::take().unwrap();
+
+    let clock_cfg = Clocks::default();
+    clock_cfg.setup().unwrap();
+
+    let rtc = Rtc::new(
+        dp.RTC,
+        RtcConfig {
+            clock_source: RtcClockSource::Lse,
+            ..Default::default()
+        },
+    );
+
+    // Configure pins for SPI1. Most SD card modules need pull-ups on MISO; many include them.
+    let _sck = Pin::new(Port::A, 5, PinMode::Alt(5));
+    let _miso = Pin::new(Port::A, 6, PinMode::Alt(5));
+    let _mosi = Pin::new(Port::A, 7, PinMode::Alt(5));
+    let cs = Pin::new(Port::A, 4, PinMode::Output);
+
+    // Cards start in SD mode; `init` sends them the SPI mode command at 100 - 400kHz, then
+    // reclocks the SPI. Eg 80Mhz APB clock / 256 = 312.5kHz, then / 4 = 20Mhz.
+    let spi = Spi::new(dp.SPI1, SpiConfig::default(), BaudRate::Div256);
+    let mut sd = SdSpi::new(spi, cs);
+    sd.init(BaudRate::Div256, BaudRate::Div4).unwrap();
+
+    let volume_mgr = VolumeManager::new(SdmmcAdapter::new(sd), RtcTime(RefCell::new(rtc)));
+
+    // The first partition on the card, and its root directory.
+    let volume = volume_mgr.open_volume(VolumeIdx(0)).unwrap();
+    let root_dir = volume.open_root_dir().unwrap();
+
+    let file = root_dir
+        .open_file_in_dir("LOG.CSV", Mode::ReadWriteCreateOrAppend)
+        .unwrap();
+    file.write(b"sample,uptime_s\n").unwrap();
+
+    let mut sample = 0;
+    loop {
+        let mut line = Line {
+            buf: [0; 64],
+            len: 0,
+        };
+        writeln!(line, "{},{}", sample, sample).ok();
+        file.write(&line.buf[..line.len]).unwrap();
+
+        // Flushing updates the file's size in its directory entry, so little is lost if power is
+        // removed. Each flush writes several blocks; for fast logging, flush less often.
+        if sample % 10 == 0 {
+            file.flush().unwrap();
+        }
+
+        sample += 1;
+        delay::delay_ms(1_000);
+    }
+}
+
+// same panicking *behavior* as `panic-probe` but doesn't print a panic message
+// this prevents the panic message being printed *twice* when `defmt::panic` is invoked
+#[defmt::panic_handler]
+fn panic() -> ! {
+    cortex_m::asm::udf()
+}
@@ -1,6 +1,6 @@
 //! Support for the ADC (Analog to Digital Converter) peripheral.
 
-use cortex_m::{asm, interrupt::free};
+use cortex_m::asm;
 
 #[cfg(feature = "embedded-hal")]
 use embedded_hal::adc::{Channel, OneShot};
@@ -8,9 +8,11 @@ use embedded_hal::adc::{Channel, OneShot};
 use core::{ops::Deref, ptr};
 
 use crate::{
+    analog::{self, AnalogSignal},
     clocks::Clocks,
     pac::{self, RCC},
-    rcc_en_reset,
+    rcc_disable, rcc_en_reset,
+    util::free,
 };
 
 use cfg_if::cfg_if;
@@ -110,7 +112,7 @@ pub enum AdcInterrupt {
 /// There is always an overhead of 13 ADC clock cycles.
 /// E.g. For Sampletime T_19 the total conversion time (in ADC clock cycles) is
 /// 13 + 19 = 32 ADC Clock Cycles
-/// [derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum SampleTime {
     /// 1.5 ADC clock cycles
@@ -131,6 +133,72 @@ pub enum SampleTime {
     T601 = 0b111,
 }
 
+// ADC input model from the datasheets' ADC characteristics tables: The sampling capacitor
+// (`C_ADC`), and the sampling switch resistance (`R_ADC`). Values are in pF and kΩ.
+cfg_if! {
+    if #[cfg(feature = "f4")] {
+        const C_ADC: f32 = 4.;
+        const R_ADC: f32 = 6.;
+    } else if #[cfg(feature = "h7")] {
+        const C_ADC: f32 = 4.;
+        const R_ADC: f32 = 1.;
+    } else {
+        const C_ADC: f32 = 5.;
+        const R_ADC: f32 = 1.;
+    }
+}
+
+impl SampleTime {
+    const ALL: [Self; 8] = [
+        Self::T1,
+        Self::T2,
+        Self::T4,
+        Self::T7,
+        Self::T19,
+        Self::T61,
+        Self::T181,
+        Self::T601,
+    ];
+
+    /// The sampling time in ADC clock cycles, for this family. The variant names are the F3
+    /// values.
+    pub fn cycles(&self) -> f32 {
+        let i = *self as usize;
+
+        cfg_if! {
+            if #[cfg(feature = "f373")] {
+                [1.5, 7.5, 13.5, 28.5, 41.5, 55.5, 71.5, 239.5][i]
+            } else if #[cfg(feature = "f3")] {
+                [1.5, 2.5, 4.5, 7.5, 19.5, 61.5, 181.5, 601.5][i]
+            } else if #[cfg(feature = "f4")] {
+                [3., 15., 28., 56., 84., 112., 144., 480.][i]
+            } else if #[cfg(any(feature = "g0", feature = "wl"))] {
+                [1.5, 3.5, 7.5, 12.5, 19.5, 39.5, 79.5, 160.5][i]
+            } else if #[cfg(feature = "h7")] {
+                [1.5, 2.5, 8.5, 16.5, 32.5, 64.5, 387.5, 810.5][i]
+            } else {
+                [2.5, 6.5, 12.5, 24.5, 47.5, 92.5, 247.5, 640.5][i]
+            }
+        }
+    }
+
+    /// Select the shortest sample time that lets the sampling capacitor settle to within 1/4 LSB
+    /// through a given source impedance. Too short a sample time makes readings low and noisy.
+    /// `adc_clock` is the ADC kernel clock, in Hz; `resolution` is in bits (eg 12), and
+    /// `source_impedance` is in kΩ, including any series resistor. Uses the datasheets'
+    /// `R_AIN` max formula: `R_AIN < (k - 0.5) / (f_ADC * C_ADC * ln(2^(N+2))) - R_ADC`. Returns
+    /// `None` if even the longest sample time is too short; buffer the signal, eg with an op-amp,
+    /// or lower the ADC clock.
+    pub fn from_impedance(adc_clock: u32, resolution: u8, source_impedance: f32) -> Option<Self> {
+        // kΩ * pF = ns.
+        let settle_time =
+            (source_impedance + R_ADC) * C_ADC * (resolution as f32 + 2.) * core::f32::consts::LN_2;
+        let min_cycles = settle_time * adc_clock as f32 / 1_000_000_000. + 0.5;
+
+        Self::ALL.iter().find(|t| t.cycles() >= min_cycles).copied()
+    }
+}
+
 impl Default for SampleTime {
     /// T_1 is also the reset value.
     fn default() -> Self {
@@ -155,6 +223,121 @@ pub enum OperationMode {
     Continuous = 1,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The edge of an external trigger that starts a conversion. Sets `ADC_CFGR` register, `EXTEN` field.
+pub enum TriggerEdge {
+    /// Hardware trigger detection disabled; conversions are started by software.
+    Disabled = 0b00,
+    Rising = 0b01,
+    Falling = 0b10,
+    Both = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// An external trigger source for regular conversions. Timer `Cc` sources are capture/compare
+/// events (eg PWM edges), and `Trgo` sources are timer trigger outputs, which can be configured to
+/// fire on each update event, for sampling at a precise rate. Not all sources are available on all
+/// ADCs; see the `External triggers for regular channels` table in the reference manual.
+pub enum AdcTrigger {
+    Tim1Cc1,
+    Tim1Cc2,
+    Tim1Cc3,
+    Tim2Cc1,
+    Tim2Cc2,
+    Tim2Cc3,
+    Tim3Cc1,
+    Tim3Cc4,
+    Tim4Cc1,
+    Tim4Cc4,
+    Tim8Cc1,
+    Tim1Trgo,
+    Tim1Trgo2,
+    Tim2Trgo,
+    Tim3Trgo,
+    Tim4Trgo,
+    Tim6Trgo,
+    Tim7Trgo,
+    Tim8Trgo,
+    Tim8Trgo2,
+    Tim15Trgo,
+    Exti2,
+    Exti11,
+}
+
+impl AdcTrigger {
+    /// The `EXTSEL` field value for this trigger, on a given ADC. Returns `None` if the trigger
+    /// isn't available on it. On F3 and G4, ADC3 and higher use a different table from ADC1 and 2.
+    fn extsel(&self, device: AdcDevice) -> Option<u8> {
+        #[cfg(any(feature = "f3", feature = "g4"))]
+        let alt_table = !matches!(device, AdcDevice::One | AdcDevice::Two);
+        #[cfg(not(any(feature = "f3", feature = "g4")))]
+        let alt_table = {
+            let _ = device;
+            false
+        };
+
+        let val = if alt_table {
+            // F303 RM, Table 90, and G4 RM, Table 164: ADC3/4/5 external triggers for regular channels.
+            match self {
+                Self::Tim3Cc1 => 0,
+                Self::Tim2Cc3 => 1,
+                Self::Tim1Cc3 => 2,
+                Self::Tim8Cc1 => 3,
+                #[cfg(feature = "f3")]
+                Self::Tim8Trgo => 4,
+                #[cfg(feature = "g4")]
+                Self::Tim3Trgo => 4,
+                Self::Exti2 => 5,
+                Self::Tim4Cc1 => 6,
+                #[cfg(feature = "f3")]
+                Self::Tim2Trgo => 7,
+                #[cfg(feature = "g4")]
+                Self::Tim8Trgo => 7,
+                Self::Tim8Trgo2 => 8,
+                Self::Tim1Trgo => 9,
+                Self::Tim1Trgo2 => 10,
+                #[cfg(feature = "f3")]
+                Self::Tim3Trgo => 11,
+                #[cfg(feature = "g4")]
+                Self::Tim2Trgo => 11,
+                Self::Tim4Trgo => 12,
+                #[cfg(feature = "f3")]
+                Self::Tim7Trgo => 13,
+                #[cfg(feature = "g4")]
+                Self::Tim6Trgo => 13,
+                Self::Tim15Trgo => 14,
+                Self::Tim2Cc1 => 15,
+                _ => return None,
+            }
+        } else {
+            // L4 RM, Table 96: ADC1, ADC2 and ADC3 - External triggers for regular channels. This is the
+            // same on F3 and G4 (ADC1 and 2), L5, and H7.
+            match self {
+                Self::Tim1Cc1 => 0,
+                Self::Tim1Cc2 => 1,
+                Self::Tim1Cc3 => 2,
+                Self::Tim2Cc2 => 3,
+                Self::Tim3Trgo => 4,
+                Self::Tim4Cc4 => 5,
+                Self::Exti11 => 6,
+                Self::Tim8Trgo => 7,
+                Self::Tim8Trgo2 => 8,
+                Self::Tim1Trgo => 9,
+                Self::Tim1Trgo2 => 10,
+                Self::Tim2Trgo => 11,
+                Self::Tim4Trgo => 12,
+                Self::Tim6Trgo => 13,
+                Self::Tim15Trgo => 14,
+                Self::Tim3Cc4 => 15,
+                _ => return None,
+            }
+        };
+
+        Some(val)
+    }
+}
+
 // todo: Check the diff ways of configuring clock; i don't think teh enum below covers all.(?)
 
 #[derive(Clone, Copy, PartialEq)]
@@ -511,6 +694,99 @@ macro_rules! hal {
                 }
             }
 
+            /// Power down the ADC between measurements, eg for battery-powered devices that sample
+            /// intermittently. Disables the ADC, and its voltage regulator. If `deep` is `true`, also
+            /// enters Deep-power-down mode (`ADC_CR` register, `DEEPPWD` field), which has the lowest
+            /// leakage, but loses the internal calibration. (F3 doesn't have this mode; `deep` is
+            /// ignored there.) Use `power_up` to resume.
+            pub fn power_down(&mut self, deep: bool) {
+                self.disable();
+                while self.is_enabled() {}
+
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        let _ = deep;
+                        self.advregen_disable();
+                    } else {
+                        if deep {
+                            // Writing DEEPPWD=1 automatically disables the ADC voltage regulator.
+                            self.regs.cr.modify(|_, w| w.deeppwd().set_bit());
+                        } else {
+                            // RM: "When the internal voltage regulator is disabled (ADVREGEN=0), the
+                            // internal analog calibration is kept."
+                            self.regs.cr.modify(|_, w| w.advregen().clear_bit());
+                        }
+                    }
+                }
+            }
+
+            /// Power up the ADC after `power_down`: Exits Deep-power-down mode if required, enables the
+            /// voltage regulator and waits for it to start, restores the calibration if it was lost,
+            /// then enables the ADC.
+            pub fn power_up(&mut self, clocks: &Clocks) {
+                #[cfg(feature = "f3")]
+                let cal_lost = false;
+                #[cfg(not(feature = "f3"))]
+                let cal_lost = self.regs.cr.read().deeppwd().bit_is_set();
+
+                self.advregen_enable(clocks);
+
+                if cal_lost {
+                    if self.cfg.cal_single_ended.is_some() || self.cfg.cal_differential.is_some() {
+                        // This enables the ADC.
+                        self.inject_calibration();
+                        return;
+                    }
+                    self.calibrate(InputType::SingleEnded, clocks);
+                    self.calibrate(InputType::Differential, clocks);
+                }
+
+                if !self.is_enabled() {
+                    self.enable();
+                }
+            }
+
+            /// Enable or disable auto-delayed conversion mode. In this mode, a new conversion only
+            /// starts once the previous result has been read, which avoids overruns, and saves power
+            /// when the application reads data slowly. Sets `ADC_CFGR` register, `AUTDLY` field.
+            /// (This is the equivalent of the `WAIT` field on G0, whose ADC isn't yet supported by this
+            /// module.)
+            pub fn set_auto_delay(&mut self, enabled: bool) {
+                // RM: "The software is allowed to write this bit only when ADSTART=0 and JADSTART=0."
+                self.stop_conversions();
+                self.regs.cfgr.modify(|_, w| w.autdly().bit(enabled));
+            }
+
+            /// Disable the ADC and its voltage regulator, and return the PAC register block. If
+            /// `gate_clock` is `true`, also disable its RCC peripheral clock. Note that on L4, L5 and G0,
+            /// this clock is shared between all ADCs.
+            pub fn free(mut self, gate_clock: bool) -> pac::$ADC {
+                self.disable();
+                self.advregen_disable();
+
+                if gate_clock {
+                    free(|_| {
+                        let rcc = unsafe { &(*RCC::ptr()) };
+
+                        paste! {
+                            cfg_if! {
+                                if #[cfg(any(feature = "f3", feature = "h7"))] {
+                                    rcc_disable!(ahb1, [<adc $rcc_num>], rcc);
+                                } else if #[cfg(feature = "f4")] {
+                                    rcc_disable!(apb2, [<adc $rcc_num>], rcc);
+                                } else if #[cfg(any(feature = "g4"))] {
+                                    rcc_disable!(ahb2, [<adc $rcc_num>], rcc);
+                                } else {  // ie L4, L5, G0(?)
+                                    rcc_disable!(ahb2, adc, rcc);
+                                }
+                            }
+                        }
+                    });
+                }
+
+                self.regs
+            }
+
             /// Wait for the advregen to startup.
             ///
             /// This is based on the MAX_ADVREGEN_STARTUP_US of the device.
@@ -694,6 +970,81 @@ macro_rules! hal {
                 }
             }
 
+            /// Enable or disable an internal signal's connection to the ADCs; find the channel to read it
+            /// on with `analog::adc_channel`. Sets the `CCR` register, `VREFEN`, and temperature sensor
+            /// and VBAT enable fields, or on G4, `OPAMPx_CSR` register, `OPAINTOEN` field. DAC outputs
+            /// don't need enabling here.
+            pub fn enable_internal_signal(&mut self, signal: AnalogSignal, enabled: bool) {
+                let common_regs = unsafe { &*pac::$ADC_COMMON::ptr() };
+
+                match signal {
+                    AnalogSignal::VRefInt => common_regs.ccr.modify(|_, w| w.vrefen().bit(enabled)),
+                    AnalogSignal::TempSensor => {
+                        cfg_if! {
+                            if #[cfg(feature = "f3")] {
+                                common_regs.ccr.modify(|_, w| w.tsen().bit(enabled));
+                            } else if #[cfg(feature = "g4")] {
+                                common_regs.ccr.modify(|_, w| w.vsensesel().bit(enabled));
+                            } else if #[cfg(feature = "h7")] {
+                                common_regs.ccr.modify(|_, w| w.vsenseen().bit(enabled));
+                            } else {
+                                common_regs.ccr.modify(|_, w| w.ch17sel().bit(enabled));
+                            }
+                        }
+                    }
+                    AnalogSignal::VBat => {
+                        cfg_if! {
+                            if #[cfg(feature = "g4")] {
+                                common_regs.ccr.modify(|_, w| w.vbatsel().bit(enabled));
+                            } else if #[cfg(any(feature = "f3", feature = "h7"))] {
+                                common_regs.ccr.modify(|_, w| w.vbaten().bit(enabled));
+                            } else {
+                                common_regs.ccr.modify(|_, w| w.ch18sel().bit(enabled));
+                            }
+                        }
+                    }
+                    #[cfg(feature = "g4")]
+                    AnalogSignal::Opamp1 => analog::set_opamp_internal_output(1, enabled),
+                    #[cfg(feature = "g4")]
+                    AnalogSignal::Opamp2 => analog::set_opamp_internal_output(2, enabled),
+                    #[cfg(feature = "g4")]
+                    AnalogSignal::Opamp3 => analog::set_opamp_internal_output(3, enabled),
+                    #[cfg(feature = "g4")]
+                    AnalogSignal::Opamp4 => analog::set_opamp_internal_output(4, enabled),
+                    #[cfg(feature = "g4")]
+                    AnalogSignal::Opamp5 => analog::set_opamp_internal_output(5, enabled),
+                    #[cfg(feature = "g4")]
+                    AnalogSignal::Opamp6 => analog::set_opamp_internal_output(6, enabled),
+                    _ => (),
+                }
+            }
+
+            /// Measure the VBAT voltage, in volts, accounting for its internal divider. Returns `None`
+            /// if VBAT isn't connected to this ADC. VBAT is only connected while measuring, since the
+            /// divider draws current from it. For backup batteries, see also
+            /// `low_power::enable_vbat_charging`.
+            pub fn read_vbat(&mut self) -> Option<f32> {
+                let channel = analog::adc_channel(self.device, AnalogSignal::VBat)?;
+
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        let divider = 2.;
+                    } else if #[cfg(feature = "h7")] {
+                        let divider = 4.;
+                    } else {
+                        let divider = 3.;
+                    }
+                }
+
+                self.enable_internal_signal(AnalogSignal::VBat, true);
+                // The VBAT channel has a high source impedance; use the longest sample time.
+                self.set_sample_time(channel, SampleTime::T601);
+                let reading = self.read(channel);
+                self.enable_internal_signal(AnalogSignal::VBat, false);
+
+                Some(self.reading_to_voltage(reading) * divider)
+            }
+
             /// Set up the internal voltage reference, to improve conversion from reading
             /// to voltage accuracy. See L44 RM, section 16.4.34: "Monitoring the internal voltage reference"
             fn setup_vdda(&mut self, clock_cfg: &Clocks) {
@@ -809,12 +1160,47 @@ macro_rules! hal {
             /// Start a conversion: Either a single measurement, or continuous conversions.
             /// See L4 RM 16.4.15 for details.
             pub fn start_conversion(&mut self, sequence: &[u8], mode: OperationMode) {
+                self.begin_conversion(sequence, mode);
+
+                // After the regular sequence is complete, after each conversion is complete,
+                // the EOC (end of regular conversion) flag is set.
+                // After the regular sequence is complete: The EOS (end of regular sequence) flag is set.
+                // (We're ignoring eoc, since this module doesn't currently support sequences)
+                while self.regs.isr.read().eos().bit_is_clear() {}  // wait until complete.
+            }
+
+            /// Start regular conversions from an external trigger, eg a timer's TRGO or capture/compare
+            /// event, instead of by software. Conversions begin on each trigger edge after calling
+            /// `begin_conversion`; use `TriggerEdge::Disabled` to return to software triggering. Sets
+            /// `ADC_CFGR` register, `EXTSEL` and `EXTEN` fields. Panics if the trigger isn't available
+            /// on this ADC.
+            pub fn set_trigger(&mut self, trigger: AdcTrigger, edge: TriggerEdge) {
+                let extsel = trigger
+                    .extsel(self.device)
+                    .expect("This trigger isn't available on this ADC.");
+
+                // RM: "The software is allowed to write these bits only when ADSTART=0 (which ensures
+                // that no regular conversion is ongoing)."
+                self.stop_conversions();
+
+                self.regs.cfgr.modify(|_, w| unsafe {
+                    w.extsel().bits(extsel);
+                    w.exten().bits(edge as u8)
+                });
+            }
+
+            /// Start a conversion, without waiting for it to complete. Use this for interrupt-driven
+            /// sampling: Enable the `EndOfConversion` interrupt to read each result with `data()`, or
+            /// `EndOfSequence` to be notified when the whole sequence is complete. Sets the
+            /// sequence length to the length of `sequence`.
+            pub fn begin_conversion(&mut self, sequence: &[u8], mode: OperationMode) {
                 // Set continuous or one-shot mode.
                 self.regs.cfgr.modify(|_, w| w.cont().bit(mode as u8 != 0));
                 // todo: You should call this elsewhere, once, to prevent unneded reg writes.
                 for (i, channel) in sequence.iter().enumerate() {
                     self.set_sequence(*channel, i as u8 + 1); // + 1, since sequences start at 1.
                 }
+                self.set_sequence_len(sequence.len() as u8);
 
                 // L4 RM: In Single conversion mode, the ADC performs once all the conversions of the channels.
                 // This mode is started with the CONT bit at 0 by either:
@@ -823,12 +1209,6 @@ macro_rules! hal {
                 // • External hardware trigger event (for a regular or injected channel)
                 // (Here, we assume a regular channel)
                 self.regs.cr.modify(|_, w| w.adstart().set_bit());  // Start
-
-                // After the regular sequence is complete, after each conversion is complete,
-                // the EOC (end of regular conversion) flag is set.
-                // After the regular sequence is complete: The EOS (end of regular sequence) flag is set.
-                // (We're ignoring eoc, since this module doesn't currently support sequences)
-                while self.regs.isr.read().eos().bit_is_clear() {}  // wait until complete.
             }
 
             /// Read data from a conversion. In OneShot mode, this will generally be run right
@@ -840,6 +1220,24 @@ macro_rules! hal {
                 return self.regs.dr.read().rdata().bits() as u16;
             }
 
+            /// Read the regular data register, without waiting for a conversion. Reading this clears
+            /// the `EOC` flag. Use this in the `EndOfConversion` interrupt handler.
+            pub fn data(&self) -> u16 {
+                self.regs.dr.read().bits() as u16
+            }
+
+            /// Read an injected data register (`JDR1` - `JDR4`), by rank 1 - 4. Use this in the
+            /// `EndOfSequenceInjected` interrupt handler.
+            pub fn injected_data(&self, rank: u8) -> u16 {
+                match rank {
+                    1 => self.regs.jdr1.read().bits() as u16,
+                    2 => self.regs.jdr2.read().bits() as u16,
+                    3 => self.regs.jdr3.read().bits() as u16,
+                    4 => self.regs.jdr4.read().bits() as u16,
+                    _ => panic!("Injected rank must be 1 - 4."),
+                }
+            }
+
             /// Take a single reading, in OneShot mode
             pub fn read(&mut self, channel: u8) -> u16 {
                 self.start_conversion(&[channel], OperationMode::OneShot);
@@ -970,6 +1368,51 @@ macro_rules! hal {
                 });
             }
 
+            /// Disable a specific type of ADC interrupt.
+            pub fn disable_interrupt(&mut self, interrupt: AdcInterrupt) {
+                self.regs.ier.modify(|_, w| match interrupt {
+                    AdcInterrupt::Ready => w.adrdyie().clear_bit(),
+                    AdcInterrupt::EndOfConversion => w.eocie().clear_bit(),
+                    AdcInterrupt::EndOfSequence => w.eosie().clear_bit(),
+                    AdcInterrupt::EndofConversionInjected => w.jeocie().clear_bit(),
+                    AdcInterrupt::EndOfSequenceInjected => w.jeosie().clear_bit(),
+                    AdcInterrupt::Watchdog1 => w.awd1ie().clear_bit(),
+                    AdcInterrupt::Watchdog2 => w.awd2ie().clear_bit(),
+                    AdcInterrupt::Watchdog3 => w.awd3ie().clear_bit(),
+                    AdcInterrupt::EndOfSamplingPhase => w.eosmpie().clear_bit(),
+                    AdcInterrupt::Overrun => w.ovrie().clear_bit(),
+                    AdcInterrupt::InjectedOverflow => w.jqovfie().clear_bit(),
+                });
+            }
+
+            /// Check if an interrupt flag is set, eg to determine which event triggered the ADC
+            /// interrupt. The flags are set whether or not the interrupt is enabled. Reads the `ISR`
+            /// register. Example, in the ADC ISR:
+            /// `if adc.read_flag(AdcInterrupt::Overrun) { adc.clear_flag(AdcInterrupt::Overrun); }`
+            pub fn read_flag(&self, interrupt: AdcInterrupt) -> bool {
+                let isr = self.regs.isr.read();
+
+                match interrupt {
+                    AdcInterrupt::Ready => isr.adrdy().bit_is_set(),
+                    AdcInterrupt::EndOfConversion => isr.eoc().bit_is_set(),
+                    AdcInterrupt::EndOfSequence => isr.eos().bit_is_set(),
+                    AdcInterrupt::EndofConversionInjected => isr.jeoc().bit_is_set(),
+                    AdcInterrupt::EndOfSequenceInjected => isr.jeos().bit_is_set(),
+                    AdcInterrupt::Watchdog1 => isr.awd1().bit_is_set(),
+                    AdcInterrupt::Watchdog2 => isr.awd2().bit_is_set(),
+                    AdcInterrupt::Watchdog3 => isr.awd3().bit_is_set(),
+                    AdcInterrupt::EndOfSamplingPhase => isr.eosmp().bit_is_set(),
+                    AdcInterrupt::Overrun => isr.ovr().bit_is_set(),
+                    AdcInterrupt::InjectedOverflow => isr.jqovf().bit_is_set(),
+                }
+            }
+
+            /// Clear an interrupt flag. The same as `clear_interrupt`; provided for symmetry with
+            /// `read_flag`.
+            pub fn clear_flag(&mut self, interrupt: AdcInterrupt) {
+                self.clear_interrupt(interrupt);
+            }
+
             /// Clear an interrupt flag of the specified type. Consider running this in the
             /// corresponding ISR.
             pub fn clear_interrupt(&mut self, interrupt: AdcInterrupt) {
@@ -0,0 +1,259 @@
+//! Alternate function tables, mapping (Port, pin, AF number) to peripheral signals. Enabled with
+//! the `af-tables` feature. Used to look up AF numbers, and to check them in debug builds:
+//! `Pin::mode` asserts that an `Alt` AF number is assigned to something on that pin.
+//!
+//! Tables are from each variant's datasheet, "Alternate function mapping" table. Only ports A and
+//! B on F405 and F407 are covered. On other variants, and other pins, the lookups return `None`,
+//! and AF numbers aren't checked.
+//!
+//! Example:
+//!
+//! `let af = af::af_for(PeripheralSignal::UsartTx(1), Port::A, 9).unwrap();`
+//! `let _uart_tx = Pin::new(Port::A, 9, PinMode::Alt(af));`
+
+use cfg_if::cfg_if;
+
+use crate::gpio::Port;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+/// A peripheral signal available as an alternate function. Numbers are the peripheral's
+/// number, eg `UsartTx(2)` is USART2_TX. U[S]ARTs share numbering, eg `UsartTx(4)` is UART4_TX.
+pub enum PeripheralSignal {
+    UsartTx(u8),
+    UsartRx(u8),
+    UsartCk(u8),
+    UsartCts(u8),
+    UsartRts(u8),
+    SpiSck(u8),
+    SpiMiso(u8),
+    SpiMosi(u8),
+    SpiNss(u8),
+    I2cScl(u8),
+    I2cSda(u8),
+    I2cSmba(u8),
+    /// Timer number, and channel number.
+    TimCh(u8, u8),
+    /// Timer number, and complementary channel number.
+    TimChN(u8, u8),
+    TimEtr(u8),
+    TimBkin(u8),
+    CanRx(u8),
+    CanTx(u8),
+    /// Debug signals, eg SWDIO, SWCLK, and JTAG.
+    Debug,
+    /// The microcontroller clock output.
+    Mco,
+    /// USB OTG FS/HS data, ID, and SOF signals.
+    Usb,
+    /// A signal not otherwise listed, eg Ethernet, DCMI, SDIO, I2S extension, and FSMC signals.
+    Other,
+}
+
+/// The AF number whose signal is available on every pin: EVENTOUT, on families that have it.
+const AF_EVENTOUT: u8 = 15;
+
+cfg_if! {
+    if #[cfg(any(feature = "f405", feature = "f407"))] {
+        use PeripheralSignal::*;
+
+        /// (Port, pin, AF, signal). See the F405/F407 datasheet, Table 9: "Alternate function
+        /// mapping". EVENTOUT (AF15) is omitted.
+        const AF_TABLE: &[(Port, u8, u8, PeripheralSignal)] = &[
+            (Port::A, 0, 1, TimCh(2, 1)),
+            (Port::A, 0, 1, TimEtr(2)),
+            (Port::A, 0, 2, TimCh(5, 1)),
+            (Port::A, 0, 3, TimEtr(8)),
+            (Port::A, 0, 7, UsartCts(2)),
+            (Port::A, 0, 8, UsartTx(4)),
+            (Port::A, 0, 11, Other),
+            (Port::A, 1, 1, TimCh(2, 2)),
+            (Port::A, 1, 2, TimCh(5, 2)),
+            (Port::A, 1, 7, UsartRts(2)),
+            (Port::A, 1, 8, UsartRx(4)),
+            (Port::A, 1, 11, Other),
+            (Port::A, 2, 1, TimCh(2, 3)),
+            (Port::A, 2, 2, TimCh(5, 3)),
+            (Port::A, 2, 3, TimCh(9, 1)),
+            (Port::A, 2, 7, UsartTx(2)),
+            (Port::A, 2, 11, Other),
+            (Port::A, 3, 1, TimCh(2, 4)),
+            (Port::A, 3, 2, TimCh(5, 4)),
+            (Port::A, 3, 3, TimCh(9, 2)),
+            (Port::A, 3, 7, UsartRx(2)),
+            (Port::A, 3, 10, Usb),
+            (Port::A, 3, 11, Other),
+            (Port::A, 4, 5, SpiNss(1)),
+            (Port::A, 4, 6, SpiNss(3)),
+            (Port::A, 4, 7, UsartCk(2)),
+            (Port::A, 4, 12, Usb),
+            (Port::A, 4, 13, Other),
+            (Port::A, 5, 1, TimCh(2, 1)),
+            (Port::A, 5, 1, TimEtr(2)),
+            (Port::A, 5, 3, TimChN(8, 1)),
+            (Port::A, 5, 5, SpiSck(1)),
+            (Port::A, 5, 10, Usb),
+            (Port::A, 6, 1, TimBkin(1)),
+            (Port::A, 6, 2, TimCh(3, 1)),
+            (Port::A, 6, 3, TimBkin(8)),
+            (Port::A, 6, 5, SpiMiso(1)),
+            (Port::A, 6, 9, TimCh(13, 1)),
+            (Port::A, 6, 13, Other),
+            (Port::A, 7, 1, TimChN(1, 1)),
+            (Port::A, 7, 2, TimCh(3, 2)),
+            (Port::A, 7, 3, TimChN(8, 1)),
+            (Port::A, 7, 5, SpiMosi(1)),
+            (Port::A, 7, 9, TimCh(14, 1)),
+            (Port::A, 7, 11, Other),
+            (Port::A, 8, 0, Mco),
+            (Port::A, 8, 1, TimCh(1, 1)),
+            (Port::A, 8, 4, I2cScl(3)),
+            (Port::A, 8, 7, UsartCk(1)),
+            (Port::A, 8, 10, Usb),
+            (Port::A, 9, 1, TimCh(1, 2)),
+            (Port::A, 9, 4, I2cSmba(3)),
+            (Port::A, 9, 7, UsartTx(1)),
+            (Port::A, 9, 13, Other),
+            (Port::A, 10, 1, TimCh(1, 3)),
+            (Port::A, 10, 7, UsartRx(1)),
+            (Port::A, 10, 10, Usb),
+            (Port::A, 10, 13, Other),
+            (Port::A, 11, 1, TimCh(1, 4)),
+            (Port::A, 11, 7, UsartCts(1)),
+            (Port::A, 11, 9, CanRx(1)),
+            (Port::A, 11, 10, Usb),
+            (Port::A, 12, 1, TimEtr(1)),
+            (Port::A, 12, 7, UsartRts(1)),
+            (Port::A, 12, 9, CanTx(1)),
+            (Port::A, 12, 10, Usb),
+            (Port::A, 13, 0, Debug),
+            (Port::A, 14, 0, Debug),
+            (Port::A, 15, 0, Debug),
+            (Port::A, 15, 1, TimCh(2, 1)),
+            (Port::A, 15, 1, TimEtr(2)),
+            (Port::A, 15, 5, SpiNss(1)),
+            (Port::A, 15, 6, SpiNss(3)),
+            (Port::B, 0, 1, TimChN(1, 2)),
+            (Port::B, 0, 2, TimCh(3, 3)),
+            (Port::B, 0, 3, TimChN(8, 2)),
+            (Port::B, 0, 10, Usb),
+            (Port::B, 0, 11, Other),
+            (Port::B, 1, 1, TimChN(1, 3)),
+            (Port::B, 1, 2, TimCh(3, 4)),
+            (Port::B, 1, 3, TimChN(8, 3)),
+            (Port::B, 1, 10, Usb),
+            (Port::B, 1, 11, Other),
+            (Port::B, 3, 0, Debug),
+            (Port::B, 3, 1, TimCh(2, 2)),
+            (Port::B, 3, 5, SpiSck(1)),
+            (Port::B, 3, 6, SpiSck(3)),
+            (Port::B, 4, 0, Debug),
+            (Port::B, 4, 2, TimCh(3, 1)),
+            (Port::B, 4, 5, SpiMiso(1)),
+            (Port::B, 4, 6, SpiMiso(3)),
+            (Port::B, 4, 7, Other),
+            (Port::B, 5, 2, TimCh(3, 2)),
+            (Port::B, 5, 4, I2cSmba(1)),
+            (Port::B, 5, 5, SpiMosi(1)),
+            (Port::B, 5, 6, SpiMosi(3)),
+            (Port::B, 5, 9, CanRx(2)),
+            (Port::B, 5, 10, Usb),
+            (Port::B, 5, 11, Other),
+            (Port::B, 5, 13, Other),
+            (Port::B, 6, 2, TimCh(4, 1)),
+            (Port::B, 6, 4, I2cScl(1)),
+            (Port::B, 6, 7, UsartTx(1)),
+            (Port::B, 6, 9, CanTx(2)),
+            (Port::B, 6, 13, Other),
+            (Port::B, 7, 2, TimCh(4, 2)),
+            (Port::B, 7, 4, I2cSda(1)),
+            (Port::B, 7, 7, UsartRx(1)),
+            (Port::B, 7, 12, Other),
+            (Port::B, 7, 13, Other),
+            (Port::B, 8, 2, TimCh(4, 3)),
+            (Port::B, 8, 3, TimCh(10, 1)),
+            (Port::B, 8, 4, I2cScl(1)),
+            (Port::B, 8, 9, CanRx(1)),
+            (Port::B, 8, 11, Other),
+            (Port::B, 8, 12, Other),
+            (Port::B, 8, 13, Other),
+            (Port::B, 9, 2, TimCh(4, 4)),
+            (Port::B, 9, 3, TimCh(11, 1)),
+            (Port::B, 9, 4, I2cSda(1)),
+            (Port::B, 9, 5, SpiNss(2)),
+            (Port::B, 9, 9, CanTx(1)),
+            (Port::B, 9, 12, Other),
+            (Port::B, 9, 13, Other),
+            (Port::B, 10, 1, TimCh(2, 3)),
+            (Port::B, 10, 4, I2cScl(2)),
+            (Port::B, 10, 5, SpiSck(2)),
+            (Port::B, 10, 7, UsartTx(3)),
+            (Port::B, 10, 10, Usb),
+            (Port::B, 10, 11, Other),
+            (Port::B, 11, 1, TimCh(2, 4)),
+            (Port::B, 11, 4, I2cSda(2)),
+            (Port::B, 11, 7, UsartRx(3)),
+            (Port::B, 11, 10, Usb),
+            (Port::B, 11, 11, Other),
+            (Port::B, 12, 1, TimBkin(1)),
+            (Port::B, 12, 4, I2cSmba(2)),
+            (Port::B, 12, 5, SpiNss(2)),
+            (Port::B, 12, 7, UsartCk(3)),
+            (Port::B, 12, 9, CanRx(2)),
+            (Port::B, 12, 10, Usb),
+            (Port::B, 12, 11, Other),
+            (Port::B, 12, 12, Usb),
+            (Port::B, 13, 1, TimChN(1, 1)),
+            (Port::B, 13, 5, SpiSck(2)),
+            (Port::B, 13, 7, UsartCts(3)),
+            (Port::B, 13, 9, CanTx(2)),
+            (Port::B, 13, 10, Usb),
+            (Port::B, 13, 11, Other),
+            (Port::B, 14, 1, TimChN(1, 2)),
+            (Port::B, 14, 3, TimChN(8, 2)),
+            (Port::B, 14, 5, SpiMiso(2)),
+            (Port::B, 14, 6, Other),
+            (Port::B, 14, 7, UsartRts(3)),
+            (Port::B, 14, 9, TimCh(12, 1)),
+            (Port::B, 14, 12, Usb),
+            (Port::B, 15, 0, Other),
+            (Port::B, 15, 1, TimChN(1, 3)),
+            (Port::B, 15, 3, TimChN(8, 3)),
+            (Port::B, 15, 5, SpiMosi(2)),
+            (Port::B, 15, 9, TimCh(12, 2)),
+            (Port::B, 15, 12, Usb),
+        ];
+    } else {
+        const AF_TABLE: &[(Port, u8, u8, PeripheralSignal)] = &[];
+    }
+}
+
+/// The AF number that connects `signal` to a pin, or `None` if it's not available on that pin, or
+/// the pin isn't in this variant's table.
+pub fn af_for(signal: PeripheralSignal, port: Port, pin: u8) -> Option<u8> {
+    AF_TABLE
+        .iter()
+        .find(|(p, n, _, s)| *p == port && *n == pin && *s == signal)
+        .map(|(_, _, af, _)| *af)
+}
+
+/// The signals available on a pin with a given AF number. Empty if none, or if the pin isn't in
+/// this variant's table.
+pub fn signals_for(port: Port, pin: u8, af: u8) -> impl Iterator<Item = PeripheralSignal> {
+    AF_TABLE
+        .iter()
+        .filter(move |(p, n, a, _)| *p == port && *n == pin && *a == af)
+        .map(|(_, _, _, s)| *s)
+}
+
+/// Returns whether anything is assigned to `af` on the pin. Returns `None` if the pin isn't in
+/// this variant's table, including on variants that don't have one yet, so it can't be checked.
+pub fn is_valid(port: Port, pin: u8, af: u8) -> Option<bool> {
+    let mut pin_entries = AF_TABLE
+        .iter()
+        .filter(|(p, n, _, _)| *p == port && *n == pin)
+        .peekable();
+
+    pin_entries.peek()?;
+
+    Some(af == AF_EVENTOUT || pin_entries.any(|(_, _, a, _)| *a == af))
+}
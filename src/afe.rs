@@ -0,0 +1,308 @@
+//! Power-on sequencing for G4 analog front ends, as used for motor control and power conversion:
+//! A DAC channel sets a bias or threshold, an OPAMP amplifies a sensed signal in PGA mode, a
+//! comparator compares it against the threshold, and its output trips an advanced-control timer's
+//! break input.
+//!
+//! Each stage must be running, and its output settled, before the next one uses it; otherwise the
+//! comparator can see a transient on startup, and trip the break before the PWM starts. `AfeChain`
+//! brings the stages up in order, waiting each one's startup time from the datasheet (G474
+//! datasheet, section 5.3.19 - 5.3.21), and enables the break last.
+//!
+//! Example, bringing up OPAMP1 (gain 8) into COMP1, with its threshold from DAC3 channel 1:
+//!
+//! ```
+//! let mut dac3 = Dac::new(dp.DAC3, DacBits::TwelveR, 3.3);
+//! DacOutput::new_internal(&mut dac3, DacChannel::C1);
+//! dac3.write_voltage(DacChannel::C1, 1.2);
+//!
+//! let chain = AfeChain {
+//!     dac_channel: Some(DacChannel::C1),
+//!     opamp: Some(OpampConfig { opamp: 1, gain: PgaGain::X8, ..Default::default() }),
+//!     comp: CompConfig { comp: 1, threshold: CompInput::Dac3Ch1, ..Default::default() },
+//!     break_source: Some(BreakSource::Comp1),
+//! };
+//!
+//! chain.bring_up(&mut dac3, &mut delay, |sources| timer.enable_break(sources, &Default::default()));
+//! ```
+//!
+//! The SYSCFG peripheral clock must be enabled first; COMP and OPAMP are clocked by it.
+
+use core::ops::Deref;
+
+use cortex_m::delay::Delay;
+
+use crate::{
+    analog::{self, CompInput},
+    dac::{Dac, DacChannel},
+    pac::{dac1, COMP, OPAMP},
+    timer::BreakSource,
+    util::RccPeriph,
+};
+
+use cfg_if::cfg_if;
+
+// Startup times, in μs. These are the datasheet maximums, rounded up.
+/// DAC wakeup time, from enable to the output settling: `tWAKEUP`, with the buffer on.
+const DAC_SETTLE_US: u32 = 8;
+/// OPAMP wakeup time, from enable to the output settling in PGA mode: `tWAKEUP`.
+const OPAMP_SETTLE_US: u32 = 6;
+/// Comparator startup time, to reach the propagation delay specification: `tSTART`.
+const COMP_SETTLE_US: u32 = 5;
+/// Startup time of the VREFINT scaler, used by the fractional VREFINT thresholds: `tSTART_SCALER`.
+const SCALER_SETTLE_US: u32 = 200;
+
+cfg_if! {
+    if #[cfg(any(feature = "g431", feature = "g441", feature = "g471", feature = "g491", feature = "g4a1"))] {
+        const NUM_COMPS: u8 = 4;
+    } else {
+        const NUM_COMPS: u8 = 7;
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// The OPAMP's gain in PGA mode, using its internal feedback resistors, non-inverting. Sets
+/// `OPAMPx_CSR` register, `PGA_GAIN` field.
+pub enum PgaGain {
+    X2 = 0b000,
+    X4 = 0b001,
+    X8 = 0b010,
+    X16 = 0b011,
+    X32 = 0b100,
+    X64 = 0b101,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Comparator hysteresis. Sets `COMPx_CSR` register, `HYST` field.
+pub enum CompHysteresis {
+    None = 0b000,
+    H10mV = 0b001,
+    H20mV = 0b010,
+    H30mV = 0b011,
+    H40mV = 0b100,
+    H50mV = 0b101,
+    H60mV = 0b110,
+    H70mV = 0b111,
+}
+
+#[derive(Clone, Copy)]
+/// An OPAMP stage in PGA mode.
+pub struct OpampConfig {
+    /// The OPAMP, from 1 to 6.
+    pub opamp: u8,
+    /// The non-inverting input, from 0 to 3. See G4 RM, Table 187: "Operational amplifier
+    /// possible connections". Sets `OPAMPx_CSR` register, `VP_SEL` field.
+    pub input: u8,
+    pub gain: PgaGain,
+    /// High-speed mode, for a faster slew rate at higher power. Sets `OPAMPx_CSR` register,
+    /// `OPAHSM` field.
+    pub high_speed: bool,
+    /// Connect the output to its internal ADC channel, instead of its pin.
+    pub internal_output: bool,
+}
+
+impl Default for OpampConfig {
+    fn default() -> Self {
+        Self {
+            opamp: 1,
+            input: 0,
+            gain: PgaGain::X2,
+            high_speed: false,
+            internal_output: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+/// A comparator stage.
+pub struct CompConfig {
+    /// The comparator, from 1 to 7.
+    pub comp: u8,
+    /// The non-inverting input: `false` for INP0, `true` for INP1. See G4 RM, Table 195: "COMPx
+    /// non-inverting input assignment". Eg, an OPAMP's output pin. Sets `COMPx_CSR` register,
+    /// `INPSEL` field.
+    pub input: bool,
+    /// The inverting input, ie the threshold. Sets `COMPx_CSR` register, `INMSEL` field.
+    pub threshold: CompInput,
+    /// Invert the output. Use this if the break input is active low. Sets `COMPx_CSR` register,
+    /// `POL` field.
+    pub invert: bool,
+    pub hysteresis: CompHysteresis,
+    /// The blanking source, from 0 (none) to 7: A timer output that masks the comparator's output,
+    /// eg to ignore the current spike when a switch turns on. See G4 RM, Table 197: "COMPx
+    /// blanking sources". Sets `COMPx_CSR` register, `BLANKSEL` field.
+    pub blanking: u8,
+}
+
+impl Default for CompConfig {
+    fn default() -> Self {
+        Self {
+            comp: 1,
+            input: false,
+            threshold: CompInput::VRefIntHalf,
+            invert: false,
+            hysteresis: CompHysteresis::None,
+            blanking: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+/// An analog front end chain: An optional DAC threshold, an optional OPAMP, a comparator, and an
+/// optional timer break.
+pub struct AfeChain {
+    /// The DAC channel setting the threshold, or bias. `None` if the threshold is from VREFINT or
+    /// a pin, or if the DAC is already running.
+    pub dac_channel: Option<DacChannel>,
+    /// The OPAMP amplifying the comparator's input. `None` if the signal connects directly.
+    pub opamp: Option<OpampConfig>,
+    pub comp: CompConfig,
+    /// The timer break source the comparator drives. `None` to leave the break unconfigured.
+    pub break_source: Option<BreakSource>,
+}
+
+impl AfeChain {
+    /// Bring up the chain in order: Enable the DAC channel, then the OPAMP, then the comparator,
+    /// waiting for each to settle. Finally, call `enable_break` with the break source, eg with a
+    /// closure calling `Timer::enable_break`. The DAC channel's mode and output value must be set
+    /// first. Panics if the OPAMP or comparator number is invalid, or the threshold can't connect
+    /// to the comparator.
+    pub fn bring_up<R, F>(&self, dac: &mut Dac<R>, delay: &mut Delay, enable_break: F)
+    where
+        R: Deref<Target = dac1::RegisterBlock> + RccPeriph,
+        F: FnOnce(&[BreakSource]),
+    {
+        if let Some(channel) = self.dac_channel {
+            dac.enable(channel);
+            delay.delay_us(DAC_SETTLE_US);
+        }
+
+        if let Some(opamp) = &self.opamp {
+            enable_opamp(opamp);
+            delay.delay_us(OPAMP_SETTLE_US);
+        }
+
+        enable_comp(&self.comp);
+        match self.comp.threshold {
+            CompInput::VRefIntQuarter
+            | CompInput::VRefIntHalf
+            | CompInput::VRefIntThreeQuarters
+            | CompInput::VRefInt => delay.delay_us(SCALER_SETTLE_US),
+            _ => delay.delay_us(COMP_SETTLE_US),
+        }
+
+        if let Some(source) = self.break_source {
+            enable_break(&[source]);
+        }
+    }
+
+    /// Shut down the chain in reverse order: The comparator, then the OPAMP, then the DAC channel.
+    /// Disable the timer break, or its outputs, before running this, so the comparator's output
+    /// changing doesn't trip it.
+    pub fn shut_down<R>(&self, dac: &mut Dac<R>)
+    where
+        R: Deref<Target = dac1::RegisterBlock> + RccPeriph,
+    {
+        set_csr_bit(comp_csr(self.comp.comp), 0, false);
+
+        if let Some(opamp) = &self.opamp {
+            set_csr_bit(opamp_csr(opamp.opamp), 0, false);
+        }
+
+        if let Some(channel) = self.dac_channel {
+            dac.disable(channel);
+        }
+    }
+}
+
+/// Configure and enable an OPAMP in PGA mode. Sets `OPAMPx_CSR` register.
+fn enable_opamp(cfg: &OpampConfig) {
+    assert!(cfg.input <= 0b11, "OPAMP input must be 0 - 3.");
+    let csr = opamp_csr(cfg.opamp);
+
+    // VM_SEL = 0b10: PGA mode. PGA_GAIN bits 4:3 = 0b00: Non-inverting, internal feedback.
+    let val = (cfg.input as u32) << 2
+        | 0b10 << 5
+        | (cfg.high_speed as u32) << 7
+        | (cfg.gain as u32) << 14;
+    let mask = 0b11 << 2 | 0b11 << 5 | 1 << 7 | 0b1_1111 << 14;
+
+    unsafe { csr.write_volatile((csr.read_volatile() & !mask) | val) };
+
+    analog::set_opamp_internal_output(cfg.opamp, cfg.internal_output);
+    set_csr_bit(csr, 0, true);
+}
+
+/// Configure and enable a comparator. Sets `COMPx_CSR` register.
+fn enable_comp(cfg: &CompConfig) {
+    assert!(
+        cfg.blanking <= 0b111,
+        "Comparator blanking source must be 0 - 7."
+    );
+    let csr = comp_csr(cfg.comp);
+
+    let inmsel = analog::comp_inmsel(cfg.comp, cfg.threshold)
+        .expect("This threshold can't connect to this comparator.");
+
+    // The fractional VREFINT thresholds need the scaler and its resistor bridge; VREFINT itself
+    // needs only the scaler.
+    let (scalen, brgen) = match cfg.threshold {
+        CompInput::VRefIntQuarter | CompInput::VRefIntHalf | CompInput::VRefIntThreeQuarters => {
+            (true, true)
+        }
+        CompInput::VRefInt => (true, false),
+        _ => (false, false),
+    };
+
+    let val = (inmsel as u32) << 4
+        | (cfg.input as u32) << 8
+        | (cfg.invert as u32) << 15
+        | (cfg.hysteresis as u32) << 16
+        | (cfg.blanking as u32) << 19
+        | (brgen as u32) << 22
+        | (scalen as u32) << 23;
+    let mask = 0b111 << 4 | 1 << 8 | 1 << 15 | 0b111 << 16 | 0b111 << 19 | 1 << 22 | 1 << 23;
+
+    unsafe { csr.write_volatile((csr.read_volatile() & !mask) | val) };
+    set_csr_bit(csr, 0, true);
+}
+
+// The CSR registers' layout is the same for each comparator and OPAMP, but the PAC gives each its
+// own type, and which are present varies by MCU; we access them by address.
+
+/// The `COMPx_CSR` register, for a 1-indexed comparator.
+fn comp_csr(comp: u8) -> *mut u32 {
+    assert!(
+        (1..=NUM_COMPS).contains(&comp),
+        "Invalid comparator number."
+    );
+    unsafe { (COMP::ptr() as *mut u32).add(comp as usize - 1) }
+}
+
+/// The `OPAMPx_CSR` register, for a 1-indexed OPAMP.
+fn opamp_csr(opamp: u8) -> *mut u32 {
+    cfg_if! {
+        if #[cfg(any(feature = "g473", feature = "g474", feature = "g483", feature = "g484"))] {
+            let valid = (1..=6).contains(&opamp);
+        } else if #[cfg(any(feature = "g491", feature = "g4a1"))] {
+            let valid = (1..=3).contains(&opamp) || opamp == 6;
+        } else {
+            let valid = (1..=3).contains(&opamp);
+        }
+    }
+    assert!(valid, "Invalid OPAMP number.");
+    unsafe { (OPAMP::ptr() as *mut u32).add(opamp as usize - 1) }
+}
+
+/// Set or clear a single bit of a CSR register.
+fn set_csr_bit(csr: *mut u32, bit: u8, value: bool) {
+    unsafe {
+        let val = csr.read_volatile();
+        csr.write_volatile(if value {
+            val | (1 << bit)
+        } else {
+            val & !(1 << bit)
+        });
+    }
+}
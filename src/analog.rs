@@ -0,0 +1,307 @@
+//! The internal analog interconnect: Which ADC channel each internal signal (VREFINT, the
+//! temperature sensor, VBAT, and internally-routed DAC and OPAMP outputs) is connected to, and
+//! which comparator inverting input setting selects a given reference. These are spread across
+//! the ADC, DAC, OPAMP, and COMP chapters of the reference manuals; they're collected here, by
+//! family.
+//!
+//! Example, reading the DAC1 channel 1 output with ADC2 on L4:
+//!
+//! `let chan = analog::adc_channel(AdcDevice::Two, AnalogSignal::Dac1Ch1).unwrap();`
+//! `adc.enable_internal_signal(AnalogSignal::Dac1Ch1, true);`
+//! `let reading = adc.read(chan);`
+//!
+//! For DAC outputs, the DAC channel must be in a mode that connects it to on-chip peripherals.
+//!
+//! `AdcPin` configures a GPIO pin as an ADC input, and finds its channel number.
+
+use cfg_if::cfg_if;
+
+#[cfg(feature = "g4")]
+use crate::pac::OPAMP;
+
+#[cfg(any(feature = "l4x5", feature = "l4x6"))]
+use core::ptr;
+
+use crate::{
+    adc::AdcDevice,
+    gpio::{Pin, PinMode, Port, Pull},
+};
+
+#[cfg(any(feature = "l4x5", feature = "l4x6"))]
+use crate::gpio;
+
+#[derive(Clone, Copy, PartialEq)]
+/// An internal analog signal that can be measured by an ADC.
+pub enum AnalogSignal {
+    /// The internal voltage reference.
+    VRefInt,
+    /// The internal temperature sensor.
+    TempSensor,
+    /// The backup domain supply, through a divider: /2 on F3, /3 on L4, L5, G0, G4, and WB, and
+    /// /4 on H7.
+    VBat,
+    Dac1Ch1,
+    Dac1Ch2,
+    /// An OPAMP output, with its internal output enabled (G4).
+    Opamp1,
+    Opamp2,
+    Opamp3,
+    Opamp4,
+    Opamp5,
+    Opamp6,
+}
+
+/// The ADC channel `signal` is internally connected to on `adc`, or `None` if it isn't connected
+/// to that ADC. See the ADC chapter of the RM, eg L4 RM, Table 68: "ADC1 and ADC2 connectivity",
+/// and G4 RM, section 21.4.11: "Channel selection".
+pub fn adc_channel(adc: AdcDevice, signal: AnalogSignal) -> Option<u8> {
+    use AdcDevice::*;
+    use AnalogSignal::*;
+
+    cfg_if! {
+        if #[cfg(feature = "f3")] {
+            // VREFINT is connected to each ADC.
+            match (adc, signal) {
+                (_, VRefInt) => Some(18),
+                (One, TempSensor) => Some(16),
+                (One, VBat) => Some(17),
+                _ => None,
+            }
+        } else if #[cfg(any(feature = "l4", feature = "l5", feature = "wb"))] {
+            match (adc, signal) {
+                (One, VRefInt) => Some(0),
+                (One | Three, TempSensor) => Some(17),
+                (One | Three, VBat) => Some(18),
+                (Two, Dac1Ch1) => Some(17),
+                (Two, Dac1Ch2) => Some(18),
+                _ => None,
+            }
+        } else if #[cfg(any(feature = "g0", feature = "wl"))] {
+            // G0 and WL have a single ADC.
+            match (adc, signal) {
+                (One, TempSensor) => Some(12),
+                (One, VRefInt) => Some(13),
+                (One, VBat) => Some(14),
+                _ => None,
+            }
+        } else if #[cfg(feature = "g4")] {
+            match (adc, signal) {
+                (One, TempSensor) => Some(16),
+                (One | Three | Five, VBat) => Some(17),
+                (One | Three | Four | Five, VRefInt) => Some(18),
+                (One, Opamp1) => Some(13),
+                (Two, Opamp2) => Some(16),
+                (Two, Opamp3) => Some(18),
+                (Three, Opamp3) => Some(13),
+                (Four, Opamp6) => Some(17),
+                (Five, Opamp5) => Some(3),
+                (Five, TempSensor) => Some(4),
+                (Five, Opamp4) => Some(5),
+                _ => None,
+            }
+        } else if #[cfg(feature = "h7")] {
+            match (adc, signal) {
+                (Two, Dac1Ch1) => Some(16),
+                (Two, Dac1Ch2) => Some(17),
+                (Three, VBat) => Some(17),
+                (Three, TempSensor) => Some(18),
+                (Three, VRefInt) => Some(19),
+                _ => None,
+            }
+        } else {
+            let _ = (adc, signal);
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// A comparator inverting input, from an internal source.
+pub enum CompInput {
+    VRefIntQuarter,
+    VRefIntHalf,
+    VRefIntThreeQuarters,
+    VRefInt,
+    Dac1Ch1,
+    Dac1Ch2,
+    Dac2Ch1,
+    Dac3Ch1,
+    Dac3Ch2,
+    Dac4Ch1,
+    Dac4Ch2,
+}
+
+/// The `COMPx_CSR` register, `INMSEL` field value that connects `input` to the inverting input of
+/// comparator `comp` (1-indexed), or `None` if it can't be connected to that comparator. See the
+/// COMP chapter of the RM, eg G4 RM, Table 196: "COMPx inverting input assignment".
+pub fn comp_inmsel(comp: u8, input: CompInput) -> Option<u8> {
+    use CompInput::*;
+
+    match input {
+        VRefIntQuarter => return Some(0b000),
+        VRefIntHalf => return Some(0b001),
+        VRefIntThreeQuarters => return Some(0b010),
+        VRefInt => return Some(0b011),
+        _ => (),
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "g4")] {
+            // Each comparator has one channel of DAC3 or DAC4, and one of DAC1 or DAC2.
+            match (comp, input) {
+                (1 | 3, Dac3Ch1) | (2 | 4, Dac3Ch2) | (5 | 7, Dac4Ch1) | (6, Dac4Ch2) => Some(0b100),
+                (1 | 3 | 4, Dac1Ch1) | (2 | 5, Dac1Ch2) | (6 | 7, Dac2Ch1) => Some(0b101),
+                _ => None,
+            }
+        } else if #[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "h7"))] {
+            let _ = comp;
+            match input {
+                Dac1Ch1 => Some(0b100),
+                Dac1Ch2 => Some(0b101),
+                _ => None,
+            }
+        } else {
+            let _ = comp;
+            None
+        }
+    }
+}
+
+#[cfg(feature = "g4")]
+/// Connect an OPAMP's output to its internal ADC channel, instead of its output pin. `opamp` is
+/// 1-indexed. Sets `OPAMPx_CSR` register, `OPAINTOEN` field.
+pub fn set_opamp_internal_output(opamp: u8, enabled: bool) {
+    let regs = unsafe { &(*OPAMP::ptr()) };
+
+    match opamp {
+        1 => regs.opamp1_csr.modify(|_, w| w.opaintoen().bit(enabled)),
+        2 => regs.opamp2_csr.modify(|_, w| w.opaintoen().bit(enabled)),
+        3 => regs.opamp3_csr.modify(|_, w| w.opaintoen().bit(enabled)),
+        #[cfg(any(feature = "g473", feature = "g474", feature = "g483", feature = "g484"))]
+        4 => regs.opamp4_csr.modify(|_, w| w.opaintoen().bit(enabled)),
+        #[cfg(any(feature = "g473", feature = "g474", feature = "g483", feature = "g484"))]
+        5 => regs.opamp5_csr.modify(|_, w| w.opaintoen().bit(enabled)),
+        #[cfg(not(any(feature = "g431", feature = "g441", feature = "g471")))]
+        6 => regs.opamp6_csr.modify(|_, w| w.opaintoen().bit(enabled)),
+        _ => panic!("Invalid OPAMP number."),
+    }
+}
+
+/// The ADC channel GPIO pin `port`/`pin` connects to on `adc`, or `None` if it isn't an input of
+/// that ADC. Covers the pins in the smallest common packages; see the "Pin definitions" table in
+/// your MCU's datasheet for others. Eg `ADC12_IN5` in the datasheet is channel 5, on ADC1 and ADC2.
+/// Always `None` on MCUs without a table here: H7, WL, and F3 other than F303 (F301, F302, F373,
+/// and F3x4).
+pub fn pin_adc_channel(adc: AdcDevice, port: Port, pin: u8) -> Option<u8> {
+    #[cfg(not(any(feature = "h7", feature = "wl")))]
+    use AdcDevice::*;
+
+    cfg_if! {
+        if #[cfg(feature = "f303")] {
+            match (adc, port, pin) {
+                (One, Port::A, 0..=3) => Some(pin + 1),
+                (One, Port::F, 4) => Some(5),
+                (One | Two, Port::C, 0..=3) => Some(pin + 6),
+                (One | Two, Port::F, 2) => Some(10),
+                (Two, Port::A, 4..=7) => Some(pin - 3),
+                (Two, Port::C, 4) => Some(5),
+                (Two, Port::C, 5) => Some(11),
+                (Two, Port::B, 2) => Some(12),
+                _ => None,
+            }
+        } else if #[cfg(feature = "f4")] {
+            match (adc, port, pin) {
+                (One | Two | Three, Port::A, 0..=3) => Some(pin),
+                (One | Two, Port::A, 4..=7) => Some(pin),
+                (One | Two, Port::B, 0..=1) => Some(pin + 8),
+                (One | Two | Three, Port::C, 0..=3) => Some(pin + 10),
+                (One | Two, Port::C, 4..=5) => Some(pin + 10),
+                _ => None,
+            }
+        } else if #[cfg(any(feature = "l4", feature = "l5"))] {
+            match (adc, port, pin) {
+                (One | Two | Three, Port::C, 0..=3) => Some(pin + 1),
+                (One | Two, Port::A, 0..=7) => Some(pin + 5),
+                (One | Two, Port::C, 4..=5) => Some(pin + 9),
+                (One | Two, Port::B, 0..=1) => Some(pin + 15),
+                _ => None,
+            }
+        } else if #[cfg(feature = "wb")] {
+            match (adc, port, pin) {
+                (One, Port::C, 0..=3) => Some(pin + 1),
+                (One, Port::A, 0..=7) => Some(pin + 5),
+                (One, Port::C, 4..=5) => Some(pin + 9),
+                (One, Port::A, 8..=9) => Some(pin + 7),
+                _ => None,
+            }
+        } else if #[cfg(feature = "g0")] {
+            match (adc, port, pin) {
+                (One, Port::A, 0..=7) => Some(pin),
+                (One, Port::B, 0..=2) => Some(pin + 8),
+                (One, Port::B, 10) => Some(11),
+                (One, Port::B, 11..=12) => Some(pin + 4),
+                (One, Port::C, 4..=5) => Some(pin + 13),
+                _ => None,
+            }
+        } else if #[cfg(feature = "g4")] {
+            match (adc, port, pin) {
+                (One | Two, Port::A, 0..=1) => Some(pin + 1),
+                (One | Two, Port::C, 0..=3) => Some(pin + 6),
+                (One | Two, Port::B, 11) => Some(14),
+                (One, Port::A, 2..=3) => Some(pin + 1),
+                (One, Port::B, 14) => Some(5),
+                (One, Port::F, 0) => Some(10),
+                (One, Port::B, 12) => Some(11),
+                (One, Port::B, 1) => Some(12),
+                (One, Port::B, 0) => Some(15),
+                (Two, Port::A, 6..=7) => Some(pin - 3),
+                (Two, Port::C, 4) => Some(5),
+                (Two, Port::F, 1) => Some(10),
+                (Two, Port::C, 5) => Some(11),
+                (Two, Port::B, 2) => Some(12),
+                (Two, Port::A, 5) => Some(13),
+                (Two, Port::B, 15) => Some(15),
+                (Two, Port::A, 4) => Some(17),
+                _ => None,
+            }
+        } else {
+            let _ = (adc, port, pin);
+            None
+        }
+    }
+}
+
+/// A GPIO pin configured as an ADC input, with its channel number. Pass `channel` to the `Adc`
+/// methods, eg `adc.read(adc_pin.channel)`.
+pub struct AdcPin {
+    pub pin: Pin,
+    pub channel: u8,
+}
+
+impl AdcPin {
+    /// Configure a pin as an input to `adc`: Sets analog mode, disables its pull resistors, and on
+    /// L47x/L48x, closes its analog switch. Panics if the pin isn't an input of that ADC; see
+    /// `pin_adc_channel`.
+    pub fn new(adc: AdcDevice, port: Port, pin: u8) -> Self {
+        let channel = match pin_adc_channel(adc, port, pin) {
+            Some(c) => c,
+            None => panic!("This pin isn't an input of this ADC."),
+        };
+
+        let mut pin = Pin::new(port, pin, PinMode::Analog);
+        // RM: "In analog mode [...] The pull-up and pull-down resistors are disabled by hardware."
+        // On some families this is software controlled, so we clear them explicitly.
+        pin.pull(Pull::Floating);
+
+        // L47x/L48x RM, section 8.4.12: "GPIO port analog switch control register (GPIOx_ASCR)".
+        // "0: Disconnect analog switch to the ADC input (reset state). 1: Connect analog switch to
+        // the ADC input". This register is missing from the PAC on L4x5.
+        #[cfg(any(feature = "l4x5", feature = "l4x6"))]
+        unsafe {
+            let ascr = (gpio::regs(port) as *const u8).add(0x2C) as *mut u32;
+            ptr::write_volatile(ascr, ptr::read_volatile(ascr) | (1 << pin.pin));
+        }
+
+        Self { pin, channel }
+    }
+}
@@ -0,0 +1,263 @@
+//! ADC gain and offset calibration, from a DAC looped back into an ADC channel. The DAC outputs a
+//! series of known codes; a straight line fitted to the ADC readings gives correction factors
+//! that map raw readings to what an ideal ADC would read. These can be stored in backup
+//! registers, or flash, and applied to later readings.
+//!
+//! The DAC and ADC share VREF+, so this corrects the ADC relative to the DAC: The DAC's own gain
+//! and offset errors are included in the result. Calibrate the DAC buffer first, eg with
+//! `Dac::calibrate_buffer`, where available.
+//!
+//! The DAC output can be routed to the ADC internally, on the MCUs where `analog::adc_channel`
+//! returns a channel for `AnalogSignal::Dac1Ch1` or `Dac1Ch2` (eg ADC2 on L4, L5, and H7), or
+//! externally, by connecting the DAC pin to an ADC input. Example, on L4:
+//!
+//! ```
+//! let dac_out = DacOutput::new_internal(&mut dac, DacChannel::C1);
+//! dac.enable(DacChannel::C1);
+//!
+//! let chan = analog::adc_channel(AdcDevice::Two, AnalogSignal::Dac1Ch1).unwrap();
+//! adc.enable_internal_signal(AnalogSignal::Dac1Ch1, true);
+//!
+//! let cfg = LoopbackConfig::default();
+//! let cal = analog_cal::loopback_calibrate(&mut dac, DacChannel::C1, || adc.read(chan), &cfg)?;
+//! cal.save_backup(0);
+//!
+//! // Later, eg after a reset:
+//! if let Some(cal) = AnalogCal::load_backup(0) {
+//!     let corrected = cal.apply(adc.read(sensor_chan));
+//! }
+//! ```
+
+use core::{fmt, ops::Deref};
+
+use crate::{
+    dac::{Dac, DacChannel},
+    delay,
+    util::RccPeriph,
+};
+
+#[cfg(not(feature = "l412"))]
+use crate::rtc;
+
+cfg_if::cfg_if! {
+    if #[cfg(any(all(feature = "f3", not(feature = "f302")), all(feature = "l4", not(feature = "l4x6")), feature = "g4", feature = "h7b3"))] {
+        use crate::pac::dac1 as dac_p;
+    } else {
+        use crate::pac::dac as dac_p;
+    }
+}
+
+/// Marks stored calibration data as valid.
+const MAGIC: u32 = 0xca1b_ad0c;
+
+/// The range of DAC codes used, as a portion of full scale. The ends are avoided, since the DAC
+/// buffer and ADC are less linear near the rails.
+const RANGE_MIN: f32 = 0.1;
+const RANGE_MAX: f32 = 0.9;
+
+/// The maximum number of DAC codes used.
+const MAX_POINTS: usize = 32;
+
+/// Limits on the fitted correction, past which the loopback is likely miswired, or the ADC or
+/// DAC misconfigured.
+const GAIN_MIN: f32 = 0.8;
+const GAIN_MAX: f32 = 1.25;
+/// As a portion of ADC full scale.
+const OFFSET_MAX: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The reason a loopback calibration failed.
+pub enum CalError {
+    /// The ADC readings don't change with the DAC output; eg the DAC isn't routed to the channel
+    /// read, or isn't enabled.
+    NoResponse,
+    /// The fitted gain or offset is outside the plausible range.
+    OutOfRange,
+}
+
+impl fmt::Display for CalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoResponse => write!(f, "ADC readings don't follow the DAC output"),
+            Self::OutOfRange => write!(f, "ADC gain or offset correction out of range"),
+        }
+    }
+}
+
+/// Loopback calibration settings.
+pub struct LoopbackConfig {
+    /// The ADC's full-scale reading, eg 4_095 for 12-bit conversions. Defaults to 4_095.
+    pub adc_max: u16,
+    /// The number of DAC codes, spread evenly from 10% to 90% of full scale; 2 to 32. Defaults
+    /// to 8.
+    pub num_points: u8,
+    /// The number of ADC readings averaged at each code. Defaults to 16.
+    pub samples: u8,
+    /// The time to wait after each DAC write, for its output to settle, in μs. Defaults to 100.
+    pub settle_us: u32,
+}
+
+impl Default for LoopbackConfig {
+    fn default() -> Self {
+        Self {
+            adc_max: 4_095,
+            num_points: 8,
+            samples: 16,
+            settle_us: 100,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// ADC correction factors: `corrected = raw * gain + offset`, in ADC counts.
+pub struct AnalogCal {
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl Default for AnalogCal {
+    /// No correction.
+    fn default() -> Self {
+        Self {
+            gain: 1.,
+            offset: 0.,
+        }
+    }
+}
+
+impl AnalogCal {
+    /// Correct a raw ADC reading, in ADC counts.
+    pub fn apply(&self, raw: u16) -> f32 {
+        raw as f32 * self.gain + self.offset
+    }
+
+    /// Serialize, with a marker and check word, eg for writing to flash.
+    pub fn to_words(&self) -> [u32; 4] {
+        let gain = self.gain.to_bits();
+        let offset = self.offset.to_bits();
+        [MAGIC, gain, offset, MAGIC ^ gain ^ offset]
+    }
+
+    /// Deserialize words written by `to_words`. Returns `None` if the marker or check word
+    /// doesn't match; eg if nothing has been stored.
+    pub fn from_words(words: &[u32; 4]) -> Option<Self> {
+        if words[0] != MAGIC || words[3] != MAGIC ^ words[1] ^ words[2] {
+            return None;
+        }
+
+        Some(Self {
+            gain: f32::from_bits(words[1]),
+            offset: f32::from_bits(words[2]),
+        })
+    }
+
+    #[cfg(not(feature = "l412"))]
+    /// Store in 4 backup registers, starting at `first_reg`. These keep their contents through
+    /// resets, and with VBAT. `rtc::enable_backup_access` (or `Rtc::new`) must have been called.
+    pub fn save_backup(&self, first_reg: usize) {
+        for (i, word) in self.to_words().iter().enumerate() {
+            rtc::write_backup_reg(first_reg + i, *word);
+        }
+    }
+
+    #[cfg(not(feature = "l412"))]
+    /// Load from backup registers written by `save_backup`. Returns `None` if they don't contain
+    /// calibration data.
+    pub fn load_backup(first_reg: usize) -> Option<Self> {
+        let mut words = [0; 4];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = rtc::read_backup_reg(first_reg + i);
+        }
+        Self::from_words(&words)
+    }
+}
+
+/// Drive a series of codes from a DAC channel, read each with `read_adc`, from an ADC channel the
+/// DAC output is routed to, and fit the gain and offset correction. The DAC channel must be
+/// enabled. Leaves it at its last code.
+pub fn loopback_calibrate<R>(
+    dac: &mut Dac<R>,
+    channel: DacChannel,
+    mut read_adc: impl FnMut() -> u16,
+    cfg: &LoopbackConfig,
+) -> Result<AnalogCal, CalError>
+where
+    R: Deref<Target = dac_p::RegisterBlock> + RccPeriph,
+{
+    assert!(
+        cfg.num_points >= 2 && cfg.num_points as usize <= MAX_POINTS,
+        "Loopback calibration needs 2 to 32 points."
+    );
+    assert!(
+        cfg.samples >= 1,
+        "Loopback calibration needs at least 1 sample."
+    );
+
+    let (dac_max, dac_shift) = dac.full_scale();
+    let adc_max = cfg.adc_max as f32;
+
+    // (Expected reading, measured reading) at each point.
+    let mut sum_x = 0.;
+    let mut sum_y = 0.;
+    let mut points = [(0_f32, 0_f32); MAX_POINTS];
+    let n = cfg.num_points as usize;
+
+    for (i, point) in points.iter_mut().take(n).enumerate() {
+        let portion = RANGE_MIN + (RANGE_MAX - RANGE_MIN) * i as f32 / (n - 1) as f32;
+        let code = (portion * dac_max as f32) as u16;
+
+        dac.write(channel, code << dac_shift);
+        delay::delay_us(cfg.settle_us);
+
+        let mut total = 0_u32;
+        for _ in 0..cfg.samples {
+            total += read_adc() as u32;
+        }
+
+        let expected = code as f32 * adc_max / dac_max as f32;
+        let measured = total as f32 / cfg.samples as f32;
+
+        *point = (expected, measured);
+        sum_x += expected;
+        sum_y += measured;
+    }
+
+    // Least squares fit of `measured = slope * expected + intercept`, about the means, which
+    // keeps the sums small enough for `f32` precision.
+    let mean_x = sum_x / n as f32;
+    let mean_y = sum_y / n as f32;
+
+    let mut sxy = 0.;
+    let mut sxx = 0.;
+    let mut syy = 0.;
+    for (x, y) in points.iter().take(n) {
+        sxy += (x - mean_x) * (y - mean_y);
+        sxx += (x - mean_x) * (x - mean_x);
+        syy += (y - mean_y) * (y - mean_y);
+    }
+
+    // Less than an ADC count of variation over the whole range.
+    if syy < n as f32 {
+        return Err(CalError::NoResponse);
+    }
+
+    let slope = sxy / sxx;
+    let intercept = mean_y - slope * mean_x;
+
+    // Invert the fit, to map measured readings to expected ones.
+    let cal = AnalogCal {
+        gain: 1. / slope,
+        offset: -intercept / slope,
+    };
+
+    let offset_abs = if cal.offset < 0. {
+        -cal.offset
+    } else {
+        cal.offset
+    };
+    if cal.gain < GAIN_MIN || cal.gain > GAIN_MAX || offset_abs > OFFSET_MAX * adc_max {
+        return Err(CalError::OutOfRange);
+    }
+
+    Ok(cal)
+}
@@ -0,0 +1,142 @@
+//! Support for async/await, using the `embedded-hal-async` and `embedded-io-async` traits. The SPI,
+//! I2C, and U[S]ART drivers implement these when the `async` feature is enabled, so they can be
+//! used directly from Embassy or RTIC 2 async tasks. (Async I2C isn't supported on F4.)
+//!
+//! Each await point enables the peripheral's relevant interrupt, and stores the task's waker in a
+//! static registry. The peripheral's interrupt handler masks the interrupt again, and wakes the task.
+//! Set up the handlers with the `async_interrupt!` macro, and unmask the corresponding lines in
+//! the NVIC. Example:
+//!
+//! `async_interrupt!(spi, SPI1, SPI1);`
+//! `async_interrupt!(i2c, I2C1_EV, I2C1);`
+//! `async_interrupt!(i2c, I2C1_ER, I2C1);`
+//! `async_interrupt!(usart, USART2, USART2);`
+//!
+//! `unsafe { NVIC::unmask(pac::Interrupt::SPI1) };`
+
+use core::{
+    cell::RefCell,
+    future::poll_fn,
+    task::{Poll, Waker},
+};
+
+#[cfg(not(feature = "critical-section"))]
+use cortex_m::interrupt::Mutex;
+#[cfg(feature = "critical-section")]
+use critical_section::Mutex;
+
+use crate::util::free;
+
+/// The number of peripherals of a given type (eg SPI) that can be awaiting at once.
+const NUM_SLOTS: usize = 8;
+
+const EMPTY_SLOT: Option<(usize, Waker)> = None;
+
+/// Stores wakers for peripherals of a given type, keyed by register block address.
+pub struct WakerRegistry {
+    slots: Mutex<RefCell<[Option<(usize, Waker)>; NUM_SLOTS]>>,
+}
+
+impl WakerRegistry {
+    pub const fn new() -> Self {
+        Self {
+            slots: Mutex::new(RefCell::new([EMPTY_SLOT; NUM_SLOTS])),
+        }
+    }
+
+    /// Store a waker for the peripheral at address `addr`, replacing any existing one.
+    pub fn register(&self, addr: usize, waker: &Waker) {
+        free(|cs| {
+            let mut slots = self.slots.borrow(cs).borrow_mut();
+
+            let i = slots
+                .iter()
+                .position(|s| matches!(s, Some((a, _)) if *a == addr))
+                .or_else(|| slots.iter().position(|s| s.is_none()))
+                .expect("Too many peripherals awaiting at once.");
+
+            match &mut slots[i] {
+                Some((_, w)) if w.will_wake(waker) => (),
+                slot => *slot = Some((addr, waker.clone())),
+            }
+        });
+    }
+
+    /// Wake the task awaiting the peripheral at address `addr`, if there is one.
+    pub fn wake(&self, addr: usize) {
+        let waker = free(|cs| {
+            let mut slots = self.slots.borrow(cs).borrow_mut();
+
+            slots
+                .iter_mut()
+                .find(|s| matches!(s, Some((a, _)) if *a == addr))
+                .and_then(|s| s.take())
+        });
+
+        if let Some((_, w)) = waker {
+            w.wake();
+        }
+    }
+}
+
+pub(crate) static SPI_WAKERS: WakerRegistry = WakerRegistry::new();
+#[cfg(not(feature = "f4"))]
+pub(crate) static I2C_WAKERS: WakerRegistry = WakerRegistry::new();
+pub(crate) static USART_WAKERS: WakerRegistry = WakerRegistry::new();
+
+/// Wait until `ready` returns `true`. Each time it doesn't, register the waker, then run
+/// `enable_interrupt`, which should enable the interrupt that indicates readiness. `ready` is
+/// checked again after enabling the interrupt, in case the flag was set in between.
+pub(crate) async fn wait_for(
+    registry: &'static WakerRegistry,
+    addr: usize,
+    mut ready: impl FnMut() -> bool,
+    mut enable_interrupt: impl FnMut(),
+) {
+    poll_fn(|cx| {
+        if ready() {
+            return Poll::Ready(());
+        }
+
+        registry.register(addr, cx.waker());
+        enable_interrupt();
+
+        if ready() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Define an interrupt handler that wakes tasks awaiting a peripheral. The first argument is
+/// the peripheral type (`spi`, `i2c`, or `usart`), the second is the interrupt name, and the
+/// third is the PAC peripheral. For I2C, call this for both the event and error interrupts, if
+/// they're separate.
+///
+/// Example: `async_interrupt!(usart, USART1, USART1);`
+#[macro_export]
+macro_rules! async_interrupt {
+    (spi, $irq:ident, $periph:ident) => {
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        unsafe extern "C" fn $irq() {
+            $crate::spi::on_interrupt_async(&*$crate::pac::$periph::ptr());
+        }
+    };
+    (i2c, $irq:ident, $periph:ident) => {
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        unsafe extern "C" fn $irq() {
+            $crate::i2c::on_interrupt_async(&*$crate::pac::$periph::ptr());
+        }
+    };
+    (usart, $irq:ident, $periph:ident) => {
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        unsafe extern "C" fn $irq() {
+            $crate::usart::on_interrupt_async(&*$crate::pac::$periph::ptr());
+        }
+    };
+}
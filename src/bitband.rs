@@ -0,0 +1,107 @@
+//! Bit-band access, for atomic reads and writes of single bits in SRAM and peripheral registers,
+//! on the Cortex-M3 and M4 cores of F3 and F4. Each bit in the first 1MB of SRAM
+//! (`0x2000_0000`), and of the peripheral region (`0x4000_0000`), has a word in an alias region:
+//! Writing the word writes only that bit, in one bus transaction, so it can't race with an
+//! interrupt handler or DMA modifying the others. This is useful for lock-free flags shared with
+//! interrupts, and for single-bit register writes without a read-modify-write.
+//!
+//! Only the first 1MB of each region is aliased. This includes APB1, APB2, and on F4, AHB1,
+//! including the GPIO ports. It excludes F3's GPIO ports (AHB2, at `0x4800_0000`), F4's AHB2 and
+//! FSMC, and CCM RAM. The functions here panic on addresses outside the regions.
+//!
+//! Example, toggling an output with F4's `GPIOx_ODR` register, and a flag shared with an interrupt:
+//!
+//! ```
+//! let led = Bit::new(unsafe { &(*pac::GPIOA::ptr()).odr } as *const _ as u32, 5);
+//! led.write(!led.read());
+//!
+//! static FLAGS: AtomicU32 = AtomicU32::new(0);
+//! let data_ready = Bit::new(FLAGS.as_ptr() as u32, 3);
+//! data_ready.set();
+//! ```
+//!
+//! See the Cortex-M4 Technical Reference Manual, section 3.7: "Bit-banding", or F4 RM, section
+//! 2.3.3: "Bit banding".
+
+use core::ptr;
+
+/// The start of the bit-band regions, and their sizes, in bytes.
+const SRAM_START: u32 = 0x2000_0000;
+const PERIPH_START: u32 = 0x4000_0000;
+const REGION_SIZE: u32 = 0x10_0000;
+
+/// The offset of each alias region from its bit-band region.
+const ALIAS_OFFSET: u32 = 0x200_0000;
+
+/// The alias word address for bit `bit` of the word or byte at `addr`, or `None` if `addr` isn't
+/// in a bit-band region, or `bit` isn't 0 - 31.
+pub const fn alias_addr(addr: u32, bit: u8) -> Option<u32> {
+    let region = if addr >= SRAM_START && addr < SRAM_START + REGION_SIZE {
+        SRAM_START
+    } else if addr >= PERIPH_START && addr < PERIPH_START + REGION_SIZE {
+        PERIPH_START
+    } else {
+        return None;
+    };
+
+    if bit > 31 {
+        return None;
+    }
+
+    Some(region + ALIAS_OFFSET + (addr - region) * 32 + bit as u32 * 4)
+}
+
+/// A single bit in a bit-band region, accessed through its alias word.
+#[derive(Clone, Copy)]
+pub struct Bit {
+    alias: u32,
+}
+
+impl Bit {
+    /// Create a handle to bit `bit` (0 - 31) of the word at `addr`. Panics if `addr` isn't in a
+    /// bit-band region.
+    pub fn new(addr: u32, bit: u8) -> Self {
+        match alias_addr(addr, bit) {
+            Some(alias) => Self { alias },
+            None => panic!("Address or bit is outside the bit-band regions."),
+        }
+    }
+
+    /// The alias word's address.
+    pub fn alias(&self) -> u32 {
+        self.alias
+    }
+
+    /// Read the bit.
+    pub fn read(&self) -> bool {
+        unsafe { ptr::read_volatile(self.alias as *const u32) & 1 != 0 }
+    }
+
+    /// Write the bit, leaving the others in its word unchanged.
+    pub fn write(&self, value: bool) {
+        unsafe { ptr::write_volatile(self.alias as *mut u32, value as u32) }
+    }
+
+    /// Set the bit.
+    pub fn set(&self) {
+        self.write(true);
+    }
+
+    /// Clear the bit.
+    pub fn clear(&self) {
+        self.write(false);
+    }
+}
+
+/// Read bit `bit` of the word at `addr`, through its alias. Panics if `addr` isn't in a bit-band
+/// region.
+pub fn read_bit(addr: u32, bit: u8) -> bool {
+    Bit::new(addr, bit).read()
+}
+
+/// Write bit `bit` of the word at `addr`, through its alias. Panics if `addr` isn't in a bit-band
+/// region. The core does this as a locked read-modify-write of the whole word, so avoid registers
+/// with bits cleared by writing 1, or by reading, eg status registers.
+pub fn write_bit(addr: u32, bit: u8, value: bool) {
+    Bit::new(addr, bit).write(value);
+}
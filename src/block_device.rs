@@ -0,0 +1,215 @@
+//! A block device interface for storage accessed in fixed-size 512-byte blocks, eg SD cards.
+//! Implemented by `SdSpi`. Filesystem and logging code written against it doesn't depend on how
+//! the card is connected.
+//!
+//! `BlockWriter` buffers a stream of bytes, eg log records, into whole blocks, and writes them
+//! several at a time. SD cards write multiple blocks much faster than single ones, since they
+//! program in larger internal pages: Eg with 16 blocks (8KiB) per write, instead of 1.
+//!
+//! Example, logging to an SD card from block 2048:
+//!
+//! `let mut log: BlockWriter<_, 16> = BlockWriter::new(sd, 2_048);`
+//! `log.write(b"t=1.00, v=3.30\n")?;`
+//! `log.flush()?;`
+//!
+//! With the `embedded-sdmmc` feature, `SdmmcAdapter` implements that crate's `BlockDevice` trait,
+//! for reading and writing files on FAT-formatted cards. Example, with an `SdSpi` card; see also
+//! the `sd_card_fat` example:
+//!
+//! `let volume_mgr = VolumeManager::new(SdmmcAdapter::new(sd), time_source);`
+//! `let volume = volume_mgr.open_volume(VolumeIdx(0))?;`
+
+#[cfg(feature = "embedded-sdmmc")]
+use core::{cell::RefCell, fmt};
+
+#[cfg(feature = "embedded-sdmmc")]
+use embedded_sdmmc::{Block as SdmmcBlock, BlockCount, BlockIdx};
+
+/// The size of a block, in bytes. SD cards always use 512-byte blocks (SDSC cards are set to this
+/// during initialization).
+pub const BLOCK_SIZE: usize = 512;
+
+/// One block of data.
+pub type Block = [u8; BLOCK_SIZE];
+
+/// A storage device read and written in blocks. Block addresses are block numbers, not bytes.
+pub trait BlockDevice {
+    type Error;
+
+    /// Read consecutive blocks, starting at block `start`, into `blocks`.
+    fn read_blocks(&mut self, start: u32, blocks: &mut [Block]) -> Result<(), Self::Error>;
+
+    /// Write consecutive blocks, starting at block `start`.
+    fn write_blocks(&mut self, start: u32, blocks: &[Block]) -> Result<(), Self::Error>;
+
+    /// The device's capacity, in blocks.
+    fn num_blocks(&mut self) -> Result<u32, Self::Error>;
+}
+
+#[derive(Clone)]
+#[repr(C, align(4))]
+/// A buffer of `N` blocks, aligned to 4 bytes, as DMA transfers of words require.
+pub struct BlockBuf<const N: usize>(pub [Block; N]);
+
+impl<const N: usize> Default for BlockBuf<N> {
+    fn default() -> Self {
+        Self([[0; BLOCK_SIZE]; N])
+    }
+}
+
+/// Buffers bytes into blocks, and writes them to a block device `N` blocks at a time, at
+/// consecutive addresses.
+pub struct BlockWriter<D, const N: usize> {
+    pub dev: D,
+    buf: BlockBuf<N>,
+    /// The number of bytes in the buffer.
+    len: usize,
+    /// The block the start of the buffer is written to.
+    block: u32,
+}
+
+impl<D: BlockDevice, const N: usize> BlockWriter<D, N> {
+    /// Create a writer that writes to the device starting at block `start`.
+    pub fn new(dev: D, start: u32) -> Self {
+        assert!(N > 0, "A block writer must buffer at least one block.");
+
+        Self {
+            dev,
+            buf: Default::default(),
+            len: 0,
+            block: start,
+        }
+    }
+
+    /// Add bytes to the buffer, writing it to the device each time it fills.
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), D::Error> {
+        while !data.is_empty() {
+            let i = self.len / BLOCK_SIZE;
+            let offset = self.len % BLOCK_SIZE;
+            let n = (BLOCK_SIZE - offset).min(data.len());
+
+            self.buf.0[i][offset..offset + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+
+            if self.len == N * BLOCK_SIZE {
+                self.dev.write_blocks(self.block, &self.buf.0)?;
+                self.block += N as u32;
+                self.len = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the buffered bytes to the device, padding the last block with zeros. The last block
+    /// stays buffered if it's partial, so later writes continue it, and rewrite it when it's
+    /// next written. Eg, run this periodically, so a power loss loses little data.
+    pub fn flush(&mut self) -> Result<(), D::Error> {
+        let full = self.len / BLOCK_SIZE;
+        let partial = self.len % BLOCK_SIZE;
+
+        if partial > 0 {
+            self.buf.0[full][partial..].fill(0);
+        }
+
+        let count = full + (partial > 0) as usize;
+        if count == 0 {
+            return Ok(());
+        }
+        self.dev.write_blocks(self.block, &self.buf.0[..count])?;
+
+        self.block += full as u32;
+        if partial > 0 {
+            self.buf.0[0] = self.buf.0[full];
+        }
+        self.len = partial;
+
+        Ok(())
+    }
+
+    /// The device byte address the next byte written goes to.
+    pub fn position(&self) -> u64 {
+        self.block as u64 * BLOCK_SIZE as u64 + self.len as u64
+    }
+
+    /// Return the block device. Run `flush` first, or buffered bytes are lost.
+    pub fn free(self) -> D {
+        self.dev
+    }
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+/// The number of blocks `SdmmcAdapter` transfers per device command, through a buffer on the
+/// stack.
+const SDMMC_CHUNK_BLOCKS: usize = 4;
+
+#[cfg(feature = "embedded-sdmmc")]
+/// Implements `embedded-sdmmc`'s `BlockDevice` trait for a block device, so `embedded-sdmmc`'s
+/// `VolumeManager` can read and write FAT filesystems on it. That trait's methods take `&self`,
+/// so the device is held in a `RefCell`. Transfers of several blocks use multi-block commands, up
+/// to `SDMMC_CHUNK_BLOCKS` at a time.
+pub struct SdmmcAdapter<D> {
+    dev: RefCell<D>,
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+impl<D: BlockDevice> SdmmcAdapter<D> {
+    pub fn new(dev: D) -> Self {
+        Self {
+            dev: RefCell::new(dev),
+        }
+    }
+
+    /// Return the block device.
+    pub fn free(self) -> D {
+        self.dev.into_inner()
+    }
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+impl<D> embedded_sdmmc::BlockDevice for SdmmcAdapter<D>
+where
+    D: BlockDevice,
+    D::Error: fmt::Debug,
+{
+    type Error = D::Error;
+
+    fn read(&self, blocks: &mut [SdmmcBlock], start: BlockIdx) -> Result<(), Self::Error> {
+        let mut dev = self.dev.borrow_mut();
+        let mut buf = [[0; BLOCK_SIZE]; SDMMC_CHUNK_BLOCKS];
+        let mut block = start.0;
+
+        // `embedded-sdmmc`'s `Block` wraps a `[u8; 512]`, but isn't `repr(transparent)`, so we
+        // copy through a buffer, instead of casting.
+        for chunk in blocks.chunks_mut(SDMMC_CHUNK_BLOCKS) {
+            let buf = &mut buf[..chunk.len()];
+            dev.read_blocks(block, buf)?;
+
+            for (dest, src) in chunk.iter_mut().zip(buf.iter()) {
+                dest.contents = *src;
+            }
+            block += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[SdmmcBlock], start: BlockIdx) -> Result<(), Self::Error> {
+        let mut dev = self.dev.borrow_mut();
+        let mut buf = [[0; BLOCK_SIZE]; SDMMC_CHUNK_BLOCKS];
+        let mut block = start.0;
+
+        for chunk in blocks.chunks(SDMMC_CHUNK_BLOCKS) {
+            for (dest, src) in buf.iter_mut().zip(chunk.iter()) {
+                *dest = src.contents;
+            }
+
+            dev.write_blocks(block, &buf[..chunk.len()])?;
+            block += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        Ok(BlockCount(self.dev.borrow_mut().num_blocks()?))
+    }
+}
@@ -0,0 +1,357 @@
+//! Pin names and clock presets for common Nucleo and Discovery boards, so examples and new
+//! projects can refer to the LED, user button, and virtual COM port (VCP) by name, without a
+//! separate board support crate. Each board's module is available when its MCU's feature is
+//! enabled. Requires the `boards` feature.
+//!
+//! The pins are `PinId` constants: ports and pin numbers. Create each `Pin` from one, once.
+//! Example, on a Nucleo-F446RE:
+//!
+//! ```
+//! use stm32_hal2::boards::nucleo_f446re as board;
+//!
+//! board::clocks().setup().unwrap();
+//!
+//! let mut led = board::LED.pin(PinMode::Output);
+//! let mut button = board::BUTTON.pin(PinMode::Input);
+//!
+//! let mut tx = board::VCP_TX.pin(PinMode::Analog);
+//! tx.configure_for(PinRole::UsartTx, board::VCP_AF);
+//! ```
+//!
+//! Check the solder bridges on your board: The clock presets assume the boards' default HSE
+//! sources, and some pins are shared with other functions on the headers.
+
+#[cfg(any(feature = "f401", feature = "f411", feature = "f446", feature = "l4x6"))]
+/// The Arduino Uno V3 headers of Nucleo-64 boards with the original layout (MB1136): F401RE,
+/// F411RE, F446RE, and L476RG. `D0` and `D1` are the VCP pins, and `D13` is the LED, unless their
+/// solder bridges are changed.
+pub mod arduino_nucleo_64 {
+    use crate::gpio::{PinId, Port};
+
+    pub const A0: PinId = PinId::new(Port::A, 0);
+    pub const A1: PinId = PinId::new(Port::A, 1);
+    pub const A2: PinId = PinId::new(Port::A, 4);
+    pub const A3: PinId = PinId::new(Port::B, 0);
+    pub const A4: PinId = PinId::new(Port::C, 1);
+    pub const A5: PinId = PinId::new(Port::C, 0);
+
+    pub const D0: PinId = PinId::new(Port::A, 3);
+    pub const D1: PinId = PinId::new(Port::A, 2);
+    pub const D2: PinId = PinId::new(Port::A, 10);
+    pub const D3: PinId = PinId::new(Port::B, 3);
+    pub const D4: PinId = PinId::new(Port::B, 5);
+    pub const D5: PinId = PinId::new(Port::B, 4);
+    pub const D6: PinId = PinId::new(Port::B, 10);
+    pub const D7: PinId = PinId::new(Port::A, 8);
+    pub const D8: PinId = PinId::new(Port::A, 9);
+    pub const D9: PinId = PinId::new(Port::C, 7);
+    pub const D10: PinId = PinId::new(Port::B, 6);
+    pub const D11: PinId = PinId::new(Port::A, 7);
+    pub const D12: PinId = PinId::new(Port::A, 6);
+    pub const D13: PinId = PinId::new(Port::A, 5);
+    /// I2C1 SDA.
+    pub const D14: PinId = PinId::new(Port::B, 9);
+    /// I2C1 SCL.
+    pub const D15: PinId = PinId::new(Port::B, 8);
+}
+
+/// Defines the pins shared by Nucleo-64 boards with the original layout.
+#[cfg(any(feature = "f401", feature = "f411", feature = "f446", feature = "l4x6"))]
+macro_rules! nucleo_64_pins {
+    () => {
+        pub use super::arduino_nucleo_64 as arduino;
+
+        /// LD2, green. Active high.
+        pub const LED: PinId = PinId::new(Port::A, 5);
+        /// B1, blue. Low when pressed.
+        pub const BUTTON: PinId = PinId::new(Port::C, 13);
+        /// USART2 TX, to the ST-LINK's VCP.
+        pub const VCP_TX: PinId = PinId::new(Port::A, 2);
+        /// USART2 RX, from the ST-LINK's VCP.
+        pub const VCP_RX: PinId = PinId::new(Port::A, 3);
+        /// The alternate function number for `VCP_TX` and `VCP_RX`.
+        pub const VCP_AF: u8 = 7;
+        /// The HSE frequency, from the ST-LINK's MCO output, in bypass mode.
+        pub const HSE_FREQ: u32 = 8_000_000;
+    };
+}
+
+#[cfg(feature = "f401")]
+/// Nucleo-F401RE.
+pub mod nucleo_f401re {
+    use crate::{
+        clocks::{Clocks, InputSrc, PllSrc},
+        gpio::{PinId, Port},
+    };
+
+    nucleo_64_pins!();
+
+    /// 84Mhz from the HSE. Not valid for USB.
+    pub fn clocks() -> Clocks {
+        Clocks {
+            input_src: InputSrc::Pll(PllSrc::Hse(HSE_FREQ)),
+            pllm: 4,
+            hse_bypass: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(feature = "f411")]
+/// Nucleo-F411RE.
+pub mod nucleo_f411re {
+    use crate::{
+        clocks::{Clocks, InputSrc, PllSrc},
+        gpio::{PinId, Port},
+    };
+
+    nucleo_64_pins!();
+
+    /// 100Mhz from the HSE. Not valid for USB.
+    pub fn clocks() -> Clocks {
+        Clocks {
+            input_src: InputSrc::Pll(PllSrc::Hse(HSE_FREQ)),
+            pllm: 4,
+            hse_bypass: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(feature = "f446")]
+/// Nucleo-F446RE.
+pub mod nucleo_f446re {
+    use crate::{
+        clocks::{Clocks, InputSrc, PllSrc},
+        gpio::{PinId, Port},
+    };
+
+    nucleo_64_pins!();
+
+    /// 180Mhz from the HSE. Not valid for USB.
+    pub fn clocks() -> Clocks {
+        Clocks {
+            input_src: InputSrc::Pll(PllSrc::Hse(HSE_FREQ)),
+            pllm: 4,
+            hse_bypass: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(feature = "l4x6")]
+/// Nucleo-L476RG.
+pub mod nucleo_l476rg {
+    use crate::{
+        clocks::{Clocks, InputSrc, PllCfg, PllSrc, Pllm},
+        gpio::{PinId, Port},
+    };
+
+    nucleo_64_pins!();
+
+    /// 80Mhz from the HSE.
+    pub fn clocks() -> Clocks {
+        Clocks {
+            input_src: InputSrc::Pll(PllSrc::Hse(HSE_FREQ)),
+            pll: PllCfg {
+                divm: Pllm::Div2,
+                ..Default::default()
+            },
+            hse_bypass: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(any(feature = "g431", feature = "g474"))]
+/// Nucleo-G431RB and Nucleo-G474RE.
+pub mod nucleo_g4 {
+    use crate::{
+        clocks::{Clocks, InputSrc, PllCfg, PllSrc, Pllm},
+        gpio::{PinId, Port},
+    };
+
+    /// LD2, green. Active high.
+    pub const LED: PinId = PinId::new(Port::A, 5);
+    /// B1, blue.
+    pub const BUTTON: PinId = PinId::new(Port::C, 13);
+    /// LPUART1 TX, to the ST-LINK's VCP.
+    pub const VCP_TX: PinId = PinId::new(Port::A, 2);
+    /// LPUART1 RX, from the ST-LINK's VCP.
+    pub const VCP_RX: PinId = PinId::new(Port::A, 3);
+    /// The alternate function number for `VCP_TX` and `VCP_RX`.
+    pub const VCP_AF: u8 = 12;
+    /// The HSE frequency, from crystal X3.
+    pub const HSE_FREQ: u32 = 24_000_000;
+
+    /// 170Mhz from the HSE.
+    pub fn clocks() -> Clocks {
+        Clocks {
+            input_src: InputSrc::Pll(PllSrc::Hse(HSE_FREQ)),
+            pll: PllCfg {
+                divm: Pllm::Div6,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(feature = "g071")]
+/// Nucleo-G071RB. It has no HSE by default; use the default clock configuration, from the HSI.
+pub mod nucleo_g071rb {
+    use crate::gpio::{PinId, Port};
+
+    /// LD4, green. Active high.
+    pub const LED: PinId = PinId::new(Port::A, 5);
+    /// B1, blue.
+    pub const BUTTON: PinId = PinId::new(Port::C, 13);
+    /// USART2 TX, to the ST-LINK's VCP.
+    pub const VCP_TX: PinId = PinId::new(Port::A, 2);
+    /// USART2 RX, from the ST-LINK's VCP.
+    pub const VCP_RX: PinId = PinId::new(Port::A, 3);
+    /// The alternate function number for `VCP_TX` and `VCP_RX`.
+    pub const VCP_AF: u8 = 1;
+}
+
+#[cfg(any(feature = "h743", feature = "h743v"))]
+/// Nucleo-H743ZI and Nucleo-H743ZI2 (Nucleo-144).
+pub mod nucleo_h743zi {
+    use crate::{
+        clocks::{Clocks, InputSrc, PllCfg, PllSrc},
+        gpio::{PinId, Port},
+    };
+
+    /// LD1, green. Active high.
+    pub const LED: PinId = PinId::new(Port::B, 0);
+    /// LD2, yellow, on the ZI2. Active high. (On the original ZI, LD2 is blue, on PB7.)
+    pub const LED2: PinId = PinId::new(Port::E, 1);
+    /// LD3, red. Active high.
+    pub const LED3: PinId = PinId::new(Port::B, 14);
+    /// B1, blue. High when pressed.
+    pub const BUTTON: PinId = PinId::new(Port::C, 13);
+    /// USART3 TX, to the ST-LINK's VCP.
+    pub const VCP_TX: PinId = PinId::new(Port::D, 8);
+    /// USART3 RX, from the ST-LINK's VCP.
+    pub const VCP_RX: PinId = PinId::new(Port::D, 9);
+    /// The alternate function number for `VCP_TX` and `VCP_RX`.
+    pub const VCP_AF: u8 = 7;
+    /// The HSE frequency, from the ST-LINK's MCO output, in bypass mode.
+    pub const HSE_FREQ: u32 = 8_000_000;
+
+    /// 400Mhz from the HSE.
+    pub fn clocks() -> Clocks {
+        Clocks {
+            input_src: InputSrc::Pll1,
+            pll_src: PllSrc::Hse(HSE_FREQ),
+            pll1: PllCfg {
+                divm: 4,
+                ..Default::default()
+            },
+            hse_bypass: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(feature = "wb55")]
+/// Nucleo-WB55RG (P-NUCLEO-WB55).
+pub mod nucleo_wb55 {
+    use crate::{
+        clocks::{Clocks, InputSrc, PllCfg, PllSrc, Pllm},
+        gpio::{PinId, Port},
+    };
+
+    /// LED1, blue. Active high.
+    pub const LED: PinId = PinId::new(Port::B, 5);
+    /// LED2, green. Active high.
+    pub const LED2: PinId = PinId::new(Port::B, 0);
+    /// LED3, red. Active high.
+    pub const LED3: PinId = PinId::new(Port::B, 1);
+    /// SW1. Low when pressed; enable the internal pull-up.
+    pub const BUTTON: PinId = PinId::new(Port::C, 4);
+    /// SW2. Low when pressed; enable the internal pull-up.
+    pub const BUTTON2: PinId = PinId::new(Port::D, 0);
+    /// SW3. Low when pressed; enable the internal pull-up.
+    pub const BUTTON3: PinId = PinId::new(Port::D, 1);
+    /// USART1 TX, to the ST-LINK's VCP.
+    pub const VCP_TX: PinId = PinId::new(Port::B, 6);
+    /// USART1 RX, from the ST-LINK's VCP.
+    pub const VCP_RX: PinId = PinId::new(Port::B, 7);
+    /// The alternate function number for `VCP_TX` and `VCP_RX`.
+    pub const VCP_AF: u8 = 7;
+    /// The HSE frequency, from the 32Mhz crystal required by the radio.
+    pub const HSE_FREQ: u32 = 32_000_000;
+
+    /// 64Mhz from the HSE.
+    pub fn clocks() -> Clocks {
+        Clocks {
+            input_src: InputSrc::Pll(PllSrc::Hse(HSE_FREQ)),
+            pll: PllCfg {
+                divm: Pllm::Div8,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(feature = "f407")]
+/// STM32F4DISCOVERY, with an F407VG. Its ST-LINK/V2 has no VCP.
+pub mod disco_f407 {
+    use crate::{
+        clocks::{Clocks, InputSrc, PllSrc, Pllq},
+        gpio::{PinId, Port},
+    };
+
+    /// LD4, green. Active high.
+    pub const LED: PinId = PinId::new(Port::D, 12);
+    /// LD3, orange. Active high.
+    pub const LED_ORANGE: PinId = PinId::new(Port::D, 13);
+    /// LD5, red. Active high.
+    pub const LED_RED: PinId = PinId::new(Port::D, 14);
+    /// LD6, blue. Active high.
+    pub const LED_BLUE: PinId = PinId::new(Port::D, 15);
+    /// B1, blue. High when pressed.
+    pub const BUTTON: PinId = PinId::new(Port::A, 0);
+    /// The HSE frequency, from crystal X2.
+    pub const HSE_FREQ: u32 = 8_000_000;
+
+    /// 168Mhz from the HSE, with 48Mhz for USB.
+    pub fn clocks() -> Clocks {
+        Clocks {
+            input_src: InputSrc::Pll(PllSrc::Hse(HSE_FREQ)),
+            pllm: 4,
+            plln: 168,
+            pllq: Pllq::Div7,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(feature = "f429")]
+/// 32F429IDISCOVERY, with an F429ZI. Its ST-LINK/V2 has no VCP.
+pub mod disco_f429 {
+    use crate::{
+        clocks::{Clocks, InputSrc, PllSrc},
+        gpio::{PinId, Port},
+    };
+
+    /// LD3, green. Active high.
+    pub const LED: PinId = PinId::new(Port::G, 13);
+    /// LD4, red. Active high.
+    pub const LED_RED: PinId = PinId::new(Port::G, 14);
+    /// B1, blue. High when pressed.
+    pub const BUTTON: PinId = PinId::new(Port::A, 0);
+    /// The HSE frequency, from crystal X2.
+    pub const HSE_FREQ: u32 = 8_000_000;
+
+    /// 180Mhz from the HSE. Not valid for USB.
+    pub fn clocks() -> Clocks {
+        Clocks {
+            input_src: InputSrc::Pll(PllSrc::Hse(HSE_FREQ)),
+            pllm: 4,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! A small persistent diagnostic record, stored in the backup registers: A boot counter, the
+//! cause of the last reset, and the program counter and message hash of the last panic. This
+//! survives resets (including the one after a panic, or a watchdog timeout), so it can be read
+//! and reported after rebooting. A CRC detects when the record is invalid, eg after the backup
+//! domain loses power.
+//!
+//! Uses 5 consecutive backup registers, starting at `first_reg`.
+//!
+//! Example:
+//!
+//! `let mut log = BootLog::new(0);`
+//! `let record = log.on_boot();`
+//! `if let Some(panic) = record.panic { report(record.boot_count, panic.pc, panic.message_hash) }`
+//!
+//! And in the panic handler: `BootLog::new(0).record_panic_info(info);`
+
+use core::{fmt, panic::PanicInfo};
+
+use cfg_if::cfg_if;
+
+use crate::{
+    pac::RCC,
+    rtc::{self, BACKUP_REG_COUNT},
+};
+
+/// The number of backup registers used by the record.
+pub const NUM_REGS: usize = 5;
+
+/// Set in the flags word when the record contains a panic.
+const FLAG_PANIC: u32 = 1 << 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+/// The cause of the last reset, from the `RCC_CSR` register (`RCC_RSR` on H7) reset flags. If
+/// several flags are set, the most specific one is reported: Eg a software reset also sets the
+/// pin reset flag on most families.
+pub enum ResetCause {
+    Unknown = 0,
+    /// Power-on, or brownout reset.
+    PowerOn = 1,
+    /// The NRST pin.
+    Pin = 2,
+    /// A software reset, eg `SCB::sys_reset`.
+    Software = 3,
+    IndependentWatchdog = 4,
+    WindowWatchdog = 5,
+    /// An illegal Stop, Standby, or Shutdown mode entry.
+    LowPower = 6,
+    /// Option byte loading.
+    OptionByteLoad = 7,
+}
+
+impl ResetCause {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            1 => Self::PowerOn,
+            2 => Self::Pin,
+            3 => Self::Software,
+            4 => Self::IndependentWatchdog,
+            5 => Self::WindowWatchdog,
+            6 => Self::LowPower,
+            7 => Self::OptionByteLoad,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Find the most specific reset cause, from the reset flags.
+fn cause_from_flags(
+    low_power: bool,
+    iwdg: bool,
+    wwdg: bool,
+    software: bool,
+    power_on: bool,
+    option_byte: bool,
+    pin: bool,
+) -> ResetCause {
+    if low_power {
+        ResetCause::LowPower
+    } else if iwdg {
+        ResetCause::IndependentWatchdog
+    } else if wwdg {
+        ResetCause::WindowWatchdog
+    } else if software {
+        ResetCause::Software
+    } else if power_on {
+        ResetCause::PowerOn
+    } else if option_byte {
+        ResetCause::OptionByteLoad
+    } else if pin {
+        ResetCause::Pin
+    } else {
+        ResetCause::Unknown
+    }
+}
+
+/// Read the cause of the last reset. The flags accumulate over resets until cleared with
+/// `clear_reset_flags`.
+pub fn reset_cause() -> ResetCause {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    cfg_if! {
+        if #[cfg(feature = "h7b3")] {
+            let r = rcc.rsr.read();
+            cause_from_flags(
+                r.lpwrrstf().bit_is_set(),
+                r.iwdgrstf().bit_is_set(),
+                r.wwdgrstf().bit_is_set(),
+                r.sftrstf().bit_is_set(),
+                r.porrstf().bit_is_set() || r.borrstf().bit_is_set(),
+                false,
+                r.pinrstf().bit_is_set(),
+            )
+        } else if #[cfg(feature = "h7")] {
+            let r = rcc.rsr.read();
+            cause_from_flags(
+                r.lpwrrstf().bit_is_set(),
+                r.iwdg1rstf().bit_is_set(),
+                r.wwdg1rstf().bit_is_set(),
+                r.sftrstf().bit_is_set(),
+                r.porrstf().bit_is_set() || r.borrstf().bit_is_set(),
+                false,
+                r.pinrstf().bit_is_set(),
+            )
+        } else if #[cfg(feature = "f4")] {
+            let r = rcc.csr.read();
+            cause_from_flags(
+                r.lpwrrstf().bit_is_set(),
+                r.wdgrstf().bit_is_set(),
+                r.wwdgrstf().bit_is_set(),
+                r.sftrstf().bit_is_set(),
+                r.porrstf().bit_is_set() || r.borrstf().bit_is_set(),
+                false,
+                r.padrstf().bit_is_set(),
+            )
+        } else if #[cfg(feature = "f3")] {
+            let r = rcc.csr.read();
+            cause_from_flags(
+                r.lpwrrstf().bit_is_set(),
+                r.iwdgrstf().bit_is_set(),
+                r.wwdgrstf().bit_is_set(),
+                r.sftrstf().bit_is_set(),
+                r.porrstf().bit_is_set(),
+                r.oblrstf().bit_is_set(),
+                r.pinrstf().bit_is_set(),
+            )
+        } else if #[cfg(feature = "g0")] {
+            let r = rcc.csr.read();
+            cause_from_flags(
+                r.lpwrrstf().bit_is_set(),
+                r.iwdgrstf().bit_is_set(),
+                r.wwdgrstf().bit_is_set(),
+                r.sftrstf().bit_is_set(),
+                r.pwrrstf().bit_is_set(),
+                r.oblrstf().bit_is_set(),
+                r.pinrstf().bit_is_set(),
+            )
+        } else if #[cfg(feature = "l5")] {
+            let r = rcc.csr.read();
+            cause_from_flags(
+                r.lpwrstf().bit_is_set(),
+                r.iwwdgrstf().bit_is_set(),
+                r.wwdgrstf().bit_is_set(),
+                r.sftrstf().bit_is_set(),
+                r.borrstf().bit_is_set(),
+                r.oblrstf().bit_is_set(),
+                r.pinrstf().bit_is_set(),
+            )
+        } else if #[cfg(any(feature = "l4", feature = "g4"))] {
+            let r = rcc.csr.read();
+            cause_from_flags(
+                r.lpwrstf().bit_is_set(),
+                r.iwdgrstf().bit_is_set(),
+                r.wwdgrstf().bit_is_set(),
+                r.sftrstf().bit_is_set(),
+                r.borrstf().bit_is_set(),
+                r.oblrstf().bit_is_set(),
+                r.pinrstf().bit_is_set(),
+            )
+        } else { // WB and WL.
+            let r = rcc.csr.read();
+            cause_from_flags(
+                r.lpwrrstf().bit_is_set(),
+                r.iwdgrstf().bit_is_set(),
+                r.wwdgrstf().bit_is_set(),
+                r.sftrstf().bit_is_set(),
+                r.borrstf().bit_is_set(),
+                r.oblrstf().bit_is_set(),
+                r.pinrstf().bit_is_set(),
+            )
+        }
+    }
+}
+
+/// Clear the reset flags, so the next reset's cause can be identified. Sets `RCC_CSR` register
+/// (`RCC_RSR` on H7), `RMVF` field.
+pub fn clear_reset_flags() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    #[cfg(feature = "h7")]
+    rcc.rsr.modify(|_, w| w.rmvf().set_bit());
+    #[cfg(not(feature = "h7"))]
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+}
+
+/// A CRC-32 (IEEE), computed in software, so it doesn't depend on the CRC peripheral's state.
+fn crc32(words: &[u32]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+
+    for word in words {
+        for byte in word.to_le_bytes() {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+
+    !crc
+}
+
+/// A 32-bit FNV-1a hash, that can be written to with `write!`, eg to hash a formatted panic
+/// message without buffering it.
+pub struct MessageHasher {
+    hash: u32,
+}
+
+impl MessageHasher {
+    pub fn new() -> Self {
+        Self { hash: 0x811c_9dc5 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.hash ^= *byte as u32;
+            self.hash = self.hash.wrapping_mul(0x0100_0193);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.hash
+    }
+}
+
+impl Default for MessageHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Write for MessageHasher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.update(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A recorded panic.
+pub struct PanicRecord {
+    /// The program counter at the panic, or the return address of the panic handler when recorded
+    /// with `record_panic_info`. Look it up in the firmware's disassembly, or with `addr2line`.
+    pub pc: u32,
+    /// A hash of the panic message, eg from `MessageHasher`. Compare against the hashes of
+    /// expected messages, or use it to group crash reports.
+    pub message_hash: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// The contents of the boot log.
+pub struct BootRecord {
+    /// The number of boots since the record was last initialized or cleared.
+    pub boot_count: u32,
+    /// The cause of the most recent reset, as of the last `on_boot` call.
+    pub reset_cause: ResetCause,
+    /// The last panic, if one was recorded since the record was last cleared.
+    pub panic: Option<PanicRecord>,
+}
+
+/// The boot log, stored in the backup registers.
+pub struct BootLog {
+    first_reg: usize,
+}
+
+impl BootLog {
+    /// Set up the boot log, in backup registers `first_reg` to `first_reg + 4`. Enables backup
+    /// domain access.
+    pub fn new(first_reg: usize) -> Self {
+        assert!(
+            first_reg + NUM_REGS <= BACKUP_REG_COUNT,
+            "Not enough backup registers for the boot log."
+        );
+
+        rtc::enable_backup_access();
+
+        Self { first_reg }
+    }
+
+    /// Read the record, or `None` if its CRC doesn't match, eg if it was never written, or the
+    /// backup domain was reset.
+    pub fn read(&self) -> Option<BootRecord> {
+        let mut words = [0; NUM_REGS];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = rtc::read_backup_reg(self.first_reg + i);
+        }
+
+        if crc32(&words[..4]) != words[4] {
+            return None;
+        }
+
+        let flags = words[3];
+        let panic = if flags & FLAG_PANIC != 0 {
+            Some(PanicRecord {
+                pc: words[1],
+                message_hash: words[2],
+            })
+        } else {
+            None
+        };
+
+        Some(BootRecord {
+            boot_count: words[0],
+            reset_cause: ResetCause::from_bits(flags as u8),
+            panic,
+        })
+    }
+
+    fn write(&mut self, record: &BootRecord) {
+        let (pc, message_hash, panic_flag) = match record.panic {
+            Some(p) => (p.pc, p.message_hash, FLAG_PANIC),
+            None => (0, 0, 0),
+        };
+
+        let mut words = [
+            record.boot_count,
+            pc,
+            message_hash,
+            record.reset_cause as u32 | panic_flag,
+            0,
+        ];
+        words[4] = crc32(&words[..4]);
+
+        for (i, word) in words.iter().enumerate() {
+            rtc::write_backup_reg(self.first_reg + i, *word);
+        }
+    }
+
+    /// Call this once at startup: Increments the boot count, and records the reset cause, then
+    /// clears the reset flags. Returns the updated record, including any panic from before this
+    /// boot. Starts a new record if the existing one is invalid.
+    pub fn on_boot(&mut self) -> BootRecord {
+        let mut record = self.read().unwrap_or(BootRecord {
+            boot_count: 0,
+            reset_cause: ResetCause::Unknown,
+            panic: None,
+        });
+
+        record.boot_count = record.boot_count.wrapping_add(1);
+        record.reset_cause = reset_cause();
+        clear_reset_flags();
+
+        self.write(&record);
+        record
+    }
+
+    /// Record a panic, eg from a panic or HardFault handler. Keeps the boot count and reset cause.
+    pub fn record_panic(&mut self, pc: u32, message_hash: u32) {
+        let mut record = self.read().unwrap_or(BootRecord {
+            boot_count: 0,
+            reset_cause: ResetCause::Unknown,
+            panic: None,
+        });
+
+        record.panic = Some(PanicRecord { pc, message_hash });
+        self.write(&record);
+    }
+
+    /// Record a panic from a panic handler, hashing its message and location. Uses the link
+    /// register as the program counter, ie the address the panic handler was called from.
+    #[inline(always)]
+    pub fn record_panic_info(&mut self, info: &PanicInfo) {
+        let pc = cortex_m::register::lr::read();
+
+        let mut hasher = MessageHasher::new();
+        let _ = fmt::write(&mut hasher, format_args!("{}", info));
+
+        self.record_panic(pc, hasher.finish());
+    }
+
+    /// Clear the recorded panic, eg after reporting it. Keeps the boot count and reset cause.
+    pub fn clear_panic(&mut self) {
+        if let Some(mut record) = self.read() {
+            record.panic = None;
+            self.write(&record);
+        }
+    }
+
+    /// Reset the record, including the boot count.
+    pub fn clear(&mut self) {
+        self.write(&BootRecord {
+            boot_count: 0,
+            reset_cause: ResetCause::Unknown,
+            panic: None,
+        });
+    }
+}
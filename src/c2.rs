@@ -0,0 +1,154 @@
+//! Coordination with CPU2 on STM32WB: the Cortex-M0+ core that runs the radio stack. CPU2 shares
+//! clocks, flash, and some peripherals with CPU1 (the core this HAL runs on), and has its own
+//! peripheral clock enables and low-power mode. These functions follow the conventions of ST's
+//! wireless firmware, so CPU1 doesn't destabilize the radio. See RM0434, section 6.2: "Power
+//! modes", and AN5289: "Building wireless applications with STM32WB Series".
+//!
+//! `Clocks::setup` and `Clocks::reselect_input` hold the RCC semaphore (`hsem::SEM_RCC`) while
+//! changing clocks. Entering Stop mode with `stop`, and exiting it with `exit_stop`, uses the Stop
+//! entry semaphore to decide which core restores the clocks.
+
+use cortex_m::{asm::wfi, Peripherals};
+
+use crate::{
+    clocks::Clocks,
+    hsem,
+    low_power::StopMode,
+    pac::{PWR, RCC},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+/// Peripherals used by CPU2, which have separate clock enables for it.
+pub enum C2Peripheral {
+    Ipcc,
+    Hsem,
+    Rng,
+    Pka,
+    Aes2,
+    Flash,
+    /// The Bluetooth Low Energy radio.
+    Ble,
+    /// The 802.15.4 radio.
+    Ieee802,
+}
+
+/// Enable or disable a peripheral's clock for CPU2. The peripheral stays clocked if either CPU
+/// has it enabled. Sets `RCC_C2AHB3ENR` or `RCC_C2APB3ENR` register fields.
+pub fn set_peripheral_enabled(periph: C2Peripheral, enabled: bool) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    match periph {
+        C2Peripheral::Ipcc => rcc.c2ahb3enr.modify(|_, w| w.ipccen().bit(enabled)),
+        C2Peripheral::Hsem => rcc.c2ahb3enr.modify(|_, w| w.hsemen().bit(enabled)),
+        C2Peripheral::Rng => rcc.c2ahb3enr.modify(|_, w| w.rngen().bit(enabled)),
+        C2Peripheral::Pka => rcc.c2ahb3enr.modify(|_, w| w.pkaen().bit(enabled)),
+        C2Peripheral::Aes2 => rcc.c2ahb3enr.modify(|_, w| w.aes2en().bit(enabled)),
+        C2Peripheral::Flash => rcc.c2ahb3enr.modify(|_, w| w.flashen().bit(enabled)),
+        C2Peripheral::Ble => rcc.c2apb3enr.modify(|_, w| w.bleen().bit(enabled)),
+        C2Peripheral::Ieee802 => rcc.c2apb3enr.modify(|_, w| w.en802().bit(enabled)),
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// CPU2's low-power mode, used when it enters deepsleep. The system enters the shallowest mode
+/// selected by the two CPUs. Sets `PWR_C2CR1` register, `LPMS` field.
+pub enum C2LowPowerMode {
+    Stop0 = 0b000,
+    Stop1 = 0b001,
+    Stop2 = 0b010,
+    Standby = 0b011,
+    Shutdown = 0b100,
+}
+
+/// Set CPU2's low-power mode. Eg, if CPU2's firmware isn't started, set `Shutdown`, so it doesn't
+/// prevent the system from entering lower power modes.
+pub fn set_low_power_mode(mode: C2LowPowerMode) {
+    let pwr = unsafe { &(*PWR::ptr()) };
+    pwr.c2cr1
+        .modify(|_, w| unsafe { w.lpms().bits(mode as u8) });
+}
+
+/// Returns `true` if CPU2 is in deepsleep. Reads `PWR_EXTSCR` register, `C2DS` field.
+pub fn c2_deepsleep() -> bool {
+    let pwr = unsafe { &(*PWR::ptr()) };
+    pwr.extscr.read().c2ds().bit_is_set()
+}
+
+/// Returns `true` if CPU2 has been in Stop mode since the flag was cleared. Reads `PWR_EXTSCR`
+/// register, `C2STOPF` field.
+pub fn c2_was_stopped() -> bool {
+    let pwr = unsafe { &(*PWR::ptr()) };
+    pwr.extscr.read().c2stopf().bit_is_set()
+}
+
+/// Returns `true` if CPU2 has been in Standby mode since the flag was cleared. Reads `PWR_EXTSCR`
+/// register, `C2SBF` field.
+pub fn c2_was_in_standby() -> bool {
+    let pwr = unsafe { &(*PWR::ptr()) };
+    pwr.extscr.read().c2sbf().bit_is_set()
+}
+
+/// Clear CPU2's Stop and Standby flags. Sets `PWR_EXTSCR` register, `C2CSSF` field.
+pub fn clear_c2_flags() {
+    let pwr = unsafe { &(*PWR::ptr()) };
+    pwr.extscr.modify(|_, w| w.c2cssf().set_bit());
+}
+
+/// Switch the system clock to HSI16, the clock Stop mode wakes up with. Sets `RCC_CFGR`
+/// register, `SW` and `STOPWUCK` fields.
+fn switch_to_hsi() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    rcc.cr.modify(|_, w| w.hsion().set_bit());
+    while rcc.cr.read().hsirdy().bit_is_clear() {}
+
+    rcc.cfgr.modify(|_, w| unsafe {
+        w.stopwuck().set_bit();
+        w.sw().bits(0b01)
+    });
+    while rcc.cfgr.read().sws().bits() != 0b01 {}
+}
+
+/// Enter Stop mode on CPU1, coordinating clocks with CPU2. If CPU2 is running, it keeps the clock
+/// configuration, and CPU1 holds the Stop entry semaphore. Otherwise, CPU1 is the last core
+/// entering Stop mode, and switches the system clock to HSI16 first. Run `exit_stop` after
+/// waking.
+pub fn stop(mode: StopMode) {
+    let mut scb = unsafe { Peripherals::steal().SCB };
+    let pwr = unsafe { &(*PWR::ptr()) };
+
+    hsem::lock(hsem::SEM_RCC);
+
+    if hsem::try_lock(hsem::SEM_STOP_ENTRY) {
+        if c2_deepsleep() || c2_was_in_standby() {
+            hsem::unlock(hsem::SEM_STOP_ENTRY);
+            switch_to_hsi();
+        }
+    } else {
+        switch_to_hsi();
+    }
+
+    hsem::unlock(hsem::SEM_RCC);
+
+    scb.set_sleepdeep();
+    pwr.cr1.modify(|_, w| unsafe { w.lpms().bits(mode as u8) });
+
+    wfi();
+}
+
+/// Restore clocks after waking from `stop`, unless CPU2 kept them running. Releases the Stop
+/// entry semaphore.
+pub fn exit_stop(clocks: &Clocks) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    hsem::lock(hsem::SEM_RCC);
+    let switched = rcc.cfgr.read().sws().bits() != clocks.input_src.bits();
+    hsem::unlock(hsem::SEM_STOP_ENTRY);
+    hsem::unlock(hsem::SEM_RCC);
+
+    // `reselect_input` holds the RCC semaphore itself.
+    if switched {
+        clocks.reselect_input();
+    }
+}
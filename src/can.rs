@@ -1,6 +1,13 @@
 //! Support for Controller Area Network (CAN) bus. Thinly wraps the [bxCAN library](https://docs.rs/bxcan/0.5.0/bxcan/).
 //! Note that this is currently for bxCAN only; different from the `fdCAN` used on newer families.
 //!
+//! Calculate the bit timing passed to `bxcan::CanBuilder::set_bit_timing` with
+//! `can_timing::BitTiming`.
+//!
+//! Alternatively, with the `embedded_can` feature, use `Can` directly through the `embedded_can`
+//! traits, after configuring it with `start`. It sends from the 3 transmit mailboxes, and receives
+//! from FIFO 0, with a filter accepting all frames.
+//!
 //! Requires the `can` feature.
 
 // todo: Add fdCAN support.
@@ -9,10 +16,14 @@ use bxcan;
 use core::ops::Deref;
 
 use crate::{
+    can_timing::BitTiming,
     pac::{self, RCC},
     rcc_en_reset,
 };
 
+#[cfg(feature = "embedded_can")]
+use crate::can_frame::{CanError, ExtendedId, Frame, Id, StandardId};
+
 #[cfg(feature = "f3")]
 use crate::pac::can;
 #[cfg(not(feature = "f3"))]
@@ -20,6 +31,35 @@ use crate::pac::can1 as can;
 
 use cfg_if::cfg_if;
 
+// Register offsets. We access registers by address, so these work on CAN1 and CAN2 alike, and
+// so filters, which are on CAN1 only, can be configured for CAN2.
+const MCR: usize = 0x00;
+const MSR: usize = 0x04;
+const BTR: usize = 0x1c;
+const FMR: usize = 0x200;
+const FM1R: usize = 0x204;
+const FS1R: usize = 0x20c;
+const FFA1R: usize = 0x214;
+const FA1R: usize = 0x21c;
+/// Filter bank x's registers, `FxR1` and `FxR2`, are at `FILTER_BANKS + 8 * x`.
+const FILTER_BANKS: usize = 0x240;
+#[cfg(feature = "embedded_can")]
+const TSR: usize = 0x08;
+#[cfg(feature = "embedded_can")]
+const RF0R: usize = 0x0c;
+#[cfg(feature = "embedded_can")]
+const ESR: usize = 0x18;
+/// Transmit mailbox x's registers, `TIxR`, `TDTxR`, `TDLxR`, and `TDHxR`, are at
+/// `TX_MAILBOXES + 0x10 * x`.
+#[cfg(feature = "embedded_can")]
+const TX_MAILBOXES: usize = 0x180;
+/// FIFO 0's output mailbox: `RI0R`, `RDT0R`, `RDL0R`, and `RDH0R`.
+#[cfg(feature = "embedded_can")]
+const RX_FIFO0: usize = 0x1b0;
+
+/// The first filter bank used by CAN2, by default: `FMR` register, `CAN2SB` field.
+const CAN2_FIRST_BANK: usize = 14;
+
 /// Interface to the CAN peripheral.
 pub struct Can<R> {
     pub regs: R,
@@ -49,6 +89,188 @@ where
 
         Self { regs }
     }
+
+    /// Configure the peripheral, and start communicating: Leave sleep mode, set the bit timing,
+    /// and set a filter passing all frames to FIFO 0. Use this when accessing the peripheral
+    /// through the `embedded_can` traits, instead of the `bxcan` crate. Frames are transmitted in
+    /// the order queued. Sets `MCR`, `BTR`, and filter registers.
+    pub fn start(&mut self, timing: &BitTiming) {
+        // Request initialization mode, and leave sleep mode.
+        self.modify(MCR, 1 << 1, 1 << 0);
+        while self.read(MSR) & 0b11 != 0b01 {}
+
+        // `TXFP`: Transmit in chronological order, instead of by ID. `ABOM`: Recover from bus-off
+        // automatically.
+        self.modify(MCR, 0, 1 << 2 | 1 << 6);
+        self.write(BTR, timing.btr());
+
+        // Filters are configured through CAN1 (or CAN on F3), for both peripherals.
+        cfg_if! {
+            if #[cfg(feature = "f3")] {
+                let filter_base = pac::CAN::ptr() as usize;
+            } else {
+                let filter_base = pac::CAN1::ptr() as usize;
+            }
+        }
+        let bank = if self.base() == filter_base {
+            0
+        } else {
+            CAN2_FIRST_BANK
+        };
+        let filter = |offset: usize| (filter_base + offset) as *mut u32;
+
+        unsafe {
+            // `FINIT`: Filter initialization mode.
+            let fmr = filter(FMR);
+            fmr.write_volatile(fmr.read_volatile() | 1);
+
+            // A 32-bit filter in mask mode, with a mask of 0, assigned to FIFO 0: All frames pass.
+            let bit = 1 << bank;
+            for (offset, set) in [(FM1R, false), (FS1R, true), (FFA1R, false)] {
+                let reg = filter(offset);
+                let val = reg.read_volatile() & !bit;
+                reg.write_volatile(if set { val | bit } else { val });
+            }
+            filter(FILTER_BANKS + 8 * bank).write_volatile(0);
+            filter(FILTER_BANKS + 8 * bank + 4).write_volatile(0);
+
+            let fa1r = filter(FA1R);
+            fa1r.write_volatile(fa1r.read_volatile() | bit);
+            fmr.write_volatile(fmr.read_volatile() & !1);
+        }
+
+        // Leave initialization mode. The peripheral joins the bus after seeing 11 consecutive
+        // recessive bits.
+        self.modify(MCR, 1 << 0, 0);
+        while self.read(MSR) & 1 != 0 {}
+    }
+
+    fn base(&self) -> usize {
+        &*self.regs as *const _ as usize
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base() + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&mut self, offset: usize, val: u32) {
+        unsafe { ((self.base() + offset) as *mut u32).write_volatile(val) }
+    }
+
+    fn modify(&mut self, offset: usize, clear: u32, set: u32) {
+        let val = self.read(offset);
+        self.write(offset, (val & !clear) | set);
+    }
+}
+
+#[cfg(feature = "embedded_can")]
+impl<R> embedded_can::nb::Can for Can<R>
+where
+    R: Deref<Target = can::RegisterBlock>,
+{
+    type Frame = Frame;
+    type Error = CanError;
+
+    /// Queue a frame in an empty transmit mailbox. Returns `WouldBlock` if all 3 are full. Frames
+    /// are sent in the order queued; none are replaced. Returns `UnsupportedFrame` for FD frames.
+    fn transmit(&mut self, frame: &Frame) -> nb::Result<Option<Frame>, CanError> {
+        use embedded_can::Frame;
+
+        if frame.is_fd() {
+            return Err(nb::Error::Other(CanError::UnsupportedFrame));
+        }
+        // `ESR` register, `BOFF` field.
+        if self.read(ESR) & (1 << 2) != 0 {
+            return Err(nb::Error::Other(CanError::BusOff));
+        }
+
+        let tsr = self.read(TSR);
+        // `TME0` - `TME2`: Which mailboxes are empty.
+        if tsr & (0b111 << 26) == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        // `CODE`: The next empty mailbox.
+        let mailbox = TX_MAILBOXES + 0x10 * ((tsr >> 24) & 0b11) as usize;
+
+        let mut data = [0; 8];
+        data[..frame.data().len()].copy_from_slice(frame.data());
+
+        // `TDTxR`: The DLC. `TDLxR`, and `TDHxR`: The data.
+        self.write(mailbox + 4, frame.dlc_code() as u32);
+        self.write(
+            mailbox + 8,
+            u32::from_le_bytes(data[..4].try_into().unwrap()),
+        );
+        self.write(
+            mailbox + 12,
+            u32::from_le_bytes(data[4..].try_into().unwrap()),
+        );
+
+        // `TIxR`: The ID, `IDE`, `RTR`, and `TXRQ`, requesting transmission.
+        let id = match frame.id() {
+            Id::Standard(id) => (id.as_raw() as u32) << 21,
+            Id::Extended(id) => id.as_raw() << 3 | 1 << 2,
+        };
+        self.write(mailbox, id | (frame.is_remote_frame() as u32) << 1 | 1);
+
+        Ok(None)
+    }
+
+    /// Read the oldest frame from FIFO 0. Returns `WouldBlock` if it's empty, and `Overrun` once if
+    /// a frame was lost since the last call.
+    fn receive(&mut self) -> nb::Result<Frame, CanError> {
+        let rf0r = self.read(RF0R);
+
+        // `FOVR0`: Cleared by writing 1.
+        if rf0r & (1 << 4) != 0 {
+            self.write(RF0R, 1 << 4);
+            return Err(nb::Error::Other(CanError::Overrun));
+        }
+        // `FMP0`: The number of frames pending.
+        if rf0r & 0b11 == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let rir = self.read(RX_FIFO0);
+        let id = if rir & (1 << 2) != 0 {
+            Id::Extended(ExtendedId::new(rir >> 3).unwrap())
+        } else {
+            Id::Standard(StandardId::new((rir >> 21) as u16).unwrap())
+        };
+        let dlc = (self.read(RX_FIFO0 + 4) & 0xf) as u8;
+
+        let mut frame = Frame::from_raw(id, rir & (1 << 1) != 0, false, false, dlc);
+
+        let mut data = [0; 8];
+        data[..4].copy_from_slice(&self.read(RX_FIFO0 + 8).to_le_bytes());
+        data[4..].copy_from_slice(&self.read(RX_FIFO0 + 12).to_le_bytes());
+        let buf = frame.data_mut();
+        let len = buf.len();
+        buf.copy_from_slice(&data[..len]);
+
+        // `RFOM0`: Release the output mailbox.
+        self.write(RF0R, 1 << 5);
+        Ok(frame)
+    }
+}
+
+#[cfg(feature = "embedded_can")]
+impl<R> embedded_can::blocking::Can for Can<R>
+where
+    R: Deref<Target = can::RegisterBlock>,
+{
+    type Frame = Frame;
+    type Error = CanError;
+
+    /// Queue a frame, waiting for an empty transmit mailbox.
+    fn transmit(&mut self, frame: &Frame) -> Result<(), CanError> {
+        nb::block!(embedded_can::nb::Can::transmit(self, frame)).map(|_| ())
+    }
+
+    /// Wait for a frame.
+    fn receive(&mut self) -> Result<Frame, CanError> {
+        nb::block!(embedded_can::nb::Can::receive(self))
+    }
 }
 
 // todo: F3 calls it "CAN", and F4 has 2 CANs.
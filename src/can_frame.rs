@@ -0,0 +1,222 @@
+//! A CAN frame type shared by the bxCAN and FDCAN drivers, implementing `embedded_can::Frame`,
+//! and the error type they return. With these, higher-level protocol crates, eg for CANopen or
+//! J1939, work with either driver through the `embedded_can::nb::Can` and
+//! `embedded_can::blocking::Can` traits.
+//!
+//! `Frame` holds classic CAN frames, of up to 8 bytes, and CAN FD frames, of up to 64 bytes. Create
+//! FD frames with `Frame::new_fd`; bxCAN can't send them.
+//!
+//! Requires the `embedded_can` feature.
+
+use core::fmt;
+
+pub use embedded_can::{ExtendedId, Id, StandardId};
+
+/// The largest payload, in bytes: a CAN FD frame's.
+pub const MAX_DATA_LEN: usize = 64;
+
+/// CAN FD payload lengths, in bytes, for DLC values 9 - 15. DLCs 0 - 8 are the lengths.
+const FD_LENS: [u8; 7] = [12, 16, 20, 24, 32, 48, 64];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Errors from the CAN drivers' `embedded_can` implementations.
+pub enum CanError {
+    /// The controller is bus-off, after too many transmit errors, and doesn't transmit or receive
+    /// until it recovers.
+    BusOff,
+    /// A received frame was lost, since the receive FIFO was full.
+    Overrun,
+    /// The controller can't send this frame, eg an FD frame with bxCAN.
+    UnsupportedFrame,
+}
+
+impl embedded_can::Error for CanError {
+    fn kind(&self) -> embedded_can::ErrorKind {
+        match self {
+            Self::Overrun => embedded_can::ErrorKind::Overrun,
+            _ => embedded_can::ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for CanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::BusOff => f.write_str("the controller is bus-off"),
+            Self::Overrun => f.write_str("a received frame was lost; the receive FIFO was full"),
+            Self::UnsupportedFrame => f.write_str("the controller can't send this frame"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+/// A classic CAN or CAN FD frame.
+pub struct Frame {
+    id: Id,
+    remote: bool,
+    fd: bool,
+    bitrate_switch: bool,
+    /// The payload length, in bytes; or for remote frames, the requested length.
+    len: u8,
+    data: [u8; MAX_DATA_LEN],
+}
+
+impl Frame {
+    /// Create a CAN FD frame. Returns `None` if `data` isn't a valid FD length: 0 - 8, 12, 16, 20,
+    /// 24, 32, 48, or 64 bytes. Pad the payload to the next valid length. With `bitrate_switch`,
+    /// the payload is sent at the data phase bitrate.
+    pub fn new_fd(id: impl Into<Id>, data: &[u8], bitrate_switch: bool) -> Option<Self> {
+        len_to_dlc(data.len())?;
+
+        let mut result = Self::empty(id.into());
+        result.fd = true;
+        result.bitrate_switch = bitrate_switch;
+        result.len = data.len() as u8;
+        result.data[..data.len()].copy_from_slice(data);
+        Some(result)
+    }
+
+    /// Returns `true` for a CAN FD frame.
+    pub fn is_fd(&self) -> bool {
+        self.fd
+    }
+
+    /// Returns `true` if a CAN FD frame's payload is sent at the data phase bitrate.
+    pub fn bitrate_switch(&self) -> bool {
+        self.bitrate_switch
+    }
+
+    fn empty(id: Id) -> Self {
+        Self {
+            id,
+            remote: false,
+            fd: false,
+            bitrate_switch: false,
+            len: 0,
+            data: [0; MAX_DATA_LEN],
+        }
+    }
+
+    /// Create a frame from its fields, as received. The DLC is the raw 4-bit code.
+    pub(crate) fn from_raw(id: Id, remote: bool, fd: bool, bitrate_switch: bool, dlc: u8) -> Self {
+        let mut result = Self::empty(id);
+        result.remote = remote;
+        result.fd = fd;
+        result.bitrate_switch = bitrate_switch;
+        result.len = dlc_to_len(dlc, fd) as u8;
+        result
+    }
+
+    /// The payload buffer, for drivers to fill when receiving. Its length is the frame's.
+    pub(crate) fn data_mut(&mut self) -> &mut [u8] {
+        let len = if self.remote { 0 } else { self.len as usize };
+        &mut self.data[..len]
+    }
+
+    /// The raw 4-bit DLC code, as transmitted.
+    pub(crate) fn dlc_code(&self) -> u8 {
+        // Lengths are validated on creation.
+        len_to_dlc(self.len as usize).unwrap_or(0)
+    }
+}
+
+impl embedded_can::Frame for Frame {
+    /// Create a classic CAN data frame. Returns `None` if `data` is longer than 8 bytes.
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+
+        let mut result = Self::empty(id.into());
+        result.len = data.len() as u8;
+        result.data[..data.len()].copy_from_slice(data);
+        Some(result)
+    }
+
+    /// Create a classic CAN remote frame, requesting `dlc` bytes. Returns `None` if `dlc` is above
+    /// 8.
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+        if dlc > 8 {
+            return None;
+        }
+
+        let mut result = Self::empty(id.into());
+        result.remote = true;
+        result.len = dlc as u8;
+        Some(result)
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(self.id, Id::Extended(_))
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote
+    }
+
+    fn id(&self) -> Id {
+        self.id
+    }
+
+    /// The payload length, in bytes. For remote frames, the length requested.
+    fn dlc(&self) -> usize {
+        self.len as usize
+    }
+
+    fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data[..self.len as usize]
+        }
+    }
+}
+
+impl PartialEq for Frame {
+    /// Compares the ID, flags, and payload; not unused buffer bytes.
+    fn eq(&self, other: &Self) -> bool {
+        use embedded_can::Frame;
+
+        self.id == other.id
+            && self.remote == other.remote
+            && self.fd == other.fd
+            && self.bitrate_switch == other.bitrate_switch
+            && self.len == other.len
+            && self.data() == other.data()
+    }
+}
+
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use embedded_can::Frame;
+
+        f.debug_struct("Frame")
+            .field("id", &self.id)
+            .field("remote", &self.remote)
+            .field("fd", &self.fd)
+            .field("bitrate_switch", &self.bitrate_switch)
+            .field("dlc", &self.len)
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+/// The payload length, in bytes, for a 4-bit DLC code. Classic CAN frames are limited to 8 bytes.
+fn dlc_to_len(dlc: u8, fd: bool) -> usize {
+    match dlc {
+        0..=8 => dlc as usize,
+        _ if fd => FD_LENS[(dlc.min(15) - 9) as usize] as usize,
+        _ => 8,
+    }
+}
+
+/// The 4-bit DLC code for a payload length, or `None` if it isn't a valid CAN FD length.
+fn len_to_dlc(len: usize) -> Option<u8> {
+    match len {
+        0..=8 => Some(len as u8),
+        _ => FD_LENS
+            .iter()
+            .position(|l| *l as usize == len)
+            .map(|i| i as u8 + 9),
+    }
+}
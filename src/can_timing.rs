@@ -0,0 +1,242 @@
+//! Bit timing calculation for CAN: Find a prescaler and segment lengths giving a bitrate exactly,
+//! with the sample point as close as possible to the requested one. Use this instead of copying
+//! bit timing register values from a calculator, which are only valid for one kernel clock.
+//!
+//! `BitTiming::from_bitrate` uses the limits of this MCU's CAN controller: bxCAN on F3, F4, and
+//! L4, and FDCAN's nominal (arbitration) phase on the others. `from_bitrate_data` calculates
+//! FDCAN's data phase timing, for bitrate switching.
+//!
+//! Example, for 500kbps with an 87.5% sample point, as recommended by CiA for CANopen:
+//!
+//! ```
+//! let timing = BitTiming::from_bitrate(clock_cfg.apb1(), 500_000, 0.875)?;
+//! // bxCAN:
+//! let can = bxcan::Can::builder(can).set_bit_timing(timing.btr()).enable();
+//! // FDCAN:
+//! fdcan.nbtp.write(|w| unsafe { w.bits(timing.nbtp()) });
+//! ```
+
+use core::fmt;
+
+use cfg_if::cfg_if;
+
+/// How far the sample point found can be from the requested one. CiA 301 recommends a tolerance
+/// of a few percent around 87.5%.
+const SAMPLE_POINT_TOLERANCE: f32 = 0.025;
+
+/// The range of each bit timing parameter, in time quanta; or for the prescaler, in kernel clock
+/// cycles per quantum.
+struct Limits {
+    prescaler: (u16, u16),
+    seg1: (u16, u16),
+    seg2: (u16, u16),
+    sjw: u16,
+    max_bitrate: u32,
+}
+
+cfg_if! {
+    if #[cfg(any(feature = "f3", feature = "f4", feature = "l4"))] {
+        /// bxCAN. See F4 RM, section 32.9.2: "CAN bit timing register (CAN_BTR)".
+        const LIMITS_NOMINAL: Limits = Limits {
+            prescaler: (1, 1_024),
+            seg1: (1, 16),
+            seg2: (1, 8),
+            sjw: 4,
+            max_bitrate: 1_000_000,
+        };
+    } else {
+        /// FDCAN, nominal bit timing. `NTSEG1` and `NTSEG2` must be at least 1, ie 2 quanta. See
+        /// G4 RM, section 44.4.4: "FDCAN nominal bit timing and prescaler register (FDCAN_NBTP)".
+        const LIMITS_NOMINAL: Limits = Limits {
+            prescaler: (1, 512),
+            seg1: (2, 256),
+            seg2: (2, 128),
+            sjw: 128,
+            max_bitrate: 1_000_000,
+        };
+
+        /// FDCAN, data bit timing. See G4 RM, section 44.4.4: "FDCAN data bit timing and
+        /// prescaler register (FDCAN_DBTP)".
+        const LIMITS_DATA: Limits = Limits {
+            prescaler: (1, 32),
+            seg1: (1, 32),
+            seg2: (1, 16),
+            sjw: 16,
+            max_bitrate: 8_000_000,
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Errors calculating bit timing.
+pub enum BitTimingError {
+    /// The bitrate is 0, or above the maximum for this phase: 1Mbps, or 8Mbps for FDCAN's data
+    /// phase.
+    InvalidBitrate,
+    /// The sample point isn't between 50% and 95% of the bit.
+    InvalidSamplePoint,
+    /// No prescaler divides the kernel clock into a whole number of time quanta per bit, within
+    /// the segment limits. Change the kernel clock, eg to a multiple of 8Mhz.
+    NoExactPrescaler,
+    /// Valid prescalers exist, but none place the sample point within 2.5% of the one requested.
+    SamplePointUnreachable,
+}
+
+impl fmt::Display for BitTimingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::InvalidBitrate => f.write_str("bitrate is 0, or above the maximum"),
+            Self::InvalidSamplePoint => f.write_str("sample point must be between 0.5 and 0.95"),
+            Self::NoExactPrescaler => {
+                f.write_str("no prescaler divides the kernel clock into this bitrate exactly")
+            }
+            Self::SamplePointUnreachable => {
+                f.write_str("no valid timing places the sample point near the one requested")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// CAN bit timing. A bit is `1 + seg1 + seg2` time quanta: the sync segment, then `seg1`
+/// (propagation and phase segment 1), then the sample point, then `seg2` (phase segment 2). Values
+/// are actual lengths, not register values, which are offset by 1.
+pub struct BitTiming {
+    /// Kernel clock cycles per time quantum.
+    pub prescaler: u16,
+    /// Time quanta before the sample point, excluding the sync segment.
+    pub seg1: u16,
+    /// Time quanta after the sample point.
+    pub seg2: u16,
+    /// Synchronization jump width: The most quanta a bit can be lengthened or shortened by, to
+    /// resynchronize.
+    pub sjw: u16,
+}
+
+impl BitTiming {
+    /// Calculate timing for a bitrate, in bps, from the CAN kernel clock, in Hz. `sample_point` is
+    /// the fraction of the bit before the sample point, eg `0.875`. Uses this MCU's CAN
+    /// controller limits; FDCAN's nominal phase, where available.
+    pub fn from_bitrate(
+        kernel_clk: u32,
+        bitrate: u32,
+        sample_point: f32,
+    ) -> Result<Self, BitTimingError> {
+        calc(kernel_clk, bitrate, sample_point, &LIMITS_NOMINAL)
+    }
+
+    #[cfg(not(any(feature = "f3", feature = "f4", feature = "l4")))]
+    /// Calculate FDCAN data phase timing, for bitrate switching. The data phase's sample point is
+    /// usually 70 - 80%.
+    pub fn from_bitrate_data(
+        kernel_clk: u32,
+        bitrate: u32,
+        sample_point: f32,
+    ) -> Result<Self, BitTimingError> {
+        calc(kernel_clk, bitrate, sample_point, &LIMITS_DATA)
+    }
+
+    /// Time quanta per bit.
+    pub fn quanta(&self) -> u32 {
+        1 + self.seg1 as u32 + self.seg2 as u32
+    }
+
+    /// The bitrate this timing gives, in bps.
+    pub fn bitrate(&self, kernel_clk: u32) -> u32 {
+        kernel_clk / (self.prescaler as u32 * self.quanta())
+    }
+
+    /// The fraction of the bit before the sample point.
+    pub fn sample_point(&self) -> f32 {
+        (1 + self.seg1) as f32 / self.quanta() as f32
+    }
+
+    /// The bxCAN `CAN_BTR` register value, eg for `bxcan::CanBuilder::set_bit_timing`. Sets the
+    /// `SJW`, `TS2`, `TS1`, and `BRP` fields; not the test mode bits.
+    pub fn btr(&self) -> u32 {
+        (self.sjw as u32 - 1) << 24
+            | (self.seg2 as u32 - 1) << 20
+            | (self.seg1 as u32 - 1) << 16
+            | (self.prescaler as u32 - 1)
+    }
+
+    /// The FDCAN `FDCAN_NBTP` register value: The `NSJW`, `NBRP`, `NTSEG1`, and `NTSEG2` fields.
+    pub fn nbtp(&self) -> u32 {
+        (self.sjw as u32 - 1) << 25
+            | (self.prescaler as u32 - 1) << 16
+            | (self.seg1 as u32 - 1) << 8
+            | (self.seg2 as u32 - 1)
+    }
+
+    /// The FDCAN `FDCAN_DBTP` register value: The `DBRP`, `DTSEG1`, `DTSEG2`, and `DSJW`
+    /// fields. Transmitter delay compensation (`TDC`) is off; set it for data bitrates above
+    /// 1Mbps.
+    pub fn dbtp(&self) -> u32 {
+        (self.prescaler as u32 - 1) << 16
+            | (self.seg1 as u32 - 1) << 8
+            | (self.seg2 as u32 - 1) << 4
+            | (self.sjw as u32 - 1)
+    }
+}
+
+/// Find the timing with the sample point nearest `sample_point`. Of equally near ones, use the
+/// most quanta per bit, for the finest resynchronization.
+fn calc(
+    kernel_clk: u32,
+    bitrate: u32,
+    sample_point: f32,
+    limits: &Limits,
+) -> Result<BitTiming, BitTimingError> {
+    if bitrate == 0 || bitrate > limits.max_bitrate {
+        return Err(BitTimingError::InvalidBitrate);
+    }
+    if !(0.5..=0.95).contains(&sample_point) {
+        return Err(BitTimingError::InvalidSamplePoint);
+    }
+
+    let min_quanta = 1 + limits.seg1.0 as u32 + limits.seg2.0 as u32;
+    let max_quanta = 1 + limits.seg1.1 as u32 + limits.seg2.1 as u32;
+
+    let mut best: Option<(BitTiming, f32)> = None;
+
+    for quanta in (min_quanta..=max_quanta).rev() {
+        let clocks_per_bit = bitrate as u64 * quanta as u64;
+        if kernel_clk as u64 % clocks_per_bit != 0 {
+            continue;
+        }
+        let prescaler = kernel_clk as u64 / clocks_per_bit;
+        if prescaler < limits.prescaler.0 as u64 || prescaler > limits.prescaler.1 as u64 {
+            continue;
+        }
+
+        // Round to the nearest quantum, then fit the segments within their limits.
+        let target = (sample_point * quanta as f32 + 0.5) as u32;
+        let seg2 = (quanta - target.clamp(2, quanta - 1))
+            .clamp(limits.seg2.0 as u32, limits.seg2.1 as u32);
+        let seg1 = quanta - 1 - seg2;
+        if seg1 < limits.seg1.0 as u32 || seg1 > limits.seg1.1 as u32 {
+            continue;
+        }
+
+        let timing = BitTiming {
+            prescaler: prescaler as u16,
+            seg1: seg1 as u16,
+            seg2: seg2 as u16,
+            sjw: (seg2 as u16).min(limits.sjw),
+        };
+
+        let error = timing.sample_point() - sample_point;
+        let error = if error < 0. { -error } else { error };
+
+        match best {
+            Some((_, best_error)) if best_error <= error => (),
+            _ => best = Some((timing, error)),
+        }
+    }
+
+    match best {
+        Some((timing, error)) if error <= SAMPLE_POINT_TOLERANCE => Ok(timing),
+        Some(_) => Err(BitTimingError::SamplePointUnreachable),
+        None => Err(BitTimingError::NoExactPrescaler),
+    }
+}
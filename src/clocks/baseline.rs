@@ -13,6 +13,9 @@ use crate::{
 #[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "g4"))]
 use crate::pac::CRS;
 
+#[cfg(feature = "wb")]
+use crate::hsem;
+
 use cfg_if::cfg_if;
 
 // todo: WB is missing second LSI2, and perhaps other things.
@@ -565,6 +568,10 @@ impl Clocks {
         let rcc = unsafe { &(*RCC::ptr()) };
         let flash = unsafe { &(*FLASH::ptr()) };
 
+        // CPU2 may also change clocks, eg for the radio; hold the RCC semaphore while changing them.
+        #[cfg(feature = "wb")]
+        hsem::lock(hsem::SEM_RCC);
+
         // Enable and reset System Configuration Controller, ie for interrupts.
         // todo: Is this the right module to do this in?
         #[cfg(not(any(feature = "wb", feature = "wl")))] // todo: Do interrupts work without enabling syscfg on wb, which
@@ -957,6 +964,11 @@ impl Clocks {
         rcc.csr
             .modify(|_, w| unsafe { w.rfwkpsel().bits(self.rf_wakeup_src as u8) });
 
+        #[cfg(feature = "wb")]
+        hsem::unlock(hsem::SEM_RCC);
+
+        super::set_core_clock(self.systick());
+
         Ok(())
     }
 
@@ -965,6 +977,9 @@ impl Clocks {
     pub fn reselect_input(&self) {
         let rcc = unsafe { &(*RCC::ptr()) };
 
+        #[cfg(feature = "wb")]
+        hsem::lock(hsem::SEM_RCC);
+
         // Re-select the input source; useful for changing input source, or reverting
         // from stop or standby mode. This assumes we're on a clean init,
         // or waking up from stop mode etc.
@@ -1079,6 +1094,11 @@ impl Clocks {
                     .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
             }
         }
+
+        #[cfg(feature = "wb")]
+        hsem::unlock(hsem::SEM_RCC);
+
+        super::set_core_clock(self.systick());
     }
 
     #[cfg(any(feature = "l4", feature = "l5"))]
@@ -1104,6 +1124,7 @@ impl Clocks {
 
         // Update our config to reflect the new speed.
         self.input_src = InputSrc::Msi(range);
+        super::set_core_clock(self.systick());
     }
 
     #[cfg(any(feature = "l4", feature = "l5"))]
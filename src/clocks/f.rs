@@ -552,6 +552,8 @@ impl Clocks {
         // todo: Is this the right module to do this in?
         rcc_en_reset!(apb2, syscfg, rcc);
 
+        super::set_core_clock(self.systick());
+
         Ok(())
     }
 
@@ -587,6 +589,8 @@ impl Clocks {
             }
             InputSrc::Hsi => (), // Already reset to this.
         }
+
+        super::set_core_clock(self.systick());
     }
 
     #[cfg(feature = "f3")]
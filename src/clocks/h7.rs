@@ -7,6 +7,7 @@
 use crate::{
     clocks::SpeedError,
     pac::{FLASH, PWR, RCC, SYSCFG},
+    power::{SupplyConfig, VoltageLevel},
 };
 
 use cfg_if::cfg_if;
@@ -323,6 +324,12 @@ pub struct Clocks {
     pub sai4b_src: SaiSrc,
     /// DFSDM1 kernel clock source selection
     pub dfsdm1_src: DfsdmSrc,
+    /// How the core supply is powered: LDO, SMPS, or bypass. This must match the board's wiring;
+    /// if it doesn't, `setup` hangs, or the clock can't be raised. Eg Nucleo-H745/H755 boards use
+    /// `SupplyConfig::DirectSmps`.
+    pub supply_config: SupplyConfig,
+    /// The SMPS output level, for `supply_config` variants that use it.
+    pub smps_level: VoltageLevel,
 }
 
 impl Clocks {
@@ -348,6 +355,10 @@ impl Clocks {
         rcc.apb4rstr.modify(|_, w| w.syscfgrst().set_bit());
         rcc.apb4rstr.modify(|_, w| w.syscfgrst().clear_bit());
 
+        // The supply configuration must be applied before changing the voltage scaling. It can
+        // only be written once after power-on.
+        self.supply_config.apply(pwr, self.smps_level);
+
         // H743 RM, sefction 6.8.6, and section 6.6.2: Voltage Scaling
         //  Voltage scaling selection according to performance
         // These bits control the VCORE voltage level and allow to obtains the best trade-off between
@@ -653,6 +664,8 @@ impl Clocks {
             while rcc.cr.read().hsi48rdy().bit_is_clear() {}
         }
 
+        super::set_core_clock(self.systick());
+
         Ok(())
     }
 
@@ -718,6 +731,8 @@ impl Clocks {
             }
             InputSrc::Csi => (), // ?
         }
+
+        super::set_core_clock(self.systick());
     }
 
     /// Calculate the input speed to the PLL. This must be between 1 and 16 Mhz. Called `refx_ck`
@@ -968,6 +983,8 @@ impl Default for Clocks {
             sai4a_src: SaiSrc::Pll1Q,
             sai4b_src: SaiSrc::Pll1Q,
             dfsdm1_src: DfsdmSrc::Pclk2,
+            supply_config: SupplyConfig::Default,
+            smps_level: VoltageLevel::V1_8,
         }
     }
 }
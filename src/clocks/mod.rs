@@ -7,6 +7,8 @@
 //!
 //! See the Reference Manuals for non-interactive visualizations.
 
+use core::sync::atomic::{AtomicU32, Ordering};
+
 cfg_if::cfg_if! {
     if #[cfg(any(feature = "f3", feature = "f4"))] {
         mod f;
@@ -29,6 +31,33 @@ cfg_if::cfg_if! {
 
 // todo: Continue working through DRY between the clock modules.
 
+/// The core clock frequency after a reset, in Hz, before the clocks are configured: MSI at 4MHz,
+/// or HSI.
+#[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+const RESET_CORE_CLOCK: u32 = 4_000_000;
+#[cfg(feature = "f3")]
+const RESET_CORE_CLOCK: u32 = 8_000_000;
+#[cfg(any(feature = "f4", feature = "g0", feature = "g4"))]
+const RESET_CORE_CLOCK: u32 = 16_000_000;
+#[cfg(feature = "h7")]
+const RESET_CORE_CLOCK: u32 = 64_000_000;
+
+/// The core clock frequency, in Hz, cached when the clocks are configured.
+static CORE_CLOCK: AtomicU32 = AtomicU32::new(RESET_CORE_CLOCK);
+
+/// Returns the core clock frequency, in Hz, as last configured by `Clocks::setup`,
+/// `Clocks::reselect_input`, or `Clocks::change_msi_speed`; or its reset value, if the clocks
+/// haven't been configured. Used by the `delay` functions, so they stay accurate after clock
+/// changes.
+pub fn core_clock() -> u32 {
+    CORE_CLOCK.load(Ordering::Relaxed)
+}
+
+/// Update the cached core clock frequency. Called by the clock configuration functions.
+pub(crate) fn set_core_clock(freq: u32) {
+    CORE_CLOCK.store(freq, Ordering::Relaxed);
+}
+
 /// Speed out of limits.
 #[derive(Debug)]
 pub struct SpeedError {
@@ -5,7 +5,10 @@
 use core::convert::TryInto;
 use core::fmt;
 
-use crate::pac::{crc, CRC, RCC};
+use crate::{
+    flash::Flash,
+    pac::{crc, CRC, RCC},
+};
 
 use cfg_if::cfg_if;
 
@@ -81,21 +84,20 @@ impl Crc {
         let mut words = data.chunks_exact(4);
         for word in words.by_ref() {
             let word = u32::from_be_bytes(word.try_into().unwrap());
-            // todo: Put back once PAC settles. Currently causing error on H7
-            // self.reg.dr_mut().write(|w| w.dr().bits(word));
+            // The write width sets how many bytes the unit processes, so write DR directly with
+            // the width we need, instead of through the PAC's 32-bit write.
+            unsafe { core::ptr::write_volatile(self.reg.dr().as_ptr(), word) };
         }
 
         // there will be at most 3 bytes remaining, so 1 half-word and 1 byte
         let mut half_word = words.remainder().chunks_exact(2);
         if let Some(half_word) = half_word.next() {
-            let _half_word = u16::from_be_bytes(half_word.try_into().unwrap());
-            // todo: Put back once PAC settles. Currently causing error on H7
-            // self.reg.dr16_mut().write(|w| w.dr16().bits(half_word));
+            let half_word = u16::from_be_bytes(half_word.try_into().unwrap());
+            unsafe { core::ptr::write_volatile(self.reg.dr16().as_ptr() as *mut u16, half_word) };
         }
 
         if let Some(byte) = half_word.remainder().first() {
-            // todo: Put back once PAC settles. Currently causing error on H7
-            // self.reg.dr8_mut().write(|w| w.dr8().bits(*byte));
+            unsafe { core::ptr::write_volatile(self.reg.dr8().as_ptr() as *mut u8, *byte) };
         }
     }
 
@@ -181,6 +183,61 @@ impl Crc {
     }
 }
 
+/// How many bytes of a firmware image to process between progress callbacks.
+const IMAGE_CHUNK_SIZE: usize = 1_024;
+
+/// Errors from verifying a firmware image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageError {
+    /// The region is too short to hold a CRC.
+    TooShort,
+    /// The CRC computed over the image doesn't match the one stored after it.
+    Mismatch { expected: u32, computed: u32 },
+}
+
+impl Crc {
+    /// Verify a firmware image stored in flash, eg at boot from a bootloader, or as a safety
+    /// self-test. The region starts at `page` (sector on H7), and is `len` bytes long, including
+    /// a trailing CRC32, stored little-endian in its last 4 bytes. Uses the standard (Ethernet,
+    /// zlib) CRC-32, as produced by eg `srec_cat` and the `crc` crate's `CRC_32_ISO_HDLC`; this
+    /// overwrites the unit's configuration.
+    ///
+    /// `progress` is called with the number of bytes processed so far, and the total, every
+    /// 1024 bytes, eg to kick a watchdog, or update a display. Returns the CRC if it matches.
+    pub fn verify_image(
+        &mut self,
+        flash: &Flash,
+        page: usize,
+        len: usize,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<u32, ImageError> {
+        if len < 4 {
+            return Err(ImageError::TooShort);
+        }
+
+        self.set_config(&Config::new().reflect(true).output_xor(0xFFFF_FFFF));
+
+        let image_len = len - 4;
+        let image = flash.slice(page, 0, image_len);
+
+        let mut processed = 0;
+        for chunk in image.chunks(IMAGE_CHUNK_SIZE) {
+            self.update(chunk);
+            processed += chunk.len();
+            progress(processed, image_len);
+        }
+
+        let computed = self.finish();
+        let expected = u32::from_le_bytes(flash.slice(page, image_len, 4).try_into().unwrap());
+
+        if computed == expected {
+            Ok(computed)
+        } else {
+            Err(ImageError::Mismatch { expected, computed })
+        }
+    }
+}
+
 #[macro_use]
 mod macros {
     /// Generate an error if number passed is even
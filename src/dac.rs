@@ -2,11 +2,12 @@
 
 use core::ops::Deref;
 
-use cortex_m::{delay::Delay, interrupt::free};
+use cortex_m::delay::Delay;
 
 use crate::{
+    gpio::{Pin, PinMode, Port, Pull},
     pac::{self, RCC},
-    util::RccPeriph,
+    util::{free, RccPeriph},
 };
 
 cfg_if! {
@@ -140,6 +141,21 @@ pub enum Trigger {
     Exti9 = 13,
 }
 
+#[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+#[derive(Clone, Copy)]
+/// Sample-and-hold timing, in cycles of the low-speed clock: LSI, or LSE on G4 if selected. The
+/// output capacitor is charged for `sample` cycles, then held for `hold` cycles with the DAC
+/// powered down, then recharged for `refresh` cycles, repeatedly. See L4 RM, section 19.4.12:
+/// "DAC channel sample and hold mode". Sets the `DAC_SHSRx`, `DAC_SHHR`, and `DAC_SHRR` registers.
+pub struct SampleHoldTiming {
+    /// `TSAMPLEx` field. 10 bits.
+    pub sample: u16,
+    /// `THOLDx` field. 10 bits.
+    pub hold: u16,
+    /// `TREFRESHx` field.
+    pub refresh: u8,
+}
+
 /// Represents a Digital to Analog Converter (DAC) peripheral.
 pub struct Dac<R> {
     pub regs: R,
@@ -229,6 +245,68 @@ where
         }
     }
 
+    #[cfg(not(any(feature = "f3", feature = "f4", feature = "wl")))]
+    /// Set a channel's mode: Whether it's connected to its pin, to on-chip peripherals, or both,
+    /// whether its output buffer is enabled, and whether it uses sample-and-hold. The channel must
+    /// be disabled. Sets the `DAC_MCR` register, `MODEx` field.
+    pub fn set_mode(&mut self, channel: DacChannel, mode: DacMode) {
+        #[cfg(any(feature = "l5", feature = "g4"))]
+        let mcr = &self.regs.dac_mcr;
+        #[cfg(not(any(feature = "l5", feature = "g4")))]
+        let mcr = &self.regs.mcr;
+
+        mcr.modify(|_, w| unsafe {
+            match channel {
+                DacChannel::C1 => w.mode1().bits(mode as u8),
+                DacChannel::C2 => w.mode2().bits(mode as u8),
+            }
+        });
+    }
+
+    #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+    /// Set up a channel's sample-and-hold timing. Use this with one of the `DacMode::Sh` modes, set
+    /// with `set_mode`, to hold a DC output in Stop mode, while drawing a few microamps. The LSI (or
+    /// LSE) must be running, including in Stop mode. Blocks until any previous write to the sample
+    /// time register is complete.
+    pub fn set_sample_hold(&mut self, channel: DacChannel, timing: SampleHoldTiming) {
+        cfg_if! {
+            if #[cfg(any(feature = "l5", feature = "g4"))] {
+                let (sr, shsr1, shsr2, shhr, shrr) = (
+                    &self.regs.dac_sr,
+                    &self.regs.dac_shsr1,
+                    &self.regs.dac_shsr2,
+                    &self.regs.dac_shhr,
+                    &self.regs.dac_shrr,
+                );
+            } else {
+                let (sr, shsr1, shsr2, shhr, shrr) = (
+                    &self.regs.sr,
+                    &self.regs.shsr1,
+                    &self.regs.shsr2,
+                    &self.regs.shhr,
+                    &self.regs.shrr,
+                );
+            }
+        }
+
+        // RM: "The DAC_SHSRx register can be written only when BWSTx = 0"; after a write, the flag
+        // is set until the new value is synchronized to the low-speed clock domain.
+        match channel {
+            DacChannel::C1 => {
+                while sr.read().bwst1().bit_is_set() {}
+                shsr1.write(|w| unsafe { w.tsample1().bits(timing.sample) });
+                shhr.modify(|_, w| unsafe { w.thold1().bits(timing.hold) });
+                shrr.modify(|_, w| unsafe { w.trefresh1().bits(timing.refresh) });
+            }
+            DacChannel::C2 => {
+                while sr.read().bwst2().bit_is_set() {}
+                shsr2.write(|w| unsafe { w.tsample2().bits(timing.sample) });
+                shhr.modify(|_, w| unsafe { w.thold2().bits(timing.hold) });
+                shrr.modify(|_, w| unsafe { w.trefresh2().bits(timing.refresh) });
+            }
+        }
+    }
+
     /// Enable the DAC, for a specific channel.
     pub fn enable(&mut self, channel: DacChannel) {
         #[cfg(any(feature = "l5", feature = "g4"))]
@@ -257,6 +335,23 @@ where
         });
     }
 
+    /// Disable both DAC channels, and return the PAC register block. If `gate_clock` is `true`,
+    /// also disable its RCC peripheral clock.
+    pub fn free(mut self, gate_clock: bool) -> R {
+        self.disable(DacChannel::C1);
+        #[cfg(not(feature = "wl"))]
+        self.disable(DacChannel::C2);
+
+        if gate_clock {
+            free(|_| {
+                let rcc = unsafe { &(*RCC::ptr()) };
+                R::disable_clock(rcc);
+            });
+        }
+
+        self.regs
+    }
+
     /// Set the DAC output word.
     pub fn write(&mut self, channel: DacChannel, val: u16) {
         // RM: DAC conversion
@@ -428,6 +523,16 @@ where
         );
     }
 
+    /// The full-scale output code for the configured precision, and the left shift `write`
+    /// expects it to have.
+    pub(crate) fn full_scale(&self) -> (u16, u8) {
+        match self.bits {
+            DacBits::EightR => (255, 0),
+            DacBits::TwelveL => (4_095, 4),
+            DacBits::TwelveR => (4_095, 0),
+        }
+    }
+
     /// Set the DAC output voltage.
     pub fn write_voltage(&mut self, channel: DacChannel, volts: f32) {
         let max_word = match self.bits {
@@ -553,3 +658,68 @@ where
         });
     }
 }
+
+/// The GPIO pin DAC `dac` (1-indexed) channel `channel` outputs to, or `None` if it has no pin.
+/// Eg G4's DAC3 and DAC4 only connect to on-chip peripherals. Note that not all MCUs in a family
+/// have DAC2; eg it's present on F303x6/8, and G47x/G48x.
+pub fn dac_pin(dac: u8, channel: DacChannel) -> Option<(Port, u8)> {
+    cfg_if! {
+        if #[cfg(feature = "wl")] {
+            match (dac, channel) {
+                (1, DacChannel::C1) => Some((Port::A, 10)),
+                _ => None,
+            }
+        } else {
+            match (dac, channel) {
+                (1, DacChannel::C1) => Some((Port::A, 4)),
+                (1, DacChannel::C2) => Some((Port::A, 5)),
+                #[cfg(any(feature = "f3", feature = "g4"))]
+                (2, DacChannel::C1) => Some((Port::A, 6)),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// A DAC channel, paired with its output pin if it uses one.
+pub struct DacOutput {
+    pub channel: DacChannel,
+    /// The output pin, in analog mode. `None` for channels only connected to on-chip peripherals.
+    pub pin: Option<Pin>,
+}
+
+impl DacOutput {
+    /// Configure the output pin of DAC `dac` (1-indexed) channel `channel`, in analog mode, with
+    /// its pull resistors disabled. Panics if the channel has no pin; see `dac_pin`.
+    pub fn new(dac: u8, channel: DacChannel) -> Self {
+        let (port, pin) = match dac_pin(dac, channel) {
+            Some(p) => p,
+            None => panic!("This DAC channel has no output pin."),
+        };
+
+        // RM: "Once the DAC channelx is enabled, the corresponding GPIO pin (PA4 or PA5) is
+        // automatically connected to the analog converter output (DAC_OUTx). In order to avoid
+        // parasitic consumption, the PA4 or PA5 pin should first be configured to analog (AIN)."
+        let mut pin = Pin::new(port, pin, PinMode::Analog);
+        pin.pull(Pull::Floating);
+
+        Self {
+            channel,
+            pin: Some(pin),
+        }
+    }
+
+    #[cfg(not(any(feature = "f3", feature = "f4", feature = "wl")))]
+    /// Use a channel for on-chip peripherals only, eg as a comparator reference or OPAMP input,
+    /// leaving its pin free for other uses. Sets the channel's mode to connect it to on-chip
+    /// peripherals, with the buffer disabled. The channel must be disabled. Sets the `DAC_MCR`
+    /// register, `MODEx` field.
+    pub fn new_internal<R>(dac: &mut Dac<R>, channel: DacChannel) -> Self
+    where
+        R: Deref<Target = dac_p::RegisterBlock> + RccPeriph,
+    {
+        dac.set_mode(channel, DacMode::NormExternalAndPeriphBuDis);
+
+        Self { channel, pin: None }
+    }
+}
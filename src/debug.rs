@@ -0,0 +1,296 @@
+//! Debug MCU (DBGMCU) configuration. Stops selected timers, watchdogs, I2C timeouts, and the RTC
+//! while the core is halted by a debugger, so they don't keep running (or reset the MCU) while
+//! stepping through code. Also keeps the debug connection alive in low-power modes.
+//!
+//! This module also sets up Serial Wire Output (SWO) tracing through the ITM, for printing over the
+//! debug probe. Example:
+//!
+//! `let mut swo = Pin::new(Port::B, 3, PinMode::Alt(0));`
+//! `debug::setup_swo(&mut cp.DCB, &mut cp.TPIU, &mut cp.ITM, &mut swo, &clock_cfg, 2_000_000);`
+//! `writeln!(ItmWriter::new(&mut cp.ITM, 0), "Hello {}", 1).ok();`
+//!
+//! The freeze register and field names are inconsistent between PAC variants, even in the same family,
+//! so we access the freeze registers by their offsets from the reference manuals instead.
+
+use core::{fmt, ptr};
+
+#[cfg(not(any(feature = "h7", feature = "wl")))]
+use cortex_m::peripheral::{DCB, TPIU};
+use cortex_m::{itm, peripheral::ITM};
+
+#[cfg(not(any(feature = "h7", feature = "wl")))]
+use crate::{
+    clocks::Clocks,
+    gpio::{Pin, PinMode, Port},
+};
+use crate::{pac::DBGMCU, util::free};
+
+use cfg_if::cfg_if;
+
+/// A peripheral that can be stopped when the core is halted. Not all peripherals are available
+/// on all MCUs.
+#[derive(Clone, Copy)]
+pub enum DebugFreeze {
+    Tim1,
+    Tim2,
+    Tim3,
+    Tim4,
+    Tim5,
+    Tim6,
+    Tim7,
+    Tim8,
+    Tim15,
+    Tim16,
+    Tim17,
+    Rtc,
+    /// Window watchdog.
+    Wwdg,
+    /// Independent watchdog.
+    Iwdg,
+    /// I2C1 SMBus timeout.
+    I2c1,
+    /// I2C2 SMBus timeout.
+    I2c2,
+    /// I2C3 SMBus timeout.
+    I2c3,
+    Lptim1,
+}
+
+impl DebugFreeze {
+    /// The offset of the freeze register from the DBGMCU base address, and the bit position in it.
+    /// See the reference manual section `DBGMCU registers` for your MCU.
+    fn location(&self) -> (usize, u8) {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                // `APB1LFZ1`, `APB2FZ1`, `APB3FZ1`, and `APB4FZ1` registers.
+                match self {
+                    Self::Tim2 => (0x3c, 0),
+                    Self::Tim3 => (0x3c, 1),
+                    Self::Tim4 => (0x3c, 2),
+                    Self::Tim5 => (0x3c, 3),
+                    Self::Tim6 => (0x3c, 4),
+                    Self::Tim7 => (0x3c, 5),
+                    Self::Lptim1 => (0x3c, 9),
+                    Self::I2c1 => (0x3c, 21),
+                    Self::I2c2 => (0x3c, 22),
+                    Self::I2c3 => (0x3c, 23),
+                    Self::Tim1 => (0x4c, 0),
+                    Self::Tim8 => (0x4c, 1),
+                    Self::Tim15 => (0x4c, 16),
+                    Self::Tim16 => (0x4c, 17),
+                    Self::Tim17 => (0x4c, 18),
+                    Self::Wwdg => (0x34, 6),
+                    Self::Rtc => (0x54, 16),
+                    Self::Iwdg => (0x54, 18),
+                }
+            } else if #[cfg(any(feature = "wb", feature = "wl"))] {
+                // CPU1's `APB1FZR1` and `APB2FZR` registers.
+                match self {
+                    Self::Tim2 => (0x3c, 0),
+                    Self::Rtc => (0x3c, 10),
+                    Self::Wwdg => (0x3c, 11),
+                    Self::Iwdg => (0x3c, 12),
+                    Self::I2c1 => (0x3c, 21),
+                    #[cfg(feature = "wl")]
+                    Self::I2c2 => (0x3c, 22),
+                    Self::I2c3 => (0x3c, 23),
+                    Self::Lptim1 => (0x3c, 31),
+                    Self::Tim1 => (0x4c, 11),
+                    Self::Tim16 => (0x4c, 17),
+                    Self::Tim17 => (0x4c, 18),
+                    _ => panic!("This peripheral can't be frozen on this MCU."),
+                }
+            } else if #[cfg(any(feature = "f3", feature = "f4"))] {
+                // `APB1_FZ` and `APB2_FZ` registers.
+                match self {
+                    Self::Tim2 => (0x08, 0),
+                    Self::Tim3 => (0x08, 1),
+                    Self::Tim4 => (0x08, 2),
+                    Self::Tim5 => (0x08, 3),
+                    Self::Tim6 => (0x08, 4),
+                    Self::Tim7 => (0x08, 5),
+                    Self::Rtc => (0x08, 10),
+                    Self::Wwdg => (0x08, 11),
+                    Self::Iwdg => (0x08, 12),
+                    Self::I2c1 => (0x08, 21),
+                    Self::I2c2 => (0x08, 22),
+                    #[cfg(feature = "f4")]
+                    Self::I2c3 => (0x08, 23),
+                    Self::Tim1 => (0x0c, 0),
+                    Self::Tim8 => (0x0c, 1),
+                    #[cfg(feature = "f3")]
+                    Self::Tim15 => (0x0c, 2),
+                    #[cfg(feature = "f3")]
+                    Self::Tim16 => (0x0c, 3),
+                    #[cfg(feature = "f3")]
+                    Self::Tim17 => (0x0c, 4),
+                    _ => panic!("This peripheral can't be frozen on this MCU."),
+                }
+            } else {
+                // `APB1FZR1` (`APB1L_FZ` on G4), and `APB2FZR` registers.
+                match self {
+                    Self::Tim2 => (0x08, 0),
+                    Self::Tim3 => (0x08, 1),
+                    Self::Tim4 => (0x08, 2),
+                    Self::Tim5 => (0x08, 3),
+                    Self::Tim6 => (0x08, 4),
+                    Self::Tim7 => (0x08, 5),
+                    Self::Rtc => (0x08, 10),
+                    Self::Wwdg => (0x08, 11),
+                    Self::Iwdg => (0x08, 12),
+                    Self::I2c1 => (0x08, 21),
+                    Self::I2c2 => (0x08, 22),
+                    #[cfg(feature = "g4")]
+                    Self::I2c3 => (0x08, 30),
+                    #[cfg(not(feature = "g4"))]
+                    Self::I2c3 => (0x08, 23),
+                    Self::Lptim1 => (0x08, 31),
+                    Self::Tim1 => (0x10, 11),
+                    Self::Tim8 => (0x10, 13),
+                    Self::Tim15 => (0x10, 16),
+                    Self::Tim16 => (0x10, 17),
+                    Self::Tim17 => (0x10, 18),
+                }
+            }
+        }
+    }
+}
+
+/// Stop (or resume) a peripheral's counter while the core is halted by a debugger. Sets the
+/// `DBGMCU_APBxFZx` register field corresponding to the peripheral.
+pub fn set_freeze(periph: DebugFreeze, freeze: bool) {
+    let (offset, bit) = periph.location();
+
+    free(|_| unsafe {
+        let reg = (DBGMCU::ptr() as usize + offset) as *mut u32;
+        let val = ptr::read_volatile(reg);
+
+        if freeze {
+            ptr::write_volatile(reg, val | (1 << bit));
+        } else {
+            ptr::write_volatile(reg, val & !(1 << bit));
+        }
+    });
+}
+
+/// Stop a peripheral's counter while the core is halted by a debugger.
+pub fn freeze(periph: DebugFreeze) {
+    set_freeze(periph, true);
+}
+
+/// Stop the counters of several peripherals while the core is halted by a debugger. Example:
+/// `freeze_all(&[DebugFreeze::Tim2, DebugFreeze::Iwdg, DebugFreeze::Wwdg])`.
+pub fn freeze_all(periphs: &[DebugFreeze]) {
+    for periph in periphs {
+        set_freeze(*periph, true);
+    }
+}
+
+/// Keep the debug connection (and the clocks it requires) active while the MCU is in Sleep, Stop,
+/// or Standby mode. Sets `DBGMCU_CR` register, `DBG_SLEEP`, `DBG_STOP`, and `DBG_STANDBY` fields.
+/// Note that this increases power use in these modes. `sleep` is ignored on L5, since debugging
+/// is always available in Sleep mode there.
+pub fn set_low_power_debug(sleep: bool, stop: bool, standby: bool) {
+    free(|_| {
+        let dbgmcu = unsafe { &(*DBGMCU::ptr()) };
+
+        cfg_if! {
+            if #[cfg(all(feature = "h7", not(any(feature = "h747cm4", feature = "h747cm7"))))] {
+                dbgmcu.cr.modify(|_, w| w.dbgsleep_d1().bit(sleep));
+                dbgmcu.cr.modify(|_, w| w.dbgstop_d1().bit(stop));
+                dbgmcu.cr.modify(|_, w| w.dbgstby_d1().bit(standby));
+            } else if #[cfg(feature = "h7")] {
+                dbgmcu.cr.modify(|_, w| w.dbgslpd1().bit(sleep));
+                dbgmcu.cr.modify(|_, w| w.dbgstpd1().bit(stop));
+                dbgmcu.cr.modify(|_, w| w.dbgstbd1().bit(standby));
+            } else {
+                #[cfg(not(feature = "l5"))]
+                dbgmcu.cr.modify(|_, w| w.dbg_sleep().bit(sleep));
+                #[cfg(feature = "l5")]
+                let _ = sleep;
+                dbgmcu.cr.modify(|_, w| w.dbg_stop().bit(stop));
+                dbgmcu.cr.modify(|_, w| w.dbg_standby().bit(standby));
+            }
+        }
+    });
+}
+
+/// Unlocks the ITM registers for writing. Written to the `ITM_LAR` register.
+const ITM_UNLOCK: u32 = 0xC5AC_CE55;
+
+#[cfg(not(any(feature = "h7", feature = "wl")))]
+/// Set up Serial Wire Output (SWO) at a given baud rate, and enable ITM stimulus port 0. Configures
+/// `swo`, which must be PB3, as TRACESWO, enables trace in `DCB_DEMCR`, sets `DBGMCU_CR` register,
+/// `TRACE_IOEN` field, and sets the TPIU to asynchronous NRZ (UART) mode. The TPIU prescaler is
+/// computed from the HCLK frequency, which is the trace clock on STM32. Set the same baud rate in
+/// your debug probe software.
+pub fn setup_swo(
+    dcb: &mut DCB,
+    tpiu: &mut TPIU,
+    itm: &mut ITM,
+    swo: &mut Pin,
+    clocks: &Clocks,
+    baud: u32,
+) {
+    let prescaler = clocks.hclk() / baud;
+    assert!(prescaler >= 1, "SWO baud rate must not be higher than HCLK.");
+    assert!(
+        swo.port == Port::B && swo.pin == 3,
+        "The SWO pin must be PB3."
+    );
+
+    swo.mode(PinMode::Alt(0));
+
+    dcb.enable_trace();
+
+    free(|_| {
+        let dbgmcu = unsafe { &(*DBGMCU::ptr()) };
+        // Asynchronous trace mode is `TRACE_MODE` = 0, the reset value.
+        dbgmcu.cr.modify(|_, w| w.trace_ioen().set_bit());
+    });
+
+    unsafe {
+        // `SPPR` = 2: Asynchronous NRZ (UART) encoding.
+        tpiu.sppr.write(2);
+        tpiu.acpr.write(prescaler - 1);
+        // Disable the formatter, since we only output ITM packets. (`FFCR` register, `EnFCont` field.)
+        tpiu.ffcr.modify(|v| v & !(1 << 1));
+
+        itm.lar.write(ITM_UNLOCK);
+        // `TraceBusID` = 1, `SYNCENA` and `ITMENA` fields.
+        itm.tcr.write((1 << 16) | (1 << 2) | 1);
+    }
+
+    enable_itm_port(itm, 0);
+}
+
+/// Enable an ITM stimulus port, 0 - 31. Sets `ITM_TER0` register.
+pub fn enable_itm_port(itm: &mut ITM, port: u8) {
+    assert!(port <= 31, "ITM port must be 0 - 31.");
+
+    unsafe {
+        itm.lar.write(ITM_UNLOCK);
+        itm.ter[0].modify(|v| v | (1 << port));
+    }
+}
+
+/// Writes text to an ITM stimulus port, eg with `write!` or `writeln!`. Blocks while the
+/// port's FIFO is full.
+pub struct ItmWriter<'a> {
+    itm: &'a mut ITM,
+    port: u8,
+}
+
+impl<'a> ItmWriter<'a> {
+    pub fn new(itm: &'a mut ITM, port: u8) -> Self {
+        assert!(port <= 31, "ITM port must be 0 - 31.");
+        Self { itm, port }
+    }
+}
+
+impl fmt::Write for ItmWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        itm::write_str(&mut self.itm.stim[self.port as usize], s);
+        Ok(())
+    }
+}
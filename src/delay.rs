@@ -0,0 +1,52 @@
+//! Busy-wait delays, calibrated from the core clock frequency cached by the clock configuration
+//! functions, so they stay accurate after changing clock speeds at runtime, eg with
+//! `Clocks::change_msi_speed`. They don't need a timer, or a `Clocks` reference.
+//!
+//! Delays are at least as long as requested; interrupts, and flash wait states, can extend them.
+//! After waking from Stop mode, run `Clocks::reselect_input` before using these; until then, the
+//! core runs from MSI or HSI, and the delays are shorter than requested.
+//!
+//! For delays that don't block, use a timer, or the `time` module's `Deadline`.
+
+use cortex_m::asm;
+
+use crate::clocks;
+
+// `asm::delay` assumes its loop takes at least 2 cycles per iteration, but the Cortex-M7 can
+// run it in 1, so on H7 we request twice as many.
+#[cfg(feature = "h7")]
+const CYCLES_PER_LOOP_SCALE: u64 = 2;
+#[cfg(not(feature = "h7"))]
+const CYCLES_PER_LOOP_SCALE: u64 = 1;
+
+/// Block for at least `cycles` core clock cycles.
+pub fn delay_cycles(cycles: u32) {
+    delay_cycles_long(cycles as u64);
+}
+
+/// Block for at least `ns` nanoseconds. Rounds up to a whole number of cycles.
+pub fn delay_ns(ns: u32) {
+    delay_cycles_long((clocks::core_clock() as u64 * ns as u64).div_ceil(1_000_000_000));
+}
+
+/// Block for at least `us` microseconds.
+pub fn delay_us(us: u32) {
+    delay_cycles_long(clocks::core_clock() as u64 * us as u64 / 1_000_000);
+}
+
+/// Block for at least `ms` milliseconds.
+pub fn delay_ms(ms: u32) {
+    delay_cycles_long(clocks::core_clock() as u64 * ms as u64 / 1_000);
+}
+
+/// Block for at least `cycles` cycles, which may not fit in a `u32`; eg long delays at high clock
+/// speeds.
+fn delay_cycles_long(cycles: u64) {
+    let mut cycles = cycles * CYCLES_PER_LOOP_SCALE;
+
+    while cycles > u32::MAX as u64 {
+        asm::delay(u32::MAX);
+        cycles -= u32::MAX as u64;
+    }
+    asm::delay(cycles as u32);
+}
@@ -2,7 +2,7 @@
 
 use core::ops::Deref;
 
-use cortex_m::interrupt::free;
+use crate::util::free;
 
 use num_traits::Float; // Float rounding.
 
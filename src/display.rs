@@ -0,0 +1,276 @@
+//! Support for SPI displays that use the ILI9341 / ST7789 command set, eg many small TFT panels.
+//! These use a data/command (DC) pin to distinguish command bytes from their parameters, and
+//! an optional chip select (CS) pin. Pixel data is written to a rectangular window set with the
+//! `CASET`, `RASET`, and `RAMWR` commands.
+//!
+//! Send initialization commands with `command`, then write framebuffer regions (eg when flushing
+//! an `embedded-graphics` framebuffer) with `write_pixels`, or without blocking with
+//! `write_window_dma`. A full frame is usually larger than a single DMA transfer allows (65535
+//! bytes), so DMA writes are split into chunks: Call `on_dma_complete` from the DMA channel's
+//! transfer complete interrupt handler to start the next chunk. This keeps the SPI bus busy
+//! between chunks, so transfers run at the SPI clock rate.
+//!
+//! Configure the SPI peripheral for 8-bit words, and Mode 0 (ST7789 panels without a CS pin may
+//! require Mode 3).
+
+use core::ops::Deref;
+
+use crate::{
+    gpio::Pin,
+    pac,
+    spi::{Error, Spi},
+    util::RccPeriph,
+};
+
+#[cfg(not(any(feature = "g0", feature = "h7", feature = "f4", feature = "l5")))]
+use crate::dma::{ChannelCfg, Dma, DmaChannel, DmaInterrupt};
+
+#[cfg(any(
+    feature = "f3",
+    feature = "l4",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl"
+))]
+use crate::pac::dma1 as dma_p;
+
+/// Column address set.
+pub const CMD_CASET: u8 = 0x2a;
+/// Row address set.
+pub const CMD_RASET: u8 = 0x2b;
+/// Memory write; the bytes that follow are pixel data.
+pub const CMD_RAMWR: u8 = 0x2c;
+
+/// The most bytes a DMA channel can transfer at once. (The `CNDTR` register is 16 bits)
+#[cfg(not(any(feature = "g0", feature = "h7", feature = "f4", feature = "l5")))]
+const DMA_CHUNK_MAX: usize = 65_535;
+
+#[cfg(not(any(feature = "g0", feature = "h7", feature = "f4", feature = "l5")))]
+/// The state of an in-progress, chunked DMA write.
+struct DmaWrite {
+    channel: DmaChannel,
+    channel_cfg: ChannelCfg,
+    /// Address of the next chunk to send.
+    next: u32,
+    /// Bytes remaining after the current chunk.
+    remaining: usize,
+    on_complete: Option<fn()>,
+}
+
+/// Represents an ILI9341, ST7789, or compatible display, connected over SPI.
+pub struct Display<R> {
+    pub spi: Spi<R>,
+    dc: Pin,
+    cs: Option<Pin>,
+    #[cfg(not(any(feature = "g0", feature = "h7", feature = "f4", feature = "l5")))]
+    dma_write: Option<DmaWrite>,
+}
+
+impl<R> Display<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    /// Create a new display. `dc` and `cs` must be configured as outputs. Pass `None` for `cs`
+    /// if the panel's CS is tied low, or managed elsewhere.
+    pub fn new(spi: Spi<R>, mut dc: Pin, mut cs: Option<Pin>) -> Self {
+        dc.set_high();
+        if let Some(cs) = &mut cs {
+            cs.set_high();
+        }
+
+        Self {
+            spi,
+            dc,
+            cs,
+            #[cfg(not(any(feature = "g0", feature = "h7", feature = "f4", feature = "l5")))]
+            dma_write: None,
+        }
+    }
+
+    fn select(&mut self) {
+        if let Some(cs) = &mut self.cs {
+            cs.set_low();
+        }
+    }
+
+    fn deselect(&mut self) {
+        if let Some(cs) = &mut self.cs {
+            cs.set_high();
+        }
+    }
+
+    /// Send a command byte (DC low), followed by its parameter bytes (DC high). Blocks until
+    /// complete, and leaves DC high.
+    fn send_command(&mut self, cmd: u8, data: &[u8]) -> Result<(), Error> {
+        self.dc.set_low();
+        let result = self.spi.write(&[cmd]);
+        self.dc.set_high();
+        result?;
+
+        if !data.is_empty() {
+            self.spi.write(data)?;
+        }
+        Ok(())
+    }
+
+    /// Send a command, followed by its parameters, eg for initialization. Blocking.
+    /// Example: `display.command(0x36, &[0x48])?;` (`MADCTL`)
+    pub fn command(&mut self, cmd: u8, data: &[u8]) -> Result<(), Error> {
+        self.select();
+        let result = self.send_command(cmd, data);
+        self.deselect();
+        result
+    }
+
+    /// Set the drawing window, and start a memory write. Bounds are inclusive. The pixel data that
+    /// follows fills the window left to right, then top to bottom. Leaves CS low.
+    fn start_window(&mut self, x0: u16, y0: u16, x1: u16, y1: u16) -> Result<(), Error> {
+        let [x0_h, x0_l] = x0.to_be_bytes();
+        let [x1_h, x1_l] = x1.to_be_bytes();
+        let [y0_h, y0_l] = y0.to_be_bytes();
+        let [y1_h, y1_l] = y1.to_be_bytes();
+
+        self.select();
+        self.send_command(CMD_CASET, &[x0_h, x0_l, x1_h, x1_l])?;
+        self.send_command(CMD_RASET, &[y0_h, y0_l, y1_h, y1_l])?;
+        self.send_command(CMD_RAMWR, &[])
+    }
+
+    /// Write pixel data to a window, blocking until complete. Bounds are inclusive. `buf` holds
+    /// the pixels in the panel's format, eg 2 big-endian bytes per pixel for RGB565.
+    pub fn write_pixels(&mut self, x0: u16, y0: u16, x1: u16, y1: u16, buf: &[u8]) -> Result<(), Error> {
+        let result = self
+            .start_window(x0, y0, x1, y1)
+            .and_then(|_| self.spi.write(buf));
+        self.deselect();
+        result
+    }
+
+    /// Fill a window with a single RGB565 color, blocking until complete. Bounds are inclusive.
+    pub fn fill(&mut self, x0: u16, y0: u16, x1: u16, y1: u16, color: u16) -> Result<(), Error> {
+        let num_pixels = (x1 as u32 - x0 as u32 + 1) * (y1 as u32 - y0 as u32 + 1);
+        let color = color.to_be_bytes();
+
+        let result = self.start_window(x0, y0, x1, y1).and_then(|_| {
+            for _ in 0..num_pixels {
+                self.spi.write(&color)?;
+            }
+            Ok(())
+        });
+        self.deselect();
+        result
+    }
+
+    #[cfg(not(any(feature = "g0", feature = "h7", feature = "f4", feature = "l5")))]
+    /// Write pixel data to a window using DMA. Bounds are inclusive. The window commands are
+    /// sent blocking; the pixel data is sent in chunks of up to 65535 bytes. Enable the DMA channel's
+    /// transfer complete interrupt, and call `on_dma_complete` from its handler. `on_complete` runs
+    /// (in the interrupt handler) once the whole window is written.
+    ///
+    /// # Safety
+    /// `buf` must remain valid, and unmodified, until the write is complete.
+    pub unsafe fn write_window_dma<D>(
+        &mut self,
+        x0: u16,
+        y0: u16,
+        x1: u16,
+        y1: u16,
+        buf: &[u8],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        on_complete: Option<fn()>,
+        dma: &mut Dma<D>,
+    ) -> Result<(), Error>
+    where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        assert!(self.dma_write.is_none(), "A display DMA write is already in progress.");
+
+        if let Err(e) = self.start_window(x0, y0, x1, y1) {
+            self.deselect();
+            return Err(e);
+        }
+
+        self.dma_write = Some(DmaWrite {
+            channel,
+            channel_cfg,
+            next: buf.as_ptr() as u32,
+            remaining: buf.len(),
+            on_complete,
+        });
+
+        self.start_chunk(dma);
+        Ok(())
+    }
+
+    #[cfg(not(any(feature = "g0", feature = "h7", feature = "f4", feature = "l5")))]
+    /// Start the next DMA chunk, if there's data remaining. Returns `true` if a chunk was started.
+    fn start_chunk<D>(&mut self, dma: &mut Dma<D>) -> bool
+    where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        let write = match &mut self.dma_write {
+            Some(w) if w.remaining > 0 => w,
+            _ => return false,
+        };
+
+        let len = write.remaining.min(DMA_CHUNK_MAX);
+        let chunk = unsafe { core::slice::from_raw_parts(write.next as *const u8, len) };
+        let (channel, channel_cfg) = (write.channel, write.channel_cfg.clone());
+
+        write.next += len as u32;
+        write.remaining -= len;
+
+        unsafe { self.spi.write_dma(chunk, channel, channel_cfg, dma) };
+        true
+    }
+
+    #[cfg(not(any(feature = "g0", feature = "h7", feature = "f4", feature = "l5")))]
+    /// Handle a DMA transfer complete interrupt: Clears the interrupt, and starts the next chunk.
+    /// After the last chunk, waits for the SPI to finish sending, releases CS, and runs the
+    /// completion callback. Returns `true` if the write is complete. Call this from the DMA
+    /// channel's interrupt handler.
+    pub fn on_dma_complete<D>(&mut self, dma: &mut Dma<D>) -> bool
+    where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        let channel = match &self.dma_write {
+            Some(w) => w.channel,
+            None => return true,
+        };
+
+        dma.clear_interrupt(channel, DmaInterrupt::TransferComplete);
+        self.spi.stop_dma(channel, dma);
+
+        if self.start_chunk(dma) {
+            return false;
+        }
+
+        // RM: Wait until TXE = 1, then until BSY = 0, before disabling the SPI or releasing the
+        // slave. Then clear the overrun flag, since we didn't read the data received during the
+        // transfer, by reading DR, then SR.
+        while self.spi.regs.sr.read().txe().bit_is_clear() {}
+        while self.spi.regs.sr.read().bsy().bit_is_set() {}
+        self.spi.regs.dr.read();
+        self.spi.regs.sr.read();
+
+        self.deselect();
+
+        let on_complete = self.dma_write.take().and_then(|w| w.on_complete);
+        if let Some(f) = on_complete {
+            f();
+        }
+        true
+    }
+
+    #[cfg(not(any(feature = "g0", feature = "h7", feature = "f4", feature = "l5")))]
+    /// Returns `true` if a DMA write is in progress.
+    pub fn is_busy(&self) -> bool {
+        self.dma_write.is_some()
+    }
+
+    /// Release the SPI peripheral and pins.
+    pub fn free(self) -> (Spi<R>, Pin, Option<Pin>) {
+        (self.spi, self.dc, self.cs)
+    }
+}
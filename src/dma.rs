@@ -9,9 +9,10 @@ use core::{
     sync::atomic::{self, Ordering},
 };
 
-use cortex_m::interrupt::free;
+use crate::util::free;
 
 use crate::{
+    interrupt::{self, Binding, DmaChan},
     pac::{self, RCC},
     rcc_en_reset,
 };
@@ -191,6 +192,8 @@ pub enum DmaInput {
     Sai1B = 88,
     Sai2A = 89,
     Sai2B = 90,
+    SpdifrxDat = 93,
+    SpdifrxCtrl = 94,
     Dfsdm1F0 = 101,
     Dfsdm1F1 = 102,
     Dfsdm1F2 = 103,
@@ -355,6 +358,28 @@ pub enum DmaChannel {
     C8 = 8,
 }
 
+impl DmaChannel {
+    /// Get a channel from its number. Eg `1` for `C1`.
+    pub fn from_num(num: u8) -> Self {
+        match num {
+            #[cfg(feature = "h7")]
+            0 => Self::C0,
+            1 => Self::C1,
+            2 => Self::C2,
+            3 => Self::C3,
+            4 => Self::C4,
+            5 => Self::C5,
+            #[cfg(not(feature = "g0"))]
+            6 => Self::C6,
+            #[cfg(not(feature = "g0"))]
+            7 => Self::C7,
+            #[cfg(any(feature = "l5", feature = "g4"))]
+            8 => Self::C8,
+            _ => panic!("Invalid DMA channel."),
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 #[repr(u8)]
 /// Set in CCR.
@@ -471,6 +496,7 @@ macro_rules! enable_interrupt {
 
 /// This struct is used to pass common (non-peripheral and non-use-specific) data when configuring
 /// a channel.
+#[derive(Clone)]
 pub struct ChannelCfg {
     pub priority: Priority,
     pub circular: Circular,
@@ -1181,6 +1207,49 @@ where
         // TEIFx bit of the DMA_ISR register is set
     }
 
+    /// Start another transfer on a channel that's already configured with `cfg_channel`, eg after
+    /// its transfer completes, with a new memory address and length. The peripheral address,
+    /// direction, and data sizes are unchanged. Sets the `CMAR` and `CNDTR` registers, or `M0AR`
+    /// and `NDTR` on H7, then re-enables the channel.
+    pub fn reload(&mut self, channel: DmaChannel, mem_addr: u32, num_data: u32) {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                let st = &self.regs.st[channel as usize];
+                st.cr.modify(|_, w| w.en().clear_bit());
+                while st.cr.read().en().bit_is_set() {}
+
+                // "Before setting EN bit to '1' to start a new transfer, the event flags
+                // corresponding to the stream in DMA_LISR or DMA_HISR register must be cleared."
+                clear_all_interrupts(&self.regs, channel);
+
+                st.m0ar.write(|w| unsafe { w.bits(mem_addr) });
+                st.ndtr.write(|w| unsafe { w.bits(num_data) });
+
+                atomic::compiler_fence(Ordering::SeqCst);
+                st.cr.modify(|_, w| w.en().set_bit());
+            } else {
+                // The channel registers are accessed by offset, since their names, and whether
+                // they're in a cluster, vary by PAC. Channel x's `CCR` is at 0x08 + 0x14 * (x - 1),
+                // followed by `CNDTR`, `CPAR`, and `CMAR`.
+                assert!(num_data <= 0xffff, "DMA transfers are limited to 65,535 items.");
+
+                let base = &*self.regs as *const _ as usize;
+                let ccr = (base + 0x08 + 0x14 * (channel as usize - 1)) as *mut u32;
+
+                unsafe {
+                    ccr.write_volatile(ccr.read_volatile() & !1);
+                    while ccr.read_volatile() & 1 != 0 {}
+
+                    ccr.add(1).write_volatile(num_data);
+                    ccr.add(3).write_volatile(mem_addr);
+
+                    atomic::compiler_fence(Ordering::SeqCst);
+                    ccr.write_volatile(ccr.read_volatile() | 1);
+                }
+            }
+        }
+    }
+
     // todo: G0 removed from this fn due to a bug introduced in PAC 0.13
     #[cfg(not(any(feature = "h7", feature = "g0")))]
     pub fn transfer_is_complete(&mut self, channel: DmaChannel) -> bool {
@@ -1379,6 +1448,18 @@ where
         }
     }
 
+    /// Enable an interrupt, and unmask the channel's interrupt line in the NVIC. `irqs` is the token
+    /// created by `bind_interrupts!`, and proves a handler exists for this channel. Eg:
+    /// `dma.bind_interrupt::<1>(DmaInterrupt::TransferComplete, &Irqs)`.
+    pub fn bind_interrupt<const CH: u8>(
+        &mut self,
+        interrupt: DmaInterrupt,
+        irqs: &impl Binding<DmaChan<D, CH>>,
+    ) {
+        self.enable_interrupt(DmaChannel::from_num(CH), interrupt);
+        interrupt::unmask(irqs);
+    }
+
     pub fn clear_interrupt(&mut self, channel: DmaChannel, interrupt: DmaInterrupt) {
         cfg_if! {
             if #[cfg(any(feature = "g4", feature = "wl"))] {
@@ -1536,6 +1617,94 @@ where
     }
 }
 
+/// Clear all of a channel's interrupt flags. This is called by the interrupt handlers that
+/// `bind_interrupts!` defines.
+pub fn clear_all_interrupts(regs: &dma::RegisterBlock, channel: DmaChannel) {
+    let mut dma = Dma { regs };
+
+    dma.clear_interrupt(channel, DmaInterrupt::TransferError);
+    dma.clear_interrupt(channel, DmaInterrupt::HalfTransfer);
+    dma.clear_interrupt(channel, DmaInterrupt::TransferComplete);
+    #[cfg(feature = "h7")]
+    dma.clear_interrupt(channel, DmaInterrupt::DirectModeError);
+    #[cfg(feature = "h7")]
+    dma.clear_interrupt(channel, DmaInterrupt::FifoError);
+}
+
+/// Transmits several non-contiguous buffers as one logical memory-to-peripheral transfer, eg a
+/// protocol header, payload, and checksum, without copying them into one buffer. Start the first
+/// buffer with the peripheral's `write_dma` method; from the channel's Transfer Complete interrupt,
+/// call `advance`, which re-arms the channel with the next buffer. There's a short gap between
+/// buffers while the interrupt runs.
+///
+/// Example:
+/// ```
+/// let mut chain = DmaChain::new(&[&HEADER, &payload, &crc], DmaChannel::C3);
+/// unsafe { spi.write_dma(chain.first(), DmaChannel::C3, Default::default(), &mut dma) };
+///
+/// // In the DMA channel 3 interrupt handler:
+/// dma.clear_interrupt(DmaChannel::C3, DmaInterrupt::TransferComplete);
+/// if !unsafe { chain.advance(&mut dma) } {
+///     // The whole chain has been sent.
+/// }
+/// ```
+///
+/// On H7, the MDMA's hardware linked lists, in the `mdma` module, don't need the interrupt.
+pub struct DmaChain<'a> {
+    bufs: &'a [&'a [u8]],
+    /// The channel the peripheral's `write_dma` was started on. On F3 and L4, this is the fixed
+    /// channel for the peripheral.
+    channel: DmaChannel,
+    /// The index of the next buffer to send.
+    next: usize,
+}
+
+impl<'a> DmaChain<'a> {
+    pub fn new(bufs: &'a [&'a [u8]], channel: DmaChannel) -> Self {
+        assert!(!bufs.is_empty(), "A DMA chain must have at least one buffer.");
+
+        Self {
+            bufs,
+            channel,
+            next: 0,
+        }
+    }
+
+    /// Returns the first buffer, to start the transfer with, eg with `write_dma`.
+    pub fn first(&mut self) -> &'a [u8] {
+        self.next = 1;
+        self.bufs[0]
+    }
+
+    /// Re-arm the channel with the next non-empty buffer. Call this from the channel's Transfer
+    /// Complete interrupt. Returns `false`, without starting a transfer, once every buffer has
+    /// been sent.
+    ///
+    /// # Safety
+    /// The buffers must stay valid until the chain is complete.
+    pub unsafe fn advance<D>(&mut self, dma: &mut Dma<D>) -> bool
+    where
+        D: Deref<Target = dma::RegisterBlock>,
+    {
+        while self.next < self.bufs.len() {
+            let buf = self.bufs[self.next];
+            self.next += 1;
+
+            if !buf.is_empty() {
+                dma.reload(self.channel, buf.as_ptr() as u32, buf.len() as u32);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns `true` once every buffer has been started; the last one is complete at its
+    /// Transfer Complete interrupt.
+    pub fn is_complete(&self) -> bool {
+        self.next >= self.bufs.len()
+    }
+}
+
 #[cfg(any(
     feature = "l5",
     feature = "g0",
@@ -0,0 +1,425 @@
+//! Support for external NOR flash memory, eg W25Q or MX25 series, using standard JEDEC
+//! commands over either QSPI (in single-line mode) or a plain SPI peripheral with a GPIO chip
+//! select. The flash's size and erase instruction are read from its SFDP (Serial Flash
+//! Discoverable Parameters) table, falling back to the JEDEC ID.
+//!
+//! With the `embedded-storage` feature, this implements its `NorFlash` traits, so crates like
+//! `sequential-storage` and `littlefs2` can use it directly.
+//!
+//! Example, using SPI1, with PA4 as chip select:
+//!
+//! `let bus = SpiFlashBus::new(spi, Pin::new(Port::A, 4, PinMode::Output));`
+//! `let mut flash = ExtFlash::new(bus)?;`
+//! `flash.erase_sector(0)?;`
+//! `flash.write(0, &[1, 2, 3])?;`
+
+use core::ops::Deref;
+
+#[cfg(feature = "embedded-storage")]
+use embedded_storage::nor_flash::{
+    self, ErrorType, MultiwriteNorFlash, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::{
+    gpio::Pin,
+    pac,
+    spi::{self, Spi},
+    util::RccPeriph,
+};
+
+/// Program (write) commands can't cross a page boundary.
+const PAGE_SIZE: u32 = 256;
+/// The smallest erasable unit on nearly all SPI NOR flash.
+pub const SECTOR_SIZE: u32 = 4_096;
+
+/// The "SFDP" signature, at the start of the SFDP table.
+const SFDP_SIGNATURE: u32 = 0x5044_4653;
+
+/// Standard commands, supported by nearly all SPI NOR flash.
+mod cmd {
+    pub const WRITE_ENABLE: u8 = 0x06;
+    pub const READ_STATUS: u8 = 0x05;
+    pub const READ: u8 = 0x03;
+    pub const PAGE_PROGRAM: u8 = 0x02;
+    pub const SECTOR_ERASE: u8 = 0x20;
+    pub const CHIP_ERASE: u8 = 0xc7;
+    pub const READ_JEDEC_ID: u8 = 0x9f;
+    pub const READ_SFDP: u8 = 0x5a;
+    pub const ENTER_4_BYTE_ADDRESS: u8 = 0xb7;
+}
+
+/// The status register's write-in-progress bit.
+const STATUS_WIP: u8 = 1;
+
+/// The data phase of a flash command.
+pub enum Data<'a> {
+    None,
+    Write(&'a [u8]),
+    Read(&'a mut [u8]),
+}
+
+/// A bus a flash chip is connected to. Implemented for `Qspi`, and `SpiFlashBus`.
+pub trait FlashBus {
+    type Error;
+
+    /// Send an instruction, followed by an optional address (`address_bytes` long), `dummy_bytes`
+    /// dummy bytes, then the data phase. All phases use a single data line.
+    fn command(
+        &mut self,
+        instruction: u8,
+        address: Option<u32>,
+        address_bytes: u8,
+        dummy_bytes: u8,
+        data: Data,
+    ) -> Result<(), Self::Error>;
+}
+
+/// A flash chip on a plain SPI bus, with a GPIO pin, configured as an output, as chip select.
+pub struct SpiFlashBus<R> {
+    pub spi: Spi<R>,
+    pub cs: Pin,
+}
+
+impl<R> SpiFlashBus<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    /// Sets the chip select pin high (inactive).
+    pub fn new(spi: Spi<R>, mut cs: Pin) -> Self {
+        cs.set_high();
+        Self { spi, cs }
+    }
+
+    fn transaction(
+        &mut self,
+        header: &[u8],
+        dummy_bytes: u8,
+        data: Data,
+    ) -> Result<(), spi::Error> {
+        self.spi.write(header)?;
+        for _ in 0..dummy_bytes {
+            self.spi.write(&[0])?;
+        }
+
+        match data {
+            Data::None => Ok(()),
+            Data::Write(buf) => self.spi.write(buf),
+            Data::Read(buf) => {
+                buf.fill(0);
+                self.spi.transfer(buf)
+            }
+        }
+    }
+}
+
+impl<R> FlashBus for SpiFlashBus<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    type Error = spi::Error;
+
+    fn command(
+        &mut self,
+        instruction: u8,
+        address: Option<u32>,
+        address_bytes: u8,
+        dummy_bytes: u8,
+        data: Data,
+    ) -> Result<(), Self::Error> {
+        let mut header = [instruction, 0, 0, 0, 0];
+        let mut header_len = 1;
+
+        if let Some(addr) = address {
+            let addr = addr.to_be_bytes();
+            let len = address_bytes as usize;
+            header[1..1 + len].copy_from_slice(&addr[4 - len..]);
+            header_len += len;
+        }
+
+        self.cs.set_low();
+        let result = self.transaction(&header[..header_len], dummy_bytes, data);
+        self.cs.set_high();
+
+        result
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Errors from external flash operations.
+pub enum ExtFlashError<E> {
+    /// An error from the underlying bus.
+    Bus(E),
+    /// The operation extends past the end of the flash.
+    OutOfBounds,
+    /// An erase address isn't aligned to `SECTOR_SIZE`.
+    NotAligned,
+    /// The flash's size couldn't be determined from its SFDP table or JEDEC ID.
+    UnknownSize,
+}
+
+impl<E> From<E> for ExtFlashError<E> {
+    fn from(e: E) -> Self {
+        Self::Bus(e)
+    }
+}
+
+/// An external NOR flash chip.
+pub struct ExtFlash<B: FlashBus> {
+    pub bus: B,
+    /// The flash size, in bytes.
+    capacity: u32,
+    /// 3, or 4 for flash larger than 16MB.
+    address_bytes: u8,
+    /// The instruction for erasing a 4kB sector.
+    erase_instruction: u8,
+}
+
+impl<B: FlashBus> ExtFlash<B> {
+    /// Identify the flash, by reading its SFDP table, or if it doesn't have one, its JEDEC ID. For
+    /// flash larger than 16MB, switches it to 4-byte addressing.
+    pub fn new(bus: B) -> Result<Self, ExtFlashError<B::Error>> {
+        let mut result = Self {
+            bus,
+            capacity: 0,
+            address_bytes: 3,
+            erase_instruction: cmd::SECTOR_ERASE,
+        };
+
+        if !result.read_sfdp_params()? {
+            // Most vendors encode the size as a power of 2 in the third JEDEC ID byte.
+            let id = result.read_jedec_id()?;
+            if !(16..=31).contains(&id[2]) {
+                return Err(ExtFlashError::UnknownSize);
+            }
+            result.capacity = 1 << id[2];
+        }
+
+        if result.capacity > 1 << 24 {
+            result
+                .bus
+                .command(cmd::ENTER_4_BYTE_ADDRESS, None, 0, 0, Data::None)?;
+            result.address_bytes = 4;
+        }
+
+        Ok(result)
+    }
+
+    /// Read the manufacturer ID, memory type, and capacity code.
+    pub fn read_jedec_id(&mut self) -> Result<[u8; 3], ExtFlashError<B::Error>> {
+        let mut id = [0; 3];
+        self.bus
+            .command(cmd::READ_JEDEC_ID, None, 0, 0, Data::Read(&mut id))?;
+        Ok(id)
+    }
+
+    /// Read from the SFDP table. (This always uses a 3-byte address, and 1 dummy byte.)
+    pub fn read_sfdp(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), ExtFlashError<B::Error>> {
+        self.bus
+            .command(cmd::READ_SFDP, Some(addr), 3, 1, Data::Read(buf))?;
+        Ok(())
+    }
+
+    /// Read the size and 4kB erase instruction from the JEDEC basic flash parameter table.
+    /// Returns `false` if the flash doesn't have an SFDP table. See JESD216: "Serial Flash
+    /// Discoverable Parameters".
+    fn read_sfdp_params(&mut self) -> Result<bool, ExtFlashError<B::Error>> {
+        let mut header = [0; 16];
+        self.read_sfdp(0, &mut header)?;
+
+        if u32::from_le_bytes(header[0..4].try_into().unwrap()) != SFDP_SIGNATURE {
+            return Ok(false);
+        }
+
+        // The first parameter header, following the SFDP header, is always the basic flash
+        // parameter table's. Bytes 4-6 are its address.
+        let table_addr = u32::from_le_bytes([header[12], header[13], header[14], 0]);
+
+        let mut params = [0; 8];
+        self.read_sfdp(table_addr, &mut params)?;
+
+        let dword1 = u32::from_le_bytes(params[0..4].try_into().unwrap());
+        let dword2 = u32::from_le_bytes(params[4..8].try_into().unwrap());
+
+        // 1st DWORD, bits 1:0: 0b01 if 4kB erase is supported, with the instruction in bits 15:8.
+        if dword1 & 0b11 == 0b01 {
+            self.erase_instruction = (dword1 >> 8) as u8;
+        }
+
+        // 2nd DWORD: The density, in bits. If bit 31 is set, the other bits are N, for 2^N bits;
+        // otherwise, they're the density minus 1.
+        let density_bits = if dword2 & (1 << 31) != 0 {
+            let n = dword2 & 0x7fff_ffff;
+            if !(3..35).contains(&n) {
+                return Err(ExtFlashError::UnknownSize);
+            }
+            1_u64 << n
+        } else {
+            dword2 as u64 + 1
+        };
+
+        self.capacity = (density_bits / 8).min(u32::MAX as u64) as u32;
+        Ok(true)
+    }
+
+    /// The flash size, in bytes.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    fn check_bounds(&self, addr: u32, len: usize) -> Result<(), ExtFlashError<B::Error>> {
+        if addr as u64 + len as u64 > self.capacity as u64 {
+            return Err(ExtFlashError::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Read the status register.
+    pub fn read_status(&mut self) -> Result<u8, ExtFlashError<B::Error>> {
+        let mut status = [0];
+        self.bus
+            .command(cmd::READ_STATUS, None, 0, 0, Data::Read(&mut status))?;
+        Ok(status[0])
+    }
+
+    /// Block until any program or erase operation in progress is complete.
+    pub fn wait_ready(&mut self) -> Result<(), ExtFlashError<B::Error>> {
+        while self.read_status()? & STATUS_WIP != 0 {}
+        Ok(())
+    }
+
+    fn write_enable(&mut self) -> Result<(), ExtFlashError<B::Error>> {
+        self.bus
+            .command(cmd::WRITE_ENABLE, None, 0, 0, Data::None)?;
+        Ok(())
+    }
+
+    /// Read data, starting at `addr`.
+    pub fn read(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), ExtFlashError<B::Error>> {
+        self.check_bounds(addr, buf.len())?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        self.bus.command(
+            cmd::READ,
+            Some(addr),
+            self.address_bytes,
+            0,
+            Data::Read(buf),
+        )?;
+        Ok(())
+    }
+
+    /// Write data, starting at `addr`, splitting it into page program commands. Blocks until
+    /// complete. As with any NOR flash, writing can only clear bits; erase first.
+    pub fn write(&mut self, mut addr: u32, mut data: &[u8]) -> Result<(), ExtFlashError<B::Error>> {
+        self.check_bounds(addr, data.len())?;
+
+        while !data.is_empty() {
+            let len = ((PAGE_SIZE - addr % PAGE_SIZE) as usize).min(data.len());
+
+            self.write_enable()?;
+            self.bus.command(
+                cmd::PAGE_PROGRAM,
+                Some(addr),
+                self.address_bytes,
+                0,
+                Data::Write(&data[..len]),
+            )?;
+            self.wait_ready()?;
+
+            addr += len as u32;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Erase the `SECTOR_SIZE` sector starting at `addr`, setting it to 0xff. Blocks until
+    /// complete.
+    pub fn erase_sector(&mut self, addr: u32) -> Result<(), ExtFlashError<B::Error>> {
+        if addr % SECTOR_SIZE != 0 {
+            return Err(ExtFlashError::NotAligned);
+        }
+        self.check_bounds(addr, SECTOR_SIZE as usize)?;
+
+        self.write_enable()?;
+        self.bus.command(
+            self.erase_instruction,
+            Some(addr),
+            self.address_bytes,
+            0,
+            Data::None,
+        )?;
+        self.wait_ready()
+    }
+
+    /// Erase the whole chip. Blocks until complete; this can take minutes on large chips.
+    pub fn erase_chip(&mut self) -> Result<(), ExtFlashError<B::Error>> {
+        self.write_enable()?;
+        self.bus.command(cmd::CHIP_ERASE, None, 0, 0, Data::None)?;
+        self.wait_ready()
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<E: core::fmt::Debug> nor_flash::NorFlashError for ExtFlashError<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<B: FlashBus> ErrorType for ExtFlash<B>
+where
+    B::Error: core::fmt::Debug,
+{
+    type Error = ExtFlashError<B::Error>;
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<B: FlashBus> ReadNorFlash for ExtFlash<B>
+where
+    B::Error: core::fmt::Debug,
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        ExtFlash::read(self, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<B: FlashBus> NorFlash for ExtFlash<B>
+where
+    B::Error: core::fmt::Debug,
+{
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to || to % SECTOR_SIZE != 0 {
+            return Err(ExtFlashError::NotAligned);
+        }
+
+        for addr in (from..to).step_by(SECTOR_SIZE as usize) {
+            self.erase_sector(addr)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        ExtFlash::write(self, offset, bytes)
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+/// NOR flash bits can be cleared by repeated writes, without erasing.
+impl<B: FlashBus> MultiwriteNorFlash for ExtFlash<B> where B::Error: core::fmt::Debug {}
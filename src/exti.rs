@@ -0,0 +1,215 @@
+//! Support for the Extended Interrupts and Events Controller (EXTI). Each EXTI line can trigger an
+//! interrupt on a rising and/or falling edge. Lines 0 - 15 connect to the GPIO pins with that
+//! number, on the port selected with `Exti::select_port`; `Pin::enable_interrupt` configures
+//! these. Higher lines connect to internal peripherals, eg the RTC or PVD; see the RM's "EXTI
+//! lines connections" table for your MCU.
+//!
+//! `Exti` covers the register differences between families: G0 and L5 have separate rising and
+//! falling pending flags, and select ports in the EXTI peripheral instead of SYSCFG; F4 and F373
+//! name their registers without the `1` suffix; H7 and WL have a mask register per core. Only
+//! lines 0 - 31 are supported.
+
+use crate::{gpio::Port, pac};
+
+use cfg_if::cfg_if;
+
+// Field names differ between families, and some are missing from the PAC, so we set bits directly.
+// These macros select each family's register names.
+cfg_if! {
+    if #[cfg(any(feature = "f4", feature = "f373"))] {
+        macro_rules! imr { ($exti:expr) => { $exti.imr }; }
+        macro_rules! rtsr { ($exti:expr) => { $exti.rtsr }; }
+        macro_rules! ftsr { ($exti:expr) => { $exti.ftsr }; }
+        macro_rules! swier { ($exti:expr) => { $exti.swier }; }
+        macro_rules! pr { ($exti:expr) => { $exti.pr }; }
+    } else {
+        cfg_if! {
+            if #[cfg(any(feature = "h747cm7", feature = "wl"))] {
+                macro_rules! imr { ($exti:expr) => { $exti.c1imr1 }; }
+            } else if #[cfg(feature = "h747cm4")] {
+                macro_rules! imr { ($exti:expr) => { $exti.c2imr1 }; }
+            } else if #[cfg(feature = "h7")] {
+                macro_rules! imr { ($exti:expr) => { $exti.cpuimr1 }; }
+            } else {
+                macro_rules! imr { ($exti:expr) => { $exti.imr1 }; }
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "h747cm7")] {
+                macro_rules! pr { ($exti:expr) => { $exti.c1pr1 }; }
+            } else if #[cfg(feature = "h747cm4")] {
+                macro_rules! pr { ($exti:expr) => { $exti.c2pr1 }; }
+            } else if #[cfg(feature = "h7")] {
+                macro_rules! pr { ($exti:expr) => { $exti.cpupr1 }; }
+            } else if #[cfg(not(any(feature = "g0", feature = "l5")))] {
+                macro_rules! pr { ($exti:expr) => { $exti.pr1 }; }
+            }
+        }
+
+        macro_rules! rtsr { ($exti:expr) => { $exti.rtsr1 }; }
+        macro_rules! ftsr { ($exti:expr) => { $exti.ftsr1 }; }
+        macro_rules! swier { ($exti:expr) => { $exti.swier1 }; }
+    }
+}
+
+/// Set or clear a single bit in a register, leaving the others unchanged.
+macro_rules! set_bit {
+    ($reg:expr, $bit:expr, $value:expr) => {
+        $reg.modify(|r, w| unsafe {
+            w.bits(if $value {
+                r.bits() | (1 << $bit)
+            } else {
+                r.bits() & !(1 << $bit)
+            })
+        })
+    };
+}
+
+/// The width of each line's port selection field in the `EXTICR` registers, in bits.
+#[cfg(any(feature = "g0", feature = "l5"))]
+const EXTICR_WIDTH: u8 = 8;
+#[cfg(not(any(feature = "g0", feature = "l5")))]
+const EXTICR_WIDTH: u8 = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// The pulse edge used to trigger interrupts.
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// An EXTI line, from 0 to 31. This doesn't own the line; create one wherever it's needed, eg in
+/// an interrupt handler to clear its pending flag.
+pub struct Exti {
+    line: u8,
+}
+
+impl Exti {
+    pub fn new(line: u8) -> Self {
+        assert!(line <= 31, "EXTI line must be 0 - 31.");
+        Self { line }
+    }
+
+    /// The line number.
+    pub fn line(&self) -> u8 {
+        self.line
+    }
+
+    /// Set which edges trigger this line. Sets the `RTSR` and `FTSR` registers.
+    pub fn set_edge(&mut self, edge: Edge) {
+        let (rising, falling) = match edge {
+            Edge::Rising => (true, false),
+            Edge::Falling => (false, true),
+            Edge::Both => (true, true),
+        };
+
+        let exti = unsafe { &(*pac::EXTI::ptr()) };
+        set_bit!(rtsr!(exti), self.line, rising);
+        set_bit!(ftsr!(exti), self.line, falling);
+    }
+
+    /// Disable both edge triggers, eg before reassigning a GPIO line to a different port. Clears
+    /// the `RTSR` and `FTSR` registers' bits for this line.
+    pub fn disable_edges(&mut self) {
+        let exti = unsafe { &(*pac::EXTI::ptr()) };
+        set_bit!(rtsr!(exti), self.line, false);
+        set_bit!(ftsr!(exti), self.line, false);
+    }
+
+    /// Unmask this line, so it generates interrupts. Sets the `IMR` register.
+    pub fn unmask(&mut self) {
+        let exti = unsafe { &(*pac::EXTI::ptr()) };
+        set_bit!(imr!(exti), self.line, true);
+    }
+
+    /// Mask this line, so it no longer generates interrupts. Its pending flag is still set on
+    /// edges. Clears the `IMR` register's bit.
+    pub fn mask(&mut self) {
+        let exti = unsafe { &(*pac::EXTI::ptr()) };
+        set_bit!(imr!(exti), self.line, false);
+    }
+
+    /// Returns `true` if this line is unmasked. Reads the `IMR` register.
+    pub fn is_unmasked(&self) -> bool {
+        let exti = unsafe { &(*pac::EXTI::ptr()) };
+        imr!(exti).read().bits() & (1 << self.line) != 0
+    }
+
+    /// Returns `true` if a selected edge occurred on this line, and the flag hasn't been cleared.
+    /// Reads the `PR` register (`RPR` and `FPR` on G0 and L5).
+    pub fn is_pending(&self) -> bool {
+        let exti = unsafe { &(*pac::EXTI::ptr()) };
+
+        cfg_if! {
+            if #[cfg(any(feature = "g0", feature = "l5"))] {
+                (exti.rpr1.read().bits() | exti.fpr1.read().bits()) & (1 << self.line) != 0
+            } else {
+                pr!(exti).read().bits() & (1 << self.line) != 0
+            }
+        }
+    }
+
+    /// Clear this line's pending flag. Run this in the interrupt handler, or it fires again. Sets
+    /// the `PR` register (`RPR` and `FPR` on G0 and L5). Atomic.
+    pub fn clear_pending(&mut self) {
+        let exti = unsafe { &(*pac::EXTI::ptr()) };
+
+        // The flags are cleared by writing 1, so we don't need to read-modify-write.
+        cfg_if! {
+            if #[cfg(any(feature = "g0", feature = "l5"))] {
+                exti.rpr1.write(|w| unsafe { w.bits(1 << self.line) });
+                exti.fpr1.write(|w| unsafe { w.bits(1 << self.line) });
+            } else {
+                pr!(exti).write(|w| unsafe { w.bits(1 << self.line) });
+            }
+        }
+    }
+
+    /// Trigger this line from software. It must be unmasked to generate an interrupt. Useful for
+    /// deferring work to a lower-priority interrupt, or for testing interrupt handlers. Sets the
+    /// `SWIER` register. Atomic.
+    pub fn trigger(&mut self) {
+        let exti = unsafe { &(*pac::EXTI::ptr()) };
+        // Writing 0 to a bit has no effect, so we don't need to read-modify-write.
+        swier!(exti).write(|w| unsafe { w.bits(1 << self.line) });
+    }
+
+    /// For lines 0 - 15, select which GPIO port's pin connects to this line. Only one port can use
+    /// each line. Sets the `SYSCFG_EXTICR` registers (`EXTI_EXTICR` on G0 and L5). The SYSCFG
+    /// peripheral clock must be enabled, on families that use it.
+    pub fn select_port(&mut self, port: Port) {
+        assert!(
+            self.line <= 15,
+            "Only EXTI lines 0 - 15 connect to GPIO pins."
+        );
+
+        cfg_if! {
+            if #[cfg(any(feature = "g0", feature = "l5"))] {
+                let regs = unsafe { &(*pac::EXTI::ptr()) };
+            } else {
+                let regs = unsafe { &(*pac::SYSCFG::ptr()) };
+            }
+        }
+
+        let shift = (self.line % 4) * EXTICR_WIDTH;
+        let mask = ((1 << EXTICR_WIDTH) - 1) << shift;
+        let val = (port.cr_val() as u32) << shift;
+
+        match self.line / 4 {
+            0 => regs
+                .exticr1
+                .modify(|r, w| unsafe { w.bits((r.bits() & !mask) | val) }),
+            1 => regs
+                .exticr2
+                .modify(|r, w| unsafe { w.bits((r.bits() & !mask) | val) }),
+            2 => regs
+                .exticr3
+                .modify(|r, w| unsafe { w.bits((r.bits() & !mask) | val) }),
+            _ => regs
+                .exticr4
+                .modify(|r, w| unsafe { w.bits((r.bits() & !mask) | val) }),
+        }
+    }
+}
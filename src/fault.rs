@@ -0,0 +1,224 @@
+//! Decoding of HardFault, MemManage, BusFault, and UsageFault exceptions, from the fault status
+//! registers, and the exception frame. Reports the cause, the faulting address, and the program
+//! counter and stack pointer, eg over a U[S]ART, before resetting. Detects stack overflows into
+//! the guard region set by `mpu::install_stack_guard`.
+//!
+//! Example, with `cortex-m-rt`:
+//!
+//! ```
+//! #[exception]
+//! unsafe fn HardFault(ef: &ExceptionFrame) -> ! {
+//!     let info = FaultInfo::read(ef as *const _ as u32);
+//!     fault::report(&mut writer, &info).ok();
+//!     power::system_reset()
+//! }
+//! ```
+//!
+//! Not available on G0, whose Cortex-M0+ core doesn't have the fault status registers. See the
+//! Cortex-M4 Devices Generic User Guide, section 4.3.10: "Configurable Fault Status Register".
+
+use core::fmt;
+
+use crate::mpu;
+
+// System control block fault registers.
+const SCB_CFSR: usize = 0xe000_ed28;
+const SCB_HFSR: usize = 0xe000_ed2c;
+const SCB_MMFAR: usize = 0xe000_ed34;
+const SCB_BFAR: usize = 0xe000_ed38;
+
+// `SCB_CFSR` register fields. MemManage fault status.
+const CFSR_IACCVIOL: u32 = 1 << 0;
+const CFSR_DACCVIOL: u32 = 1 << 1;
+const CFSR_MUNSTKERR: u32 = 1 << 3;
+const CFSR_MSTKERR: u32 = 1 << 4;
+const CFSR_MMARVALID: u32 = 1 << 7;
+// BusFault status.
+const CFSR_IBUSERR: u32 = 1 << 8;
+const CFSR_PRECISERR: u32 = 1 << 9;
+const CFSR_IMPRECISERR: u32 = 1 << 10;
+const CFSR_UNSTKERR: u32 = 1 << 11;
+const CFSR_STKERR: u32 = 1 << 12;
+const CFSR_BFARVALID: u32 = 1 << 15;
+// UsageFault status.
+const CFSR_UNDEFINSTR: u32 = 1 << 16;
+const CFSR_INVSTATE: u32 = 1 << 17;
+const CFSR_INVPC: u32 = 1 << 18;
+const CFSR_NOCP: u32 = 1 << 19;
+const CFSR_UNALIGNED: u32 = 1 << 24;
+const CFSR_DIVBYZERO: u32 = 1 << 25;
+
+// `SCB_HFSR` register fields.
+const HFSR_VECTTBL: u32 = 1 << 1;
+
+/// The offsets of the stacked LR and PC in the exception frame, in words.
+const FRAME_LR: usize = 5;
+const FRAME_PC: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The cause of a fault, decoded from the `SCB_CFSR` and `SCB_HFSR` registers.
+pub enum FaultCause {
+    /// The stack overflowed into the guard region, or stacking the exception frame failed.
+    StackOverflow,
+    /// An access to memory the MPU doesn't allow, eg executing from a no-execute region.
+    MemoryAccess,
+    /// A bus error on an instruction fetch, or data access, eg to an unclocked peripheral.
+    Bus,
+    UndefinedInstruction,
+    /// An invalid EPSR state, eg a branch to an address without the Thumb bit set.
+    InvalidState,
+    /// An invalid `EXC_RETURN` value.
+    InvalidReturn,
+    /// An FPU instruction, with the FPU disabled.
+    NoCoprocessor,
+    UnalignedAccess,
+    DivideByZero,
+    /// A bus fault reading the vector table.
+    VectorTable,
+    Unknown,
+}
+
+impl fmt::Display for FaultCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Self::StackOverflow => "stack overflow",
+            Self::MemoryAccess => "memory access violation",
+            Self::Bus => "bus error",
+            Self::UndefinedInstruction => "undefined instruction",
+            Self::InvalidState => "invalid state",
+            Self::InvalidReturn => "invalid exception return",
+            Self::NoCoprocessor => "coprocessor (FPU) disabled",
+            Self::UnalignedAccess => "unaligned access",
+            Self::DivideByZero => "divide by zero",
+            Self::VectorTable => "vector table read error",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Information about a fault.
+pub struct FaultInfo {
+    pub cause: FaultCause,
+    /// The faulting data address, if valid. From `SCB_MMFAR` or `SCB_BFAR`.
+    pub address: Option<u32>,
+    /// The program counter, and link register, from the exception frame. `None` if stacking the
+    /// frame failed.
+    pub pc: Option<u32>,
+    pub lr: Option<u32>,
+    /// The stack pointer, after stacking the exception frame: The frame's address.
+    pub sp: u32,
+    /// The raw `SCB_CFSR` and `SCB_HFSR` register values.
+    pub cfsr: u32,
+    pub hfsr: u32,
+}
+
+impl FaultInfo {
+    /// Read and decode the fault status registers, and the exception frame at address `frame`:
+    /// The stack pointer the handler was entered with, eg the `ExceptionFrame` passed to
+    /// `cortex-m-rt`'s HardFault handler.
+    ///
+    /// # Safety
+    /// `frame` must be the address of the exception frame, in a fault handler.
+    pub unsafe fn read(frame: u32) -> Self {
+        let cfsr = read(SCB_CFSR);
+        let hfsr = read(SCB_HFSR);
+
+        let address = if cfsr & CFSR_MMARVALID != 0 {
+            Some(read(SCB_MMFAR))
+        } else if cfsr & CFSR_BFARVALID != 0 {
+            Some(read(SCB_BFAR))
+        } else {
+            None
+        };
+
+        let in_guard = match (mpu::stack_guard(), address) {
+            (Some(guard), Some(addr)) => addr >= guard && addr < guard + mpu::STACK_GUARD_SIZE,
+            _ => false,
+        };
+
+        let cause = if cfsr & (CFSR_MSTKERR | CFSR_STKERR) != 0 || in_guard {
+            FaultCause::StackOverflow
+        } else if cfsr & (CFSR_IACCVIOL | CFSR_DACCVIOL | CFSR_MUNSTKERR) != 0 {
+            FaultCause::MemoryAccess
+        } else if cfsr & (CFSR_IBUSERR | CFSR_PRECISERR | CFSR_IMPRECISERR | CFSR_UNSTKERR) != 0 {
+            FaultCause::Bus
+        } else if cfsr & CFSR_UNDEFINSTR != 0 {
+            FaultCause::UndefinedInstruction
+        } else if cfsr & CFSR_INVSTATE != 0 {
+            FaultCause::InvalidState
+        } else if cfsr & CFSR_INVPC != 0 {
+            FaultCause::InvalidReturn
+        } else if cfsr & CFSR_NOCP != 0 {
+            FaultCause::NoCoprocessor
+        } else if cfsr & CFSR_UNALIGNED != 0 {
+            FaultCause::UnalignedAccess
+        } else if cfsr & CFSR_DIVBYZERO != 0 {
+            FaultCause::DivideByZero
+        } else if hfsr & HFSR_VECTTBL != 0 {
+            FaultCause::VectorTable
+        } else {
+            FaultCause::Unknown
+        };
+
+        // If stacking failed, the frame contents are invalid.
+        let (pc, lr) = if cfsr & (CFSR_MSTKERR | CFSR_STKERR) != 0 {
+            (None, None)
+        } else {
+            let frame_ptr = frame as *const u32;
+            (
+                Some(frame_ptr.add(FRAME_PC).read_volatile()),
+                Some(frame_ptr.add(FRAME_LR).read_volatile()),
+            )
+        };
+
+        Self {
+            cause,
+            address,
+            pc,
+            lr,
+            sp: frame,
+            cfsr,
+            hfsr,
+        }
+    }
+}
+
+impl fmt::Display for FaultInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fault: {}", self.cause)?;
+        if let Some(addr) = self.address {
+            write!(f, ", at address {:#010x}", addr)?;
+        }
+        f.write_str("\r\n")?;
+
+        match self.pc {
+            Some(pc) => write!(f, "PC: {:#010x}", pc)?,
+            None => f.write_str("PC: unknown")?,
+        }
+        if let Some(lr) = self.lr {
+            write!(f, ", LR: {:#010x}", lr)?;
+        }
+        write!(f, ", SP: {:#010x}\r\n", self.sp)?;
+
+        write!(f, "CFSR: {:#010x}, HFSR: {:#010x}", self.cfsr, self.hfsr)
+    }
+}
+
+/// Write a fault report, eg to a U[S]ART, or the ITM.
+pub fn report<W: fmt::Write>(writer: &mut W, info: &FaultInfo) -> fmt::Result {
+    write!(writer, "\r\n{}\r\n", info)
+}
+
+/// Clear the fault status registers, by writing 1 to their set bits; eg after reporting a fault
+/// in a handler that returns. Sets `SCB_CFSR` and `SCB_HFSR` registers.
+pub fn clear() {
+    unsafe {
+        (SCB_CFSR as *mut u32).write_volatile(read(SCB_CFSR));
+        (SCB_HFSR as *mut u32).write_volatile(read(SCB_HFSR));
+    }
+}
+
+fn read(addr: usize) -> u32 {
+    unsafe { (addr as *const u32).read_volatile() }
+}
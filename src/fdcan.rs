@@ -0,0 +1,542 @@
+//! Configuration of the FDCAN peripheral's operating modes, for CAN tooling and conformance
+//! testing: Bus monitoring (listen-only), restricted operation, and internal and external
+//! loopback; and its timestamp counter, for timestamping received frames and transmit events.
+//!
+//! Configuration takes place in initialization mode: Call `init_mode`, configure, then `start`.
+//! With the `embedded_can` feature, `FdCan` sends and receives frames through the `embedded_can`
+//! traits, using a transmit FIFO, and receive FIFO 0. No acceptance filters are configured, so
+//! all frames are received.
+//!
+//! Example, listening to a bus without affecting it:
+//!
+//! ```
+//! let mut can = FdCan::new(dp.FDCAN1);
+//! can.init_mode();
+//! can.set_bit_timing(&BitTiming::from_bitrate(clock_cfg.apb1(), 500_000, 0.875)?, None);
+//! can.set_mode(FdCanMode::BusMonitoring);
+//! can.enable_timestamp(1, TimestampSource::Internal);
+//! can.start();
+//! ```
+//!
+//! See G4 RM, section 44.3.3: "Operating modes", and 44.3.4: "Test modes".
+
+use core::ops::Deref;
+
+use crate::{
+    can_timing::BitTiming,
+    pac::{self, RCC},
+    util::free,
+};
+
+#[cfg(feature = "g4")]
+use crate::rcc_en_reset;
+
+#[cfg(feature = "embedded_can")]
+use crate::can_frame::{CanError, ExtendedId, Frame, Id, StandardId};
+
+use cfg_if::cfg_if;
+
+cfg_if! {
+    if #[cfg(feature = "g4")] {
+        use pac::fdcan as fdcan_p;
+    } else {
+        use pac::fdcan1 as fdcan_p;
+    }
+}
+
+// The register layout is the same on each family, but the PAC names registers and fields
+// differently between them; we access them by offset from the peripheral's base address.
+const DBTP: usize = 0x0c;
+const TEST: usize = 0x10;
+const CCCR: usize = 0x18;
+const NBTP: usize = 0x1c;
+const TSCC: usize = 0x20;
+const TSCV: usize = 0x24;
+const ECR: usize = 0x40;
+const PSR: usize = 0x44;
+#[cfg(feature = "embedded_can")]
+const IR: usize = 0x50;
+#[cfg(feature = "embedded_can")]
+const TXFQS: usize = 0xc4;
+
+// H7's FDCAN has configurable message RAM, and more registers; some are at different offsets.
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const SIDFC: usize = 0x84;
+        const XIDFC: usize = 0x88;
+        const RXF0C: usize = 0xa0;
+        #[cfg(feature = "embedded_can")]
+        const RXF0S: usize = 0xa4;
+        #[cfg(feature = "embedded_can")]
+        const RXF0A: usize = 0xa8;
+        const RXESC: usize = 0xbc;
+        const TXBC: usize = 0xc0;
+        const TXESC: usize = 0xc8;
+        #[cfg(feature = "embedded_can")]
+        const TXBAR: usize = 0xd0;
+        const TXEFC: usize = 0xf0;
+
+        /// `IR` register, `RF0L` field: A message was lost, since RX FIFO 0 was full.
+        #[cfg(feature = "embedded_can")]
+        const IR_RF0L: u32 = 1 << 3;
+
+        /// The message RAM, at 0x4000_AC00, is 10KiB shared by both peripherals; each uses its own
+        /// half.
+        const H7_MSG_RAM: usize = 0x4000_ac00;
+        const H7_MSG_RAM_PER_PERIPH: usize = 0x1400;
+
+        /// The number of RX FIFO 0 and TX FIFO elements.
+        const RX_FIFO_LEN: usize = 16;
+        const TX_FIFO_LEN: usize = 16;
+    } else {
+        #[cfg(feature = "embedded_can")]
+        const RXF0S: usize = 0x90;
+        #[cfg(feature = "embedded_can")]
+        const RXF0A: usize = 0x94;
+        #[cfg(feature = "embedded_can")]
+        const TXBAR: usize = 0xcc;
+
+        #[cfg(feature = "embedded_can")]
+        const IR_RF0L: u32 = 1 << 2;
+    }
+}
+
+// The message RAM layout. On G4 and L5, it's fixed, with room for 28 standard and 8 extended
+// filters, then 3 elements each of RX FIFO 0, RX FIFO 1, TX events, and TX buffers. See G4 RM,
+// section 44.3.3: "Message RAM".
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const RX_FIFO0_OFFSET: usize = 0;
+        const TX_OFFSET: usize = RX_FIFO_LEN * ELEMENT_SIZE;
+    } else {
+        #[cfg(feature = "embedded_can")]
+        const RX_FIFO0_OFFSET: usize = 0xb0;
+        #[cfg(feature = "embedded_can")]
+        const TX_OFFSET: usize = 0x278;
+    }
+}
+
+/// The size of an RX or TX element, with a 64-byte data field, in bytes.
+#[cfg(any(feature = "h7", feature = "embedded_can"))]
+const ELEMENT_SIZE: usize = 72;
+
+// `CCCR` bits.
+const CCCR_INIT: u32 = 1 << 0;
+const CCCR_CCE: u32 = 1 << 1;
+const CCCR_ASM: u32 = 1 << 2;
+const CCCR_MON: u32 = 1 << 5;
+const CCCR_TEST: u32 = 1 << 7;
+const CCCR_FDOE: u32 = 1 << 8;
+const CCCR_BRSE: u32 = 1 << 9;
+
+/// `TEST` register, `LBCK` field.
+const TEST_LBCK: u32 = 1 << 4;
+
+/// `PSR` register, `BO` field: Bus-off.
+const PSR_BO: u32 = 1 << 7;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The controller's operating mode. Sets `CCCR` register, `ASM`, `MON`, and `TEST` fields, and
+/// `TEST` register, `LBCK` field.
+pub enum FdCanMode {
+    /// Normal operation.
+    Normal,
+    /// Receives valid frames and acknowledges them, but doesn't transmit data frames, or send
+    /// error frames or overload frames. Eg for automatic bitrate detection: Errors don't disturb
+    /// the bus while trying bitrates.
+    RestrictedOperation,
+    /// Listen-only: Receives valid frames, but sends only recessive bits on the bus; it doesn't
+    /// acknowledge frames, or send error frames. Eg for a bus analyzer.
+    BusMonitoring,
+    /// Transmitted frames are received internally, and the TX pin stays recessive; the bus is
+    /// unaffected. For self-test, without a bus.
+    InternalLoopback,
+    /// Transmitted frames are received internally, and sent on the bus. Acknowledges its own
+    /// frames, so it works without other nodes; eg to test the transceiver.
+    ExternalLoopback,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+/// The timestamp counter's source. Sets `TSCC` register, `TSS` field.
+pub enum TimestampSource {
+    /// The counter stays at 0.
+    Disabled = 0b00,
+    /// Counts CAN bit times, divided by the prescaler.
+    Internal = 0b01,
+    /// An external counter: TIM3 on G4 and L5, or the TTCAN time base on H7.
+    External = 0b10,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Error counters, from `ECR` register, and the protocol status, from `PSR` register.
+pub struct ErrorStatus {
+    /// `TEC`: The transmit error counter.
+    pub tx_errors: u8,
+    /// `REC`: The receive error counter.
+    pub rx_errors: u8,
+    /// `RP`: The receive error counter has reached the error passive level, 128.
+    pub rx_passive: bool,
+    /// `EP`: The controller is error passive.
+    pub error_passive: bool,
+    /// `BO`: The controller is bus-off.
+    pub bus_off: bool,
+    /// `LEC`: The last error code, from 0 (none) to 7 (no change). See G4 RM, section 44.4.12.
+    pub last_error: u8,
+}
+
+/// Represents an FDCAN peripheral.
+pub struct FdCan<R> {
+    pub regs: R,
+}
+
+impl<R> FdCan<R>
+where
+    R: Deref<Target = fdcan_p::RegisterBlock>,
+{
+    /// Enable and reset the peripheral's clock. On G4 and H7, this clock is shared by all FDCAN
+    /// peripherals; create the first before configuring the others. On H7, this also configures
+    /// the message RAM. The peripheral starts in initialization mode.
+    pub fn new(regs: R) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            cfg_if! {
+                if #[cfg(feature = "g4")] {
+                    rcc_en_reset!(apb1, fdcan, rcc);
+                } else if #[cfg(feature = "h7")] {
+                    rcc.apb1henr.modify(|_, w| w.fdcanen().set_bit());
+                    rcc.apb1hrstr.modify(|_, w| w.fdcanrst().set_bit());
+                    rcc.apb1hrstr.modify(|_, w| w.fdcanrst().clear_bit());
+                } else {
+                    rcc.apb1enr2.modify(|_, w| w.fdcan1en().set_bit());
+                    rcc.apb1rstr2.modify(|_, w| w.fdcan1rst().set_bit());
+                    rcc.apb1rstr2.modify(|_, w| w.fdcan1rst().clear_bit());
+                }
+            }
+        });
+
+        #[allow(unused_mut)]
+        let mut result = Self { regs };
+
+        #[cfg(feature = "h7")]
+        result.configure_msg_ram();
+
+        result
+    }
+
+    #[cfg(feature = "h7")]
+    /// Allocate this peripheral's half of the message RAM: No filters, then RX FIFO 0, then a TX
+    /// FIFO, with 64-byte data fields. Sets `SIDFC`, `XIDFC`, `RXF0C`, `RXESC`, `TXBC`, `TXESC`,
+    /// and `TXEFC` registers.
+    fn configure_msg_ram(&mut self) {
+        self.init_mode();
+
+        // Start addresses are byte offsets from the start of the message RAM.
+        let start = self.msg_ram() - H7_MSG_RAM;
+        let rx_start = (start + RX_FIFO0_OFFSET) as u32;
+        let tx_start = (start + TX_OFFSET) as u32;
+
+        self.write(SIDFC, start as u32);
+        self.write(XIDFC, start as u32);
+        // Blocking mode: When full, new messages are discarded.
+        self.write(RXF0C, (RX_FIFO_LEN as u32) << 16 | rx_start);
+        // 64-byte data fields: `F0DS`, and `TBDS` = 0b111.
+        self.write(RXESC, 0b111);
+        // No dedicated TX buffers; a TX FIFO.
+        self.write(TXBC, (TX_FIFO_LEN as u32) << 24 | tx_start);
+        self.write(TXESC, 0b111);
+        self.write(TXEFC, 0);
+    }
+
+    /// The address of this peripheral's message RAM.
+    #[cfg(any(feature = "h7", feature = "embedded_can"))]
+    fn msg_ram(&self) -> usize {
+        let base = &*self.regs as *const _ as usize;
+
+        cfg_if! {
+            if #[cfg(feature = "g4")] {
+                // SRAMCAN, at 0x4000_A400, with 0x350 bytes per peripheral, in order.
+                0x4000_a400 + (base - 0x4000_6400) / 0x400 * 0x350
+            } else if #[cfg(feature = "h7")] {
+                // FDCAN1 is at 0x4000_A000, and FDCAN2 0x4000_A400.
+                H7_MSG_RAM + (base - 0x4000_a000) / 0x400 * H7_MSG_RAM_PER_PERIPH
+            } else {
+                // L5: 0x800 above the registers, for both the secure and non-secure aliases.
+                base + 0x800
+            }
+        }
+    }
+
+    /// Enter initialization mode, and enable writes to the configuration registers. Stops
+    /// communication, after any frame in progress. Sets `CCCR` register, `INIT` and `CCE` fields.
+    pub fn init_mode(&mut self) {
+        self.modify(CCCR, 0, CCCR_INIT);
+        while self.read(CCCR) & CCCR_INIT == 0 {}
+        self.modify(CCCR, 0, CCCR_CCE);
+    }
+
+    /// Leave initialization mode, and start communicating. The controller joins the bus after
+    /// seeing 11 consecutive recessive bits. Clears `CCCR` register, `INIT` field.
+    pub fn start(&mut self) {
+        self.modify(CCCR, CCCR_INIT, 0);
+        while self.read(CCCR) & CCCR_INIT != 0 {}
+    }
+
+    /// Returns `true` if in initialization mode, eg after a bus-off. Reads `CCCR` register, `INIT`
+    /// field.
+    pub fn is_init_mode(&self) -> bool {
+        self.read(CCCR) & CCCR_INIT != 0
+    }
+
+    /// Set the nominal bit timing, and optionally the data phase timing, which enables CAN FD
+    /// frames with bitrate switching. Must be in initialization mode. Sets `NBTP` and `DBTP`
+    /// registers, and `CCCR` register, `FDOE` and `BRSE` fields.
+    pub fn set_bit_timing(&mut self, nominal: &BitTiming, data: Option<&BitTiming>) {
+        self.assert_init();
+        self.write(NBTP, nominal.nbtp());
+
+        match data {
+            Some(timing) => {
+                self.write(DBTP, timing.dbtp());
+                self.modify(CCCR, 0, CCCR_FDOE | CCCR_BRSE);
+            }
+            None => self.modify(CCCR, CCCR_FDOE | CCCR_BRSE, 0),
+        }
+    }
+
+    /// Set the operating mode. Must be in initialization mode.
+    pub fn set_mode(&mut self, mode: FdCanMode) {
+        self.assert_init();
+
+        // The loopback modes are test modes.
+        let (asm, mon, test) = match mode {
+            FdCanMode::Normal => (false, false, false),
+            FdCanMode::RestrictedOperation => (true, false, false),
+            FdCanMode::BusMonitoring => (false, true, false),
+            FdCanMode::InternalLoopback => (false, true, true),
+            FdCanMode::ExternalLoopback => (false, false, true),
+        };
+
+        let set = if asm { CCCR_ASM } else { 0 }
+            | if mon { CCCR_MON } else { 0 }
+            | if test { CCCR_TEST } else { 0 };
+        self.modify(CCCR, CCCR_ASM | CCCR_MON | CCCR_TEST, set);
+
+        // The `TEST` register is writable only with `CCCR.TEST` set; clearing that resets it.
+        if test {
+            self.modify(TEST, 0, TEST_LBCK);
+        }
+    }
+
+    /// The current operating mode.
+    pub fn mode(&self) -> FdCanMode {
+        let cccr = self.read(CCCR);
+        let lbck = self.read(TEST) & TEST_LBCK != 0;
+
+        if cccr & CCCR_TEST != 0 && lbck {
+            if cccr & CCCR_MON != 0 {
+                FdCanMode::InternalLoopback
+            } else {
+                FdCanMode::ExternalLoopback
+            }
+        } else if cccr & CCCR_MON != 0 {
+            FdCanMode::BusMonitoring
+        } else if cccr & CCCR_ASM != 0 {
+            FdCanMode::RestrictedOperation
+        } else {
+            FdCanMode::Normal
+        }
+    }
+
+    /// Leave restricted operation mode. The controller enters it automatically on detecting a
+    /// message RAM access failure; it doesn't need initialization mode to leave it. Clears `CCCR`
+    /// register, `ASM` field.
+    pub fn clear_restricted_operation(&mut self) {
+        self.modify(CCCR, CCCR_ASM, 0);
+    }
+
+    /// Configure the timestamp counter, which timestamps received frames and transmit events.
+    /// With the internal source, it counts CAN bit times divided by `prescaler`, from 1 to 16.
+    /// Must be in initialization mode. Sets `TSCC` register, `TCP` and `TSS` fields.
+    pub fn enable_timestamp(&mut self, prescaler: u8, source: TimestampSource) {
+        assert!(
+            (1..=16).contains(&prescaler),
+            "Timestamp prescaler must be 1 - 16."
+        );
+        self.assert_init();
+
+        self.write(TSCC, (prescaler as u32 - 1) << 16 | source as u32);
+    }
+
+    /// The timestamp counter's value. It wraps at 16 bits. Reads `TSCV` register, `TSC` field.
+    pub fn timestamp(&self) -> u16 {
+        self.read(TSCV) as u16
+    }
+
+    /// Reset the timestamp counter to 0. (With the external source, reset the source instead.)
+    /// Sets `TSCV` register.
+    pub fn reset_timestamp(&mut self) {
+        // "A write access to FDCAN_TSCV register resets the counter to 0."
+        self.write(TSCV, 0);
+    }
+
+    /// Read the error counters and protocol status. Reading resets `PSR`'s last error code to 7,
+    /// and `ECR`'s CAN error logging counter, `CEL`.
+    pub fn error_status(&self) -> ErrorStatus {
+        let ecr = self.read(ECR);
+        let psr = self.read(PSR);
+
+        ErrorStatus {
+            tx_errors: ecr as u8,
+            rx_errors: ((ecr >> 8) & 0x7f) as u8,
+            rx_passive: ecr & (1 << 15) != 0,
+            error_passive: psr & (1 << 5) != 0,
+            bus_off: psr & PSR_BO != 0,
+            last_error: (psr & 0b111) as u8,
+        }
+    }
+
+    fn assert_init(&self) {
+        let cccr = self.read(CCCR);
+        assert!(
+            cccr & CCCR_INIT != 0 && cccr & CCCR_CCE != 0,
+            "FDCAN must be in initialization mode; call `init_mode` first."
+        );
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (&*self.regs as *const _ as usize + offset) as *mut u32
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { self.reg(offset).read_volatile() }
+    }
+
+    fn write(&mut self, offset: usize, val: u32) {
+        unsafe { self.reg(offset).write_volatile(val) }
+    }
+
+    fn modify(&mut self, offset: usize, clear: u32, set: u32) {
+        let val = self.read(offset);
+        self.write(offset, (val & !clear) | set);
+    }
+}
+
+#[cfg(feature = "embedded_can")]
+impl<R> embedded_can::nb::Can for FdCan<R>
+where
+    R: Deref<Target = fdcan_p::RegisterBlock>,
+{
+    type Frame = Frame;
+    type Error = CanError;
+
+    /// Queue a frame in the TX FIFO. Returns `WouldBlock` if it's full. Frames are sent in the
+    /// order queued; none are replaced. Returns `UnsupportedFrame` for an FD frame, if FD
+    /// operation isn't enabled with `set_bit_timing`.
+    fn transmit(&mut self, frame: &Frame) -> nb::Result<Option<Frame>, CanError> {
+        use embedded_can::Frame;
+
+        if self.read(PSR) & PSR_BO != 0 {
+            return Err(nb::Error::Other(CanError::BusOff));
+        }
+        if frame.is_fd() && self.read(CCCR) & CCCR_FDOE == 0 {
+            return Err(nb::Error::Other(CanError::UnsupportedFrame));
+        }
+
+        let txfqs = self.read(TXFQS);
+        // `TFQF`: The FIFO is full.
+        if txfqs & (1 << 21) != 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        // `TFQPI`: The put index.
+        let i = ((txfqs >> 16) & 0x1f) as usize;
+
+        let id = match frame.id() {
+            Id::Standard(id) => (id.as_raw() as u32) << 18,
+            Id::Extended(id) => id.as_raw() | 1 << 30,
+        };
+        let t0 = id | (frame.is_remote_frame() as u32) << 29;
+        let t1 = (frame.is_fd() as u32) << 21
+            | (frame.bitrate_switch() as u32) << 20
+            | (frame.dlc_code() as u32) << 16;
+
+        // The message RAM must be written in words.
+        let element = (self.msg_ram() + TX_OFFSET + i * ELEMENT_SIZE) as *mut u32;
+        unsafe {
+            element.write_volatile(t0);
+            element.add(1).write_volatile(t1);
+
+            for (j, chunk) in frame.data().chunks(4).enumerate() {
+                let mut word = [0; 4];
+                word[..chunk.len()].copy_from_slice(chunk);
+                element.add(2 + j).write_volatile(u32::from_le_bytes(word));
+            }
+        }
+
+        self.write(TXBAR, 1 << i);
+        Ok(None)
+    }
+
+    /// Read the oldest frame from RX FIFO 0. Returns `WouldBlock` if it's empty, and `Overrun`
+    /// once if a frame was lost since the last call.
+    fn receive(&mut self) -> nb::Result<Frame, CanError> {
+        let rxf0s = self.read(RXF0S);
+
+        // `RF0L`; cleared by clearing its interrupt flag.
+        if rxf0s & (1 << 25) != 0 {
+            self.write(IR, IR_RF0L);
+            return Err(nb::Error::Other(CanError::Overrun));
+        }
+        // `F0FL`: The fill level.
+        if rxf0s & 0x7f == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        // `F0GI`: The get index.
+        let i = ((rxf0s >> 8) & 0x3f) as usize;
+
+        let element = (self.msg_ram() + RX_FIFO0_OFFSET + i * ELEMENT_SIZE) as *const u32;
+        let (r0, r1) = unsafe { (element.read_volatile(), element.add(1).read_volatile()) };
+
+        let id = if r0 & (1 << 30) != 0 {
+            Id::Extended(ExtendedId::new(r0 & 0x1fff_ffff).unwrap())
+        } else {
+            Id::Standard(StandardId::new(((r0 >> 18) & 0x7ff) as u16).unwrap())
+        };
+
+        let mut frame = Frame::from_raw(
+            id,
+            r0 & (1 << 29) != 0,
+            r1 & (1 << 21) != 0,
+            r1 & (1 << 20) != 0,
+            ((r1 >> 16) & 0xf) as u8,
+        );
+
+        for (j, chunk) in frame.data_mut().chunks_mut(4).enumerate() {
+            let word = unsafe { element.add(2 + j).read_volatile() }.to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+
+        // Acknowledge the element, freeing it.
+        self.write(RXF0A, i as u32);
+        Ok(frame)
+    }
+}
+
+#[cfg(feature = "embedded_can")]
+impl<R> embedded_can::blocking::Can for FdCan<R>
+where
+    R: Deref<Target = fdcan_p::RegisterBlock>,
+{
+    type Frame = Frame;
+    type Error = CanError;
+
+    /// Queue a frame, waiting for room in the TX FIFO.
+    fn transmit(&mut self, frame: &Frame) -> Result<(), CanError> {
+        nb::block!(embedded_can::nb::Can::transmit(self, frame)).map(|_| ())
+    }
+
+    /// Wait for a frame.
+    fn receive(&mut self) -> Result<Frame, CanError> {
+        nb::block!(embedded_can::nb::Can::receive(self))
+    }
+}
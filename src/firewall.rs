@@ -0,0 +1,188 @@
+//! Support for the firewall (FW), on L4. This protects a code segment in flash, and optionally a
+//! non-volatile data segment in flash, and a volatile data segment in SRAM1, eg holding key
+//! material, from access by code outside the code segment. It's a lighter-weight alternative to
+//! TrustZone.
+//!
+//! Once enabled, the firewall is closed: Any access to a segment resets the MCU, except for a
+//! call to the code segment's call gate, at its start + 4 bytes, which opens it. While open, code
+//! inside the code segment can access the data segments. The protected code must set the
+//! prearm flag (`Firewall::set_prearm`) before returning: Leaving the code segment then closes the
+//! firewall; without it, leaving resets the MCU.
+//!
+//! The segments can only be changed, and the firewall only disabled, by a reset.
+//!
+//! Example, protecting 8kB of code, and a 1kB key store in SRAM1:
+//!
+//! ```
+//! let cfg = FirewallConfig {
+//!     code: Segment { start: 0x0803_0000, len: 8 * 1_024 },
+//!     volatile_data: Segment { start: 0x2000_0000, len: 1_024 },
+//!     ..Default::default()
+//! };
+//!
+//! let mut fw = Firewall::new(dp.FIREWALL, cfg);
+//! fw.enable(&mut dp.SYSCFG);
+//!
+//! let result = unsafe { fw.call_gate(SIGN_COMMAND) };
+//! ```
+//!
+//! See L4 RM, chapter 4: Firewall (FW).
+
+use crate::{
+    pac::{FIREWALL, RCC, SYSCFG},
+    util::free,
+};
+
+/// The start of flash memory, which the code and non-volatile data segments are offsets from.
+const FLASH_START: u32 = 0x0800_0000;
+/// The start of SRAM1, which the volatile data segment is an offset from.
+const SRAM1_START: u32 = 0x2000_0000;
+
+// Masks of the segment address and length fields. These are the address bits they correspond
+// to, so offsets are written directly.
+const CODE_ADDR_MASK: u32 = 0x00ff_ff00;
+const CODE_LEN_MASK: u32 = 0x003f_ff00;
+const VOLATILE_ADDR_MASK: u32 = 0x0001_ffc0;
+const VOLATILE_LEN_MASK: u32 = 0x0001_ffc0;
+
+/// The offset of the call gate from the start of the code segment.
+const CALL_GATE_OFFSET: u32 = 4;
+
+#[derive(Clone, Copy, Default)]
+/// A protected segment: An absolute start address, and length, in bytes. A length of 0 leaves
+/// it unprotected.
+pub struct Segment {
+    pub start: u32,
+    pub len: u32,
+}
+
+#[derive(Clone, Default)]
+/// Firewall configuration. Sets the `FW_CSSA`, `FW_CSL`, `FW_NVDSSA`, `FW_NVDSL`, `FW_VDSSA`,
+/// `FW_VDSL`, and `FW_CR` registers.
+pub struct FirewallConfig {
+    /// The code segment, in flash. Its start and length must be multiples of 256 bytes.
+    pub code: Segment,
+    /// The non-volatile data segment, in flash. Its start and length must be multiples of 256
+    /// bytes.
+    pub nv_data: Segment,
+    /// The volatile data segment, in SRAM1. Its start and length must be multiples of 64 bytes.
+    pub volatile_data: Segment,
+    /// Allow access to the volatile data segment while the firewall is closed; eg to pass data to
+    /// and from the protected code. `VDS` field. Defaults to `false`.
+    pub volatile_data_shared: bool,
+    /// Allow executing code from the volatile data segment. It's then part of the protected code,
+    /// and can't be shared. `VDE` field. Defaults to `false`.
+    pub volatile_data_executable: bool,
+}
+
+/// Represents the firewall peripheral.
+pub struct Firewall {
+    pub regs: FIREWALL,
+    pub cfg: FirewallConfig,
+}
+
+impl Firewall {
+    /// Initialize the firewall, enabling its clock, and setting its segments. It isn't active
+    /// until `enable` is called.
+    pub fn new(regs: FIREWALL, cfg: FirewallConfig) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            // The firewall clock can only be disabled by a reset.
+            rcc.apb2enr.modify(|_, w| w.firewallen().set_bit());
+        });
+
+        let code = flash_offsets(&cfg.code);
+        let nv_data = flash_offsets(&cfg.nv_data);
+
+        assert!(
+            cfg.volatile_data.start % 64 == 0 && cfg.volatile_data.len % 64 == 0,
+            "The volatile data segment must be aligned to 64 bytes."
+        );
+        let volatile_data = if cfg.volatile_data.len == 0 {
+            (0, 0)
+        } else {
+            assert!(
+                cfg.volatile_data.start >= SRAM1_START,
+                "The volatile data segment must be in SRAM1."
+            );
+            (cfg.volatile_data.start - SRAM1_START, cfg.volatile_data.len)
+        };
+
+        unsafe {
+            regs.cssa.write(|w| w.bits(code.0 & CODE_ADDR_MASK));
+            regs.csl.write(|w| w.bits(code.1 & CODE_LEN_MASK));
+            regs.nvdssa.write(|w| w.bits(nv_data.0 & CODE_ADDR_MASK));
+            regs.nvdsl.write(|w| w.bits(nv_data.1 & CODE_LEN_MASK));
+            regs.vdssa
+                .write(|w| w.bits(volatile_data.0 & VOLATILE_ADDR_MASK));
+            regs.vdsl
+                .write(|w| w.bits(volatile_data.1 & VOLATILE_LEN_MASK));
+        }
+
+        regs.cr.modify(|_, w| {
+            w.vds().bit(cfg.volatile_data_shared);
+            w.vde().bit(cfg.volatile_data_executable)
+        });
+
+        Self { regs, cfg }
+    }
+
+    /// Enable the firewall, closed. It can only be disabled by a reset. Clears `SYSCFG_CFGR1`
+    /// register, `FWDIS` field.
+    pub fn enable(&mut self, syscfg: &mut SYSCFG) {
+        syscfg.cfgr1.modify(|_, w| w.fwdis().clear_bit());
+    }
+
+    /// Returns `true` if the firewall is enabled. Reads `SYSCFG_CFGR1` register, `FWDIS` field.
+    pub fn is_enabled(&self, syscfg: &SYSCFG) -> bool {
+        syscfg.cfgr1.read().fwdis().bit_is_clear()
+    }
+
+    /// Call the protected code through the call gate, opening the firewall, with an argument
+    /// in `r0`, and return the value it returns in `r0`. The function at the call gate must use
+    /// the C ABI, eg `extern "C" fn(u32) -> u32`, and set the prearm flag before returning.
+    ///
+    /// # Safety
+    /// The code segment must contain a valid function at its call gate.
+    pub unsafe fn call_gate(&self, arg: u32) -> u32 {
+        // Set the Thumb bit.
+        let addr = (self.cfg.code.start + CALL_GATE_OFFSET) | 1;
+        let f: extern "C" fn(u32) -> u32 = core::mem::transmute(addr as usize);
+
+        f(arg)
+    }
+
+    /// Set or clear the prearm flag. With it set, leaving the code segment closes the firewall;
+    /// with it clear, leaving it resets the MCU. Call from the protected code, with `true`, before
+    /// returning; it's only accessible while the firewall is open. Sets `FW_CR` register, `FPA`
+    /// field.
+    pub fn set_prearm(&mut self, prearm: bool) {
+        self.regs.cr.modify(|_, w| w.fpa().bit(prearm));
+    }
+
+    /// Set whether the volatile data segment is accessible while the firewall is closed. Only
+    /// accessible while the firewall is open, or before it's enabled. Sets `FW_CR` register,
+    /// `VDS` field.
+    pub fn set_volatile_data_shared(&mut self, shared: bool) {
+        self.regs.cr.modify(|_, w| w.vds().bit(shared));
+        self.cfg.volatile_data_shared = shared;
+    }
+}
+
+/// Validate a flash segment, and return its start offset from the start of flash, and length.
+fn flash_offsets(segment: &Segment) -> (u32, u32) {
+    assert!(
+        segment.start % 256 == 0 && segment.len % 256 == 0,
+        "Flash firewall segments must be aligned to 256 bytes."
+    );
+
+    if segment.len == 0 {
+        return (0, 0);
+    }
+
+    assert!(
+        segment.start >= FLASH_START,
+        "Code and non-volatile data segments must be in flash."
+    );
+    (segment.start - FLASH_START, segment.len)
+}
@@ -23,9 +23,36 @@ use core;
 
 use cfg_if::cfg_if;
 
+#[cfg(all(feature = "embedded-storage", not(feature = "f4")))]
+use embedded_storage::nor_flash::{self, ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
 const FLASH_KEY1: u32 = 0x4567_0123;
 const FLASH_KEY2: u32 = 0xCDEF_89AB;
 
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        /// The erase unit, in bytes: A page, or a sector on H7.
+        pub const PAGE_SIZE: usize = 0x2_0000;
+        /// The programming unit, in bytes: A 256-bit flash word.
+        pub const WRITE_SIZE: usize = 32;
+    } else if #[cfg(feature = "wb")] {
+        pub const PAGE_SIZE: usize = 4_096;
+        /// The programming unit, in bytes: A double word.
+        pub const WRITE_SIZE: usize = 8;
+    } else {
+        /// The erase unit, in bytes. (On dual-bank L5 and G4 variants, this assumes the default
+        /// dual-bank mode.)
+        pub const PAGE_SIZE: usize = 2_048;
+        /// The programming unit, in bytes: A double word.
+        pub const WRITE_SIZE: usize = 8;
+    }
+}
+
+#[cfg(any(feature = "l4", feature = "g0", feature = "g4"))]
+const OPT_KEY1: u32 = 0x0819_2A3B;
+#[cfg(any(feature = "l4", feature = "g0", feature = "g4"))]
+const OPT_KEY2: u32 = 0x4C5D_6E7F;
+
 #[cfg(feature = "l5")]
 #[derive(Clone, Copy)]
 /// Cortex-M33 secure programming, or nonsecure.
@@ -49,6 +76,31 @@ pub enum Bank {
     B2,
 }
 
+#[cfg(any(feature = "g0", feature = "g4"))]
+#[derive(Clone, Copy, PartialEq)]
+/// Where the BOOT0 value is read from at reset. Sets the `OPTR` register, `nBOOT_SEL` field on G0,
+/// and `nSWBOOT0` on G4.
+pub enum Boot0Src {
+    /// The BOOT0 pin: PA14 on G0, shared with SWCLK, and PB8 on G4.
+    Pin,
+    /// The `nBOOT0` option bit, set with `BootMode`. The pin is then free for other uses.
+    OptionBit,
+}
+
+#[cfg(any(feature = "g0", feature = "g4"))]
+#[derive(Clone, Copy, PartialEq)]
+/// The memory to boot from, when BOOT0 is read from the option bit. Sets the `OPTR` register,
+/// `nBOOT0` and `nBOOT1` fields.
+pub enum BootMode {
+    /// Main flash memory. On G0, if the flash was empty at the last power-on or option byte
+    /// load, this boots system memory instead; see `Flash::clear_empty_flag`.
+    MainFlash,
+    /// System memory: The ST bootloader.
+    SystemMemory,
+    /// Embedded SRAM.
+    Sram,
+}
+
 #[derive(Copy, Clone, Debug)]
 /// Possible error states for flash operations.
 pub enum Error {
@@ -62,6 +114,8 @@ pub enum Error {
     PageOutOfRange,
     /// (Legal) command failed
     Failure,
+    /// An address or length isn't a multiple of the write or erase size
+    NotAligned,
 }
 
 // todo: Bank 2 support on H7 and others.
@@ -199,6 +253,162 @@ impl Flash {
         self.regs.bank1().cr.modify(|_, w| w.lock().set_bit());
     }
 
+    #[cfg(any(feature = "l4", feature = "g0", feature = "g4"))]
+    /// Unlock the option bytes, allowing changes to them. Unlocks the flash memory first.
+    /// See L4 RM, section 3.4.2.
+    pub fn unlock_options(&mut self) -> Result<(), Error> {
+        self.unlock()?;
+
+        self.regs.optkeyr.write(|w| unsafe { w.bits(OPT_KEY1) });
+        self.regs.optkeyr.write(|w| unsafe { w.bits(OPT_KEY2) });
+
+        if self.regs.cr.read().optlock().bit_is_clear() {
+            Ok(())
+        } else {
+            Err(Error::Failure)
+        }
+    }
+
+    #[cfg(feature = "l4")]
+    /// Read the bank the MCU boots from, on dual-bank variants. Reads the `OPTR` register,
+    /// `BFB2` field.
+    pub fn boot_bank(&self) -> Bank {
+        if self.regs.optr.read().bfb2().bit_is_set() {
+            Bank::B2
+        } else {
+            Bank::B1
+        }
+    }
+
+    #[cfg(feature = "l4")]
+    /// Set the bank to boot from, on dual-bank variants, by programming the `BFB2` option bit.
+    /// This then reloads the option bytes, which resets the MCU; it only returns if there's an
+    /// error. See L4 RM, section 3.4.2: Option bytes programming.
+    pub fn set_boot_bank(&mut self, bank: Bank) -> Result<(), Error> {
+        self.unlock_options()?;
+
+        // Check that no Flash memory operation is ongoing by checking the BSY bit in the Flash
+        // status register (FLASH_SR).
+        while self.regs.sr.read().bsy().bit_is_set() {}
+
+        if check_illegal(&self.regs).is_err() {
+            self.lock();
+            return Err(Error::Illegal);
+        };
+
+        // Write the desired option value in the options registers: FLASH_OPTR [...]
+        self.regs
+            .optr
+            .modify(|_, w| w.bfb2().bit(matches!(bank, Bank::B2)));
+
+        // Set the Options Start bit OPTSTRT in the Flash Control Register (FLASH_CR).
+        self.regs.cr.modify(|_, w| w.optstrt().set_bit());
+
+        // Wait for the BSY bit to be cleared.
+        while self.regs.sr.read().bsy().bit_is_set() {}
+
+        // "Option byte loading can be forced by software by setting the OBL_LAUNCH bit in the
+        // FLASH_CR register. [...] This bit generates a reset."
+        self.regs.cr.modify(|_, w| w.obl_launch().set_bit());
+
+        // We should be reset by now.
+        self.lock();
+        Err(Error::Failure)
+    }
+
+    #[cfg(any(feature = "g0", feature = "g4"))]
+    /// Read the boot configuration from the option bytes: Where BOOT0 is read from, and the memory
+    /// booted when it's read from the option bit. Reads the `OPTR` register.
+    pub fn boot_config(&self) -> (Boot0Src, BootMode) {
+        let optr = self.regs.optr.read();
+
+        #[cfg(feature = "g0")]
+        let src = if optr.n_boot_sel().bit_is_set() {
+            Boot0Src::OptionBit
+        } else {
+            Boot0Src::Pin
+        };
+        #[cfg(feature = "g4")]
+        let src = if optr.n_swboot0().bit_is_set() {
+            Boot0Src::Pin
+        } else {
+            Boot0Src::OptionBit
+        };
+
+        let mode = if optr.n_boot0().bit_is_set() {
+            BootMode::MainFlash
+        } else if optr.n_boot1().bit_is_set() {
+            BootMode::SystemMemory
+        } else {
+            BootMode::Sram
+        };
+
+        (src, mode)
+    }
+
+    #[cfg(any(feature = "g0", feature = "g4"))]
+    /// Set the boot configuration, by programming the option bytes. Eg, to always boot main flash,
+    /// regardless of the BOOT0 pin's level: `flash.set_boot_config(Boot0Src::OptionBit,
+    /// BootMode::MainFlash)`. This then reloads the option bytes, which resets the MCU; it only
+    /// returns if there's an error, or the configuration is already set. See G0 RM, section 3.4.2:
+    /// "FLASH option byte programming", and section 2.5: "Boot configuration".
+    pub fn set_boot_config(&mut self, boot0_src: Boot0Src, mode: BootMode) -> Result<(), Error> {
+        let (current_src, current_mode) = self.boot_config();
+        if current_src == boot0_src && current_mode == mode {
+            return Ok(());
+        }
+
+        self.unlock_options()?;
+
+        while self.regs.sr.read().bsy().bit_is_set() {}
+
+        if check_illegal(&self.regs).is_err() {
+            self.lock();
+            return Err(Error::Illegal);
+        };
+
+        let from_option = boot0_src == Boot0Src::OptionBit;
+
+        self.regs.optr.modify(|_, w| {
+            #[cfg(feature = "g0")]
+            w.n_boot_sel().bit(from_option);
+            #[cfg(feature = "g4")]
+            w.n_swboot0().bit(!from_option);
+            w.n_boot0().bit(mode == BootMode::MainFlash);
+            // nBOOT1 only applies when nBOOT0 is clear. It's set by default.
+            w.n_boot1().bit(mode != BootMode::Sram)
+        });
+
+        self.regs.cr.modify(|_, w| w.optstrt().set_bit());
+
+        while self.regs.sr.read().bsy().bit_is_set() {}
+
+        // This generates a reset.
+        self.regs.cr.modify(|_, w| w.obl_launch().set_bit());
+
+        // We should be reset by now.
+        self.lock();
+        Err(Error::Failure)
+    }
+
+    #[cfg(feature = "g0")]
+    /// Returns `true` if main flash was empty (its first word erased) at the last power-on or
+    /// option byte load; if so, booting main flash boots system memory instead. Reads the `ACR`
+    /// register, `EMPTY` field.
+    pub fn main_flash_empty(&self) -> bool {
+        self.regs.acr.read().empty().bit_is_set()
+    }
+
+    #[cfg(feature = "g0")]
+    /// Clear the empty-check flag, so the next reset boots main flash, if selected, without a
+    /// power cycle. The flag is only updated at power-on and option byte loading, so after
+    /// programming a blank device, eg over SWD, or from a bootloader in RAM, it's still set; a
+    /// plain reset then boots the ST bootloader instead of the new code. Call this after
+    /// programming, before resetting. Sets the `ACR` register, `EMPTY` field.
+    pub fn clear_empty_flag(&mut self) {
+        self.regs.acr.modify(|_, w| w.empty().clear_bit());
+    }
+
     #[cfg(feature = "l5")]
     /// Lock the flash memory, allowing writes.
     pub fn lock(&mut self, security: Security) {
@@ -596,6 +806,17 @@ impl Flash {
     #[cfg(not(feature = "l5"))]
     /// Write the contents of a page. Must be erased first. See L4 RM, section 3.3.7.
     pub fn write_page(&mut self, page: usize, data: &[u64]) -> Result<(), Error> {
+        self.write_page_at(page, 0, data)
+    }
+
+    #[cfg(not(feature = "l5"))]
+    /// Write to a page, starting `offset` bytes from its start. `offset` must be a multiple of
+    /// `WRITE_SIZE`. The area written must be erased first.
+    pub fn write_page_at(&mut self, page: usize, offset: usize, data: &[u64]) -> Result<(), Error> {
+        if offset % WRITE_SIZE != 0 {
+            return Err(Error::NotAligned);
+        }
+
         // todo: Consider a u8-based approach.
         // todo: DRY from `erase_page`.
         // The Flash memory programming sequence in standard mode is as follows:
@@ -628,9 +849,9 @@ impl Flash {
         // block or OTP area. Only double word can be programmed.
 
         #[cfg(not(feature = "h7"))]
-        let mut address = page_to_address(page) as *mut u32;
+        let mut address = (page_to_address(page) + offset) as *mut u32;
         #[cfg(feature = "h7")]
-        let mut address = sector_to_address(page, Bank::B1) as *mut u32;
+        let mut address = (sector_to_address(page, Bank::B1) + offset) as *mut u32;
 
         for dword in data {
             unsafe {
@@ -669,6 +890,23 @@ impl Flash {
         data: &[u64],
         security: Security,
     ) -> Result<(), Error> {
+        self.write_page_at(page, 0, data, security)
+    }
+
+    #[cfg(feature = "l5")]
+    /// Write to a page, starting `offset` bytes from its start. `offset` must be a multiple of
+    /// `WRITE_SIZE`. The area written must be erased first.
+    pub fn write_page_at(
+        &mut self,
+        page: usize,
+        offset: usize,
+        data: &[u64],
+        security: Security,
+    ) -> Result<(), Error> {
+        if offset % WRITE_SIZE != 0 {
+            return Err(Error::NotAligned);
+        }
+
         // todo: Consider a u8-based approach.
         // todo: DRY from `erase_page`.
         // The Flash memory programming sequence in standard mode is as follows:
@@ -697,7 +935,7 @@ impl Flash {
                 // todo: You have 3x DRY here re teh writing. Put that in  a fn?
                 // 4. Perform the data write operation at the desired memory address, inside main memory
                 // block or OTP area. Only double word can be programmed.
-                let mut address = page_to_address(page) as *mut u32;
+                let mut address = (page_to_address(page) + offset) as *mut u32;
 
                 for dword in data {
                     unsafe {
@@ -738,7 +976,7 @@ impl Flash {
 
                 self.regs.seccr.modify(|_, w| w.secpg().set_bit());
 
-                let mut address = page_to_address(page) as *mut u32;
+                let mut address = (page_to_address(page) + offset) as *mut u32;
 
                 for dword in data {
                     unsafe {
@@ -798,6 +1036,18 @@ impl Flash {
         }
     }
 
+    /// Access a region of flash memory directly, starting at a given page (sector on H7), and
+    /// offset in bytes from the page. Flash is memory-mapped, so this doesn't copy.
+    pub fn slice(&self, page: usize, offset: usize, len: usize) -> &[u8] {
+        #[cfg(not(feature = "h7"))]
+        let addr = page_to_address(page) + offset;
+        #[cfg(feature = "h7")]
+        // todo: Don't hard-code bank1.
+        let addr = sector_to_address(page, Bank::B1) + offset;
+
+        unsafe { core::slice::from_raw_parts(addr as *const u8, len) }
+    }
+
     // #[cfg(feature = "h7")]
     // /// Read flash memory at a given page and offset into a buffer.
     // ///    H742 RM, section 4.3.8:
@@ -819,10 +1069,9 @@ impl Flash {
 }
 
 #[cfg(not(feature = "h7"))]
-/// Calculate the address of the start of a given page. Each page is 2,048 Kb for non-H7.
-/// For H7, sectors are 128Kb, with 8 sectors per bank.
+/// Calculate the address of the start of a given page. Each page is `PAGE_SIZE` bytes.
 fn page_to_address(page: usize) -> usize {
-    0x0800_0000 + page * 2048
+    0x0800_0000 + page * PAGE_SIZE
 }
 
 #[cfg(feature = "h7")]
@@ -836,5 +1085,153 @@ fn sector_to_address(sector: usize, bank: Bank) -> usize {
         Bank::B2 => 0x0810_0000,
     };
 
-    starting_pt + sector * 0x2_0000
+    starting_pt + sector * PAGE_SIZE
+}
+
+#[cfg(not(feature = "f4"))]
+/// A range of flash pages, eg reserved for data storage, addressed in bytes from its start.
+/// With the `embedded-storage` feature, this implements its `NorFlash` traits, for use with
+/// crates like `sequential-storage`. Make sure the region doesn't overlap the program, eg by
+/// shortening the `FLASH` region in `memory.x`. Non-secure on L5; bank 1 only on H7.
+pub struct FlashRegion<'a> {
+    flash: &'a mut Flash,
+    /// The first page (sector on H7) of the region.
+    start_page: usize,
+    /// The number of pages in the region.
+    num_pages: usize,
+}
+
+#[cfg(not(feature = "f4"))]
+impl<'a> FlashRegion<'a> {
+    pub fn new(flash: &'a mut Flash, start_page: usize, num_pages: usize) -> Self {
+        Self {
+            flash,
+            start_page,
+            num_pages,
+        }
+    }
+
+    /// The region's size, in bytes.
+    pub fn capacity(&self) -> usize {
+        self.num_pages * PAGE_SIZE
+    }
+
+    fn check_bounds(&self, offset: u32, len: usize) -> Result<(), Error> {
+        if offset as usize + len > self.capacity() {
+            return Err(Error::PageOutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Read data, starting `offset` bytes from the start of the region.
+    pub fn read(&self, offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+        self.check_bounds(offset, buf.len())?;
+        buf.copy_from_slice(
+            self.flash
+                .slice(self.start_page, offset as usize, buf.len()),
+        );
+        Ok(())
+    }
+
+    /// Erase the pages from `from` to `to`, in bytes from the start of the region. Both must be
+    /// multiples of `PAGE_SIZE`.
+    pub fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        let (from, to) = (from as usize, to as usize);
+        if from % PAGE_SIZE != 0 || to % PAGE_SIZE != 0 || from > to {
+            return Err(Error::NotAligned);
+        }
+        self.check_bounds(from as u32, to - from)?;
+
+        for page in from / PAGE_SIZE..to / PAGE_SIZE {
+            #[cfg(feature = "l5")]
+            self.flash
+                .erase_page(self.start_page + page, Security::NonSecure)?;
+            #[cfg(not(feature = "l5"))]
+            self.flash.erase_page(self.start_page + page)?;
+        }
+        Ok(())
+    }
+
+    /// Write data, starting `offset` bytes from the start of the region. `offset`, and the
+    /// length of `data`, must be multiples of `WRITE_SIZE`. The area written must be erased first.
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        let mut offset = offset as usize;
+        if offset % WRITE_SIZE != 0 || data.len() % WRITE_SIZE != 0 {
+            return Err(Error::NotAligned);
+        }
+        self.check_bounds(offset as u32, data.len())?;
+
+        // Write in chunks that fit in this buffer, and don't cross a page boundary, since writes
+        // are addressed by page.
+        let mut dwords = [0_u64; 32];
+        let mut remaining = data;
+
+        while !remaining.is_empty() {
+            let page_offset = offset % PAGE_SIZE;
+            let len = remaining
+                .len()
+                .min(PAGE_SIZE - page_offset)
+                .min(dwords.len() * 8);
+
+            for (dword, bytes) in dwords.iter_mut().zip(remaining[..len].chunks_exact(8)) {
+                *dword = u64::from_le_bytes(bytes.try_into().unwrap());
+            }
+
+            let page = self.start_page + offset / PAGE_SIZE;
+            let dwords = &dwords[..len / 8];
+
+            #[cfg(feature = "l5")]
+            self.flash
+                .write_page_at(page, page_offset, dwords, Security::NonSecure)?;
+            #[cfg(not(feature = "l5"))]
+            self.flash.write_page_at(page, page_offset, dwords)?;
+
+            offset += len;
+            remaining = &remaining[len..];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "embedded-storage", not(feature = "f4")))]
+impl nor_flash::NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::PageOutOfRange => NorFlashErrorKind::OutOfBounds,
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(all(feature = "embedded-storage", not(feature = "f4")))]
+impl<'a> ErrorType for FlashRegion<'a> {
+    type Error = Error;
+}
+
+#[cfg(all(feature = "embedded-storage", not(feature = "f4")))]
+impl<'a> ReadNorFlash for FlashRegion<'a> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        FlashRegion::read(self, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        FlashRegion::capacity(self)
+    }
+}
+
+#[cfg(all(feature = "embedded-storage", not(feature = "f4")))]
+impl<'a> NorFlash for FlashRegion<'a> {
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = PAGE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        FlashRegion::erase(self, from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        FlashRegion::write(self, offset, bytes)
+    }
 }
@@ -0,0 +1,260 @@
+//! Measures the frequency of a digital signal, from sub-Hz to MHz, using timer input capture. The
+//! counter runs at the timer clock, and each capture timestamps an input edge; the frequency is the
+//! number of edges over the time between the first and last edges of a gate. This is reciprocal
+//! counting: the result is accurate to ±1 timer tick over the gate, independent of the input
+//! frequency, plus the accuracy of the timer clock.
+//!
+//! Ranging is automatic: The input capture prescaler is set to capture every 1, 2, 4, or 8 edges,
+//! to keep the capture interrupt rate below `max_capture_rate`, and a gate lasts at least
+//! `gate_time`, and at least one captured edge to the next, so slow signals are measured over a
+//! whole period. The input frequency this can measure is limited by the interrupt rate: up to 8
+//! times `max_capture_rate`.
+//!
+//! Example, using TIM2 channel 1:
+//!
+//! `let mut meter = FreqMeter::new(TimChannel::C1, Default::default());`
+//! `timer.enable_freq_meter(&mut meter);`
+//!
+//! Then, in the timer's interrupt handler: `timer.handle_freq_meter(&mut meter);`, and to read
+//! measurements, eg: `if let Some(m) = meter.measurement() { let freq = m.freq; }`.
+//!
+//! Use a 32-bit timer (eg TIM2) where available: A 16-bit one overflows, and interrupts, every
+//! 65_536 timer clock cycles.
+
+use crate::timer::TimChannel;
+
+/// The maximum input capture prescaler setting: capture every 8 edges.
+const MAX_PSC: u8 = 3;
+
+/// The minimum number of captures in a gate before checking if the capture rate is too high.
+const RANGE_CHECK_CAPTURES: u32 = 16;
+
+/// Initial configuration data for frequency measurement.
+#[derive(Clone)]
+pub struct FreqMeterConfig {
+    /// The minimum gate time, in μs. Longer gates give lower uncertainty, and less frequent
+    /// measurements. Defaults to 100ms.
+    pub gate_time: u32,
+    /// The maximum capture interrupt rate, in Hz; the capture prescaler is raised to stay below
+    /// this. Defaults to 50kHz.
+    pub max_capture_rate: u32,
+    /// If no edge is captured for this long, in μs, the signal is considered lost, and the
+    /// measurement is cleared. This must be longer than the period of the slowest signal measured.
+    /// It's only checked at timer overflows. Defaults to 10s.
+    pub timeout: u32,
+    /// The accuracy of the timer clock, in parts per million, included in the reported
+    /// uncertainty. Eg 20 - 50 for an HSE crystal, or 10_000 for HSI. Defaults to 50.
+    pub clock_ppm: u32,
+}
+
+impl Default for FreqMeterConfig {
+    fn default() -> Self {
+        Self {
+            gate_time: 100_000,
+            max_capture_rate: 50_000,
+            timeout: 10_000_000,
+            clock_ppm: 50,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The result of a frequency measurement.
+pub struct Measurement {
+    /// The measured frequency, in Hz.
+    pub freq: f32,
+    /// The uncertainty of the frequency, in Hz: ± this value.
+    pub uncertainty: f32,
+    /// The time the measurement was made over, in seconds.
+    pub gate_time: f32,
+    /// The number of input edges counted in the gate.
+    pub edges: u32,
+}
+
+/// Stores the state of frequency measurement, on one timer channel.
+pub struct FreqMeter {
+    channel: TimChannel,
+    pub cfg: FreqMeterConfig,
+    /// Timer counter rate, in Hz.
+    tick_freq: u32,
+    /// The number of ticks per counter overflow.
+    wrap: u64,
+    /// The time of the most recent overflow, in ticks since the measurement started.
+    overflow_ticks: u64,
+    /// The input capture prescaler setting; captures are every `2^psc` edges.
+    psc: u8,
+    /// The time of the first capture of the current gate, in ticks.
+    gate_start: Option<u64>,
+    /// The number of captures in the current gate, after the first.
+    captures: u32,
+    /// The time of the most recent capture, in ticks.
+    last_capture: u64,
+    measurement: Option<Measurement>,
+}
+
+impl FreqMeter {
+    /// Create a new frequency meter, for the given timer channel.
+    pub fn new(channel: TimChannel, cfg: FreqMeterConfig) -> Self {
+        assert!(
+            cfg.max_capture_rate > 0,
+            "The maximum capture rate must be greater than 0."
+        );
+
+        Self {
+            channel,
+            cfg,
+            tick_freq: 0,
+            wrap: 0,
+            overflow_ticks: 0,
+            psc: 0,
+            gate_start: None,
+            captures: 0,
+            last_capture: 0,
+            measurement: None,
+        }
+    }
+
+    /// The timer channel measured.
+    pub fn channel(&self) -> TimChannel {
+        self.channel
+    }
+
+    /// The most recent measurement. Returns `None` if no gate has completed yet, or if the signal
+    /// is lost.
+    pub fn measurement(&self) -> Option<Measurement> {
+        self.measurement
+    }
+
+    /// The most recently measured frequency, in Hz. Returns `None` if no gate has completed yet,
+    /// or if the signal is lost.
+    pub fn freq(&self) -> Option<f32> {
+        self.measurement.map(|m| m.freq)
+    }
+
+    /// The number of input edges per capture, selected by ranging: 1, 2, 4, or 8.
+    pub fn capture_prescaler(&self) -> u8 {
+        1 << self.psc
+    }
+
+    /// Reset the measurement and ranging, and set the counter rate, and the ticks per counter
+    /// overflow. For use in the timer module.
+    pub(crate) fn start(&mut self, tick_freq: u32, wrap: u64) {
+        self.tick_freq = tick_freq;
+        self.wrap = wrap;
+        self.overflow_ticks = 0;
+        self.psc = 0;
+        self.gate_start = None;
+        self.captures = 0;
+        self.last_capture = 0;
+        self.measurement = None;
+    }
+
+    /// Convert a time in μs to timer ticks.
+    fn ticks(&self, us: u32) -> u64 {
+        us as u64 * self.tick_freq as u64 / 1_000_000
+    }
+
+    /// Record a capture; `count` is the captured counter value. Returns a new input capture
+    /// prescaler setting, if ranging changed it. For use in the timer module.
+    pub(crate) fn on_capture(&mut self, count: u32) -> Option<u8> {
+        let time = self.overflow_ticks + count as u64;
+        self.last_capture = time;
+
+        let start = match self.gate_start {
+            Some(s) => s,
+            None => {
+                self.gate_start = Some(time);
+                self.captures = 0;
+                return None;
+            }
+        };
+
+        self.captures += 1;
+        let elapsed = time - start;
+        let edges = self.captures << self.psc;
+
+        // Range up during a gate if captures are too frequent, instead of waiting for the gate to
+        // end; at high input frequencies, that could be many interrupts.
+        if self.captures >= RANGE_CHECK_CAPTURES && self.psc < MAX_PSC {
+            let rate = self.captures as u64 * self.tick_freq as u64 / elapsed.max(1);
+            if rate > self.cfg.max_capture_rate as u64 {
+                let input_freq = (edges as u64 * self.tick_freq as u64 / elapsed.max(1)) as u32;
+                return self.set_psc(self.psc_for(input_freq, self.cfg.max_capture_rate));
+            }
+        }
+
+        if elapsed < self.ticks(self.cfg.gate_time) {
+            return None;
+        }
+
+        let freq = edges as f32 * self.tick_freq as f32 / elapsed as f32;
+        // ±1 tick over the gate, from sampling the edges at the start and end, and the clock's
+        // accuracy.
+        let rel_uncertainty = 1. / elapsed as f32 + self.cfg.clock_ppm as f32 / 1_000_000.;
+
+        self.measurement = Some(Measurement {
+            freq,
+            uncertainty: freq * rel_uncertainty,
+            gate_time: elapsed as f32 / self.tick_freq as f32,
+            edges,
+        });
+
+        // This capture starts the next gate.
+        self.gate_start = Some(time);
+        self.captures = 0;
+
+        // Range down with hysteresis, so a frequency near a range boundary doesn't switch
+        // prescaler settings each gate.
+        let input_freq = freq as u32;
+        let up = self.psc_for(input_freq, self.cfg.max_capture_rate);
+        let down = self.psc_for(input_freq, self.cfg.max_capture_rate / 2);
+
+        if up > self.psc {
+            self.set_psc(up)
+        } else if down < self.psc {
+            self.set_psc(down)
+        } else {
+            None
+        }
+    }
+
+    /// Record a timer overflow, and check for a lost signal. Returns a new input capture prescaler
+    /// setting, if the signal is lost, and ranging reset it. For use in the timer module.
+    pub(crate) fn on_overflow(&mut self) -> Option<u8> {
+        self.overflow_ticks += self.wrap;
+
+        if self.overflow_ticks.saturating_sub(self.last_capture) <= self.ticks(self.cfg.timeout) {
+            return None;
+        }
+
+        self.measurement = None;
+        self.gate_start = None;
+        // Prevents reporting the signal lost at each overflow, and the time since the last capture
+        // from growing without bound.
+        self.last_capture = self.overflow_ticks;
+
+        if self.psc != 0 {
+            self.set_psc(0)
+        } else {
+            None
+        }
+    }
+
+    /// The smallest prescaler setting that keeps the capture rate at or below `max_rate`.
+    fn psc_for(&self, input_freq: u32, max_rate: u32) -> u8 {
+        let mut psc = 0;
+        while psc < MAX_PSC && (input_freq >> psc) > max_rate {
+            psc += 1;
+        }
+        psc
+    }
+
+    /// Change the prescaler setting. Changing it resets the timer's prescaler counter, so this
+    /// restarts the gate.
+    fn set_psc(&mut self, psc: u8) -> Option<u8> {
+        self.psc = psc;
+        self.gate_start = None;
+        self.captures = 0;
+        Some(psc)
+    }
+}
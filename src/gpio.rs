@@ -8,10 +8,14 @@
 
 #[cfg(feature = "embedded-hal")]
 use core::convert::Infallible;
+#[cfg(feature = "l5")]
+use core::fmt;
 
-use cortex_m::interrupt::free;
+use crate::util::free;
 
 use crate::{
+    exti,
+    interrupt::{self, Binding, Exti},
     pac::{self, RCC},
     rcc_en_reset, // todo?
 };
@@ -20,8 +24,16 @@ use crate::{
 use embedded_hal::digital::v2::{InputPin, OutputPin, ToggleableOutputPin};
 
 use cfg_if::cfg_if;
+#[cfg(feature = "gpio-pac-fields")]
 use paste::paste;
 
+#[cfg(feature = "l5")]
+/// The offset of each port's secure alias from its non-secure address.
+const SECURE_ALIAS_OFFSET: usize = 0x1000_0000;
+#[cfg(feature = "l5")]
+/// The offset of the `SECCFGR` register in a port's register block.
+const SECCFGR_OFFSET: usize = 0x30;
+
 #[derive(Copy, Clone)]
 #[repr(u8)]
 /// Values for `GPIOx_MODER`
@@ -66,6 +78,30 @@ pub enum OutputSpeed {
     High = 0b11, // Called "Very high speed" on some families.
 }
 
+impl OutputSpeed {
+    /// A conservative maximum output frequency for this setting, in Hz, with a 50pF load, and
+    /// VDD of at least 2.7V. It varies between families; see the datasheet's "I/O AC
+    /// characteristics" table. Used to validate communication bitrates.
+    pub fn max_freq(&self) -> u32 {
+        cfg_if! {
+            if #[cfg(feature = "f3")] {
+                match self {
+                    Self::Low => 2_000_000,
+                    Self::Medium => 10_000_000,
+                    Self::High => 50_000_000,
+                }
+            } else {
+                match self {
+                    Self::Low => 2_000_000,
+                    Self::Medium => 25_000_000,
+                    Self::Fast => 50_000_000,
+                    Self::High => 100_000_000,
+                }
+            }
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 #[repr(u8)]
 /// Values for `GPIOx_PUPDR`
@@ -99,8 +135,87 @@ pub enum ResetState {
     Reset = 1,
 }
 
-// todo: If you get rid of Port struct, rename this enum Port
+#[cfg(feature = "l5")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// GPIO errors.
+pub enum GpioError {
+    /// The pin is secure, and the core is in the non-secure state; its configuration is
+    /// write-ignored.
+    SecurePin,
+}
+
+#[cfg(feature = "l5")]
+impl fmt::Display for GpioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::SecurePin => {
+                f.write_str("pin is secure, and can't be configured from non-secure code")
+            }
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
+/// Common roles of alternate function pins. Used by `Pin::configure_for` to set the output type,
+/// speed, and pull recommended for the role, in addition to the mode and alternate function.
+/// Using a higher speed than required increases EMI and ringing; using a lower one degrades
+/// edges at high clock rates.
+pub enum PinRole {
+    /// I2C SCL or SDA: Open drain, with pull-up. The internal pull-ups (~40kΩ) are only suitable
+    /// for low speeds and short buses; use external ones in most cases.
+    I2c,
+    /// USART or UART TX: Push-pull, with pull-up so the line idles high while the peripheral is
+    /// disabled.
+    UsartTx,
+    /// USART or UART RX: Pull-up, so the receiver doesn't see noise as a start bit when the
+    /// line is disconnected.
+    UsartRx,
+    /// SPI SCK, MOSI, or MISO: Push-pull, floating, high speed.
+    Spi,
+    /// SDMMC CK: Push-pull, floating, very high speed.
+    SdmmcClk,
+    /// SDMMC CMD or D0-D7: Push-pull, with pull-up (required by the SD spec, if not fitted
+    /// externally), very high speed.
+    SdmmcCmdData,
+    /// QSPI or OCTOSPI CLK, or IO0-IO7: Push-pull, floating, very high speed.
+    Qspi,
+    /// QSPI or OCTOSPI NCS: Push-pull, with pull-up so the flash is deselected while the
+    /// peripheral is disabled, very high speed.
+    QspiNcs,
+}
+
+impl PinRole {
+    /// The recommended output type for this role.
+    pub const fn output_type(&self) -> OutputType {
+        match self {
+            Self::I2c => OutputType::OpenDrain,
+            _ => OutputType::PushPull,
+        }
+    }
+
+    /// The recommended output speed for this role.
+    pub const fn output_speed(&self) -> OutputSpeed {
+        match self {
+            Self::I2c | Self::UsartTx | Self::UsartRx => OutputSpeed::Low,
+            #[cfg(not(feature = "f3"))]
+            Self::Spi => OutputSpeed::Fast,
+            #[cfg(feature = "f3")]
+            Self::Spi => OutputSpeed::High,
+            Self::SdmmcClk | Self::SdmmcCmdData | Self::Qspi | Self::QspiNcs => OutputSpeed::High,
+        }
+    }
+
+    /// The recommended pull resistor for this role.
+    pub const fn pull(&self) -> Pull {
+        match self {
+            Self::Spi | Self::SdmmcClk | Self::Qspi => Pull::Floating,
+            _ => Pull::Up,
+        }
+    }
+}
+
+// todo: If you get rid of Port struct, rename this enum Port
+#[derive(Copy, Clone, PartialEq)]
 /// GPIO port letter
 pub enum Port {
     A,
@@ -160,7 +275,7 @@ pub enum Port {
 
 impl Port {
     /// See F303 RM section 12.1.3: each reg has an associated value
-    fn cr_val(&self) -> u8 {
+    pub(crate) fn cr_val(&self) -> u8 {
         match self {
             Self::A => 0,
             Self::B => 1,
@@ -219,14 +334,13 @@ impl Port {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-/// The pulse edge used to trigger interrupts.
-pub enum Edge {
-    Rising,
-    Falling,
-}
+pub use crate::exti::Edge;
 
-// These macros are used to interate over pin number, for use with PAC fields.
+// These macros are used to interate over pin number, for use with PAC fields. With the
+// `gpio-pac-fields` feature, they match on the pin to use each pin's PAC field accessor. Otherwise,
+// they shift into the raw register word, which generates less code, and fewer branches; each pin's
+// field is at the same offset in all families' PACs.
+#[cfg(feature = "gpio-pac-fields")]
 macro_rules! set_field {
     ($regs: expr, $pin:expr, $reg:ident, $field:ident, $bit:ident, $val:expr, [$($num:expr),+]) => {
         paste! {
@@ -242,6 +356,7 @@ macro_rules! set_field {
     }
 }
 
+#[cfg(feature = "gpio-pac-fields")]
 macro_rules! set_alt {
     ($regs: expr, $pin:expr, $field_af:ident, $val:expr, [$(($num:expr, $lh:ident)),+]) => {
         paste! {
@@ -263,6 +378,7 @@ macro_rules! set_alt {
     }
 }
 
+#[cfg(feature = "gpio-pac-fields")]
 macro_rules! get_input_data {
     ($regs: expr, $pin:expr, [$($num:expr),+]) => {
         paste! {
@@ -278,6 +394,7 @@ macro_rules! get_input_data {
     }
 }
 
+#[cfg(feature = "gpio-pac-fields")]
 macro_rules! set_state {
     ($regs: expr, $pin:expr, $offset: expr, [$($num:expr),+]) => {
         paste! {
@@ -293,129 +410,138 @@ macro_rules! set_state {
     }
 }
 
-// todo: Consolidate these exti macros
-
-// Reduce DRY for setting up interrupts.
-macro_rules! set_exti {
-    ($pin:expr, $trigger:expr, $val:expr, [$(($num:expr, $crnum:expr)),+]) => {
-        let exti = unsafe { &(*pac::EXTI::ptr()) };
-        let syscfg  = unsafe { &(*pac::SYSCFG::ptr()) };
-
-        paste! {
-            match $pin {
-                $(
-                    $num => {
-                    // todo: Core 2 interrupts for wb. (?)
-                        cfg_if! {
-                            if #[cfg(all(feature = "h7", not(any(feature = "h747cm4", feature = "h747cm7"))))] {
-                                exti.cpuimr1.modify(|_, w| w.[<mr $num>]().set_bit());
-                            } else if #[cfg(any(feature = "h747cm4", feature = "h747cm7"))] {
-                                exti.c1imr1.modify(|_, w| w.[<mr $num>]().set_bit());
-                            }else if #[cfg(any(feature = "g4", feature = "wb", feature = "wl"))] {
-                                exti.imr1.modify(|_, w| w.[<im $num>]().set_bit());
-                            } else {
-                                exti.imr1.modify(|_, w| w.[<mr $num>]().set_bit());
-                            }
-                        }
+#[cfg(not(feature = "gpio-pac-fields"))]
+macro_rules! set_field {
+    // `bit` fields are 1 bit wide per pin, and `bits` fields 2 bits.
+    ($regs: expr, $pin:expr, $reg:ident, $field:ident, bit, $val:expr, [$($num:expr),+]) => {
+        set_field!(@shift $regs, $pin, $reg, 1, $val)
+    };
+    ($regs: expr, $pin:expr, $reg:ident, $field:ident, bits, $val:expr, [$($num:expr),+]) => {
+        set_field!(@shift $regs, $pin, $reg, 2, $val)
+    };
+    (@shift $regs: expr, $pin:expr, $reg:ident, $width:expr, $val:expr) => {{
+        let pin = $pin as u32;
+        assert!(pin <= 15, "GPIO pins must be 0 - 15.");
+        let shift = pin * $width;
+        let mask = ((1 << $width) - 1) << shift;
+        unsafe {
+            (*$regs)
+                .$reg
+                .modify(|r, w| w.bits((r.bits() & !mask) | (($val as u32) << shift)));
+        }
+    }};
+}
 
-                        cfg_if! {
-                            if #[cfg(any(feature = "g4", feature = "wb", feature = "wl"))] {
-                                exti.rtsr1.modify(|_, w| w.[<rt $num>]().bit($trigger));
-                                exti.ftsr1.modify(|_, w| w.[<ft $num>]().bit(!$trigger));
-                            // } else if #[cfg(any(feature = "wb", feature = "wl"))] {
-                            //     // todo: Missing in PAC, so we read+write. https://github.com/stm32-rs/stm32-rs/issues/570
-                            //     let val_r =  $exti.rtsr1.read().bits();
-                            //     $exti.rtsr1.write(|w| unsafe { w.bits(val_r | (1 << $num)) });
-                            //     let val_f =  $exti.ftsr1.read().bits();
-                            //     $exti.ftsr1.write(|w| unsafe { w.bits(val_f | (1 << $num)) });
-                            //     // todo: Core 2 interrupts.
-                            } else {
-                                exti.rtsr1.modify(|_, w| w.[<tr $num>]().bit($trigger));
-                                exti.ftsr1.modify(|_, w| w.[<tr $num>]().bit(!$trigger));
-                            }
-                        }
-                        syscfg
-                            .[<exticr $crnum>]
-                            .modify(|_, w| unsafe { w.[<exti $num>]().bits($val) });
-                    }
-                )+
-                _ => panic!("GPIO pins must be 0 - 15."),
+#[cfg(not(feature = "gpio-pac-fields"))]
+macro_rules! set_alt {
+    ($regs: expr, $pin:expr, $field_af:ident, $val:expr, [$(($num:expr, $lh:ident)),+]) => {{
+        let pin = $pin as u32;
+        assert!(pin <= 15, "GPIO pins must be 0 - 15.");
+        let moder_shift = pin * 2;
+        // AF fields are 4 bits wide, pins 0 - 7 in `AFRL`, and 8 - 15 in `AFRH`.
+        let af_shift = (pin % 8) * 4;
+        unsafe {
+            (*$regs).moder.modify(|r, w| {
+                w.bits(
+                    (r.bits() & !(0b11 << moder_shift))
+                        | ((PinMode::Alt(0).val() as u32) << moder_shift),
+                )
+            });
+            if pin < 8 {
+                (*$regs).afrl.modify(|r, w| {
+                    w.bits((r.bits() & !(0xf << af_shift)) | (($val as u32) << af_shift))
+                });
+            } else {
+                (*$regs).afrh.modify(|r, w| {
+                    w.bits((r.bits() & !(0xf << af_shift)) | (($val as u32) << af_shift))
+                });
             }
         }
-    }
+    }};
 }
 
-#[cfg(feature = "f4")]
-// Similar to `set_exti`, but with reg names sans `1`.
-macro_rules! set_exti_f4 {
-    ($pin:expr, $trigger:expr, $val:expr, [$(($num:expr, $crnum:expr)),+]) => {
-        let exti = unsafe { &(*pac::EXTI::ptr()) };
-        let syscfg  = unsafe { &(*pac::SYSCFG::ptr()) };
+#[cfg(not(feature = "gpio-pac-fields"))]
+macro_rules! get_input_data {
+    ($regs: expr, $pin:expr, [$($num:expr),+]) => {{
+        let pin = $pin as u32;
+        assert!(pin <= 15, "GPIO pins must be 0 - 15.");
+        unsafe { (*$regs).idr.read().bits() >> pin & 1 != 0 }
+    }};
+}
 
-        paste! {
-            match $pin {
-                $(
-                    $num => {
-                        exti.imr.modify(|_, w| w.[<mr $num>]().unmasked());
-                        exti.rtsr.modify(|_, w| w.[<tr $num>]().bit($trigger));
-                        exti.ftsr.modify(|_, w| w.[<tr $num>]().bit(!$trigger));
-                        syscfg
-                            .[<exticr $crnum>]
-                            .modify(|_, w| unsafe { w.[<exti $num>]().bits($val) });
-                    }
-                )+
-                _ => panic!("GPIO pins must be 0 - 15."),
-            }
-        }
-    }
+#[cfg(not(feature = "gpio-pac-fields"))]
+macro_rules! set_state {
+    ($regs: expr, $pin:expr, $offset: expr, [$($num:expr),+]) => {{
+        let pin = $pin as u32;
+        assert!(pin <= 15, "GPIO pins must be 0 - 15.");
+        unsafe { (*$regs).bsrr.write(|w| w.bits(1 << ($offset + pin))) };
+    }};
 }
 
-#[cfg(feature = "l5")]
-// For L5 See `set_exti!`. Different method naming pattern for exticr.
-macro_rules! set_exti_l5 {
-    ($pin:expr, $trigger:expr, $val:expr, [$(($num:expr, $crnum:expr, $num2:expr)),+]) => {
-        let exti = unsafe { &(*pac::EXTI::ptr()) };
+#[derive(Clone, Copy, Default)]
+/// Pending changes to a pin's configuration, accumulated by `Pin::configure`. Settings not
+/// changed are left as they are.
+pub struct PinConfig {
+    mode: Option<PinMode>,
+    output_type: Option<OutputType>,
+    output_speed: Option<OutputSpeed>,
+    pull: Option<Pull>,
+}
 
-        paste! {
-            match $pin {
-                $(
-                    $num => {
-                        exti.imr1.modify(|_, w| w.[<im $num>]().set_bit());  // unmask
-                        exti.rtsr1.modify(|_, w| w.[<rt $num>]().bit($trigger));  // Rising trigger
-                        exti.ftsr1.modify(|_, w| w.[<ft $num>]().bit(!$trigger));   // Falling trigger
-                        exti
-                            .[<exticr $crnum>]
-                            .modify(|_, w| unsafe { w.[<exti $num2>]().bits($val) });
-                    }
-                )+
-                _ => panic!("GPIO pins must be 0 - 15."),
-            }
-        }
+impl PinConfig {
+    /// Set the mode, including the alternate function number for `Alt`.
+    pub fn mode(&mut self, value: PinMode) -> &mut Self {
+        self.mode = Some(value);
+        self
+    }
+
+    /// Set the output type.
+    pub fn output_type(&mut self, value: OutputType) -> &mut Self {
+        self.output_type = Some(value);
+        self
+    }
+
+    /// Set the output speed.
+    pub fn output_speed(&mut self, value: OutputSpeed) -> &mut Self {
+        self.output_speed = Some(value);
+        self
+    }
+
+    /// Set the internal pull resistor.
+    pub fn pull(&mut self, value: Pull) -> &mut Self {
+        self.pull = Some(value);
+        self
     }
 }
 
-#[cfg(feature = "g0")]
-// For G0. See `set_exti!`. Todo? Reduce DRY.
-macro_rules! set_exti_g0 {
-    ($pin:expr, $trigger:expr, $val:expr, [$(($num:expr, $crnum:expr, $num2:expr)),+]) => {
-        let exti = unsafe { &(*pac::EXTI::ptr()) };
+/// Replace a pin's field of `width` bits in a register word.
+fn replace_field(word: u32, pin: u8, width: u8, value: u32) -> u32 {
+    let shift = pin as u32 * width as u32;
+    let mask = ((1 << width) - 1) << shift;
+    (word & !mask) | (value << shift)
+}
 
-        paste! {
-            match $pin {
-                $(
-                    $num => {
-                        exti.imr1.modify(|_, w| w.[<im $num>]().set_bit());  // unmask
-                        exti.rtsr1.modify(|_, w| w.[<tr $num>]().bit($trigger));  // Rising trigger
-                        // This field name is probably a PAC error.
-                        exti.ftsr1.modify(|_, w| w.[<tr $num>]().bit(!$trigger));   // Falling trigger
-                        exti
-                            .[<exticr $crnum>]
-                            .modify(|_, w| unsafe { w.[<exti $num2>]().bits($val) });
-                    }
-                )+
-                _ => panic!("GPIO pins must be 0 - 15."),
-            }
-        }
+#[derive(Clone, Copy, PartialEq)]
+/// A pin's port and number, without the ability to configure it. Unlike `Pin`, it can be created
+/// in a `const` context, and copied, eg for board definition tables. Create the pin from it with
+/// `pin`. Example: `pub const LED: PinId = PinId::new(Port::A, 5);`
+pub struct PinId {
+    /// The GPIO port letter.
+    pub port: Port,
+    /// The pin number: 0 - 15.
+    pub pin: u8,
+}
+
+impl PinId {
+    pub const fn new(port: Port, pin: u8) -> Self {
+        assert!(pin <= 15, "Pin must be 0 - 15.");
+
+        Self { port, pin }
+    }
+
+    /// Create the pin, with a specific mode. See `Pin::new`.
+    pub fn pin(self, mode: PinMode) -> Pin {
+        Pin::new(self.port, self.pin, mode)
     }
 }
 
@@ -428,10 +554,14 @@ pub struct Pin {
 }
 
 impl Pin {
-    /// Internal function to get the appropriate GPIO block pointer.
-    const fn regs(&self) -> *const pac::gpioa::RegisterBlock {
-        // Note that we use this `const` fn and pointer casting since not all ports actually
-        // deref to GPIOA in PAC.
+    /// Internal function to get the appropriate GPIO block pointer. On L5, in the secure state,
+    /// this is the port's secure alias, since secure pins are only accessible through it.
+    fn regs(&self) -> *const pac::gpioa::RegisterBlock {
+        #[cfg(feature = "l5")]
+        if crate::gtzc::is_secure_state() {
+            return (regs(self.port) as usize + SECURE_ALIAS_OFFSET) as *const _;
+        }
+
         regs(self.port)
     }
 
@@ -440,6 +570,31 @@ impl Pin {
     pub fn new(port: Port, pin: u8, mode: PinMode) -> Self {
         assert!(pin <= 15, "Pin must be 0 - 15.");
 
+        Self::enable_port_clock(port);
+
+        Self::new_unchecked(port, pin, mode)
+    }
+
+    /// Create a new pin, with a specific mode, without enabling the port's RCC clock; it must
+    /// already be enabled, eg with `enable_port_clocks` at startup. This skips the RCC read and
+    /// critical section of `new`, for pins created frequently, eg in interrupt handlers or generic
+    /// drivers. If the clock isn't enabled, the mode isn't set.
+    pub fn new_unchecked(port: Port, pin: u8, mode: PinMode) -> Self {
+        assert!(pin <= 15, "Pin must be 0 - 15.");
+
+        let mut result = Self { port, pin };
+        result.mode(mode);
+
+        result
+    }
+
+    /// The pin's port and number.
+    pub fn id(&self) -> PinId {
+        PinId::new(self.port, self.pin)
+    }
+
+    /// Enable a port's RCC peripheral clock, and reset the port, if not already enabled.
+    fn enable_port_clock(port: Port) {
         free(|_| {
             let rcc = unsafe { &(*RCC::ptr()) };
 
@@ -734,11 +889,6 @@ impl Pin {
                 }
             }
         });
-
-        let mut result = Self { port, pin };
-        result.mode(mode);
-
-        result
     }
 
     /// Set pin mode. Eg, Output, Input, Analog, or Alt. Sets the `MODER` register.
@@ -754,10 +904,160 @@ impl Pin {
         );
 
         if let PinMode::Alt(alt) = value {
+            // Catch AF numbers that aren't assigned to anything on this pin.
+            #[cfg(feature = "af-tables")]
+            debug_assert!(
+                crate::af::is_valid(self.port, self.pin, alt) != Some(false),
+                "AF {} isn't available on this pin; see the datasheet's alternate function mapping table.",
+                alt
+            );
+
             self.alt_fn(alt);
         }
     }
 
+    /// Change several settings at once, with one read-modify-write per register, in a single
+    /// critical section. `MODER` is written last, so the pin doesn't drive its new output type,
+    /// speed, or alternate function before they're set. Example:
+    /// `pin.configure(|cfg| { cfg.mode(PinMode::Output).output_speed(OutputSpeed::High); });`.
+    /// Sets the `OTYPER`, `OSPEEDR`, `PUPDR`, `AFR`, and `MODER` registers, as required.
+    pub fn configure<F: FnOnce(&mut PinConfig)>(&mut self, f: F) {
+        let mut cfg = PinConfig::default();
+        f(&mut cfg);
+
+        self.write_config(cfg);
+    }
+
+    /// Write the settings changed in `cfg`. Used by `configure` and `try_configure`.
+    fn write_config(&mut self, cfg: PinConfig) {
+        if let Some(PinMode::Alt(alt)) = cfg.mode {
+            assert!(alt <= 15, "Alt function must be 0 to 15.");
+
+            #[cfg(feature = "af-tables")]
+            debug_assert!(
+                crate::af::is_valid(self.port, self.pin, alt) != Some(false),
+                "AF {} isn't available on this pin; see the datasheet's alternate function mapping table.",
+                alt
+            );
+        }
+
+        let pin = self.pin;
+
+        free(|_| unsafe {
+            let regs = &*self.regs();
+
+            if let Some(v) = cfg.output_type {
+                regs.otyper
+                    .modify(|r, w| w.bits(replace_field(r.bits(), pin, 1, v as u32)));
+            }
+            if let Some(v) = cfg.output_speed {
+                regs.ospeedr
+                    .modify(|r, w| w.bits(replace_field(r.bits(), pin, 2, v as u32)));
+            }
+            if let Some(v) = cfg.pull {
+                regs.pupdr
+                    .modify(|r, w| w.bits(replace_field(r.bits(), pin, 2, v as u32)));
+            }
+            if let Some(mode) = cfg.mode {
+                if let PinMode::Alt(alt) = mode {
+                    // AF fields are 4 bits wide, pins 0 - 7 in `AFRL`, and 8 - 15 in `AFRH`.
+                    if pin < 8 {
+                        regs.afrl
+                            .modify(|r, w| w.bits(replace_field(r.bits(), pin, 4, alt as u32)));
+                    } else {
+                        regs.afrh.modify(|r, w| {
+                            w.bits(replace_field(r.bits(), pin - 8, 4, alt as u32))
+                        });
+                    }
+                }
+                regs.moder
+                    .modify(|r, w| w.bits(replace_field(r.bits(), pin, 2, mode.val() as u32)));
+            }
+        });
+    }
+
+    #[cfg(feature = "l5")]
+    /// Like `configure`, but returns an error if the pin is secure and the core is in the
+    /// non-secure state, or if the registers don't hold the new settings after writing; the
+    /// hardware ignores writes to a secure pin from non-secure code. Reads the `SECCFGR`
+    /// register, and the registers written.
+    pub fn try_configure<F: FnOnce(&mut PinConfig)>(&mut self, f: F) -> Result<(), GpioError> {
+        if self.is_secure() && !crate::gtzc::is_secure_state() {
+            return Err(GpioError::SecurePin);
+        }
+
+        let mut cfg = PinConfig::default();
+        f(&mut cfg);
+
+        self.write_config(cfg);
+
+        if !self.config_matches(cfg) {
+            return Err(GpioError::SecurePin);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "l5")]
+    /// Returns `true` if the registers hold the settings changed in `cfg`.
+    fn config_matches(&self, cfg: PinConfig) -> bool {
+        let pin = self.pin as u32;
+        let field = |word: u32, width: u32| (word >> (pin * width)) & ((1 << width) - 1);
+        let regs = unsafe { &*self.regs() };
+
+        if let Some(v) = cfg.output_type {
+            if field(regs.otyper.read().bits(), 1) != v as u32 {
+                return false;
+            }
+        }
+        if let Some(v) = cfg.output_speed {
+            if field(regs.ospeedr.read().bits(), 2) != v as u32 {
+                return false;
+            }
+        }
+        if let Some(v) = cfg.pull {
+            if field(regs.pupdr.read().bits(), 2) != v as u32 {
+                return false;
+            }
+        }
+        if let Some(mode) = cfg.mode {
+            if let PinMode::Alt(alt) = mode {
+                // AF fields are 4 bits wide, pins 0 - 7 in `AFRL`, and 8 - 15 in `AFRH`.
+                let (afr, i) = if pin < 8 {
+                    (regs.afrl.read().bits(), pin)
+                } else {
+                    (regs.afrh.read().bits(), pin - 8)
+                };
+                if (afr >> (i * 4)) & 0xf != alt as u32 {
+                    return false;
+                }
+            }
+            if field(regs.moder.read().bits(), 2) != mode.val() as u32 {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Configure the pin for an alternate function, with the output type, speed, and pull
+    /// recommended for its role. Example: `scl.configure_for(PinRole::I2c, 4);`. Sets the
+    /// `MODER`, `AFR`, `OTYPER`, `OSPEEDR`, and `PUPDR` registers.
+    pub fn configure_for(&mut self, role: PinRole, alt_fn: u8) {
+        self.configure(|cfg| {
+            cfg.output_type(role.output_type())
+                .output_speed(role.output_speed())
+                .pull(role.pull())
+                .mode(PinMode::Alt(alt_fn));
+        });
+    }
+
+    /// Return the pin to analog mode (its reset state, and the lowest-power configuration), and
+    /// return its port and pin number. Doesn't disable the port's RCC clock, since other pins
+    /// may be using it.
+    pub fn free(mut self) -> (Port, u8) {
+        self.mode(PinMode::Analog);
+        (self.port, self.pin)
+    }
+
     /// Set output type. Sets the `OTYPER` register.
     pub fn output_type(&mut self, value: OutputType) {
         set_field!(
@@ -812,6 +1112,37 @@ impl Pin {
         );
     }
 
+    #[cfg(feature = "l5")]
+    /// Set the pin as secure, or non-secure. A secure pin's configuration and data registers are
+    /// only accessible from secure code; eg, to reserve it for a secure LED or tamper line. Must
+    /// be called from the secure state. Sets the `SECCFGR` register, through the port's secure
+    /// alias.
+    pub fn set_secure(&mut self, secure: bool) {
+        assert!(
+            crate::gtzc::is_secure_state(),
+            "Pin security can only be set from the secure state."
+        );
+
+        let pin = self.pin;
+
+        // The PAC marks `SECCFGR` as write-only, so we access it by address. We're in the secure
+        // state, so `regs` is the secure alias.
+        let addr = (self.regs() as usize + SECCFGR_OFFSET) as *mut u32;
+
+        free(|_| unsafe {
+            let val = addr.read_volatile();
+            addr.write_volatile(replace_field(val, pin, 1, secure as u32));
+        });
+    }
+
+    #[cfg(feature = "l5")]
+    /// Returns `true` if the pin is secure. This is readable from non-secure code. Reads the
+    /// `SECCFGR` register.
+    pub fn is_secure(&self) -> bool {
+        let addr = (self.regs() as usize + SECCFGR_OFFSET) as *const u32;
+        unsafe { addr.read_volatile() & (1 << self.pin) != 0 }
+    }
+
     /// Read the input data register. Eg determine if the pin is high or low. See also `is_high()`
     /// and `is_low()`. Reads from the `IDR` register.
     pub fn get_state(&mut self) -> PinState {
@@ -864,45 +1195,31 @@ impl Pin {
         }
     }
 
-    #[cfg(not(any(feature = "f373", feature = "wl")))]
-    /// Configure this pin as an interrupt source. Set the edge as Rising or Falling.
+    /// Configure this pin as an interrupt source, triggered on a rising edge, falling edge, or
+    /// both. Selects this pin's port for its EXTI line, and unmasks the line. See `exti::Exti`.
     pub fn enable_interrupt(&mut self, edge: Edge) {
-        let rise_trigger = match edge {
-            Edge::Rising => {
-                // configure EXTI line to trigger on rising edge, disable trigger on falling edge.
-                true
-            }
-            Edge::Falling => {
-                // configure EXTI line to trigger on falling edge, disable trigger on rising edge.
-                false
-            }
-        };
+        let mut line = exti::Exti::new(self.pin);
+        line.select_port(self.port);
+        line.set_edge(edge);
+        line.unmask();
+    }
 
-        cfg_if! {
-            if #[cfg(feature = "g0")] {
-                set_exti_g0!(self.pin, rise_trigger, self.port.cr_val(), [(0, 1, 0_7), (1, 1, 0_7), (2, 1, 0_7),
-                    (3, 1, 0_7), (4, 2, 0_7), (5, 2, 0_7), (6, 2, 0_7), (7, 2, 0_7), (8, 3, 8_15),
-                    (9, 3, 8_15), (10, 3, 8_15), (11, 3, 8_15), (12, 4, 8_15),
-                    (13, 4, 8_15), (14, 4, 8_15), (15, 4, 8_15)]
-                );
-            } else if #[cfg(feature = "l5")] {
-                set_exti_l5!(self.pin, rise_trigger, self.port.cr_val(), [(0, 1, 0_7), (1, 1, 0_7), (2, 1, 0_7),
-                    (3, 1, 0_7), (4, 2, 0_7), (5, 2, 0_7), (6, 2, 0_7), (7, 2, 0_7), (8, 3, 8_15),
-                    (9, 3, 8_15), (10, 3, 8_15), (11, 3, 8_15), (12, 4, 8_15),
-                    (13, 4, 8_15), (14, 4, 8_15), (15, 4, 8_15)]
-                );
-            } else if #[cfg(feature = "f4")] {
-                set_exti_f4!(self.pin, rise_trigger, self.port.cr_val(), [(0, 1), (1, 1), (2, 1),
-                        (3, 1), (4, 2), (5, 2), (6, 2), (7, 2), (8, 3), (9, 3), (10, 3), (11, 3), (12, 4),
-                        (13, 4), (14, 4), (15, 4)]
-                );
-            } else {
-                set_exti!(self.pin, rise_trigger, self.port.cr_val(), [(0, 1), (1, 1), (2, 1),
-                    (3, 1), (4, 2), (5, 2), (6, 2), (7, 2), (8, 3), (9, 3), (10, 3), (11, 3), (12, 4),
-                    (13, 4), (14, 4), (15, 4)]
-                );
-            }
-        }
+    /// Stop this pin from generating interrupts, by masking its EXTI line and disabling its edge
+    /// triggers.
+    pub fn disable_interrupt(&mut self) {
+        let mut line = exti::Exti::new(self.pin);
+        line.mask();
+        line.disable_edges();
+    }
+
+    /// Configure this pin as an interrupt source, and unmask its EXTI line in the NVIC. `irqs` is
+    /// the token created by `bind_interrupts!`, and proves a handler exists for this line. Eg:
+    /// `pin.bind_interrupt::<5>(Edge::Rising, &Irqs)`.
+    pub fn bind_interrupt<const LINE: u8>(&mut self, edge: Edge, irqs: &impl Binding<Exti<LINE>>) {
+        assert!(self.pin == LINE, "The interrupt binding must be for this pin's EXTI line.");
+
+        self.enable_interrupt(edge);
+        interrupt::unmask(irqs);
     }
 
     /// Check if the pin's input voltage is high. Reads from the `IDR` register.
@@ -1003,6 +1320,49 @@ pub fn set_low(port: Port, pin: u8) {
     set_state(port, pin, PinState::Low);
 }
 
+/// Set and clear multiple pins on a port in a single write, so they change in the same clock
+/// cycle; eg for stepper motor phase patterns, parallel bus strobes, or many LEDs at once.
+/// `set_mask` and `reset_mask` are bitmasks, with bit `n` for pin `n`; pins in neither are
+/// unchanged. If a pin is in both, it's set. Sets the `BSRR` register. Atomic, so pins on the port
+/// not in either mask can be changed elsewhere, eg from an interrupt, without a critical section.
+/// Does not require a `Pin` struct.
+pub fn write_pins(port: Port, set_mask: u16, reset_mask: u16) {
+    // RM: "If both BSx and BRx are set, BSx has priority."
+    unsafe {
+        (*regs(port))
+            .bsrr
+            .write(|w| w.bits(((reset_mask as u32) << 16) | set_mask as u32));
+    }
+}
+
+/// Read the input levels of all pins on a port, with bit `n` for pin `n`. Reads from the `IDR`
+/// register. Does not require a `Pin` struct.
+pub fn read_port(port: Port) -> u16 {
+    unsafe { (*regs(port)).idr.read().bits() as u16 }
+}
+
+/// Enable the RCC peripheral clocks of several GPIO ports, if not already enabled; eg once at
+/// startup, before creating pins with `Pin::new_unchecked`.
+pub fn enable_port_clocks(ports: &[Port]) {
+    for port in ports {
+        Pin::enable_port_clock(*port);
+    }
+}
+
+/// Clear an EXTI interrupt's pending flag, for a given line. Sets the `PR` register. Atomic.
+/// Does not require a `Pin` struct. See `exti::Exti::clear_pending`.
+pub fn clear_exti_interrupt(line: u8) {
+    exti::Exti::new(line).clear_pending();
+}
+
+/// Trigger an EXTI interrupt from software, for a given line (0 - 31). The line must be unmasked,
+/// eg with `Pin::enable_interrupt`. Useful for deferring work to a lower-priority interrupt, or
+/// for testing interrupt handlers. Clear it as you would a hardware-triggered interrupt, with
+/// `clear_exti_interrupt`. Sets the `SWIER` register. Atomic. Does not require a `Pin` struct.
+pub fn trigger_exti_interrupt(line: u8) {
+    exti::Exti::new(line).trigger();
+}
+
 /// Set a pin state (ie set high or low output voltage level). See also `set_high()` and
 /// `set_low()`. Sets the `BSRR` register. Atomic.
 /// Does not require a `Pin` struct.
@@ -1020,7 +1380,127 @@ fn set_state(port: Port, pin: u8, value: PinState) {
     );
 }
 
-const fn regs(port: Port) -> *const pac::gpioa::RegisterBlock {
+/// Implemented for the PAC GPIO port peripherals, for use with `GpioPort`.
+pub trait GpioPeriph {
+    const PORT: Port;
+}
+
+macro_rules! impl_gpio_periph {
+    ($GPIO:ident, $port:ident) => {
+        impl GpioPeriph for pac::$GPIO {
+            const PORT: Port = Port::$port;
+        }
+    };
+}
+
+impl_gpio_periph!(GPIOA, A);
+impl_gpio_periph!(GPIOB, B);
+#[cfg(not(feature = "wl"))]
+impl_gpio_periph!(GPIOC, C);
+#[cfg(not(any(feature = "f410", feature = "wl")))]
+impl_gpio_periph!(GPIOD, D);
+#[cfg(not(any(
+    feature = "f301",
+    feature = "f3x4",
+    feature = "f410",
+    feature = "g0",
+    feature = "wb",
+    feature = "wl"
+)))]
+impl_gpio_periph!(GPIOE, E);
+#[cfg(not(any(
+    feature = "f401",
+    feature = "f410",
+    feature = "f411",
+    feature = "l4x1",
+    feature = "l4x2",
+    feature = "l412",
+    feature = "l4x3",
+    feature = "wb",
+    feature = "wl"
+)))]
+impl_gpio_periph!(GPIOF, F);
+#[cfg(not(any(
+    feature = "f373",
+    feature = "f301",
+    feature = "f3x4",
+    feature = "f401",
+    feature = "f410",
+    feature = "f411",
+    feature = "l4",
+    feature = "g0",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl"
+)))]
+impl_gpio_periph!(GPIOG, G);
+#[cfg(not(any(
+    feature = "f373",
+    feature = "f301",
+    feature = "f3x4",
+    feature = "f410",
+    feature = "l4",
+    feature = "g0",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl"
+)))]
+impl_gpio_periph!(GPIOH, H);
+
+/// An owned GPIO port. This is an optional, stricter alternative to `Pin::new`: It takes the PAC
+/// port peripheral by value, so the port can't be aliased by other drivers, and checks that each
+/// pin is only created once. Return pins with `release`, and the PAC peripheral with `free`.
+/// Example: `let mut gpioa = GpioPort::new(dp.GPIOA); let pa1 = gpioa.pin(1, PinMode::Output);`
+pub struct GpioPort<R: GpioPeriph> {
+    pub regs: R,
+    /// Bit `n` is set if pin `n` has been created, and not released.
+    taken: u16,
+}
+
+impl<R: GpioPeriph> GpioPort<R> {
+    pub fn new(regs: R) -> Self {
+        Self { regs, taken: 0 }
+    }
+
+    /// Create a pin on this port, with a specific mode. Enables the RCC peripheral clock to the
+    /// port, if not already enabled. Panics if the pin has already been created, and not released.
+    pub fn pin(&mut self, pin: u8, mode: PinMode) -> Pin {
+        assert!(pin <= 15, "Pin must be 0 - 15.");
+        assert!(self.taken & (1 << pin) == 0, "Pin is already in use.");
+
+        self.taken |= 1 << pin;
+        Pin::new(R::PORT, pin, mode)
+    }
+
+    /// Return a pin to analog mode, and allow it to be created again.
+    pub fn release(&mut self, pin: Pin) {
+        assert!(
+            pin.port.cr_val() == R::PORT.cr_val(),
+            "Pin is from a different port."
+        );
+
+        let (_, pin) = pin.free();
+        self.taken &= !(1 << pin);
+    }
+
+    /// Set and clear multiple pins on this port in a single write. See `gpio::write_pins`. Panics
+    /// if a mask includes a pin that hasn't been created with `pin`.
+    pub fn write_pins(&mut self, set_mask: u16, reset_mask: u16) {
+        assert!(
+            (set_mask | reset_mask) & !self.taken == 0,
+            "Masks must only include pins created on this port."
+        );
+        write_pins(R::PORT, set_mask, reset_mask);
+    }
+
+    /// Return the PAC port peripheral. Panics if any pins haven't been released.
+    pub fn free(self) -> R {
+        assert!(self.taken == 0, "All pins must be released first.");
+        self.regs
+    }
+}
+
+pub(crate) const fn regs(port: Port) -> *const pac::gpioa::RegisterBlock {
     // Note that we use this `const` fn and pointer casting since not all ports actually
     // deref to GPIOA in PAC.
     match port {
@@ -0,0 +1,502 @@
+//! Support for the Global TrustZone Controller (GTZC), on L5. This configures, from the secure
+//! world, which peripherals and SRAM blocks are secure, or privileged-only, and reports illegal
+//! accesses to them, eg a non-secure write to a secure peripheral, with the `GTZC` interrupt.
+//! TrustZone must be enabled, with the `TZEN` option bit, for these settings to have an effect.
+//!
+//! The GTZC has 3 parts: The TrustZone security controller (TZSC), which sets attributes of
+//! peripherals, and the watermarks of external memories; the block-based memory protection
+//! controllers (MPCBB1 and MPCBB2), which set attributes of SRAM1 and SRAM2, in 256-byte blocks;
+//! and the TrustZone illegal access controller (TZIC).
+//!
+//! Example, making USART1 and the first 64kB of SRAM1 secure, and interrupting on illegal access:
+//!
+//! ```
+//! let mut gtzc = Gtzc::new(
+//!     dp.SEC_GTZC_TZSC,
+//!     dp.SEC_GTZC_TZIC,
+//!     dp.SEC_GTZC_MPCBB1,
+//!     dp.SEC_GTZC_MPCBB2,
+//! );
+//! gtzc.set_secure(SecurePeriph::Usart1, true);
+//! gtzc.set_sram_secure(Sram::Sram1, 0, 256, true);
+//! gtzc.enable_illegal_access_interrupt(IllegalAccess::Usart1);
+//! gtzc.lock();
+//! ```
+//!
+//! In the `GTZC` interrupt handler:
+//!
+//! ```
+//! while let Some(source) = gtzc.illegal_access() {
+//!     // Handle the access, eg by logging `source`, or resetting.
+//!     gtzc.clear_illegal_access(source);
+//! }
+//! ```
+//!
+//! See L552 RM, chapter 5: Global TrustZone controller (GTZC).
+
+use crate::{
+    pac::{RCC, SEC_GTZC_MPCBB1, SEC_GTZC_MPCBB2, SEC_GTZC_TZIC, SEC_GTZC_TZSC},
+    util::free,
+};
+
+// MPCBB register offsets, and `MPCBBx_CR` fields. We access these by offset, since the PAC has
+// a separate type for each MPCBB, with differently-named registers.
+const MPCBB_CR: usize = 0x00;
+const MPCBB_LCKVTR1: usize = 0x10;
+const MPCBB_VCTR0: usize = 0x100;
+const MPCBB_CR_INVSECSTATE: u32 = 1 << 30;
+const MPCBB_CR_SRWILADIS: u32 = 1 << 31;
+
+/// `CPUID`, in the non-secure alias of the system control block.
+const SCB_NS_CPUID: usize = 0xe002_ed00;
+
+/// The size of an SRAM block, in bytes.
+pub const BLOCK_SIZE: usize = 256;
+
+/// Generates an enum of register bits, indexed as `32 * register + bit`, and a function to look
+/// up a variant by index.
+macro_rules! bit_enum {
+    ($(#[$meta:meta])* $name:ident { $($(#[$vmeta:meta])* $variant:ident = $index:literal,)+ }) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        #[repr(u8)]
+        pub enum $name {
+            $($(#[$vmeta])* $variant = $index,)+
+        }
+
+        impl $name {
+            fn from_index(index: u8) -> Option<Self> {
+                match index {
+                    $($index => Some(Self::$variant),)+
+                    _ => None,
+                }
+            }
+
+            /// The register number, from 0, and bit.
+            fn reg_bit(&self) -> (usize, u32) {
+                (*self as usize / 32, 1 << (*self as u32 % 32))
+            }
+        }
+    };
+}
+
+bit_enum! {
+    /// Peripherals with a configurable security and privilege attribute. Sets `TZSC_SECCFGRx`
+    /// and `TZSC_PRIVCFGRx` registers. Other peripherals, eg GPIO, DMA, and RCC, have their own
+    /// security configuration.
+    SecurePeriph {
+        Tim2 = 0,
+        Tim3 = 1,
+        Tim4 = 2,
+        Tim5 = 3,
+        Tim6 = 4,
+        Tim7 = 5,
+        Wwdg = 6,
+        Iwdg = 7,
+        Spi2 = 8,
+        Spi3 = 9,
+        Usart2 = 10,
+        Usart3 = 11,
+        Uart4 = 12,
+        Uart5 = 13,
+        I2c1 = 14,
+        I2c2 = 15,
+        I2c3 = 16,
+        Crs = 17,
+        Dac = 18,
+        Opamp = 19,
+        Lptim1 = 20,
+        Lpuart1 = 21,
+        I2c4 = 22,
+        Lptim2 = 23,
+        Lptim3 = 24,
+        Fdcan1 = 25,
+        UsbFs = 26,
+        Ucpd1 = 27,
+        Vrefbuf = 28,
+        Comp = 29,
+        Tim1 = 30,
+        Spi1 = 31,
+        Tim8 = 32,
+        Usart1 = 33,
+        Tim15 = 34,
+        Tim16 = 35,
+        Tim17 = 36,
+        Sai1 = 37,
+        Sai2 = 38,
+        Dfsdm1 = 39,
+        Crc = 40,
+        Tsc = 41,
+        Icache = 42,
+        Adc = 43,
+        Aes = 44,
+        Hash = 45,
+        Rng = 46,
+        Pka = 47,
+        Sdmmc1 = 48,
+        /// The FMC (FSMC) registers.
+        FmcReg = 49,
+        /// The OCTOSPI1 registers.
+        Octospi1Reg = 50,
+    }
+}
+
+bit_enum! {
+    /// Sources of illegal access events. Reads `TZIC_SRx` register.
+    IllegalAccess {
+        Tim2 = 0,
+        Tim3 = 1,
+        Tim4 = 2,
+        Tim5 = 3,
+        Tim6 = 4,
+        Tim7 = 5,
+        Wwdg = 6,
+        Iwdg = 7,
+        Spi2 = 8,
+        Spi3 = 9,
+        Usart2 = 10,
+        Usart3 = 11,
+        Uart4 = 12,
+        Uart5 = 13,
+        I2c1 = 14,
+        I2c2 = 15,
+        I2c3 = 16,
+        Crs = 17,
+        Dac = 18,
+        Opamp = 19,
+        Lptim1 = 20,
+        Lpuart1 = 21,
+        I2c4 = 22,
+        Lptim2 = 23,
+        Lptim3 = 24,
+        Fdcan1 = 25,
+        UsbFs = 26,
+        Ucpd1 = 27,
+        Vrefbuf = 28,
+        Comp = 29,
+        Tim1 = 30,
+        Spi1 = 31,
+        Tim8 = 32,
+        Usart1 = 33,
+        Tim15 = 34,
+        Tim16 = 35,
+        Tim17 = 36,
+        Sai1 = 37,
+        Sai2 = 38,
+        Dfsdm1 = 39,
+        Crc = 40,
+        Tsc = 41,
+        Icache = 42,
+        Adc = 43,
+        Aes = 44,
+        Hash = 45,
+        Rng = 46,
+        Pka = 47,
+        Sdmmc1 = 48,
+        FmcReg = 49,
+        Octospi1Reg = 50,
+        Rtc = 51,
+        Pwr = 52,
+        Syscfg = 53,
+        Dma1 = 54,
+        Dma2 = 55,
+        Dmamux1 = 56,
+        Rcc = 57,
+        /// The flash memory.
+        Flash = 58,
+        /// The flash interface registers.
+        FlashReg = 59,
+        Exti = 60,
+        Otfdec1 = 61,
+        Tzsc = 64,
+        Tzic = 65,
+        /// The OCTOSPI1 memory, via its watermark.
+        Mpcwm1 = 66,
+        /// The FMC memory, via its watermark.
+        Mpcwm2 = 67,
+        /// SRAM1 memory, via MPCBB1.
+        Mpcbb1 = 68,
+        Mpcbb1Reg = 69,
+        /// SRAM2 memory, via MPCBB2.
+        Mpcbb2 = 70,
+        Mpcbb2Reg = 71,
+    }
+}
+
+impl IllegalAccess {
+    /// The peripheral this source is an access to, if its security attribute is configurable with
+    /// `Gtzc::set_secure`.
+    pub fn periph(&self) -> Option<SecurePeriph> {
+        SecurePeriph::from_index(*self as u8)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// An SRAM with block-based security attribution.
+pub enum Sram {
+    /// SRAM1: 192kB, from `0x2000_0000`. Configured with MPCBB1.
+    Sram1,
+    /// SRAM2: 64kB, from `0x2003_0000`. Configured with MPCBB2.
+    Sram2,
+}
+
+impl Sram {
+    /// The number of 256-byte blocks.
+    pub fn num_blocks(&self) -> usize {
+        match self {
+            Self::Sram1 => 192 * 1_024 / BLOCK_SIZE,
+            Self::Sram2 => 64 * 1_024 / BLOCK_SIZE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// An external memory region with a non-secure watermark. Sets `TZSC_MPCWMx_NSWMRx` registers.
+pub enum ExternalMemory {
+    /// OCTOSPI1 memory, in 128kB units.
+    Octospi1,
+    /// FMC NOR/PSRAM memory, subregion 1, in 128kB units.
+    FmcNor1,
+    /// FMC NOR/PSRAM memory, subregion 2, in 128kB units.
+    FmcNor2,
+    /// FMC NAND memory, in 128kB units.
+    FmcNand,
+}
+
+/// Represents the GTZC peripheral, through its secure aliases. Its registers may only be written
+/// from the secure world.
+pub struct Gtzc {
+    pub tzsc: SEC_GTZC_TZSC,
+    pub tzic: SEC_GTZC_TZIC,
+    pub mpcbb1: SEC_GTZC_MPCBB1,
+    pub mpcbb2: SEC_GTZC_MPCBB2,
+}
+
+impl Gtzc {
+    /// Initialize the GTZC, enabling its clock. It isn't reset, since a previous boot stage
+    /// may have configured it.
+    pub fn new(
+        tzsc: SEC_GTZC_TZSC,
+        tzic: SEC_GTZC_TZIC,
+        mpcbb1: SEC_GTZC_MPCBB1,
+        mpcbb2: SEC_GTZC_MPCBB2,
+    ) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.ahb1enr.modify(|_, w| w.gtzcen().set_bit());
+        });
+
+        Self {
+            tzsc,
+            tzic,
+            mpcbb1,
+            mpcbb2,
+        }
+    }
+
+    /// Set a peripheral as secure, or non-secure. Secure peripherals are only accessible from the
+    /// secure world. Sets `TZSC_SECCFGRx` register.
+    pub fn set_secure(&mut self, periph: SecurePeriph, secure: bool) {
+        let (reg, bit) = periph.reg_bit();
+        if reg == 0 {
+            self.tzsc
+                .tzsc_seccfgr1
+                .modify(|r, w| unsafe { w.bits(set_bit(r.bits(), bit, secure)) });
+        } else {
+            self.tzsc
+                .tzsc_seccfgr2
+                .modify(|r, w| unsafe { w.bits(set_bit(r.bits(), bit, secure)) });
+        }
+    }
+
+    /// Returns `true` if a peripheral is secure. Reads `TZSC_SECCFGRx` register.
+    pub fn is_secure(&self, periph: SecurePeriph) -> bool {
+        let (reg, bit) = periph.reg_bit();
+        let val = if reg == 0 {
+            self.tzsc.tzsc_seccfgr1.read().bits()
+        } else {
+            self.tzsc.tzsc_seccfgr2.read().bits()
+        };
+
+        val & bit != 0
+    }
+
+    /// Set a peripheral as privileged-only, or accessible by unprivileged code. Sets
+    /// `TZSC_PRIVCFGRx` register.
+    pub fn set_privileged(&mut self, periph: SecurePeriph, privileged: bool) {
+        let (reg, bit) = periph.reg_bit();
+        if reg == 0 {
+            self.tzsc
+                .tzsc_privcfgr1
+                .modify(|r, w| unsafe { w.bits(set_bit(r.bits(), bit, privileged)) });
+        } else {
+            self.tzsc
+                .tzsc_privcfgr2
+                .modify(|r, w| unsafe { w.bits(set_bit(r.bits(), bit, privileged)) });
+        }
+    }
+
+    /// Set the non-secure area of an external memory: `len` units from `start`; the rest is
+    /// secure. Sets `TZSC_MPCWMx_NSWMRx` register, `NSWMxSTRT` and `NSWMxLGTH` fields.
+    pub fn set_external_nonsecure(&mut self, mem: ExternalMemory, start: u16, len: u16) {
+        assert!(start <= 0x7ff, "Watermark start must be 11 bits.");
+        assert!(len <= 0xfff, "Watermark length must be 12 bits.");
+
+        let val = start as u32 | (len as u32) << 16;
+
+        unsafe {
+            match mem {
+                ExternalMemory::Octospi1 => self.tzsc.tzsc_mpcwm1_nswmr1.write(|w| w.bits(val)),
+                ExternalMemory::FmcNor1 => self.tzsc.tzsc_mpcwm2_nswmr1.write(|w| w.bits(val)),
+                ExternalMemory::FmcNor2 => self.tzsc.tzsc_mpcwm2_nswmr2.write(|w| w.bits(val)),
+                ExternalMemory::FmcNand => self.tzsc.tzsc_mpcwm3_nswmr1.write(|w| w.bits(val)),
+            }
+        }
+    }
+
+    /// Lock the TZSC configuration until the next reset. Sets `TZSC_CR` register, `LCK` field.
+    pub fn lock(&mut self) {
+        self.tzsc.tzsc_cr.modify(|_, w| w.lck().set_bit());
+    }
+
+    /// Set `count` SRAM blocks, of 256 bytes, from block `start`, as secure, or non-secure. Sets
+    /// `MPCBBx_VCTRy` registers.
+    pub fn set_sram_secure(&mut self, sram: Sram, start: usize, count: usize, secure: bool) {
+        assert!(
+            start + count <= sram.num_blocks(),
+            "SRAM blocks are out of range."
+        );
+
+        for block in start..start + count {
+            let offset = MPCBB_VCTR0 + (block / 32) * 4;
+            let val = self.mpcbb_read(sram, offset);
+            self.mpcbb_write(sram, offset, set_bit(val, 1 << (block % 32), secure));
+        }
+    }
+
+    /// Returns `true` if an SRAM block is secure. Reads `MPCBBx_VCTRy` register.
+    pub fn is_sram_secure(&self, sram: Sram, block: usize) -> bool {
+        assert!(block < sram.num_blocks(), "SRAM block is out of range.");
+
+        self.mpcbb_read(sram, MPCBB_VCTR0 + (block / 32) * 4) & (1 << (block % 32)) != 0
+    }
+
+    /// Configure an SRAM's behavior with secure accesses. With `invert`, secure blocks are
+    /// non-secure, and vice versa; this is useful if most of it is secure. With `allow_ns_read`,
+    /// secure code may read (but not write) non-secure blocks without an illegal access event.
+    /// Sets `MPCBBx_CR` register, `INVSECSTATE` and `SRWILADIS` fields.
+    pub fn set_sram_mode(&mut self, sram: Sram, invert: bool, allow_ns_read: bool) {
+        let mut val = self.mpcbb_read(sram, MPCBB_CR);
+        val = set_bit(val, MPCBB_CR_INVSECSTATE, invert);
+        val = set_bit(val, MPCBB_CR_SRWILADIS, allow_ns_read);
+        self.mpcbb_write(sram, MPCBB_CR, val);
+    }
+
+    /// Lock the configuration of each 8kB superblock of an SRAM (32 blocks), with bit `n` of
+    /// `superblocks` for superblock `n`, until the next reset. Sets `MPCBBx_LCKVTR1` register.
+    pub fn lock_sram(&mut self, sram: Sram, superblocks: u32) {
+        let val = self.mpcbb_read(sram, MPCBB_LCKVTR1);
+        self.mpcbb_write(sram, MPCBB_LCKVTR1, val | superblocks);
+    }
+
+    /// Enable the `GTZC` interrupt for an illegal access source. Sets `TZIC_IERx` register.
+    pub fn enable_illegal_access_interrupt(&mut self, source: IllegalAccess) {
+        self.set_illegal_access_interrupt(source, true);
+    }
+
+    /// Disable the `GTZC` interrupt for an illegal access source. Clears `TZIC_IERx` register.
+    pub fn disable_illegal_access_interrupt(&mut self, source: IllegalAccess) {
+        self.set_illegal_access_interrupt(source, false);
+    }
+
+    /// Returns `true` if an illegal access from this source has occurred. Reads `TZIC_SRx`
+    /// register.
+    pub fn is_illegal_access(&self, source: IllegalAccess) -> bool {
+        let (reg, bit) = source.reg_bit();
+        self.illegal_access_flags(reg) & bit != 0
+    }
+
+    /// Returns the first source with an illegal access flag set, if any. Reads `TZIC_SRx`
+    /// registers. Clear the flag with `clear_illegal_access`, eg in a loop, to decode all of them.
+    pub fn illegal_access(&self) -> Option<IllegalAccess> {
+        for reg in 0..3 {
+            let flags = self.illegal_access_flags(reg);
+            if flags != 0 {
+                return IllegalAccess::from_index((reg * 32) as u8 + flags.trailing_zeros() as u8);
+            }
+        }
+        None
+    }
+
+    /// Clear an illegal access flag. Sets `TZIC_FCRx` register.
+    pub fn clear_illegal_access(&mut self, source: IllegalAccess) {
+        let (reg, bit) = source.reg_bit();
+
+        unsafe {
+            match reg {
+                0 => self.tzic.fcr1.write(|w| w.bits(bit)),
+                1 => self.tzic.fcr2.write(|w| w.bits(bit)),
+                _ => self.tzic.fcr3.write(|w| w.bits(bit)),
+            }
+        }
+    }
+
+    fn set_illegal_access_interrupt(&mut self, source: IllegalAccess, enabled: bool) {
+        let (reg, bit) = source.reg_bit();
+
+        unsafe {
+            match reg {
+                0 => self
+                    .tzic
+                    .ier1
+                    .modify(|r, w| w.bits(set_bit(r.bits(), bit, enabled))),
+                1 => self
+                    .tzic
+                    .ier2
+                    .modify(|r, w| w.bits(set_bit(r.bits(), bit, enabled))),
+                _ => self
+                    .tzic
+                    .ier3
+                    .modify(|r, w| w.bits(set_bit(r.bits(), bit, enabled))),
+            }
+        }
+    }
+
+    fn illegal_access_flags(&self, reg: usize) -> u32 {
+        match reg {
+            0 => self.tzic.sr1.read().bits(),
+            1 => self.tzic.sr2.read().bits(),
+            _ => self.tzic.sr3.read().bits(),
+        }
+    }
+
+    fn mpcbb_base(&self, sram: Sram) -> usize {
+        match sram {
+            Sram::Sram1 => &*self.mpcbb1 as *const _ as usize,
+            Sram::Sram2 => &*self.mpcbb2 as *const _ as usize,
+        }
+    }
+
+    fn mpcbb_read(&self, sram: Sram, offset: usize) -> u32 {
+        unsafe { ((self.mpcbb_base(sram) + offset) as *const u32).read_volatile() }
+    }
+
+    fn mpcbb_write(&mut self, sram: Sram, offset: usize, val: u32) {
+        unsafe { ((self.mpcbb_base(sram) + offset) as *mut u32).write_volatile(val) }
+    }
+}
+
+/// Returns `true` if the core is running in the secure state. This reads `CPUID` through the
+/// non-secure alias of the system control block, which reads as 0 from the non-secure state.
+pub fn is_secure_state() -> bool {
+    unsafe { (SCB_NS_CPUID as *const u32).read_volatile() != 0 }
+}
+
+/// Set or clear `bit` in `val`.
+fn set_bit(val: u32, bit: u32, set: bool) -> u32 {
+    if set {
+        val | bit
+    } else {
+        val & !bit
+    }
+}
@@ -3,9 +3,7 @@
 
 use crate::pac::{self, HSEM, RCC};
 
-use cortex_m::interrupt::free;
-
-use paste::paste;
+use crate::util::free;
 
 #[derive(Clone, Copy)]
 /// The core that's performing the requested operation. Core 1 is the M4 core, and Core 2 is the M0+ core.
@@ -15,21 +13,79 @@ pub enum Core {
     C2,
 }
 
-pub struct Hsem {
-    regs: HSEM,
+impl Core {
+    /// The `COREID` value for this core's AHB bus master.
+    fn id(&self) -> u32 {
+        match self {
+            Self::C1 => 4,
+            Self::C2 => 8,
+        }
+    }
 }
 
-// Helper, since we need to access one of to 31 similarly-named registers.
-macro_rules! set_register_sem {
-    ($semaphore_num:expr, $regs:expr, $core_id:expr, $proc_id:expr) => {
-        paste! {
-            $regs.[<r $semaphore_num>].modify(|_, w| {
-                w.procid().bits($proc_id);
-                w.coreid().bits($core_id);
-                w.lock().set_bit()
-            })
-        }
-    };
+// Semaphore IDs used by ST's wireless stack on CPU2, and its reference applications on CPU1.
+// (`CFG_HW_*_SEMID` in their `app_conf.h`). Use these when accessing the shared resources.
+
+/// Protects RNG access.
+pub const SEM_RNG: u8 = 0;
+/// Protects PKA access.
+pub const SEM_PKA: u8 = 1;
+/// Protects flash access, by either core.
+pub const SEM_FLASH: u8 = 2;
+/// Protects RCC registers, eg when changing clocks.
+pub const SEM_RCC: u8 = 3;
+/// Held by the core entering Stop mode last, to coordinate clock restoration on wakeup.
+pub const SEM_STOP_ENTRY: u8 = 4;
+/// Protects the 48Mhz clock configuration, used by USB and RNG.
+pub const SEM_CLK48: u8 = 5;
+/// Held by CPU2 while it's not allowing CPU1 to write or erase flash.
+pub const SEM_FLASH_C2: u8 = 6;
+/// Held by CPU1 while it's writing or erasing flash.
+pub const SEM_FLASH_C1: u8 = 7;
+
+/// The `LOCK` bit of the `HSEM_Rx` and `HSEM_RLRx` registers.
+const LOCK: u32 = 1 << 31;
+
+/// The address of the `HSEM_Rx` register for a semaphore.
+fn r_ptr(semaphore_num: u8) -> *mut u32 {
+    (HSEM::ptr() as usize + 4 * semaphore_num as usize) as *mut u32
+}
+
+/// The address of the `HSEM_RLRx` register for a semaphore.
+fn rlr_ptr(semaphore_num: u8) -> *mut u32 {
+    (HSEM::ptr() as usize + 0x80 + 4 * semaphore_num as usize) as *mut u32
+}
+
+/// Try to lock a semaphore from CPU1, without an `Hsem` struct, eg from other modules. Uses the
+/// 1-step procedure. Returns `true` if the lock was successful. Enables the HSEM clock, but
+/// doesn't reset the peripheral, since CPU2 may be holding semaphores.
+pub fn try_lock(semaphore_num: u8) -> bool {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.ahb3enr.modify(|_, w| w.hsemen().set_bit());
+
+    unsafe { core::ptr::read_volatile(rlr_ptr(semaphore_num)) == LOCK | Core::C1.id() << 8 }
+}
+
+/// Lock a semaphore from CPU1, blocking until it's available.
+pub fn lock(semaphore_num: u8) {
+    while !try_lock(semaphore_num) {}
+}
+
+/// Unlock a semaphore locked by CPU1 with `lock` or `try_lock`.
+pub fn unlock(semaphore_num: u8) {
+    unsafe { core::ptr::write_volatile(r_ptr(semaphore_num), Core::C1.id() << 8) };
+}
+
+/// Run a closure while holding a semaphore; eg `hsem::with_lock(hsem::SEM_RCC, || ...)`.
+pub fn with_lock<R>(semaphore_num: u8, f: impl FnOnce() -> R) -> R {
+    lock(semaphore_num);
+    let result = f();
+    unlock(semaphore_num);
+    result
+}
+
+pub struct Hsem {
+    regs: HSEM,
 }
 
 /// Represents an Hardware Semiphore (HSEM) peripheral.
@@ -52,32 +108,32 @@ impl Hsem {
     }
 
     /// RM: The 2-step lock procedure consists in a write to lock the semaphore, followed by a read to
-    /// check if the lock has been successful, carried out from the HSEM_Rx register
-    pub fn lock_2_step(&mut self, core: Core, semaphore_num: u8) {
+    /// check if the lock has been successful, carried out from the HSEM_Rx register. Returns `true`
+    /// if the lock was successful.
+    pub fn lock_2_step(&mut self, core: Core, semaphore_num: u8) -> bool {
         if semaphore_num > 31 {
             panic!("Semaphore number must be 0 - 31.")
         }
 
-        // todo: You need a macro to do this! Currently only works on semaphore 1.
-
-        let core_id = 0; // todo temp!
-        let proc_id = 0; // todo temp!
+        let proc_id = 0;
 
         // * Write semaphore with PROCID and COREID, and LOCK = 1. The COREID data
         // written by software must match the AHB bus master information. i.e. a AHB bus master
-        // set_register_sem!(semaphore_num, self.regs, core_id, proc_id);  // todo problem with macro syntax
-
         // ID = 1writes data COREID = 1.
         // Lock is put in place when the semaphore is free at write time.
+        let val = LOCK | core.id() << 8 | proc_id;
+        unsafe { core::ptr::write_volatile(r_ptr(semaphore_num), val) };
+
         // * Read-back the semaphore
         // The software checks the lock status, if PROCID and COREID match the written data,
         // then the lock is confirmed.
         // * Else retry (the semaphore has been locked by another process, AHB bus master ID).
+        unsafe { core::ptr::read_volatile(r_ptr(semaphore_num)) == val }
     }
 
     /// RM: The 1-step procedure consists in a read to lock and check the semaphore in a single step,
-    /// carried out from the HSEM_RLRx register.
-    pub fn lock_1_step(&mut self, core: Core, semaphore_num: u8) {
+    /// carried out from the HSEM_RLRx register. Returns `true` if the lock was successful.
+    pub fn lock_1_step(&mut self, core: Core, semaphore_num: u8) -> bool {
         if semaphore_num > 31 {
             panic!("Semaphore number must be 0 - 31.")
         }
@@ -90,6 +146,7 @@ impl Hsem {
         // PROCID is 0. Read locking a locked semaphore returns the COREID and PROCID that
         // locked it. All read locks, including the first one that locks the semaphore, return the COREID
         // that locks or locked the semaphore.
+        unsafe { core::ptr::read_volatile(rlr_ptr(semaphore_num)) == LOCK | core.id() << 8 }
     }
 
     /// Unlock a semaphore.
@@ -105,12 +162,22 @@ impl Hsem {
         // semaphore interrupt shall be enabled.
         // The unlock procedure consists in a write to the semaphore HSEM_Rx register with
         // matching COREID regardless on how the semaphore has been locked (1-step or 2-step).
-        //  Write semaphore with PROCID, COREID, and LOCK = 0
-        //  If the written data matches the semaphore PROCID and COREID and the AHB bus
+        //  Write semaphore with PROCID, COREID, and LOCK = 0
+        //  If the written data matches the semaphore PROCID and COREID and the AHB bus
         // master ID , the semaphore is unlocked and an interrupt may be generated when
         // enabled, else write is ignored, semaphore remains locked and no interrupt is generated
         // (the semaphore is locked by another process, AHB bus master ID or the written data
         // does not match the AHB bus master signaling).
+        unsafe { core::ptr::write_volatile(r_ptr(semaphore_num), core.id() << 8) };
+    }
+
+    /// Returns `true` if a semaphore is locked, by either core. Reads the `HSEM_Rx` register,
+    /// `LOCK` field.
+    pub fn is_locked(&self, semaphore_num: u8) -> bool {
+        if semaphore_num > 31 {
+            panic!("Semaphore number must be 0 - 31.")
+        }
+        unsafe { core::ptr::read_volatile(r_ptr(semaphore_num)) & LOCK != 0 }
     }
 
     /// Enable an interrupt.
@@ -5,17 +5,27 @@
 use cast::u16;
 use core::ops::Deref;
 
-use cortex_m::interrupt::free;
-
 #[cfg(feature = "embedded-hal")]
 use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
 
+#[cfg(feature = "async")]
+use embedded_hal_async::i2c::{ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+
+#[cfg(feature = "async")]
+use crate::asynch::{self, I2C_WAKERS};
+
 use crate::{
+    interrupt::InterruptPeriph,
+    time::{Deadline, Duration},
     clocks::Clocks,
     pac::{self, RCC},
-    util::RccPeriph,
+    util::{free, RccPeriph},
 };
 
+use crate::gpio::OutputSpeed;
+
+pub use crate::util::BitrateError;
+
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::util::DmaPeriph;
 
@@ -37,9 +47,12 @@ use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::DmaInput;
 
+use cfg_if::cfg_if;
+
 // todo: Get rid of this macro.
 macro_rules! busy_wait {
-    ($regs:expr, $flag:ident) => {
+    ($regs:expr, $flag:ident, $timeout:expr) => {
+        let deadline = Deadline::new($timeout);
         loop {
             let isr = $regs.isr.read();
 
@@ -65,6 +78,8 @@ macro_rules! busy_wait {
                 }
 
                 return Err(Error::Nack);
+            } else if deadline.is_passed() {
+                return Err(Error::Timeout);
             } else {
                 // try again
             }
@@ -72,6 +87,39 @@ macro_rules! busy_wait {
     };
 }
 
+/// Wait asynchronously for a flag, using the interrupt that corresponds to it. Then return an
+/// error or continue, as in `busy_wait!`.
+#[cfg(feature = "async")]
+macro_rules! wait_async {
+    ($regs:expr, $flag:ident, $ie:ident, $timeout:expr) => {
+        {
+            let regs = &*$regs;
+
+            asynch::wait_for(
+                &I2C_WAKERS,
+                regs as *const _ as usize,
+                || {
+                    let isr = regs.isr.read();
+                    isr.$flag().bit_is_set()
+                        || isr.berr().bit_is_set()
+                        || isr.arlo().bit_is_set()
+                        || isr.nackf().bit_is_set()
+                },
+                || {
+                    regs.cr1.modify(|_, w| {
+                        w.$ie().set_bit();
+                        w.nackie().set_bit();
+                        w.errie().set_bit()
+                    })
+                },
+            )
+            .await;
+        }
+
+        busy_wait!($regs, $flag, $timeout);
+    };
+}
+
 /// I2C error
 #[non_exhaustive]
 #[derive(Debug)]
@@ -82,12 +130,44 @@ pub enum Error {
     Arbitration,
     /// NACK
     Nack,
+    /// A flag wasn't set before the timeout set in `I2cConfig` elapsed, eg due to a missing
+    /// pull-up, or a device holding the clock low.
+    Timeout,
     // Overrun, // slave mode only
     // Pec, // SMBUS mode only
     // Timeout, // SMBUS mode only
     // Alert, // SMBUS mode only
 }
 
+#[derive(Clone, Copy, Debug, Default)]
+/// A snapshot of an I2C peripheral's status, from `I2c::dump_state`; eg to log when a bus hangs.
+pub struct I2cState {
+    /// `CR1` register, `PE` field.
+    pub enabled: bool,
+    /// `ISR` register, `BUSY` field: A START condition has been detected on the bus.
+    pub busy: bool,
+    /// `BERR` field.
+    pub bus_error: bool,
+    /// `ARLO` field.
+    pub arbitration_lost: bool,
+    /// `OVR` field. Slave mode only.
+    pub overrun: bool,
+    /// `NACKF` field.
+    pub nack: bool,
+    /// `TIMEOUT` field. SMBus mode only.
+    pub timeout: bool,
+    /// `PECERR` field. SMBus mode only.
+    pub pec_error: bool,
+    /// `STOPF` field.
+    pub stop: bool,
+    /// `TXE` field.
+    pub tx_empty: bool,
+    /// `RXNE` field.
+    pub rx_not_empty: bool,
+    /// `TC` field.
+    pub transfer_complete: bool,
+}
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 /// Set master or slave mode. Sets the __ register, _ field.
@@ -112,7 +192,10 @@ pub enum I2cSpeed {
     Standard100K,
     /// Fast-mode: 400kHz.
     Fast400K,
-    /// Fast-mode +: 1Mhz.
+    /// Fast-mode +: 1Mhz. This also enables the Fast-mode Plus (20mA) drive on the peripheral's
+    /// pins, by setting its `I2Cx_FMP` bit in `SYSCFG_CFGR1` (`SYSCFG_PMCR` on H7); without this,
+    /// the edges are too slow for 1Mhz with typical pull-ups. Requires the SYSCFG clock to be
+    /// enabled (eg by `Clocks::setup`). Not supported on G0; set the bits manually.
     FastPlus1M,
 }
 
@@ -124,6 +207,54 @@ pub enum AddressBits {
     B10 = 1,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Which bits of the second own address (OA2) are ignored when matching a received address. Eg
+/// `Bits2` with an address of `0x50` responds to `0x50` - `0x53`, as with multiple EEPROM pages.
+/// Sets the OAR2 register, OA2MSK field.
+pub enum Oa2Mask {
+    /// All 7 bits are compared.
+    NoMask = 0,
+    /// Bit 0 (OA2[1]) is ignored.
+    Bits1 = 1,
+    /// Bits 1:0 (OA2[2:1]) are ignored.
+    Bits2 = 2,
+    Bits3 = 3,
+    Bits4 = 4,
+    Bits5 = 5,
+    Bits6 = 6,
+    /// All 7-bit addresses, except reserved ones, are acknowledged.
+    Bits7 = 7,
+}
+
+#[derive(Clone, Copy)]
+/// A second 7-bit address the slave responds to, in addition to `I2cConfig::own_address`.
+pub struct OwnAddress2 {
+    /// The 7-bit address, right-aligned.
+    pub address: u8,
+    pub mask: Oa2Mask,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The direction of a slave transfer, from the master's perspective. Read from the ISR register,
+/// DIR field.
+pub enum SlaveDirection {
+    /// The master is writing; this slave receives.
+    Write,
+    /// The master is reading; this slave transmits.
+    Read,
+}
+
+#[derive(Clone, Copy)]
+/// An address matched by this device, in slave mode.
+pub struct AddressMatch {
+    /// The 7-bit address received, right-aligned. This lets a slave with `OwnAddress2` masking
+    /// tell which of its addresses was matched. For 10-bit addresses, this is the header:
+    /// `0b11110` followed by the address's 2 MSBs.
+    pub address: u8,
+    pub direction: SlaveDirection,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 /// Set the number of address bits to 7 or 10. Sets the CR1 register, ANFOFF and DNF fields.
 pub enum NoiseFilter {
@@ -153,9 +284,19 @@ pub struct I2cConfig {
     pub noise_filter: NoiseFilter,
     /// Support for SMBUS, including hardware PEC, and alert pin. Defaults to false.
     pub smbus: bool,
+    /// This device's address, in slave mode: 7 bits, right-aligned, or 10 bits, as set by
+    /// `address_bits`. Sets the OAR1 register. Defaults to 0.
+    pub own_address: u16,
+    /// An optional second 7-bit address, with a mask to respond to a range of addresses. Only
+    /// relevant in slave mode. Sets the OAR2 register. Defaults to `None`.
+    pub own_address_2: Option<OwnAddress2>,
     /// Optionally disable clock stretching. Defaults to false (stretching allowed)
     /// Only relevant in slave mode.
     pub nostretch: bool,
+    /// The longest to wait for each step of a blocking transfer, before returning
+    /// `Error::Timeout`. Requires the `time` module's tick source to be initialized; has no
+    /// effect otherwise. `None` waits indefinitely. Defaults to 100ms.
+    pub timeout: Option<Duration>,
 }
 
 impl Default for I2cConfig {
@@ -166,22 +307,128 @@ impl Default for I2cConfig {
             address_bits: AddressBits::B7,
             noise_filter: NoiseFilter::Analog,
             smbus: false,
+            own_address: 0,
+            own_address_2: None,
             nostretch: false,
+            timeout: Some(Duration::from_millis(100)),
         }
     }
 }
 
+#[cfg(not(feature = "g0"))]
+/// Enable the Fast-mode Plus drive capability (20mA sink) on the pins of the I2C peripheral at
+/// `regs`. Sets `SYSCFG_CFGR1` register, `I2Cx_FMP` field (`SYSCFG_PMCR`, `I2CxFMP` on H7).
+fn enable_fast_mode_plus(regs: *const pac::i2c1::RegisterBlock) {
+    free(|_| {
+        let syscfg = unsafe { &(*pac::SYSCFG::ptr()) };
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                if regs == pac::I2C1::ptr() {
+                    syscfg.pmcr.modify(|_, w| w.i2c1fmp().set_bit());
+                } else if regs == pac::I2C2::ptr() {
+                    syscfg.pmcr.modify(|_, w| w.i2c2fmp().set_bit());
+                } else if regs == pac::I2C3::ptr() as *const _ {
+                    syscfg.pmcr.modify(|_, w| w.i2c3fmp().set_bit());
+                } else if regs == pac::I2C4::ptr() as *const _ {
+                    syscfg.pmcr.modify(|_, w| w.i2c4fmp().set_bit());
+                }
+            } else if #[cfg(feature = "wb")] {
+                if regs == pac::I2C1::ptr() {
+                    syscfg.cfgr1.modify(|_, w| w.i2c1_fmp().set_bit());
+                } else if regs == pac::I2C3::ptr() {
+                    syscfg.cfgr1.modify(|_, w| w.i2c3_fmp().set_bit());
+                }
+            } else if #[cfg(feature = "f3x4")] {
+                if regs == pac::I2C1::ptr() {
+                    syscfg.cfgr1.modify(|_, w| w.i2c1_fmp().set_bit());
+                }
+            } else {
+                if regs == pac::I2C1::ptr() {
+                    syscfg.cfgr1.modify(|_, w| w.i2c1_fmp().set_bit());
+                } else if regs == pac::I2C2::ptr() {
+                    syscfg.cfgr1.modify(|_, w| w.i2c2_fmp().set_bit());
+                }
+            }
+        }
+    });
+}
+
 /// Represents an Inter-Integrated Circuit (I2C) peripheral.
 pub struct I2c<R> {
     pub regs: R,
     pub cfg: I2cConfig,
 }
 
+impl<R: InterruptPeriph> I2c<R> {
+    /// The NVIC interrupt line this peripheral uses, eg for use with `interrupt::set_priority`.
+    pub fn interrupt(&self) -> pac::Interrupt {
+        R::INTERRUPT
+    }
+}
+
 impl<R> I2c<R>
 where
     // R: Deref<Target = pac::i2c1::RegisterBlock> + DmaPeriph + RccPeriph,
     R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
 {
+    /// Initialize an I2C peripheral, as with `new`, returning an error instead if APB1 is too
+    /// slow for the speed mode's timing, or too fast for the SCL low period to fit its 8-bit field,
+    /// or the speed is above the maximum for the pins' output speed setting.
+    pub fn try_new(
+        regs: R,
+        cfg: I2cConfig,
+        pin_speed: OutputSpeed,
+        clocks: &Clocks,
+    ) -> Result<Self, BitrateError> {
+        let clock_freq = clocks.apb1();
+
+        // The minimum I2C kernel clock for each mode. See L4 RM, section 39.4.9: "I2C master
+        // mode", table: "Minimum I2CCLK frequency in all I2C modes". Also `new`'s `presc_const`;
+        // the clock must be at least this, for a prescaler of at least 1. This raises the Standard
+        // mode minimum from the RM's 2Mhz.
+        let (freq, min_clock, presc_const) = match cfg.speed {
+            I2cSpeed::Standard10K => (10_000, 4_000_000, 4_000_000),
+            I2cSpeed::Standard100K => (100_000, 4_000_000, 4_000_000),
+            I2cSpeed::Fast400K => (400_000, 9_000_000, 8_000_000),
+            I2cSpeed::FastPlus1M => (1_000_000, 19_000_000, 8_000_000),
+        };
+
+        if clock_freq < min_clock {
+            return Err(BitrateError::AboveClock {
+                periph: "I2C",
+                requested: freq,
+                clock: "APB1",
+                clock_freq,
+            });
+        }
+        if freq > pin_speed.max_freq() {
+            return Err(BitrateError::PinSpeed {
+                periph: "I2C",
+                requested: freq,
+                max: pin_speed.max_freq(),
+            });
+        }
+
+        // The `SCLL` value, as `new` calculates it, before subtracting 1 to write it to the 8-bit
+        // field; the prescaler is at most 16.
+        let scll = if clock_freq / presc_const >= 16 {
+            (clock_freq / 16) / (2 * freq)
+        } else {
+            presc_const / (2 * freq)
+        };
+        if scll > 256 {
+            return Err(BitrateError::BelowClock {
+                periph: "I2C",
+                requested: freq,
+                clock: "APB1",
+                clock_freq,
+            });
+        }
+
+        Ok(Self::new(regs, cfg, clocks))
+    }
+
     /// Initialize a I2C peripheral, including configuration register writes, and enabling and resetting
     /// its RCC peripheral clock. `freq` is in Hz.
     pub fn new(regs: R, cfg: I2cConfig, clocks: &Clocks) -> Self {
@@ -327,6 +574,11 @@ where
         assert!(scll <= 255);
         assert!(sclh <= 255);
 
+        #[cfg(not(feature = "g0"))]
+        if let I2cSpeed::FastPlus1M = cfg.speed {
+            enable_fast_mode_plus(&*regs as *const _);
+        }
+
         regs.timingr.write(|w| unsafe {
             w.presc().bits(presc as u8);
             w.scldel().bits(scldel as u8);
@@ -360,6 +612,44 @@ where
 
         if let I2cMode::Slave = cfg.mode {
             regs.cr1.modify(|_, w| w.nostretch().bit(cfg.nostretch));
+
+            // L44 RM: "Own Address 1 ... OA1EN: Cleared by software to change the address."
+            // 7-bit addresses are written to OA1[7:1].
+            let oa1 = match cfg.address_bits {
+                AddressBits::B7 => {
+                    assert!(cfg.own_address <= 0x7f);
+                    cfg.own_address << 1
+                }
+                AddressBits::B10 => {
+                    assert!(cfg.own_address <= 0x3ff);
+                    cfg.own_address
+                }
+            };
+
+            regs.oar1.write(|w| w.oa1en().clear_bit());
+            regs.oar1.write(|w| unsafe {
+                cfg_if! {
+                    if #[cfg(feature = "g0")] {
+                        w.oa1_0().bit(oa1 & 1 != 0);
+                        w.oa1_7_1().bits(((oa1 >> 1) & 0x7f) as u8);
+                        w.oa1_8_9().bits((oa1 >> 8) as u8);
+                    } else {
+                        w.oa1().bits(oa1);
+                    }
+                }
+                w.oa1mode().bit(cfg.address_bits as u8 != 0);
+                w.oa1en().set_bit()
+            });
+
+            regs.oar2.write(|w| w.oa2en().clear_bit());
+            if let Some(oa2) = &cfg.own_address_2 {
+                assert!(oa2.address <= 0x7f);
+                regs.oar2.write(|w| unsafe {
+                    w.oa2().bits(oa2.address);
+                    w.oa2msk().bits(oa2.mask as u8);
+                    w.oa2en().set_bit()
+                });
+            }
         }
 
         let mut result = Self { regs, cfg };
@@ -405,6 +695,75 @@ where
         }
     }
 
+    /// In slave mode, returns the address received and transfer direction if this device has been
+    /// addressed, ie the ISR register's ADDR flag is set; the clock is stretched until it's cleared
+    /// with `clear_address_match`. Reads the ISR register, ADDCODE and DIR fields.
+    pub fn address_match(&self) -> Option<AddressMatch> {
+        let isr = self.regs.isr.read();
+        if isr.addr().bit_is_clear() {
+            return None;
+        }
+
+        Some(AddressMatch {
+            address: isr.addcode().bits(),
+            direction: if isr.dir().bit_is_set() {
+                SlaveDirection::Read
+            } else {
+                SlaveDirection::Write
+            },
+        })
+    }
+
+    /// Clear the address match flag, releasing the clock so the transfer continues. Sets the ICR
+    /// register, ADDRCF field.
+    pub fn clear_address_match(&mut self) {
+        self.regs.icr.write(|w| w.addrcf().set_bit());
+    }
+
+    /// Enable the address match interrupt, eg to handle slave transfers from an interrupt
+    /// handler, using `address_match`. Sets the CR1 register, ADDRIE field.
+    pub fn enable_address_match_interrupt(&mut self) {
+        self.regs.cr1.modify(|_, w| w.addrie().set_bit());
+    }
+
+    /// Read the peripheral's status flags, without modifying them. Reads the `ISR` register.
+    pub fn dump_state(&self) -> I2cState {
+        let isr = self.regs.isr.read();
+
+        I2cState {
+            enabled: self.regs.cr1.read().pe().bit_is_set(),
+            busy: isr.busy().bit_is_set(),
+            bus_error: isr.berr().bit_is_set(),
+            arbitration_lost: isr.arlo().bit_is_set(),
+            overrun: isr.ovr().bit_is_set(),
+            nack: isr.nackf().bit_is_set(),
+            timeout: isr.timeout().bit_is_set(),
+            pec_error: isr.pecerr().bit_is_set(),
+            stop: isr.stopf().bit_is_set(),
+            tx_empty: isr.txe().bit_is_set(),
+            rx_not_empty: isr.rxne().bit_is_set(),
+            transfer_complete: isr.tc().bit_is_set(),
+        }
+    }
+
+    /// Disable the peripheral by clearing `CR1` register, `PE` field, and return the PAC register
+    /// block, eg to reconfigure its pins for other uses. If `gate_clock` is `true`, also disable
+    /// its RCC peripheral clock.
+    pub fn free(self, gate_clock: bool) -> R {
+        // RM: "When cleared, PE must be kept low for at least 3 APB clock cycles."
+        self.regs.cr1.modify(|_, w| w.pe().clear_bit());
+        while self.regs.cr1.read().pe().bit_is_set() {}
+
+        if gate_clock {
+            free(|_| {
+                let rcc = unsafe { &(*RCC::ptr()) };
+                R::disable_clock(rcc);
+            });
+        }
+
+        self.regs
+    }
+
     /// Read multiple words to a buffer. Can return an error due to Bus, Arbitration, or NACK.
     pub fn read(&mut self, addr: u8, bytes: &mut [u8]) -> Result<(), Error> {
         // Wait for any previous address sequence to end
@@ -415,11 +774,11 @@ where
         // Set START and prepare to receive bytes into
         // `buffer`. The START bit can be set even if the bus
         // is BUSY or I2C is in slave mode.
-        self.set_cr2_read(addr, bytes.len() as u8);
+        self.set_cr2_read(addr, bytes.len() as u8, true);
 
         for byte in bytes {
             // Wait until we have received something
-            busy_wait!(self.regs, rxne);
+            busy_wait!(self.regs, rxne, self.cfg.timeout);
 
             *byte = self.regs.rxdr.read().rxdata().bits();
         }
@@ -440,7 +799,7 @@ where
             // Wait until we are allowed to send data
             // (START has been ACKed or last byte when
             // through)
-            busy_wait!(self.regs, txis); // TXDR register is empty
+            busy_wait!(self.regs, txis, self.cfg.timeout); // TXDR register is empty
 
             // Put byte on the wire
             self.regs.txdr.write(|w| unsafe { w.txdata().bits(*byte) });
@@ -462,22 +821,22 @@ where
             // Wait until we are allowed to send data
             // (START has been ACKed or last byte went through)
 
-            busy_wait!(self.regs, txis); // TXDR register is empty
+            busy_wait!(self.regs, txis, self.cfg.timeout); // TXDR register is empty
 
             // Put byte on the wire
             self.regs.txdr.write(|w| unsafe { w.txdata().bits(*byte) });
         }
 
         // Wait until the write finishes before beginning to read.
-        busy_wait!(self.regs, tc); // transfer is complete
+        busy_wait!(self.regs, tc, self.cfg.timeout); // transfer is complete
 
         // reSTART and prepare to receive bytes into `buffer`
 
-        self.set_cr2_read(addr, buffer.len() as u8);
+        self.set_cr2_read(addr, buffer.len() as u8, true);
 
         for byte in buffer {
             // Wait until we have received something
-            busy_wait!(self.regs, rxne);
+            busy_wait!(self.regs, rxne, self.cfg.timeout);
 
             *byte = self.regs.rxdr.read().rxdata().bits();
         }
@@ -525,14 +884,14 @@ where
     }
 
     /// Helper function to prevent repetition between `read`, `write_read`, and `read_dma`.
-    fn set_cr2_read(&mut self, addr: u8, len: u8) {
+    fn set_cr2_read(&mut self, addr: u8, len: u8, autoend: bool) {
         self.regs.cr2.write(|w| {
             unsafe {
                 w.add10().bit(self.cfg.address_bits as u8 != 0);
                 w.sadd().bits(u16(addr << 1));
                 w.rd_wrn().set_bit(); // read
                 w.nbytes().bits(len);
-                w.autoend().set_bit(); // automatic end mode
+                w.autoend().bit(autoend); // automatic end mode
                                        // When the SMBus master wants to receive the PEC followed by a STOP at the end of the
                                        // transfer, automatic end mode can be selected (AUTOEND=1). The PECBYTE bit must be
                                        // set and the slave address must be programmed, before setting the START bit. In this case,
@@ -650,7 +1009,7 @@ where
         // START bit are programmed by software. When all data are transferred using DMA, the
         // DMA must be initialized before setting the START bit. The end of transfer is managed
         // with the NBYTES counter.
-        self.set_cr2_read(addr, len as u8);
+        self.set_cr2_read(addr, len as u8, true);
 
         // • In slave mode with NOSTRETCH=0, when all data are transferred using DMA, the
         // DMA must be initialized before the address match event, or in the ADDR interrupt
@@ -716,3 +1075,113 @@ where
         I2c::write_read(self, addr, bytes, buffer)
     }
 }
+
+#[cfg(feature = "async")]
+/// Interrupt handler for async reads and writes. Masks the interrupts enabled while awaiting,
+/// and wakes the awaiting task. Set this up for both the event and error interrupts using
+/// the `async_interrupt!` macro.
+pub fn on_interrupt_async(regs: &pac::i2c1::RegisterBlock) {
+    regs.cr1.modify(|_, w| {
+        w.txie().clear_bit();
+        w.rxie().clear_bit();
+        w.tcie().clear_bit();
+        w.nackie().clear_bit();
+        w.errie().clear_bit()
+    });
+
+    I2C_WAKERS.wake(regs as *const _ as usize);
+}
+
+#[cfg(feature = "async")]
+impl<R> I2c<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    /// Write an array of words, waiting asynchronously between them. If `last` is `true`, a STOP
+    /// is generated afterwards; otherwise, wait for the transfer to complete, so the next operation
+    /// starts with a repeated START.
+    async fn write_async(&mut self, addr: u8, bytes: &[u8], last: bool) -> Result<(), Error> {
+        while self.regs.cr2.read().start().bit_is_set() {}
+
+        self.set_cr2_write(addr, bytes.len() as u8, last);
+
+        for byte in bytes {
+            wait_async!(self.regs, txis, txie, self.cfg.timeout); // TXDR register is empty
+
+            self.regs.txdr.write(|w| unsafe { w.txdata().bits(*byte) });
+        }
+
+        if !last {
+            wait_async!(self.regs, tc, tcie, self.cfg.timeout); // transfer is complete
+        }
+
+        Ok(())
+    }
+
+    /// Read multiple words to a buffer, waiting asynchronously between them. `last` behaves as
+    /// in `write_async`.
+    async fn read_async(&mut self, addr: u8, bytes: &mut [u8], last: bool) -> Result<(), Error> {
+        while self.regs.cr2.read().start().bit_is_set() {}
+
+        self.set_cr2_read(addr, bytes.len() as u8, last);
+
+        for byte in bytes.iter_mut() {
+            wait_async!(self.regs, rxne, rxie, self.cfg.timeout);
+
+            *byte = self.regs.rxdr.read().rxdata().bits();
+        }
+
+        if !last {
+            wait_async!(self.regs, tc, tcie, self.cfg.timeout);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::i2c::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Bus => ErrorKind::Bus,
+            Self::Arbitration => ErrorKind::ArbitrationLoss,
+            Self::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            Self::Timeout => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R> ErrorType for I2c<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    type Error = Error;
+}
+
+#[cfg(feature = "async")]
+impl<R> embedded_hal_async::i2c::I2c for I2c<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    /// Run a sequence of operations. Each starts with a START or repeated START, and the
+    /// last is followed by a STOP.
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Error> {
+        let num_ops = operations.len();
+
+        for (i, op) in operations.iter_mut().enumerate() {
+            let last = i == num_ops - 1;
+
+            match op {
+                Operation::Read(buf) => self.read_async(address, buf, last).await?,
+                Operation::Write(bytes) => self.write_async(address, bytes, last).await?,
+            }
+        }
+
+        Ok(())
+    }
+}
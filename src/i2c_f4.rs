@@ -7,7 +7,7 @@
 
 use core::ops::Deref;
 
-use cortex_m::interrupt::free;
+use crate::util::free;
 
 #[cfg(feature = "embedded-hal")]
 use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
@@ -40,6 +40,37 @@ pub enum Error {
     ARBITRATION,
 }
 
+#[derive(Clone, Copy, Debug, Default)]
+/// A snapshot of an I2C peripheral's status, from `I2c::dump_state`; eg to log when a bus hangs.
+pub struct I2cState {
+    /// `CR1` register, `PE` field.
+    pub enabled: bool,
+    /// Whether the status registers were read. If not, the fields below are `false`.
+    pub status_read: bool,
+    /// `SR2` register, `BUSY` field: Communication is ongoing on the bus.
+    pub busy: bool,
+    /// `SR1` register, `BERR` field.
+    pub bus_error: bool,
+    /// `ARLO` field.
+    pub arbitration_lost: bool,
+    /// `OVR` field.
+    pub overrun: bool,
+    /// `AF` field: Acknowledge failure.
+    pub nack: bool,
+    /// `TIMEOUT` field. SMBus mode only.
+    pub timeout: bool,
+    /// `PECERR` field. SMBus mode only.
+    pub pec_error: bool,
+    /// `STOPF` field. Slave mode only.
+    pub stop: bool,
+    /// `TXE` field.
+    pub tx_empty: bool,
+    /// `RXNE` field.
+    pub rx_not_empty: bool,
+    /// `BTF` field: Byte transfer finished.
+    pub transfer_complete: bool,
+}
+
 /// Represents an Inter-Integrated Circuit (I2C) peripheral.
 pub struct I2c<R> {
     pub regs: R,
@@ -140,6 +171,52 @@ where
         self.regs.cr1.modify(|_, w| w.pe().set_bit());
     }
 
+    /// Disable the peripheral by clearing `CR1` register, `PE` field, and return the PAC register
+    /// block. Note that unlike the I2C module for other families, this doesn't gate the RCC
+    /// peripheral clock, since we don't keep track of which I2C device this is.
+    pub fn free(self) -> R {
+        self.regs.cr1.modify(|_, w| w.pe().clear_bit());
+        self.regs
+    }
+
+    /// Read the peripheral's state. Reads `CR1`, and if `read_status` is `true`, the `SR1` and
+    /// `SR2` status registers; otherwise the status fields are `false`.
+    ///
+    /// Reading the status registers has side effects: Reading `SR1` is the first half of the
+    /// sequence that clears `ADDR`, `STOPF`, and `BTF`, so the next `DR` access, or `CR1` write,
+    /// clears flags a transfer in progress may still be waiting on. Only read them when no
+    /// transfer is in progress, eg after a timeout. `SR2` is read before `SR1`, since reading `SR1`
+    /// then `SR2` clears `ADDR`.
+    pub fn dump_state(&self, read_status: bool) -> I2cState {
+        let enabled = self.regs.cr1.read().pe().bit_is_set();
+
+        if !read_status {
+            return I2cState {
+                enabled,
+                ..Default::default()
+            };
+        }
+
+        let sr2 = self.regs.sr2.read();
+        let sr1 = self.regs.sr1.read();
+
+        I2cState {
+            enabled,
+            status_read: true,
+            busy: sr2.busy().bit_is_set(),
+            bus_error: sr1.berr().bit_is_set(),
+            arbitration_lost: sr1.arlo().bit_is_set(),
+            overrun: sr1.ovr().bit_is_set(),
+            nack: sr1.af().bit_is_set(),
+            timeout: sr1.timeout().bit_is_set(),
+            pec_error: sr1.pecerr().bit_is_set(),
+            stop: sr1.stopf().bit_is_set(),
+            tx_empty: sr1.tx_e().bit_is_set(),
+            rx_not_empty: sr1.rx_ne().bit_is_set(),
+            transfer_complete: sr1.btf().bit_is_set(),
+        }
+    }
+
     pub fn check_and_clear_error_flags(&self) -> Result<i2c1::sr1::R, Error> {
         // Note that flags should only be cleared once they have been registered. If flags are
         // cleared otherwise, there may be an inherent race condition and flags may be missed.
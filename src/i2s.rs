@@ -0,0 +1,545 @@
+//! Support for Inter-IC Sound (I2S) on F4, using SPI2 and SPI3 in I2S mode, including full duplex
+//! with their I2S2ext and I2S3ext extension blocks. Most audio codecs need full duplex: The main
+//! block is the master, and generates the clocks; the extension block runs as a slave on the same
+//! clocks, in the opposite direction, so audio in and out use the same 4 pins, plus MCK.
+//!
+//! The I2S kernel clock is PLLI2S's R output. Enable it with `enable_plli2s`, which returns its
+//! frequency, then pass this to `I2s::new`. Eg, with a 1Mhz PLL input (`pllm` set to the input
+//! frequency in Mhz), N = 258 and R = 3 give 86Mhz, for 48kHz with MCK within 0.02%.
+//!
+//! Data is transferred as 16-bit words, left channel first. 24 and 32-bit samples are 2 words
+//! each, most significant first. Use `transfer_dma` to stream both directions with DMA1; the
+//! streams used are fixed: 4 (TX) and 3 (RX) for SPI2, and 5 (TX) and 0 (RX) for SPI3. Use circular
+//! mode, and the stream half-transfer and transfer-complete interrupts, for double buffering.
+//!
+//! Example, full duplex with a codec on I2S2:
+//!
+//! ```
+//! let i2s_clk = i2s::enable_plli2s(&clock_cfg, 258, 3);
+//! let mut i2s = I2s::new_full_duplex(dp.SPI2, dp.I2S2EXT, Default::default(), i2s_clk);
+//! unsafe { i2s.transfer_dma(&TX_BUF, &mut RX_BUF, true, &mut dp.DMA1) };
+//! ```
+
+use core::{fmt, ops::Deref};
+
+use crate::{
+    clocks::{Clocks, InputSrc, PllSrc},
+    pac::{self, spi1, RCC},
+    util::{free, RccPeriph},
+};
+
+#[cfg(feature = "f411")]
+use crate::pac::dma1 as dma_p;
+#[cfg(not(feature = "f411"))]
+use crate::pac::dma2 as dma_p;
+
+/// `DMA_SxCR` register, `CHSEL` field values for the extension blocks' requests. The main blocks
+/// use channel 0. See F4 RM, table 42: "DMA1 request mapping".
+const CHSEL_EXT_TX: u8 = 2;
+const CHSEL_EXT_RX: u8 = 3;
+
+/// Flag clear bits for one stream, in `DMA_LIFCR` or `DMA_HIFCR`.
+const DMA_STREAM_FLAGS: u32 = 0b11_1101;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// I2S errors.
+pub enum I2sError {
+    /// Received data was lost, since it wasn't read before the next word arrived.
+    Overrun,
+    /// In slave mode, the master requested data before it was written.
+    Underrun,
+    /// In slave mode, the word select line changed at an unexpected time.
+    Frame,
+}
+
+impl fmt::Display for I2sError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Overrun => f.write_str("received data was lost"),
+            Self::Underrun => f.write_str("the master requested data before it was written"),
+            Self::Frame => f.write_str("unexpected word select transition"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The main block's role. In full duplex, the extension block is a slave in the opposite
+/// direction. Sets `SPI_I2SCFGR` register, `I2SCFG` field.
+pub enum I2sMode {
+    SlaveTx = 0b00,
+    SlaveRx = 0b01,
+    MasterTx = 0b10,
+    MasterRx = 0b11,
+}
+
+impl I2sMode {
+    fn is_tx(&self) -> bool {
+        matches!(self, Self::SlaveTx | Self::MasterTx)
+    }
+
+    fn is_master(&self) -> bool {
+        matches!(self, Self::MasterTx | Self::MasterRx)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The frame format. Sets `SPI_I2SCFGR` register, `I2SSTD` and `PCMSYNC` fields.
+pub enum I2sStandard {
+    /// Philips I2S: Data starts 1 clock after the word select transition.
+    Philips,
+    /// Left-justified: Data starts at the word select transition.
+    MsbJustified,
+    /// Right-justified: Data ends at the word select transition.
+    LsbJustified,
+    /// PCM, with a 1-clock frame sync pulse.
+    PcmShort,
+    /// PCM, with a 13-clock frame sync pulse.
+    PcmLong,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Data and channel lengths. Sets `SPI_I2SCFGR` register, `DATLEN` and `CHLEN` fields.
+pub enum DataFormat {
+    /// 16-bit data, in a 16-bit channel.
+    D16C16,
+    /// 16-bit data, in a 32-bit channel.
+    D16C32,
+    /// 24-bit data, in a 32-bit channel.
+    D24C32,
+    /// 32-bit data, in a 32-bit channel.
+    D32C32,
+}
+
+impl DataFormat {
+    /// `DATLEN` and `CHLEN` field values.
+    fn bits(&self) -> (u8, bool) {
+        match self {
+            Self::D16C16 => (0b00, false),
+            Self::D16C32 => (0b00, true),
+            Self::D24C32 => (0b01, true),
+            Self::D32C32 => (0b10, true),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The serial clock's idle level. Sets `SPI_I2SCFGR` register, `CKPOL` field.
+pub enum ClockPolarity {
+    IdleLow = 0,
+    IdleHigh = 1,
+}
+
+/// Configuration data for I2S.
+#[derive(Clone)]
+pub struct I2sConfig {
+    /// Defaults to `MasterTx`.
+    pub mode: I2sMode,
+    /// Defaults to `Philips`.
+    pub standard: I2sStandard,
+    /// Defaults to 16-bit data in 16-bit channels.
+    pub data_format: DataFormat,
+    /// Defaults to idle low.
+    pub clock_polarity: ClockPolarity,
+    /// Output the master clock, at 256 times the sample rate, on MCK. Only used in master modes.
+    /// Sets `SPI_I2SPR` register, `MCKOE` field. Defaults to `true`.
+    pub master_clock: bool,
+    /// The sample rate, in Hz. Only used in master modes. Defaults to 48kHz.
+    pub sample_rate: u32,
+}
+
+impl Default for I2sConfig {
+    fn default() -> Self {
+        Self {
+            mode: I2sMode::MasterTx,
+            standard: I2sStandard::Philips,
+            data_format: DataFormat::D16C16,
+            clock_polarity: ClockPolarity::IdleLow,
+            master_clock: true,
+            sample_rate: 48_000,
+        }
+    }
+}
+
+/// SPI peripherals with an I2S extension block, for full duplex.
+pub trait I2sPeriph: Deref<Target = spi1::RegisterBlock> + RccPeriph {
+    /// The extension block.
+    type Ext: Deref<Target = spi1::RegisterBlock>;
+    /// The DMA1 stream for transmitting, from either block.
+    const DMA_TX_STREAM: usize;
+    /// The DMA1 stream for receiving, from either block.
+    const DMA_RX_STREAM: usize;
+}
+
+impl I2sPeriph for pac::SPI2 {
+    type Ext = pac::I2S2EXT;
+    const DMA_TX_STREAM: usize = 4;
+    const DMA_RX_STREAM: usize = 3;
+}
+
+impl I2sPeriph for pac::SPI3 {
+    type Ext = pac::I2S3EXT;
+    const DMA_TX_STREAM: usize = 5;
+    const DMA_RX_STREAM: usize = 0;
+}
+
+/// Represents an SPI peripheral in I2S mode, optionally with its extension block for full
+/// duplex.
+pub struct I2s<R: I2sPeriph> {
+    pub regs: R,
+    pub ext: Option<R::Ext>,
+    pub cfg: I2sConfig,
+}
+
+impl<R: I2sPeriph> I2s<R> {
+    /// Initialize an SPI peripheral in I2S mode, for one direction, including enabling and
+    /// resetting its RCC peripheral clock. `i2s_clock` is the I2S kernel clock, in Hz, eg from
+    /// `enable_plli2s`. Doesn't start communication; run `enable`, or a DMA function.
+    pub fn new(regs: R, cfg: I2sConfig, i2s_clock: u32) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            R::en_reset(rcc);
+        });
+
+        configure(&regs, &cfg, cfg.mode);
+
+        if cfg.mode.is_master() {
+            set_prescaler(&regs, &cfg, i2s_clock);
+        }
+
+        Self {
+            regs,
+            ext: None,
+            cfg,
+        }
+    }
+
+    /// Initialize an SPI peripheral in I2S mode, with its extension block for full duplex. The
+    /// extension block uses the same format, and the opposite direction, as a slave.
+    pub fn new_full_duplex(regs: R, ext: R::Ext, cfg: I2sConfig, i2s_clock: u32) -> Self {
+        let mut result = Self::new(regs, cfg, i2s_clock);
+
+        let ext_mode = if result.cfg.mode.is_tx() {
+            I2sMode::SlaveRx
+        } else {
+            I2sMode::SlaveTx
+        };
+        configure(&ext, &result.cfg, ext_mode);
+
+        result.ext = Some(ext);
+        result
+    }
+
+    /// Start communicating. In full duplex, enables the extension block before the main block,
+    /// so both start on the same frame. Sets `SPI_I2SCFGR` register, `I2SE` field.
+    pub fn enable(&mut self) {
+        if let Some(ext) = &self.ext {
+            ext.i2scfgr.modify(|_, w| w.i2se().set_bit());
+        }
+        self.regs.i2scfgr.modify(|_, w| w.i2se().set_bit());
+    }
+
+    /// Stop communicating, after the current word is transferred.
+    pub fn disable(&mut self) {
+        // See F4 RM, section 28.4.7: "I2S slave mode", and 28.4.6: "I2S master mode": Wait for
+        // the last word to leave the transmit buffer.
+        if self.cfg.mode.is_tx() {
+            while self.regs.sr.read().txe().bit_is_clear() {}
+            while self.regs.sr.read().bsy().bit_is_set() {}
+        }
+
+        self.regs.i2scfgr.modify(|_, w| w.i2se().clear_bit());
+        if let Some(ext) = &self.ext {
+            ext.i2scfgr.modify(|_, w| w.i2se().clear_bit());
+        }
+    }
+
+    /// The block that transmits: the main block in TX modes, and the extension otherwise.
+    fn tx_regs(&self) -> &spi1::RegisterBlock {
+        match (&self.ext, self.cfg.mode.is_tx()) {
+            (Some(ext), false) => ext,
+            _ => &self.regs,
+        }
+    }
+
+    /// The block that receives: the main block in RX modes, and the extension otherwise.
+    fn rx_regs(&self) -> &spi1::RegisterBlock {
+        match (&self.ext, self.cfg.mode.is_tx()) {
+            (Some(ext), true) => ext,
+            _ => &self.regs,
+        }
+    }
+
+    /// Write a word, if the transmit buffer is empty. Sets `SPI_DR` register.
+    pub fn write(&mut self, word: u16) -> nb::Result<(), I2sError> {
+        let regs = self.tx_regs();
+        let sr = regs.sr.read();
+
+        if sr.udr().bit_is_set() {
+            // Cleared by reading `SR`.
+            return Err(nb::Error::Other(I2sError::Underrun));
+        } else if sr.fre().bit_is_set() {
+            return Err(nb::Error::Other(I2sError::Frame));
+        } else if sr.txe().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        regs.dr.write(|w| unsafe { w.dr().bits(word) });
+        Ok(())
+    }
+
+    /// Read a word, if one has been received. Reads `SPI_DR` register.
+    pub fn read(&mut self) -> nb::Result<u16, I2sError> {
+        let regs = self.rx_regs();
+        let sr = regs.sr.read();
+
+        if sr.ovr().bit_is_set() {
+            // Cleared by reading `DR`, then `SR`.
+            regs.dr.read();
+            regs.sr.read();
+            return Err(nb::Error::Other(I2sError::Overrun));
+        } else if sr.fre().bit_is_set() {
+            return Err(nb::Error::Other(I2sError::Frame));
+        } else if sr.rxne().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        Ok(regs.dr.read().dr().bits())
+    }
+
+    /// Start transmitting from `buf` with DMA, and enable I2S. With `circular`, repeats until
+    /// `stop_dma`. Sets `SPI_CR2` register, `TXDMAEN` field.
+    pub unsafe fn write_dma(&mut self, buf: &[u16], circular: bool, dma: &mut pac::DMA1) {
+        self.start_tx_dma(buf, circular, dma);
+        self.enable();
+    }
+
+    /// Start receiving to `buf` with DMA, and enable I2S. With `circular`, repeats until
+    /// `stop_dma`. Sets `SPI_CR2` register, `RXDMAEN` field.
+    pub unsafe fn read_dma(&mut self, buf: &mut [u16], circular: bool, dma: &mut pac::DMA1) {
+        self.start_rx_dma(buf, circular, dma);
+        self.enable();
+    }
+
+    /// In full duplex, transmit from `tx_buf` and receive to `rx_buf` with DMA, and enable I2S.
+    /// Both directions start on the same frame. With `circular`, repeats until `stop_dma`. For
+    /// double buffering, use buffers of the same length, and process each half in the RX stream's
+    /// half-transfer and transfer-complete interrupts.
+    pub unsafe fn transfer_dma(
+        &mut self,
+        tx_buf: &[u16],
+        rx_buf: &mut [u16],
+        circular: bool,
+        dma: &mut pac::DMA1,
+    ) {
+        assert!(
+            self.ext.is_some(),
+            "Full duplex DMA requires the extension block; use `new_full_duplex`."
+        );
+
+        // See F4 RM, section 28.4.10: "DMA features": Enable the DMA streams and requests before
+        // I2S, so the first words are ready.
+        self.start_rx_dma(rx_buf, circular, dma);
+        self.start_tx_dma(tx_buf, circular, dma);
+        self.enable();
+    }
+
+    /// Stop DMA transfers, and I2S. Disables both streams, and clears `SPI_CR2` register,
+    /// `TXDMAEN` and `RXDMAEN` fields.
+    pub fn stop_dma(&mut self, dma: &mut pac::DMA1) {
+        for stream in [R::DMA_TX_STREAM, R::DMA_RX_STREAM] {
+            let cr = &dma.st[stream].cr;
+            cr.modify(|_, w| w.en().clear_bit());
+            while cr.read().en().bit_is_set() {}
+        }
+
+        self.disable();
+
+        self.regs.cr2.modify(|_, w| {
+            w.txdmaen().clear_bit();
+            w.rxdmaen().clear_bit()
+        });
+        if let Some(ext) = &self.ext {
+            ext.cr2.modify(|_, w| {
+                w.txdmaen().clear_bit();
+                w.rxdmaen().clear_bit()
+            });
+        }
+    }
+
+    unsafe fn start_tx_dma(&mut self, buf: &[u16], circular: bool, dma: &mut pac::DMA1) {
+        let chsel = if self.cfg.mode.is_tx() {
+            0
+        } else {
+            CHSEL_EXT_TX
+        };
+        let regs = self.tx_regs();
+
+        cfg_stream(
+            dma,
+            R::DMA_TX_STREAM,
+            chsel,
+            &regs.dr as *const _ as u32,
+            buf.as_ptr() as u32,
+            buf.len(),
+            true,
+            circular,
+        );
+        regs.cr2.modify(|_, w| w.txdmaen().set_bit());
+    }
+
+    unsafe fn start_rx_dma(&mut self, buf: &mut [u16], circular: bool, dma: &mut pac::DMA1) {
+        let chsel = if self.cfg.mode.is_tx() {
+            CHSEL_EXT_RX
+        } else {
+            0
+        };
+        let regs = self.rx_regs();
+
+        cfg_stream(
+            dma,
+            R::DMA_RX_STREAM,
+            chsel,
+            &regs.dr as *const _ as u32,
+            buf.as_mut_ptr() as u32,
+            buf.len(),
+            false,
+            circular,
+        );
+        regs.cr2.modify(|_, w| w.rxdmaen().set_bit());
+    }
+}
+
+/// Set the I2S format of a main or extension block. Sets `SPI_I2SCFGR` register.
+fn configure(regs: &spi1::RegisterBlock, cfg: &I2sConfig, mode: I2sMode) {
+    let (std, pcm_long) = match cfg.standard {
+        I2sStandard::Philips => (0b00, false),
+        I2sStandard::MsbJustified => (0b01, false),
+        I2sStandard::LsbJustified => (0b10, false),
+        I2sStandard::PcmShort => (0b11, false),
+        I2sStandard::PcmLong => (0b11, true),
+    };
+    let (datlen, chlen) = cfg.data_format.bits();
+
+    regs.i2scfgr.write(|w| unsafe {
+        w.i2smod().set_bit();
+        w.i2scfg().bits(mode as u8);
+        w.i2sstd().bits(std);
+        w.pcmsync().bit(pcm_long);
+        w.ckpol().bit(cfg.clock_polarity as u8 != 0);
+        w.datlen().bits(datlen);
+        w.chlen().bit(chlen)
+    });
+}
+
+/// Set the master's clock divider for the sample rate. See F4 RM, section 28.4.4: "Clock
+/// generator". Sets `SPI_I2SPR` register.
+fn set_prescaler(regs: &spi1::RegisterBlock, cfg: &I2sConfig, i2s_clock: u32) {
+    // Kernel clock cycles per sample, per unit of the divider.
+    let cycles = if cfg.master_clock {
+        256
+    } else if cfg.data_format == DataFormat::D16C16 {
+        32
+    } else {
+        64
+    };
+
+    let div = (i2s_clock + cfg.sample_rate * cycles / 2) / (cfg.sample_rate * cycles);
+    assert!(
+        (4..=511).contains(&div),
+        "The I2S clock can't be divided to this sample rate."
+    );
+
+    regs.i2spr.write(|w| unsafe {
+        w.i2sdiv().bits((div / 2) as u8);
+        w.odd().bit(div % 2 != 0);
+        w.mckoe().bit(cfg.master_clock)
+    });
+}
+
+/// Configure and enable a DMA1 stream, for 16-bit transfers between a data register and memory.
+/// See F4 RM, section 10.3.17: "Stream configuration procedure".
+unsafe fn cfg_stream(
+    dma: &dma_p::RegisterBlock,
+    stream: usize,
+    chsel: u8,
+    periph_addr: u32,
+    mem_addr: u32,
+    len: usize,
+    to_periph: bool,
+    circular: bool,
+) {
+    assert!(len <= 65_535, "DMA transfers are limited to 65,535 words.");
+
+    free(|_| {
+        let rcc = &(*RCC::ptr());
+        rcc.ahb1enr.modify(|_, w| w.dma1en().set_bit());
+    });
+
+    let st = &dma.st[stream];
+    st.cr.modify(|_, w| w.en().clear_bit());
+    while st.cr.read().en().bit_is_set() {}
+
+    // Clear the stream's flags, since the stream won't enable with any set. Streams 0 - 3 use
+    // `LIFCR`, and 4 - 7 `HIFCR`, at the same offsets.
+    let offset = [0, 6, 16, 22][stream % 4];
+    if stream < 4 {
+        dma.lifcr.write(|w| w.bits(DMA_STREAM_FLAGS << offset));
+    } else {
+        dma.hifcr.write(|w| w.bits(DMA_STREAM_FLAGS << offset));
+    }
+
+    st.par.write(|w| w.bits(periph_addr));
+    st.m0ar.write(|w| w.bits(mem_addr));
+    st.ndtr.write(|w| w.bits(len as u32));
+
+    st.cr.write(|w| {
+        w.chsel().bits(chsel);
+        // High priority: Audio underruns are audible.
+        w.pl().bits(0b10);
+        w.msize().bits(0b01);
+        w.psize().bits(0b01);
+        w.minc().set_bit();
+        w.circ().bit(circular);
+        w.dir().bits(to_periph as u8);
+        w.tcie().set_bit();
+        w.htie().bit(circular);
+        w.en().set_bit()
+    });
+}
+
+/// Enable PLLI2S, and return the I2S kernel clock it generates, in Hz. It shares the main PLL's
+/// input, divided by `pllm`, so the PLL must be the system clock's input. `n` multiplies this,
+/// for a VCO of 100 - 432Mhz, and `r` divides the VCO, for an I2S clock up to 192Mhz. Sets
+/// `RCC_PLLI2SCFGR` register.
+pub fn enable_plli2s(clocks: &Clocks, n: u16, r: u8) -> u32 {
+    assert!((50..=432).contains(&n), "PLLI2S N must be 50 - 432.");
+    assert!((2..=7).contains(&r), "PLLI2S R must be 2 - 7.");
+
+    let input = match clocks.input_src {
+        InputSrc::Pll(PllSrc::Hsi) => 16_000_000,
+        InputSrc::Pll(PllSrc::Hse(freq)) => freq,
+        _ => panic!("PLLI2S requires the main PLL to be the system clock input."),
+    } / clocks.pllm as u32;
+
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        rcc.cr.modify(|_, w| w.plli2son().clear_bit());
+        while rcc.cr.read().plli2srdy().bit_is_set() {}
+
+        rcc.plli2scfgr.modify(|_, w| unsafe {
+            // These parts have a separate input divider; match the main PLL's.
+            #[cfg(any(feature = "f411", feature = "f412"))]
+            w.plli2sm().bits(clocks.pllm);
+            w.plli2sn().bits(n);
+            w.plli2sr().bits(r)
+        });
+
+        rcc.cr.modify(|_, w| w.plli2son().set_bit());
+        while rcc.cr.read().plli2srdy().bit_is_clear() {}
+    });
+
+    input * n as u32 / r as u32
+}
@@ -0,0 +1,153 @@
+//! In-application programming (IAP): Update firmware from within the running application.
+//! Firmware is received in chunks of any size (eg over UART or USB), buffered a page at a time,
+//! and written to a staging region of flash that's separate from the running image: Either a
+//! range of pages reserved for this, or the inactive bank on dual-bank variants. Each page is
+//! read back and compared after writing.
+//!
+//! Example:
+//!
+//! `let mut iap = Iap::new(&mut flash, STAGING_PAGE, STAGING_NUM_PAGES);`
+//! `iap.write(&chunk)?;` // For each chunk received.
+//! `let len = iap.finalize()?;`
+//!
+//! After finalizing, verify the image (eg with `Crc::verify_image` on the staging region, where
+//! available), then either reset with `reset`, so a bootloader can copy the staged image into
+//! place, or on L4 dual-bank variants, boot from the bank just written with `swap_and_reset`.
+//!
+//! This uses `flash::PAGE_SIZE` pages; it's not available on H7, which uses 128kB sectors, on F4,
+//! where `Flash::erase_page` erases a whole 16 - 128kB sector, or on L5.
+
+use cortex_m::peripheral::SCB;
+
+use crate::flash::{self, Flash};
+
+#[cfg(feature = "l4")]
+use crate::flash::Bank;
+
+/// The flash page size, in bytes. Data is buffered, erased, and written in units of this size.
+pub const PAGE_SIZE: usize = flash::PAGE_SIZE;
+
+#[derive(Copy, Clone, Debug)]
+/// Errors that can occur during a firmware update.
+pub enum IapError {
+    /// An error from erasing or writing flash.
+    Flash(flash::Error),
+    /// The image doesn't fit in the staging region.
+    Overflow,
+    /// A page didn't read back as written.
+    Verify { page: usize },
+}
+
+impl From<flash::Error> for IapError {
+    fn from(e: flash::Error) -> Self {
+        Self::Flash(e)
+    }
+}
+
+/// Writes a firmware image to a staging region of flash, in chunks.
+pub struct Iap<'a> {
+    flash: &'a mut Flash,
+    /// The first page of the staging region.
+    start_page: usize,
+    /// The number of pages in the staging region.
+    num_pages: usize,
+    /// The page currently being filled.
+    buf: [u8; PAGE_SIZE],
+    buf_len: usize,
+    /// Number of pages written to flash so far.
+    pages_written: usize,
+}
+
+impl<'a> Iap<'a> {
+    /// Start a firmware update, to a staging region of `num_pages` pages starting at `start_page`.
+    /// On dual-bank variants, to write to the inactive bank, pass its first page; eg 256 on L4
+    /// variants with 1MB of flash, when running from bank 1.
+    pub fn new(flash: &'a mut Flash, start_page: usize, num_pages: usize) -> Self {
+        Self {
+            flash,
+            start_page,
+            num_pages,
+            buf: [0xff; PAGE_SIZE],
+            buf_len: 0,
+            pages_written: 0,
+        }
+    }
+
+    /// Accept a chunk of the image. Writes to flash each time a page's worth of data is buffered.
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), IapError> {
+        while !data.is_empty() {
+            let len = data.len().min(PAGE_SIZE - self.buf_len);
+            self.buf[self.buf_len..self.buf_len + len].copy_from_slice(&data[..len]);
+            self.buf_len += len;
+            data = &data[len..];
+
+            if self.buf_len == PAGE_SIZE {
+                self.commit_page()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Erase the next page of the staging region, write the buffer to it, and verify it.
+    fn commit_page(&mut self) -> Result<(), IapError> {
+        if self.pages_written >= self.num_pages {
+            return Err(IapError::Overflow);
+        }
+
+        let page = self.start_page + self.pages_written;
+
+        // Pad the rest of the page as if erased.
+        self.buf[self.buf_len..].fill(0xff);
+
+        let mut dwords = [0_u64; PAGE_SIZE / 8];
+        for (dword, bytes) in dwords.iter_mut().zip(self.buf.chunks_exact(8)) {
+            *dword = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+
+        self.flash.erase_page(page)?;
+        self.flash.write_page(page, &dwords)?;
+
+        if self.flash.slice(page, 0, PAGE_SIZE) != self.buf {
+            return Err(IapError::Verify { page });
+        }
+
+        self.pages_written += 1;
+        self.buf_len = 0;
+        Ok(())
+    }
+
+    /// The number of bytes accepted so far.
+    pub fn bytes_written(&self) -> usize {
+        self.pages_written * PAGE_SIZE + self.buf_len
+    }
+
+    /// Write any partially-filled page to flash. Returns the image length, in bytes. Call
+    /// this after the last chunk.
+    pub fn finalize(&mut self) -> Result<usize, IapError> {
+        let len = self.bytes_written();
+        if self.buf_len > 0 {
+            self.commit_page()?;
+        }
+        Ok(len)
+    }
+
+    /// Reset the MCU, eg so a bootloader can install a finalized image.
+    pub fn reset(self) -> ! {
+        SCB::sys_reset()
+    }
+
+    #[cfg(feature = "l4")]
+    /// Boot from the bank the image was written to, on dual-bank variants. This toggles the
+    /// `BFB2` option bit, which resets the MCU. Only returns if there's an error.
+    pub fn swap_and_reset(self) -> IapError {
+        let bank = match self.flash.boot_bank() {
+            Bank::B1 => Bank::B2,
+            Bank::B2 => Bank::B1,
+        };
+
+        match self.flash.set_boot_bank(bank) {
+            Ok(()) => IapError::Flash(flash::Error::Failure),
+            Err(e) => e.into(),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Bind interrupt handlers to peripherals, using the `bind_interrupts!` macro. This defines the
+//! interrupt handlers, takes care of the housekeeping the HAL needs in them (eg clearing EXTI
+//! and DMA flags), and creates a token that proves the handlers exist. Pass this token to the
+//! `bind_interrupt` methods of `Usart`, `Dma`, and `Pin`; these enable the peripheral's
+//! interrupt, and unmask the correct NVIC line.
+//!
+//! Example:
+//!
+//! `bind_interrupts!(struct Irqs {
+//!     USART1 => usart(USART1) => usart1_isr,
+//!     DMA1_CH1 => dma(DMA1, 1) => dma1_ch1_isr,
+//!     EXTI9_5 => exti(5, 6) => exti9_5_isr,
+//! });`
+//!
+//! `usart.bind_interrupt(UsartInterrupt::ReadNotEmpty, &Irqs);`
+//! `dma.bind_interrupt::<1>(DmaInterrupt::TransferComplete, &Irqs);`
+//! `pin.bind_interrupt::<5>(Edge::Rising, &Irqs);`
+
+use core::marker::PhantomData;
+
+use cortex_m::{peripheral::NVIC, Peripherals};
+
+use crate::pac;
+
+/// Implemented by the token `bind_interrupts!` creates, for each peripheral (or DMA channel,
+/// or EXTI line) it has a handler for. `P` is the PAC peripheral, `DmaChan`, or `Exti`.
+///
+/// # Safety
+/// Only implement this using `bind_interrupts!`, which defines the handler.
+pub unsafe trait Binding<P> {
+    /// The interrupt line the handler is bound to.
+    const INTERRUPT: pac::Interrupt;
+}
+
+/// Identifies a DMA channel in a `Binding`. `D` is the PAC DMA peripheral; `CH` is the channel
+/// number.
+pub struct DmaChan<D, const CH: u8>(PhantomData<D>);
+
+/// Identifies an EXTI line (ie GPIO pin number) in a `Binding`.
+pub struct Exti<const LINE: u8>;
+
+/// Unmask the NVIC line of a bound interrupt.
+pub fn unmask<P, B: Binding<P>>(_irqs: &B) {
+    unsafe { NVIC::unmask(B::INTERRUPT) };
+}
+
+/// Implemented for PAC peripherals, to identify the NVIC interrupt line they use, since its name
+/// differs between families. For peripherals with separate event and error lines (eg I2C),
+/// this is the event line. Access it from a HAL struct with its `interrupt` method.
+pub trait InterruptPeriph {
+    const INTERRUPT: pac::Interrupt;
+}
+
+macro_rules! impl_interrupt_periph {
+    ($periph:ident, $irq:ident) => {
+        impl InterruptPeriph for pac::$periph {
+            const INTERRUPT: pac::Interrupt = pac::Interrupt::$irq;
+        }
+    };
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "f3", not(feature = "f373")))] {
+        impl_interrupt_periph!(USART1, USART1_EXTI25);
+        impl_interrupt_periph!(USART2, USART2_EXTI26);
+        impl_interrupt_periph!(I2C1, I2C1_EV_EXTI23);
+    } else if #[cfg(feature = "g0")] {
+        impl_interrupt_periph!(USART1, USART1);
+        impl_interrupt_periph!(USART2, USART2);
+        impl_interrupt_periph!(I2C1, I2C1);
+    } else {
+        impl_interrupt_periph!(USART1, USART1);
+        #[cfg(not(any(feature = "wb", feature = "wl")))]
+        impl_interrupt_periph!(USART2, USART2);
+        impl_interrupt_periph!(I2C1, I2C1_EV);
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "f301")] {
+        impl_interrupt_periph!(SPI1, SPI1_IRQ);
+    } else if #[cfg(not(any(feature = "g0b0", feature = "g0b1", feature = "g0c1")))] {
+        impl_interrupt_periph!(SPI1, SPI1);
+    }
+}
+
+/// Set the priority of an interrupt line. Lower values are higher priority. `priority` is in
+/// the range the MCU supports, eg 0 - 15 on most families; it's shifted into the implemented
+/// (upper) bits of the `NVIC_IPR` register here.
+///
+/// # Safety
+/// Changing priorities can break priority-based critical sections, eg those used by RTIC.
+pub unsafe fn set_priority(interrupt: pac::Interrupt, priority: u8) {
+    assert!(
+        priority < 1 << pac::NVIC_PRIO_BITS,
+        "Priority is higher than the MCU supports."
+    );
+
+    let mut nvic = Peripherals::steal().NVIC;
+    nvic.set_priority(interrupt, priority << (8 - pac::NVIC_PRIO_BITS));
+}
+
+/// Read the priority of an interrupt line, in the same range as `set_priority` uses.
+pub fn priority(interrupt: pac::Interrupt) -> u8 {
+    NVIC::get_priority(interrupt) >> (8 - pac::NVIC_PRIO_BITS)
+}
+
+/// Enable an interrupt line in the NVIC.
+///
+/// # Safety
+/// This can break mask-based critical sections. A handler must be defined for the line.
+pub unsafe fn enable(interrupt: pac::Interrupt) {
+    NVIC::unmask(interrupt);
+}
+
+/// Disable an interrupt line in the NVIC.
+pub fn disable(interrupt: pac::Interrupt) {
+    NVIC::mask(interrupt);
+}
+
+/// Returns `true` if an interrupt line is enabled in the NVIC.
+pub fn is_enabled(interrupt: pac::Interrupt) -> bool {
+    NVIC::is_enabled(interrupt)
+}
+
+/// Define interrupt handlers, and a token type that proves they exist. Each line takes the form
+/// `INTERRUPT => kind(args) => handler`, where `kind(args)` is one of:
+///
+/// - `usart(USART1)`: Calls the handler.
+/// - `dma(DMA1, 1)`: Calls the handler, then clears all of the channel's interrupt flags.
+/// - `exti(5, 6)`: Clears the pending flags of the listed EXTI lines, then calls the handler.
+///
+/// Handlers are functions that take no arguments.
+#[macro_export]
+macro_rules! bind_interrupts {
+    ($vis:vis struct $name:ident { $($irq:ident => $kind:ident($($args:tt),+) => $handler:path),* $(,)? }) => {
+        #[derive(Clone, Copy)]
+        $vis struct $name;
+
+        $(
+            $crate::__bind_interrupt!($kind, $name, $irq, $handler, $($args),+);
+        )*
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __bind_interrupt {
+    (usart, $name:ident, $irq:ident, $handler:path, $periph:ident) => {
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        unsafe extern "C" fn $irq() {
+            $handler();
+        }
+
+        unsafe impl $crate::interrupt::Binding<$crate::pac::$periph> for $name {
+            const INTERRUPT: $crate::pac::Interrupt = $crate::pac::Interrupt::$irq;
+        }
+    };
+    (dma, $name:ident, $irq:ident, $handler:path, $periph:ident, $ch:literal) => {
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        unsafe extern "C" fn $irq() {
+            $handler();
+
+            $crate::dma::clear_all_interrupts(
+                &*$crate::pac::$periph::ptr(),
+                $crate::dma::DmaChannel::from_num($ch),
+            );
+        }
+
+        unsafe impl $crate::interrupt::Binding<$crate::interrupt::DmaChan<$crate::pac::$periph, $ch>>
+            for $name
+        {
+            const INTERRUPT: $crate::pac::Interrupt = $crate::pac::Interrupt::$irq;
+        }
+    };
+    (exti, $name:ident, $irq:ident, $handler:path, $($line:literal),+) => {
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        unsafe extern "C" fn $irq() {
+            $(
+                $crate::gpio::clear_exti_interrupt($line);
+            )+
+
+            $handler();
+        }
+
+        $(
+            unsafe impl $crate::interrupt::Binding<$crate::interrupt::Exti<$line>> for $name {
+                const INTERRUPT: $crate::pac::Interrupt = $crate::pac::Interrupt::$irq;
+            }
+        )+
+    };
+}
@@ -3,7 +3,7 @@
 
 use crate::pac::{self, IPCC, RCC};
 
-use cortex_m::interrupt::free;
+use crate::util::free;
 
 // todo: C1_1 and C2_1 etc for channels instead of separate core enum?
 // todo: Consider macros to reduce DRY here, re Core and Channel matching.
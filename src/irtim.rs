@@ -0,0 +1,117 @@
+//! Support for the infrared interface (IRTIM), for consumer IR transmitters, eg to emulate a TV
+//! remote. The IR_OUT pin is driven by a combination of two timers' channel 1 outputs: TIM17
+//! generates the carrier (eg 38kHz), and TIM16 the envelope; the carrier is output while the
+//! envelope is active. Configure the IR_OUT pin in its alternate function mode. See the L4 RM
+//! chapter: Infrared interface (IRTIM).
+//!
+//! Example, sending an NEC protocol leader (9ms mark, 4.5ms space) followed by a bit:
+//!
+//! `let carrier = Timer::new_tim17(dp.TIM17, 38_000., Default::default(), &clock_cfg);`
+//! `let envelope = Timer::new_tim16(dp.TIM16, 1_000., Default::default(), &clock_cfg);`
+//! `let mut ir = IrTransmitter::new(carrier, envelope, &clock_cfg);`
+//! `ir.transmit(&[9_000, 4_500, 562, 562]);`
+
+use crate::{
+    clocks::Clocks,
+    pac::{TIM16, TIM17},
+    timer::{OutputCompare, TimChannel, Timer, TimerInterrupt},
+};
+
+#[cfg(all(feature = "g0", not(any(feature = "g071", feature = "g081"))))]
+use crate::pac::SYSCFG;
+#[cfg(any(feature = "g071", feature = "g081"))]
+use crate::pac::SYSCFG_VREFBUF as SYSCFG;
+
+/// An infrared transmitter, using TIM17 for the carrier, and TIM16 for the envelope.
+pub struct IrTransmitter {
+    pub carrier: Timer<TIM17>,
+    pub envelope: Timer<TIM16>,
+    /// The envelope timer's prescaler value for a 1Mhz count, for timing marks and spaces.
+    envelope_psc: u16,
+}
+
+impl IrTransmitter {
+    /// Set up the carrier timer's channel 1 to output a square wave at the frequency it was
+    /// initialized with, and the envelope timer's channel 1 for software-timed marks and spaces.
+    /// Starts with the output idle (a space).
+    pub fn new(mut carrier: Timer<TIM17>, mut envelope: Timer<TIM16>, clocks: &Clocks) -> Self {
+        carrier.enable_pwm_output(TimChannel::C1, OutputCompare::Pwm1, 0.5);
+        // TIM16 and TIM17 have complementary outputs, so their outputs are only enabled when
+        // `BDTR` register, `MOE` field is set.
+        carrier.regs.bdtr.modify(|_, w| w.moe().set_bit());
+        carrier.enable();
+
+        envelope.set_output_compare(TimChannel::C1, OutputCompare::ForceInactive);
+        envelope.enable_capture_compare(TimChannel::C1);
+        envelope.regs.bdtr.modify(|_, w| w.moe().set_bit());
+
+        // Both timers are on APB2; see `make_timer!` in the `timer` module.
+        let envelope_psc = (clocks.apb2_timer() / 1_000_000).max(1) as u16 - 1;
+
+        #[cfg(feature = "g0")]
+        set_envelope_source_tim16();
+
+        Self {
+            carrier,
+            envelope,
+            envelope_psc,
+        }
+    }
+
+    /// Start outputting the carrier: Forces the envelope active.
+    pub fn mark(&mut self) {
+        self.envelope
+            .set_output_compare(TimChannel::C1, OutputCompare::ForceActive);
+    }
+
+    /// Stop outputting the carrier: Forces the envelope inactive.
+    pub fn space(&mut self) {
+        self.envelope
+            .set_output_compare(TimChannel::C1, OutputCompare::ForceInactive);
+    }
+
+    /// Send a sequence of alternating marks and spaces, starting with a mark. Each duration is in
+    /// microseconds, and must be at least 1. Timed with the envelope timer; blocks until
+    /// complete. Leaves the output idle.
+    pub fn transmit(&mut self, durations: &[u16]) {
+        self.envelope.disable();
+        self.envelope.set_prescaler(self.envelope_psc);
+
+        for (i, duration) in durations.iter().enumerate() {
+            assert!(*duration > 0);
+
+            if i % 2 == 0 {
+                self.mark();
+            } else {
+                self.space();
+            }
+
+            self.envelope.set_auto_reload(*duration as u32 - 1);
+            // Load the prescaler and auto-reload values, and reset the counter.
+            self.envelope.reinitialize();
+            self.envelope.enable();
+
+            while self.envelope.regs.sr.read().uif().bit_is_clear() {}
+
+            self.envelope.disable();
+            self.envelope.clear_interrupt(TimerInterrupt::Update);
+        }
+
+        self.space();
+    }
+
+    #[cfg(feature = "g0")]
+    /// Invert the IR_OUT signal. Sets `SYSCFG_CFGR1` register, `IR_POL` field.
+    pub fn set_inverted(&mut self, inverted: bool) {
+        let syscfg = unsafe { &(*SYSCFG::ptr()) };
+        syscfg.cfgr1.modify(|_, w| w.ir_pol().bit(inverted));
+    }
+}
+
+#[cfg(feature = "g0")]
+/// On G0, the envelope can come from TIM16, USART1, or USART4; select TIM16. Sets
+/// `SYSCFG_CFGR1` register, `IR_MOD` field. (The SYSCFG clock is enabled by `Clocks::setup`.)
+fn set_envelope_source_tim16() {
+    let syscfg = unsafe { &(*SYSCFG::ptr()) };
+    syscfg.cfgr1.modify(|_, w| unsafe { w.ir_mod().bits(0b00) });
+}
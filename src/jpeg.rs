@@ -0,0 +1,652 @@
+//! Support for the hardware JPEG codec (H7). It decodes baseline JPEG streams, parsing their
+//! headers, and encodes images with a generated header, using the standard (ITU T.81 Annex K)
+//! Huffman and quantization tables, scaled by a quality setting.
+//!
+//! Raw image data, in both directions, is in MCU (minimum coded unit) order, not raster order:
+//! Each MCU is a sequence of 8x8 blocks of 8-bit samples; for YCbCr, the luma (Y) blocks in the
+//! MCU, then one Cb block, then one Cr block. Convert to and from RGB in software, or with DMA2D.
+//!
+//! Example, decoding an image:
+//!
+//! `let mut jpeg = Jpeg::new(dp.JPEG);`
+//! `let (info, len) = jpeg.decode(&jpeg_data, &mut mcu_buf)?;`
+//!
+//! For large images, or non-blocking use, call `start_decode` or `start_encode`, and move data
+//! with `write_input` and `read_output` from the `JPEG` interrupt handler, or with an MDMA channel;
+//! see `enable_dma`. See H743 RM, chapter 33: JPEG codec (JPEG).
+
+use core::ptr;
+
+use crate::{
+    pac::{JPEG, RCC},
+    rcc_en_reset,
+    util::free,
+};
+
+// Offsets of the codec's internal memories, from the peripheral's base address. These aren't
+// included in the PAC. See H743 RM, section 33.5: JPEG codec registers.
+/// Quantization tables 0 and 1, in zig-zag order, 64 bytes each.
+const QMEM_OFFSET: usize = 0x50;
+/// The Huffman tables (BITS and HUFFVAL) written to the generated header's DHT marker.
+const DHTMEM_OFFSET: usize = 0x360;
+/// Huffman encoder code tables, derived from the DHT tables.
+const HUFFENC_AC0_OFFSET: usize = 0x500;
+const HUFFENC_AC1_OFFSET: usize = 0x660;
+const HUFFENC_DC0_OFFSET: usize = 0x7c0;
+const HUFFENC_DC1_OFFSET: usize = 0x7e0;
+
+/// The natural-order index of each coefficient, in zig-zag order.
+const ZIGZAG: [u8; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Annex K, table K.1: Luminance quantization table, in natural order.
+const QUANT_LUMA: [u8; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// Annex K, table K.2: Chrominance quantization table, in natural order.
+const QUANT_CHROMA: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+// Annex K, section K.3: Huffman tables. `BITS` is the number of codes of each length, from 1 to
+// 16 bits, and `VALS` the symbols, in order of increasing code length.
+const DC_LUMA_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const DC_CHROMA_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALS: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const AC_LUMA_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const AC_LUMA_VALS: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+const AC_CHROMA_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const AC_CHROMA_VALS: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// JPEG errors.
+pub enum Error {
+    /// The output buffer filled before the conversion completed.
+    OutputFull,
+    /// The encoder configuration isn't supported, eg a zero image size.
+    InvalidConfig,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+/// The image's color space. Sets `JPEG_CONFR1` register, `COLORSPACE` field, when encoding.
+pub enum ColorSpace {
+    Grayscale = 0b00,
+    YCbCr = 0b01,
+    Rgb = 0b10,
+    Cmyk = 0b11,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Chroma subsampling, for YCbCr images. This sets the MCU size: 8x8 pixels for 4:4:4 (and
+/// grayscale), 16x8 for 4:2:2, and 16x16 for 4:2:0.
+pub enum ChromaSubsampling {
+    S444,
+    S422,
+    S420,
+}
+
+impl ChromaSubsampling {
+    /// MCU width and height, in pixels.
+    fn mcu_size(&self) -> (u32, u32) {
+        match self {
+            Self::S444 => (8, 8),
+            Self::S422 => (16, 8),
+            Self::S420 => (16, 16),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Image information, parsed from a JPEG header when decoding.
+pub struct ImageInfo {
+    pub width: u16,
+    pub height: u16,
+    pub color_space: ColorSpace,
+    pub chroma_subsampling: ChromaSubsampling,
+    /// The number of MCUs the decoder outputs.
+    pub num_mcus: u32,
+}
+
+#[derive(Clone, Copy)]
+/// Configuration for encoding an image.
+pub struct EncodeConfig {
+    pub width: u16,
+    pub height: u16,
+    /// `Grayscale` or `YCbCr`. Defaults to `YCbCr`.
+    pub color_space: ColorSpace,
+    /// Ignored for grayscale images. Defaults to 4:2:0.
+    pub chroma_subsampling: ChromaSubsampling,
+    /// Image quality, from 1 to 100, used to scale the quantization tables. Defaults to 90.
+    pub quality: u8,
+}
+
+impl Default for EncodeConfig {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            color_space: ColorSpace::YCbCr,
+            chroma_subsampling: ChromaSubsampling::S420,
+            quality: 90,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+/// JPEG interrupts. Sets `JPEG_CR` register, and reads `JPEG_SR` register.
+pub enum JpegInterrupt {
+    /// The input FIFO is at least half empty.
+    InputThreshold,
+    InputNotFull,
+    /// The output FIFO is at least half full.
+    OutputThreshold,
+    OutputNotEmpty,
+    EndOfConversion,
+    HeaderParsed,
+}
+
+/// Write a word to the codec's internal memory, at `offset` bytes from its base address.
+fn write_mem(offset: usize, word_i: usize, val: u32) {
+    unsafe {
+        let addr = (JPEG::ptr() as *mut u8).add(offset) as *mut u32;
+        ptr::write_volatile(addr.add(word_i), val);
+    }
+}
+
+/// Load a quantization table into `QMEM`, scaled by `quality`, using the IJG scaling formula.
+fn set_quant_table(table_i: usize, table: &[u8; 64], quality: u8) {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 {
+        5_000 / quality
+    } else {
+        200 - quality * 2
+    };
+
+    for word_i in 0..16 {
+        let mut word = 0;
+        for j in 0..4 {
+            let coeff = table[ZIGZAG[word_i * 4 + j] as usize] as u32;
+            let val = ((coeff * scale + 50) / 100).clamp(1, 255);
+            word |= val << (8 * j);
+        }
+        write_mem(QMEM_OFFSET, table_i * 16 + word_i, word);
+    }
+}
+
+/// Generate the code lengths and codes of a canonical Huffman table, in the order of `bits`.
+fn huffman_codes(bits: &[u8; 16], lens: &mut [u8], codes: &mut [u16]) {
+    let mut code = 0;
+    let mut k = 0;
+
+    for (len_i, count) in bits.iter().enumerate() {
+        for _ in 0..*count {
+            lens[k] = len_i as u8 + 1;
+            codes[k] = code;
+            code += 1;
+            k += 1;
+        }
+        code <<= 1;
+    }
+}
+
+/// A `HUFFENC` table entry: The code length minus 1, and the lower 8 bits of the code. (Longer
+/// codes start with all 1s.)
+fn huffenc_entry(len: u8, code: u16) -> u32 {
+    (((len as u32 - 1) & 0xf) << 8) | (code as u32 & 0xff)
+}
+
+/// Load a DC Huffman encoder table. Its 12 entries are indexed by symbol (coefficient size).
+fn set_huffenc_dc(offset: usize, bits: &[u8; 16]) {
+    let mut lens = [0; 12];
+    let mut codes = [0; 12];
+    huffman_codes(bits, &mut lens, &mut codes);
+
+    let mut entries = [0; 12];
+    for (i, symbol) in DC_VALS.iter().enumerate() {
+        entries[*symbol as usize] = huffenc_entry(lens[i], codes[i]);
+    }
+
+    for word_i in 0..6 {
+        write_mem(
+            offset,
+            word_i,
+            entries[word_i * 2] | (entries[word_i * 2 + 1] << 16),
+        );
+    }
+    // Unused entries.
+    write_mem(offset, 6, 0x0fff_0fff);
+    write_mem(offset, 7, 0x0fff_0fff);
+}
+
+/// Load an AC Huffman encoder table. Its entries are indexed by run length and coefficient size:
+/// `run * 10 + size - 1`, then EOB (0x00) at 160, and ZRL (0xf0) at 161.
+fn set_huffenc_ac(offset: usize, bits: &[u8; 16], vals: &[u8; 162]) {
+    let mut lens = [0; 162];
+    let mut codes = [0; 162];
+    huffman_codes(bits, &mut lens, &mut codes);
+
+    let mut entries = [0; 162];
+    for (i, symbol) in vals.iter().enumerate() {
+        let entry_i = match symbol {
+            0x00 => 160,
+            0xf0 => 161,
+            _ => (symbol >> 4) as usize * 10 + (symbol & 0xf) as usize - 1,
+        };
+        entries[entry_i] = huffenc_entry(lens[i], codes[i]);
+    }
+
+    for word_i in 0..81 {
+        write_mem(
+            offset,
+            word_i,
+            entries[word_i * 2] | (entries[word_i * 2 + 1] << 16),
+        );
+    }
+
+    // RM: Locations 162 to 175 are used internally by the codec, and must be set to these values.
+    let internal = [
+        0x0fff_0fff,
+        0x0fff_0fff,
+        0x0fff_0fff,
+        0x0fd1_0fd0,
+        0x0fd3_0fd2,
+        0x0fd5_0fd4,
+        0x0fd7_0fd6,
+    ];
+    for (i, word) in internal.iter().enumerate() {
+        write_mem(offset, 81 + i, *word);
+    }
+}
+
+/// Load the Huffman tables written to the generated header's DHT marker. `DHTMEM` holds the BITS
+/// and HUFFVAL tables for DC0, AC0, DC1, and AC1, packed as a byte sequence.
+fn set_dht_mem() {
+    let mut dht = [0; 412];
+    let tables: [&[u8]; 8] = [
+        &DC_LUMA_BITS,
+        &DC_VALS,
+        &AC_LUMA_BITS,
+        &AC_LUMA_VALS,
+        &DC_CHROMA_BITS,
+        &DC_VALS,
+        &AC_CHROMA_BITS,
+        &AC_CHROMA_VALS,
+    ];
+
+    let mut i = 0;
+    for table in tables {
+        dht[i..i + table.len()].copy_from_slice(table);
+        i += table.len();
+    }
+
+    for (word_i, bytes) in dht.chunks_exact(4).enumerate() {
+        write_mem(
+            DHTMEM_OFFSET,
+            word_i,
+            u32::from_le_bytes(bytes.try_into().unwrap()),
+        );
+    }
+}
+
+/// Represents the JPEG codec peripheral.
+pub struct Jpeg {
+    pub regs: JPEG,
+}
+
+impl Jpeg {
+    /// Initialize the JPEG codec, including enabling and resetting its RCC peripheral clock, and
+    /// loading the standard Huffman tables used for encoding.
+    pub fn new(regs: JPEG) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc_en_reset!(ahb3, jpgdec, rcc);
+        });
+
+        regs.cr.write(|w| w.jcen().set_bit());
+        regs.confr0.write(|w| w.start().clear_bit());
+
+        set_huffenc_dc(HUFFENC_DC0_OFFSET, &DC_LUMA_BITS);
+        set_huffenc_dc(HUFFENC_DC1_OFFSET, &DC_CHROMA_BITS);
+        set_huffenc_ac(HUFFENC_AC0_OFFSET, &AC_LUMA_BITS, &AC_LUMA_VALS);
+        set_huffenc_ac(HUFFENC_AC1_OFFSET, &AC_CHROMA_BITS, &AC_CHROMA_VALS);
+        set_dht_mem();
+
+        Self { regs }
+    }
+
+    /// Stop any conversion, flush the FIFOs, and clear the status flags.
+    fn reset_process(&mut self) {
+        self.regs.confr0.write(|w| w.start().clear_bit());
+        self.regs.cr.modify(|_, w| {
+            w.iff().set_bit();
+            w.off().set_bit()
+        });
+        self.regs.cfr.write(|w| {
+            w.ceocf().set_bit();
+            w.chpdf().set_bit()
+        });
+    }
+
+    /// Start decoding a JPEG stream, with header parsing. Feed it with `write_input` (or DMA), and
+    /// read the decoded MCUs with `read_output`. The image information is available from
+    /// `image_info` once the `HeaderParsed` flag is set. Sets `JPEG_CONFR1` register, `DE` and
+    /// `HDR` fields, and `JPEG_CONFR0` register, `START` field.
+    pub fn start_decode(&mut self) {
+        self.reset_process();
+
+        self.regs.confr1.modify(|_, w| {
+            w.de().set_bit();
+            w.hdr().set_bit()
+        });
+
+        self.regs.confr0.write(|w| w.start().set_bit());
+    }
+
+    /// Start encoding an image, with a generated header. Feed it raw MCUs with `write_input` (or
+    /// DMA), and read the JPEG stream with `read_output`. Only grayscale and YCbCr images are
+    /// supported.
+    pub fn start_encode(&mut self, config: &EncodeConfig) -> Result<(), Error> {
+        if config.width == 0 || config.height == 0 {
+            return Err(Error::InvalidConfig);
+        }
+
+        let (num_components, subsampling) = match config.color_space {
+            ColorSpace::Grayscale => (1, ChromaSubsampling::S444),
+            ColorSpace::YCbCr => (3, config.chroma_subsampling),
+            _ => return Err(Error::InvalidConfig),
+        };
+
+        self.reset_process();
+
+        set_quant_table(0, &QUANT_LUMA, config.quality);
+        set_quant_table(1, &QUANT_CHROMA, config.quality);
+
+        let (mcu_width, mcu_height) = subsampling.mcu_size();
+        let num_mcus = ((config.width as u32 + mcu_width - 1) / mcu_width)
+            * ((config.height as u32 + mcu_height - 1) / mcu_height);
+
+        self.regs.confr1.write(|w| unsafe {
+            w.nf().bits(num_components - 1);
+            w.ns().bits(num_components - 1);
+            // The number of quantization tables, minus 1, when encoding.
+            w.colorspace().bits(config.color_space as u8);
+            w.ysize().bits(config.height);
+            w.de().clear_bit();
+            w.hdr().set_bit()
+        });
+        self.regs
+            .confr2
+            .write(|w| unsafe { w.nmcu().bits(num_mcus - 1) });
+        self.regs
+            .confr3
+            .write(|w| unsafe { w.xsize().bits(config.width) });
+
+        // The luma component's sampling factors, and blocks per MCU.
+        let (hsf, vsf) = match subsampling {
+            ChromaSubsampling::S444 => (1, 1),
+            ChromaSubsampling::S422 => (2, 1),
+            ChromaSubsampling::S420 => (2, 2),
+        };
+
+        self.regs.confrn1.write(|w| unsafe {
+            w.hsf().bits(hsf);
+            w.vsf().bits(vsf);
+            w.nb().bits(hsf * vsf - 1);
+            w.qt().bits(0);
+            w.ha().clear_bit();
+            w.hd().clear_bit()
+        });
+
+        // The chroma components use quantization and Huffman tables 1.
+        if num_components == 3 {
+            self.regs.confrn2.write(|w| unsafe {
+                w.hsf().bits(1);
+                w.vsf().bits(1);
+                w.nb().bits(0);
+                w.qt().bits(1);
+                w.ha().set_bit();
+                w.hd().set_bit()
+            });
+            self.regs.confrn3.write(|w| unsafe {
+                w.hsf().bits(1);
+                w.vsf().bits(1);
+                w.nb().bits(0);
+                w.qt().bits(1);
+                w.ha().set_bit();
+                w.hd().set_bit()
+            });
+        }
+
+        self.regs.confr0.write(|w| w.start().set_bit());
+
+        Ok(())
+    }
+
+    /// Stop the current conversion. Clears `JPEG_CONFR0` register, `START` field.
+    pub fn stop(&mut self) {
+        self.regs.confr0.write(|w| w.start().clear_bit());
+    }
+
+    /// Read the image information parsed from the header. Only valid once the `HeaderParsed` flag
+    /// is set, when decoding.
+    pub fn image_info(&self) -> ImageInfo {
+        let confr1 = self.regs.confr1.read();
+
+        let color_space = match confr1.nf().bits() {
+            0 => ColorSpace::Grayscale,
+            3 => ColorSpace::Cmyk,
+            _ => ColorSpace::YCbCr,
+        };
+
+        // Infer subsampling from the number of blocks of each component, per MCU.
+        let y_blocks = self.regs.confrn1.read().nb().bits();
+        let chroma_subsampling = if color_space == ColorSpace::YCbCr {
+            match y_blocks {
+                1 => ChromaSubsampling::S422,
+                3 => ChromaSubsampling::S420,
+                _ => ChromaSubsampling::S444,
+            }
+        } else {
+            ChromaSubsampling::S444
+        };
+
+        ImageInfo {
+            width: self.regs.confr3.read().xsize().bits(),
+            height: confr1.ysize().bits(),
+            color_space,
+            chroma_subsampling,
+            num_mcus: self.regs.confr2.read().nmcu().bits() + 1,
+        }
+    }
+
+    /// Write data to the input FIFO until it's full, or `data` is consumed, without blocking.
+    /// Returns the number of bytes written. Data is written a word at a time; a final partial word
+    /// is padded with zeros.
+    pub fn write_input(&mut self, data: &[u8]) -> usize {
+        let mut i = 0;
+
+        while i < data.len() && self.regs.sr.read().ifnff().bit_is_set() {
+            let end = (i + 4).min(data.len());
+            let mut bytes = [0; 4];
+            bytes[..end - i].copy_from_slice(&data[i..end]);
+
+            self.regs
+                .dir
+                .write(|w| unsafe { w.datain().bits(u32::from_le_bytes(bytes)) });
+            i = end;
+        }
+
+        i
+    }
+
+    /// Read data from the output FIFO until it's empty, or `buf` has less than a word free,
+    /// without blocking. Returns the number of bytes read.
+    pub fn read_output(&mut self, buf: &mut [u8]) -> usize {
+        let mut i = 0;
+
+        while i + 4 <= buf.len() && self.regs.sr.read().ofnef().bit_is_set() {
+            let word = self.regs.dor.read().dataout().bits();
+            buf[i..i + 4].copy_from_slice(&word.to_le_bytes());
+            i += 4;
+        }
+
+        i
+    }
+
+    /// Run a started conversion to completion, feeding `input` and reading into `output`. Returns
+    /// the number of bytes written to `output`.
+    fn run(&mut self, input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+        let mut in_i = 0;
+        let mut out_i = 0;
+
+        loop {
+            in_i += self.write_input(&input[in_i..]);
+            out_i += self.read_output(&mut output[out_i..]);
+
+            let sr = self.regs.sr.read();
+
+            if sr.hpdf().bit_is_set() {
+                self.regs.cfr.write(|w| w.chpdf().set_bit());
+            }
+
+            if sr.eocf().bit_is_set() && sr.ofnef().bit_is_clear() {
+                break;
+            }
+
+            if sr.ofnef().bit_is_set() && output.len() - out_i < 4 {
+                self.stop();
+                return Err(Error::OutputFull);
+            }
+        }
+
+        self.regs.cfr.write(|w| w.ceocf().set_bit());
+        self.stop();
+
+        Ok(out_i)
+    }
+
+    /// Decode a JPEG stream into `output`, as MCUs, blocking until complete. Returns the image
+    /// information, and the number of bytes written. Blocks indefinitely if `input` is truncated.
+    pub fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Result<(ImageInfo, usize), Error> {
+        self.start_decode();
+        let len = self.run(input, output)?;
+
+        Ok((self.image_info(), len))
+    }
+
+    /// Encode an image from raw MCUs, into a JPEG stream in `output`, blocking until complete.
+    /// Returns the number of bytes written; the stream may be followed by up to 3 bytes of padding.
+    pub fn encode(
+        &mut self,
+        config: &EncodeConfig,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.start_encode(config)?;
+        self.run(input, output)
+    }
+
+    /// Enable DMA requests from the input and output FIFOs, when they reach their thresholds. On
+    /// H7, these requests are only connected to the MDMA; use `input_addr` and `output_addr` as
+    /// the peripheral addresses, with 32-bit transfers. Sets `JPEG_CR` register, `IDMAEN` and
+    /// `ODMAEN` fields.
+    pub fn enable_dma(&mut self, input: bool, output: bool) {
+        self.regs.cr.modify(|_, w| {
+            w.idmaen().bit(input);
+            w.odmaen().bit(output)
+        });
+    }
+
+    /// The address of the input FIFO (`JPEG_DIR` register), for use with DMA.
+    pub fn input_addr(&self) -> u32 {
+        self.regs.dir.as_ptr() as u32
+    }
+
+    /// The address of the output FIFO (`JPEG_DOR` register), for use with DMA.
+    pub fn output_addr(&self) -> u32 {
+        self.regs.dor.as_ptr() as u32
+    }
+
+    /// Enable a specific type of JPEG interrupt.
+    pub fn enable_interrupt(&mut self, interrupt: JpegInterrupt) {
+        self.regs.cr.modify(|_, w| match interrupt {
+            JpegInterrupt::InputThreshold => w.iftie().set_bit(),
+            JpegInterrupt::InputNotFull => w.ifnfie().set_bit(),
+            JpegInterrupt::OutputThreshold => w.oftie().set_bit(),
+            JpegInterrupt::OutputNotEmpty => w.ofneie().set_bit(),
+            JpegInterrupt::EndOfConversion => w.eocie().set_bit(),
+            JpegInterrupt::HeaderParsed => w.hpdie().set_bit(),
+        });
+    }
+
+    /// Disable a specific type of JPEG interrupt.
+    pub fn disable_interrupt(&mut self, interrupt: JpegInterrupt) {
+        self.regs.cr.modify(|_, w| match interrupt {
+            JpegInterrupt::InputThreshold => w.iftie().clear_bit(),
+            JpegInterrupt::InputNotFull => w.ifnfie().clear_bit(),
+            JpegInterrupt::OutputThreshold => w.oftie().clear_bit(),
+            JpegInterrupt::OutputNotEmpty => w.ofneie().clear_bit(),
+            JpegInterrupt::EndOfConversion => w.eocie().clear_bit(),
+            JpegInterrupt::HeaderParsed => w.hpdie().clear_bit(),
+        });
+    }
+
+    /// Check if an interrupt flag is set. Reads the `JPEG_SR` register.
+    pub fn read_flag(&self, interrupt: JpegInterrupt) -> bool {
+        let sr = self.regs.sr.read();
+
+        match interrupt {
+            JpegInterrupt::InputThreshold => sr.iftf().bit_is_set(),
+            JpegInterrupt::InputNotFull => sr.ifnff().bit_is_set(),
+            JpegInterrupt::OutputThreshold => sr.oftf().bit_is_set(),
+            JpegInterrupt::OutputNotEmpty => sr.ofnef().bit_is_set(),
+            JpegInterrupt::EndOfConversion => sr.eocf().bit_is_set(),
+            JpegInterrupt::HeaderParsed => sr.hpdf().bit_is_set(),
+        }
+    }
+
+    /// Clear an interrupt flag. Only `EndOfConversion` and `HeaderParsed` can be cleared by
+    /// software; the FIFO flags follow the FIFO levels. Sets `JPEG_CFR` register.
+    pub fn clear_interrupt(&mut self, interrupt: JpegInterrupt) {
+        match interrupt {
+            JpegInterrupt::EndOfConversion => self.regs.cfr.write(|w| w.ceocf().set_bit()),
+            JpegInterrupt::HeaderParsed => self.regs.cfr.write(|w| w.chpdf().set_bit()),
+            _ => (),
+        }
+    }
+}
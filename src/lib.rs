@@ -358,6 +358,42 @@ pub use stm32wl::stm32wle5 as pac;
 #[cfg(not(any(feature = "f301", feature = "f302")))]
 pub mod adc;
 
+#[cfg(feature = "af-tables")]
+pub mod af;
+
+#[cfg(not(any(feature = "f301", feature = "f302")))]
+pub mod analog;
+
+// Requires both an ADC and a DAC.
+#[cfg(not(any(
+    feature = "f301",
+    feature = "f302",
+    feature = "f401",
+    feature = "f411",
+    feature = "f412",
+    feature = "wb",
+    feature = "g0"
+)))]
+pub mod analog_cal;
+
+#[cfg(feature = "g4")]
+pub mod afe;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+
+#[cfg(any(feature = "f3", feature = "f4"))]
+pub mod bitband;
+
+pub mod block_device;
+
+#[cfg(feature = "boards")]
+pub mod boards;
+
+// The L412 PAC is missing the backup registers.
+#[cfg(not(feature = "l412"))]
+pub mod boot_log;
+
 // bxCAN families: F3, F4, L4,
 // fdCAN families: L5, U5, G4, H7
 // H7 suppords fd and can_ccu. (What's that?)
@@ -368,6 +404,25 @@ pub mod adc;
 ))]
 pub mod can;
 
+// CAN bit timing calculation; available on MCUs with bxCAN or FDCAN.
+#[cfg(not(any(
+    feature = "f301",
+    feature = "f401",
+    feature = "f410",
+    feature = "f411",
+    feature = "wb",
+    feature = "wl",
+    all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))
+)))]
+pub mod can_timing;
+
+// Used by the bxCAN and FDCAN drivers.
+#[cfg(all(
+    feature = "embedded_can",
+    any(feature = "can", feature = "g4", feature = "h7", feature = "l5")
+))]
+pub mod can_frame;
+
 pub mod clocks;
 // todo: You could get CRC working on most of these with some effort.
 #[cfg(not(any(
@@ -389,6 +444,12 @@ pub mod crc;
 // WB doesn't have a DAC. Some G0 variants do - add it! Most F4 variants have it, some don't
 pub mod dac;
 
+// todo: G0 support. Its DBG peripheral is named inconsistently in the PAC, or missing.
+#[cfg(not(feature = "g0"))]
+pub mod debug;
+
+pub mod delay;
+
 #[cfg(not(any(
     feature = "f3",
     feature = "f4",
@@ -405,6 +466,8 @@ pub mod dac;
 )))]
 pub mod dfsdm;
 
+pub mod display;
+
 // todo: G0 missing many DMA registers like CCR?
 // todo: F4 needs some mods. So, only working on L4 and G4.
 // todo: L5 has a PAC bug on CCR registers past 1.
@@ -412,6 +475,17 @@ pub mod dfsdm;
 #[cfg(not(any(feature = "f4", feature = "l5")))]
 pub mod dma;
 
+pub mod ext_flash;
+
+#[cfg(not(feature = "g0"))]
+pub mod fault;
+
+#[cfg(any(feature = "g4", feature = "h7", feature = "l5"))]
+pub mod fdcan;
+
+#[cfg(feature = "l4")]
+pub mod firewall;
+
 // #[cfg(not(any(feature = "h747cm4", feature = "h747cm7")))]
 // PAC error on bank 2 accessor for H747cmx.
 pub mod flash;
@@ -421,8 +495,17 @@ pub mod flash;
 // todo: Also G4.
 // pub mod fmac;
 
+pub mod exti;
+
+// Uses 4-channel timers, which aren't available on these MCUs.
+#[cfg(not(any(feature = "f410", feature = "l5", feature = "wb")))]
+pub mod freq_meter;
+
 pub mod gpio;
 
+#[cfg(feature = "l5")]
+pub mod gtzc;
+
 // #[cfg(feature = "wb")]
 // pub mod bluetooth;
 // #[cfg(feature = "wb")]
@@ -431,6 +514,9 @@ pub mod gpio;
 #[cfg(feature = "wb")]
 pub mod hsem;
 
+#[cfg(feature = "wb")]
+pub mod c2;
+
 #[cfg(not(feature = "f4"))]
 pub mod i2c;
 #[cfg(feature = "f4")]
@@ -438,14 +524,70 @@ pub mod i2c_f4;
 #[cfg(feature = "f4")]
 pub use i2c_f4 as i2c;
 
+// F410, F413, and F446 don't have I2S extension blocks.
+#[cfg(any(
+    feature = "f401",
+    feature = "f405",
+    feature = "f407",
+    feature = "f411",
+    feature = "f412",
+    feature = "f427",
+    feature = "f429",
+    feature = "f469"
+))]
+pub mod i2s;
+
+// F4 erases variable-size sectors, not pages; see the module docs.
+#[cfg(not(any(feature = "f4", feature = "l5", feature = "h7")))]
+pub mod iap;
+
+pub mod interrupt;
+
+#[cfg(any(
+    feature = "f3",
+    feature = "l4x5",
+    feature = "l4x6",
+    feature = "g030",
+    feature = "g031",
+    feature = "g041",
+    feature = "g071",
+    feature = "g081",
+    feature = "wb"
+))]
+pub mod irtim;
+
 #[cfg(feature = "wb")]
 pub mod ipcc;
 
+#[cfg(feature = "h7")]
+pub mod jpeg;
+
 pub mod low_power;
 
-#[cfg(any(feature = "h747cm4", feature = "h747cm7"))]
+pub mod manchester;
+
+#[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+pub mod lptim;
+
+#[cfg(feature = "logger")]
+pub mod logger;
+
+#[cfg(feature = "h7")]
+pub mod mdma;
+
+pub mod mpu;
+
+pub mod onewire;
+
+#[cfg(feature = "panic-uart")]
+pub mod panic_uart;
+
+pub mod pdm;
+
 pub mod power;
 
+pub mod pulse_counter;
+
 // F3, F4, L5, G0, and WL don't have Quad SPI.
 #[cfg(not(any(
 feature = "f3",
@@ -463,6 +605,10 @@ feature = "wl",
 )))]
 pub mod qspi;
 
+// Uses 4-channel timers, which aren't available on these MCUs.
+#[cfg(not(any(feature = "f410", feature = "l5", feature = "wb")))]
+pub mod rc_input;
+
 // Note: Some F4 variants support RNG, but we haven't figured out the details yet. Send a PR if interested.
 #[cfg(not(any(
     feature = "f3",
@@ -486,10 +632,34 @@ pub mod rtc;
 )))]
 pub mod sai;
 
+pub mod sd_spi;
+
+#[cfg(feature = "self-test")]
+pub mod self_test;
+
+pub mod servo;
+
+pub mod shared_bus;
+
+pub mod soft_pwm;
+
+#[cfg(any(feature = "f446", feature = "h7"))]
+pub mod spdifrx;
+
 pub mod spi;
 
+// todo: G0 support. Its SYSCFG peripheral is named inconsistently in the PAC, or missing.
+#[cfg(not(any(feature = "g0", feature = "h7")))]
+pub mod syscfg;
+
+#[cfg(any(feature = "l5", feature = "g4", feature = "wl"))]
+pub mod tamp;
+
+pub mod time;
 pub mod timer;
 pub mod usart;
+pub mod vector_table;
+pub mod ws2812;
 
 // See note at top of `usb` module for info on G0; not avail on modules the PAC has avail.
 cfg_if::cfg_if! {
@@ -517,6 +687,27 @@ cfg_if::cfg_if! {
     }
 }
 
+#[cfg(all(
+    feature = "usb-serial",
+    any(feature = "usb", feature = "usbotg_fs", feature = "usbotg_hs")
+))]
+pub mod usb_serial;
+
+// The L412 PAC is missing the backup registers, used to request the bootloader.
+#[cfg(all(
+    feature = "usb-dfu",
+    any(feature = "usb", feature = "usbotg_fs", feature = "usbotg_hs"),
+    not(feature = "l412")
+))]
+pub mod usb_dfu;
+
+#[cfg(all(
+    feature = "usb-host",
+    any(feature = "f4", feature = "h7"),
+    not(feature = "f410")
+))]
+pub mod usb_host;
+
 mod util;
 
 // todo: should these helper macros be removed from this library? It has nothing to do with STM32.
@@ -567,7 +758,7 @@ macro_rules! make_simple_globals {
 }
 
 // todo: Remove this debug_workaroudn function on MCUs that don't require it. Ie, is this required on G4? G0?
-use cortex_m::interrupt::free;
+use crate::util::free;
 
 #[cfg(not(any(feature = "g0")))]
 /// Workaround due to debugger disconnecting in WFI (and low-power) modes.
@@ -576,26 +767,7 @@ use cortex_m::interrupt::free;
 /// use by the DMA clock.
 /// For why we enable the DMA clock, see STM32F446 errata, section 2.1.1.
 pub fn debug_workaround() {
-    free(|_| {
-        let dbgmcu = unsafe { &(*pac::DBGMCU::ptr()) };
-
-        cfg_if::cfg_if! {
-            if #[cfg(all(feature = "h7", not(any(feature = "h747cm4", feature = "h747cm7"))))] {
-                dbgmcu.cr.modify(|_, w| w.dbgsleep_d1().set_bit());
-                dbgmcu.cr.modify(|_, w| w.dbgstop_d1().set_bit());
-                dbgmcu.cr.modify(|_, w| w.dbgstby_d1().set_bit());
-            } else if #[cfg(feature = "h7")] {
-                dbgmcu.cr.modify(|_, w| w.dbgslpd1().set_bit());
-                dbgmcu.cr.modify(|_, w| w.dbgstpd1().set_bit());
-                dbgmcu.cr.modify(|_, w| w.dbgstbd1().set_bit());
-            } else {
-                #[cfg(not(feature = "l5"))]
-                dbgmcu.cr.modify(|_, w| w.dbg_sleep().set_bit());
-                dbgmcu.cr.modify(|_, w| w.dbg_stop().set_bit());
-                dbgmcu.cr.modify(|_, w| w.dbg_standby().set_bit());
-            }
-        }
-    });
+    debug::set_low_power_debug(true, true, true);
 
     free(|_| {
         let rcc = unsafe { &(*pac::RCC::ptr()) };
@@ -614,6 +786,7 @@ pub fn debug_workaround() {
 /// In the prelude, we export helper macros.
 pub mod prelude {
     pub use access_global;
+    pub use crate::bind_interrupts;
     pub use make_globals;
     pub use make_simple_globals;
 }
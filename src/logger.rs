@@ -0,0 +1,448 @@
+//! A backend for the `log` facade, that writes log messages to a U[S]ART, an RTT up channel, or
+//! the ITM, selected at runtime. Enabled with the `logger` feature. The RTT sink also requires
+//! the `logger-rtt` feature, since it exports the `_SEGGER_RTT` symbol, which conflicts with other
+//! RTT crates, eg `rtt-target` or `defmt-rtt`.
+//!
+//! Each message is formatted into a line buffer, then written in a critical section, so messages
+//! logged from interrupts don't interleave. For the U[S]ART, the critical section only copies the
+//! line to a transmit buffer; it's then sent with interrupts enabled, a byte at a time, before
+//! `log` returns. Bytes that don't fit in the buffer are dropped, and counted by `dropped`. ITM
+//! writes block until the line is sent. RTT writes don't, and drop what doesn't fit in the RTT
+//! buffer, eg if no debugger is reading.
+//!
+//! Example:
+//!
+//! ```
+//! logger::init(LevelFilter::Info);
+//!
+//! let uart = Usart::new(dp.USART2, 115_200, Default::default(), &clock_cfg);
+//! logger::set_usart(&uart);
+//!
+//! log::info!("Clocks: {} MHz", clock_cfg.sysclk() / 1_000_000);
+//!
+//! // Or, with a debug probe attached, and the `logger-rtt` feature:
+//! logger::set_sink(Sink::Rtt);
+//! ```
+
+#[cfg(feature = "logger-rtt")]
+use core::{cell::UnsafeCell, ptr};
+use core::{
+    cell::{Cell, RefCell},
+    fmt::{self, Write},
+    ops::Deref,
+};
+
+#[cfg(not(feature = "critical-section"))]
+use cortex_m::interrupt::Mutex;
+#[cfg(feature = "critical-section")]
+use critical_section::Mutex;
+
+use cfg_if::cfg_if;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::{pac, usart::Usart, util::free};
+
+/// The maximum length of a log line, including the level, target, and line ending. Longer
+/// messages are truncated.
+const LINE_LEN: usize = 256;
+
+#[cfg(feature = "logger-rtt")]
+/// The size of the RTT up channel's ring buffer.
+const RTT_BUF_SIZE: usize = 1_024;
+
+/// The size of the U[S]ART transmit ring buffer.
+const USART_BUF_SIZE: usize = 1_024;
+
+// ITM registers. See the ARMv7-M Architecture Reference Manual, section C1.7: "Instrumentation
+// Trace Macrocell".
+#[cfg(not(feature = "g0"))]
+const ITM_STIM0: usize = 0xe000_0000;
+#[cfg(not(feature = "g0"))]
+const ITM_TER: usize = 0xe000_0e00;
+#[cfg(not(feature = "g0"))]
+const ITM_TCR: usize = 0xe000_0e80;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Where log messages are written.
+pub enum Sink {
+    /// Discard log messages.
+    None,
+    /// The U[S]ART bound with `set_usart`.
+    Usart,
+    #[cfg(feature = "logger-rtt")]
+    /// RTT up channel 0, read by a debug probe, eg with `probe-rs`.
+    Rtt,
+    #[cfg(not(feature = "g0"))]
+    /// ITM stimulus port 0. The debugger must configure the ITM and TPIU, and enable the port.
+    Itm,
+}
+
+/// The logger. `init` installs it with the `log` facade.
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+static SINK: Mutex<Cell<Sink>> = Mutex::new(Cell::new(Sink::None));
+
+/// The address of the bound U[S]ART's register block.
+static LOG_USART: Mutex<Cell<Option<usize>>> = Mutex::new(Cell::new(None));
+
+/// Bytes waiting to be sent to the bound U[S]ART.
+static USART_BUF: Mutex<RefCell<TxBuf>> = Mutex::new(RefCell::new(TxBuf::new()));
+
+/// Install the logger with the `log` facade, and set the maximum level logged. Call once, at
+/// startup; later calls only set the level.
+pub fn init(level: LevelFilter) {
+    free(|_| unsafe {
+        // The racy versions are sound here, in a critical section, on our single core; the
+        // others aren't available on cores without atomics, eg G0.
+        let _ = log::set_logger_racy(&LOGGER);
+        log::set_max_level_racy(level);
+    });
+}
+
+/// Select where log messages are written. Selecting `Sink::Rtt` initializes the RTT control
+/// block, so the debugger can find it.
+pub fn set_sink(sink: Sink) {
+    #[cfg(feature = "logger-rtt")]
+    if sink == Sink::Rtt {
+        RTT.init();
+    }
+    free(|cs| SINK.borrow(cs).set(sink));
+}
+
+/// Returns where log messages are written.
+pub fn sink() -> Sink {
+    free(|cs| SINK.borrow(cs).get())
+}
+
+/// Write log messages to this U[S]ART, and select it as the sink.
+pub fn set_usart<R>(usart: &Usart<R>)
+where
+    R: Deref<Target = pac::usart1::RegisterBlock>,
+{
+    let addr = &*usart.regs as *const pac::usart1::RegisterBlock as usize;
+    free(|cs| {
+        LOG_USART.borrow(cs).set(Some(addr));
+        SINK.borrow(cs).set(Sink::Usart);
+    });
+}
+
+/// Unbind the U[S]ART with register block address `addr`, if it's bound. Called by `Usart::free`.
+pub(crate) fn release(addr: usize) {
+    free(|cs| {
+        let usart = LOG_USART.borrow(cs);
+        if usart.get() == Some(addr) {
+            usart.set(None);
+            USART_BUF.borrow(cs).borrow_mut().clear();
+        }
+    });
+}
+
+/// The number of bytes dropped, since startup, because the U[S]ART transmit buffer was full.
+pub fn dropped() -> u32 {
+    free(|cs| USART_BUF.borrow(cs).borrow().dropped)
+}
+
+/// Send the bytes in the U[S]ART transmit buffer. Each byte is sent in its own short critical
+/// section, when the U[S]ART is ready for it, so interrupts aren't blocked while the line is sent,
+/// and a message logged from an interrupt during this is sent in order.
+fn drain_usart() {
+    loop {
+        let done = free(|cs| {
+            let addr = match LOG_USART.borrow(cs).get() {
+                Some(a) => a,
+                None => return true,
+            };
+            let mut buf = USART_BUF.borrow(cs).borrow_mut();
+            if buf.len == 0 {
+                return true;
+            }
+
+            let regs = unsafe { &*(addr as *const pac::usart1::RegisterBlock) };
+            if usart_ready(regs) {
+                let byte = buf.pop();
+                write_usart(regs, byte);
+            }
+            false
+        });
+
+        if done {
+            break;
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut line = LineBuf::new();
+        let _ = write!(
+            line,
+            "{} {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        line.terminate();
+
+        let sink = free(|cs| {
+            let sink = SINK.borrow(cs).get();
+            match sink {
+                Sink::None => (),
+                Sink::Usart => {
+                    if LOG_USART.borrow(cs).get().is_some() {
+                        USART_BUF.borrow(cs).borrow_mut().push(line.as_bytes());
+                    }
+                }
+                #[cfg(feature = "logger-rtt")]
+                Sink::Rtt => RTT.write(line.as_bytes()),
+                #[cfg(not(feature = "g0"))]
+                Sink::Itm => write_itm(line.as_bytes()),
+            }
+            sink
+        });
+
+        if sink == Sink::Usart {
+            drain_usart();
+        }
+    }
+
+    /// Send the bytes in the U[S]ART transmit buffer, eg ones left when the sink was changed.
+    fn flush(&self) {
+        drain_usart();
+    }
+}
+
+/// A ring buffer of bytes waiting to be sent to the U[S]ART.
+struct TxBuf {
+    buf: [u8; USART_BUF_SIZE],
+    /// The index of the oldest byte.
+    read: usize,
+    len: usize,
+    /// The number of bytes dropped because the buffer was full.
+    dropped: u32,
+}
+
+impl TxBuf {
+    const fn new() -> Self {
+        Self {
+            buf: [0; USART_BUF_SIZE],
+            read: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Append bytes, dropping and counting what doesn't fit.
+    fn push(&mut self, bytes: &[u8]) {
+        let n = bytes.len().min(USART_BUF_SIZE - self.len);
+
+        for &byte in &bytes[..n] {
+            self.buf[(self.read + self.len) % USART_BUF_SIZE] = byte;
+            self.len += 1;
+        }
+
+        self.dropped = self.dropped.wrapping_add((bytes.len() - n) as u32);
+    }
+
+    /// Remove the oldest byte. Check `len` first.
+    fn pop(&mut self) -> u8 {
+        let byte = self.buf[self.read];
+        self.read = (self.read + 1) % USART_BUF_SIZE;
+        self.len -= 1;
+        byte
+    }
+
+    fn clear(&mut self) {
+        self.read = 0;
+        self.len = 0;
+    }
+}
+
+/// A fixed-size buffer, that truncates writes past its end, keeping room for the line ending.
+struct LineBuf {
+    buf: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl LineBuf {
+    fn new() -> Self {
+        Self {
+            buf: [0; LINE_LEN],
+            len: 0,
+        }
+    }
+
+    /// Append the line ending.
+    fn terminate(&mut self) {
+        self.buf[self.len..self.len + 2].copy_from_slice(b"\r\n");
+        self.len += 2;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Write for LineBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let avail = LINE_LEN - 2 - self.len;
+        let n = s.len().min(avail);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Returns `true` if a U[S]ART's transmit data register is empty, so it can accept a byte.
+fn usart_ready(regs: &pac::usart1::RegisterBlock) -> bool {
+    cfg_if! {
+        if #[cfg(feature = "f4")] {
+            regs.sr.read().txe().bit_is_set()
+        } else {
+            regs.isr.read().txe().bit_is_set()
+        }
+    }
+}
+
+/// Write a byte to a U[S]ART, directly through its registers. Check `usart_ready` first.
+fn write_usart(regs: &pac::usart1::RegisterBlock, byte: u8) {
+    cfg_if! {
+        if #[cfg(feature = "f4")] {
+            regs.dr.write(|w| unsafe { w.dr().bits(byte as u16) });
+        } else {
+            regs.tdr.write(|w| unsafe { w.tdr().bits(byte as u16) });
+        }
+    }
+}
+
+#[cfg(not(feature = "g0"))]
+/// Blocking writes to ITM stimulus port 0. Discards the bytes if the ITM, or the port, isn't
+/// enabled, so logging doesn't hang without a debugger.
+fn write_itm(bytes: &[u8]) {
+    unsafe {
+        let tcr = (ITM_TCR as *const u32).read_volatile();
+        let ter = (ITM_TER as *const u32).read_volatile();
+        if tcr & 1 == 0 || ter & 1 == 0 {
+            return;
+        }
+
+        let stim = ITM_STIM0 as *mut u32;
+        for &byte in bytes {
+            // Reads as 1 when the port's FIFO can accept a write.
+            while (stim as *const u32).read_volatile() & 1 == 0 {}
+            (stim as *mut u8).write_volatile(byte);
+        }
+    }
+}
+
+#[cfg(feature = "logger-rtt")]
+#[repr(C)]
+/// An RTT up channel descriptor. The layout is fixed by the RTT protocol.
+struct RttUpChannel {
+    name: *const u8,
+    buffer: *mut u8,
+    size: u32,
+    write: u32,
+    read: u32,
+    flags: u32,
+}
+
+#[cfg(feature = "logger-rtt")]
+#[repr(C)]
+/// The RTT control block the debugger searches RAM for, with one up channel, and no down
+/// channels.
+struct RttControlBlock {
+    id: [u8; 16],
+    max_up: u32,
+    max_down: u32,
+    up: RttUpChannel,
+}
+
+#[cfg(feature = "logger-rtt")]
+#[repr(C)]
+/// The RTT control block, and its up channel's buffer. Only accessed in critical sections, and
+/// by the debugger.
+struct Rtt {
+    cb: UnsafeCell<RttControlBlock>,
+    buf: UnsafeCell<[u8; RTT_BUF_SIZE]>,
+}
+
+#[cfg(feature = "logger-rtt")]
+unsafe impl Sync for Rtt {}
+
+// Debuggers find the control block by this symbol, or by searching RAM for its ID.
+#[cfg(feature = "logger-rtt")]
+#[export_name = "_SEGGER_RTT"]
+#[used]
+static RTT: Rtt = Rtt {
+    cb: UnsafeCell::new(RttControlBlock {
+        id: [0; 16],
+        max_up: 1,
+        max_down: 0,
+        up: RttUpChannel {
+            name: ptr::null(),
+            buffer: ptr::null_mut(),
+            size: 0,
+            write: 0,
+            read: 0,
+            flags: 0,
+        },
+    }),
+    buf: UnsafeCell::new([0; RTT_BUF_SIZE]),
+};
+
+#[cfg(feature = "logger-rtt")]
+impl Rtt {
+    /// Set up the control block, if it isn't already. The ID is written last, so the debugger
+    /// doesn't find a partially-initialized block.
+    fn init(&self) {
+        free(|_| unsafe {
+            let cb = self.cb.get();
+            if (*cb).id[0] != 0 {
+                return;
+            }
+
+            ptr::addr_of_mut!((*cb).up.name).write_volatile(b"Terminal\0".as_ptr());
+            ptr::addr_of_mut!((*cb).up.buffer).write_volatile(self.buf.get() as *mut u8);
+            ptr::addr_of_mut!((*cb).up.size).write_volatile(RTT_BUF_SIZE as u32);
+
+            // Written in reverse, since a non-zero first byte marks the block as initialized.
+            for (i, &b) in b"SEGGER RTT\0\0\0\0\0\0".iter().enumerate().rev() {
+                ptr::addr_of_mut!((*cb).id[i]).write_volatile(b);
+            }
+        });
+    }
+
+    /// Write to the up channel's ring buffer, dropping what doesn't fit. Call in a critical
+    /// section.
+    fn write(&self, bytes: &[u8]) {
+        unsafe {
+            let cb = self.cb.get();
+            let buf = self.buf.get() as *mut u8;
+
+            let mut write = ptr::addr_of!((*cb).up.write).read_volatile() as usize;
+            // Updated by the debugger.
+            let read = ptr::addr_of!((*cb).up.read).read_volatile() as usize;
+
+            for &byte in bytes {
+                let next = (write + 1) % RTT_BUF_SIZE;
+                if next == read {
+                    break;
+                }
+                buf.add(write).write_volatile(byte);
+                write = next;
+            }
+
+            ptr::addr_of_mut!((*cb).up.write).write_volatile(write as u32);
+        }
+    }
+}
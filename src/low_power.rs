@@ -362,3 +362,69 @@ fn sleep() {
 
     wfi();
 }
+
+#[cfg(not(any(feature = "f3", feature = "f4")))]
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// The resistor VBAT is charged through, from VDD. Sets `PWR_CR4` register (`PWR_CR3` on H7),
+/// `VBRS` field.
+pub enum VbatChargeResistor {
+    /// 5kΩ
+    R5k = 0,
+    /// 1.5kΩ
+    R1_5k = 1,
+}
+
+#[cfg(not(any(feature = "f3", feature = "f4")))]
+/// Charge a rechargeable battery or supercapacitor on VBAT through an internal resistor, while
+/// VDD is present. Sets `PWR_CR4` register (`PWR_CR3` on H7), `VBE` and `VBRS` fields. See L4 RM,
+/// section 5.1.5: "Battery backup domain". Don't use this with a non-rechargeable battery.
+pub fn enable_vbat_charging(resistor: VbatChargeResistor) {
+    let pwr = unsafe { &(*PWR::ptr()) };
+
+    enable_pwr_clock();
+
+    cfg_if! {
+        if #[cfg(feature = "h7")] {
+            pwr.cr3.modify(|_, w| {
+                w.vbrs().bit(resistor as u8 != 0);
+                w.vbe().set_bit()
+            });
+        } else {
+            pwr.cr4.modify(|_, w| {
+                w.vbrs().bit(resistor as u8 != 0);
+                w.vbe().set_bit()
+            });
+        }
+    }
+}
+
+#[cfg(not(any(feature = "f3", feature = "f4")))]
+/// Stop charging VBAT. Sets `PWR_CR4` register (`PWR_CR3` on H7), `VBE` field.
+pub fn disable_vbat_charging() {
+    let pwr = unsafe { &(*PWR::ptr()) };
+
+    enable_pwr_clock();
+
+    #[cfg(feature = "h7")]
+    pwr.cr3.modify(|_, w| w.vbe().clear_bit());
+    #[cfg(not(feature = "h7"))]
+    pwr.cr4.modify(|_, w| w.vbe().clear_bit());
+}
+
+#[cfg(not(any(feature = "f3", feature = "f4")))]
+/// Enable the PWR peripheral clock, on families where it's gated. (It's always on, on H7, WB,
+/// and WL.)
+fn enable_pwr_clock() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    cfg_if! {
+        if #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))] {
+            rcc.apb1enr1.modify(|_, w| w.pwren().set_bit());
+        } else if #[cfg(feature = "g0")] {
+            rcc.apbenr1.modify(|_, w| w.pwren().set_bit());
+        } else {
+            let _ = rcc;
+        }
+    }
+}
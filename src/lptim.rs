@@ -0,0 +1,301 @@
+//! Support for the low-power timer (LPTIM1) as a monotonic tick source. It can be clocked from
+//! the LSE (or LSI), so it keeps counting in Stop 2 mode, and its interrupts wake the MCU from it.
+//! This lets a scheduler (eg RTIC or an async executor) sleep in Stop 2 between timer deadlines.
+//! It can instead count external pulses, with `LpTimCounter`; see the `pulse_counter` module.
+//!
+//! The 16-bit counter is extended to 64 bits in software, using the auto-reload match interrupt.
+//! Set up `LpTimMonotonic::on_interrupt` in the `LPTIM1` interrupt handler, and don't mask it for
+//! longer than half a counter period: 1 second, with the LSE and no prescaler.
+//!
+//! Example, with the LSE enabled (eg by `Rtc::new` with `RtcClockSource::Lse`):
+//!
+//! `let mut mono = LpTimMonotonic::new(dp.LPTIM1, LpTimClock::Lse, 32_768, LpTimPrescaler::Div1);`
+//!
+//! To use this with RTIC, implement its `Monotonic` trait on a wrapper type, forwarding `now`,
+//! `set_compare`, `clear_compare_flag`, and `on_interrupt` to the methods here. To use it with the
+//! `time` module: `time::init(lptim::ticks_u32, mono.freq());`
+
+use core::cell::Cell;
+
+#[cfg(not(feature = "critical-section"))]
+use cortex_m::interrupt::Mutex;
+#[cfg(feature = "critical-section")]
+use critical_section::Mutex;
+
+use cfg_if::cfg_if;
+
+use crate::{
+    pac::{self, LPTIM1, RCC},
+    pulse_counter::PulseCounter,
+    rcc_en_reset,
+    util::free,
+};
+
+/// The auto-reload value; the counter runs over its full 16-bit range.
+const ARR_VAL: u16 = 0xffff;
+
+/// The number of times the counter has reached `ARR_VAL`; the upper 48 bits of the tick count.
+static PERIODS: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+/// The last tick count returned; used to keep reads monotonic.
+static LAST: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// The LPTIM1 kernel clock. Only `Lse` and `Lsi` keep running in Stop 2. Sets `RCC_CCIPR`
+/// register, `LPTIM1SEL` field.
+pub enum LpTimClock {
+    Pclk = 0b00,
+    Lsi = 0b01,
+    Hsi16 = 0b10,
+    Lse = 0b11,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Divides the kernel clock. Sets `LPTIM_CFGR` register, `PRESC` field.
+pub enum LpTimPrescaler {
+    Div1 = 0b000,
+    Div2 = 0b001,
+    Div4 = 0b010,
+    Div8 = 0b011,
+    Div16 = 0b100,
+    Div32 = 0b101,
+    Div64 = 0b110,
+    Div128 = 0b111,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// The input edges counted by `LpTimCounter`. Sets `LPTIM_CFGR` register, `CKPOL` field.
+pub enum LpTimEdge {
+    Rising = 0b00,
+    Falling = 0b01,
+    /// Both edges. The kernel clock must be at least 4 times the input frequency.
+    Both = 0b10,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// The digital filter on the input counted by `LpTimCounter`: The number of consecutive kernel
+/// clock samples at a new level needed to validate a transition. Sets `LPTIM_CFGR` register,
+/// `CKFLT` field.
+pub enum LpTimFilter {
+    None = 0b00,
+    Clocks2 = 0b01,
+    Clocks4 = 0b10,
+    Clocks8 = 0b11,
+}
+
+/// Read the counter. RM: "It should be read twice... in order to be sure to get a reliable
+/// value", when the LPTIM is clocked asynchronously from the APB clock.
+fn read_cnt(regs: &pac::lptim1::RegisterBlock) -> u16 {
+    loop {
+        let a = regs.cnt.read().cnt().bits();
+        let b = regs.cnt.read().cnt().bits();
+        if a == b {
+            return a;
+        }
+    }
+}
+
+/// Enable and reset LPTIM1, and select its kernel clock.
+fn setup_rcc(clock: LpTimClock) {
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc_en_reset!(apb1, lptim1, rcc);
+
+        cfg_if! {
+            if #[cfg(feature = "l5")] {
+                rcc.ccipr1.modify(|_, w| unsafe { w.lptim1sel().bits(clock as u8) });
+            } else {
+                rcc.ccipr.modify(|_, w| unsafe { w.lptim1sel().bits(clock as u8) });
+            }
+        }
+    });
+}
+
+/// The number of ticks since `LpTimMonotonic::new` was called, extended to 64 bits.
+pub fn ticks() -> u64 {
+    let regs = unsafe { &(*LPTIM1::ptr()) };
+
+    free(|cs| {
+        // The ARRM flag is set when the counter reaches `ARR_VAL`, so treat that value as the
+        // start of the next period: The flag, and the software period count, then line up with
+        // the wrap.
+        let count = read_cnt(regs).wrapping_add(1);
+        let mut periods = PERIODS.borrow(cs).get();
+
+        // A wrap that the interrupt handler hasn't counted yet. Checked after reading the
+        // counter, so a wrap after the read doesn't count.
+        if regs.isr.read().arrm().bit_is_set() && count < 0x8000 {
+            periods += 1;
+        }
+
+        let ticks = (periods << 16) | count as u64;
+
+        // The flag is synchronized to the APB clock, so it may lag the counter by a few cycles.
+        let last = LAST.borrow(cs);
+        let ticks = ticks.max(last.get());
+        last.set(ticks);
+
+        ticks
+    })
+}
+
+/// The tick count, truncated to 32 bits, for use with `time::init`.
+pub fn ticks_u32() -> u32 {
+    ticks() as u32
+}
+
+/// A free-running, 64-bit monotonic timer, using LPTIM1.
+pub struct LpTimMonotonic {
+    pub regs: LPTIM1,
+    freq: u32,
+}
+
+impl LpTimMonotonic {
+    /// Configure and start LPTIM1, counting from 0. `clock_freq` is the frequency of `clock`,
+    /// eg 32_768 for the LSE. The selected clock must already be running. Enables the auto-reload
+    /// and compare match interrupts; unmask the `LPTIM1` interrupt in the NVIC to use them. On
+    /// L4, LPTIM1 wakes the MCU from Stop 2 through EXTI line 32, which is unmasked by default.
+    pub fn new(
+        regs: LPTIM1,
+        clock: LpTimClock,
+        clock_freq: u32,
+        prescaler: LpTimPrescaler,
+    ) -> Self {
+        setup_rcc(clock);
+
+        free(|cs| {
+            PERIODS.borrow(cs).set(0);
+            LAST.borrow(cs).set(0);
+        });
+
+        // RM: "The LPTIM_CFGR register must only be modified when the LPTIM is disabled", as must
+        // the LPTIM_IER register.
+        regs.cfgr.write(|w| unsafe {
+            w.presc().bits(prescaler as u8);
+            // Clock the counter from the internal (kernel) clock.
+            w.cksel().clear_bit()
+        });
+
+        regs.ier.write(|w| {
+            w.arrmie().set_bit();
+            w.cmpmie().set_bit()
+        });
+
+        // The ARR and CMP registers must only be written while the LPTIM is enabled.
+        regs.cr.write(|w| w.enable().set_bit());
+
+        regs.arr.write(|w| unsafe { w.arr().bits(ARR_VAL) });
+        while regs.isr.read().arrok().bit_is_clear() {}
+        regs.icr.write(|w| w.arrokcf().set_bit());
+
+        // RM: "The LPTIM_CMP register value must be less than the LPTIM_ARR register value."
+        regs.cmp.write(|w| unsafe { w.cmp().bits(ARR_VAL - 1) });
+        while regs.isr.read().cmpok().bit_is_clear() {}
+        regs.icr.write(|w| w.cmpokcf().set_bit());
+
+        // Start counting, in continuous mode.
+        regs.cr.modify(|_, w| w.cntstrt().set_bit());
+
+        let freq = clock_freq / (1 << prescaler as u8);
+
+        Self { regs, freq }
+    }
+
+    /// The tick frequency, in Hz.
+    pub fn freq(&self) -> u32 {
+        self.freq
+    }
+
+    /// The number of ticks since this timer was started.
+    pub fn now(&self) -> u64 {
+        ticks()
+    }
+
+    /// Schedule the compare match interrupt for `instant`, in ticks. If `instant` isn't in the
+    /// current 16-bit counter period, the compare isn't changed; the auto-reload interrupt at the
+    /// end of each period is the caller's cue to call this again. Blocks for a few timer clock
+    /// cycles, until the register write is synchronized. Sets `LPTIM_CMP` register.
+    pub fn set_compare(&mut self, instant: u64) {
+        let now = self.now();
+        if instant >> 16 != now >> 16 {
+            return;
+        }
+
+        // Undo the offset used in `ticks`, and keep below `ARR_VAL`; this fires up to a tick
+        // early at the end of a period, and the caller reschedules.
+        let cmp = (instant as u16).wrapping_sub(1).min(ARR_VAL - 1);
+
+        self.regs.cmp.write(|w| unsafe { w.cmp().bits(cmp) });
+        while self.regs.isr.read().cmpok().bit_is_clear() {}
+        self.regs.icr.write(|w| w.cmpokcf().set_bit());
+    }
+
+    /// Clear the compare match flag. Sets `LPTIM_ICR` register, `CMPMCF` field.
+    pub fn clear_compare_flag(&mut self) {
+        self.regs.icr.write(|w| w.cmpmcf().set_bit());
+    }
+
+    /// Call this in the `LPTIM1` interrupt handler. Counts counter wraps, for extending it to 64
+    /// bits.
+    pub fn on_interrupt(&mut self) {
+        if self.regs.isr.read().arrm().bit_is_set() {
+            free(|cs| {
+                self.regs.icr.write(|w| w.arrmcf().set_bit());
+                let periods = PERIODS.borrow(cs);
+                periods.set(periods.get() + 1);
+            });
+        }
+    }
+}
+
+/// Counts pulses on LPTIM1's IN1 input, for use with a `PulseCounter`, eg from an anemometer or
+/// flow sensor. The kernel clock samples the input, so with the LSE or LSI, counting continues in
+/// Stop 2 mode. LPTIM1 can't be used for this and `LpTimMonotonic` at once.
+pub struct LpTimCounter {
+    pub regs: LPTIM1,
+}
+
+impl LpTimCounter {
+    /// Configure and start LPTIM1, counting pulses on its IN1 input from 0, and wrapping at
+    /// 0xffff. The selected clock must already be running, and be faster than the pulses: At least
+    /// twice the input frequency, and more with a filter.
+    pub fn new(regs: LPTIM1, clock: LpTimClock, edge: LpTimEdge, filter: LpTimFilter) -> Self {
+        setup_rcc(clock);
+
+        regs.cfgr.write(|w| unsafe {
+            // Count valid pulses on IN1, sampled by the internal (kernel) clock; this is needed
+            // to use the filter.
+            w.cksel().clear_bit();
+            w.countmode().set_bit();
+            w.ckpol().bits(edge as u8);
+            w.ckflt().bits(filter as u8)
+        });
+
+        // The ARR register must only be written while the LPTIM is enabled.
+        regs.cr.write(|w| w.enable().set_bit());
+
+        regs.arr.write(|w| unsafe { w.arr().bits(ARR_VAL) });
+        while regs.isr.read().arrok().bit_is_clear() {}
+        regs.icr.write(|w| w.arrokcf().set_bit());
+
+        // Start counting, in continuous mode.
+        regs.cr.modify(|_, w| w.cntstrt().set_bit());
+
+        Self { regs }
+    }
+
+    /// The number of pulses counted, wrapping at 0xffff.
+    pub fn count(&self) -> u16 {
+        read_cnt(&self.regs)
+    }
+
+    /// Sample the count for a `PulseCounter`. Call this every `sample_period`, eg from the RTC
+    /// wakeup interrupt handler.
+    pub fn sample(&self, counter: &mut PulseCounter) {
+        counter.on_sample(self.count() as u32, ARR_VAL as u32, 1);
+    }
+}
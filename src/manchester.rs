@@ -0,0 +1,443 @@
+//! A software Manchester (bi-phase) codec, driven by timers, for single-wire field buses such as
+//! DALI lighting control. Use it on parts without hardware for the bus (eg HDMI-CEC or a DALI
+//! peripheral), or to use any pin.
+//!
+//! Each bit is 2 half-bits of opposite levels, so there's always an edge mid-bit. Frames start
+//! with start bits, followed by 1 - 32 data bits, then the line idles for the stop bits. The first
+//! half of the start bits is the opposite of the idle level, so the first edge of a frame is at a
+//! bit boundary. With the defaults, this matches DALI: 1200bps, idle high, IEEE 802.3 convention
+//! (a 1 is low, then high), 1 start bit, MSB first, and 2 stop bits.
+//!
+//! Transmitting: `ManchesterTx` writes a GPIO pin from a timer's update interrupt, running at twice
+//! the bitrate; `ManchesterTx::timer_freq` gives the frequency.
+//!
+//! Receiving: `ManchesterRx` decodes edge timestamps, from a free-running timer capturing both
+//! edges of the input. The counter must wrap at 0xffff, and run fast enough to resolve a
+//! half-bit; eg at 1Mhz.
+//!
+//! Example, for DALI:
+//!
+//! ```
+//! static TX: Mutex<RefCell<Option<ManchesterTx>>> = Mutex::new(RefCell::new(None));
+//! static RX: Mutex<RefCell<Option<ManchesterRx>>> = Mutex::new(RefCell::new(None));
+//!
+//! let cfg = ManchesterConfig::default();
+//!
+//! // Transmit on PA5, configured as an output.
+//! let tx = ManchesterTx::new(Port::A, 5, cfg.clone());
+//! let mut tx_timer = Timer::new_tim6(dp.TIM6, tx.timer_freq(), Default::default(), &clock_cfg);
+//! tx_timer.enable_interrupt(TimerInterrupt::Update);
+//! tx_timer.enable();
+//!
+//! // Receive on TIM2 channel 1, at 1Mhz, capturing both edges.
+//! let rx = ManchesterRx::new(cfg, 1_000_000);
+//! let mut rx_timer = Timer::new_tim2(dp.TIM2, 1., Default::default(), &clock_cfg);
+//! rx_timer.set_prescaler((clock_cfg.apb1_timer() / 1_000_000 - 1) as u16);
+//! rx_timer.set_auto_reload(0xffff);
+//! rx_timer.set_capture_compare(TimChannel::C1, CaptureCompare::InputTi1);
+//! rx_timer.set_polarity(TimChannel::C1, Polarity::ActiveLow);
+//! rx_timer.set_complementary_polarity(TimChannel::C1, Polarity::ActiveLow);
+//! rx_timer.enable_capture_compare(TimChannel::C1);
+//! rx_timer.enable_interrupt(TimerInterrupt::CaptureCompare1);
+//! rx_timer.enable();
+//!
+//! free(|cs| access_global!(TX, tx, cs).send(0xff_00, 16).unwrap());
+//!
+//! #[interrupt]
+//! fn TIM6_DAC() {
+//!     free(|cs| {
+//!         unsafe { (*pac::TIM6::ptr()).sr.modify(|_, w| w.uif().clear_bit()) };
+//!         access_global!(TX, tx, cs);
+//!         tx.on_update();
+//!     });
+//! }
+//!
+//! #[interrupt]
+//! fn TIM2() {
+//!     free(|cs| {
+//!         // Reading the capture clears its flag.
+//!         let count = unsafe { (*pac::TIM2::ptr()).ccr1.read().bits() } as u16;
+//!         access_global!(RX, rx, cs);
+//!         rx.on_edge(count, gpio::is_high(Port::A, 0));
+//!     });
+//! }
+//!
+//! // Later, eg in the main loop, with the current count:
+//! match rx.read(rx_timer.read_count() as u16) {
+//!     Some(Ok(frame)) => println!("Received {} bits: {:x}", frame.bits, frame.data),
+//!     Some(Err(e)) => println!("Receive error: {}", e),
+//!     None => (),
+//! }
+//! ```
+
+use core::fmt;
+
+use crate::gpio::{self, Port};
+
+/// The most data bits in a frame.
+const MAX_DATA_BITS: u8 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Errors transmitting or receiving Manchester frames.
+pub enum ManchesterError {
+    /// A frame is already being transmitted.
+    Busy,
+    /// The time between edges wasn't a half-bit or a bit, within the tolerance.
+    Timing,
+    /// A bit's halves had the same level, or a start bit had the wrong value.
+    Encoding,
+    /// The frame had more than 32 data bits.
+    TooLong,
+}
+
+impl fmt::Display for ManchesterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Busy => f.write_str("a frame is already being transmitted"),
+            Self::Timing => f.write_str("the time between edges isn't a half-bit or a bit"),
+            Self::Encoding => f.write_str("a bit isn't validly Manchester-encoded"),
+            Self::TooLong => f.write_str("the frame has more than 32 data bits"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Which mid-bit edge represents a 1.
+pub enum Convention {
+    /// A 1 is low, then high: a rising edge. Used by DALI and Ethernet.
+    Ieee,
+    /// A 1 is high, then low: a falling edge. G. E. Thomas's original convention.
+    Thomas,
+}
+
+/// Manchester codec configuration, shared by the transmitter and receiver.
+#[derive(Clone)]
+pub struct ManchesterConfig {
+    /// The bitrate, in bps. Defaults to 1200.
+    pub bitrate: f32,
+    /// Which mid-bit edge represents a 1. Defaults to `Ieee`.
+    pub convention: Convention,
+    /// The line level between frames. Defaults to `true`, ie high.
+    pub idle_high: bool,
+    /// The number of start bits. Their first half is the opposite of the idle level, which sets
+    /// their value. Defaults to 1.
+    pub start_bits: u8,
+    /// The number of bit periods the transmitter idles for after each frame. Defaults to 2.
+    pub stop_bits: u8,
+    /// Send and receive data bits most significant first. Defaults to `true`.
+    pub msb_first: bool,
+    /// How far an edge can be from its expected time, as a fraction of a half-bit. Defaults to
+    /// 0.25.
+    pub tolerance: f32,
+}
+
+impl Default for ManchesterConfig {
+    fn default() -> Self {
+        Self {
+            bitrate: 1_200.,
+            convention: Convention::Ieee,
+            idle_high: true,
+            start_bits: 1,
+            stop_bits: 2,
+            msb_first: true,
+            tolerance: 0.25,
+        }
+    }
+}
+
+impl ManchesterConfig {
+    /// The value of the start bits: the bit whose first half is the opposite of the idle level.
+    fn start_bit(&self) -> bool {
+        // With the IEEE convention, a 1 starts low.
+        self.idle_high == (self.convention == Convention::Ieee)
+    }
+
+    /// The levels of a bit's first and second halves.
+    fn encode(&self, bit: bool) -> (bool, bool) {
+        let first_high = bit != (self.convention == Convention::Ieee);
+        (first_high, !first_high)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A received frame.
+pub struct ManchesterFrame {
+    /// The data bits, right-aligned. With `msb_first`, the first bit received is the highest.
+    pub data: u32,
+    /// The number of data bits.
+    pub bits: u8,
+}
+
+/// Transmits Manchester frames on a GPIO pin, from a timer's update interrupt.
+pub struct ManchesterTx {
+    port: Port,
+    pin: u8,
+    pub cfg: ManchesterConfig,
+    data: u32,
+    bits: u8,
+    /// The next half-bit to output, counting from the first start bit, if transmitting.
+    half_bit: Option<u16>,
+}
+
+impl ManchesterTx {
+    /// Create a transmitter on a pin, and set the pin to the idle level. Configure the pin as an
+    /// output first.
+    pub fn new(port: Port, pin: u8, cfg: ManchesterConfig) -> Self {
+        assert!(pin <= 15, "GPIO pins must be 0 - 15.");
+
+        let result = Self {
+            port,
+            pin,
+            cfg,
+            data: 0,
+            bits: 0,
+            half_bit: None,
+        };
+        result.write(result.cfg.idle_high);
+        result
+    }
+
+    /// The timer update frequency to drive `on_update` at, in Hz: twice the bitrate.
+    pub fn timer_freq(&self) -> f32 {
+        self.cfg.bitrate * 2.
+    }
+
+    /// Start transmitting a frame of `bits` data bits, from the low bits of `data`. Returns
+    /// `Busy` if a frame is being transmitted.
+    pub fn send(&mut self, data: u32, bits: u8) -> Result<(), ManchesterError> {
+        assert!(
+            bits >= 1 && bits <= MAX_DATA_BITS,
+            "Manchester frames must have 1 - 32 data bits."
+        );
+
+        if self.is_busy() {
+            return Err(ManchesterError::Busy);
+        }
+
+        self.data = data;
+        self.bits = bits;
+        self.half_bit = Some(0);
+        Ok(())
+    }
+
+    /// Returns `true` while a frame, or its stop bits, are being transmitted.
+    pub fn is_busy(&self) -> bool {
+        self.half_bit.is_some()
+    }
+
+    /// Output the next half-bit. Call this from the timer's update interrupt handler, after
+    /// clearing its flag. Does nothing when idle.
+    pub fn on_update(&mut self) {
+        let half_bit = match self.half_bit {
+            Some(h) => h,
+            None => return,
+        };
+
+        let start = self.cfg.start_bits as u16;
+        let data_end = start + self.bits as u16;
+        let i = half_bit / 2;
+
+        let level = if i < start {
+            let (first, second) = self.cfg.encode(self.cfg.start_bit());
+            if half_bit % 2 == 0 {
+                first
+            } else {
+                second
+            }
+        } else if i < data_end {
+            let n = (i - start) as u8;
+            let shift = if self.cfg.msb_first {
+                self.bits - 1 - n
+            } else {
+                n
+            };
+            let (first, second) = self.cfg.encode(self.data >> shift & 1 != 0);
+            if half_bit % 2 == 0 {
+                first
+            } else {
+                second
+            }
+        } else {
+            self.cfg.idle_high
+        };
+
+        self.write(level);
+
+        let next = half_bit + 1;
+        self.half_bit = if next < (data_end + self.cfg.stop_bits as u16) * 2 {
+            Some(next)
+        } else {
+            None
+        };
+    }
+
+    /// Stop transmitting, and set the pin to the idle level.
+    pub fn abort(&mut self) {
+        self.half_bit = None;
+        self.write(self.cfg.idle_high);
+    }
+
+    fn write(&self, high: bool) {
+        if high {
+            gpio::set_high(self.port, self.pin);
+        } else {
+            gpio::set_low(self.port, self.pin);
+        }
+    }
+}
+
+/// Decodes Manchester frames from edge timestamps, captured by a timer.
+pub struct ManchesterRx {
+    pub cfg: ManchesterConfig,
+    /// A half-bit, in timer counts.
+    half_bit: f32,
+    /// The count at the most recent edge, if receiving a frame.
+    last_edge: Option<u16>,
+    /// The level of a bit's first half, once received.
+    first_half: Option<bool>,
+    /// Bits received, including start bits.
+    bits: u8,
+    data: u32,
+    error: Option<ManchesterError>,
+    frame: Option<Result<ManchesterFrame, ManchesterError>>,
+}
+
+impl ManchesterRx {
+    /// Create a receiver. `count_freq` is the rate the capture timer counts at, in Hz.
+    pub fn new(cfg: ManchesterConfig, count_freq: u32) -> Self {
+        let half_bit = count_freq as f32 / (cfg.bitrate * 2.);
+        assert!(
+            half_bit >= 4. && half_bit * 2. * (1. + cfg.tolerance) < 65_536.,
+            "The capture timer must count 4 - 32k times per half-bit."
+        );
+
+        Self {
+            cfg,
+            half_bit,
+            last_edge: None,
+            first_half: None,
+            bits: 0,
+            data: 0,
+            error: None,
+            frame: None,
+        }
+    }
+
+    /// Record an edge. Call this from the capture interrupt handler. `count` is the captured
+    /// counter value, and `high` is the line level after the edge.
+    pub fn on_edge(&mut self, count: u16, high: bool) {
+        let last = match self.last_edge {
+            Some(l) => l,
+            None => {
+                // The first edge of a frame, from idle, is at the start of the first start bit.
+                if high != self.cfg.idle_high {
+                    self.start_frame(count);
+                }
+                return;
+            }
+        };
+
+        // The counter wraps at 0xffff, so wrapping subtraction handles edges that span an
+        // overflow.
+        let elapsed = count.wrapping_sub(last) as f32 / self.half_bit;
+
+        if elapsed > 2. + self.cfg.tolerance && high != self.cfg.idle_high {
+            // The line idled, and this edge starts the next frame.
+            self.end_frame();
+            self.start_frame(count);
+            return;
+        }
+
+        let halves = if elapsed < 1.5 { 1 } else { 2 };
+        let error = elapsed - halves as f32;
+        if error > self.cfg.tolerance || error < -self.cfg.tolerance {
+            self.error.get_or_insert(ManchesterError::Timing);
+        }
+
+        self.last_edge = Some(count);
+
+        // The level before this edge lasted `halves` half-bits.
+        for _ in 0..halves {
+            self.push_half(!high);
+        }
+    }
+
+    /// Returns the most recent frame, once the line has been idle for over a bit, and clears it.
+    /// `count` is the capture timer's current count. Returns `None` while receiving, or if no frame
+    /// has been received since the last call. Call this at least once per counter overflow, so
+    /// the idle time doesn't wrap.
+    pub fn read(&mut self, count: u16) -> Option<Result<ManchesterFrame, ManchesterError>> {
+        if let Some(last) = self.last_edge {
+            let elapsed = count.wrapping_sub(last) as f32 / self.half_bit;
+            if elapsed > 2. + self.cfg.tolerance {
+                self.end_frame();
+            }
+        }
+
+        self.frame.take()
+    }
+
+    fn start_frame(&mut self, count: u16) {
+        self.last_edge = Some(count);
+        self.first_half = None;
+        self.bits = 0;
+        self.data = 0;
+        self.error = None;
+    }
+
+    /// Finish a frame, once the line idles. The last level continues into the idle period, so if
+    /// we've only seen the first half of the last bit, its second half is the idle level.
+    fn end_frame(&mut self) {
+        if self.first_half.is_some() {
+            self.push_half(self.cfg.idle_high);
+        }
+
+        let data_bits = self.bits.saturating_sub(self.cfg.start_bits);
+
+        let result = match self.error {
+            Some(e) => Err(e),
+            None if data_bits == 0 => Err(ManchesterError::Encoding),
+            None if self.cfg.msb_first => Ok(ManchesterFrame {
+                data: self.data,
+                bits: data_bits,
+            }),
+            None => Ok(ManchesterFrame {
+                // Bits were shifted in from the right; the first is at the bottom.
+                data: self.data.reverse_bits() >> (32 - data_bits as u32),
+                bits: data_bits,
+            }),
+        };
+
+        self.frame = Some(result);
+        self.last_edge = None;
+    }
+
+    fn push_half(&mut self, high: bool) {
+        let first = match self.first_half.take() {
+            Some(f) => f,
+            None => {
+                self.first_half = Some(high);
+                return;
+            }
+        };
+
+        if first == high {
+            self.error.get_or_insert(ManchesterError::Encoding);
+            return;
+        }
+
+        let bit = self.cfg.encode(true).0 == first;
+
+        if self.bits < self.cfg.start_bits {
+            if bit != self.cfg.start_bit() {
+                self.error.get_or_insert(ManchesterError::Encoding);
+            }
+        } else if self.bits - self.cfg.start_bits >= MAX_DATA_BITS {
+            self.error.get_or_insert(ManchesterError::TooLong);
+            return;
+        } else {
+            self.data = self.data << 1 | bit as u32;
+        }
+        self.bits += 1;
+    }
+}
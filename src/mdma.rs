@@ -0,0 +1,383 @@
+//! Support for the Master Direct Memory Access (MDMA) controller, on H7. Its channels follow linked
+//! lists of transfer nodes in memory, so a list of non-contiguous buffers is sent as one logical
+//! transfer, without copying them into one buffer, or CPU involvement between them. Eg for
+//! gathering a frame from a header, payload, and trailer, into a peripheral's data register, or a
+//! contiguous buffer.
+//!
+//! Transfers are either started by software, or paced by a hardware request, eg QUADSPI's FIFO
+//! threshold, or a DMA1 or DMA2 stream's Transfer Complete flag. With the D-cache enabled, clean
+//! the source buffers before starting. The linked list nodes are cleaned from the D-cache when a
+//! list is started, so the MDMA reads them from memory; they don't need to be non-cacheable.
+//!
+//! Example, gathering 3 buffers into the QUADSPI data register:
+//!
+//! ```
+//! static mut NODES: [MdmaNode; 3] = [MdmaNode::new(); 3];
+//!
+//! let mut mdma = Mdma::new(dp.MDMA);
+//! let cfg = MdmaConfig {
+//!     trigger: MdmaTrigger::Hardware(22), // QUADSPI FIFO threshold
+//!     buffer_len: 4,
+//!     ..Default::default()
+//! };
+//!
+//! let qspi_dr = &qspi.regs.dr as *const _ as u32;
+//! unsafe { mdma.write_list(0, &[&header, &payload, &crc], qspi_dr, &mut NODES, &cfg) };
+//!
+//! while !mdma.transfer_is_complete(0) {}
+//! mdma.clear_interrupt(0, MdmaInterrupt::ChannelTransferComplete);
+//! ```
+//!
+//! See H743 RM, chapter 14: Master direct memory access controller (MDMA).
+
+use cortex_m::asm;
+
+use crate::{
+    dma::{DataSize, Priority},
+    pac::{MDMA, RCC},
+    rcc_en_reset,
+    util::free,
+};
+
+/// The number of MDMA channels.
+const NUM_CHANNELS: u8 = 16;
+
+// Channel register offsets, from the channel's base: 0x40 + 0x40 * channel. These are accessed by
+// offset, since the PAC has a field per channel, instead of an array.
+const CH_ISR: usize = 0x00;
+const CH_IFCR: usize = 0x04;
+const CH_CR: usize = 0x0c;
+/// The first register a linked list node is loaded into. The node's words map to the registers
+/// from here to `CxMDR`.
+const CH_TCR: usize = 0x10;
+
+// `MDMA_CxCR` register fields.
+const CR_EN: u32 = 1 << 0;
+const CR_SWRQ: u32 = 1 << 16;
+
+// `MDMA_CxTCR` register fields.
+const TCR_SWRM: u32 = 1 << 30;
+/// Each request triggers one buffer transfer.
+const TCR_TRGM_BUFFER: u32 = 0b00 << 28;
+/// Each request triggers the entire linked list.
+const TCR_TRGM_LIST: u32 = 0b11 << 28;
+/// Increment the address, by the data size.
+const INC_INCREMENT: u32 = 0b10;
+
+// `MDMA_CxTBR` register fields. Select the AHB bus, for the TCMs.
+const TBR_SBUS: u32 = 1 << 16;
+const TBR_DBUS: u32 = 1 << 17;
+
+/// The maximum bytes in a block; ie a node.
+const MAX_BLOCK_LEN: usize = 0x1_ffff;
+
+/// The Cortex-M7 `DCCMVAC` register: Clean the D-cache line containing an address.
+const DCCMVAC: usize = 0xe000_ef68;
+/// The Cortex-M7 D-cache line size, in bytes.
+const DCACHE_LINE_LEN: usize = 32;
+
+#[derive(Clone, Copy, PartialEq)]
+/// What starts transfers on a channel. Sets `MDMA_CxTCR` register, `SWRM` field, and `MDMA_CxTBR`
+/// register, `TSEL` field.
+pub enum MdmaTrigger {
+    /// The whole list runs as one memory-to-memory transfer, when started.
+    Software,
+    /// Each buffer transfer is triggered by this MDMA request. Eg 0 - 7 for DMA1 streams 0 - 7's
+    /// Transfer Complete flag, 8 - 15 for DMA2's, or 22 for the QUADSPI FIFO threshold. See H743
+    /// RM, Table 95: MDMA hardware requests.
+    Hardware(u8),
+}
+
+#[derive(Clone, Copy)]
+/// MDMA channel interrupt types. Sets the `MDMA_CxCR` register's interrupt enable fields.
+pub enum MdmaInterrupt {
+    TransferError = 0,
+    /// The whole linked list is complete.
+    ChannelTransferComplete = 1,
+    BlockRepeatTransferComplete = 2,
+    /// A node's block is complete.
+    BlockTransferComplete = 3,
+    /// A buffer transfer, of `buffer_len` bytes, is complete.
+    BufferTransferComplete = 4,
+}
+
+/// MDMA channel configuration, shared by each node in a list.
+pub struct MdmaConfig {
+    pub priority: Priority,
+    pub trigger: MdmaTrigger,
+    /// The number of bytes transferred per request, from 1 to 128; eg the peripheral's FIFO
+    /// threshold. Only used with hardware triggers. Sets `MDMA_CxTCR` register, `TLEN` field.
+    pub buffer_len: u8,
+    /// The size of each write to the destination; the source is read in bytes. Buffer lengths must
+    /// be a multiple of this.
+    pub dest_size: DataSize,
+}
+
+impl Default for MdmaConfig {
+    fn default() -> Self {
+        Self {
+            priority: Priority::Medium,
+            trigger: MdmaTrigger::Software,
+            buffer_len: 1,
+            dest_size: DataSize::S8,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, align(8))]
+/// A linked list node, which the MDMA loads into a channel's `MDMA_CxTCR` to `MDMA_CxMDR`
+/// registers when the previous node completes. These must stay in place, and unchanged, until the
+/// transfer is complete; eg in a `static`. See H743 RM, section 14.3.7: "MDMA linked list mode".
+pub struct MdmaNode {
+    tcr: u32,
+    bndtr: u32,
+    sar: u32,
+    dar: u32,
+    brur: u32,
+    lar: u32,
+    tbr: u32,
+    _reserved: u32,
+    mar: u32,
+    mdr: u32,
+}
+
+impl MdmaNode {
+    pub const fn new() -> Self {
+        Self {
+            tcr: 0,
+            bndtr: 0,
+            sar: 0,
+            dar: 0,
+            brur: 0,
+            lar: 0,
+            tbr: 0,
+            _reserved: 0,
+            mar: 0,
+            mdr: 0,
+        }
+    }
+}
+
+/// Represents the MDMA peripheral.
+pub struct Mdma {
+    pub regs: MDMA,
+}
+
+impl Mdma {
+    /// Initialize the MDMA peripheral, including enabling and resetting its RCC peripheral clock.
+    pub fn new(regs: MDMA) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc_en_reset!(ahb3, mdma, rcc);
+        });
+
+        Self { regs }
+    }
+
+    /// Send a list of buffers to one destination address, as one logical transfer, using a linked
+    /// list of `nodes`, one per buffer. The destination isn't incremented, eg for a peripheral
+    /// data register. Returns immediately; use `transfer_is_complete`, or the
+    /// `ChannelTransferComplete` interrupt, to check completion.
+    ///
+    /// # Safety
+    /// The buffers and nodes must stay valid, and unchanged, until the transfer is complete.
+    pub unsafe fn write_list(
+        &mut self,
+        channel: u8,
+        bufs: &[&[u8]],
+        dest_addr: u32,
+        nodes: &mut [MdmaNode],
+        cfg: &MdmaConfig,
+    ) {
+        self.start_list(channel, bufs, dest_addr, false, nodes, cfg);
+    }
+
+    /// Gather a list of buffers into a contiguous buffer at `dest_addr`, in order, using a linked
+    /// list of `nodes`, one per buffer. Returns immediately; use `transfer_is_complete`, or the
+    /// `ChannelTransferComplete` interrupt, to check completion.
+    ///
+    /// # Safety
+    /// The buffers and nodes must stay valid, and unchanged, until the transfer is complete, and
+    /// the destination must be large enough to hold every buffer.
+    pub unsafe fn gather_list(
+        &mut self,
+        channel: u8,
+        bufs: &[&[u8]],
+        dest_addr: u32,
+        nodes: &mut [MdmaNode],
+        cfg: &MdmaConfig,
+    ) {
+        self.start_list(channel, bufs, dest_addr, true, nodes, cfg);
+    }
+
+    /// Build the linked list, load its first node into the channel, and start it.
+    unsafe fn start_list(
+        &mut self,
+        channel: u8,
+        bufs: &[&[u8]],
+        dest_addr: u32,
+        dest_incr: bool,
+        nodes: &mut [MdmaNode],
+        cfg: &MdmaConfig,
+    ) {
+        assert!(channel < NUM_CHANNELS, "The MDMA channel must be 0 - 15.");
+        assert!(
+            !bufs.is_empty(),
+            "An MDMA list must have at least one buffer."
+        );
+        assert!(
+            nodes.len() >= bufs.len(),
+            "An MDMA list needs a node for each buffer."
+        );
+        assert!(
+            cfg.buffer_len >= 1 && cfg.buffer_len <= 128,
+            "The MDMA buffer length must be 1 - 128 bytes."
+        );
+
+        let dest_size = cfg.dest_size as u32;
+        let dest_inc = if dest_incr { INC_INCREMENT } else { 0 };
+
+        let mut tcr = ((cfg.buffer_len as u32 - 1) << 18)
+            | (dest_size << 10) // `DINCOS`: Increment by the destination size.
+            | (dest_size << 6) // `DSIZE`
+            | (dest_inc << 2) // `DINC`
+            | INC_INCREMENT; // `SINC`, with `SSIZE` and `SINCOS` at bytes.
+
+        // Pack source bytes into larger destination words.
+        if dest_size != DataSize::S8 as u32 {
+            tcr |= 1 << 25; // `PKE`
+        }
+
+        let mut tbr = 0;
+        match cfg.trigger {
+            MdmaTrigger::Software => tcr |= TCR_SWRM | TCR_TRGM_LIST,
+            MdmaTrigger::Hardware(request) => {
+                tcr |= TCR_TRGM_BUFFER;
+                tbr = request as u32 & 0x3f;
+            }
+        }
+        if is_tcm(dest_addr) {
+            tbr |= TBR_DBUS;
+        }
+
+        let mut dest = dest_addr;
+        for (i, buf) in bufs.iter().enumerate() {
+            assert!(
+                buf.len() <= MAX_BLOCK_LEN,
+                "MDMA buffers are limited to 131,071 bytes."
+            );
+            assert!(
+                buf.len() % (1 << dest_size) == 0,
+                "MDMA buffer lengths must be a multiple of the destination size."
+            );
+
+            let src = buf.as_ptr() as u32;
+            let node_tbr = if is_tcm(src) { tbr | TBR_SBUS } else { tbr };
+
+            // The last node's link address is 0, which ends the list.
+            let lar = if i + 1 < bufs.len() {
+                &nodes[i + 1] as *const MdmaNode as u32
+            } else {
+                0
+            };
+
+            nodes[i] = MdmaNode {
+                tcr,
+                bndtr: buf.len() as u32,
+                sar: src,
+                dar: dest,
+                lar,
+                tbr: node_tbr,
+                ..MdmaNode::new()
+            };
+
+            if dest_incr {
+                dest += buf.len() as u32;
+            }
+        }
+
+        // "A linked list node must be 64-bit aligned", which `MdmaNode`'s alignment guarantees.
+        // The MDMA reads the nodes from memory, so write them back from the D-cache, if enabled.
+        // This is harmless if it isn't, or if the nodes are in DTCM.
+        let nodes_start = nodes.as_ptr() as usize;
+        let nodes_end = nodes_start + core::mem::size_of_val(&nodes[..bufs.len()]);
+        let mut line = nodes_start & !(DCACHE_LINE_LEN - 1);
+        while line < nodes_end {
+            (DCCMVAC as *mut u32).write_volatile(line as u32);
+            line += DCACHE_LINE_LEN;
+        }
+        asm::dsb();
+
+        let cr = self.ch_reg(channel, CH_CR);
+        cr.write_volatile(cr.read_volatile() & !CR_EN);
+        while cr.read_volatile() & CR_EN != 0 {}
+
+        // Clear all flags, then load the first node into the channel's registers.
+        self.ch_reg(channel, CH_IFCR).write_volatile(0x1f);
+
+        let first = &nodes[0] as *const MdmaNode as *const u32;
+        for word in 0..10 {
+            // Skip the reserved word.
+            if word != 7 {
+                self.ch_reg(channel, CH_TCR + word * 4)
+                    .write_volatile(first.add(word).read());
+            }
+        }
+
+        // Complete the node clean, and channel register writes, before enabling the channel.
+        asm::dsb();
+
+        cr.write_volatile(((cfg.priority as u32) << 6) | CR_EN);
+        if cfg.trigger == MdmaTrigger::Software {
+            cr.write_volatile(cr.read_volatile() | CR_SWRQ);
+        }
+    }
+
+    /// Stop a channel's transfer. Clears `MDMA_CxCR` register, `EN` field.
+    pub fn stop(&mut self, channel: u8) {
+        let cr = self.ch_reg(channel, CH_CR);
+        unsafe {
+            cr.write_volatile(cr.read_volatile() & !CR_EN);
+            while cr.read_volatile() & CR_EN != 0 {}
+        }
+    }
+
+    /// Returns `true` when the whole linked list is complete. Reads `MDMA_CxISR` register,
+    /// `CTCIF` field.
+    pub fn transfer_is_complete(&self, channel: u8) -> bool {
+        unsafe { self.ch_reg(channel, CH_ISR).read_volatile() & (1 << 1) != 0 }
+    }
+
+    /// Enable an interrupt. Sets `MDMA_CxCR` register, `TEIE`, `CTCIE`, `BRTIE`, `BTIE`, or
+    /// `TCIE` field.
+    pub fn enable_interrupt(&mut self, channel: u8, interrupt: MdmaInterrupt) {
+        let cr = self.ch_reg(channel, CH_CR);
+        unsafe { cr.write_volatile(cr.read_volatile() | (1 << (interrupt as u32 + 1))) };
+    }
+
+    /// Disable an interrupt.
+    pub fn disable_interrupt(&mut self, channel: u8, interrupt: MdmaInterrupt) {
+        let cr = self.ch_reg(channel, CH_CR);
+        unsafe { cr.write_volatile(cr.read_volatile() & !(1 << (interrupt as u32 + 1))) };
+    }
+
+    /// Clear an interrupt flag. Sets `MDMA_CxIFCR` register.
+    pub fn clear_interrupt(&mut self, channel: u8, interrupt: MdmaInterrupt) {
+        unsafe {
+            self.ch_reg(channel, CH_IFCR)
+                .write_volatile(1 << interrupt as u32)
+        };
+    }
+
+    /// Get a pointer to a channel's register.
+    fn ch_reg(&self, channel: u8, offset: usize) -> *mut u32 {
+        let base = &*self.regs as *const _ as usize;
+        (base + 0x40 + 0x40 * channel as usize + offset) as *mut u32
+    }
+}
+
+/// Returns `true` if an address is in ITCM or DTCM, which the MDMA accesses over its AHB bus.
+fn is_tcm(addr: u32) -> bool {
+    addr < 0x0001_0000 || (addr >= 0x2000_0000 && addr < 0x2002_0000)
+}
@@ -0,0 +1,368 @@
+//! Memory protection unit (MPU) configuration. The MPU sets memory attributes and access
+//! permissions of up to 8 (16 on H7 and L5) regions. This module provides helpers for common
+//! uses:
+//!
+//! - Non-cacheable DMA buffers, on H7: With the D-cache enabled, the CPU may read stale data from
+//!   the cache after a DMA write, or DMA may read stale data from RAM before the cache is cleaned.
+//!   Placing DMA buffers in a non-cacheable region avoids both.
+//! - Stack guards: A no-access region at the bottom of the stack, so an overflow causes a fault,
+//!   instead of silently corrupting static data.
+//! - Preventing execution from the peripheral region.
+//!
+//! Example, on H7, for a 16kB DMA buffer section in SRAM1, and a stack guard:
+//!
+//! ```
+//! mpu::disable(&mut cp.MPU);
+//! mpu::set_non_cacheable(&mut cp.MPU, 0, 0x3000_0000, 16 * 1_024);
+//! mpu::set_stack_guard(&mut cp.MPU, 1, 0x2000_0000, 256);
+//! mpu::set_peripherals_no_execute(&mut cp.MPU, 2);
+//! mpu::enable(&mut cp.MPU, true);
+//! ```
+//!
+//! Or, to only set up a stack guard, and report overflows with the `fault` module:
+//! `mpu::install_stack_guard(&mut cp.MPU);`
+//!
+//! Regions with higher numbers take priority where they overlap. With `privileged_default` set in
+//! `enable`, privileged code can access memory outside all regions with the default memory map.
+//!
+//! L5 (Cortex-M33, Armv8-M) has a different MPU model, with base and limit addresses, and
+//! attributes set through the `MPU_MAIR` registers. We access registers by address, since the
+//! `cortex-m` register block depends on the target architecture. See the Cortex-M7 (or M4)
+//! Devices Generic User Guide, section 4.5: "Optional Memory Protection Unit", and ST AN4838:
+//! "Managing memory protection unit in STM32 MCUs".
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::{asm, peripheral::MPU};
+
+// Register offsets, from `MPU_TYPE`.
+const TYPE: usize = 0x00;
+const CTRL: usize = 0x04;
+const RNR: usize = 0x08;
+const RBAR: usize = 0x0c;
+// `MPU_RASR` on Armv6-M and Armv7-M; `MPU_RLAR` on Armv8-M.
+const RASR_RLAR: usize = 0x10;
+#[cfg(feature = "l5")]
+const MAIR0: usize = 0x30;
+
+// `MPU_CTRL` register fields.
+const CTRL_ENABLE: u32 = 1 << 0;
+const CTRL_HFNMIENA: u32 = 1 << 1;
+const CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+/// `SCB_SHCSR`, and its `MEMFAULTENA` field.
+#[cfg(not(feature = "g0"))]
+const SCB_SHCSR: usize = 0xe000_ed24;
+#[cfg(not(feature = "g0"))]
+const SHCSR_MEMFAULTENA: u32 = 1 << 16;
+
+/// The size of the guard region set by `install_stack_guard`. The HardFault handler runs with
+/// the MPU disabled, so after an overflow, it uses this region as its stack.
+pub const STACK_GUARD_SIZE: u32 = 1_024;
+
+/// The base address of the guard region set by `install_stack_guard`, or 0 if none.
+static STACK_GUARD: AtomicU32 = AtomicU32::new(0);
+
+/// The start, and size, of the peripheral region.
+const PERIPH_START: u32 = 0x4000_0000;
+const PERIPH_SIZE: u32 = 0x2000_0000;
+
+#[cfg(feature = "l5")]
+/// The `MPU_MAIR0` value, setting an attribute for each `MemAttr` index: Normal write-back,
+/// normal non-cacheable, device nGnRE, and device nGnRnE.
+const MAIR0_VAL: u32 = 0x00_04_44_ff;
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Memory attributes. (The values are indexes into `MPU_MAIR0`, on L5)
+pub enum MemAttr {
+    /// Normal memory, cacheable, write-back, with read and write allocate. Eg code and data.
+    Normal = 0,
+    /// Normal memory, non-cacheable, and shareable. Eg DMA buffers.
+    NonCacheable = 1,
+    /// Device memory, shareable, with bufferable writes. Eg peripherals.
+    Device = 2,
+    /// Strongly-ordered memory: Accesses complete in program order, without buffering.
+    StronglyOrdered = 3,
+}
+
+impl MemAttr {
+    #[cfg(not(feature = "l5"))]
+    /// The `MPU_RASR` `TEX`, `S`, `C`, and `B` fields, in place.
+    fn rasr_bits(&self) -> u32 {
+        // (TEX, S, C, B). Cortex-M0+ only supports TEX = 0b000.
+        #[cfg(not(feature = "g0"))]
+        let (tex, s, c, b) = match self {
+            Self::Normal => (0b001, 0, 1, 1),
+            Self::NonCacheable => (0b001, 1, 0, 0),
+            Self::Device => (0b000, 1, 0, 1),
+            Self::StronglyOrdered => (0b000, 0, 0, 0),
+        };
+        #[cfg(feature = "g0")]
+        let (tex, s, c, b) = match self {
+            Self::Normal => (0b000, 0, 1, 1),
+            Self::NonCacheable => (0b000, 1, 0, 0),
+            Self::Device => (0b000, 1, 0, 1),
+            Self::StronglyOrdered => (0b000, 0, 0, 0),
+        };
+
+        tex << 19 | s << 18 | c << 17 | b << 16
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Access permissions for privileged and unprivileged code. Sets the `AP` field.
+pub enum Access {
+    /// Read and write, from any privilege level.
+    ReadWrite,
+    /// Read-only, from any privilege level.
+    ReadOnly,
+    /// Read and write from privileged code only.
+    PrivilegedReadWrite,
+    /// No access. On L5, where this isn't available, this is privileged read-only: Writes fault,
+    /// which is enough for a stack guard.
+    NoAccess,
+}
+
+impl Access {
+    /// The `AP` field value.
+    fn ap(&self) -> u32 {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "l5")] {
+                match self {
+                    Self::PrivilegedReadWrite => 0b00,
+                    Self::ReadWrite => 0b01,
+                    Self::NoAccess => 0b10,
+                    Self::ReadOnly => 0b11,
+                }
+            } else {
+                match self {
+                    Self::NoAccess => 0b000,
+                    Self::PrivilegedReadWrite => 0b001,
+                    Self::ReadWrite => 0b011,
+                    Self::ReadOnly => 0b110,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+/// An MPU region. On MCUs other than L5, `size` must be a power of 2, of at least 32 bytes (256
+/// bytes on G0), and `base` must be a multiple of it. On L5, both must be multiples of 32 bytes.
+pub struct Region {
+    pub base: u32,
+    /// The size, in bytes.
+    pub size: u32,
+    pub attr: MemAttr,
+    pub access: Access,
+    /// Allow instruction fetches. Inverts the `XN` field.
+    pub execute: bool,
+}
+
+/// Returns the number of regions the MPU supports, or 0 if it has none. Reads `MPU_TYPE`
+/// register, `DREGION` field.
+pub fn num_regions(_mpu: &MPU) -> u8 {
+    ((read(TYPE) >> 8) & 0xff) as u8
+}
+
+/// Enable the MPU. With `privileged_default`, privileged code can access memory outside all
+/// regions using the default memory map; otherwise this faults. Includes the barriers required
+/// for the new configuration to apply to subsequent instructions. Sets `MPU_CTRL` register.
+pub fn enable(_mpu: &mut MPU, privileged_default: bool) {
+    #[cfg(feature = "l5")]
+    write(MAIR0, MAIR0_VAL);
+
+    let mut ctrl = CTRL_ENABLE;
+    if privileged_default {
+        ctrl |= CTRL_PRIVDEFENA;
+    }
+    write(CTRL, ctrl);
+
+    asm::dsb();
+    asm::isb();
+}
+
+/// Disable the MPU, eg before changing its regions. Completes outstanding memory accesses first.
+/// Clears `MPU_CTRL` register.
+pub fn disable(_mpu: &mut MPU) {
+    asm::dmb();
+    write(CTRL, 0);
+}
+
+/// Returns `true` if the MPU is enabled. Reads `MPU_CTRL` register, `ENABLE` field.
+pub fn is_enabled(_mpu: &MPU) -> bool {
+    read(CTRL) & CTRL_ENABLE != 0
+}
+
+/// Keep the MPU enabled in the HardFault and NMI handlers; by default, it's disabled in them.
+/// Sets `MPU_CTRL` register, `HFNMIENA` field.
+pub fn set_enabled_in_faults(_mpu: &mut MPU, enabled: bool) {
+    let ctrl = read(CTRL);
+    write(
+        CTRL,
+        if enabled {
+            ctrl | CTRL_HFNMIENA
+        } else {
+            ctrl & !CTRL_HFNMIENA
+        },
+    );
+}
+
+/// Configure and enable a region. Sets the `MPU_RNR`, `MPU_RBAR`, and `MPU_RASR` (or `MPU_RLAR`
+/// on L5) registers.
+pub fn set_region(mpu: &mut MPU, number: u8, region: &Region) {
+    assert!(number < num_regions(mpu), "Invalid MPU region number.");
+
+    write(RNR, number as u32);
+    // Disable the region while changing it.
+    write(RASR_RLAR, 0);
+
+    let xn = !region.execute as u32;
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "l5")] {
+            assert!(
+                region.base % 32 == 0 && region.size % 32 == 0 && region.size > 0,
+                "MPU region base and size must be multiples of 32 bytes."
+            );
+            // Normal non-cacheable memory is outer shareable; others aren't shared.
+            let sh = if region.attr == MemAttr::NonCacheable { 0b10 } else { 0b00 };
+            let limit = region.base + region.size - 1;
+
+            write(RBAR, region.base | sh << 3 | region.access.ap() << 1 | xn);
+            write(RASR_RLAR, (limit & !0x1f) | (region.attr as u32) << 1 | 1);
+        } else {
+            #[cfg(feature = "g0")]
+            let min_size = 256;
+            #[cfg(not(feature = "g0"))]
+            let min_size = 32;
+
+            assert!(
+                region.size.is_power_of_two() && region.size >= min_size,
+                "MPU region size must be a power of 2, of at least 32 bytes (256 on G0)."
+            );
+            assert!(
+                region.base % region.size == 0,
+                "MPU region base must be a multiple of its size."
+            );
+
+            // `SIZE` is log2(size) - 1.
+            let size = region.size.trailing_zeros() - 1;
+
+            write(RBAR, region.base);
+            write(
+                RASR_RLAR,
+                xn << 28 | region.access.ap() << 24 | region.attr.rasr_bits() | size << 1 | 1,
+            );
+        }
+    }
+}
+
+/// Disable a region. Clears `MPU_RASR` (or `MPU_RLAR`) register, `ENABLE` field.
+pub fn clear_region(mpu: &mut MPU, number: u8) {
+    assert!(number < num_regions(mpu), "Invalid MPU region number.");
+
+    write(RNR, number as u32);
+    write(RASR_RLAR, 0);
+}
+
+/// Set a region as normal, non-cacheable memory, for DMA buffers, with read and write access,
+/// and no execution. `base` and `size` have the same requirements as `Region`; eg place the
+/// buffers in a linker section aligned to its size.
+pub fn set_non_cacheable(mpu: &mut MPU, number: u8, base: u32, size: u32) {
+    set_region(
+        mpu,
+        number,
+        &Region {
+            base,
+            size,
+            attr: MemAttr::NonCacheable,
+            access: Access::ReadWrite,
+            execute: false,
+        },
+    );
+}
+
+/// Set a no-access, no-execute guard region at the bottom of a stack, which grows down towards
+/// `bottom`. A stack overflow into it causes a MemManage fault (or HardFault, if MemManage isn't
+/// enabled, or on G0). `size` has the same requirements as `Region`.
+pub fn set_stack_guard(mpu: &mut MPU, number: u8, bottom: u32, size: u32) {
+    set_region(
+        mpu,
+        number,
+        &Region {
+            base: bottom,
+            size,
+            attr: MemAttr::Normal,
+            access: Access::NoAccess,
+            execute: false,
+        },
+    );
+}
+
+/// Set the peripheral region, `0x4000_0000` - `0x5fff_ffff`, as device memory, with no execution.
+/// This prevents executing data written to peripherals, eg as part of an exploit. Privileged and
+/// unprivileged code can access it.
+pub fn set_peripherals_no_execute(mpu: &mut MPU, number: u8) {
+    set_region(
+        mpu,
+        number,
+        &Region {
+            base: PERIPH_START,
+            size: PERIPH_SIZE,
+            attr: MemAttr::Device,
+            access: Access::ReadWrite,
+            execute: false,
+        },
+    );
+}
+
+/// Install a stack guard region below the stack, and enable the MPU, and the MemManage fault.
+/// This assumes the `cortex-m-rt` memory layout: The stack starts at the top of RAM, and grows
+/// down towards the static variables, which end at the `__sheap` linker symbol. The guard is the
+/// first `STACK_GUARD_SIZE` bytes above them, aligned to its size; the stack can't use these. It
+/// uses the highest-numbered region, so it takes priority over others, and returns the guard's
+/// base address.
+///
+/// An overflow causes a MemManage fault; its handler can't stack its frame either, so this
+/// escalates to a HardFault. Decode it with `fault::FaultInfo` there. On G0, which has no
+/// MemManage fault, it causes a HardFault directly.
+pub fn install_stack_guard(mpu: &mut MPU) -> u32 {
+    extern "C" {
+        static mut __sheap: u32;
+    }
+
+    let statics_end = unsafe { core::ptr::addr_of_mut!(__sheap) as u32 };
+    let base = (statics_end + STACK_GUARD_SIZE - 1) & !(STACK_GUARD_SIZE - 1);
+
+    disable(mpu);
+    let number = num_regions(mpu) - 1;
+    set_stack_guard(mpu, number, base, STACK_GUARD_SIZE);
+    enable(mpu, true);
+
+    #[cfg(not(feature = "g0"))]
+    unsafe {
+        let shcsr = SCB_SHCSR as *mut u32;
+        shcsr.write_volatile(shcsr.read_volatile() | SHCSR_MEMFAULTENA);
+    }
+
+    STACK_GUARD.store(base, Ordering::Relaxed);
+    base
+}
+
+/// The base address of the guard region set by `install_stack_guard`, if any.
+pub fn stack_guard() -> Option<u32> {
+    match STACK_GUARD.load(Ordering::Relaxed) {
+        0 => None,
+        base => Some(base),
+    }
+}
+
+fn read(offset: usize) -> u32 {
+    unsafe { ((MPU::PTR as usize + offset) as *const u32).read_volatile() }
+}
+
+fn write(offset: usize, val: u32) {
+    unsafe { ((MPU::PTR as usize + offset) as *mut u32).write_volatile(val) }
+}
@@ -0,0 +1,476 @@
+//! A 1-Wire (Dallas/Maxim) bus master, eg for DS18B20 temperature sensors. The bus is driven
+//! either by a U[S]ART in half-duplex mode (`OneWireUart`), or by bit-banging an open-drain GPIO
+//! pin with SysTick delays (`OneWireGpio`). Both implement `OneWireBus`, which provides byte
+//! transfers and ROM commands; `RomSearch` discovers the devices on a bus.
+//!
+//! The UART method sends a 0xF0 byte at 9600 baud as the reset pulse, and one byte at 115200 baud
+//! per bit: 0xFF writes a 1 or reads a bit, and 0x00 writes a 0. The byte received back shows
+//! whether a device held the line low. This needs no CPU time during each slot, and is
+//! unaffected by interrupts. See Maxim AN214: "Using a UART to Implement a 1-Wire Bus Master".
+//!
+//! The GPIO method follows the standard speed timings in Maxim AN126: "1-Wire Communication
+//! Through Software", with interrupts disabled during each time slot.
+//!
+//! Example, reading a single DS18B20:
+//!
+//! `let mut bus = OneWireUart::new(dp.USART2, &clock_cfg);`
+//! `onewire::ds18b20_convert(&mut bus, None)?;`
+//! `delay.delay_ms(750);`
+//! `let temp = onewire::ds18b20_read_temp(&mut bus, None)?;`
+
+use core::ops::Deref;
+
+use cortex_m::delay::Delay;
+
+use crate::{
+    clocks::Clocks,
+    gpio::Pin,
+    pac,
+    usart::Usart,
+    util::{free, BaudPeriph, RccPeriph},
+};
+
+use cfg_if::cfg_if;
+
+// ROM commands, common to all 1-Wire devices.
+const CMD_SEARCH_ROM: u8 = 0xF0;
+const CMD_ALARM_SEARCH: u8 = 0xEC;
+const CMD_READ_ROM: u8 = 0x33;
+const CMD_MATCH_ROM: u8 = 0x55;
+const CMD_SKIP_ROM: u8 = 0xCC;
+
+// DS18B20 function commands.
+const CMD_CONVERT_T: u8 = 0x44;
+const CMD_READ_SCRATCHPAD: u8 = 0xBE;
+
+/// The DS18B20's family code, the first byte of its ROM code.
+pub const FAMILY_DS18B20: u8 = 0x28;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// 1-Wire errors.
+pub enum Error {
+    /// No device responded to the reset pulse with a presence pulse.
+    NoPresence,
+    /// During a ROM search, no device responded to a bit; eg a device was removed mid-search.
+    Search,
+    /// The CRC of a ROM code or scratchpad didn't match its contents.
+    Crc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A device's 64-bit ROM code: the family code, 48-bit serial number, and CRC, in the order
+/// they're sent on the bus.
+pub struct Rom(pub [u8; 8]);
+
+impl Rom {
+    /// The device family code, eg `FAMILY_DS18B20`.
+    pub fn family(&self) -> u8 {
+        self.0[0]
+    }
+
+    /// Returns `true` if the ROM code's CRC byte matches its contents.
+    pub fn crc_valid(&self) -> bool {
+        crc8(&self.0[..7]) == self.0[7]
+    }
+}
+
+/// Compute the Dallas/Maxim CRC-8 (polynomial x^8 + x^5 + x^4 + 1) of a buffer, as used for ROM
+/// codes and scratchpads.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0;
+    for byte in data {
+        let mut byte = *byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 1;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
+
+/// A 1-Wire bus master. Implementors provide the reset pulse and a single time slot; the other
+/// methods are built on these.
+pub trait OneWireBus {
+    /// Send a reset pulse. Returns `true` if a device responds with a presence pulse.
+    fn reset(&mut self) -> bool;
+
+    /// Run one time slot. If `bit` is `false`, writes a 0. If `true`, writes a 1, which is also a
+    /// read slot: returns `false` if a device held the line low.
+    fn touch_bit(&mut self, bit: bool) -> bool;
+
+    /// Send a reset pulse, and return an error if no device is present.
+    fn reset_presence(&mut self) -> Result<(), Error> {
+        if self.reset() {
+            Ok(())
+        } else {
+            Err(Error::NoPresence)
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        self.touch_bit(bit);
+    }
+
+    fn read_bit(&mut self) -> bool {
+        self.touch_bit(true)
+    }
+
+    /// Write a byte, least significant bit first.
+    fn write_byte(&mut self, byte: u8) {
+        for i in 0..8 {
+            self.write_bit(byte & (1 << i) != 0);
+        }
+    }
+
+    /// Read a byte, least significant bit first.
+    fn read_byte(&mut self) -> u8 {
+        let mut result = 0;
+        for i in 0..8 {
+            if self.read_bit() {
+                result |= 1 << i;
+            }
+        }
+        result
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_byte(*byte);
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.read_byte();
+        }
+    }
+
+    /// Reset the bus, and address all devices (Skip ROM), eg when there's only one device, or to
+    /// start conversions on all sensors at once.
+    fn skip_rom(&mut self) -> Result<(), Error> {
+        self.reset_presence()?;
+        self.write_byte(CMD_SKIP_ROM);
+        Ok(())
+    }
+
+    /// Reset the bus, and address a single device by its ROM code (Match ROM).
+    fn match_rom(&mut self, rom: &Rom) -> Result<(), Error> {
+        self.reset_presence()?;
+        self.write_byte(CMD_MATCH_ROM);
+        self.write_bytes(&rom.0);
+        Ok(())
+    }
+
+    /// Reset the bus, and address a device by its ROM code if `rom` is `Some`, or all devices
+    /// otherwise.
+    fn select(&mut self, rom: Option<&Rom>) -> Result<(), Error> {
+        match rom {
+            Some(r) => self.match_rom(r),
+            None => self.skip_rom(),
+        }
+    }
+
+    /// Read the ROM code of the only device on the bus (Read ROM). If there are multiple devices,
+    /// their responses collide; use `RomSearch` instead.
+    fn read_rom(&mut self) -> Result<Rom, Error> {
+        self.reset_presence()?;
+        self.write_byte(CMD_READ_ROM);
+
+        let mut rom = Rom([0; 8]);
+        self.read_bytes(&mut rom.0);
+
+        if rom.crc_valid() {
+            Ok(rom)
+        } else {
+            Err(Error::Crc)
+        }
+    }
+}
+
+/// Finds the ROM codes of all devices on a bus, one per call to `next`, using the algorithm in
+/// Maxim AN187: "1-Wire Search Algorithm".
+/// Example: `let mut search = RomSearch::new(); while let Some(rom) = search.next(&mut bus)? {}`
+pub struct RomSearch {
+    rom: [u8; 8],
+    /// The bit position (1 - 64) of the last 0 branch taken at a discrepancy; 0 if none.
+    last_discrepancy: u8,
+    done: bool,
+    command: u8,
+}
+
+impl RomSearch {
+    /// Search for all devices.
+    pub fn new() -> Self {
+        Self {
+            rom: [0; 8],
+            last_discrepancy: 0,
+            done: false,
+            command: CMD_SEARCH_ROM,
+        }
+    }
+
+    /// Search only for devices with an alarm condition set, eg a DS18B20 past its temperature
+    /// limits.
+    pub fn new_alarm() -> Self {
+        Self {
+            command: CMD_ALARM_SEARCH,
+            ..Self::new()
+        }
+    }
+
+    /// Find the next device. Returns `None` once all devices have been found.
+    pub fn next<B: OneWireBus>(&mut self, bus: &mut B) -> Result<Option<Rom>, Error> {
+        if self.done {
+            return Ok(None);
+        }
+
+        if !bus.reset() {
+            // No devices on the bus.
+            self.done = true;
+            return Ok(None);
+        }
+        bus.write_byte(self.command);
+
+        let mut last_zero = 0;
+
+        for bit_num in 1..=64 {
+            let byte = ((bit_num - 1) / 8) as usize;
+            let mask = 1 << ((bit_num - 1) % 8);
+
+            // Each device sends its bit, then its complement.
+            let bit = bus.read_bit();
+            let complement = bus.read_bit();
+
+            let direction = match (bit, complement) {
+                (true, true) => return Err(Error::Search),
+                (false, false) => {
+                    // Devices disagree on this bit. Take the 0 branch until we reach the last
+                    // discrepancy, then the 1 branch; past it, take the 0 branch.
+                    let direction = if bit_num < self.last_discrepancy {
+                        self.rom[byte] & mask != 0
+                    } else {
+                        bit_num == self.last_discrepancy
+                    };
+                    if !direction {
+                        last_zero = bit_num;
+                    }
+                    direction
+                }
+                // All remaining devices have this value.
+                (b, _) => b,
+            };
+
+            if direction {
+                self.rom[byte] |= mask;
+            } else {
+                self.rom[byte] &= !mask;
+            }
+            bus.write_bit(direction);
+        }
+
+        self.last_discrepancy = last_zero;
+        if last_zero == 0 {
+            self.done = true;
+        }
+
+        let rom = Rom(self.rom);
+        if rom.crc_valid() {
+            Ok(Some(rom))
+        } else {
+            Err(Error::Crc)
+        }
+    }
+}
+
+impl Default for RomSearch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Start a temperature conversion on a DS18B20, or all of them if `rom` is `None`. The result is
+/// ready after up to 750ms, at 12-bit resolution.
+pub fn ds18b20_convert<B: OneWireBus>(bus: &mut B, rom: Option<&Rom>) -> Result<(), Error> {
+    bus.select(rom)?;
+    bus.write_byte(CMD_CONVERT_T);
+    Ok(())
+}
+
+/// Read the result of the last temperature conversion from a DS18B20, in °C. If `rom` is `None`,
+/// there must be only one device on the bus.
+pub fn ds18b20_read_temp<B: OneWireBus>(bus: &mut B, rom: Option<&Rom>) -> Result<f32, Error> {
+    bus.select(rom)?;
+    bus.write_byte(CMD_READ_SCRATCHPAD);
+
+    let mut scratchpad = [0; 9];
+    bus.read_bytes(&mut scratchpad);
+
+    if crc8(&scratchpad[..8]) != scratchpad[8] {
+        return Err(Error::Crc);
+    }
+
+    // The temperature is a signed 16-bit value, in 1/16°C.
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+    Ok(raw as f32 / 16.)
+}
+
+/// A 1-Wire bus on a U[S]ART in half-duplex mode, using only its TX pin. Configure the TX pin as
+/// alternate function, open-drain, with a pull-up resistor (eg 4.7kΩ) to the bus supply.
+pub struct OneWireUart<R> {
+    pub usart: Usart<R>,
+    /// `BRR` values for the reset pulse (9600 baud), and the time slots (115200 baud).
+    brr_reset: u32,
+    brr_slot: u32,
+}
+
+impl<R> OneWireUart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Initialize a U[S]ART as a 1-Wire bus master. Sets `USART_CR3` register, `HDSEL` field.
+    pub fn new(regs: R, clock_cfg: &Clocks) -> Self {
+        let usart = Usart::new(regs, 115_200, Default::default(), clock_cfg);
+
+        // RM: "This bit can only be written when the USART is disabled (UE=0)."
+        usart.regs.cr1.modify(|_, w| w.ue().clear_bit());
+        while usart.regs.cr1.read().ue().bit_is_set() {}
+        usart.regs.cr3.modify(|_, w| w.hdsel().set_bit());
+        usart.regs.cr1.modify(|_, w| w.ue().set_bit());
+
+        // This uses the default 16x oversampling, where BRR = USARTDIV.
+        let fclk = R::baud(clock_cfg);
+
+        Self {
+            usart,
+            brr_reset: fclk / 9_600,
+            brr_slot: fclk / 115_200,
+        }
+    }
+
+    /// Change the baud rate, by writing a precomputed `BRR` value. Sets the `USART_BRR` register.
+    fn set_brr(&mut self, brr: u32) {
+        let regs = &self.usart.regs;
+
+        regs.cr1.modify(|_, w| w.ue().clear_bit());
+        while regs.cr1.read().ue().bit_is_set() {}
+        regs.brr.write(|w| unsafe { w.bits(brr) });
+        regs.cr1.modify(|_, w| w.ue().set_bit());
+    }
+
+    /// Send a byte, and return the byte read back from the line. In half-duplex mode, the receiver
+    /// sees our own transmission, with any bits devices held low cleared.
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let regs = &self.usart.regs;
+
+        cfg_if! {
+            if #[cfg(feature = "f4")] {
+                // Clear any stale data and errors, by reading SR then DR.
+                regs.sr.read();
+                regs.dr.read();
+
+                regs.dr.write(|w| unsafe { w.dr().bits(byte as u16) });
+                while regs.sr.read().rxne().bit_is_clear() {}
+                regs.dr.read().dr().bits() as u8
+            } else {
+                regs.icr.write(|w| {
+                    w.orecf().set_bit();
+                    w.fecf().set_bit()
+                });
+                regs.rqr.write(|w| w.rxfrq().set_bit());
+
+                regs.tdr.write(|w| unsafe { w.tdr().bits(byte as u16) });
+                while regs.isr.read().rxne().bit_is_clear() {}
+                regs.rdr.read().rdr().bits() as u8
+            }
+        }
+    }
+
+    /// Disable the U[S]ART, and return the PAC register block. See `Usart::free`.
+    pub fn free(self, gate_clock: bool) -> R {
+        self.usart.free(gate_clock)
+    }
+}
+
+impl<R> OneWireBus for OneWireUart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    fn reset(&mut self) -> bool {
+        self.set_brr(self.brr_reset);
+        // At 9600 baud, the start bit and 4 low data bits make a 520μs reset pulse. A presence
+        // pulse pulls some of the high data bits low.
+        let result = self.transfer(0xF0);
+        self.set_brr(self.brr_slot);
+
+        result != 0xF0
+    }
+
+    fn touch_bit(&mut self, bit: bool) -> bool {
+        // At 115200 baud, the start bit is a 8.7μs low pulse; sending 0x00 holds the line low for
+        // 78μs.
+        let sent = if bit { 0xFF } else { 0x00 };
+        self.transfer(sent) == 0xFF
+    }
+}
+
+/// A 1-Wire bus bit-banged on a GPIO pin, timed with the SysTick delay. Configure the pin as
+/// an open-drain output, with a pull-up resistor (eg 4.7kΩ) to the bus supply.
+pub struct OneWireGpio {
+    pub pin: Pin,
+    pub delay: Delay,
+}
+
+impl OneWireGpio {
+    pub fn new(mut pin: Pin, delay: Delay) -> Self {
+        // Release the bus.
+        pin.set_high();
+        Self { pin, delay }
+    }
+
+    /// Return the pin and delay, eg to use them for other purposes.
+    pub fn free(self) -> (Pin, Delay) {
+        (self.pin, self.delay)
+    }
+}
+
+impl OneWireBus for OneWireGpio {
+    fn reset(&mut self) -> bool {
+        // AN126 timings H, I, and J.
+        let present = free(|_| {
+            self.pin.set_low();
+            self.delay.delay_us(480);
+            self.pin.set_high();
+            self.delay.delay_us(70);
+            self.pin.is_low()
+        });
+        self.delay.delay_us(410);
+
+        present
+    }
+
+    fn touch_bit(&mut self, bit: bool) -> bool {
+        free(|_| {
+            self.pin.set_low();
+
+            if bit {
+                // AN126 timings A, E, and F.
+                self.delay.delay_us(6);
+                self.pin.set_high();
+                self.delay.delay_us(9);
+                let result = self.pin.is_high();
+                self.delay.delay_us(55);
+                result
+            } else {
+                // AN126 timings C and D.
+                self.delay.delay_us(60);
+                self.pin.set_high();
+                self.delay.delay_us(10);
+                false
+            }
+        })
+    }
+}
@@ -0,0 +1,109 @@
+//! A panic handler that prints the panic message to a U[S]ART, then resets the MCU with
+//! `power::system_reset`, which runs any registered pre-reset hooks. Enabled with the
+//! `panic-uart` feature, which replaces crates like `panic-halt` or `panic-probe`.
+//!
+//! The U[S]ART is bound at runtime, once it's configured:
+//!
+//! `let uart = Usart::new(dp.USART2, 115_200, Default::default(), &clock_cfg);`
+//! `panic_uart::set_usart(&uart);`
+//!
+//! The U[S]ART's pins and configuration must stay as they are; `Usart::free` unbinds it.
+
+use core::{
+    cell::Cell,
+    fmt::{self, Write},
+    ops::Deref,
+    panic::PanicInfo,
+};
+
+#[cfg(not(feature = "critical-section"))]
+use cortex_m::interrupt::Mutex;
+#[cfg(feature = "critical-section")]
+use critical_section::Mutex;
+
+use cfg_if::cfg_if;
+
+use crate::{pac, usart::Usart, util::free};
+
+/// The address of the bound U[S]ART's register block.
+static PANIC_USART: Mutex<Cell<Option<usize>>> = Mutex::new(Cell::new(None));
+
+/// Print panic messages to this U[S]ART.
+pub fn set_usart<R>(usart: &Usart<R>)
+where
+    R: Deref<Target = pac::usart1::RegisterBlock>,
+{
+    let addr = &*usart.regs as *const pac::usart1::RegisterBlock as usize;
+    free(|cs| PANIC_USART.borrow(cs).set(Some(addr)));
+}
+
+/// Stop printing panic messages to a U[S]ART. The panic handler still resets the MCU.
+pub fn clear_usart() {
+    free(|cs| PANIC_USART.borrow(cs).set(None));
+}
+
+/// Unbind the U[S]ART with register block address `addr`, if it's bound. Called by `Usart::free`.
+pub(crate) fn release(addr: usize) {
+    free(|cs| {
+        let usart = PANIC_USART.borrow(cs);
+        if usart.get() == Some(addr) {
+            usart.set(None);
+        }
+    });
+}
+
+/// Blocking writes to the bound U[S]ART, directly through its registers.
+struct PanicWriter {
+    regs: &'static pac::usart1::RegisterBlock,
+}
+
+impl PanicWriter {
+    fn write_byte(&mut self, byte: u8) {
+        cfg_if! {
+            if #[cfg(feature = "f4")] {
+                while self.regs.sr.read().txe().bit_is_clear() {}
+                self.regs.dr.write(|w| unsafe { w.dr().bits(byte as u16) });
+            } else {
+                while self.regs.isr.read().txe().bit_is_clear() {}
+                self.regs.tdr.write(|w| unsafe { w.tdr().bits(byte as u16) });
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        #[cfg(feature = "f4")]
+        while self.regs.sr.read().tc().bit_is_clear() {}
+        #[cfg(not(feature = "f4"))]
+        while self.regs.isr.read().tc().bit_is_clear() {}
+    }
+}
+
+impl Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    if let Some(addr) = free(|cs| PANIC_USART.borrow(cs).get()) {
+        let regs = unsafe { &*(addr as *const pac::usart1::RegisterBlock) };
+
+        // The transmitter may have been disabled, eg by a half-duplex driver.
+        regs.cr1.modify(|_, w| {
+            w.te().set_bit();
+            w.ue().set_bit()
+        });
+
+        let mut writer = PanicWriter { regs };
+        let _ = write!(writer, "\r\n{}\r\n", info);
+        writer.flush();
+    }
+
+    crate::power::system_reset()
+}
@@ -0,0 +1,128 @@
+//! Software decimation of PDM (pulse density modulation) bitstreams from digital microphones into
+//! PCM samples, eg for data read with SAI's PDM interface. This is a CIC (cascaded
+//! integrator-comb) filter: cheap, since it needs no multiplications, but its passband droops, and
+//! it attenuates aliases less than a FIR filter. For voice, follow it with a low-pass FIR filter
+//! and a DC-blocking high-pass filter, eg using CMSIS-DSP, or decimate by a further 2 with a
+//! half-band FIR.
+//!
+//! Example, for one microphone of a pair, decimating a 3.072Mhz bitstream by 64 to 48kHz:
+//!
+//! ```
+//! let mut cic = CicDecimator::new(4, 64);
+//! // `frames` is data read from SAI_A, as bytes; with 2 microphones, each frame has a byte of each.
+//! let mut left = [0; 128];
+//! let len = pdm::deinterleave(&frames, 2, 0, &mut left);
+//! let mut pcm = [0; 16];
+//! let count = cic.process(&left[..len], &mut pcm);
+//! // Scale to ±1.
+//! let sample = pcm[0] as f32 / cic.full_scale() as f32;
+//! ```
+
+/// The most integrator and comb stages.
+const MAX_ORDER: usize = 5;
+
+/// A CIC decimation filter, converting a 1-bit PDM stream to PCM.
+pub struct CicDecimator {
+    order: usize,
+    decimation: u16,
+    integrators: [i32; MAX_ORDER],
+    combs: [i32; MAX_ORDER],
+    /// Input bits since the last output sample.
+    count: u16,
+}
+
+impl CicDecimator {
+    /// Create a filter with `order` stages (1 - 5; 4 or 5 are typical for audio), outputting one
+    /// sample per `decimation` input bits. The output's range, `full_scale`, is
+    /// `decimation ^ order`, which must fit in 31 bits.
+    pub fn new(order: u8, decimation: u16) -> Self {
+        assert!(
+            order >= 1 && order as usize <= MAX_ORDER,
+            "CIC order must be 1 - 5."
+        );
+        assert!(decimation >= 2, "CIC decimation must be at least 2.");
+        assert!(
+            (decimation as u64).pow(order as u32) < 1 << 31,
+            "CIC output must fit in 31 bits; reduce the order or decimation."
+        );
+
+        Self {
+            order: order as usize,
+            decimation,
+            integrators: [0; MAX_ORDER],
+            combs: [0; MAX_ORDER],
+            count: 0,
+        }
+    }
+
+    /// The output for a constant input of all 1s. Outputs range from `-full_scale()` to
+    /// `full_scale()`.
+    pub fn full_scale(&self) -> i32 {
+        (self.decimation as i32).pow(self.order as u32)
+    }
+
+    /// Filter PDM data, each byte holding 8 bits, the oldest in the most significant bit. Writes
+    /// one output sample to `pcm` per `decimation` bits, and returns the number written. Panics
+    /// if `pcm` is too short.
+    pub fn process(&mut self, pdm: &[u8], pcm: &mut [i32]) -> usize {
+        let mut written = 0;
+
+        for byte in pdm {
+            for i in (0..8).rev() {
+                if let Some(sample) = self.push_bit(byte >> i & 1 != 0) {
+                    pcm[written] = sample;
+                    written += 1;
+                }
+            }
+        }
+        written
+    }
+
+    /// Filter one PDM bit, and return an output sample every `decimation` bits.
+    pub fn push_bit(&mut self, bit: bool) -> Option<i32> {
+        // The integrators wrap; the combs undo this, as long as the output fits.
+        let mut acc = if bit { 1 } else { -1 };
+        for integrator in self.integrators[..self.order].iter_mut() {
+            *integrator = integrator.wrapping_add(acc);
+            acc = *integrator;
+        }
+
+        self.count += 1;
+        if self.count < self.decimation {
+            return None;
+        }
+        self.count = 0;
+
+        for comb in self.combs[..self.order].iter_mut() {
+            let prev = *comb;
+            *comb = acc;
+            acc = acc.wrapping_sub(prev);
+        }
+        Some(acc)
+    }
+
+    /// Clear the filter's state, eg after a gap in the input.
+    pub fn reset(&mut self) {
+        self.integrators = [0; MAX_ORDER];
+        self.combs = [0; MAX_ORDER];
+        self.count = 0;
+    }
+}
+
+/// Extract one microphone's PDM bytes from SAI PDM frames, each holding 1 byte per microphone.
+/// `num_mics` is the number per frame, as set with `NumPdmMics`, and `mic` the byte's index in the
+/// frame; see the RM's SAI PDM data format figure for which microphone is at each. Returns the
+/// number of bytes written to `out`, which is limited by its length.
+pub fn deinterleave(frames: &[u8], num_mics: u8, mic: u8, out: &mut [u8]) -> usize {
+    assert!(mic < num_mics, "Invalid PDM microphone.");
+
+    let mut written = 0;
+    for (o, byte) in out
+        .iter_mut()
+        .zip(frames.iter().skip(mic as usize).step_by(num_mics as usize))
+    {
+        *o = *byte;
+        written += 1;
+    }
+    written
+}
@@ -1,13 +1,177 @@
-//! Manage STM32H7 supply configuration.
+//! System resets, with pre-reset hooks, and entering the built-in bootloader. On H7, also
+//! manages supply configuration.
+//!
+//! Example, parking a motor before any reset made with this module:
+//!
+//! `power::add_pre_reset_hook(park_motor).unwrap();`
+//! `power::system_reset();`
+//!
+//! Example, entering the built-in bootloader from a reset state, on request: Call
+//! `power::check_bootloader_request(0)` first thing in `main`; then elsewhere,
+//! `power::reset_to_bootloader(0)`.
 
-use crate::pac::PWR;
+use core::cell::Cell;
 
+#[cfg(not(feature = "critical-section"))]
+use cortex_m::interrupt::Mutex;
+#[cfg(feature = "critical-section")]
+use critical_section::Mutex;
+
+use cortex_m::peripheral::{NVIC, SCB, SYST};
+
+use cfg_if::cfg_if;
+
+#[cfg(feature = "h7")]
+use crate::pac::{pwr, PWR};
+
+use crate::util::free;
+
+#[cfg(not(feature = "l412"))]
+use crate::rtc;
+
+/// The maximum number of pre-reset hooks that can be registered.
+pub const MAX_PRE_RESET_HOOKS: usize = 4;
+
+static PRE_RESET_HOOKS: Mutex<Cell<[Option<fn()>; MAX_PRE_RESET_HOOKS]>> =
+    Mutex::new(Cell::new([None; MAX_PRE_RESET_HOOKS]));
+
+cfg_if! {
+    if #[cfg(feature = "f3")] {
+        /// The address of the system memory, which contains the built-in bootloader. See AN2606,
+        /// Table 170: "Bootloader device-dependent parameters".
+        const SYSTEM_MEMORY: u32 = 0x1FFF_D800;
+    } else if #[cfg(feature = "l5")] {
+        const SYSTEM_MEMORY: u32 = 0x0BF9_0000;
+    } else if #[cfg(feature = "h7b3")] {
+        const SYSTEM_MEMORY: u32 = 0x1FF0_A000;
+    } else if #[cfg(feature = "h7")] {
+        const SYSTEM_MEMORY: u32 = 0x1FF0_9800;
+    } else {
+        const SYSTEM_MEMORY: u32 = 0x1FFF_0000;
+    }
+}
+
+/// The address of the `VTOR` register. (The `cortex-m` crate doesn't expose it on Armv6-M, eg G0.)
+const VTOR: *mut u32 = 0xE000_ED08 as *mut u32;
+
+/// Written to a backup register by `reset_to_bootloader`, to request entering a bootloader after
+/// the reset. A custom bootloader can check for this value too.
+pub const BOOTLOADER_REQUEST: u32 = 0xB007_10AD;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// `MAX_PRE_RESET_HOOKS` hooks are already registered.
+    TooManyHooks,
+}
+
+/// Register a function to run before resetting with `system_reset` or `enter_dfu`, eg to flush
+/// logs, or put motors in a safe state. Hooks run in the order they're registered, with
+/// interrupts disabled, so they can't rely on interrupt-driven drivers.
+pub fn add_pre_reset_hook(hook: fn()) -> Result<(), Error> {
+    free(|cs| {
+        let cell = PRE_RESET_HOOKS.borrow(cs);
+        let mut hooks = cell.get();
+
+        let slot = hooks
+            .iter_mut()
+            .find(|h| h.is_none())
+            .ok_or(Error::TooManyHooks)?;
+        *slot = Some(hook);
+
+        cell.set(hooks);
+        Ok(())
+    })
+}
+
+/// Remove all registered pre-reset hooks.
+pub fn clear_pre_reset_hooks() {
+    free(|cs| PRE_RESET_HOOKS.borrow(cs).set([None; MAX_PRE_RESET_HOOKS]));
+}
+
+/// Disable interrupts, and run the registered pre-reset hooks.
+fn prepare_reset() {
+    cortex_m::interrupt::disable();
+
+    let hooks = free(|cs| PRE_RESET_HOOKS.borrow(cs).get());
+    for hook in hooks.iter().flatten() {
+        hook();
+    }
+}
+
+/// Run the registered pre-reset hooks, then reset the MCU. Sets the `SCB_AIRCR` register,
+/// `SYSRESETREQ` field.
+pub fn system_reset() -> ! {
+    prepare_reset();
+    SCB::sys_reset()
+}
+
+/// Run the registered pre-reset hooks, then jump to the built-in (system memory) bootloader, eg
+/// to update firmware over USB DFU, or U[S]ART. Stops SysTick, and disables and un-pends all
+/// interrupts first, so the bootloader starts without handlers from this firmware firing.
+///
+/// The bootloader expects a state close to that after a reset. If it doesn't respond after
+/// clocks and peripherals have been configured, call this early in the program instead, eg after
+/// checking a flag set in a backup register before a `system_reset`.
+pub fn enter_dfu() -> ! {
+    prepare_reset();
+
+    unsafe {
+        (*SYST::PTR).csr.write(0);
+
+        // Armv6-M (G0) only has one of each of these registers.
+        cfg_if! {
+            if #[cfg(feature = "g0")] {
+                let nvic_regs = 1;
+            } else {
+                let nvic_regs = (*NVIC::PTR).icer.len();
+            }
+        }
+
+        for i in 0..nvic_regs {
+            (*NVIC::PTR).icer[i].write(0xffff_ffff);
+            (*NVIC::PTR).icpr[i].write(0xffff_ffff);
+        }
+
+        core::ptr::write_volatile(VTOR, SYSTEM_MEMORY);
+
+        // All interrupts are disabled in the NVIC; the bootloader enables the ones it uses.
+        cortex_m::interrupt::enable();
+
+        cortex_m::asm::bootload(SYSTEM_MEMORY as *const u32)
+    }
+}
+
+#[cfg(not(feature = "l412"))]
+/// Request the bootloader, by writing `BOOTLOADER_REQUEST` to backup register `reg`, then reset
+/// with `system_reset`. After the reset, `check_bootloader_request` enters the built-in
+/// bootloader; or a custom bootloader can check the register itself. This is more reliable than
+/// `enter_dfu` from a running program, since the bootloader starts from a reset state.
+pub fn reset_to_bootloader(reg: usize) -> ! {
+    rtc::enable_backup_access();
+    rtc::write_backup_reg(reg, BOOTLOADER_REQUEST);
+    system_reset()
+}
+
+#[cfg(not(feature = "l412"))]
+/// If backup register `reg` contains `BOOTLOADER_REQUEST`, clear it, and enter the built-in
+/// bootloader with `enter_dfu`; otherwise, return. Call this at the start of the program, before
+/// configuring clocks and peripherals. The register is cleared first, so resetting from the
+/// bootloader starts this firmware normally.
+pub fn check_bootloader_request(reg: usize) {
+    rtc::enable_backup_access();
+    if rtc::read_backup_reg(reg) == BOOTLOADER_REQUEST {
+        rtc::write_backup_reg(reg, 0);
+        enter_dfu();
+    }
+}
+
+#[cfg(feature = "h7")]
 #[derive(Clone, Copy)]
 #[repr(u8)]
 /// SMPS step-down converter voltage output level selection.
 /// This bit is used when both the LDO and SMPS step-down converter are enabled with SDEN and
 /// LDOEN enabled or when SDEXTHP is enabled. In this case SDLEVEL has to be written with a
-/// value different than 00 at system startup.
+/// value different than 00 at system startup. Ignored on variants without an SMPS, eg H743.
 pub enum VoltageLevel {
     /// 1.8V
     V1_8 = 0b01,
@@ -15,74 +179,96 @@ pub enum VoltageLevel {
     V2_5 = 0b10,
 }
 
+#[cfg(feature = "h7")]
 #[derive(Clone, Copy)]
-/// See RM0399, Table 32. Supply configuration control, for available configurations.
+/// See RM0399, Table 32. Supply configuration control, for available configurations. This must
+/// match how the board's supply pins are wired; see the datasheet's "Power supply scheme" figures.
 /// Sets the PWR_CR3 register, LDOEN, SDEN, SDEXTHP, SDLEVEL, and BYPASS fields.
 pub enum SupplyConfig {
-    /// Default configuration
+    /// Default configuration; the configuration after power-on. On variants with an SMPS, this
+    /// is the SMPS step-down converter supplying the LDO. On others, it's the LDO.
     Default,
     /// LDO supply
     Ldo,
+    #[cfg(not(any(feature = "h747cm4", feature = "h747cm7", feature = "h7b3")))]
+    /// LDO bypass; VCORE is supplied externally.
+    Bypass,
+    #[cfg(any(feature = "h747cm4", feature = "h747cm7", feature = "h7b3"))]
     /// Direct SMPS step-down converter supply
     DirectSmps,
+    #[cfg(any(feature = "h747cm4", feature = "h747cm7", feature = "h7b3"))]
     /// SMPS step-down converter supplies LDO
     SmpsStepdownLdo,
+    #[cfg(any(feature = "h747cm4", feature = "h747cm7", feature = "h7b3"))]
     /// SMPS step-down converter supplies External and LDO
     SmpsStepdownExtLdo,
+    #[cfg(any(feature = "h747cm4", feature = "h747cm7", feature = "h7b3"))]
     /// SMPS step-down converter supplies external and LDO Bypass
     SmpsStpdownExtBypass,
+    #[cfg(any(feature = "h747cm4", feature = "h747cm7", feature = "h7b3"))]
     /// SMPS step-down converter disabled and LDO Bypass
     SmpsStepdownDisabledBypass,
 }
 
+#[cfg(feature = "h7")]
 impl SupplyConfig {
-    /// Apply a given supply config. `voltage_level` only affects certain variants.
+    /// Apply a given supply config. `voltage_level` only affects certain variants. Blocks until
+    /// the supply is ready.
     pub fn setup(&self, pwr: &mut PWR, voltage_level: VoltageLevel) {
-        match self {
-            Self::Default => pwr.cr3.modify(|_, w| unsafe {
-                w.sdlevel().bits(voltage_level as u8);
-                w.sdexthp().clear_bit();
-                w.sden().set_bit();
-                w.ldoen().set_bit();
-                w.bypass().clear_bit()
-            }),
-            Self::Ldo => pwr.cr3.modify(|_, w| unsafe {
-                w.sden().clear_bit();
-                w.ldoen().set_bit();
-                w.bypass().clear_bit()
-            }),
-            Self::DirectSmps => pwr.cr3.modify(|_, w| unsafe {
-                w.sdexthp().clear_bit();
-                w.sden().set_bit();
-                w.ldoen().clear_bit();
-                w.bypass().clear_bit()
-            }),
-            Self::SmpsStepdownLdo => pwr.cr3.modify(|_, w| unsafe {
-                w.sdlevel().bits(voltage_level as u8);
-                w.sdexthp().clear_bit();
-                w.sden().set_bit();
-                w.ldoen().set_bit();
-                w.bypass().clear_bit()
-            }),
-            Self::SmpsStepdownExtLdo => pwr.cr3.modify(|_, w| unsafe {
-                w.sdlevel().bits(voltage_level as u8);
-                w.sdexthp().set_bit();
-                w.sden().set_bit();
-                w.ldoen().set_bit();
-                w.bypass().clear_bit()
-            }),
-            Self::SmpsStpdownExtBypass => pwr.cr3.modify(|_, w| unsafe {
-                w.sdlevel().bits(voltage_level as u8);
-                w.sdexthp().set_bit();
-                w.sden().set_bit();
-                w.ldoen().clear_bit();
-                w.bypass().set_bit()
-            }),
-            Self::SmpsStepdownDisabledBypass => pwr.cr3.modify(|_, w| unsafe {
-                w.sden().clear_bit();
-                w.ldoen().clear_bit();
-                w.bypass().set_bit()
-            }),
+        self.apply(pwr, voltage_level);
+    }
+
+    /// Apply the supply config, then wait for `PWR_CSR1` register, `ACTVOSRDY` field. This
+    /// configuration can only be written once after power-on; later writes are ignored. If it
+    /// doesn't match the board's wiring, the ready flag doesn't set, and this doesn't return.
+    pub(crate) fn apply(&self, pwr: &pwr::RegisterBlock, voltage_level: VoltageLevel) {
+        cfg_if! {
+            if #[cfg(any(feature = "h747cm4", feature = "h747cm7", feature = "h7b3"))] {
+                // (SMPS enabled, LDO enabled, SMPS supplies external, bypass, sets the level)
+                let (sden, ldoen, sdexthp, bypass, set_level) = match self {
+                    Self::Default => (true, true, false, false, true),
+                    Self::Ldo => (false, true, false, false, false),
+                    Self::DirectSmps => (true, false, false, false, false),
+                    Self::SmpsStepdownLdo => (true, true, false, false, true),
+                    Self::SmpsStepdownExtLdo => (true, true, true, false, true),
+                    Self::SmpsStpdownExtBypass => (true, false, true, true, true),
+                    Self::SmpsStepdownDisabledBypass => (false, false, false, true, false),
+                };
+
+                #[cfg(feature = "h7b3")]
+                pwr.cr3.modify(|_, w| unsafe {
+                    if set_level {
+                        w.smpslevel().bits(voltage_level as u8);
+                    }
+                    w.smpsexthp().bit(sdexthp);
+                    w.smpsen().bit(sden);
+                    w.ldoen().bit(ldoen);
+                    w.bypass().bit(bypass)
+                });
+
+                #[cfg(not(feature = "h7b3"))]
+                pwr.cr3.modify(|_, w| unsafe {
+                    if set_level {
+                        w.sdlevel().bits(voltage_level as u8);
+                    }
+                    w.sdexthp().bit(sdexthp);
+                    w.sden().bit(sden);
+                    w.ldoen().bit(ldoen);
+                    w.bypass().bit(bypass)
+                });
+            } else {
+                let _ = voltage_level;
+                let bypass = matches!(self, Self::Bypass);
+
+                pwr.cr3.modify(|_, w| {
+                    w.ldoen().bit(!bypass);
+                    w.bypass().bit(bypass)
+                });
+            }
         }
+
+        // The voltage scaling can't be changed until the supply configuration is applied; see
+        // RM0399, section 7.8.4: "PWR control status register 1 (PWR_CSR1)".
+        while pwr.csr1.read().actvosrdy().bit_is_clear() {}
     }
 }
@@ -0,0 +1,172 @@
+//! Counts pulses, and computes their rate over a moving window; eg for fan tachometers, flow
+//! sensors, and anemometers. The pulses are counted in hardware, by a timer in external clock
+//! mode (`Timer::enable_pulse_counter`), or by LPTIM1 in counter mode (`lptim::LpTimCounter`), so
+//! counting doesn't need an interrupt per pulse, and with LPTIM, continues in Stop mode.
+//!
+//! Sample the hardware count at a fixed period, eg from another timer's interrupt, or the RTC
+//! wakeup interrupt. Each sample records the pulses since the previous one, and the rate is the
+//! sum over the last `window` samples. Counter overflows are handled by wrapping arithmetic, so
+//! the counter must overflow at most once per sample period: fewer than 65_536 pulses, for a 16-bit
+//! counter.
+//!
+//! Example, counting a fan's tach output on TIM2's channel 1 input, sampled every 100ms, with a
+//! 1 second window:
+//!
+//! `let cfg = PulseCounterConfig { pulses_per_unit: 2., ..Default::default() };`
+//! `let mut tach = PulseCounter::new(cfg);`
+//! `timer.enable_pulse_counter(PulseInput::Ti1 { polarity: Polarity::ActiveHigh, filter: 8 });`
+//!
+//! Then, every 100ms: `timer.sample_pulse_counter(&mut tach);`, and to read the fan speed, in RPM:
+//! `let rpm = tach.unit_rate().unwrap_or(0.) * 60.;`
+
+/// The maximum number of samples in the rate window.
+const MAX_WINDOW: usize = 32;
+
+/// Initial configuration data for pulse counting.
+#[derive(Clone)]
+pub struct PulseCounterConfig {
+    /// The time between samples, in ms. Defaults to 100ms.
+    pub sample_period: u32,
+    /// The number of samples the rate is computed over; 1 to 32. Defaults to 10, for a window of
+    /// 1 second with the default sample period.
+    pub window: u8,
+    /// The number of pulses per unit measured, used by `unit_rate` and `units`; eg 2 pulses per
+    /// revolution for most PC fans, or 450 pulses per litre for a flow sensor rated at 7.5 pulses
+    /// per second per L/min. Defaults to 1.
+    pub pulses_per_unit: f32,
+}
+
+impl Default for PulseCounterConfig {
+    fn default() -> Self {
+        Self {
+            sample_period: 100,
+            window: 10,
+            pulses_per_unit: 1.,
+        }
+    }
+}
+
+/// Stores pulse counts, and computes their rate.
+pub struct PulseCounter {
+    pub cfg: PulseCounterConfig,
+    /// The hardware count at the previous sample. `None` until the first sample.
+    last_count: Option<u32>,
+    /// The number of pulses since the first sample.
+    total: u64,
+    /// Pulses counted in each of the most recent samples; a ring buffer.
+    history: [u32; MAX_WINDOW],
+    /// The index in `history` of the next sample.
+    next: usize,
+    /// The number of samples in `history`, up to the window size.
+    filled: usize,
+}
+
+impl PulseCounter {
+    /// Create a new pulse counter. Sample it with `Timer::sample_pulse_counter`, or
+    /// `LpTimCounter::sample`.
+    pub fn new(cfg: PulseCounterConfig) -> Self {
+        assert!(
+            cfg.window >= 1 && cfg.window as usize <= MAX_WINDOW,
+            "The pulse counter window must be 1 to 32 samples."
+        );
+        assert!(
+            cfg.sample_period > 0,
+            "The pulse counter sample period must be greater than 0."
+        );
+
+        Self {
+            cfg,
+            last_count: None,
+            total: 0,
+            history: [0; MAX_WINDOW],
+            next: 0,
+            filled: 0,
+        }
+    }
+
+    /// Clear the counts and rate window. The next sample sets the baseline count. Call this after
+    /// re-enabling the hardware counter, since that resets its count.
+    pub fn reset(&mut self) {
+        self.last_count = None;
+        self.total = 0;
+        self.next = 0;
+        self.filled = 0;
+    }
+
+    /// Record a sample of the hardware count. `count_max` is the count the counter wraps after,
+    /// and `scale` the number of pulses per count, eg from an input prescaler. The first sample
+    /// only sets the baseline. For use by the timer and LPTIM modules.
+    pub(crate) fn on_sample(&mut self, count: u32, count_max: u32, scale: u32) {
+        let last = match self.last_count.replace(count) {
+            Some(l) => l,
+            None => return,
+        };
+
+        // Handles a counter overflow since the last sample.
+        let counts = if count >= last {
+            count - last
+        } else {
+            (count_max - last) + count + 1
+        };
+        let pulses = counts.saturating_mul(scale);
+
+        self.total += pulses as u64;
+
+        let window = self.window();
+        self.history[self.next % window] = pulses;
+        self.next = (self.next + 1) % window;
+        self.filled = (self.filled + 1).min(window);
+    }
+
+    /// The window size, in samples. `cfg` is public, so this is clamped to the valid range.
+    fn window(&self) -> usize {
+        (self.cfg.window as usize).clamp(1, MAX_WINDOW)
+    }
+
+    /// The number of pulses since the first sample.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The number of units since the first sample, eg revolutions, or litres.
+    pub fn units(&self) -> f32 {
+        self.total as f32 / self.cfg.pulses_per_unit
+    }
+
+    /// The number of pulses in the most recent sample period. Returns `None` before two samples
+    /// have been taken.
+    pub fn last_sample(&self) -> Option<u32> {
+        if self.filled == 0 {
+            return None;
+        }
+        let window = self.window();
+        Some(self.history[(self.next + window - 1) % window])
+    }
+
+    /// The pulse rate, in Hz, over the window. Until the window fills, this is over the samples
+    /// taken so far, so it's noisier. Returns `None` before two samples have been taken.
+    pub fn rate(&self) -> Option<f32> {
+        if self.filled == 0 {
+            return None;
+        }
+
+        // The most recent `filled` samples; the ring buffer has no gaps, since it holds either
+        // the whole window, or the samples from the start.
+        let filled = self.filled.min(self.window());
+        let pulses: u64 = self.history[..filled].iter().map(|&p| p as u64).sum();
+        let time = filled as f32 * self.cfg.sample_period as f32 / 1_000.;
+
+        Some(pulses as f32 / time)
+    }
+
+    /// The rate in units per second, over the window, eg revolutions per second, or litres per
+    /// second. Returns `None` before two samples have been taken.
+    pub fn unit_rate(&self) -> Option<f32> {
+        Some(self.rate()? / self.cfg.pulses_per_unit)
+    }
+
+    /// Returns `true` if the window is full, so `rate` is over the whole window.
+    pub fn window_full(&self) -> bool {
+        self.filled >= self.window()
+    }
+}
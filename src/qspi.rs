@@ -6,9 +6,15 @@ use crate::{
     pac::{QUADSPI, RCC},
 };
 
-use core::ptr;
+use core::{ptr, slice};
 
-use cortex_m::interrupt::free;
+#[cfg(feature = "h7")]
+use cortex_m::peripheral::SCB;
+
+use crate::{
+    ext_flash::{Data, FlashBus},
+    util::free,
+};
 
 // todo: Status-polling mode.
 
@@ -109,6 +115,53 @@ impl Default for QspiConfig {
     }
 }
 
+/// Configuration of memory-mapped (XIP) reads, for `Qspi::enter_xip`. The defaults are for the
+/// Fast Read Quad I/O command (`0xEB`) on W25Q and similar flash, with 24-bit addresses.
+#[derive(Copy, Clone)]
+pub struct XipConfig {
+    /// The read instruction. Sets `QUADSPI_CCR` register, `INSTRUCTION` field.
+    pub instruction: u8,
+    /// The lines the instruction is sent on. Sets `QUADSPI_CCR` register, `IMODE` field.
+    pub instruction_mode: ProtocolMode,
+    /// The lines the address and alternate byte are sent on. Sets `QUADSPI_CCR` register,
+    /// `ADMODE` and `ABMODE` fields.
+    pub address_mode: ProtocolMode,
+    pub address_size: AddressSize,
+    /// The mode byte sent after the address, if the instruction takes one. Eg `0xFF` on W25Q to
+    /// stay out of continuous read mode, or `0x20` to enter it. Sets `QUADSPI_ABR` register.
+    pub alternate_byte: Option<u8>,
+    /// The lines data is received on. Sets `QUADSPI_CCR` register, `DMODE` field.
+    pub data_mode: ProtocolMode,
+    /// Dummy cycles between the address (or alternate byte) and data phases, from the flash's
+    /// datasheet for this instruction and clock speed. Sets `QUADSPI_CCR` register, `DCYC` field.
+    pub dummy_cycles: u8,
+    /// Send the instruction only for the first read, for flash in continuous read mode. This
+    /// requires an `alternate_byte` that keeps the flash in that mode. Sets `QUADSPI_CCR`
+    /// register, `SIOO` field.
+    pub send_instruction_once: bool,
+    /// Release chip select after this many clock cycles without an access, so the flash can
+    /// enter standby instead of waiting for a prefetch that may never be read. `None` holds chip
+    /// select low until the next access, for the lowest latency. Sets `QUADSPI_LPTR` register,
+    /// and `QUADSPI_CR` register, `TCEN` field.
+    pub timeout: Option<u16>,
+}
+
+impl Default for XipConfig {
+    fn default() -> Self {
+        Self {
+            instruction: 0xEB,
+            instruction_mode: ProtocolMode::Single,
+            address_mode: ProtocolMode::Quad,
+            address_size: AddressSize::A24,
+            alternate_byte: Some(0xFF),
+            data_mode: ProtocolMode::Quad,
+            dummy_cycles: 4,
+            send_instruction_once: false,
+            timeout: Some(256),
+        }
+    }
+}
+
 /// Interrupt events
 #[derive(Copy, Clone, PartialEq)]
 pub enum QspiInterrupt {
@@ -383,4 +436,183 @@ impl Qspi {
         let addr = MEM_MAPPED_BASE_ADDR as *const u32; // as const what?
         unsafe { core::ptr::read(addr.offset(offset)) }
     }
+
+    /// Enter memory-mapped mode, so code can execute from, and data be read from, the flash at
+    /// address `0x9000_0000`, as if it were internal memory. Aborts any operation in progress
+    /// first. Returns the flash contents as a slice; indirect and `FlashBus` commands can't run
+    /// until it's dropped, and `exit_xip` is called.
+    ///
+    /// The QUADSPI prefetches data after each read, holding chip select low. Writing to or
+    /// erasing the flash requires `exit_xip` first, to abort the prefetch. On H7, also call
+    /// `invalidate_xip_cache` after re-entering, and make sure the MPU doesn't let the CPU
+    /// speculatively read past the end of the flash: See AN4760, section 5.1.3.
+    pub fn enter_xip(&mut self, cfg: &XipConfig) -> &[u8] {
+        assert!(
+            cfg.dummy_cycles < 32,
+            "Dummy cycles must be between 0 and 31."
+        );
+        assert!(
+            !cfg.send_instruction_once || cfg.alternate_byte.is_some(),
+            "Sending the instruction once requires an alternate byte to keep the flash in \
+             continuous read mode."
+        );
+
+        self.abort();
+
+        // RM: "TCEN: This bit is valid only when memory-mapped mode (FMODE = 11) is selected.
+        // Activating this bit causes the chip select (nCS) to be released (and thus reduces
+        // consumption) if there has not been an access after a certain amount of time."
+        if let Some(timeout) = cfg.timeout {
+            self.regs
+                .lptr
+                .write(|w| unsafe { w.timeout().bits(timeout) });
+        }
+        self.regs
+            .cr
+            .modify(|_, w| w.tcen().bit(cfg.timeout.is_some()));
+
+        let abmode = match cfg.alternate_byte {
+            Some(byte) => {
+                self.regs
+                    .abr
+                    .write(|w| unsafe { w.alternate().bits(byte as u32) });
+                cfg.address_mode as u8
+            }
+            None => 0,
+        };
+
+        self.clear_interrupt(QspiInterrupt::TransferComplete);
+
+        // RM: In memory-mapped mode, the external Flash memory is seen as internal memory.
+        // Memory-mapped mode is entered by writing `FMODE` = 11 in `CCR`.
+        self.regs.ccr.write(|w| unsafe {
+            w.fmode().bits(FunctionalMode::MemoryMapped as u8);
+            w.sioo().bit(cfg.send_instruction_once);
+            w.ddrm().bit(self.cfg.data_mode as u8 != 0);
+            w.dmode().bits(cfg.data_mode as u8);
+            w.dcyc().bits(cfg.dummy_cycles);
+            w.absize().bits(0); // 8-bit alternate byte.
+            w.abmode().bits(abmode);
+            w.adsize().bits(cfg.address_size as u8);
+            w.admode().bits(cfg.address_mode as u8);
+            w.imode().bits(cfg.instruction_mode as u8);
+            w.instruction().bits(cfg.instruction)
+        });
+
+        unsafe {
+            slice::from_raw_parts(
+                MEM_MAPPED_BASE_ADDR as *const u8,
+                self.cfg.mem_size as usize * 1_024 * 1_024,
+            )
+        }
+    }
+
+    /// Leave memory-mapped mode, aborting any prefetch in progress, so indirect and `FlashBus`
+    /// commands can run. Don't call this from code executing from the flash.
+    pub fn exit_xip(&mut self) {
+        self.abort();
+        // Leave memory-mapped mode, so a read of the region doesn't start a new one.
+        self.regs
+            .ccr
+            .modify(|_, w| unsafe { w.fmode().bits(FunctionalMode::IndirectRead as u8) });
+        self.regs.cr.modify(|_, w| w.tcen().clear_bit());
+    }
+
+    /// Abort the operation in progress, including a memory-mapped prefetch, and wait for the
+    /// peripheral to be idle. Sets `QUADSPI_CR` register, `ABORT` field.
+    pub fn abort(&mut self) {
+        // RM: "ABORT: This bit is automatically reset once the abort is complete." In
+        // memory-mapped mode, BUSY stays set while the prefetch holds chip select; an abort is
+        // the only way to change modes.
+        self.regs.cr.modify(|_, w| w.abort().set_bit());
+        while self.regs.cr.read().abort().bit_is_set() {}
+        while self.is_busy() {}
+    }
+
+    #[cfg(feature = "h7")]
+    /// Invalidate the data cache for the memory-mapped region, so reads after re-entering XIP
+    /// see data written or erased since. Run this after `enter_xip`.
+    pub fn invalidate_xip_cache(&self, scb: &mut SCB) {
+        unsafe {
+            scb.invalidate_dcache_by_address(
+                MEM_MAPPED_BASE_ADDR,
+                self.cfg.mem_size as usize * 1_024 * 1_024,
+            );
+        }
+    }
+}
+
+impl FlashBus for Qspi {
+    type Error = QspiError;
+
+    /// Sends the command in single-line indirect mode. This overwrites the `CCR` register's
+    /// instruction, mode, address size, and dummy cycle settings from `QspiConfig`.
+    fn command(
+        &mut self,
+        instruction: u8,
+        address: Option<u32>,
+        address_bytes: u8,
+        dummy_bytes: u8,
+        data: Data,
+    ) -> Result<(), Self::Error> {
+        let single = ProtocolMode::Single as u8;
+
+        let (fmode, dmode, len) = match &data {
+            Data::None => (FunctionalMode::IndirectWrite, 0, 0),
+            Data::Write(buf) => (FunctionalMode::IndirectWrite, single, buf.len()),
+            Data::Read(buf) => (FunctionalMode::IndirectRead, single, buf.len()),
+        };
+
+        self.clear_interrupt(QspiInterrupt::TransferComplete);
+        while self.is_busy() {}
+
+        if len > 0 {
+            self.regs
+                .dlr
+                .write(|w| unsafe { w.dl().bits(len as u32 - 1) });
+        }
+
+        // RM: The command starts as soon as the last of the instruction (`CCR`), address (`AR`),
+        // or data (`DR`) registers it requires is written.
+        self.regs.ccr.write(|w| unsafe {
+            w.fmode().bits(fmode as u8);
+            w.dmode().bits(dmode);
+            w.dcyc().bits(dummy_bytes * 8);
+            w.abmode().bits(0);
+            w.adsize().bits(address_bytes.max(1) - 1);
+            w.admode().bits(if address.is_some() { single } else { 0 });
+            w.imode().bits(single);
+            w.instruction().bits(instruction)
+        });
+
+        if let Some(addr) = address {
+            self.regs.ar.write(|w| unsafe { w.address().bits(addr) });
+        }
+
+        let dr = self.regs.dr.as_ptr() as *mut u8;
+
+        match data {
+            Data::None => (),
+            Data::Write(buf) => {
+                for byte in buf {
+                    // The FIFO threshold flag is set while there's room in the FIFO.
+                    while self.regs.sr.read().ftf().bit_is_clear() {}
+                    unsafe { ptr::write_volatile(dr, *byte) };
+                }
+            }
+            Data::Read(buf) => {
+                for byte in buf {
+                    while self.regs.sr.read().flevel().bits() == 0 {}
+                    *byte = unsafe { ptr::read_volatile(dr) };
+                }
+            }
+        }
+
+        while self.regs.sr.read().tcf().bit_is_clear() {}
+        self.clear_interrupt(QspiInterrupt::TransferComplete);
+
+        while self.is_busy() {}
+
+        Ok(())
+    }
 }
@@ -0,0 +1,146 @@
+//! Decodes PWM from hobby RC receivers (eg for drones and rovers), using timer input capture.
+//! Each channel is a pulse, nominally 1000 - 2000μs long, repeated every 10 - 20ms. This module
+//! measures the pulses on up to 4 channels of a single general-purpose timer, and reports their
+//! width, a calibrated value, and whether the signal has been lost (failsafe).
+//!
+//! Example, using TIM3 channels 1 - 4:
+//!
+//! `let mut rc = RcInput::new([TimChannel::C1, TimChannel::C2, TimChannel::C3, TimChannel::C4], Default::default());`
+//! `timer.enable_rc_input(&rc);`
+//!
+//! Then, in the timer's interrupt handler: `timer.handle_rc_input(&mut rc);`, and to read values,
+//! eg: `let throttle = rc.value(2);`.
+
+use crate::timer::TimChannel;
+
+/// The time between timer overflows, in μs. The timer counts at 1Mhz, with an auto-reload value
+/// of 0xffff.
+const OVERFLOW_PERIOD: u32 = 65_536;
+
+/// Pulses shorter or longer than this, in μs, are treated as noise, and ignored.
+const VALID_PULSE_MIN: u16 = 500;
+const VALID_PULSE_MAX: u16 = 2_500;
+
+/// Initial configuration data for RC input.
+#[derive(Clone)]
+pub struct RcInputConfig {
+    /// The pulse width, in μs, that corresponds to a value of 0. Defaults to 1000.
+    pub min_pulse: u16,
+    /// The pulse width, in μs, that corresponds to a value of 1. Defaults to 2000.
+    pub max_pulse: u16,
+    /// If no valid pulse is received on a channel for this long, in μs, the channel is
+    /// considered lost, and failsafe is reported. Defaults to 100ms. Note that this is
+    /// only checked once per timer overflow, ie every 65.5ms.
+    pub timeout: u32,
+}
+
+impl Default for RcInputConfig {
+    fn default() -> Self {
+        Self {
+            min_pulse: 1_000,
+            max_pulse: 2_000,
+            timeout: 100_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct RcChannel {
+    channel: TimChannel,
+    /// Counter value at the rising edge, if we're waiting for the falling edge.
+    rising_edge: Option<u16>,
+    /// Width of the most recent valid pulse, in μs.
+    width: u16,
+    /// Time since the most recent valid pulse, in μs.
+    age: u32,
+}
+
+/// Stores the state of RC input decoding, for `N` timer channels.
+pub struct RcInput<const N: usize> {
+    channels: [RcChannel; N],
+    pub cfg: RcInputConfig,
+}
+
+impl<const N: usize> RcInput<N> {
+    /// Create a new RC input decoder for the given timer channels. Channel indexes used
+    /// in the other methods are positions in this array.
+    pub fn new(channels: [TimChannel; N], cfg: RcInputConfig) -> Self {
+        assert!(N <= 4, "RC input supports up to 4 channels per timer.");
+
+        Self {
+            channels: channels.map(|channel| RcChannel {
+                channel,
+                rising_edge: None,
+                width: 0,
+                age: u32::MAX,
+            }),
+            cfg,
+        }
+    }
+
+    /// The timer channel at a given index.
+    pub fn channel(&self, i: usize) -> TimChannel {
+        self.channels[i].channel
+    }
+
+    /// Record a capture on the channel at index `i`. `count` is the captured counter value. Returns
+    /// `true` if the next edge to capture is falling. For use in the timer module.
+    pub(crate) fn on_capture(&mut self, i: usize, count: u16) -> bool {
+        let ch = &mut self.channels[i];
+
+        match ch.rising_edge.take() {
+            None => {
+                ch.rising_edge = Some(count);
+                true
+            }
+            Some(rising) => {
+                // The counter wraps at 0xffff, so wrapping subtraction handles pulses that span an
+                // overflow.
+                let width = count.wrapping_sub(rising);
+
+                if (VALID_PULSE_MIN..=VALID_PULSE_MAX).contains(&width) {
+                    ch.width = width;
+                    ch.age = 0;
+                }
+                false
+            }
+        }
+    }
+
+    /// Record a timer overflow. For use in the timer module.
+    pub(crate) fn on_overflow(&mut self) {
+        for ch in &mut self.channels {
+            ch.age = ch.age.saturating_add(OVERFLOW_PERIOD);
+        }
+    }
+
+    /// Returns `true` if the channel at index `i` hasn't received a valid pulse within the timeout.
+    pub fn is_lost(&self, i: usize) -> bool {
+        self.channels[i].age > self.cfg.timeout
+    }
+
+    /// Returns `true` if any channel has lost its signal. Use this to trigger failsafe behavior.
+    pub fn failsafe(&self) -> bool {
+        (0..N).any(|i| self.is_lost(i))
+    }
+
+    /// The width of the most recent pulse on the channel at index `i`, in μs. Returns `None` if
+    /// the signal is lost.
+    pub fn pulse_width(&self, i: usize) -> Option<u16> {
+        if self.is_lost(i) {
+            None
+        } else {
+            Some(self.channels[i].width)
+        }
+    }
+
+    /// The calibrated value of the channel at index `i`: 0 at `min_pulse`, and 1 at `max_pulse`,
+    /// clamped to this range. Returns `None` if the signal is lost.
+    pub fn value(&self, i: usize) -> Option<f32> {
+        let width = self.pulse_width(i)? as f32;
+        let min = self.cfg.min_pulse as f32;
+        let max = self.cfg.max_pulse as f32;
+
+        Some(((width - min) / (max - min)).clamp(0., 1.))
+    }
+}
@@ -1,10 +1,10 @@
 //! Support for the Random Number Generator (RNG) peripheral.
 
-use cortex_m::interrupt::free;
+use crate::util::free;
 
 use crate::{
     pac::{RCC, RNG},
-    rcc_en_reset,
+    rcc_disable, rcc_en_reset,
 };
 
 use cfg_if::cfg_if;
@@ -40,6 +40,33 @@ impl Rng {
         Self { regs }
     }
 
+    /// Disable the RNG by clearing `CR` register, `RNGEN` field, and return the PAC register block.
+    /// If `gate_clock` is `true`, also disable its RCC peripheral clock.
+    pub fn free(self, gate_clock: bool) -> RNG {
+        #[cfg(feature = "l5")]
+        self.regs.rng_cr.modify(|_, w| w.rngen().clear_bit());
+        #[cfg(not(feature = "l5"))]
+        self.regs.cr.modify(|_, w| w.rngen().clear_bit());
+
+        if gate_clock {
+            free(|_| {
+                let rcc = unsafe { &(*RCC::ptr()) };
+
+                cfg_if! {
+                    if #[cfg(feature = "g0")] {
+                        rcc_disable!(ahb1, rng, rcc);
+                    } else if #[cfg(any(feature = "wb", feature = "wl"))] {
+                        rcc_disable!(ahb3, rng, rcc);
+                    } else {
+                        rcc_disable!(ahb2, rng, rcc);
+                    }
+                }
+            });
+        }
+
+        self.regs
+    }
+
     /// Load a random number from the data register
     pub fn read(&mut self) -> i32 {
         // When data is not ready (DRDY=”0”) RNG_DR returns zero.
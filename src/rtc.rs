@@ -7,7 +7,7 @@
 use crate::pac::{EXTI, PWR, RCC, RTC};
 use core::convert::TryInto;
 
-use cortex_m::interrupt::free;
+use crate::util::free;
 
 use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
 
@@ -114,63 +114,119 @@ impl Default for RtcConfig {
     }
 }
 
+/// Enable access to the backup domain: The RTC, backup registers, and `RCC_BDCR` register. Enables
+/// the PWR (and RTC APB) peripheral clocks, and sets `PWR_CR1` register, `DBP` field. This is
+/// called by `Rtc::new`; call it directly to use the backup registers without the RTC.
+pub fn enable_backup_access() {
+    // You must enable the `pwren()` bit before making RTC register writes, or they won't stay
+    // set. Enable the backup interface by setting PWREN
+
+    // Note that unlock other RCC enableing processes, there's no corresponding reset
+    // field here.
+
+    // See L4 RM, `Backup domain access` section.
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let pwr = unsafe { &(*PWR::ptr()) };
+
+        cfg_if! {
+            if #[cfg(any(feature = "f3", feature = "f4"))] {
+                rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+                pwr.cr.read(); // read to allow the pwr clock to enable
+                pwr.cr.modify(|_, w| w.dbp().set_bit());
+                while pwr.cr.read().dbp().bit_is_clear() {}
+            } else if #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "l412", feature = "wb", feature = "wl"))] {
+                // 1. Enable the power interface clock by setting the PWREN bits in the Section 6.4.18:
+                // APB1 peripheral clock enable register 1 (RCC_APB1ENR1)
+                #[cfg(not(any(feature = "wb", feature = "wl")))]
+                rcc.apb1enr1.modify(|_, w| {
+                    w.pwren().set_bit();
+                    w.rtcapben().set_bit()
+                });
+                #[cfg(any(feature = "wb", feature = "wl"))]
+                rcc.apb1enr1.modify(|_, w| w.rtcapben().set_bit());
+
+                rcc.apb1smenr1.modify(|_, w| w.rtcapbsmen().set_bit());  // In sleep and stop modes.
+                pwr.cr1.read(); // Read to allow the pwr clock to enable
+                // 2. Set the DBP bit in the Power control register 1 (PWR_CR1) to enable access to the
+                // backup domain
+                pwr.cr1.modify( | _, w| w.dbp().set_bit()); // Unlock the backup domain
+                while pwr.cr1.read().dbp().bit_is_clear() {}
+            } else if #[cfg(any(feature = "g0"))] {
+                rcc.apbenr1.modify(|_, w| {
+                    w.pwren().set_bit();
+                    w.rtcapben().set_bit()
+                });
+                rcc.apbsmenr1.modify(|_, w| w.rtcapbsmen().set_bit());  // In sleep and stop modes.
+                pwr.cr1.read();
+                pwr.cr1.modify( | _, w| w.dbp().set_bit());
+                while pwr.cr1.read().dbp().bit_is_clear() {}
+            } else { // eg h7
+                rcc.apb4enr.modify(|_, w| w.rtcapben().set_bit());
+                rcc.apb4lpenr.modify(|_, w| w.rtcapblpen().set_bit());  // In sleep and stop modes.
+                pwr.cr1.read(); // read to allow the pwr clock to enable
+                pwr.cr1.modify( | _, w| w.dbp().set_bit());
+                while pwr.cr1.read().dbp().bit_is_clear() {}
+            }
+        }
+    });
+}
+
+// The L412 PAC is missing the backup registers.
+#[cfg(not(feature = "l412"))]
+cfg_if! {
+    if #[cfg(feature = "g0")] {
+        /// The number of 32-bit backup registers.
+        pub const BACKUP_REG_COUNT: usize = 5;
+    } else if #[cfg(feature = "f3")] {
+        pub const BACKUP_REG_COUNT: usize = 16;
+    } else if #[cfg(any(feature = "f4", feature = "wb", feature = "wl"))] {
+        pub const BACKUP_REG_COUNT: usize = 20;
+    } else {
+        pub const BACKUP_REG_COUNT: usize = 32;
+    }
+}
+
+#[cfg(not(feature = "l412"))]
+/// A pointer to the first backup register. They're in the RTC peripheral on F3, F4, L4, WB, and
+/// H7, and in TAMP on L5, G0, G4, and WL.
+fn backup_reg_ptr() -> *mut u32 {
+    cfg_if! {
+        if #[cfg(any(feature = "l5", feature = "g0", feature = "g4", feature = "wl"))] {
+            unsafe { (*crate::pac::TAMP::ptr()).bkp0r.as_ptr() }
+        } else if #[cfg(any(feature = "wb", feature = "f3x4"))] {
+            unsafe { (*RTC::ptr()).bkp0r.as_ptr() }
+        } else {
+            unsafe { (*RTC::ptr()).bkpr[0].as_ptr() }
+        }
+    }
+}
+
+#[cfg(not(feature = "l412"))]
+/// Read a backup register. These keep their contents through resets, and in Standby mode, and are
+/// powered by VBAT when VDD is off. They're cleared on a backup domain reset, or a tamper event.
+/// `enable_backup_access` (or `Rtc::new`) must have been called.
+pub fn read_backup_reg(i: usize) -> u32 {
+    assert!(i < BACKUP_REG_COUNT, "Invalid backup register.");
+    unsafe { core::ptr::read_volatile(backup_reg_ptr().add(i)) }
+}
+
+#[cfg(not(feature = "l412"))]
+/// Write a backup register. `enable_backup_access` (or `Rtc::new`) must have been called.
+pub fn write_backup_reg(i: usize, val: u32) {
+    assert!(i < BACKUP_REG_COUNT, "Invalid backup register.");
+    unsafe { core::ptr::write_volatile(backup_reg_ptr().add(i), val) }
+}
+
 impl Rtc {
     /// Initialize the RTC, including configuration register writes.
     pub fn new(regs: RTC, config: RtcConfig) -> Self {
         let mut result = Self { regs, config };
 
-        // Enable the peripheral clock for communication
-        // You must enable the `pwren()` bit before making RTC register writes, or they won't stay
-        // set. Enable the backup interface by setting PWREN
-
-        // Note that unlock other RCC enableing processes, there's no corresponding reset
-        // field here.
+        enable_backup_access();
 
-        // See L4 RM, `Backup domain access` section.
         free(|_| {
             let rcc = unsafe { &(*RCC::ptr()) };
-            let mut pwr = unsafe { &(*PWR::ptr()) };
-
-            cfg_if! {
-                if #[cfg(any(feature = "f3", feature = "f4"))] {
-                    rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
-                    pwr.cr.read(); // read to allow the pwr clock to enable
-                    pwr.cr.modify(|_, w| w.dbp().set_bit());
-                    while pwr.cr.read().dbp().bit_is_clear() {}
-                } else if #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "l412", feature = "wb", feature = "wl"))] {
-                    // 1. Enable the power interface clock by setting the PWREN bits in the Section 6.4.18:
-                    // APB1 peripheral clock enable register 1 (RCC_APB1ENR1)
-                    #[cfg(not(any(feature = "wb", feature = "wl")))]
-                    rcc.apb1enr1.modify(|_, w| {
-                        w.pwren().set_bit();
-                        w.rtcapben().set_bit()
-                    });
-                    #[cfg(any(feature = "wb", feature = "wl"))]
-                    rcc.apb1enr1.modify(|_, w| w.rtcapben().set_bit());
-
-                    rcc.apb1smenr1.modify(|_, w| w.rtcapbsmen().set_bit());  // In sleep and stop modes.
-                    pwr.cr1.read(); // Read to allow the pwr clock to enable
-                    // 2. Set the DBP bit in the Power control register 1 (PWR_CR1) to enable access to the
-                    // backup domain
-                    pwr.cr1.modify( | _, w| w.dbp().set_bit()); // Unlock the backup domain
-                    while pwr.cr1.read().dbp().bit_is_clear() {}
-                } else if #[cfg(any(feature = "g0"))] {
-                    rcc.apbenr1.modify(|_, w| {
-                        w.pwren().set_bit();
-                        w.rtcapben().set_bit()
-                    });
-                    rcc.apbsmenr1.modify(|_, w| w.rtcapbsmen().set_bit());  // In sleep and stop modes.
-                    pwr.cr1.read();
-                    pwr.cr1.modify( | _, w| w.dbp().set_bit());
-                    while pwr.cr1.read().dbp().bit_is_clear() {}
-                } else { // eg h7
-                    rcc.apb4enr.modify(|_, w| w.rtcapben().set_bit());
-                    rcc.apb4lpenr.modify(|_, w| w.rtcapblpen().set_bit());  // In sleep and stop modes.
-                    pwr.cr1.read(); // read to allow the pwr clock to enable
-                    pwr.cr1.modify( | _, w| w.dbp().set_bit());
-                    while pwr.cr1.read().dbp().bit_is_clear() {}
-                }
-            }
 
             // Set up the LSI or LSE as required.
             match config.clock_source {
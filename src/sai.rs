@@ -1,11 +1,21 @@
 //! Serial audio interface (SAI) support. Used for I2S, PCM/DSP, TDM, AC'97 etc.
 //! See L443 Reference Manual, section 41. H743 FM, section 51.
+//!
+//! PDM microphones: On H7, L5, and WB, SAI_A's PDM interface reads up to 4 pairs of
+//! microphones (2 on L5), each pair sharing a data line, and one of the CK1 - CK4 clock lines.
+//! Use `SaiConfig::pdm_mic_preset`, and `SaiConfig::set_pdm_clock` to set the bitstream clock.
+//! Each TDM frame read from SAI_A holds 8 bits of PDM data per microphone; decimate these to PCM
+//! in software, eg with `pdm::CicDecimator`, followed by a low-pass FIR filter, or ST's PDM2PCM
+//! library. Alternatively, on parts with DFSDM, connect the microphones to it, for hardware
+//! decimation filters; see the `dfsdm` module.
 
 use core::ops::Deref;
 
-use cortex_m::interrupt::free;
-
-use crate::{clocks::Clocks, pac::RCC, util::RccPeriph};
+use crate::{
+    clocks::Clocks,
+    pac::RCC,
+    util::{free, RccPeriph},
+};
 
 #[cfg(not(feature = "h7"))]
 use crate::pac::sai1 as sai;
@@ -32,6 +42,9 @@ use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::DmaInput;
 
+#[cfg(not(feature = "l4"))]
+use cfg_if::cfg_if;
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 /// Select Master or Slave mode. Sets xCR1 register, MODE field.
@@ -146,6 +159,13 @@ pub enum NumPdmMics {
     N7 = 0b11,
 }
 
+impl NumPdmMics {
+    /// The number of microphones.
+    pub fn value(&self) -> u8 {
+        (*self as u8 + 1) * 2
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 /// FIFO threshold. Affects xCR2 reg, FTH field. Affects when SAI interrupts, and
@@ -398,8 +418,9 @@ pub struct SaiConfig {
     pub pdm_mode: bool,
     /// The number of connected PDM mics, if applicable. Defualts to 2.
     pub num_pdm_mics: NumPdmMics,
-    /// Which PDM CK line to enable. Must be 1-4. Defaults to 1. (CK1 in User manuals)
-    pub pdm_clock_used: u8,
+    /// Which PDM clock lines to enable, as a bit mask: bit 0 is CK1, through bit 3 for CK4 (CK2 on
+    /// L5). Pairs of microphones can share a clock line. Defaults to `0b0001`, ie CK1 only.
+    pub pdm_clocks: u8,
     /// Master clock divider. Divides the kernel clock input. Defaults to 0, for no division.
     pub mckdiv: u8,
 }
@@ -431,7 +452,7 @@ impl Default for SaiConfig {
             fifo_thresh: FifoThresh::T1_4,
             pdm_mode: false,
             num_pdm_mics: NumPdmMics::N2,
-            pdm_clock_used: 1,
+            pdm_clocks: 0b0001,
             mckdiv: 0,
         }
     }
@@ -510,10 +531,10 @@ impl SaiConfig {
 
     /// Default configuration for PDM microphones. See H743 RM, Table 422. TDM settings.
     /// See table 423 for how to configure Frame Length, and number of slots.
-    /// This default configures for 48kHz sample rate, assuming 3.072Mhz SAI clock,
-    /// and 1 slots of 16 bits per frame. If using something else, see Table 423, and
-    /// modify as required.
-    pub fn pdm_mic_preset(num_mics: NumPdmMics, clock_used: u8) -> Self {
+    /// This uses one 16-bit slot per pair of microphones, so each frame holds 8 bits of each. Set
+    /// the bitstream clock with `set_pdm_clock`. `clocks` is the mask of CK lines to enable, as in
+    /// `pdm_clocks`.
+    pub fn pdm_mic_preset(num_mics: NumPdmMics, clocks: u8) -> Self {
         Self {
             // These first settings (up to `pdm_mode1) are taken directly from Table 422.
             //Mode must be MASTER receiver
@@ -537,24 +558,38 @@ impl SaiConfig {
             first_bit_offset: 0,
             master_clock: MasterClock::NotUsed,
 
-            // These next 3 settings may depend on master clock speed, sample rate, and number
-            // of mics. See table 423.
-            // This is currently set for 2 mics, 1 slot of 16-bits per frame.
-            // 3.072Mhz SAI freq.
-            // RM: FRL = (16 x (MICNBR + 1)) - 1
-            frame_length: (16 * (num_mics as u8 as u16 + 1)) - 1,
-            // todo: Make these more flexible instead of hard-coded
+            // These next settings depend on the number of mics. See table 423.
+            // RM: FRL = (16 x (MICNBR + 1)) - 1. (`frame_length` is FRL + 1)
+            frame_length: 16 * (num_mics as u8 as u16 + 1),
             datasize: DataSize::S16,
-            num_slots: 1,
+            slotsize: SlotSize::S16,
+            num_slots: num_mics as u8 + 1,
             fifo_thresh: FifoThresh::Empty,
 
             pdm_mode: true,
             num_pdm_mics: num_mics,
-            pdm_clock_used: clock_used,
+            pdm_clocks: clocks,
             ..Default::default()
         }
     }
 
+    #[cfg(not(feature = "l4"))]
+    /// Set `mckdiv` for a PDM bitstream clock, in Hz, from the SAI kernel clock, in Hz. Call
+    /// this after setting `num_pdm_mics`. The bit clock is the bitstream clock times the number
+    /// of microphones, and `mckdiv` divides the kernel clock into it. Returns the bitstream clock
+    /// achieved. Eg, 64 times the sample rate, for decimation by 64: 3.072Mhz for 48kHz.
+    pub fn set_pdm_clock(&mut self, kernel_clk: u32, pdm_clk: u32) -> u32 {
+        let sck = pdm_clk * self.num_pdm_mics.value() as u32;
+        let div = (kernel_clk + sck / 2) / sck;
+        assert!(
+            div >= 1 && div <= 0b11_1111,
+            "The SAI kernel clock can't be divided to this PDM clock."
+        );
+
+        self.mckdiv = div as u8;
+        kernel_clk / div / self.num_pdm_mics.value() as u32
+    }
+
     /// Default configuration for AC'97
     pub fn ac97_preset() -> Self {
         Self {
@@ -818,19 +853,35 @@ where
         // 2. Configure the PDM interface as follows:
         #[cfg(not(feature = "l4"))]
         if config_a.pdm_mode {
-            assert!(config_a.pdm_clock_used <= 4 && config_a.pdm_clock_used >= 1);
+            cfg_if! {
+                if #[cfg(feature = "l5")] {
+                    assert!(
+                        config_a.pdm_clocks != 0 && config_a.pdm_clocks <= 0b11,
+                        "PDM clocks must be a non-zero mask of CK1 and CK2."
+                    );
+                    assert!(
+                        config_a.num_pdm_mics as u8 <= NumPdmMics::N4 as u8,
+                        "L5 supports up to 4 PDM microphones."
+                    );
+                } else {
+                    assert!(
+                        config_a.pdm_clocks != 0 && config_a.pdm_clocks <= 0b1111,
+                        "PDM clocks must be a non-zero mask of CK1 - CK4."
+                    );
+                }
+            }
 
             regs.pdmcr.modify(|_, w| unsafe {
                 // a) Define the number of digital microphones via MICNBR.
                 w.micnbr().bits(config_a.num_pdm_mics as u8);
                 // b) Enable the bitstream clock needed in the application by setting the corresponding
                 // bits on CKEN to 1.
-                w.cken1().bit(config_a.pdm_clock_used == 1);
-                w.cken2().bit(config_a.pdm_clock_used == 2);
+                w.cken1().bit(config_a.pdm_clocks & 0b0001 != 0);
+                w.cken2().bit(config_a.pdm_clocks & 0b0010 != 0);
                 #[cfg(not(feature = "l5"))]
-                w.cken3().bit(config_a.pdm_clock_used == 3);
+                w.cken3().bit(config_a.pdm_clocks & 0b0100 != 0);
                 #[cfg(not(feature = "l5"))]
-                w.cken4().bit(config_a.pdm_clock_used == 4);
+                w.cken4().bit(config_a.pdm_clocks & 0b1000 != 0);
                 // 3. Enable the PDM interface, via PDMEN bit.
                 w.pdmen().set_bit()
             })
@@ -913,6 +964,21 @@ It can generate an interrupt if WCKCFGIE bit is set in SAI_xIM register");
         }
     }
 
+    #[cfg(not(feature = "l4"))]
+    /// Delay a PDM microphone's data, by 0 - 7 bitstream clock periods, eg to align microphones
+    /// for beamforming. `mic` is 0 - 7: 0 is the left microphone of the first pair (M1L), 1 the
+    /// right (M1R), 2 M2L etc. Can be set while receiving. Sets the `PDMDLY` register.
+    pub fn set_pdm_delay(&mut self, mic: u8, delay: u8) {
+        assert!(mic < self.config_a.num_pdm_mics.value(), "Invalid PDM microphone.");
+        assert!(delay <= 7, "PDM delays must be 0 - 7 clock periods.");
+
+        // Each microphone has a 3-bit field, at 4-bit intervals.
+        let shift = mic * 4;
+        self.regs.pdmdly.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b111 << shift) | (delay as u32) << shift)
+        });
+    }
+
     /// Read a word of data.
     pub fn read(&self, channel: SaiChannel) -> i32 {
         match channel {
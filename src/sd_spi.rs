@@ -0,0 +1,566 @@
+//! Support for SD cards in SPI mode, for MCUs without an SDMMC peripheral, or boards that wire
+//! the card to an SPI bus. Supports SDSC (v1 and v2), SDHC, and SDXC cards. Commands and data
+//! blocks are CRC-protected: CRC7 on commands, and CRC16 on data, with the card's CRC checking
+//! enabled.
+//!
+//! `SdSpi` implements `BlockDevice`. Transfers of more than one block use multi-block commands
+//! (CMD18 and CMD25, with ACMD23 pre-erase); see `read_multiple` and `write_multiple`. Example, using SPI1, with PA4 as chip select:
+//!
+//! `let spi = Spi::new(dp.SPI1, SpiConfig::default(), BaudRate::Div256);`
+//! `let mut sd = SdSpi::new(spi, Pin::new(Port::A, 4, PinMode::Output));`
+//! `sd.init(BaudRate::Div256, BaudRate::Div4)?;`
+//! `let mut blocks = [[0; BLOCK_SIZE]; 1];`
+//! `sd.read_blocks(0, &mut blocks)?;`
+//!
+//! See the SD Association's Physical Layer Simplified Specification, section 7: SPI Mode.
+
+use core::ops::Deref;
+
+use crate::{
+    block_device::{Block, BlockDevice, BLOCK_SIZE},
+    gpio::Pin,
+    pac,
+    spi::{self, BaudRate, Spi},
+    util::RccPeriph,
+};
+
+/// Commands used in SPI mode.
+mod cmd {
+    pub const GO_IDLE_STATE: u8 = 0;
+    pub const SEND_IF_COND: u8 = 8;
+    pub const SEND_CSD: u8 = 9;
+    pub const STOP_TRANSMISSION: u8 = 12;
+    pub const SET_BLOCKLEN: u8 = 16;
+    pub const READ_SINGLE_BLOCK: u8 = 17;
+    pub const READ_MULTIPLE_BLOCK: u8 = 18;
+    pub const WRITE_BLOCK: u8 = 24;
+    pub const WRITE_MULTIPLE_BLOCK: u8 = 25;
+    pub const APP_CMD: u8 = 55;
+    pub const READ_OCR: u8 = 58;
+    pub const CRC_ON_OFF: u8 = 59;
+    /// An application command; send `APP_CMD` first.
+    pub const SD_SEND_OP_COND: u8 = 41;
+    /// An application command; send `APP_CMD` first.
+    pub const SET_WR_BLK_ERASE_COUNT: u8 = 23;
+}
+
+/// R1 response: The card is in the idle state, running its initialization.
+const R1_IDLE: u8 = 0x01;
+/// R1 response: The command isn't supported, eg `SEND_IF_COND` on a v1 card.
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+
+/// Sent before a data block, in either direction.
+const DATA_START_TOKEN: u8 = 0xfe;
+/// Sent before each block of a multiple block write.
+const MULTI_WRITE_START_TOKEN: u8 = 0xfc;
+/// Ends a multiple block write.
+const STOP_TRAN_TOKEN: u8 = 0xfd;
+/// The data response token's status bits, after a block is written.
+const DATA_ACCEPTED: u8 = 0b0_0101;
+const DATA_CRC_ERROR: u8 = 0b0_1011;
+
+/// `SEND_IF_COND` argument: 2.7 - 3.6V supply, and the check pattern `0xAA`.
+const IF_COND_ARG: u32 = 0x1aa;
+/// `SD_SEND_OP_COND` argument, and OCR bit: The host supports, or card is, high capacity.
+const HCS: u32 = 1 << 30;
+
+// Timeouts, in bytes clocked: The card sends 0xFF while not ready.
+/// The number of bytes to wait for an R1 response. The spec's maximum is 8.
+const RESPONSE_TIMEOUT: u32 = 16;
+/// The number of `SD_SEND_OP_COND` attempts: The spec allows up to 1s for initialization.
+const INIT_TIMEOUT: u32 = 10_000;
+/// The number of bytes to wait for a data start token, or for the card to finish a write.
+const BUSY_TIMEOUT: u32 = 500_000;
+
+#[derive(Clone, Copy, Debug)]
+/// Errors from SD card operations.
+pub enum SdSpiError {
+    /// An error from the SPI peripheral.
+    Spi(spi::Error),
+    /// The card didn't respond, or didn't finish in time; eg no card is inserted.
+    Timeout,
+    /// The card doesn't support our voltage range, or responded unexpectedly during
+    /// initialization.
+    UnsupportedCard,
+    /// The card responded to a command with an error. Contains its R1 response.
+    Command(u8),
+    /// A data block's CRC didn't match, in either direction.
+    Crc,
+    /// The card rejected a written block, or sent an error token instead of a read block.
+    Data(u8),
+    /// `init` hasn't run successfully.
+    NotInitialized,
+}
+
+impl From<spi::Error> for SdSpiError {
+    fn from(e: spi::Error) -> Self {
+        Self::Spi(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The type of card, determined during initialization.
+pub enum CardType {
+    /// Standard capacity, version 1: Up to 2GB, byte addressed.
+    SdV1,
+    /// Standard capacity, version 2: Up to 2GB, byte addressed.
+    SdV2,
+    /// High or extended capacity (SDHC or SDXC): Block addressed.
+    SdHc,
+}
+
+/// An SD card on a plain SPI bus, with a GPIO pin, configured as an output, as chip select. The
+/// SPI peripheral must be in mode 0, with 8-bit words.
+pub struct SdSpi<R> {
+    pub spi: Spi<R>,
+    pub cs: Pin,
+    card_type: Option<CardType>,
+}
+
+impl<R> SdSpi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    /// Sets the chip select pin high (inactive). Run `init` before reading or writing.
+    pub fn new(spi: Spi<R>, mut cs: Pin) -> Self {
+        cs.set_high();
+        Self {
+            spi,
+            cs,
+            card_type: None,
+        }
+    }
+
+    /// Initialize the card, and put it in SPI mode. `init_baud` must give an SPI clock of 100 -
+    /// 400kHz; afterwards, the SPI is reclocked to `baud`, up to 25MHz. Run this after inserting
+    /// a card. See the spec, Figure 7-2: "SPI Mode Initialization Flow".
+    pub fn init(&mut self, init_baud: BaudRate, baud: BaudRate) -> Result<CardType, SdSpiError> {
+        self.card_type = None;
+        self.spi.reclock(init_baud);
+
+        // Spec: "the host shall supply at least 74 SD clocks" with CS high, for the card to power
+        // up.
+        self.cs.set_high();
+        for _ in 0..10 {
+            self.transfer_byte(0xff)?;
+        }
+
+        // CMD0 with CS low puts the card in SPI mode.
+        let mut r1 = 0xff;
+        for _ in 0..10 {
+            r1 = self.command(cmd::GO_IDLE_STATE, 0)?;
+            if r1 == R1_IDLE {
+                break;
+            }
+        }
+        if r1 != R1_IDLE {
+            return Err(SdSpiError::Timeout);
+        }
+
+        // Enable CRC checking, so the card rejects corrupted commands and data.
+        self.command_checked(cmd::CRC_ON_OFF, 1)?;
+
+        // v1 cards don't support CMD8.
+        let v2 = {
+            self.cs.set_low();
+            let r1 = self.send_command(cmd::SEND_IF_COND, IF_COND_ARG);
+            let r7 = match r1 {
+                Ok(r1) if r1 & R1_ILLEGAL_COMMAND == 0 => self.read_u32().map(Some),
+                Ok(_) => Ok(None),
+                Err(e) => Err(e),
+            };
+            self.deselect()?;
+
+            match r7? {
+                Some(r7) if r7 & 0xfff == IF_COND_ARG => true,
+                Some(_) => return Err(SdSpiError::UnsupportedCard),
+                None => false,
+            }
+        };
+
+        let arg = if v2 { HCS } else { 0 };
+        let mut ready = false;
+        for _ in 0..INIT_TIMEOUT {
+            self.command_checked(cmd::APP_CMD, 0)?;
+            let r1 = self.command(cmd::SD_SEND_OP_COND, arg)?;
+            if r1 == 0 {
+                ready = true;
+                break;
+            }
+            if r1 != R1_IDLE {
+                return Err(SdSpiError::Command(r1));
+            }
+        }
+        if !ready {
+            return Err(SdSpiError::Timeout);
+        }
+
+        let card_type = if v2 {
+            self.cs.set_low();
+            let ocr = self.send_command(cmd::READ_OCR, 0).and_then(|r1| {
+                if r1 != 0 {
+                    return Err(SdSpiError::Command(r1));
+                }
+                self.read_u32()
+            });
+            self.deselect()?;
+
+            if ocr? & HCS != 0 {
+                CardType::SdHc
+            } else {
+                CardType::SdV2
+            }
+        } else {
+            CardType::SdV1
+        };
+
+        if card_type != CardType::SdHc {
+            // Standard capacity cards may default to a different block length.
+            self.command_checked(cmd::SET_BLOCKLEN, BLOCK_SIZE as u32)?;
+        }
+
+        self.spi.reclock(baud);
+        self.card_type = Some(card_type);
+
+        Ok(card_type)
+    }
+
+    /// The card type, or `None` if `init` hasn't run successfully.
+    pub fn card_type(&self) -> Option<CardType> {
+        self.card_type
+    }
+
+    /// Read the card's 16-byte CSD (Card-Specific Data) register.
+    pub fn read_csd(&mut self) -> Result<[u8; 16], SdSpiError> {
+        let mut csd = [0; 16];
+
+        self.cs.set_low();
+        let result = self.send_command(cmd::SEND_CSD, 0).and_then(|r1| {
+            if r1 != 0 {
+                return Err(SdSpiError::Command(r1));
+            }
+            self.read_data(&mut csd)
+        });
+        self.deselect()?;
+
+        result.map(|_| csd)
+    }
+
+    /// Read one block.
+    pub fn read_block(&mut self, block: u32, buf: &mut Block) -> Result<(), SdSpiError> {
+        let addr = self.address(block)?;
+
+        self.cs.set_low();
+        let result = self
+            .send_command(cmd::READ_SINGLE_BLOCK, addr)
+            .and_then(|r1| {
+                if r1 != 0 {
+                    return Err(SdSpiError::Command(r1));
+                }
+                self.read_data(buf)
+            });
+        self.deselect()?;
+
+        result
+    }
+
+    /// Write one block, and wait for the card to finish programming it.
+    pub fn write_block(&mut self, block: u32, data: &Block) -> Result<(), SdSpiError> {
+        let addr = self.address(block)?;
+
+        self.cs.set_low();
+        let result = self.send_command(cmd::WRITE_BLOCK, addr).and_then(|r1| {
+            if r1 != 0 {
+                return Err(SdSpiError::Command(r1));
+            }
+            self.write_data(DATA_START_TOKEN, data)?;
+            self.wait_not_busy()
+        });
+        self.deselect()?;
+
+        result
+    }
+
+    /// Read consecutive blocks with one command, which is faster than reading them one at a time.
+    pub fn read_multiple(&mut self, start: u32, blocks: &mut [Block]) -> Result<(), SdSpiError> {
+        let addr = self.address(start)?;
+
+        self.cs.set_low();
+        let result = self
+            .send_command(cmd::READ_MULTIPLE_BLOCK, addr)
+            .and_then(|r1| {
+                if r1 != 0 {
+                    return Err(SdSpiError::Command(r1));
+                }
+                for block in blocks.iter_mut() {
+                    self.read_data(block)?;
+                }
+                Ok(())
+            });
+        // Stop the transfer even if a block failed, so the card returns to the transfer state.
+        let stop = self.stop_transmission();
+        self.deselect()?;
+
+        result.and(stop)
+    }
+
+    /// Write consecutive blocks with one command. The card is told the number of blocks first,
+    /// so it can erase them in advance; this is much faster than writing them one at a time, eg
+    /// for data logging.
+    pub fn write_multiple(&mut self, start: u32, blocks: &[Block]) -> Result<(), SdSpiError> {
+        let addr = self.address(start)?;
+
+        // Pre-erase. Spec: "Setting a number of write blocks to be pre-erased (ACMD23) will make
+        // a following Multiple Block Write operation faster compared to the same operation without
+        // preceding ACMD23."
+        self.command_checked(cmd::APP_CMD, 0)?;
+        self.command_checked(cmd::SET_WR_BLK_ERASE_COUNT, blocks.len() as u32)?;
+
+        self.cs.set_low();
+        let result = self
+            .send_command(cmd::WRITE_MULTIPLE_BLOCK, addr)
+            .and_then(|r1| {
+                if r1 != 0 {
+                    return Err(SdSpiError::Command(r1));
+                }
+                for block in blocks {
+                    self.write_data(MULTI_WRITE_START_TOKEN, block)?;
+                    self.wait_not_busy()?;
+                }
+                Ok(())
+            });
+
+        // End the transfer even if a block was rejected. The card is busy after the stop token,
+        // following one byte.
+        let stop = self
+            .spi
+            .write(&[STOP_TRAN_TOKEN])
+            .map_err(SdSpiError::from)
+            .and_then(|_| {
+                self.transfer_byte(0xff)?;
+                self.wait_not_busy()
+            });
+        self.deselect()?;
+
+        result.and(stop)
+    }
+
+    /// The command argument addressing a block: The block number on SDHC cards, and the byte
+    /// address on standard capacity ones.
+    fn address(&self, block: u32) -> Result<u32, SdSpiError> {
+        match self.card_type {
+            Some(CardType::SdHc) => Ok(block),
+            Some(_) => Ok(block * BLOCK_SIZE as u32),
+            None => Err(SdSpiError::NotInitialized),
+        }
+    }
+
+    /// Send a command with no further data, returning its R1 response.
+    fn command(&mut self, index: u8, arg: u32) -> Result<u8, SdSpiError> {
+        self.cs.set_low();
+        let result = self.send_command(index, arg);
+        self.deselect()?;
+        result
+    }
+
+    /// Send a command, and return an error if its R1 response has any error bits set.
+    fn command_checked(&mut self, index: u8, arg: u32) -> Result<(), SdSpiError> {
+        match self.command(index, arg)? {
+            0 | R1_IDLE => Ok(()),
+            r1 => Err(SdSpiError::Command(r1)),
+        }
+    }
+
+    /// Send a command frame, with CS already low, and return its R1 response.
+    fn send_command(&mut self, index: u8, arg: u32) -> Result<u8, SdSpiError> {
+        self.send_frame(index, arg)?;
+        self.read_r1()
+    }
+
+    /// End a multiple block read, with CS already low.
+    fn stop_transmission(&mut self) -> Result<(), SdSpiError> {
+        self.send_frame(cmd::STOP_TRANSMISSION, 0)?;
+        // Spec: The card may send one more byte of the block being read before responding.
+        self.transfer_byte(0xff)?;
+
+        match self.read_r1()? {
+            0 => self.wait_not_busy(),
+            r1 => Err(SdSpiError::Command(r1)),
+        }
+    }
+
+    fn send_frame(&mut self, index: u8, arg: u32) -> Result<(), SdSpiError> {
+        let mut frame = [0; 6];
+        frame[0] = 0x40 | index;
+        frame[1..5].copy_from_slice(&arg.to_be_bytes());
+        frame[5] = (crc7(&frame[..5]) << 1) | 1;
+
+        // Make sure the card is ready for a command.
+        self.transfer_byte(0xff)?;
+        self.spi.write(&frame)?;
+        Ok(())
+    }
+
+    fn read_r1(&mut self) -> Result<u8, SdSpiError> {
+        // The response starts with a cleared bit, after 0 - 8 bytes of 0xFF.
+        for _ in 0..RESPONSE_TIMEOUT {
+            let r1 = self.transfer_byte(0xff)?;
+            if r1 & 0x80 == 0 {
+                return Ok(r1);
+            }
+        }
+
+        Err(SdSpiError::Timeout)
+    }
+
+    /// Raise CS, then clock one more byte, so the card releases MISO.
+    fn deselect(&mut self) -> Result<(), SdSpiError> {
+        self.cs.set_high();
+        self.transfer_byte(0xff)?;
+        Ok(())
+    }
+
+    /// Read the 4 bytes following an R3 or R7 response.
+    fn read_u32(&mut self) -> Result<u32, SdSpiError> {
+        let mut buf = [0xff; 4];
+        self.spi.transfer(&mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    /// Wait for a data start token, then read a data block and check its CRC.
+    fn read_data(&mut self, buf: &mut [u8]) -> Result<(), SdSpiError> {
+        let mut token = 0xff;
+        for _ in 0..BUSY_TIMEOUT {
+            token = self.transfer_byte(0xff)?;
+            if token != 0xff {
+                break;
+            }
+        }
+
+        match token {
+            DATA_START_TOKEN => (),
+            0xff => return Err(SdSpiError::Timeout),
+            // An error token: Bits 7:4 are clear, and bits 3:0 indicate the error.
+            t => return Err(SdSpiError::Data(t)),
+        }
+
+        buf.fill(0xff);
+        self.spi.transfer(buf)?;
+
+        let mut crc = [0xff; 2];
+        self.spi.transfer(&mut crc)?;
+
+        if u16::from_be_bytes(crc) != crc16(buf) {
+            return Err(SdSpiError::Crc);
+        }
+        Ok(())
+    }
+
+    /// Send a data block with its start token and CRC, and check the card accepted it.
+    fn write_data(&mut self, token: u8, data: &[u8]) -> Result<(), SdSpiError> {
+        self.spi.write(&[token])?;
+        self.spi.write(data)?;
+        self.spi.write(&crc16(data).to_be_bytes())?;
+
+        match self.transfer_byte(0xff)? & 0x1f {
+            DATA_ACCEPTED => Ok(()),
+            DATA_CRC_ERROR => Err(SdSpiError::Crc),
+            response => Err(SdSpiError::Data(response)),
+        }
+    }
+
+    /// Wait while the card holds MISO low, eg while programming a written block.
+    fn wait_not_busy(&mut self) -> Result<(), SdSpiError> {
+        for _ in 0..BUSY_TIMEOUT {
+            if self.transfer_byte(0xff)? == 0xff {
+                return Ok(());
+            }
+        }
+        Err(SdSpiError::Timeout)
+    }
+
+    fn transfer_byte(&mut self, byte: u8) -> Result<u8, SdSpiError> {
+        let mut buf = [byte];
+        self.spi.transfer(&mut buf)?;
+        Ok(buf[0])
+    }
+}
+
+impl<R> BlockDevice for SdSpi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    type Error = SdSpiError;
+
+    fn read_blocks(&mut self, start: u32, blocks: &mut [Block]) -> Result<(), Self::Error> {
+        match blocks.len() {
+            0 => Ok(()),
+            1 => self.read_block(start, &mut blocks[0]),
+            _ => self.read_multiple(start, blocks),
+        }
+    }
+
+    fn write_blocks(&mut self, start: u32, blocks: &[Block]) -> Result<(), Self::Error> {
+        match blocks.len() {
+            0 => Ok(()),
+            1 => self.write_block(start, &blocks[0]),
+            _ => self.write_multiple(start, blocks),
+        }
+    }
+
+    /// Computed from the CSD register. See the spec, section 5.3: CSD Register.
+    fn num_blocks(&mut self) -> Result<u32, Self::Error> {
+        let csd = self.read_csd()?;
+
+        match csd[0] >> 6 {
+            // CSD version 1: Capacity = (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) * 2^READ_BL_LEN.
+            0 => {
+                let read_bl_len = (csd[5] & 0x0f) as u32;
+                let c_size =
+                    ((csd[6] as u32 & 0x03) << 10) | ((csd[7] as u32) << 2) | (csd[8] as u32 >> 6);
+                let c_size_mult = ((csd[9] as u32 & 0x03) << 1) | (csd[10] as u32 >> 7);
+                Ok((c_size + 1) << (c_size_mult + 2 + read_bl_len - 9))
+            }
+            // CSD version 2: Capacity = (C_SIZE + 1) * 512KiB.
+            1 => {
+                let c_size =
+                    ((csd[7] as u32 & 0x3f) << 16) | ((csd[8] as u32) << 8) | csd[9] as u32;
+                Ok((c_size + 1) * 1_024)
+            }
+            _ => Err(SdSpiError::UnsupportedCard),
+        }
+    }
+}
+
+/// The 7-bit CRC used for commands: Polynomial x^7 + x^3 + 1.
+fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0;
+    for byte in data {
+        for i in (0..8).rev() {
+            let bit = (byte >> i) & 1;
+            let msb = (crc >> 6) & 1;
+            crc = (crc << 1) & 0x7f;
+            if bit ^ msb != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+/// The 16-bit CRC used for data blocks: CRC-16-CCITT, polynomial x^16 + x^12 + x^5 + 1, with an
+/// initial value of 0.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
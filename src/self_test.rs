@@ -0,0 +1,197 @@
+//! Self-tests for production test and field diagnostic firmware, that run without a debug probe.
+//! Each test returns `Ok(())` on pass, or the reason it failed. They use internal test features
+//! where the hardware has them, and otherwise note what the test fixture needs to provide.
+//!
+//! Note that these tests take over the peripheral while running; don't run them while it's in use
+//! elsewhere, eg from an interrupt handler.
+
+use core::ops::Deref;
+
+use crate::{
+    gpio::Pin,
+    pac,
+    spi::Spi,
+    usart::Usart,
+    util::{BaudPeriph, RccPeriph},
+};
+
+use cfg_if::cfg_if;
+
+/// Patterns sent during loopback tests: Alternating bits, and all bits cleared and set.
+const PATTERNS: [u8; 4] = [0x55, 0xAA, 0x00, 0xFF];
+
+/// The number of status register polls before a loopback test times out.
+const TIMEOUT_ITERS: u32 = 1_000_000;
+
+/// The range of valid VREFINT voltages, in V. This spans the datasheet minimum and maximum
+/// for the supported families, eg 1.182 - 1.232V on G4, and 1.18 - 1.24V on F4.
+const VREFINT_MIN: f32 = 1.15;
+const VREFINT_MAX: f32 = 1.27;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The reason a self-test failed.
+pub enum SelfTestError {
+    /// A word wasn't received in time.
+    Timeout,
+    /// The word received back doesn't match the word sent.
+    Mismatch { sent: u8, received: u8 },
+    /// The peripheral reported an error, eg an overrun.
+    Peripheral,
+    /// A line read low while released; eg a missing pull-up, or a short to ground.
+    StuckLow,
+    /// A line read high while driven low; eg a short to the supply.
+    StuckHigh,
+    /// Driving one line low pulled another low too.
+    Shorted,
+    /// A measurement was outside its valid range.
+    OutOfRange,
+}
+
+/// Test a U[S]ART's transmitter and receiver, using half-duplex mode, where TX connects to RX
+/// internally. The patterns sent appear on the TX pin; its line must not be held low externally.
+/// Restores the previous mode afterwards. Sets `USART_CR3` register, `HDSEL` field.
+pub fn usart_loopback<R>(usart: &mut Usart<R>) -> Result<(), SelfTestError>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    let regs = &usart.regs;
+    let originally_hd = regs.cr3.read().hdsel().bit_is_set();
+
+    // RM: "This bit can only be written when the USART is disabled (UE=0)."
+    regs.cr1.modify(|_, w| w.ue().clear_bit());
+    while regs.cr1.read().ue().bit_is_set() {}
+    regs.cr3.modify(|_, w| w.hdsel().set_bit());
+    regs.cr1.modify(|_, w| w.ue().set_bit());
+
+    let mut result = Ok(());
+
+    for sent in PATTERNS {
+        match usart_transfer(regs, sent) {
+            Some(received) if received == sent => (),
+            Some(received) => {
+                result = Err(SelfTestError::Mismatch { sent, received });
+                break;
+            }
+            None => {
+                result = Err(SelfTestError::Timeout);
+                break;
+            }
+        }
+    }
+
+    regs.cr1.modify(|_, w| w.ue().clear_bit());
+    while regs.cr1.read().ue().bit_is_set() {}
+    regs.cr3.modify(|_, w| w.hdsel().bit(originally_hd));
+    regs.cr1.modify(|_, w| w.ue().set_bit());
+
+    result
+}
+
+/// Send a byte, and return the byte received, or `None` if none was received in time.
+fn usart_transfer(regs: &pac::usart1::RegisterBlock, byte: u8) -> Option<u8> {
+    cfg_if! {
+        if #[cfg(feature = "f4")] {
+            // Clear any stale data and errors, by reading SR then DR.
+            regs.sr.read();
+            regs.dr.read();
+
+            regs.dr.write(|w| unsafe { w.dr().bits(byte as u16) });
+            for _ in 0..TIMEOUT_ITERS {
+                if regs.sr.read().rxne().bit_is_set() {
+                    return Some(regs.dr.read().dr().bits() as u8);
+                }
+            }
+        } else {
+            regs.icr.write(|w| {
+                w.orecf().set_bit();
+                w.fecf().set_bit()
+            });
+            regs.rqr.write(|w| w.rxfrq().set_bit());
+
+            regs.tdr.write(|w| unsafe { w.tdr().bits(byte as u16) });
+            for _ in 0..TIMEOUT_ITERS {
+                if regs.isr.read().rxne().bit_is_set() {
+                    return Some(regs.rdr.read().rdr().bits() as u8);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Test an SPI peripheral in master mode, by comparing the words received with those sent. SPI has
+/// no internal loopback, so the test fixture must connect MOSI to MISO. Any chip select must be
+/// deasserted, so no device drives MISO.
+pub fn spi_loopback<R>(spi: &mut Spi<R>) -> Result<(), SelfTestError>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    let mut buf = PATTERNS;
+    spi.transfer(&mut buf)
+        .map_err(|_| SelfTestError::Peripheral)?;
+
+    for (sent, received) in PATTERNS.iter().zip(buf.iter()) {
+        if sent != received {
+            return Err(SelfTestError::Mismatch {
+                sent: *sent,
+                received: *received,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Test an I2C bus's SCL and SDA lines: Both must read high when released, and driving each low
+/// must not pull the other low. Catches missing pull-ups, and shorts between the lines or to a
+/// supply. Configure both pins as open-drain outputs first, eg before `I2c::new`, or after
+/// `I2c::free`. Leaves both lines released.
+pub fn i2c_lines(scl: &mut Pin, sda: &mut Pin) -> Result<(), SelfTestError> {
+    scl.set_high();
+    sda.set_high();
+    settle();
+
+    if scl.is_low() || sda.is_low() {
+        return Err(SelfTestError::StuckLow);
+    }
+
+    drive_low_check(scl, sda)?;
+    drive_low_check(sda, scl)
+}
+
+/// Drive a line low, and check that it reads low, and that another line stays high.
+fn drive_low_check(driven: &mut Pin, other: &Pin) -> Result<(), SelfTestError> {
+    driven.set_low();
+    settle();
+    let driven_high = driven.is_high();
+    let other_low = other.is_low();
+    driven.set_high();
+    settle();
+
+    if driven_high {
+        Err(SelfTestError::StuckHigh)
+    } else if other_low {
+        Err(SelfTestError::Shorted)
+    } else {
+        Ok(())
+    }
+}
+
+/// Wait for a line to settle after changing state, through its pull-up and the bus capacitance.
+/// Eg 4.7kΩ with 400pF has a time constant of 1.9μs.
+fn settle() {
+    cortex_m::asm::delay(10_000);
+}
+
+/// Check that a VREFINT reading is plausible, given the ADC's reference voltage (VDDA or VREF+),
+/// in V. Catches a missing or noisy analog supply, and a misconfigured ADC. `reading` is a
+/// right-aligned 12-bit conversion of the VREFINT channel, taken with the longest sample time.
+pub fn adc_vrefint(reading: u16, vref: f32) -> Result<(), SelfTestError> {
+    let v = reading as f32 * vref / 4_095.;
+
+    if (VREFINT_MIN..=VREFINT_MAX).contains(&v) {
+        Ok(())
+    } else {
+        Err(SelfTestError::OutOfRange)
+    }
+}
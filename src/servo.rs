@@ -0,0 +1,72 @@
+//! Support for hobby servos, using timer PWM. A servo's position is set by the width of a pulse,
+//! nominally 1000 - 2000μs, repeated at 50Hz. `Servo` stores a channel's frame rate, pulse width
+//! endpoints, and angle range; pass it to the `Timer` servo methods to set the output.
+//!
+//! Example, using TIM2 channel 1:
+//!
+//! `let servo = Servo::new(TimChannel::C1, Default::default());`
+//! `timer.enable_servo(&servo);`
+//! `timer.set_servo_angle(&servo, 90.);`
+//!
+//! Note that all channels of a timer share its frame rate.
+
+use crate::timer::TimChannel;
+
+/// Initial configuration data for a servo.
+#[derive(Clone)]
+pub struct ServoConfig {
+    /// Frame rate, in Hz. Defaults to 50.
+    pub freq: f32,
+    /// The shortest pulse width allowed, in μs. Corresponds to `min_angle`. Defaults to 1000.
+    pub min_pulse: f32,
+    /// The longest pulse width allowed, in μs. Corresponds to `max_angle`. Defaults to 2000.
+    pub max_pulse: f32,
+    /// The angle at `min_pulse`, in degrees. Defaults to 0.
+    pub min_angle: f32,
+    /// The angle at `max_pulse`, in degrees. Defaults to 180.
+    pub max_angle: f32,
+}
+
+impl Default for ServoConfig {
+    fn default() -> Self {
+        Self {
+            freq: 50.,
+            min_pulse: 1_000.,
+            max_pulse: 2_000.,
+            min_angle: 0.,
+            max_angle: 180.,
+        }
+    }
+}
+
+/// Represents a servo connected to a timer PWM channel.
+pub struct Servo {
+    pub channel: TimChannel,
+    pub cfg: ServoConfig,
+}
+
+impl Servo {
+    pub fn new(channel: TimChannel, cfg: ServoConfig) -> Self {
+        assert!(
+            cfg.min_pulse < cfg.max_pulse,
+            "Servo `min_pulse` must be less than `max_pulse`."
+        );
+        assert!(
+            cfg.max_pulse < 1_000_000. / cfg.freq,
+            "Servo `max_pulse` must be shorter than the frame period."
+        );
+
+        Self { channel, cfg }
+    }
+
+    /// Clamp a pulse width, in μs, to the configured endpoints.
+    pub fn clamp_pulse(&self, pulse: f32) -> f32 {
+        pulse.clamp(self.cfg.min_pulse, self.cfg.max_pulse)
+    }
+
+    /// Convert an angle, in degrees, to a pulse width in μs, clamped to the configured endpoints.
+    pub fn angle_to_pulse(&self, angle: f32) -> f32 {
+        let portion = (angle - self.cfg.min_angle) / (self.cfg.max_angle - self.cfg.min_angle);
+        self.clamp_pulse(self.cfg.min_pulse + portion * (self.cfg.max_pulse - self.cfg.min_pulse))
+    }
+}
@@ -0,0 +1,211 @@
+//! Share an I2C or SPI bus between multiple device drivers. Most boards have several devices on a
+//! single bus, but drivers usually take ownership of the bus they use. These adapters wrap a
+//! reference to a bus stored in a `Mutex<RefCell<>>`, and lock it in a critical section for
+//! each transaction. (This uses the `critical-section` crate if its feature is enabled, and
+//! disables interrupts otherwise.) Each adapter can be passed to a different driver.
+//!
+//! The adapters implement the `embedded-hal` blocking traits when the `embedded-hal` feature is
+//! enabled, in addition to the same read and write methods as the underlying bus. Example:
+//!
+//! `let bus = Mutex::new(RefCell::new(I2c::new(dp.I2C1, Default::default(), &clock_cfg)));`
+//! `let imu = Imu::new(SharedI2c::new(&bus));`
+//! `let baro = Baro::new(SharedI2c::new(&bus));`
+//!
+//! For SPI, each device also has its own chip select (CS) pin, which is set low for the duration
+//! of each transaction:
+//!
+//! `let flash = Flash::new(SharedSpi::new(&bus, flash_cs));`
+//!
+//! Don't use a shared bus from an interrupt handler while a DMA transfer started elsewhere on the
+//! same bus is in progress. The I2C adapter isn't available on F4.
+
+use core::{cell::RefCell, ops::Deref};
+
+#[cfg(not(feature = "critical-section"))]
+use cortex_m::interrupt::Mutex;
+#[cfg(feature = "critical-section")]
+use critical_section::Mutex;
+
+#[cfg(feature = "embedded-hal")]
+use embedded_hal::blocking::{i2c, spi};
+
+#[cfg(not(feature = "f4"))]
+use crate::i2c::{self as i2c_mod, I2c};
+use crate::{
+    gpio::Pin,
+    pac,
+    spi::{self as spi_mod, ChipSelect, CsTiming, Spi, SpiOperation},
+    util::{free, RccPeriph},
+};
+
+#[cfg(not(feature = "f4"))]
+/// An I2C bus shared between devices. Each method locks the bus for the duration of the transaction.
+pub struct SharedI2c<'a, R> {
+    bus: &'a Mutex<RefCell<I2c<R>>>,
+}
+
+#[cfg(not(feature = "f4"))]
+impl<'a, R> SharedI2c<'a, R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    pub fn new(bus: &'a Mutex<RefCell<I2c<R>>>) -> Self {
+        Self { bus }
+    }
+
+    /// Run a closure with exclusive access to the bus, eg for a sequence of transactions that must
+    /// not be interrupted by other devices.
+    pub fn lock<T>(&mut self, f: impl FnOnce(&mut I2c<R>) -> T) -> T {
+        free(|cs| f(&mut self.bus.borrow(cs).borrow_mut()))
+    }
+
+    /// Read multiple words to a buffer. See `I2c::read`.
+    pub fn read(&mut self, addr: u8, bytes: &mut [u8]) -> Result<(), i2c_mod::Error> {
+        self.lock(|bus| bus.read(addr, bytes))
+    }
+
+    /// Write an array of words. See `I2c::write`.
+    pub fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), i2c_mod::Error> {
+        self.lock(|bus| bus.write(addr, bytes))
+    }
+
+    /// Write and read an array of words, without releasing the bus in between. See `I2c::write_read`.
+    pub fn write_read(
+        &mut self,
+        addr: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), i2c_mod::Error> {
+        self.lock(|bus| bus.write_read(addr, bytes, buffer))
+    }
+}
+
+/// A device on a shared SPI bus, with its own chip select pin. Each method locks the bus, and
+/// sets CS low, for the duration of the transaction.
+pub struct SharedSpi<'a, R> {
+    bus: &'a Mutex<RefCell<Spi<R>>>,
+    cs: Pin,
+}
+
+impl<'a, R> SharedSpi<'a, R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    /// Create a new device on the bus. `cs` must be configured as an output; it's set high here.
+    pub fn new(bus: &'a Mutex<RefCell<Spi<R>>>, mut cs: Pin) -> Self {
+        cs.set_high();
+        Self { bus, cs }
+    }
+
+    /// Run a closure with exclusive access to the bus, with CS low. Use this for transactions
+    /// made of several writes and reads, eg a command, followed by a read.
+    pub fn transaction<T>(&mut self, f: impl FnOnce(&mut Spi<R>) -> T) -> T {
+        let cs = &mut self.cs;
+
+        free(|cs_token| {
+            let mut bus = self.bus.borrow(cs_token).borrow_mut();
+
+            cs.set_low();
+            let result = f(&mut bus);
+            cs.set_high();
+
+            result
+        })
+    }
+
+    /// Write multiple bytes. See `Spi::write`.
+    pub fn write(&mut self, words: &[u8]) -> Result<(), spi_mod::Error> {
+        self.transaction(|bus| bus.write(words))
+    }
+
+    /// Run a list of operations as one transaction, with this device's CS, and the given timing.
+    /// See `Spi::transaction`.
+    pub fn operations(
+        &mut self,
+        timing: CsTiming,
+        operations: &mut [SpiOperation],
+    ) -> Result<(), spi_mod::Error> {
+        let cs = &mut self.cs;
+
+        free(|cs_token| {
+            let mut bus = self.bus.borrow(cs_token).borrow_mut();
+            bus.transaction(ChipSelect::Gpio(cs), timing, operations)
+        })
+    }
+
+    /// Write and read multiple bytes, in place. See `Spi::transfer`.
+    pub fn transfer(&mut self, words: &mut [u8]) -> Result<(), spi_mod::Error> {
+        self.transaction(|bus| bus.transfer(words))
+    }
+
+    /// Release the CS pin.
+    pub fn free(self) -> Pin {
+        self.cs
+    }
+}
+
+#[cfg(all(feature = "embedded-hal", not(feature = "f4")))]
+impl<R> i2c::Write for SharedI2c<'_, R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    type Error = i2c_mod::Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), i2c_mod::Error> {
+        SharedI2c::write(self, addr, bytes)
+    }
+}
+
+#[cfg(all(feature = "embedded-hal", not(feature = "f4")))]
+impl<R> i2c::Read for SharedI2c<'_, R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    type Error = i2c_mod::Error;
+
+    fn read(&mut self, addr: u8, bytes: &mut [u8]) -> Result<(), i2c_mod::Error> {
+        SharedI2c::read(self, addr, bytes)
+    }
+}
+
+#[cfg(all(feature = "embedded-hal", not(feature = "f4")))]
+impl<R> i2c::WriteRead for SharedI2c<'_, R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    type Error = i2c_mod::Error;
+
+    fn write_read(
+        &mut self,
+        addr: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), i2c_mod::Error> {
+        SharedI2c::write_read(self, addr, bytes, buffer)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<R> spi::Write<u8> for SharedSpi<'_, R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    type Error = spi_mod::Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), spi_mod::Error> {
+        SharedSpi::write(self, words)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<R> spi::Transfer<u8> for SharedSpi<'_, R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    type Error = spi_mod::Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], spi_mod::Error> {
+        SharedSpi::transfer(self, words)?;
+        Ok(words)
+    }
+}
@@ -0,0 +1,174 @@
+//! Software PWM on many GPIO pins, driven by one timer's update interrupt. Useful for dimming LED
+//! matrices and front-panel indicators when the hardware timer channels run out. Each interrupt
+//! advances a counter by one step, and writes each port's changed pins at once with
+//! `gpio::write_pins`, so pins on the same port switch together, without glitches.
+//!
+//! The PWM frequency is the timer's update frequency divided by `resolution`; `SoftPwm::timer_freq`
+//! computes the timer frequency to use. Eg, 32 channels at 100 steps and 200Hz needs a 20kHz
+//! interrupt; keep the interrupt short, and its priority high if flicker matters.
+//!
+//! Example, dimming 16 LEDs at 200Hz with 100 steps:
+//!
+//! ```
+//! static PWM: Mutex<RefCell<Option<SoftPwm<16>>>> = Mutex::new(RefCell::new(None));
+//!
+//! // Configure the pins as outputs first.
+//! let pwm = SoftPwm::new([(Port::B, 0), (Port::B, 1), /* ... */], 100);
+//! let mut timer = Timer::new_tim2(dp.TIM2, pwm.timer_freq(200.), Default::default(), &clock_cfg);
+//! timer.enable_interrupt(TimerInterrupt::Update);
+//! timer.enable();
+//!
+//! #[interrupt]
+//! fn TIM2() {
+//!     free(|cs| {
+//!         unsafe { (*pac::TIM2::ptr()).sr.modify(|_, w| w.uif().clear_bit()) };
+//!         access_global!(PWM, pwm, cs);
+//!         pwm.on_update();
+//!     });
+//! }
+//! ```
+
+use crate::gpio::{self, Port};
+
+/// The number of GPIO ports a `SoftPwm` can drive at once.
+const MAX_PORTS: usize = 8;
+
+/// Dims up to `N` GPIO pins with software PWM. Each pin has a duty cycle from 0 (always low) to
+/// `resolution` (always high).
+pub struct SoftPwm<const N: usize> {
+    pins: [(Port, u8); N],
+    duty: [u16; N],
+    resolution: u16,
+    /// The current step in the PWM period, from 0 to `resolution - 1`.
+    counter: u16,
+}
+
+impl<const N: usize> SoftPwm<N> {
+    /// Create a software PWM engine for a set of pins, each as a (port, pin number) tuple.
+    /// Configure the pins as outputs before calling `on_update`. `resolution` is the number of
+    /// duty cycle steps per period. All channels start with a duty cycle of 0.
+    pub fn new(pins: [(Port, u8); N], resolution: u16) -> Self {
+        assert!(
+            resolution > 0,
+            "Soft PWM resolution must be greater than 0."
+        );
+
+        let mut ports = [None; MAX_PORTS];
+        for (port, pin) in pins.iter() {
+            assert!(*pin <= 15, "GPIO pins must be 0 - 15.");
+            assert!(
+                port_index(&mut ports, *port).is_some(),
+                "Soft PWM pins must be on at most 8 ports."
+            );
+        }
+
+        Self {
+            pins,
+            duty: [0; N],
+            resolution,
+            counter: 0,
+        }
+    }
+
+    /// The timer update frequency needed for a given PWM frequency, in Hz.
+    pub fn timer_freq(&self, pwm_freq: f32) -> f32 {
+        pwm_freq * self.resolution as f32
+    }
+
+    /// Set a channel's duty cycle, from 0 to `resolution`. Values past `resolution` are clamped.
+    /// Takes effect at the start of the next period.
+    pub fn set_duty(&mut self, channel: usize, duty: u16) {
+        self.duty[channel] = duty.min(self.resolution);
+    }
+
+    /// Set all channels' duty cycles.
+    pub fn set_duty_all(&mut self, duty: u16) {
+        let duty = duty.min(self.resolution);
+        for d in self.duty.iter_mut() {
+            *d = duty;
+        }
+    }
+
+    /// Get a channel's duty cycle.
+    pub fn get_duty(&self, channel: usize) -> u16 {
+        self.duty[channel]
+    }
+
+    /// Get the number of duty cycle steps per period.
+    pub fn get_resolution(&self) -> u16 {
+        self.resolution
+    }
+
+    /// Advance the PWM by one step. Call this from the timer's update interrupt handler, after
+    /// clearing its flag. At the start of each period, sets all pins with a non-zero duty cycle;
+    /// clears each pin when the counter reaches its duty cycle.
+    pub fn on_update(&mut self) {
+        let mut ports = [None; MAX_PORTS];
+        let mut set = [0_u16; MAX_PORTS];
+        let mut reset = [0_u16; MAX_PORTS];
+
+        for ((port, pin), duty) in self.pins.iter().zip(self.duty.iter()) {
+            // We've checked in `new` that there's room for every port.
+            let i = port_index(&mut ports, *port).unwrap();
+            let bit = 1 << pin;
+
+            if self.counter == 0 {
+                if *duty > 0 {
+                    set[i] |= bit;
+                } else {
+                    reset[i] |= bit;
+                }
+            } else if *duty == self.counter {
+                reset[i] |= bit;
+            }
+        }
+
+        for (i, port) in ports.iter().enumerate() {
+            if let Some(port) = port {
+                if set[i] != 0 || reset[i] != 0 {
+                    gpio::write_pins(*port, set[i], reset[i]);
+                }
+            }
+        }
+
+        self.counter += 1;
+        if self.counter >= self.resolution {
+            self.counter = 0;
+        }
+    }
+
+    /// Set all pins low, and restart the period. Eg, run this after stopping the timer.
+    pub fn all_off(&mut self) {
+        let mut ports = [None; MAX_PORTS];
+        let mut reset = [0_u16; MAX_PORTS];
+
+        for (port, pin) in self.pins.iter() {
+            let i = port_index(&mut ports, *port).unwrap();
+            reset[i] |= 1 << pin;
+        }
+
+        for (i, port) in ports.iter().enumerate() {
+            if let Some(port) = port {
+                gpio::write_pins(*port, 0, reset[i]);
+            }
+        }
+
+        self.counter = 0;
+    }
+}
+
+/// Find a port's index in a list of ports, adding it if it's not present. Returns `None` if the
+/// list is full.
+fn port_index(ports: &mut [Option<Port>; MAX_PORTS], port: Port) -> Option<usize> {
+    for (i, p) in ports.iter_mut().enumerate() {
+        match p {
+            Some(p) if *p == port => return Some(i),
+            None => {
+                *p = Some(port);
+                return Some(i);
+            }
+            _ => (),
+        }
+    }
+    None
+}
@@ -0,0 +1,610 @@
+//! Support for the S/PDIF receiver (SPDIFRX), on H7 and F446. It decodes IEC 60958 streams, eg from
+//! an optical or coaxial digital audio input, recovering the symbol clock from the signal itself.
+//! Audio samples are read from the data register, by polling or DMA; channel status and user bits
+//! are read separately, from the control flow, eg to check the sample rate and format the
+//! transmitter reports.
+//!
+//! The kernel clock must be fast enough for the highest sample rate expected; see the RM's table
+//! of minimum `spdifrx_ker_ck` frequencies. On H7, it's `pll1_q_ck` by default, set with
+//! `RCC_D2CCIP1R` register, `SPDIFSEL` field. On F446, it's PLLI2S's P output, or the main PLL's R
+//! output, set with `RCC_DCKCFGR2` register, `SPDIFRXSEL` field.
+//!
+//! Example, reading samples after synchronizing:
+//!
+//! ```
+//! let mut spdif = SpdifRx::new(dp.SPDIFRX, Default::default());
+//! spdif.sync()?;
+//! let fs = spdif.sample_rate(spdif_clk);
+//! spdif.enable();
+//! let word = block!(spdif.read())?;
+//! let sample = Sample::new(word, DataFormat::RightAligned);
+//! ```
+//!
+//! See H743 RM, chapter 52: SPDIF receiver interface (SPDIFRX), and F446 RM, chapter 31.
+
+use core::{fmt, ptr};
+
+use cfg_if::cfg_if;
+
+use crate::{
+    pac::{RCC, SPDIFRX},
+    rcc_en_reset,
+    util::free,
+};
+
+#[cfg(feature = "h7")]
+use core::ops::Deref;
+
+#[cfg(feature = "h7")]
+use crate::{
+    dma::{self, ChannelCfg, Dma, DmaChannel},
+    pac::dma1 as dma_p,
+};
+
+#[cfg(feature = "f446")]
+use crate::pac;
+
+/// `SPDIFRX_DR` register offset. On H7, the PAC presents this as 3 aliased registers, one per
+/// data format, so we read it by address on both families.
+const DR_OFFSET: usize = 0x10;
+
+/// The DMA1 stream for samples on F446, on channel 0. (Stream 6 carries the control flow.) See
+/// F446 RM, table 28: "DMA1 request mapping".
+#[cfg(feature = "f446")]
+const DMA_STREAM_DATA: usize = 1;
+
+/// `DMA_LIFCR` and `DMA_HIFCR` bits for one stream: `TCIF`, `HTIF`, `TEIF`, `DMEIF`, and `FEIF`.
+#[cfg(feature = "f446")]
+const DMA_STREAM_FLAGS: u32 = 0b111101;
+
+/// The number of frames in an IEC 60958 block, and so the number of channel status bits.
+const FRAMES_PER_BLOCK: usize = 192;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// SPDIFRX errors. `Parity` and `Overrun` are cleared by `read`. The others stop reception;
+/// call `disable` then `sync` to restart.
+pub enum SpdifError {
+    /// A sample's parity bit didn't match. `SPDIFRX_SR` register, `PERR` field.
+    Parity,
+    /// A sample was received before the previous one was read. `SPDIFRX_SR` register, `OVR`
+    /// field.
+    Overrun,
+    /// A manchester violation, or unexpected preamble, after synchronization. `SPDIFRX_SR`
+    /// register, `FERR` field.
+    Framing,
+    /// Synchronization failed after the retries set with `SpdifConfig::sync_retries`.
+    /// `SPDIFRX_SR` register, `SERR` field.
+    Sync,
+    /// The input had no transitions for too long, eg a disconnected cable. `SPDIFRX_SR`
+    /// register, `TERR` field.
+    Timeout,
+}
+
+impl fmt::Display for SpdifError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Parity => f.write_str("parity error"),
+            Self::Overrun => f.write_str("a sample was lost; the data register overran"),
+            Self::Framing => f.write_str("framing error"),
+            Self::Sync => f.write_str("synchronization failed"),
+            Self::Timeout => f.write_str("the input had no transitions"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The input pin, `SPDIFRX_IN0` - `SPDIFRX_IN3`. Sets `SPDIFRX_CR` register, `INSEL` field.
+pub enum SpdifInput {
+    In0 = 0,
+    In1 = 1,
+    In2 = 2,
+    In3 = 3,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The layout of samples in the data register. Sets `SPDIFRX_CR` register, `DRFMT` field.
+pub enum DataFormat {
+    /// The sample in bits 23:0, and the status bits in 29:24.
+    RightAligned = 0b00,
+    /// The sample in bits 31:8, and the status bits in 5:0.
+    LeftAligned = 0b01,
+    /// The 16 most significant bits of 2 samples: channel A in bits 15:0, and B in 31:16. No
+    /// status bits. Requires `stereo`.
+    Packed = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The number of synchronization attempts before failing with `SpdifError::Sync`. Sets
+/// `SPDIFRX_CR` register, `NBTR` field.
+pub enum SyncRetries {
+    None = 0b00,
+    R3 = 0b01,
+    R15 = 0b10,
+    R63 = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The subframe whose channel status is read. Sets `SPDIFRX_CR` register, `CHSEL` field.
+pub enum Channel {
+    A,
+    B,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The preamble that started a subframe: The first subframe of a block, or of each other frame,
+/// is channel A; the second is channel B.
+pub enum Preamble {
+    /// Channel A, first frame of a block.
+    B,
+    /// Channel A.
+    M,
+    /// Channel B.
+    W,
+}
+
+/// Configuration data for SPDIFRX.
+#[derive(Clone)]
+pub struct SpdifConfig {
+    /// Defaults to `In0`.
+    pub input: SpdifInput,
+    /// Defaults to `RightAligned`.
+    pub data_format: DataFormat,
+    /// Alternate channel A and B samples in the data register, resynchronizing on an overrun so
+    /// the order is kept. Sets `SPDIFRX_CR` register, `RXSTEO` field. Defaults to `true`.
+    pub stereo: bool,
+    /// Defaults to 63 retries.
+    pub sync_retries: SyncRetries,
+    /// Wait for activity on the input before synchronizing, instead of timing out. Sets
+    /// `SPDIFRX_CR` register, `WFA` field. Defaults to `true`.
+    pub wait_for_activity: bool,
+    /// Include the parity error, validity, user, channel status, and preamble bits with each
+    /// sample. Clears `SPDIFRX_CR` register, `PMSK`, `VMSK`, `CUMSK`, and `PTMSK` fields.
+    /// Defaults to `true`.
+    pub status_bits: bool,
+    /// Defaults to `A`.
+    pub channel_status_channel: Channel,
+    #[cfg(feature = "h7")]
+    /// Output the recovered symbol clock to other peripherals, eg SAI, for output at the same
+    /// rate. Sets `SPDIFRX_CR` register, `CKSEN` field. Defaults to `false`.
+    pub symbol_clock: bool,
+    #[cfg(feature = "h7")]
+    /// Keep the symbol clock running from an estimate if the input is lost. Sets `SPDIFRX_CR`
+    /// register, `CKSBKPEN` field. Defaults to `false`.
+    pub symbol_clock_backup: bool,
+}
+
+impl Default for SpdifConfig {
+    fn default() -> Self {
+        Self {
+            input: SpdifInput::In0,
+            data_format: DataFormat::RightAligned,
+            stereo: true,
+            sync_retries: SyncRetries::R63,
+            wait_for_activity: true,
+            status_bits: true,
+            channel_status_channel: Channel::A,
+            #[cfg(feature = "h7")]
+            symbol_clock: false,
+            #[cfg(feature = "h7")]
+            symbol_clock_backup: false,
+        }
+    }
+}
+
+/// A sample read from the data register, with its status bits, in the `RightAligned` or
+/// `LeftAligned` format, with `status_bits` set.
+#[derive(Clone, Copy)]
+pub struct Sample {
+    /// The sample, sign-extended from 24 bits. 16 and 20-bit samples are in the most
+    /// significant bits.
+    pub data: i32,
+    pub parity_error: bool,
+    /// The validity bit. Set if the sample isn't valid linear PCM, eg compressed audio.
+    pub invalid: bool,
+    pub user: bool,
+    pub channel_status: bool,
+    pub preamble: Preamble,
+}
+
+impl Sample {
+    /// Split a data register word into its sample and status bits. Panics on the `Packed` format.
+    pub fn new(word: u32, format: DataFormat) -> Self {
+        let (data, status) = match format {
+            DataFormat::RightAligned => (word << 8, word >> 24),
+            DataFormat::LeftAligned => (word & !0xff, word),
+            DataFormat::Packed => panic!("Packed samples have no status bits."),
+        };
+
+        Self {
+            data: data as i32 >> 8,
+            parity_error: status & 1 != 0,
+            invalid: status >> 1 & 1 != 0,
+            user: status >> 2 & 1 != 0,
+            channel_status: status >> 3 & 1 != 0,
+            preamble: match status >> 4 & 0b11 {
+                1 => Preamble::B,
+                2 => Preamble::M,
+                _ => Preamble::W,
+            },
+        }
+    }
+}
+
+/// A block's channel status and user bits, read with `read_channel_status`.
+pub struct ChannelStatus {
+    /// The 192 channel status bits of the selected channel, as 24 bytes; the first received in
+    /// byte 0, bit 0. For consumer streams, byte 0, bit 0 is clear, and byte 3 holds the sample
+    /// rate; see IEC 60958-3.
+    pub status: [u8; 24],
+    /// The user bits, as `SPDIFRX_CSR` register, `USR` field, for each 8 frames.
+    pub user: [u16; 24],
+}
+
+impl ChannelStatus {
+    /// Returns `true` for a professional (AES3) stream, from byte 0, bit 0.
+    pub fn is_professional(&self) -> bool {
+        self.status[0] & 1 != 0
+    }
+
+    /// Returns `true` if the samples aren't linear PCM, eg compressed audio, from byte 0, bit 1.
+    pub fn is_non_audio(&self) -> bool {
+        self.status[0] & 0b10 != 0
+    }
+
+    /// The sample rate the transmitter reports, in Hz, for consumer streams. From byte 3, bits
+    /// 3:0. Returns `None` if it isn't indicated.
+    pub fn sample_rate(&self) -> Option<u32> {
+        match self.status[3] & 0xf {
+            0b0000 => Some(44_100),
+            0b0010 => Some(48_000),
+            0b0011 => Some(32_000),
+            0b1000 => Some(88_200),
+            0b1010 => Some(96_000),
+            0b1100 => Some(176_400),
+            0b1110 => Some(192_000),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+/// SPDIFRX interrupts. Sets `SPDIFRX_IMR` register.
+pub enum SpdifInterrupt {
+    /// A sample is ready to read. `RXNEIE` field.
+    RxNotEmpty,
+    /// Channel status and user bits are ready to read. `CSRNEIE` field.
+    ChannelStatusNotEmpty,
+    /// `PERRIE` field.
+    Parity,
+    /// `OVRIE` field.
+    Overrun,
+    /// A block started. `SBLKIE` field.
+    StartOfBlock,
+    /// Synchronization finished. `SYNCDIE` field.
+    SyncDone,
+    /// Framing, synchronization, or timeout errors. `IFEIE` field.
+    Error,
+}
+
+/// Represents the S/PDIF receiver.
+pub struct SpdifRx {
+    pub regs: SPDIFRX,
+    pub cfg: SpdifConfig,
+}
+
+impl SpdifRx {
+    /// Initialize the SPDIFRX peripheral, including configuring and enabling its RCC clock. Leaves
+    /// it idle; start it with `sync`.
+    pub fn new(regs: SPDIFRX, cfg: SpdifConfig) -> Self {
+        assert!(
+            cfg.data_format != DataFormat::Packed || cfg.stereo,
+            "The packed format requires stereo mode."
+        );
+
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            cfg_if! {
+                if #[cfg(feature = "h7")] {
+                    rcc_en_reset!(apb1, spdifrx, rcc);
+                } else {
+                    rcc_en_reset!(apb1, spdif, rcc);
+                }
+            }
+        });
+
+        // The masks are set to exclude the status bits.
+        let mask = !cfg.status_bits;
+
+        regs.cr.write(|w| unsafe {
+            w.insel().bits(cfg.input as u8);
+            w.nbtr().bits(cfg.sync_retries as u8);
+            w.wfa().bit(cfg.wait_for_activity);
+            w.drfmt().bits(cfg.data_format as u8);
+            w.rxsteo().bit(cfg.stereo);
+            w.pmsk().bit(mask);
+            w.vmsk().bit(mask);
+            w.cumsk().bit(mask);
+            w.ptmsk().bit(mask);
+            #[cfg(feature = "h7")]
+            w.cksen().bit(cfg.symbol_clock);
+            #[cfg(feature = "h7")]
+            w.cksbkpen().bit(cfg.symbol_clock_backup);
+            w.chsel().bit(cfg.channel_status_channel == Channel::B)
+        });
+
+        Self { regs, cfg }
+    }
+
+    /// Set `SPDIFRX_CR` register, `SPDIFRXEN` field. 0b00 is idle, 0b01 synchronizes only, and
+    /// 0b11 synchronizes, then receives.
+    fn set_state(&mut self, state: u8) {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                self.regs.cr.modify(|_, w| unsafe { w.spdifrxen().bits(state) });
+            } else {
+                self.regs.cr.modify(|_, w| unsafe { w.spdifen().bits(state) });
+            }
+        }
+    }
+
+    /// Synchronize to the input, blocking until done: The receiver measures the input's
+    /// transitions to recover the symbol clock. Doesn't receive data; follow with `enable`. Use
+    /// `sample_rate` to check the rate between these.
+    pub fn sync(&mut self) -> Result<(), SpdifError> {
+        self.set_state(0b01);
+
+        loop {
+            let sr = self.regs.sr.read();
+            if sr.serr().bit_is_set() {
+                self.disable();
+                return Err(SpdifError::Sync);
+            } else if sr.terr().bit_is_set() {
+                self.disable();
+                return Err(SpdifError::Timeout);
+            } else if sr.syncd().bit_is_set() {
+                break;
+            }
+        }
+
+        self.regs.ifcr.write(|w| w.syncdcf().set_bit());
+        Ok(())
+    }
+
+    /// Start receiving. If not already synchronized with `sync`, this synchronizes first; then
+    /// errors are reported by `read`, or the `Error` interrupt.
+    pub fn enable(&mut self) {
+        self.set_state(0b11);
+    }
+
+    /// Stop receiving. This clears the framing, synchronization, and timeout errors.
+    pub fn disable(&mut self) {
+        self.set_state(0b00);
+    }
+
+    /// Returns `true` if synchronized to the input. `SPDIFRX_SR` register, `SYNCD` field.
+    pub fn is_synced(&self) -> bool {
+        self.regs.sr.read().syncd().bit_is_set()
+    }
+
+    /// Estimate the sample rate of the input, in Hz, from the symbol duration measured when
+    /// synchronizing. `kernel_clk` is the SPDIFRX kernel clock frequency, in Hz. Returns `None`
+    /// if not synchronized. Reads `SPDIFRX_SR` register, `WIDTH5` field.
+    pub fn sample_rate(&self, kernel_clk: u32) -> Option<u32> {
+        // WIDTH5 is the duration of 5 symbols, in kernel clock cycles; each frame is 64 symbols.
+        // See H743 RM, section 52.3.9: "Symbol clock generation".
+        let width5 = self.regs.sr.read().width5().bits() as u32;
+        if !self.is_synced() || width5 == 0 {
+            return None;
+        }
+        Some(5 * kernel_clk / (width5 * 64))
+    }
+
+    /// Check for errors, clearing `Parity` and `Overrun`.
+    fn check_errors(&mut self) -> Result<(), SpdifError> {
+        let sr = self.regs.sr.read();
+
+        if sr.ferr().bit_is_set() {
+            Err(SpdifError::Framing)
+        } else if sr.serr().bit_is_set() {
+            Err(SpdifError::Sync)
+        } else if sr.terr().bit_is_set() {
+            Err(SpdifError::Timeout)
+        } else if sr.ovr().bit_is_set() {
+            self.regs.ifcr.write(|w| w.ovrcf().set_bit());
+            Err(SpdifError::Overrun)
+        } else if sr.perr().bit_is_set() {
+            self.regs.ifcr.write(|w| w.perrcf().set_bit());
+            Err(SpdifError::Parity)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Read a word from the data register, in the format set by `SpdifConfig::data_format`;
+    /// split it with `Sample::new`. In stereo mode, channel A and B alternate.
+    pub fn read(&mut self) -> nb::Result<u32, SpdifError> {
+        self.check_errors().map_err(nb::Error::Other)?;
+
+        if self.regs.sr.read().rxne().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        Ok(unsafe { ptr::read_volatile(self.dr_addr() as *const u32) })
+    }
+
+    /// Read a block's channel status and user bits, blocking until the next block starts, and
+    /// ends. Receive audio concurrently, eg with DMA, so the data register doesn't overrun.
+    /// Reads `SPDIFRX_CSR` register.
+    pub fn read_channel_status(&mut self) -> Result<ChannelStatus, SpdifError> {
+        let mut result = ChannelStatus {
+            status: [0; 24],
+            user: [0; 24],
+        };
+
+        // Each read holds 8 frames' bits; `SOB` marks the first of a block.
+        let mut i = 0;
+        while i < FRAMES_PER_BLOCK / 8 {
+            let sr = self.regs.sr.read();
+            if sr.ferr().bit_is_set() {
+                return Err(SpdifError::Framing);
+            } else if sr.terr().bit_is_set() {
+                return Err(SpdifError::Timeout);
+            } else if sr.csrne().bit_is_clear() {
+                continue;
+            }
+
+            let csr = self.regs.csr.read();
+            if i == 0 && csr.sob().bit_is_clear() {
+                continue;
+            }
+            result.status[i] = csr.cs().bits();
+            result.user[i] = csr.usr().bits();
+            i += 1;
+        }
+
+        Ok(result)
+    }
+
+    /// The data register's address, for DMA.
+    fn dr_addr(&self) -> u32 {
+        &*self.regs as *const _ as u32 + DR_OFFSET as u32
+    }
+
+    #[cfg(feature = "h7")]
+    /// Receive samples with DMA, then start receiving. Set the channel's DMAMUX input to
+    /// `DmaInput::SpdifrxDat` first. Sets `SPDIFRX_CR` register, `RXDMAEN` field.
+    pub unsafe fn read_dma<D>(
+        &mut self,
+        buf: &mut [u32],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Dma<D>,
+    ) where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+
+        self.regs.cr.modify(|_, w| w.rxdmaen().set_bit());
+
+        dma.cfg_channel(
+            channel,
+            self.dr_addr(),
+            ptr as u32,
+            len as u32,
+            dma::Direction::ReadFromPeriph,
+            dma::DataSize::S32,
+            dma::DataSize::S32,
+            channel_cfg,
+        );
+
+        self.enable();
+    }
+
+    #[cfg(feature = "h7")]
+    /// Stop a DMA transfer, and receiving. Clears `SPDIFRX_CR` register, `RXDMAEN` field.
+    pub fn stop_dma<D>(&mut self, channel: DmaChannel, dma: &mut Dma<D>)
+    where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        dma.stop(channel);
+        self.disable();
+        self.regs.cr.modify(|_, w| w.rxdmaen().clear_bit());
+    }
+
+    #[cfg(feature = "f446")]
+    /// Receive samples with DMA1, stream 1, then start receiving. With `circular`, repeats until
+    /// `stop_dma`; use the stream's half-transfer and transfer-complete interrupts for double
+    /// buffering. Sets `SPDIFRX_CR` register, `RXDMAEN` field.
+    pub unsafe fn read_dma(&mut self, buf: &mut [u32], circular: bool, dma: &mut pac::DMA1) {
+        assert!(
+            buf.len() <= 65_535,
+            "DMA transfers are limited to 65,535 words."
+        );
+
+        free(|_| {
+            let rcc = &(*RCC::ptr());
+            rcc.ahb1enr.modify(|_, w| w.dma1en().set_bit());
+        });
+
+        let st = &dma.st[DMA_STREAM_DATA];
+        st.cr.modify(|_, w| w.en().clear_bit());
+        while st.cr.read().en().bit_is_set() {}
+
+        // The stream won't enable with any of its flags set. Stream 1 uses `LIFCR`.
+        dma.lifcr.write(|w| w.bits(DMA_STREAM_FLAGS << 6));
+
+        st.par.write(|w| w.bits(self.dr_addr()));
+        st.m0ar.write(|w| w.bits(buf.as_mut_ptr() as u32));
+        st.ndtr.write(|w| w.bits(buf.len() as u32));
+
+        st.cr.write(|w| {
+            w.chsel().bits(0);
+            // High priority: Audio overruns are audible.
+            w.pl().bits(0b10);
+            w.msize().bits(0b10);
+            w.psize().bits(0b10);
+            w.minc().set_bit();
+            w.circ().bit(circular);
+            w.dir().bits(0b00);
+            w.tcie().set_bit();
+            w.htie().bit(circular);
+            w.en().set_bit()
+        });
+
+        self.regs.cr.modify(|_, w| w.rxdmaen().set_bit());
+        self.enable();
+    }
+
+    #[cfg(feature = "f446")]
+    /// Stop a DMA transfer, and receiving. Disables the stream, and clears `SPDIFRX_CR` register,
+    /// `RXDMAEN` field.
+    pub fn stop_dma(&mut self, dma: &mut pac::DMA1) {
+        let cr = &dma.st[DMA_STREAM_DATA].cr;
+        cr.modify(|_, w| w.en().clear_bit());
+        while cr.read().en().bit_is_set() {}
+
+        self.disable();
+        self.regs.cr.modify(|_, w| w.rxdmaen().clear_bit());
+    }
+
+    /// Enable an interrupt. Sets `SPDIFRX_IMR` register.
+    pub fn enable_interrupt(&mut self, interrupt: SpdifInterrupt) {
+        self.regs.imr.modify(|_, w| match interrupt {
+            SpdifInterrupt::RxNotEmpty => w.rxneie().set_bit(),
+            SpdifInterrupt::ChannelStatusNotEmpty => w.csrneie().set_bit(),
+            SpdifInterrupt::Parity => w.perrie().set_bit(),
+            SpdifInterrupt::Overrun => w.ovrie().set_bit(),
+            SpdifInterrupt::StartOfBlock => w.sblkie().set_bit(),
+            SpdifInterrupt::SyncDone => w.syncdie().set_bit(),
+            SpdifInterrupt::Error => w.ifeie().set_bit(),
+        });
+    }
+
+    /// Disable an interrupt. Clears `SPDIFRX_IMR` register.
+    pub fn disable_interrupt(&mut self, interrupt: SpdifInterrupt) {
+        self.regs.imr.modify(|_, w| match interrupt {
+            SpdifInterrupt::RxNotEmpty => w.rxneie().clear_bit(),
+            SpdifInterrupt::ChannelStatusNotEmpty => w.csrneie().clear_bit(),
+            SpdifInterrupt::Parity => w.perrie().clear_bit(),
+            SpdifInterrupt::Overrun => w.ovrie().clear_bit(),
+            SpdifInterrupt::StartOfBlock => w.sblkie().clear_bit(),
+            SpdifInterrupt::SyncDone => w.syncdie().clear_bit(),
+            SpdifInterrupt::Error => w.ifeie().clear_bit(),
+        });
+    }
+
+    /// Clear an interrupt flag. Sets `SPDIFRX_IFCR` register. `RxNotEmpty` and
+    /// `ChannelStatusNotEmpty` are cleared by reading the data; `Error` by `disable`.
+    pub fn clear_interrupt(&mut self, interrupt: SpdifInterrupt) {
+        match interrupt {
+            SpdifInterrupt::Parity => self.regs.ifcr.write(|w| w.perrcf().set_bit()),
+            SpdifInterrupt::Overrun => self.regs.ifcr.write(|w| w.ovrcf().set_bit()),
+            SpdifInterrupt::StartOfBlock => self.regs.ifcr.write(|w| w.sbdcf().set_bit()),
+            SpdifInterrupt::SyncDone => self.regs.ifcr.write(|w| w.syncdcf().set_bit()),
+            _ => (),
+        }
+    }
+}
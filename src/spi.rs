@@ -4,16 +4,28 @@
 
 use core::{ops::Deref, ptr};
 
-use cortex_m::interrupt::free;
-
 #[cfg(feature = "embedded-hal")]
 use embedded_hal::spi::FullDuplex;
 
+#[cfg(feature = "async")]
+use embedded_hal_async::spi::{ErrorKind, ErrorType, SpiBus};
+
+#[cfg(feature = "async")]
+use crate::asynch::{self, SPI_WAKERS};
+
 use crate::{
+    delay,
+    gpio::Pin,
+    interrupt::InterruptPeriph,
     pac::{self, RCC},
-    util::RccPeriph,
+    util::{free, RccPeriph, RingBuffer},
 };
 
+#[cfg(not(feature = "h7"))]
+use crate::{clocks::Clocks, gpio::OutputSpeed, util::BaudPeriph};
+
+pub use crate::util::BitrateError;
+
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::util::DmaPeriph;
 
@@ -49,6 +61,35 @@ pub enum Error {
     Crc,
 }
 
+#[derive(Clone, Copy, Debug, Default)]
+/// A snapshot of an SPI peripheral's status, from `Spi::dump_state`; eg to log when communication
+/// stalls. Flags a family doesn't have read as `false` or 0.
+pub struct SpiState {
+    /// `CR1` register, `SPE` field.
+    pub enabled: bool,
+    /// `SR` register, `BSY` field. On H7, a transfer is started, and not at its end (`CSTART`,
+    /// and not `EOT`).
+    pub busy: bool,
+    /// `OVR` field.
+    pub overrun: bool,
+    /// `UDR` field, for slave transmission and I2S.
+    pub underrun: bool,
+    /// `MODF` field.
+    pub mode_fault: bool,
+    /// `CRCERR` field. (`CRCE` on H7)
+    pub crc_error: bool,
+    /// TI mode frame format error. `FRE` field. (`TIFRFE` or `TIFRE` on some families)
+    pub frame_error: bool,
+    /// `TXE` field. (`TXP` on H7)
+    pub tx_empty: bool,
+    /// `RXNE` field. (`RXP` on H7)
+    pub rx_not_empty: bool,
+    /// `FTLVL` field: 0 for empty, to 3 for full.
+    pub tx_fifo_level: u8,
+    /// `FRLVL` field: 0 for empty, to 3 for full. On H7, the `RXPLVL` field: the number of frames.
+    pub rx_fifo_level: u8,
+}
+
 /// Possible interrupt types. Enable these in CR2. Check and clear with SR. There is no explicit
 /// way to clear these.
 #[derive(Copy, Clone)]
@@ -152,6 +193,15 @@ pub enum SpiCommMode {
     ReceiveOnly,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+/// The data direction in half-duplex (bidirectional, or 3-wire) mode, where a single data line
+/// is shared for transmitting and receiving. Sets `SPI_CR1` register, `BIDIOE` field (`HDDIR` on
+/// H7).
+pub enum BidiDirection {
+    Receive = 0,
+    Transmit = 1,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 /// Used for managing NSS / CS pin. Sets CR1 register, SSM field.
 pub enum SlaveSelect {
@@ -174,6 +224,28 @@ pub enum SlaveSelect {
     HardwareOutDisable,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+/// Whether this device drives the clock (master), or responds to another device's clock (slave).
+/// Sets `SPI_CR1` register, `MSTR` field (`SPI_CFG2` register, `MASTER` field on H7).
+pub enum SpiRole {
+    Master,
+    /// In slave mode, the baud rate setting is ignored. With `SlaveSelect::Software`, the slave is
+    /// always selected; use `SlaveSelect::HardwareOutDisable` to use the NSS pin as a chip select input.
+    Slave,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The SPI frame format. Sets `SPI_CR2` register, `FRF` field (`SPI_CFG2` register, `SP` field on H7).
+pub enum FrameFormat {
+    /// The standard SPI protocol.
+    Motorola = 0,
+    /// The TI synchronous serial protocol, used by some DSPs and TI devices. The clock polarity,
+    /// phase, and slave select settings are ignored in this mode; NSS is pulsed before each frame
+    /// by hardware.
+    Ti = 1,
+}
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 /// Clock polarity. Sets CFGR2 register, CPOL field. Stored in the config as a field of `SpiMode`.
@@ -241,12 +313,22 @@ impl SpiMode {
 pub struct SpiConfig {
     /// SPI mode associated with Polarity and Phase. Defaults to Mode0: Idle low, capture on first transition.
     pub mode: SpiMode,
+    /// Master or slave. Defaults to master.
+    pub role: SpiRole,
+    /// Full duplex, half duplex (3-wire, using `BidiDirection`), or simplex. Defaults to full duplex.
     pub comm_mode: SpiCommMode,
     pub slave_select: SlaveSelect,
     /// Data size. Defaults to 8 bits.
     pub data_size: DataSize,
     /// FIFO reception threshhold. Defaults to 8 bits.
     pub fifo_reception_thresh: ReceptionThresh,
+    /// Frame format: Motorola (standard SPI), or TI. Defaults to Motorola.
+    pub frame_format: FrameFormat,
+    /// Pulse NSS high between each data frame, for devices that need a per-word chip select.
+    /// Requires `SlaveSelect::HardwareOutEnable`, the Motorola frame format, and capture on the first
+    /// clock transition (CPHA = 0). Sets `SPI_CR2` register, `NSSP` field (`SPI_CFG2` register,
+    /// `SSOM` field on H7). Not available on F4. Defaults to `false`.
+    pub nss_pulse: bool,
     // pub cs_delay: f32,
     // pub swap_miso_mosi: bool,
     // pub suspend_when_inactive: bool,
@@ -256,20 +338,129 @@ impl Default for SpiConfig {
     fn default() -> Self {
         Self {
             mode: SpiMode::mode0(),
+            role: SpiRole::Master,
             comm_mode: SpiCommMode::FullDuplex,
             slave_select: SlaveSelect::Software,
             data_size: DataSize::D8,
             fifo_reception_thresh: ReceptionThresh::D8,
+            frame_format: FrameFormat::Motorola,
+            nss_pulse: false,
         }
     }
 }
 
+/// One step of a transaction run by `Spi::transaction`. The variants other than `CsToggle` match
+/// `embedded-hal` 1.0's `spi::Operation`.
+pub enum SpiOperation<'a> {
+    /// Read into the buffer, writing 0s.
+    Read(&'a mut [u8]),
+    /// Write the buffer, discarding the bytes read.
+    Write(&'a [u8]),
+    /// Write the second buffer, while reading into the first. If they're different lengths, the
+    /// extra bytes read are discarded, or the extra bytes written are 0s.
+    Transfer(&'a mut [u8], &'a [u8]),
+    /// Write the buffer, replacing its contents with the bytes read.
+    TransferInPlace(&'a mut [u8]),
+    /// Wait this many nanoseconds, with CS asserted, and the clock idle.
+    DelayNs(u32),
+    /// Deassert CS, then re-assert it, using the hold, inactive, and setup times from `CsTiming`.
+    /// Eg for devices that latch a command on CS rising.
+    CsToggle,
+}
+
+/// The chip select used by `Spi::transaction`.
+pub enum ChipSelect<'a> {
+    /// The SPI's NSS pin, driven by hardware. Requires `SlaveSelect::HardwareOutEnable`. NSS is
+    /// low while the SPI is enabled, so the SPI is disabled between transactions; use
+    /// `transaction` for all communication with this setting.
+    Hardware,
+    /// A GPIO pin, configured as an output, driven by software.
+    Gpio(&'a mut Pin),
+}
+
+#[derive(Clone, Copy, Default)]
+/// Chip select timing for `Spi::transaction`, in nanoseconds. Defaults to 0 for each, ie only the
+/// time taken by the software between steps.
+pub struct CsTiming {
+    /// The minimum time between asserting CS, and the first clock edge.
+    pub setup_ns: u32,
+    /// The minimum time between the last clock edge, and deasserting CS.
+    pub hold_ns: u32,
+    /// The minimum time CS stays deasserted during `SpiOperation::CsToggle`.
+    pub inactive_ns: u32,
+}
+
 /// Represents a Serial Peripheral Interface (SPI) peripheral.
 pub struct Spi<R> {
     pub regs: R,
     pub cfg: SpiConfig,
 }
 
+impl<R: InterruptPeriph> Spi<R> {
+    /// The NVIC interrupt line this peripheral uses, eg for use with `interrupt::set_priority`.
+    pub fn interrupt(&self) -> pac::Interrupt {
+        R::INTERRUPT
+    }
+}
+
+#[cfg(not(feature = "h7"))]
+impl<R> Spi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Initialize an SPI peripheral, with the fastest baud rate at or below `freq`, in Hz.
+    /// Returns an error instead if `freq` is above the maximum for the SPI's kernel clock (half
+    /// of it), or for the pins' output speed setting, or below the minimum (1/256 of it).
+    pub fn try_new(
+        regs: R,
+        cfg: SpiConfig,
+        freq: u32,
+        pin_speed: OutputSpeed,
+        clocks: &Clocks,
+    ) -> Result<Self, BitrateError> {
+        let clock_freq = R::baud(clocks);
+
+        if freq > clock_freq / 2 {
+            return Err(BitrateError::AboveClock {
+                periph: "SPI",
+                requested: freq,
+                clock: R::clock_name(),
+                clock_freq,
+            });
+        }
+        if freq > pin_speed.max_freq() {
+            return Err(BitrateError::PinSpeed {
+                periph: "SPI",
+                requested: freq,
+                max: pin_speed.max_freq(),
+            });
+        }
+
+        let baud_rate = [
+            BaudRate::Div2,
+            BaudRate::Div4,
+            BaudRate::Div8,
+            BaudRate::Div16,
+            BaudRate::Div32,
+            BaudRate::Div64,
+            BaudRate::Div128,
+            BaudRate::Div256,
+        ]
+        .into_iter()
+        .find(|br| clock_freq >> (*br as u8 + 1) <= freq);
+
+        match baud_rate {
+            Some(br) => Ok(Self::new(regs, cfg, br)),
+            None => Err(BitrateError::BelowClock {
+                periph: "SPI",
+                requested: freq,
+                clock: R::clock_name(),
+                clock_freq,
+            }),
+        }
+    }
+}
+
 impl<R> Spi<R>
 where
     // R: Deref<Target = pac::spi1::RegisterBlock> + DmaPeriph + RccPeriph,
@@ -278,6 +469,17 @@ where
     /// Initialize an SPI peripheral, including configuration register writes, and enabling and resetting
     /// its RCC peripheral clock.
     pub fn new(regs: R, cfg: SpiConfig, baud_rate: BaudRate) -> Self {
+        if cfg.nss_pulse {
+            assert!(
+                cfg.frame_format == FrameFormat::Motorola
+                    && cfg.slave_select == SlaveSelect::HardwareOutEnable
+                    && cfg.mode.phase as u8 == 0,
+                "NSS pulse mode requires the Motorola frame format, hardware NSS output, and CPHA = 0."
+            );
+            #[cfg(feature = "f4")]
+            panic!("NSS pulse mode isn't available on F4.");
+        }
+
         free(|_| {
             let rcc = unsafe { &(*RCC::ptr()) };
             R::en_reset(rcc);
@@ -294,8 +496,8 @@ where
 
                 });
 
-                // ssi: select slave = master mode
-                regs.cr1.write(|w| w.ssi().slave_not_selected());
+                // ssi: In master mode, this must be set (slave not selected).
+                regs.cr1.write(|w| w.ssi().bit(cfg.role == SpiRole::Master));
 
                 // todo: Data size on H7.
 
@@ -329,10 +531,20 @@ where
                 // lsbfrst: MSB first
                 // comm: full-duplex
                 // todo: Flesh this out.
-                regs.cfg2.write(|w| {
+                regs.cfg2.write(|w| unsafe {
+                    w.sp().bits(cfg.frame_format as u8);
+                    w.comm().bits(match cfg.comm_mode {
+                        SpiCommMode::FullDuplex => 0b00,
+                        SpiCommMode::TransmitOnly => 0b01,
+                        SpiCommMode::ReceiveOnly => 0b10,
+                        SpiCommMode::HalfDuplex => 0b11,
+                    });
+                    // In NSS pulse mode, SS is driven inactive between data frames. (Requires SSOE)
+                    w.ssom().bit(cfg.nss_pulse);
+                    w.ssoe().bit(cfg.slave_select == SlaveSelect::HardwareOutEnable);
                     w.cpha().bit(cfg.mode.phase as u8 != 0);
                         w.cpol().bit(cfg.mode.polarity as u8 != 0);
-                        w.master().master();
+                        w.master().bit(cfg.role == SpiRole::Master);
                         w.lsbfrst().msbfirst()
                         // w.ssom().bit(config.suspend_when_inactive);
                         // w.ssm().bit(config.managed_cs == false);
@@ -344,7 +556,7 @@ where
                 });
 
                 // spe: enable the SPI bus
-                regs.cr1.write(|w| w.ssi().slave_not_selected().spe().enabled());
+                regs.cr1.write(|w| w.ssi().bit(cfg.role == SpiRole::Master).spe().enabled());
             } else {
                 // L44 RM, section 40.4.7: Configuration of SPI
                 // The configuration procedure is almost the same for master and slave. For specific mode
@@ -367,6 +579,12 @@ where
                     // c) Select simplex or half-duplex mode by configuring RXONLY or BIDIMODE and
                     // BIDIOE (RXONLY and BIDIMODE can't be set at the same time).
                     w.bidimode().bit(cfg.comm_mode == SpiCommMode::HalfDuplex);
+                    // Start half-duplex mode transmitting as a master, since receiving starts the
+                    // clock as soon as the SPI is enabled. A slave starts receiving, so it doesn't
+                    // drive the line before it's addressed.
+                    w.bidioe().bit(
+                        cfg.comm_mode == SpiCommMode::HalfDuplex && cfg.role == SpiRole::Master,
+                    );
                     w.rxonly().bit(cfg.comm_mode == SpiCommMode::ReceiveOnly);
                     // d) Configure the LSBFIRST bit to define the frame format (Note: 2).
                     w.lsbfirst().clear_bit();
@@ -375,16 +593,21 @@ where
                     w.crcen().clear_bit();
                     // f) Configure SSM and SSI (Notes: 2 & 3).
                     w.ssm().bit(cfg.slave_select == SlaveSelect::Software);
-                    w.ssi().set_bit(); // todo?
+                    // With software slave management, SSI sets the internal NSS level: High as a
+                    // master, and low (selected) as a slave.
+                    w.ssi().bit(cfg.role == SpiRole::Master);
                     // g) Configure the MSTR bit (in multimaster NSS configuration, avoid conflict state on
                     // NSS if master is configured to prevent MODF error).
-                    w.mstr().set_bit();
+                    w.mstr().bit(cfg.role == SpiRole::Master);
                     w.spe().set_bit() // Enable SPI
                 });
 
                 // 3. Write to SPI_CR2 register:
                 #[cfg(feature = "f4")]
-                regs.cr2.modify(|_, w| w.ssoe().bit(cfg.slave_select == SlaveSelect::HardwareOutEnable));
+                regs.cr2.modify(|_, w| {
+                    w.ssoe().bit(cfg.slave_select == SlaveSelect::HardwareOutEnable);
+                    w.frf().bit(cfg.frame_format == FrameFormat::Ti)
+                });
 
                 #[cfg(not(feature = "f4"))]
                 regs.cr2
@@ -393,15 +616,16 @@ where
                         w.ds().bits(cfg.data_size as u8);
                         // b) Configure SSOE (Notes: 1 & 2 & 3).
                         w.ssoe().bit(cfg.slave_select == SlaveSelect::HardwareOutEnable);
+                        // c) Set the FRF bit if the TI protocol is required (keep NSSP bit cleared in TI mode).
+                        w.frf().bit(cfg.frame_format == FrameFormat::Ti);
+                        // d) Set the NSSP bit if the NSS pulse mode between two data units is required (keep
+                        // CHPA and TI bits cleared in NSSP mode).
+                        w.nssp().bit(cfg.nss_pulse);
                         // e) Configure the FRXTH bit. The RXFIFO threshold must be aligned to the read
                         // access size for the SPIx_DR register.
                         w.frxth().bit(cfg.fifo_reception_thresh as u8 != 0)
                     });
 
-                // c) Set the FRF bit if the TI protocol is required (keep NSSP bit cleared in TI mode).
-                // d) Set the NSSP bit if the NSS pulse mode between two data units is required (keep
-                // CHPA and TI bits cleared in NSSP mode).
-
                 // f) Initialize LDMA_TX and LDMA_RX bits if DMA is used in packed mode.
                 // 4. Write to SPI_CRCPR register: Configure the CRC polynomial if needed.
                 // 5. Write proper DMA registers: Configure DMA streams dedicated for SPI Tx and Rx in
@@ -474,9 +698,38 @@ where
         }
     }
 
+    /// Disable the SPI using the procedure in `disable()`, and return the PAC register block, eg
+    /// to reconfigure its pins for other uses. If `gate_clock` is `true`, also disable
+    /// its RCC peripheral clock.
+    pub fn free(mut self, gate_clock: bool) -> R {
+        self.disable();
+
+        if gate_clock {
+            free(|_| {
+                let rcc = unsafe { &(*RCC::ptr()) };
+                R::disable_clock(rcc);
+            });
+        }
+
+        self.regs
+    }
+
+    /// The number of bits in each data frame.
+    fn frame_bits(&self) -> u8 {
+        // On all families, the `DS` (`DSIZE` on H7) field value is the number of bits, minus 1.
+        self.cfg.data_size as u8 + 1
+    }
+
     /// Read a single byte if available, or block until it's available.
     /// See L44 RM, section 40.4.9: Data transmission and reception procedures.
     pub fn read(&mut self) -> nb::Result<u8, Error> {
+        self.read_word().map(|w| w as u8)
+    }
+
+    /// Read a single data frame if available, or block until it's available. The data register
+    /// is accessed with a width matching the configured data size: 8 bits for frames up to 8
+    /// bits, 16 bits for frames up to 16 bits, and 32 bits for larger frames (H7 only).
+    pub fn read_word(&mut self) -> nb::Result<u32, Error> {
         let sr = self.regs.sr.read();
 
         cfg_if! {
@@ -497,10 +750,19 @@ where
             Err(nb::Error::Other(Error::Crc))
         } else if not_empty {
             #[cfg(feature = "h7")]
-            // todo: note: H7 can support words beyond u8. (Can others too?)
-            let result = unsafe { ptr::read_volatile(&self.regs.rxdr as *const _ as *const u8) };
+            let addr = self.regs.rxdr.as_ptr();
             #[cfg(not(feature = "h7"))]
-            let result = unsafe { ptr::read_volatile(&self.regs.dr as *const _ as *const u8) };
+            let addr = self.regs.dr.as_ptr();
+
+            // RM: "The data register access must be aligned with the data size", or the FIFO
+            // packs or splits frames.
+            let result = unsafe {
+                match self.frame_bits() {
+                    0..=8 => ptr::read_volatile(addr as *const u8) as u32,
+                    9..=16 => ptr::read_volatile(addr as *const u16) as u32,
+                    _ => ptr::read_volatile(addr as *const u32),
+                }
+            };
             Ok(result)
         } else {
             Err(nb::Error::WouldBlock)
@@ -510,6 +772,12 @@ where
     /// Write a single byte if available, or block until it's available.
     /// See L44 RM, section 40.4.9: Data transmission and reception procedures.
     pub fn write_one(&mut self, byte: u8) -> nb::Result<(), Error> {
+        self.write_one_word(byte as u32)
+    }
+
+    /// Write a single data frame if available, or block until it's available. The data register
+    /// is accessed with a width matching the configured data size; see `read_word`.
+    pub fn write_one_word(&mut self, word: u32) -> nb::Result<(), Error> {
         let sr = self.regs.sr.read();
 
         cfg_if! {
@@ -529,17 +797,23 @@ where
         } else if crce {
             Err(nb::Error::Other(Error::Crc))
         } else if rdy {
-            cfg_if! {
-                if #[cfg(feature = "h7")] {
-                    // todo: note: H7 can support words beyond u8. (Can others too?)
-                    unsafe { ptr::write_volatile(&self.regs.txdr as *const _ as *mut u8, byte) };
-                    // write CSTART to start a transaction in master mode
-                    self.regs.cr1.modify(|_, w| w.cstart().started());
-                }
-                 else {
-                    unsafe { ptr::write_volatile(&self.regs.dr as *const _ as *mut u8, byte) };
+            #[cfg(feature = "h7")]
+            let addr = self.regs.txdr.as_ptr();
+            #[cfg(not(feature = "h7"))]
+            let addr = self.regs.dr.as_ptr();
+
+            unsafe {
+                match self.frame_bits() {
+                    0..=8 => ptr::write_volatile(addr as *mut u8, word as u8),
+                    9..=16 => ptr::write_volatile(addr as *mut u16, word as u16),
+                    _ => ptr::write_volatile(addr as *mut u32, word),
                 }
             }
+
+            // write CSTART to start a transaction in master mode
+            #[cfg(feature = "h7")]
+            self.regs.cr1.modify(|_, w| w.cstart().started());
+
             Ok(())
         } else {
             Err(nb::Error::WouldBlock)
@@ -568,6 +842,308 @@ where
         Ok(())
     }
 
+    /// Run a list of operations as one transaction, with CS asserted for its duration, matching
+    /// `embedded-hal` 1.0's `SpiDevice::transaction`. CS is deasserted at the end, including if an
+    /// operation fails. Blocks until complete.
+    pub fn transaction(
+        &mut self,
+        mut cs: ChipSelect,
+        timing: CsTiming,
+        operations: &mut [SpiOperation],
+    ) -> Result<(), Error> {
+        // With hardware NSS, make sure NSS is high first, so the device sees a falling edge.
+        if let ChipSelect::Hardware = cs {
+            self.set_cs(&mut cs, false);
+        }
+
+        self.set_cs(&mut cs, true);
+        delay::delay_ns(timing.setup_ns);
+
+        let mut result = Ok(());
+        for op in operations.iter_mut() {
+            result = self.run_operation(op, &mut cs, timing);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        self.wait_tx_complete();
+        delay::delay_ns(timing.hold_ns);
+        self.set_cs(&mut cs, false);
+
+        result
+    }
+
+    /// Run a single transaction operation.
+    fn run_operation(
+        &mut self,
+        op: &mut SpiOperation,
+        cs: &mut ChipSelect,
+        timing: CsTiming,
+    ) -> Result<(), Error> {
+        match op {
+            SpiOperation::Read(buf) => {
+                for word in buf.iter_mut() {
+                    nb::block!(self.write_one(0))?;
+                    *word = nb::block!(self.read())?;
+                }
+            }
+            SpiOperation::Write(buf) => self.write(buf)?,
+            SpiOperation::Transfer(read, write) => {
+                for i in 0..read.len().max(write.len()) {
+                    nb::block!(self.write_one(write.get(i).copied().unwrap_or(0)))?;
+                    let word = nb::block!(self.read())?;
+                    if let Some(r) = read.get_mut(i) {
+                        *r = word;
+                    }
+                }
+            }
+            SpiOperation::TransferInPlace(buf) => self.transfer(buf)?,
+            SpiOperation::DelayNs(ns) => {
+                self.wait_tx_complete();
+                delay::delay_ns(*ns);
+            }
+            SpiOperation::CsToggle => {
+                self.wait_tx_complete();
+                delay::delay_ns(timing.hold_ns);
+                self.set_cs(cs, false);
+                delay::delay_ns(timing.inactive_ns);
+                self.set_cs(cs, true);
+                delay::delay_ns(timing.setup_ns);
+            }
+        }
+        Ok(())
+    }
+
+    /// Assert (`true`) or deassert CS. With hardware NSS, this enables or disables the SPI.
+    fn set_cs(&mut self, cs: &mut ChipSelect, asserted: bool) {
+        match cs {
+            ChipSelect::Hardware => {
+                if asserted {
+                    self.regs.cr1.modify(|_, w| w.spe().set_bit());
+                } else {
+                    self.wait_tx_complete();
+                    self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+                }
+            }
+            ChipSelect::Gpio(pin) => {
+                if asserted {
+                    pin.set_low();
+                } else {
+                    pin.set_high();
+                }
+            }
+        }
+    }
+
+    /// Wait until the transmit FIFO is empty, and the last frame has been sent.
+    fn wait_tx_complete(&self) {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                while self.regs.sr.read().txc().bit_is_clear() {}
+            } else {
+                #[cfg(not(feature = "f4"))]
+                while self.regs.sr.read().ftlvl().bits() != 0 {}
+                while self.regs.sr.read().txe().bit_is_clear() {}
+                while self.regs.sr.read().bsy().bit_is_set() {}
+            }
+        }
+    }
+
+    /// Set the data direction in half-duplex (3-wire) mode. Waits for any transmission in progress
+    /// to complete before switching. Note that as a master, setting `Receive` while the SPI is
+    /// enabled starts the clock immediately, and it runs until the SPI is disabled; consider
+    /// `read_half_duplex` instead. Sets `SPI_CR1` register, `BIDIOE` field (`HDDIR` on H7).
+    pub fn set_direction(&mut self, direction: BidiDirection) {
+        assert!(
+            self.cfg.comm_mode == SpiCommMode::HalfDuplex,
+            "The SPI must be configured for half-duplex mode."
+        );
+
+        self.wait_tx_complete();
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                self.regs.cr1.modify(|_, w| w.hddir().bit(direction == BidiDirection::Transmit));
+            } else {
+                self.regs.cr1.modify(|_, w| w.bidioe().bit(direction == BidiDirection::Transmit));
+            }
+        }
+    }
+
+    #[cfg(not(feature = "h7"))]
+    /// Write multiple bytes in half-duplex (3-wire) mode, blocking until they've been sent. Switches
+    /// to the transmit direction first, if required. As a slave, switches back to receiving
+    /// afterwards, so it only drives the line while transmitting.
+    pub fn write_half_duplex(&mut self, words: &[u8]) -> Result<(), Error> {
+        self.set_direction(BidiDirection::Transmit);
+
+        let mut result = Ok(());
+        for word in words {
+            if let Err(e) = nb::block!(self.write_one(*word)) {
+                result = Err(e);
+                break;
+            }
+        }
+
+        self.wait_tx_complete();
+
+        if self.cfg.role == SpiRole::Slave {
+            self.set_direction(BidiDirection::Receive);
+        }
+
+        result
+    }
+
+    #[cfg(not(feature = "h7"))]
+    /// Read multiple bytes in half-duplex (3-wire) mode, as a master, blocking until complete. This
+    /// generates the clock for exactly the frames requested, then switches back to the transmit
+    /// direction, so the line isn't left driven by both sides.
+    /// See L44 RM, section 40.4.9: Procedure for disabling the SPI, for receive-only modes.
+    pub fn read_half_duplex(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        if words.is_empty() {
+            return Ok(());
+        }
+
+        // Switch direction with the SPI disabled, then enable it to start the clock.
+        self.wait_tx_complete();
+        self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+        self.regs.cr1.modify(|_, w| w.bidioe().clear_bit());
+        self.regs.cr1.modify(|_, w| w.spe().set_bit());
+
+        let last = words.len() - 1;
+        let mut result = Ok(());
+
+        for (i, word) in words.iter_mut().enumerate() {
+            // "Interrupt the receive flow by disabling SPI (SPE=0) in the specific time window
+            // while the last data frame is ongoing."
+            if i == last {
+                self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+            }
+
+            match nb::block!(self.read()) {
+                Ok(w) => *word = w,
+                Err(e) => {
+                    self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        // "Wait until BSY=0 (the last data frame is processed). Read data until FRLVL[1:0] = 00
+        // (read all the received data)." Frames clocked in before SPE was cleared are discarded.
+        while self.regs.sr.read().bsy().bit_is_set() {}
+        while self.regs.sr.read().rxne().bit_is_set() {
+            unsafe { ptr::read_volatile(self.regs.dr.as_ptr() as *const u8) };
+        }
+
+        self.regs.cr1.modify(|_, w| w.bidioe().set_bit());
+        self.regs.cr1.modify(|_, w| w.spe().set_bit());
+
+        result
+    }
+
+    /// Write multiple data frames of 9 to 16 bits, blocking until complete. Set the data size to
+    /// match first, eg with `set_data_size`.
+    pub fn write_u16(&mut self, words: &[u16]) -> Result<(), Error> {
+        for word in words {
+            nb::block!(self.write_one_word(*word as u32))?;
+            nb::block!(self.read_word())?;
+        }
+
+        Ok(())
+    }
+
+    /// Write and read multiple data frames of 9 to 16 bits, in place, blocking until complete.
+    pub fn transfer_u16(&mut self, words: &mut [u16]) -> Result<(), Error> {
+        for word in words.iter_mut() {
+            nb::block!(self.write_one_word(*word as u32))?;
+            *word = nb::block!(self.read_word())? as u16;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "h7")]
+    /// Write multiple data frames of 17 to 32 bits, blocking until complete.
+    pub fn write_u32(&mut self, words: &[u32]) -> Result<(), Error> {
+        for word in words {
+            nb::block!(self.write_one_word(*word))?;
+            nb::block!(self.read_word())?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "h7")]
+    /// Write and read multiple data frames of 17 to 32 bits, in place, blocking until complete.
+    pub fn transfer_u32(&mut self, words: &mut [u32]) -> Result<(), Error> {
+        for word in words.iter_mut() {
+            nb::block!(self.write_one_word(*word))?;
+            *word = nb::block!(self.read_word())?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Change the number of bits in each data frame, eg for devices with odd word lengths. Sets
+    /// `SPI_CR2` register, `DS` field (`SPI_CFG1` register, `DSIZE` field on H7). On families other
+    /// than H7, this also sets the RX FIFO threshold to match: 8 bits for frames up to 8 bits, and
+    /// 16 bits for larger frames.
+    pub fn set_data_size(&mut self, size: DataSize) {
+        // RM: "This field must be written when the SPI is disabled (SPE=0)" (H7), and the FIFO
+        // must not contain data of the previous size.
+        self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+
+        self.cfg.data_size = size;
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                self.regs.cfg1.modify(|_, w| unsafe { w.dsize().bits(size as u8) });
+            } else {
+                let thresh = if self.frame_bits() <= 8 {
+                    ReceptionThresh::D8
+                } else {
+                    ReceptionThresh::D16
+                };
+                self.cfg.fifo_reception_thresh = thresh;
+
+                self.regs.cr2.modify(|_, w| unsafe {
+                    w.ds().bits(size as u8);
+                    w.frxth().bit(thresh as u8 != 0)
+                });
+            }
+        }
+
+        self.regs.cr1.modify(|_, w| w.spe().set_bit());
+    }
+
+    #[cfg(not(any(feature = "f4", feature = "h7")))]
+    /// Set the RX FIFO threshold; RXNE is set when this much data is in the FIFO. This must match
+    /// the data register read access size. Sets `SPI_CR2` register, `FRXTH` field.
+    pub fn set_reception_thresh(&mut self, thresh: ReceptionThresh) {
+        self.cfg.fifo_reception_thresh = thresh;
+        self.regs
+            .cr2
+            .modify(|_, w| w.frxth().bit(thresh as u8 != 0));
+    }
+
+    #[cfg(feature = "h7")]
+    /// Set the FIFO threshold level, in data frames (1 - 16). This is the number of frames in each
+    /// packet; `RXP` and `TXP` are set when a full packet can be read or written. Sets `SPI_CFG1`
+    /// register, `FTHLV` field. Note that the FIFO size limits this for large frame sizes.
+    pub fn set_fifo_thresh(&mut self, frames: u8) {
+        assert!((1..=16).contains(&frames), "FIFO threshold must be 1 - 16 frames.");
+
+        self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+        self.regs
+            .cfg1
+            .modify(|_, w| unsafe { w.fthlv().bits(frames - 1) });
+        self.regs.cr1.modify(|_, w| w.spe().set_bit());
+    }
+
     #[cfg(not(any(feature = "g0", feature = "f4", feature = "l5")))]
     /// Transmit data using DMA. See L44 RM, section 40.4.9: Communication using DMA.
     /// Note that the `channel` argument has no effect on F3 and L4.
@@ -704,6 +1280,60 @@ where
         })
     }
 
+    /// Read the peripheral's status flags and FIFO levels, without modifying them. Reading
+    /// `SR` doesn't clear any flags.
+    pub fn dump_state(&self) -> SpiState {
+        let sr = self.regs.sr.read();
+
+        let mut result = SpiState {
+            enabled: self.regs.cr1.read().spe().bit_is_set(),
+            overrun: sr.ovr().bit_is_set(),
+            mode_fault: sr.modf().bit_is_set(),
+            ..Default::default()
+        };
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                result.busy = self.regs.cr1.read().cstart().bit_is_set() && sr.eot().bit_is_clear();
+                result.underrun = sr.udr().bit_is_set();
+                result.crc_error = sr.crce().bit_is_set();
+                result.frame_error = sr.tifre().bit_is_set();
+                result.tx_empty = sr.txp().bit_is_set();
+                result.rx_not_empty = sr.rxp().bit_is_set();
+                result.rx_fifo_level = sr.rxplvl().bits();
+            } else {
+                result.busy = sr.bsy().bit_is_set();
+                result.crc_error = sr.crcerr().bit_is_set();
+                result.tx_empty = sr.txe().bit_is_set();
+                result.rx_not_empty = sr.rxne().bit_is_set();
+            }
+        }
+
+        #[cfg(any(feature = "f3", feature = "f4", feature = "g0", feature = "wl"))]
+        {
+            result.underrun = sr.udr().bit_is_set();
+        }
+
+        cfg_if! {
+            if #[cfg(all(
+                any(feature = "f3", feature = "f4", feature = "l4", feature = "wl"),
+                not(feature = "f373")
+            ))] {
+                result.frame_error = sr.fre().bit_is_set();
+            } else if #[cfg(not(feature = "h7"))] {
+                result.frame_error = sr.tifrfe().bit_is_set();
+            }
+        }
+
+        #[cfg(not(any(feature = "f4", feature = "h7")))]
+        {
+            result.tx_fifo_level = sr.ftlvl().bits();
+            result.rx_fifo_level = sr.frlvl().bits();
+        }
+
+        result
+    }
+
     #[cfg(not(feature = "h7"))]
     /// Enable an interrupt. Note that unlike on other peripherals, there's no explicit way to
     /// clear these. RM: "Writing to the transmit data register always clears the TXE bit.
@@ -717,6 +1347,183 @@ where
     }
 }
 
+/// Runs an SPI slave from its interrupt handler, without DMA. Each received byte is pushed to a
+/// receive ring buffer, and the transmit buffer is kept fed from a queue of bytes to send; when
+/// the queue is empty, an idle byte is sent. This is suited to register-style protocols with a
+/// host MCU: Parse commands from `read`, and queue responses with `queue_tx`. Uses 8-bit frames.
+///
+/// Note that bytes are loaded into the transmit FIFO before the master clocks them out, so a
+/// response queued after receiving a command is sent after the bytes already in the FIFO (Up to
+/// 2 idle bytes with the 32-bit FIFO; 1 on F4, and up to 8 on H7, depending on its FIFO size).
+/// The host should send that many dummy bytes between a command and reading its response.
+///
+/// Example, with the SPI configured with `role: SpiRole::Slave`:
+/// `static SPI_SLAVE: Mutex<RefCell<Option<SpiSlaveService<SPI1, 64>>>> = Mutex::new(RefCell::new(None));`
+/// `let slave = SpiSlaveService::new(spi, 0xff);`
+/// In the SPI1 interrupt handler: `free(|cs| { access_global!(SPI_SLAVE, slave, cs); slave.on_interrupt(); });`
+pub struct SpiSlaveService<R, const N: usize> {
+    pub spi: Spi<R>,
+    /// The byte sent when the transmit queue is empty.
+    pub idle_byte: u8,
+    rx: RingBuffer<N>,
+    tx: RingBuffer<N>,
+    /// Received bytes dropped because the receive buffer was full, or due to an overrun.
+    rx_dropped: usize,
+}
+
+impl<R, const N: usize> SpiSlaveService<R, N>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    /// Start servicing the slave: Enables the receive and transmit interrupts. `spi` must be
+    /// configured as a slave, with 8-bit frames.
+    pub fn new(spi: Spi<R>, idle_byte: u8) -> Self {
+        assert!(
+            spi.cfg.role == SpiRole::Slave,
+            "The SPI must be configured as a slave."
+        );
+
+        let result = Self {
+            spi,
+            idle_byte,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            rx_dropped: 0,
+        };
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                result.spi.regs.ier.modify(|_, w| {
+                    w.rxpie().set_bit();
+                    w.txpie().set_bit()
+                });
+            } else {
+                result.spi.regs.cr2.modify(|_, w| {
+                    w.rxneie().set_bit();
+                    w.txeie().set_bit()
+                });
+            }
+        }
+
+        result
+    }
+
+    /// Stop servicing the slave: Disables the receive and transmit interrupts, and returns the SPI.
+    pub fn free(self) -> Spi<R> {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                self.spi.regs.ier.modify(|_, w| {
+                    w.rxpie().clear_bit();
+                    w.txpie().clear_bit()
+                });
+            } else {
+                self.spi.regs.cr2.modify(|_, w| {
+                    w.rxneie().clear_bit();
+                    w.txeie().clear_bit()
+                });
+            }
+        }
+
+        self.spi
+    }
+
+    /// Call this from the SPI's interrupt handler. Moves received bytes to the receive buffer,
+    /// and fills the transmit FIFO from the transmit queue. Overruns are cleared, and counted as
+    /// dropped bytes.
+    pub fn on_interrupt(&mut self) {
+        loop {
+            match self.spi.read() {
+                Ok(byte) => {
+                    if !self.rx.push(byte) {
+                        self.rx_dropped += 1;
+                    }
+                }
+                Err(nb::Error::Other(Error::Overrun)) => {
+                    self.clear_overrun();
+                    self.rx_dropped += 1;
+                }
+                _ => break,
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                let addr = self.spi.regs.txdr.as_ptr() as *mut u8;
+                while self.spi.regs.sr.read().txp().bit_is_set() {
+                    let byte = self.tx.pop().unwrap_or(self.idle_byte);
+                    unsafe { ptr::write_volatile(addr, byte) };
+                }
+            } else {
+                let addr = self.spi.regs.dr.as_ptr() as *mut u8;
+                while self.spi.regs.sr.read().txe().bit_is_set() {
+                    let byte = self.tx.pop().unwrap_or(self.idle_byte);
+                    unsafe { ptr::write_volatile(addr, byte) };
+                }
+            }
+        }
+    }
+
+    /// Clear the overrun flag. RM: "Clearing the OVR bit is done by a read access to the SPI_DR
+    /// register followed by a read access to the SPI_SR register." (Sets `SPI_IFCR` register,
+    /// `OVRC` field on H7.)
+    fn clear_overrun(&mut self) {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                self.spi.regs.ifcr.write(|w| w.ovrc().set_bit());
+            } else {
+                let _ = unsafe { ptr::read_volatile(self.spi.regs.dr.as_ptr() as *const u8) };
+                let _ = self.spi.regs.sr.read();
+            }
+        }
+    }
+
+    /// Queue bytes to send. Returns the number queued, which is less than `data.len()` if the
+    /// queue is full.
+    pub fn queue_tx(&mut self, data: &[u8]) -> usize {
+        data.iter().take_while(|b| self.tx.push(**b)).count()
+    }
+
+    /// Move received bytes into `buf`, oldest first. Returns the number of bytes read.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut count = 0;
+        for word in buf.iter_mut() {
+            match self.rx.pop() {
+                Some(b) => *word = b,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// The number of received bytes waiting to be read.
+    pub fn rx_len(&self) -> usize {
+        self.rx.len
+    }
+
+    /// The number of queued bytes not yet loaded into the transmit FIFO.
+    pub fn tx_len(&self) -> usize {
+        self.tx.len
+    }
+
+    /// Discard queued bytes that haven't been loaded into the transmit FIFO, eg when the host
+    /// deselects the slave partway through a response.
+    pub fn clear_tx(&mut self) {
+        self.tx.clear();
+    }
+
+    /// Discard received bytes that haven't been read.
+    pub fn clear_rx(&mut self) {
+        self.rx.clear();
+    }
+
+    /// The number of received bytes dropped since this was created, because the receive buffer
+    /// was full, or due to an overrun.
+    pub fn rx_dropped(&self) -> usize {
+        self.rx_dropped
+    }
+}
+
 #[cfg(feature = "embedded-hal")]
 // #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
 impl<R> FullDuplex<u8> for Spi<R>
@@ -747,3 +1554,186 @@ impl<R> embedded_hal::blocking::spi::write::Default<u8> for Spi<R> where
     R: Deref<Target = pac::spi1::RegisterBlock> + DmaPeriph + RccPeriph
 {
 }
+
+#[cfg(feature = "async")]
+/// Interrupt handler for async reads and writes. Masks the interrupts enabled while awaiting,
+/// and wakes the awaiting task. Set this up using the `async_interrupt!` macro.
+pub fn on_interrupt_async(regs: &pac::spi1::RegisterBlock) {
+    cfg_if! {
+        if #[cfg(feature = "h7")] {
+            regs.ier.modify(|_, w| {
+                w.txpie().clear_bit();
+                w.rxpie().clear_bit();
+                w.ovrie().clear_bit();
+                w.modfie().clear_bit()
+            });
+        } else {
+            regs.cr2.modify(|_, w| {
+                w.txeie().clear_bit();
+                w.rxneie().clear_bit();
+                w.errie().clear_bit()
+            });
+        }
+    }
+
+    SPI_WAKERS.wake(regs as *const _ as usize);
+}
+
+#[cfg(feature = "async")]
+impl<R> Spi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    /// Read a single byte, waiting asynchronously until it's available. Requires the SPI
+    /// interrupt handler to be set up with `async_interrupt!`.
+    pub async fn read_async(&mut self) -> Result<u8, Error> {
+        loop {
+            {
+                let regs = &*self.regs;
+
+                asynch::wait_for(
+                    &SPI_WAKERS,
+                    regs as *const _ as usize,
+                    || {
+                        let sr = regs.sr.read();
+                        #[cfg(feature = "h7")]
+                        let not_empty = sr.rxp().bit_is_set();
+                        #[cfg(not(feature = "h7"))]
+                        let not_empty = sr.rxne().bit_is_set();
+
+                        not_empty || sr.ovr().bit_is_set() || sr.modf().bit_is_set()
+                    },
+                    || {
+                        #[cfg(feature = "h7")]
+                        regs.ier.modify(|_, w| {
+                            w.rxpie().set_bit();
+                            w.ovrie().set_bit();
+                            w.modfie().set_bit()
+                        });
+                        #[cfg(not(feature = "h7"))]
+                        regs.cr2.modify(|_, w| w.rxneie().set_bit().errie().set_bit());
+                    },
+                )
+                .await;
+            }
+
+            match self.read() {
+                Ok(v) => return Ok(v),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) => (),
+            }
+        }
+    }
+
+    /// Write a single byte, waiting asynchronously until the Tx buffer is empty. Requires the SPI
+    /// interrupt handler to be set up with `async_interrupt!`.
+    pub async fn write_one_async(&mut self, byte: u8) -> Result<(), Error> {
+        loop {
+            {
+                let regs = &*self.regs;
+
+                asynch::wait_for(
+                    &SPI_WAKERS,
+                    regs as *const _ as usize,
+                    || {
+                        let sr = regs.sr.read();
+                        #[cfg(feature = "h7")]
+                        let rdy = sr.txp().bit_is_set();
+                        #[cfg(not(feature = "h7"))]
+                        let rdy = sr.txe().bit_is_set();
+
+                        rdy || sr.ovr().bit_is_set() || sr.modf().bit_is_set()
+                    },
+                    || {
+                        #[cfg(feature = "h7")]
+                        regs.ier.modify(|_, w| {
+                            w.txpie().set_bit();
+                            w.ovrie().set_bit();
+                            w.modfie().set_bit()
+                        });
+                        #[cfg(not(feature = "h7"))]
+                        regs.cr2.modify(|_, w| w.txeie().set_bit().errie().set_bit());
+                    },
+                )
+                .await;
+            }
+
+            match self.write_one(byte) {
+                Ok(()) => return Ok(()),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) => (),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::spi::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Overrun => ErrorKind::Overrun,
+            Self::ModeFault => ErrorKind::ModeFault,
+            Self::Crc => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R> ErrorType for Spi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    type Error = Error;
+}
+
+#[cfg(feature = "async")]
+impl<R> SpiBus<u8> for Spi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        for word in words.iter_mut() {
+            self.write_one_async(0).await?;
+            *word = self.read_async().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+        for word in words {
+            self.write_one_async(*word).await?;
+            self.read_async().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+        for i in 0..read.len().max(write.len()) {
+            self.write_one_async(write.get(i).copied().unwrap_or(0)).await?;
+            let word = self.read_async().await?;
+
+            if let Some(r) = read.get_mut(i) {
+                *r = word;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        for word in words.iter_mut() {
+            self.write_one_async(*word).await?;
+            *word = self.read_async().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        // Each word written above is read back before returning, so there's nothing
+        // left to flush.
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+//! This module contains system configuration (SYSCFG) functionality that isn't tied to a specific
+//! peripheral: Remapping memory at address `0x0000_0000`, eg for custom bootloaders or for executing
+//! from SRAM, enabling CCM RAM, and enabling FPU interrupts.
+//!
+//! The SYSCFG peripheral clock must be enabled before using these; `Clocks::setup` does this.
+//! Note that L5 selects the memory at `0x0000_0000` using option bytes instead of SYSCFG.
+
+use crate::pac::SYSCFG;
+
+#[cfg(any(feature = "f429", feature = "f469"))]
+use crate::pac::RCC;
+
+#[cfg(any(feature = "f429", feature = "f469"))]
+use crate::util::free;
+
+use cfg_if::cfg_if;
+
+#[cfg(not(feature = "l5"))]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Selects the memory mapped at address `0x0000_0000`. Sets `SYSCFG_MEMRMP` register (`MEMRM` on F4,
+/// `CFGR1` on F3), `MEM_MODE` field.
+pub enum MemMode {
+    /// Main flash memory. This is the reset state when booting from flash.
+    MainFlash = 0b000,
+    /// System flash memory, which contains the built-in bootloader.
+    SystemFlash = 0b001,
+    /// Embedded SRAM (SRAM1 on families that have more than one SRAM block).
+    Sram = 0b011,
+}
+
+#[cfg(not(feature = "l5"))]
+/// Remap the memory at address `0x0000_0000`. After remapping, the selected memory is accessible
+/// both at `0x0000_0000`, and at its original address. This is used to run code from SRAM with a vector
+/// table at address 0, or to jump to the system bootloader.
+pub fn remap_memory(syscfg: &mut SYSCFG, mode: MemMode) {
+    cfg_if! {
+        if #[cfg(feature = "f3")] {
+            syscfg.cfgr1.modify(|_, w| match mode {
+                MemMode::MainFlash => w.mem_mode().main_flash(),
+                MemMode::SystemFlash => w.mem_mode().system_flash(),
+                MemMode::Sram => w.mem_mode().sram(),
+            });
+        } else if #[cfg(feature = "wl")] {
+            syscfg.memrmp.modify(|_, w| match mode {
+                MemMode::MainFlash => w.mem_mode().main_flash(),
+                MemMode::SystemFlash => w.mem_mode().system_flash(),
+                MemMode::Sram => w.mem_mode().sram(),
+            });
+        } else if #[cfg(feature = "f4")] {
+            syscfg.memrm.modify(|_, w| unsafe { w.mem_mode().bits(mode as u8) });
+        } else {
+            syscfg.memrmp.modify(|_, w| unsafe { w.mem_mode().bits(mode as u8) });
+        }
+    }
+}
+
+#[cfg(any(feature = "f429", feature = "f469"))]
+/// Enable the core-coupled memory (CCM) data RAM. Sets `RCC_AHB1ENR` register, `CCMDATARAMEN` field.
+/// (This is a RCC setting, but is here to keep memory configuration in one place.)
+pub fn enable_ccm_ram() {
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.ahb1enr.modify(|_, w| w.ccmdataramen().set_bit());
+    });
+}
+
+#[cfg(not(any(feature = "f4", feature = "wl")))]
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// FPU exceptions that can trigger the FPU global interrupt. The values are the bit positions
+/// in the `FPU_IE` field of the `SYSCFG_CFGR1` register (`FPUIMR` on L5).
+pub enum FpuInterrupt {
+    InvalidOperation = 0,
+    DivideByZero = 1,
+    Underflow = 2,
+    Overflow = 3,
+    InputDenormal = 4,
+    Inexact = 5,
+}
+
+#[cfg(not(any(feature = "f4", feature = "wl")))]
+/// Enable or disable an FPU exception as a source of the FPU global interrupt. Sets `SYSCFG_CFGR1`
+/// register (`FPUIMR` on L5), `FPU_IE` field.
+pub fn set_fpu_interrupt(syscfg: &mut SYSCFG, interrupt: FpuInterrupt, enabled: bool) {
+    cfg_if! {
+        if #[cfg(feature = "f3")] {
+            syscfg.cfgr1.modify(|_, w| match interrupt {
+                FpuInterrupt::InvalidOperation => w.fpu_ie0().bit(enabled),
+                FpuInterrupt::DivideByZero => w.fpu_ie1().bit(enabled),
+                FpuInterrupt::Underflow => w.fpu_ie2().bit(enabled),
+                FpuInterrupt::Overflow => w.fpu_ie3().bit(enabled),
+                FpuInterrupt::InputDenormal => w.fpu_ie4().bit(enabled),
+                FpuInterrupt::Inexact => w.fpu_ie5().bit(enabled),
+            });
+        } else {
+            let mask = 1 << interrupt as u8;
+
+            #[cfg(feature = "l5")]
+            let reg = &syscfg.fpuimr;
+            #[cfg(not(feature = "l5"))]
+            let reg = &syscfg.cfgr1;
+
+            reg.modify(|r, w| unsafe {
+                let val = r.fpu_ie().bits();
+                w.fpu_ie().bits(if enabled { val | mask } else { val & !mask })
+            });
+        }
+    }
+}
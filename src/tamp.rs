@@ -0,0 +1,358 @@
+//! Support for the tamper and backup registers block (TAMP), on L5, G4, and WL. It detects
+//! tamper events on external pins, and from internal sources, eg clock or temperature
+//! monitoring, and erases the backup registers when one occurs, unless configured not to. On
+//! L5, inputs can be active: Each is paired with an output driving a pseudo-random pattern, so
+//! opening, shorting, or faking the loop is detected. On L5 and WL, it also has a monotonic
+//! counter, eg for anti-rollback of firmware versions.
+//!
+//! The TAMP block is in the backup domain, and clocked by RTCCLK; configure the RTC clock source,
+//! eg with `Rtc::new`, before using active tamper detection or the filters.
+//!
+//! Example, erasing the backup registers if the LSE fails, and reading the counter:
+//!
+//! ```
+//! let mut tamp = Tamp::new(dp.TAMP);
+//! tamp.enable_internal(InternalTamper::LseMonitor, true);
+//! tamp.enable_interrupt(TamperSource::Internal(InternalTamper::LseMonitor));
+//! let version = tamp.counter();
+//! ```
+//!
+//! See L552 RM, chapter 42: Tamper and backup registers (TAMP). The PACs are missing several
+//! registers and fields, so we access registers by offset; their layout is the same on these
+//! families.
+
+use crate::{pac::TAMP, rtc};
+
+// Register offsets. See L552 RM, section 42.6: "TAMP registers".
+const CR1: usize = 0x00;
+const CR2: usize = 0x04;
+#[cfg(not(feature = "g4"))]
+const CR3: usize = 0x08;
+const FLTCR: usize = 0x0c;
+#[cfg(feature = "l5")]
+const ATCR1: usize = 0x10;
+#[cfg(feature = "l5")]
+const ATSEEDR: usize = 0x14;
+#[cfg(feature = "l5")]
+const ATOR: usize = 0x18;
+const IER: usize = 0x2c;
+const SR: usize = 0x30;
+const MISR: usize = 0x34;
+const SCR: usize = 0x3c;
+#[cfg(not(feature = "g4"))]
+const COUNTR: usize = 0x40;
+
+// `TAMP_ATOR` register fields.
+#[cfg(feature = "l5")]
+const ATOR_SEEDF: u32 = 1 << 14;
+#[cfg(feature = "l5")]
+const ATOR_INITS: u32 = 1 << 15;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "l5")] {
+        /// The number of external tamper inputs, `TAMP_IN1` - `TAMP_IN8`.
+        pub const NUM_INPUTS: u8 = 8;
+    } else {
+        /// The number of external tamper inputs, `TAMP_IN1` - `TAMP_IN3`.
+        pub const NUM_INPUTS: u8 = 3;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Internal tamper sources. The values are their `ITAMPx` numbers.
+pub enum InternalTamper {
+    #[cfg(feature = "l5")]
+    /// The backup domain voltage is outside its operating range.
+    BackupVoltage = 1,
+    #[cfg(feature = "l5")]
+    /// The temperature is outside its operating range.
+    Temperature = 2,
+    /// The LSE clock failed, or its frequency is out of range.
+    LseMonitor = 3,
+    #[cfg(feature = "g4")]
+    /// The HSE clock failed, as detected by the RTC.
+    HseMonitor = 4,
+    /// The RTC calendar overflowed.
+    RtcOverflow = 5,
+    #[cfg(any(feature = "g4", feature = "wl"))]
+    /// A debug access, with readout protection active.
+    DebugAccess = 6,
+    #[cfg(any(feature = "l5", feature = "wl"))]
+    /// The monotonic counter overflowed.
+    CounterOverflow = 8,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// A tamper event source: An external input, 1 - `NUM_INPUTS`, or an internal source.
+pub enum TamperSource {
+    External(u8),
+    Internal(InternalTamper),
+}
+
+impl TamperSource {
+    /// The source's bit, in the `CR1`, `IER`, `SR`, `MISR`, and `SCR` registers.
+    fn bit(&self) -> u32 {
+        match self {
+            Self::External(n) => {
+                assert!(*n >= 1 && *n <= NUM_INPUTS, "Invalid tamper input.");
+                1 << (n - 1)
+            }
+            Self::Internal(t) => 1 << (15 + *t as u32),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The active level or edge of an external tamper input. Sets `TAMP_CR2` register, `TAMPxTRG`
+/// field.
+pub enum TamperTrigger {
+    /// A rising edge, or with a filter, a high level.
+    RisingOrHigh,
+    /// A falling edge, or with a filter, a low level.
+    FallingOrLow,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Filtering of passive external inputs. Sets `TAMP_FLTCR` register, `TAMPFLT` field.
+pub enum TamperFilter {
+    /// Trigger on an edge. Pins are neither sampled, nor precharged.
+    Edge = 0b00,
+    /// Trigger on a level held for 2 consecutive samples.
+    Level2 = 0b01,
+    Level4 = 0b10,
+    Level8 = 0b11,
+}
+
+/// Configuration of passive external input sampling. Sets `TAMP_FLTCR` register.
+#[derive(Clone)]
+pub struct FilterConfig {
+    /// Defaults to `Edge`.
+    pub filter: TamperFilter,
+    /// The sampling rate, as RTCCLK / 2^(15 - `freq`), for `freq` 0 - 7. `TAMPFREQ` field.
+    /// Defaults to 0: 1Hz with a 32.768kHz RTCCLK.
+    pub freq: u8,
+    /// The precharge duration, in RTCCLK cycles, as 2^`precharge`, for `precharge` 0 - 3.
+    /// `TAMPPRCH` field. Defaults to 0.
+    pub precharge: u8,
+    /// Precharge inputs, with the internal pull-up, before sampling them. Inverts `TAMPPUDIS`
+    /// field. Defaults to `true`.
+    pub pull_up: bool,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            filter: TamperFilter::Edge,
+            freq: 0,
+            precharge: 0,
+            pull_up: true,
+        }
+    }
+}
+
+#[cfg(feature = "l5")]
+/// Configuration of active tamper detection. Sets `TAMP_ATCR1` register.
+#[derive(Clone)]
+pub struct ActiveTamperConfig {
+    /// The active tamper clock, as RTCCLK / 2^`prescaler`, for `prescaler` 0 - 6. `ATCKSEL`
+    /// field. Defaults to 0.
+    pub prescaler: u8,
+    /// The outputs change every 2^`period` cycles of the active tamper clock, for `period` 0 - 7.
+    /// `ATPER` field. Defaults to 4.
+    pub period: u8,
+    /// Only trigger after 2 mismatches in 4 comparisons, to reject glitches. `FLTEN` field.
+    /// Defaults to `false`.
+    pub filter: bool,
+}
+
+#[cfg(feature = "l5")]
+impl Default for ActiveTamperConfig {
+    fn default() -> Self {
+        Self {
+            prescaler: 0,
+            period: 4,
+            filter: false,
+        }
+    }
+}
+
+/// Represents the TAMP peripheral.
+pub struct Tamp {
+    pub regs: TAMP,
+}
+
+impl Tamp {
+    /// Initialize the TAMP peripheral. Enables access to the backup domain, and the RTC APB clock,
+    /// which TAMP shares, with `rtc::enable_backup_access`.
+    pub fn new(regs: TAMP) -> Self {
+        rtc::enable_backup_access();
+        Self { regs }
+    }
+
+    /// Configure the sampling of passive external inputs. Set this before enabling them. Sets
+    /// `TAMP_FLTCR` register.
+    pub fn set_filter(&mut self, cfg: &FilterConfig) {
+        assert!(cfg.freq <= 7, "Tamper sampling frequency must be 0 - 7.");
+        assert!(cfg.precharge <= 3, "Tamper precharge must be 0 - 3.");
+
+        self.write(
+            FLTCR,
+            cfg.freq as u32
+                | (cfg.filter as u32) << 3
+                | (cfg.precharge as u32) << 5
+                | (!cfg.pull_up as u32) << 7,
+        );
+    }
+
+    /// Enable a passive external tamper input, 1 - `NUM_INPUTS`. With `erase`, a tamper event
+    /// erases the backup registers; otherwise, they're kept, and only read and write access is
+    /// blocked until the flag is cleared. Sets `TAMP_CR1` register, `TAMPxE` field, and
+    /// `TAMP_CR2` register, `TAMPxTRG` and `TAMPxNOER` fields.
+    pub fn enable_input(&mut self, input: u8, trigger: TamperTrigger, erase: bool) {
+        let bit = TamperSource::External(input).bit();
+
+        let trg = if trigger == TamperTrigger::FallingOrLow {
+            bit << 24
+        } else {
+            0
+        };
+        let noer = if erase { 0 } else { bit };
+        self.modify(CR2, bit << 24 | bit, trg | noer);
+
+        self.modify(CR1, 0, bit);
+    }
+
+    /// Enable an internal tamper source. With `erase`, a tamper event erases the backup
+    /// registers. (On G4, they're always erased.) Sets `TAMP_CR1` register, `ITAMPxE` field, and
+    /// `TAMP_CR3` register, `ITAMPxNOER` field.
+    pub fn enable_internal(&mut self, source: InternalTamper, erase: bool) {
+        #[cfg(not(feature = "g4"))]
+        {
+            let noer_bit = 1 << (source as u32 - 1);
+            let noer = if erase { 0 } else { noer_bit };
+            self.modify(CR3, noer_bit, noer);
+        }
+        #[cfg(feature = "g4")]
+        let _ = erase;
+
+        self.modify(CR1, 0, TamperSource::Internal(source).bit());
+    }
+
+    /// Disable a tamper source. Clears its `TAMP_CR1` register, `TAMPxE` or `ITAMPxE` field.
+    pub fn disable(&mut self, source: TamperSource) {
+        self.modify(CR1, source.bit(), 0);
+    }
+
+    #[cfg(feature = "l5")]
+    /// Enable active tamper detection on pairs of (input, output), eg `&[(1, 1), (2, 2)]`. Each
+    /// output drives a pseudo-random pattern, seeded with `seed`; eg from the RNG. The input
+    /// triggers a tamper event, erasing the backup registers, if it doesn't match. An output may
+    /// be shared by several inputs; with sharing, use inputs and outputs 1 - 4. Other inputs
+    /// already enabled are disabled while this is configured, then re-enabled. Sets `TAMP_ATCR1` and
+    /// `TAMP_ATSEEDR` registers. See L552 RM, section 42.3.6: "TAMP active tamper
+    /// initialization".
+    pub fn enable_active(&mut self, pairs: &[(u8, u8)], cfg: &ActiveTamperConfig, seed: &[u32; 4]) {
+        assert!(cfg.prescaler <= 6, "Active tamper prescaler must be 0 - 6.");
+        assert!(cfg.period <= 7, "Active tamper period must be 0 - 7.");
+
+        let shared = pairs.iter().any(|(input, output)| input != output);
+
+        let mut inputs = 0;
+        let mut atcr1 = (cfg.prescaler as u32) << 16
+            | (cfg.period as u32) << 24
+            | (shared as u32) << 30
+            | (cfg.filter as u32) << 31;
+
+        for (input, output) in pairs {
+            let bit = TamperSource::External(*input).bit();
+            inputs |= bit;
+            atcr1 |= bit;
+
+            if shared {
+                assert!(
+                    *input <= 4 && *output >= 1 && *output <= 4,
+                    "With shared outputs, active tamper inputs and outputs must be 1 - 4."
+                );
+                // `ATOSELx`, 2 bits each, from bit 8.
+                atcr1 |= (*output as u32 - 1) << (8 + (*input as u32 - 1) * 2);
+            } else {
+                assert!(*output == *input, "Invalid active tamper output.");
+            }
+        }
+
+        // The active tamper configuration may only be changed with the inputs disabled.
+        let cr1 = self.read(CR1);
+        self.write(CR1, cr1 & !0xff);
+
+        self.write(ATCR1, atcr1);
+
+        for word in seed {
+            self.write(ATSEEDR, *word);
+        }
+        while self.read(ATOR) & ATOR_SEEDF != 0 {}
+
+        self.write(CR1, cr1 | inputs);
+
+        while self.read(ATOR) & ATOR_INITS == 0 {}
+    }
+
+    /// Returns `true` if a tamper event from this source has occurred. Reads `TAMP_SR` register.
+    pub fn is_triggered(&self, source: TamperSource) -> bool {
+        self.read(SR) & source.bit() != 0
+    }
+
+    /// Returns `true` if this source's interrupt is pending. Reads `TAMP_MISR` register.
+    pub fn is_interrupt_pending(&self, source: TamperSource) -> bool {
+        self.read(MISR) & source.bit() != 0
+    }
+
+    /// Clear a tamper event's flag. This re-enables access to the backup registers, if they
+    /// weren't erased. Sets `TAMP_SCR` register.
+    pub fn clear(&mut self, source: TamperSource) {
+        self.write(SCR, source.bit());
+    }
+
+    /// Enable an interrupt for a tamper source. Sets `TAMP_IER` register.
+    pub fn enable_interrupt(&mut self, source: TamperSource) {
+        self.modify(IER, 0, source.bit());
+    }
+
+    /// Disable an interrupt for a tamper source. Clears `TAMP_IER` register.
+    pub fn disable_interrupt(&mut self, source: TamperSource) {
+        self.modify(IER, source.bit(), 0);
+    }
+
+    #[cfg(not(feature = "g4"))]
+    /// Read the monotonic counter. It's only reset by a backup domain reset, or a tamper event
+    /// that erases the backup registers. Reads `TAMP_COUNTR` register.
+    pub fn counter(&self) -> u32 {
+        self.read(COUNTR)
+    }
+
+    #[cfg(not(feature = "g4"))]
+    /// Increment the monotonic counter. It can't be decreased, or set. On overflow, it triggers
+    /// the `CounterOverflow` internal tamper source, if enabled. Writes `TAMP_COUNTR` register.
+    pub fn increment_counter(&mut self) {
+        // Any write increments the counter.
+        self.write(COUNTR, 0);
+    }
+
+    fn base(&self) -> usize {
+        &*self.regs as *const _ as usize
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base() + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&mut self, offset: usize, val: u32) {
+        unsafe { ((self.base() + offset) as *mut u32).write_volatile(val) }
+    }
+
+    fn modify(&mut self, offset: usize, clear: u32, set: u32) {
+        let val = self.read(offset);
+        self.write(offset, (val & !clear) | set);
+    }
+}
@@ -0,0 +1,396 @@
+//! `Instant` and `Duration` types for measuring time, and implementing timeouts, similar to those
+//! in the standard library. Instants are read from a free-running 32-bit tick counter, extended
+//! to 64 bits in software: Either the DWT cycle counter (not available on G0), or a 32-bit timer
+//! counter, eg TIM2's.
+//!
+//! Example:
+//!
+//! `time::init_dwt(&clock_cfg);`
+//! `let start = Instant::now();`
+//! `while !done() { if start.elapsed() > Duration::from_millis(10) { return Err(Timeout) } }`
+//!
+//! `with_timeout` and `Deadline` bound blocking waits; drivers use these internally, eg for the
+//! I2C timeout set in `I2cConfig`. If the tick source isn't initialized, they wait indefinitely.
+//!
+//! The counter's wraps are detected each time it's read, so read it (eg with `Instant::now`) at
+//! least once per wrap period: 2^32 ticks, eg about 25 seconds with a 170Mhz DWT.
+
+use core::{
+    cell::Cell,
+    ops::{Add, AddAssign, Sub, SubAssign},
+};
+
+#[cfg(not(feature = "critical-section"))]
+use cortex_m::interrupt::Mutex;
+#[cfg(feature = "critical-section")]
+use critical_section::Mutex;
+
+#[cfg(not(feature = "g0"))]
+use cortex_m::{peripheral::DWT, Peripherals};
+
+#[cfg(not(feature = "g0"))]
+use crate::clocks::Clocks;
+use crate::util::free;
+
+const MICROS_PER_SEC: u64 = 1_000_000;
+
+#[derive(Clone, Copy)]
+/// The counter `Instant`s are read from, and its state for detecting wraps.
+struct TickSource {
+    read: fn() -> u32,
+    freq: u32,
+    /// The counter value at the previous read.
+    last: u32,
+    /// The number of times the counter has wrapped; the upper 32 bits of the tick count.
+    wraps: u32,
+}
+
+static TICK_SOURCE: Mutex<Cell<Option<TickSource>>> = Mutex::new(Cell::new(None));
+
+/// Set the tick source to a free-running, 32-bit up-counter. `read` returns its current value,
+/// and `freq` is its frequency, in Hz. Example, with TIM2 configured to count at 1Mhz, with an
+/// auto-reload value of `u32::MAX`:
+/// `time::init(|| unsafe { (*pac::TIM2::ptr()).cnt.read().bits() }, 1_000_000);`
+pub fn init(read: fn() -> u32, freq: u32) {
+    assert!(freq > 0, "The tick frequency must be greater than 0.");
+
+    free(|cs| {
+        TICK_SOURCE.borrow(cs).set(Some(TickSource {
+            read,
+            freq,
+            last: read(),
+            wraps: 0,
+        }))
+    });
+}
+
+#[cfg(not(feature = "g0"))]
+/// Use the DWT cycle counter as the tick source. Enables the counter; ticks are at the core
+/// clock frequency.
+pub fn init_dwt(clocks: &Clocks) {
+    let mut cp = unsafe { Peripherals::steal() };
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    init(DWT::cycle_count, clocks.sysclk());
+}
+
+/// The tick source's frequency, in Hz. Panics if `init` hasn't been called.
+pub fn tick_freq() -> u32 {
+    free(|cs| TICK_SOURCE.borrow(cs).get())
+        .expect("The time tick source must be initialized before use.")
+        .freq
+}
+
+/// Read the tick count, extended to 64 bits.
+fn read_ticks() -> u64 {
+    free(|cs| {
+        let cell = TICK_SOURCE.borrow(cs);
+        let mut source = cell
+            .get()
+            .expect("The time tick source must be initialized before use.");
+
+        let count = (source.read)();
+        if count < source.last {
+            source.wraps = source.wraps.wrapping_add(1);
+        }
+        source.last = count;
+        cell.set(Some(source));
+
+        ((source.wraps as u64) << 32) | count as u64
+    })
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A span of time, with microsecond resolution.
+pub struct Duration {
+    micros: u64,
+}
+
+impl Duration {
+    pub const ZERO: Self = Self { micros: 0 };
+    pub const MAX: Self = Self { micros: u64::MAX };
+
+    pub const fn from_micros(micros: u64) -> Self {
+        Self { micros }
+    }
+
+    pub const fn from_millis(millis: u64) -> Self {
+        Self {
+            micros: millis.saturating_mul(1_000),
+        }
+    }
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Self {
+            micros: secs.saturating_mul(MICROS_PER_SEC),
+        }
+    }
+
+    /// Convert a number of ticks of a timer running at `freq` Hz to a duration, rounding down.
+    pub const fn from_ticks(ticks: u64, freq: u32) -> Self {
+        let freq = freq as u64;
+        // Split into whole seconds, and the remainder, so this doesn't overflow for large values.
+        let secs = ticks / freq;
+        let rem = ticks % freq;
+
+        Self {
+            micros: secs
+                .saturating_mul(MICROS_PER_SEC)
+                .saturating_add(rem * MICROS_PER_SEC / freq),
+        }
+    }
+
+    pub const fn as_micros(&self) -> u64 {
+        self.micros
+    }
+
+    pub const fn as_millis(&self) -> u64 {
+        self.micros / 1_000
+    }
+
+    pub const fn as_secs(&self) -> u64 {
+        self.micros / MICROS_PER_SEC
+    }
+
+    pub fn as_secs_f32(&self) -> f32 {
+        self.micros as f32 / MICROS_PER_SEC as f32
+    }
+
+    /// Convert to a number of ticks of a timer running at `freq` Hz, rounding down.
+    pub const fn as_ticks(&self, freq: u32) -> u64 {
+        let freq = freq as u64;
+        let secs = self.micros / MICROS_PER_SEC;
+        let rem = self.micros % MICROS_PER_SEC;
+
+        secs.saturating_mul(freq)
+            .saturating_add(rem * freq / MICROS_PER_SEC)
+    }
+
+    pub const fn is_zero(&self) -> bool {
+        self.micros == 0
+    }
+
+    /// Add two durations, returning `None` on overflow.
+    pub const fn checked_add(self, rhs: Self) -> Option<Self> {
+        match self.micros.checked_add(rhs.micros) {
+            Some(micros) => Some(Self { micros }),
+            None => None,
+        }
+    }
+
+    /// Subtract a duration, returning `None` if the result would be negative.
+    pub const fn checked_sub(self, rhs: Self) -> Option<Self> {
+        match self.micros.checked_sub(rhs.micros) {
+            Some(micros) => Some(Self { micros }),
+            None => None,
+        }
+    }
+
+    /// Add two durations, returning `Duration::MAX` on overflow.
+    pub const fn saturating_add(self, rhs: Self) -> Self {
+        Self {
+            micros: self.micros.saturating_add(rhs.micros),
+        }
+    }
+
+    /// Subtract a duration, returning `Duration::ZERO` if the result would be negative.
+    pub const fn saturating_sub(self, rhs: Self) -> Self {
+        Self {
+            micros: self.micros.saturating_sub(rhs.micros),
+        }
+    }
+}
+
+impl Add for Duration {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        self.checked_add(rhs)
+            .expect("Overflow when adding durations.")
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Duration {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self.checked_sub(rhs)
+            .expect("Overflow when subtracting durations.")
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl From<core::time::Duration> for Duration {
+    /// Saturates at `Duration::MAX`.
+    fn from(d: core::time::Duration) -> Self {
+        Self {
+            micros: d.as_micros().min(u64::MAX as u128) as u64,
+        }
+    }
+}
+
+impl From<Duration> for core::time::Duration {
+    fn from(d: Duration) -> Self {
+        core::time::Duration::from_micros(d.micros)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A point in time, read from the tick source. Only comparable with other instants from the
+/// same source.
+pub struct Instant {
+    ticks: u64,
+}
+
+impl Instant {
+    /// Read the current time. Panics if the tick source hasn't been initialized with `init`.
+    pub fn now() -> Self {
+        Self {
+            ticks: read_ticks(),
+        }
+    }
+
+    /// The number of ticks since the tick source was initialized.
+    pub const fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// The time elapsed since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Self::now().saturating_duration_since(*self)
+    }
+
+    /// The time elapsed from `earlier` to this instant, or `None` if `earlier` is later.
+    pub fn checked_duration_since(&self, earlier: Self) -> Option<Duration> {
+        self.ticks
+            .checked_sub(earlier.ticks)
+            .map(|ticks| Duration::from_ticks(ticks, tick_freq()))
+    }
+
+    /// The time elapsed from `earlier` to this instant, or zero if `earlier` is later.
+    pub fn saturating_duration_since(&self, earlier: Self) -> Duration {
+        self.checked_duration_since(earlier)
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Returns `None` on overflow.
+    pub fn checked_add(&self, duration: Duration) -> Option<Self> {
+        self.ticks
+            .checked_add(duration.as_ticks(tick_freq()))
+            .map(|ticks| Self { ticks })
+    }
+
+    /// Returns `None` if the result would be before the tick source was initialized.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Self> {
+        self.ticks
+            .checked_sub(duration.as_ticks(tick_freq()))
+            .map(|ticks| Self { ticks })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Self;
+
+    fn add(self, rhs: Duration) -> Self {
+        self.checked_add(rhs)
+            .expect("Overflow when adding a duration to an instant.")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Self;
+
+    fn sub(self, rhs: Duration) -> Self {
+        self.checked_sub(rhs)
+            .expect("Overflow when subtracting a duration from an instant.")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    /// Saturates at zero, if `rhs` is later than `self`.
+    fn sub(self, rhs: Self) -> Duration {
+        self.saturating_duration_since(rhs)
+    }
+}
+
+/// Returns `true` if the tick source has been initialized, with `init` or `init_dwt`.
+pub fn is_initialized() -> bool {
+    free(|cs| TICK_SOURCE.borrow(cs).get().is_some())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The error returned when an operation doesn't complete before its timeout.
+pub struct TimedOut;
+
+#[derive(Clone, Copy)]
+/// A point in time after which a blocking operation should give up. Use this in loops that
+/// can't easily be expressed as a closure for `with_timeout`, eg ones that return errors.
+pub struct Deadline {
+    end: Option<Instant>,
+}
+
+impl Deadline {
+    /// Create a deadline `timeout` from now. It never passes if `timeout` is `None`, or if the
+    /// tick source isn't initialized.
+    pub fn new(timeout: Option<Duration>) -> Self {
+        let end = match timeout {
+            Some(t) if is_initialized() => Instant::now().checked_add(t),
+            _ => None,
+        };
+
+        Self { end }
+    }
+
+    /// Returns `true` if the deadline has passed.
+    pub fn is_passed(&self) -> bool {
+        match self.end {
+            Some(end) => Instant::now() >= end,
+            None => false,
+        }
+    }
+}
+
+/// Poll `op` until it returns `Some`, or until `timeout` has elapsed. If `timeout` is `None`, or
+/// the tick source isn't initialized, polls indefinitely. Example:
+/// `with_timeout(Some(Duration::from_millis(5)), || adc_ready().then_some(()))?;`
+pub fn with_timeout<T>(
+    timeout: Option<Duration>,
+    mut op: impl FnMut() -> Option<T>,
+) -> Result<T, TimedOut> {
+    let deadline = Deadline::new(timeout);
+
+    loop {
+        if let Some(result) = op() {
+            return Ok(result);
+        }
+        if deadline.is_passed() {
+            return Err(TimedOut);
+        }
+    }
+}
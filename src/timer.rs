@@ -1,15 +1,18 @@
 //! Provides support for timers. Includes initialization, interrupts,
 //! and PWM features.
 //!
-//! Low-power timers (LPTIM) are not yet supported.
+//! For low-power timers (LPTIM), see the `lptim` module.
 
 // todo: WB and WL should support pwm features
 
-use core::ops::Deref;
+use core::{cell::Cell, ops::Deref};
 
-use num_traits::float::Float;
+#[cfg(not(feature = "critical-section"))]
+use cortex_m::interrupt::Mutex;
+#[cfg(feature = "critical-section")]
+use critical_section::Mutex;
 
-use cortex_m::interrupt::free;
+use num_traits::float::Float;
 
 #[cfg(feature = "embedded-hal")]
 use embedded_hal::{
@@ -22,8 +25,8 @@ use embedded_hal::{
 use crate::{
     clocks::Clocks,
     pac::{self, RCC},
-    rcc_en_reset,
-    util::RccPeriph,
+    rcc_disable, rcc_en_reset,
+    util::{free, RccPeriph},
 };
 
 #[cfg(any(feature = "f3", feature = "l4"))]
@@ -36,7 +39,8 @@ use crate::pac::dma as dma_p;
     feature = "l4",
     feature = "g4",
     feature = "h7",
-    feature = "wb"
+    feature = "wb",
+    feature = "wl"
 ))]
 use crate::pac::dma1 as dma_p;
 
@@ -46,6 +50,23 @@ use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::DmaInput;
 
+use crate::{pulse_counter::PulseCounter, servo::Servo};
+// Matches the timers `cc_4_channels!` is instantiated for.
+#[cfg(not(any(feature = "f410", feature = "l5", feature = "wb")))]
+use crate::{freq_meter::FreqMeter, rc_input::RcInput};
+
+#[cfg(not(any(
+    feature = "f401",
+    feature = "f410",
+    feature = "f411",
+    feature = "f412",
+    feature = "f413",
+    feature = "g0",
+    feature = "wb",
+    feature = "wl"
+)))]
+use crate::dac::Trigger;
+
 use cfg_if::cfg_if;
 use paste::paste;
 
@@ -289,6 +310,160 @@ pub enum CaptureCompareDma {
     Update = 1,
 }
 
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Slave mode, where the counter is controlled by the trigger input selected with `TriggerSource`.
+/// Sets `TIMx_SMCR` register, `SMS` field.
+pub enum SlaveMode {
+    /// The counter is clocked by the internal clock.
+    Disabled = 0b000,
+    /// Rising edge of the trigger input reinitializes the counter, and generates an update.
+    Reset = 0b100,
+    /// The counter clock is enabled when the trigger input is high, and stops (without being
+    /// reset) when it's low. Eg, to measure how long an external signal is active.
+    Gated = 0b101,
+    /// The counter starts at a rising edge of the trigger input (but isn't reset).
+    Trigger = 0b110,
+    /// External clock mode 1: Rising edges of the trigger input clock the counter.
+    ExternalClock1 = 0b111,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// The trigger input used by the slave mode controller. Sets `TIMx_SMCR` register, `TS` field.
+pub enum TriggerSource {
+    /// Internal trigger 0: Another timer's TRGO; see the RM's timer interconnect table.
+    Itr0 = 0b000,
+    Itr1 = 0b001,
+    Itr2 = 0b010,
+    Itr3 = 0b011,
+    /// TI1 edge detector: Both edges of channel 1's input.
+    Ti1FEdge = 0b100,
+    /// Filtered timer input 1.
+    Ti1Fp1 = 0b101,
+    /// Filtered timer input 2.
+    Ti2Fp2 = 0b110,
+    /// External trigger input (ETR pin), after its prescaler and filter.
+    Etrf = 0b111,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Divides the external trigger (ETR) signal. The frequency of the divided signal must be at most
+/// 1/4 of the timer clock's. Sets `TIMx_SMCR` register, `ETPS` field.
+pub enum EtrPrescaler {
+    Div1 = 0b00,
+    Div2 = 0b01,
+    Div4 = 0b10,
+    Div8 = 0b11,
+}
+
+#[derive(Clone, Copy)]
+/// Configuration of the external trigger (ETR) input.
+pub struct EtrConfig {
+    /// `ActiveHigh` to use ETR as-is (rising edges, or high level); `ActiveLow` to invert it.
+    /// Sets `TIMx_SMCR` register, `ETP` field.
+    pub polarity: Polarity,
+    pub prescaler: EtrPrescaler,
+    /// The digital filter: A value from 0 (no filter) to 15, which sets the sampling frequency,
+    /// and the number of consecutive samples needed to validate a transition. See the RM's
+    /// `ETF` field description. Sets `TIMx_SMCR` register, `ETF` field.
+    pub filter: u8,
+}
+
+impl Default for EtrConfig {
+    fn default() -> Self {
+        Self {
+            polarity: Polarity::ActiveHigh,
+            prescaler: EtrPrescaler::Div1,
+            filter: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+/// The input whose pulses a timer counts, with `enable_pulse_counter`.
+pub enum PulseInput {
+    /// Channel 1's input, in external clock mode 1. `ActiveHigh` polarity counts rising edges,
+    /// and `ActiveLow` falling ones. `filter` is the digital filter: A value from 0 (no filter) to
+    /// 15; see the RM's `IC1F` field description.
+    Ti1 { polarity: Polarity, filter: u8 },
+    /// Channel 2's input, in external clock mode 1.
+    Ti2 { polarity: Polarity, filter: u8 },
+    /// The external trigger (ETR) input, in external clock mode 2. A prescaler other than `Div1`
+    /// counts higher pulse rates; the pulse counter scales the count by it.
+    Etr(EtrConfig),
+}
+
+#[derive(Clone, Copy)]
+/// A signal that triggers the break function of an advanced-control timer: On F3 and F4, only the
+/// BKIN pin. (On F3, route a comparator to the break with its `COMPx_CSR` register, `OUTSEL`
+/// field.) Sets `TIMx_OR2` register (`TIMx_AF1` on G0, G4, H7, WB, and WL), `BKINE` and
+/// `BKCMPxE` fields.
+pub enum BreakSource {
+    /// The BKIN pin.
+    Pin,
+    #[cfg(not(any(feature = "f3", feature = "f4")))]
+    Comp1,
+    #[cfg(not(any(feature = "f3", feature = "f4")))]
+    Comp2,
+    #[cfg(feature = "g4")]
+    Comp3,
+    #[cfg(feature = "g4")]
+    Comp4,
+    #[cfg(feature = "g4")]
+    Comp5,
+    #[cfg(feature = "g4")]
+    Comp6,
+    #[cfg(feature = "g4")]
+    Comp7,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Write protection of the break, dead-time, and output configuration. Once set, this can't be
+/// changed until the MCU is reset. Sets `TIMx_BDTR` register, `LOCK` field.
+pub enum LockLevel {
+    /// No write protection.
+    Off = 0b00,
+    /// The `BDTR` register's `DTG`, `BKE`, `BKP`, and `AOE` fields, and the `CR2` register's
+    /// `OISx` fields can't be written.
+    Level1 = 0b01,
+    /// Level 1, and the output polarity, and the `OSSR` and `OSSI` fields can't be written.
+    Level2 = 0b10,
+    /// Level 2, and the output compare mode and preload can't be written.
+    Level3 = 0b11,
+}
+
+#[derive(Clone, Copy)]
+/// Configuration of an advanced-control timer's break (fault) input. When the break is
+/// triggered, hardware disables the PWM outputs (clearing `MOE`), independent of software.
+pub struct BreakConfig {
+    /// The break's active level. Eg `ActiveLow` for an open-drain fault signal. Sets `TIMx_BDTR`
+    /// register, `BKP` field.
+    pub polarity: Polarity,
+    /// The digital filter: A value from 0 (no filter) to 15, which sets the sampling frequency,
+    /// and the number of consecutive samples needed to validate the break. Not available on F4.
+    /// Sets `TIMx_BDTR` register, `BKF` field.
+    pub filter: u8,
+    /// If `true`, the outputs are re-enabled at the next update event once the break input is
+    /// inactive; otherwise, they stay disabled until `clear_break` is called. Sets `TIMx_BDTR`
+    /// register, `AOE` field.
+    pub automatic_output: bool,
+    pub lock: LockLevel,
+}
+
+impl Default for BreakConfig {
+    fn default() -> Self {
+        Self {
+            polarity: Polarity::ActiveHigh,
+            filter: 0,
+            automatic_output: false,
+            lock: LockLevel::Off,
+        }
+    }
+}
+
 /// Initial configuration data for Timer peripherals.
 #[derive(Clone)]
 pub struct TimerConfig {
@@ -329,8 +504,17 @@ pub struct Timer<TIM> {
     clock_speed: u32, // Associated timer clock speed in Hz.
 }
 
+/// A function run on a timer's update event. Closures that don't capture anything can be used.
+pub type UpdateCallback = fn();
+
 macro_rules! make_timer {
     ($TIMX:ident, $tim:ident, $apb:expr, $res:ident) => {
+        paste! {
+            /// The callback run by `Timer::handle_update` for this timer.
+            static [<$TIMX _UPDATE_CALLBACK>]: Mutex<Cell<Option<UpdateCallback>>> =
+                Mutex::new(Cell::new(None));
+        }
+
         impl Timer<pac::$TIMX> {
             paste! {
                 /// Initialize a DFSDM peripheral, including  enabling and resetting
@@ -445,9 +629,51 @@ macro_rules! make_timer {
                 }
             }
 
+            paste! {
+                /// Register a function to run on each update event (eg overflow), and enable the
+                /// update interrupt. This replaces any callback previously registered for this
+                /// timer. To dispatch it, call `handle_update` in the timer's interrupt handler,
+                /// and unmask that interrupt. Example, for a periodic task:
+                ///
+                /// `timer.set_update_callback(|| blink_led());`
+                /// `#[interrupt] fn TIM3() { Timer::<TIM3>::handle_update(); }`
+                pub fn set_update_callback(&mut self, callback: UpdateCallback) {
+                    free(|cs| [<$TIMX _UPDATE_CALLBACK>].borrow(cs).set(Some(callback)));
+                    self.enable_interrupt(TimerInterrupt::Update);
+                }
+
+                /// Remove this timer's update callback, and disable the update interrupt.
+                pub fn clear_update_callback(&mut self) {
+                    self.disable_interrupt(TimerInterrupt::Update);
+                    free(|cs| [<$TIMX _UPDATE_CALLBACK>].borrow(cs).set(None));
+                }
+
+                /// Call this in the timer's interrupt handler. If the update flag is set, and the
+                /// update interrupt is enabled, clears the flag, and runs the registered callback,
+                /// if any. Returns `true` if it was handled. On interrupt lines shared by several
+                /// timers, eg `TIM1_UP_TIM16`, call it for each. The callback runs outside a
+                /// critical section.
+                pub fn handle_update() -> bool {
+                    let regs = unsafe { &(*pac::$TIMX::ptr()) };
+
+                    // Skip timers polled in software, whose update interrupt isn't enabled.
+                    if regs.sr.read().uif().bit_is_clear() || regs.dier.read().uie().bit_is_clear() {
+                        return false;
+                    }
+                    // See `clear_interrupt`.
+                    regs.sr.write(|w| unsafe { w.bits(0xffff_ffff).uif().clear_bit() });
+
+                    if let Some(callback) = free(|cs| [<$TIMX _UPDATE_CALLBACK>].borrow(cs).get()) {
+                        callback();
+                    }
+                    true
+                }
+            }
+
             /// Enable the timer.
             pub fn enable(&mut self) {
-                self.regs.cr1.write(|w| w.cen().set_bit());
+                // Modify, to keep the configuration set in the constructor, eg `ARPE`.
+                self.regs.cr1.modify(|_, w| w.cen().set_bit());
             }
 
             /// Disable the timer.
@@ -460,6 +686,23 @@ macro_rules! make_timer {
                 self.regs.cr1.read().cen().bit_is_set()
             }
 
+            paste! {
+                /// Disable the timer, and return the PAC register block, eg to reconfigure its
+                /// pins for other uses. If `gate_clock` is `true`, also disable its RCC peripheral clock.
+                pub fn free(mut self, gate_clock: bool) -> pac::$TIMX {
+                    self.disable();
+
+                    if gate_clock {
+                        free(|_| {
+                            let rcc = unsafe { &(*RCC::ptr()) };
+                            rcc_disable!([<apb $apb>], $tim, rcc);
+                        });
+                    }
+
+                    self.regs
+                }
+            }
+
             /// Set the timer frequency, in Hz. Overrides the period or frequency set
             /// in the constructor.
             pub fn set_freq(&mut self, mut freq: f32) -> Result<(), ValueError> {
@@ -542,6 +785,39 @@ macro_rules! make_timer {
                 self.enable_capture_compare(channel);
             }
 
+            /// Set up a channel to drive a servo: Sets the timer's frame rate, with a 1Mhz tick if
+            /// the timer clock allows it, and enables PWM output on the servo's channel with no pulse.
+            /// Set the position with `set_servo_pulse` or `set_servo_angle`. See the `servo` module.
+            pub fn enable_servo(&mut self, servo: &Servo) {
+                let psc = (self.clock_speed / 1_000_000).max(1) - 1;
+                let arr = (self.clock_speed as f32 / ((psc + 1) as f32 * servo.cfg.freq)) as u32 - 1;
+
+                if psc <= 0xffff && arr <= 0xffff {
+                    self.set_prescaler(psc as u16);
+                    self.set_auto_reload(arr);
+                } else {
+                    self.set_freq(servo.cfg.freq).ok();
+                }
+
+                self.enable_pwm_output(servo.channel, OutputCompare::Pwm1, 0.);
+            }
+
+            /// Set a servo's pulse width, in μs. It's clamped to the servo's configured endpoints.
+            pub fn set_servo_pulse(&mut self, servo: &Servo, pulse: f32) {
+                let psc = self.regs.psc.read().bits();
+                // Timer ticks per μs.
+                let ticks_per_us = self.clock_speed as f32 / ((psc + 1) as f32 * 1_000_000.);
+
+                let duty = servo.clamp_pulse(pulse) * ticks_per_us;
+                self.set_duty(servo.channel, duty as $res);
+            }
+
+            /// Set a servo's angle, in degrees. It's converted to a pulse width using the servo's
+            /// configured angle range, and clamped to its endpoints.
+            pub fn set_servo_angle(&mut self, servo: &Servo, angle: f32) {
+                self.set_servo_pulse(servo, servo.angle_to_pulse(angle));
+            }
+
             /// Return the integer associated with the maximum duty period.
             pub fn get_max_duty(&self) -> $res {
                 #[cfg(feature = "g0")]
@@ -550,6 +826,206 @@ macro_rules! make_timer {
                 self.regs.arr.read().arr().bits().try_into().unwrap()
             }
 
+            /// Set the timer frequency, in Hz, taking effect at the next update event instead of
+            /// immediately, so a running PWM output never has a truncated (runt) or extended period.
+            /// Enables auto-reload preload. Use `force_update` to apply it immediately instead.
+            pub fn set_freq_buffered(&mut self, mut freq: f32) -> Result<(), ValueError> {
+                assert!(freq > 0.);
+                match self.cfg.alignment {
+                    Alignment::Edge => (),
+                    _ => freq *= 2.,
+                }
+
+                let (psc, arr) = calc_freq_vals(freq, self.clock_speed)?;
+
+                self.regs.cr1.modify(|_, w| w.arpe().set_bit());
+                self.cfg.auto_reload_preload = true;
+
+                self.update_buffered(|t| {
+                    t.set_prescaler(psc);
+                    t.set_auto_reload(arr as u32);
+                });
+
+                Ok(())
+            }
+
+            /// Set the timer period, in seconds, taking effect at the next update event. See
+            /// `set_freq_buffered`.
+            pub fn set_period_buffered(&mut self, period: f32) -> Result<(), ValueError> {
+                assert!(period > 0.);
+                self.set_freq_buffered(1. / period)
+            }
+
+            /// Make several buffered changes, eg to the period and duty cycles, that are loaded
+            /// together at the next update event. Update events are disabled while `f` runs, by
+            /// setting `CR1` register, `UDIS` field, so an update can't load some of the changes
+            /// without the others. Values are only buffered with auto-reload preload, and each
+            /// channel's compare preload; `enable_pwm_output` sets the latter.
+            pub fn update_buffered(&mut self, f: impl FnOnce(&mut Self)) {
+                self.regs.cr1.modify(|_, w| w.udis().set_bit());
+                f(self);
+                self.regs.cr1.modify(|_, w| w.udis().clear_bit());
+            }
+
+            /// Load buffered values immediately, instead of at the next update event. Generates an
+            /// update event by setting `EGR` register, `UG` field, with `CR1` register, `URS` field
+            /// set, so it doesn't set the update interrupt flag, or make a DMA request. This resets
+            /// the counter.
+            pub fn force_update(&mut self) {
+                let urs = self.regs.cr1.read().urs().bit();
+
+                self.regs.cr1.modify(|_, w| w.urs().set_bit());
+                self.regs.egr.write(|w| w.ug().set_bit());
+                self.regs.cr1.modify(|_, w| w.urs().bit(urs));
+            }
+
+            /// Write a new prescaler, auto-reload, and channel duty value, that are loaded together
+            /// at the next update event.
+            fn write_buffered(&mut self, psc: u16, arr: u32, channel: TimChannel, duty: $res) {
+                self.update_buffered(|t| {
+                    t.set_prescaler(psc);
+                    t.set_auto_reload(arr);
+                    t.set_duty(channel, duty);
+                });
+            }
+
+            /// Output a square wave at `freq` Hz on a PWM channel, eg to drive a buzzer. Run
+            /// `enable_pwm_output` on the channel first. Starts the timer if it's not running. If it
+            /// is, the new frequency takes effect at the end of the current period, so the output
+            /// doesn't glitch. Assumes edge alignment. Enables auto-reload preload.
+            pub fn set_tone(&mut self, channel: TimChannel, freq: f32) -> Result<(), ValueError> {
+                assert!(freq > 0.);
+                let (psc, arr) = calc_freq_vals(freq, self.clock_speed)?;
+
+                self.regs.cr1.modify(|_, w| w.arpe().set_bit());
+                self.cfg.auto_reload_preload = true;
+
+                let duty = ((arr as u32 + 1) / 2) as $res;
+                self.write_buffered(psc, arr as u32, channel, duty);
+
+                if !self.is_enabled() {
+                    self.reinitialize();
+                    self.enable();
+                }
+                Ok(())
+            }
+
+            /// Silence a tone started with `set_tone` at the end of the current period, by setting
+            /// the channel's duty to 0. The timer keeps running.
+            pub fn stop_tone(&mut self, channel: TimChannel) {
+                self.set_duty(channel, 0);
+            }
+
+            /// Output a tone at `freq` Hz for `duration` seconds, then silence it. Blocks. This polls
+            /// the update flag, so the update interrupt must not be enabled while it runs.
+            pub fn tone(&mut self, channel: TimChannel, freq: f32, duration: f32) -> Result<(), ValueError> {
+                self.set_tone(channel, freq)?;
+                self.clear_interrupt(TimerInterrupt::Update);
+
+                let periods = (freq * duration) as u32;
+                for _ in 0..periods {
+                    while self.regs.sr.read().uif().bit_is_clear() {}
+                    self.clear_interrupt(TimerInterrupt::Update);
+                }
+
+                self.stop_tone(channel);
+                Ok(())
+            }
+
+            #[cfg(not(any(feature = "g0", feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+            /// Sweep a tone's frequency linearly from `start_freq` to `end_freq`, using DMA to load a
+            /// new period and duty at each update event; eg for sirens, or rising confirmation beeps.
+            /// `buf` is filled with the sweep's values; it holds one step per timer period, using
+            /// `2 + n` words for channel `n`. Run `enable_pwm_output` on the channel first. Select the
+            /// timer's update DMA request in the DMA mux or channel selection, as with
+            /// `write_dma_burst`. The burst rewrites `RCR`, and lower-numbered channels' `CCRx`, with
+            /// their values from when this is called; don't change them during the sweep.
+            pub unsafe fn tone_sweep_dma<D>(
+                &mut self,
+                channel: TimChannel,
+                start_freq: f32,
+                end_freq: f32,
+                buf: &mut [u32],
+                dma_channel: DmaChannel,
+                channel_cfg: ChannelCfg,
+                dma: &mut Dma<D>,
+            ) -> Result<(), ValueError>
+            where
+                D: Deref<Target = dma_p::RegisterBlock>,
+            {
+                assert!(start_freq > 0. && end_freq > 0.);
+
+                // Each step writes `ARR`, `RCR`, and `CCR1` through the channel's `CCRx`, in a DMA
+                // burst starting at `ARR`. (`RCR` is reserved on timers without a repetition counter.)
+                let words = match channel {
+                    TimChannel::C1 => 3,
+                    TimChannel::C2 => 4,
+                    TimChannel::C3 => 5,
+                    #[cfg(not(feature = "wl"))]
+                    TimChannel::C4 => 6,
+                };
+                let steps = buf.len() / words;
+                assert!(steps > 0);
+
+                // Use a fixed prescaler, that allows the lowest frequency.
+                let min_freq = if start_freq < end_freq { start_freq } else { end_freq };
+                let psc = (self.clock_speed as f32 / (min_freq * 65_536.)) as u32;
+                if psc > 0xffff {
+                    return Err(ValueError {});
+                }
+                let tick_freq = self.clock_speed as f32 / (psc + 1) as f32;
+
+                // The burst also writes `RCR`, and the `CCRx` registers of lower-numbered channels, so
+                // seed each step with their current values, leaving PWM on those channels running.
+                let mut current = [0; 6];
+                let arr_addr = &self.regs.arr as *const _ as *const u32;
+                for (i, word) in current.iter_mut().enumerate().take(words) {
+                    *word = core::ptr::read_volatile(arr_addr.add(i));
+                }
+
+                for (i, step) in buf.chunks_exact_mut(words).enumerate() {
+                    let freq = if steps == 1 {
+                        start_freq
+                    } else {
+                        start_freq + (end_freq - start_freq) * i as f32 / (steps - 1) as f32
+                    };
+                    let arr = (tick_freq / freq) as u32 - 1;
+
+                    step.copy_from_slice(&current[..words]);
+                    step[0] = arr;
+                    step[words - 1] = (arr + 1) / 2;
+                }
+
+                self.set_tone(channel, start_freq)?;
+                let arr = buf[0];
+                self.write_buffered(psc as u16, arr, channel, ((arr + 1) / 2) as $res);
+
+                // `DBA` is the offset of `ARR` from `CR1`, in words.
+                self.regs.dcr.modify(|_, w| {
+                    w.dba().bits(0x2c / 4);
+                    w.dbl().bits(words as u8 - 1)
+                });
+
+                #[cfg(feature = "h7")]
+                let len = buf.len() as u32;
+                #[cfg(not(feature = "h7"))]
+                let len = buf.len() as u16;
+
+                dma.cfg_channel(
+                    dma_channel,
+                    &self.regs.dmar as *const _ as u32,
+                    buf.as_ptr() as u32,
+                    len,
+                    dma::Direction::ReadFromMem,
+                    dma::DataSize::S32,
+                    dma::DataSize::S32,
+                    channel_cfg,
+                );
+
+                self.enable_interrupt(TimerInterrupt::UpdateDma);
+                Ok(())
+            }
+
              /// See G4 RM, section 29.4.24: Dma burst mode. "The TIMx timers have the capability to
              /// generate multiple DMA requests upon a single event.
              /// The main purpose is to be able to re-program part of the timer multiple times without
@@ -742,6 +1218,18 @@ macro_rules! make_timer {
     }
 }
 
+/// The `CCxIF` and `CCxIE` bit positions in the `SR` and `DIER` registers, for a channel.
+#[cfg(not(any(feature = "f410", feature = "l5", feature = "wb")))]
+fn rc_cc_bit(channel: TimChannel) -> u32 {
+    match channel {
+        TimChannel::C1 => 1 << 1,
+        TimChannel::C2 => 1 << 2,
+        TimChannel::C3 => 1 << 3,
+        #[cfg(not(feature = "wl"))]
+        TimChannel::C4 => 1 << 4,
+    }
+}
+
 // We use macros to support the varying number of capture compare channels available on
 // different timers.
 // Note that there's lots of DRY between these implementations.
@@ -800,6 +1288,245 @@ macro_rules! cc_4_channels {
                 // });
             }
 
+            /// Set up this timer to decode RC receiver PWM on the channels `rc` was created with: The
+            /// counter runs at 1Mhz and wraps at 0xffff, and each channel captures its input, starting
+            /// with the rising edge. Enables the update and capture/compare interrupts; call
+            /// `handle_rc_input` in this timer's interrupt handler. See the `rc_input` module.
+            pub fn enable_rc_input<const N: usize>(&mut self, rc: &RcInput<N>) {
+                self.disable();
+
+                self.set_prescaler((self.clock_speed / 1_000_000 - 1) as u16);
+                self.set_auto_reload(0xffff);
+
+                let mut dier_bits = 1; // `UIE`
+                for i in 0..N {
+                    let channel = rc.channel(i);
+
+                    self.disable_capture_compare(channel);
+                    // For each channel, `InputTi1` (CCxS = 01) maps ICx to its own input, TIx.
+                    self.set_capture_compare(channel, CaptureCompare::InputTi1);
+                    self.set_polarity(channel, Polarity::ActiveHigh);
+                    self.enable_capture_compare(channel);
+
+                    dier_bits |= rc_cc_bit(channel);
+                }
+
+                // We set `DIER` bits directly, since not all `CCxIE` fields are available in the PAC.
+                self.regs.dier.modify(|r, w| unsafe { w.bits(r.bits() | dier_bits) });
+
+                self.reinitialize();
+                self.enable();
+            }
+
+            /// Handle capture and overflow interrupts for RC input, and clear their flags. Run this
+            /// in the timer's interrupt handler.
+            pub fn handle_rc_input<const N: usize>(&mut self, rc: &mut RcInput<N>) {
+                let sr = self.regs.sr.read().bits();
+                let mut flags = 0;
+
+                if sr & 1 != 0 {
+                    rc.on_overflow();
+                    flags |= 1;
+                }
+
+                for i in 0..N {
+                    let channel = rc.channel(i);
+                    let bit = rc_cc_bit(channel);
+
+                    if sr & bit != 0 {
+                        let count = self.get_duty(channel) as u16;
+
+                        // Alternate between capturing rising and falling edges.
+                        let polarity = if rc.on_capture(i, count) {
+                            Polarity::ActiveLow
+                        } else {
+                            Polarity::ActiveHigh
+                        };
+                        self.set_polarity(channel, polarity);
+
+                        flags |= bit;
+                    }
+                }
+
+                // These flags are cleared by writing 0; writing 1 has no effect.
+                self.regs.sr.write(|w| unsafe { w.bits(!flags) });
+            }
+
+            /// Set up this timer to measure the frequency on `meter`'s channel: The counter runs at
+            /// the timer clock, and wraps at its maximum, and the channel captures rising edges.
+            /// Enables the update and capture/compare interrupts; call `handle_freq_meter` in this
+            /// timer's interrupt handler. See the `freq_meter` module.
+            pub fn enable_freq_meter(&mut self, meter: &mut FreqMeter) {
+                self.disable();
+
+                self.set_prescaler(0);
+                self.set_auto_reload($res::MAX as u32);
+                meter.start(self.clock_speed, $res::MAX as u64 + 1);
+
+                let channel = meter.channel();
+
+                self.disable_capture_compare(channel);
+                // `InputTi1` (CCxS = 01) maps ICx to its own input, TIx.
+                self.set_capture_compare(channel, CaptureCompare::InputTi1);
+                self.set_input_prescaler(channel, 0);
+                self.set_polarity(channel, Polarity::ActiveHigh);
+                self.enable_capture_compare(channel);
+
+                // `UIE`, and `CCxIE`. We set `DIER` bits directly, since not all `CCxIE` fields
+                // are available in the PAC.
+                let bits = 1 | rc_cc_bit(channel);
+                self.regs.dier.modify(|r, w| unsafe { w.bits(r.bits() | bits) });
+
+                self.reinitialize();
+                // Clear the update flag set by `reinitialize`, so it isn't counted as an overflow.
+                self.regs.sr.write(|w| unsafe { w.bits(0) });
+                self.enable();
+            }
+
+            /// Handle capture and overflow interrupts for frequency measurement, and clear their
+            /// flags. Run this in the timer's interrupt handler.
+            pub fn handle_freq_meter(&mut self, meter: &mut FreqMeter) {
+                let sr = self.regs.sr.read().bits();
+                let channel = meter.channel();
+                let bit = rc_cc_bit(channel);
+
+                let overflow = sr & 1 != 0;
+                let mut flags = if overflow { 1 } else { 0 };
+
+                let capture = if sr & bit != 0 {
+                    flags |= bit;
+                    Some(self.get_duty(channel) as u32)
+                } else {
+                    None
+                };
+
+                // If both flags are set, a capture early in the count happened after the
+                // overflow, and one late in the count happened before it.
+                let overflow_first = match capture {
+                    Some(count) => count < ($res::MAX / 2) as u32,
+                    None => true,
+                };
+
+                let mut psc = None;
+                if overflow && overflow_first {
+                    psc = meter.on_overflow();
+                }
+                if let Some(count) = capture {
+                    psc = meter.on_capture(count).or(psc);
+                }
+                if overflow && !overflow_first {
+                    psc = meter.on_overflow().or(psc);
+                }
+
+                // These flags are cleared by writing 0; writing 1 has no effect.
+                self.regs.sr.write(|w| unsafe { w.bits(!flags) });
+
+                if let Some(psc) = psc {
+                    // Disabling the channel resets its prescaler counter.
+                    self.disable_capture_compare(channel);
+                    self.set_input_prescaler(channel, psc);
+                    self.enable_capture_compare(channel);
+                }
+            }
+
+            /// Set the input capture prescaler: capture every 1, 2, 4, or 8 events, for `psc` values
+            /// of 0 - 3. Sets `CCMRx` register, `ICxPSC` field. We set bits directly, since the
+            /// input fields aren't available in all PACs.
+            fn set_input_prescaler(&mut self, channel: TimChannel, psc: u8) {
+                let psc = psc as u32;
+                match channel {
+                    TimChannel::C1 => self.regs.ccmr1_output().modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(0b11 << 2) | psc << 2)
+                    }),
+                    TimChannel::C2 => self.regs.ccmr1_output().modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(0b11 << 10) | psc << 10)
+                    }),
+                    TimChannel::C3 => self.regs.ccmr2_output().modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(0b11 << 2) | psc << 2)
+                    }),
+                    #[cfg(not(feature = "wl"))]
+                    TimChannel::C4 => self.regs.ccmr2_output().modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(0b11 << 10) | psc << 10)
+                    }),
+                }
+            }
+
+            /// Capture the counter on each capture event of `channel`, to `buf`, using DMA. This
+            /// records edge timestamps of a pulse train (eg from an IR remote, 1-Wire, or DHT22
+            /// sensor) without an interrupt per edge; the pulse widths are the differences between
+            /// consecutive values, wrapping at ARR. Set the count rate with `set_prescaler`, and
+            /// set ARR to its maximum with `set_auto_reload`. Configure the edges to capture with
+            /// `set_polarity` and `set_complementary_polarity`: Setting both to `ActiveLow` captures
+            /// both edges. Enables the channel as an input, its `CCxDE` DMA request, and the timer.
+            /// The DMA channel must be mapped to this timer channel's request (eg using DMAMUX).
+            #[cfg(not(any(feature = "g0", feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+            pub unsafe fn read_capture_dma<D>(
+                &mut self,
+                buf: &mut [$res],
+                channel: TimChannel,
+                dma_channel: DmaChannel,
+                channel_cfg: ChannelCfg,
+                dma: &mut Dma<D>,
+            ) where
+                D: Deref<Target = dma_p::RegisterBlock>,
+            {
+                let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+
+                self.disable_capture_compare(channel);
+                // `InputTi1` (CCxS = 01) maps ICx to its own input, TIx.
+                self.set_capture_compare(channel, CaptureCompare::InputTi1);
+                self.enable_capture_compare(channel);
+
+                let periph_addr = match channel {
+                    TimChannel::C1 => &self.regs.ccr1 as *const _ as u32,
+                    TimChannel::C2 => &self.regs.ccr2 as *const _ as u32,
+                    TimChannel::C3 => &self.regs.ccr3 as *const _ as u32,
+                    #[cfg(not(feature = "wl"))]
+                    TimChannel::C4 => &self.regs.ccr4 as *const _ as u32,
+                };
+
+                #[cfg(feature = "h7")]
+                let len = len as u32;
+                #[cfg(not(feature = "h7"))]
+                let len = len as u16;
+
+                let data_size = match core::mem::size_of::<$res>() {
+                    2 => dma::DataSize::S16,
+                    _ => dma::DataSize::S32,
+                };
+
+                dma.cfg_channel(
+                    dma_channel,
+                    periph_addr,
+                    ptr as u32,
+                    len,
+                    dma::Direction::ReadFromPeriph,
+                    data_size,
+                    data_size,
+                    channel_cfg,
+                );
+
+                // The `CCxDE` bits are 8 bits above `CCxIE`. We set `DIER` bits directly, since
+                // not all `CCxDE` fields are available in the PAC.
+                let bit = rc_cc_bit(channel) << 8;
+                self.regs.dier.modify(|r, w| w.bits(r.bits() | bit));
+
+                self.enable();
+            }
+
+            /// Stop a capture DMA transfer started with `read_capture_dma`: Stops the DMA channel, and
+            /// disables the `CCxDE` DMA request.
+            #[cfg(not(any(feature = "g0", feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+            pub fn stop_capture_dma<D>(&mut self, channel: TimChannel, dma_channel: DmaChannel, dma: &mut Dma<D>)
+            where
+                D: Deref<Target = dma_p::RegisterBlock>,
+            {
+                dma.stop(dma_channel);
+
+                let bit = rc_cc_bit(channel) << 8;
+                self.regs.dier.modify(|r, w| unsafe { w.bits(r.bits() & !bit) });
+            }
+
             // todo: more advanced PWM modes. Asymmetric, combined, center-aligned etc.
 
             /// Set Output Compare Mode. See docs on the `OutputCompare` enum.
@@ -1455,6 +2182,196 @@ macro_rules! cc_1_channel {
     }
 }
 
+// Timers with an external trigger (ETR) input, and slave mode controller.
+macro_rules! etr {
+    ($TIMX:ident) => {
+        impl Timer<pac::$TIMX> {
+            /// Configure the external trigger (ETR) input's polarity, prescaler, and filter. Use it
+            /// with `enable_external_clock2`, or with a slave mode, with `TriggerSource::Etrf`.
+            pub fn set_etr(&mut self, cfg: &EtrConfig) {
+                assert!(cfg.filter <= 0b1111);
+
+                self.regs.smcr.modify(|_, w| unsafe {
+                    w.etp().bit(cfg.polarity.bit());
+                    w.etps().bits(cfg.prescaler as u8);
+                    w.etf().bits(cfg.filter)
+                });
+            }
+
+            /// Set the slave mode, and the trigger input it uses. Eg `SlaveMode::ExternalClock1` to
+            /// count external pulses on a channel input, or `SlaveMode::Gated` to count only while an
+            /// external signal is active. Sets `TIMx_SMCR` register, `SMS` and `TS` fields.
+            pub fn set_slave_mode(&mut self, mode: SlaveMode, trigger: TriggerSource) {
+                // RM: "The trigger selection (TS) must be changed only when it's not used (when
+                // SMS=000), to avoid wrong edge detections at the transition."
+                self.regs.smcr.modify(|_, w| unsafe { w.sms().bits(0) });
+                self.regs.smcr.modify(|_, w| unsafe { w.ts().bits(trigger as u8) });
+                self.regs.smcr.modify(|_, w| unsafe { w.sms().bits(mode as u8) });
+            }
+
+            /// External clock mode 2: Count each active edge of the ETR input, eg to count pulses
+            /// from a flow meter or tachometer. This can be combined with a slave mode using another
+            /// trigger input, eg `Gated`. Set the prescaler to 0 with `set_prescaler` to count each
+            /// edge. Sets `TIMx_SMCR` register, `ECE` field.
+            pub fn enable_external_clock2(&mut self, cfg: &EtrConfig) {
+                self.set_etr(cfg);
+                self.regs.smcr.modify(|_, w| w.ece().set_bit());
+            }
+
+            /// Set up this timer to count pulses on `input`, for use with a `PulseCounter`: Its
+            /// edges clock the counter, which wraps at its maximum. Resets the count; call
+            /// `PulseCounter::reset` if reusing a counter. Call `sample_pulse_counter` every sample
+            /// period. See the `pulse_counter` module.
+            pub fn enable_pulse_counter(&mut self, input: PulseInput) {
+                self.disable();
+                self.disable_external_clock();
+
+                self.set_prescaler(0);
+                self.set_auto_reload(u32::MAX);
+
+                match input {
+                    PulseInput::Ti1 { polarity, filter } => {
+                        self.set_ti_counting(0, polarity, filter);
+                        self.set_slave_mode(SlaveMode::ExternalClock1, TriggerSource::Ti1Fp1);
+                    }
+                    PulseInput::Ti2 { polarity, filter } => {
+                        self.set_ti_counting(8, polarity, filter);
+                        self.set_slave_mode(SlaveMode::ExternalClock1, TriggerSource::Ti2Fp2);
+                    }
+                    PulseInput::Etr(cfg) => self.enable_external_clock2(&cfg),
+                }
+
+                self.reinitialize();
+                self.enable();
+            }
+
+            /// Sample the count for a `PulseCounter`. Call this every `sample_period`, eg from
+            /// another timer's interrupt handler.
+            pub fn sample_pulse_counter(&mut self, counter: &mut PulseCounter) {
+                // Only ETR has a prescaler; it's 0 (`Div1`) when counting a channel input.
+                let scale = 1 << self.regs.smcr.read().etps().bits();
+                // `set_auto_reload` wrote all ones; on 16-bit timers, only the low half is kept.
+                let count_max = self.regs.arr.read().bits();
+
+                counter.on_sample(self.read_count(), count_max, scale);
+            }
+
+            /// Configure channel 1 (`shift` = 0) or 2 (`shift` = 8) as an input, for counting its
+            /// edges in external clock mode 1. Sets `CCMR1` register, `CCxS` and `ICxF` fields, and
+            /// `CCER` register, `CCxP` and `CCxNP` fields. We set bits directly, since the input
+            /// fields aren't available in all PACs.
+            fn set_ti_counting(&mut self, shift: u8, polarity: Polarity, filter: u8) {
+                assert!(filter <= 0b1111);
+
+                // `CCxS` = 01: ICx is mapped on TIx. `ICxF` is 4 bits above it.
+                let ccmr = (0b01 | (filter as u32) << 4) << shift;
+                self.regs
+                    .ccmr1_output()
+                    .modify(|r, w| unsafe { w.bits(r.bits() & !(0xf3 << shift) | ccmr) });
+
+                // `CCxP` and `CCxNP` are 1 and 3 bits above `CCxE`, in groups of 4 bits per
+                // channel. `CCxNP` is left clear; setting both counts both edges.
+                let shift = shift / 2;
+                let ccp = (polarity.bit() as u32) << (shift + 1);
+                self.regs
+                    .ccer
+                    .modify(|r, w| unsafe { w.bits(r.bits() & !(0b1010 << shift) | ccp) });
+            }
+
+            /// Clock the counter from the internal clock again: Disables external clock mode 2, and
+            /// the slave mode. Sets `TIMx_SMCR` register, `ECE` and `SMS` fields.
+            pub fn disable_external_clock(&mut self) {
+                self.regs.smcr.modify(|_, w| unsafe {
+                    w.ece().clear_bit();
+                    w.sms().bits(0)
+                });
+            }
+        }
+    };
+}
+
+// Advanced-control timers, with a break input.
+macro_rules! brk {
+    ($TIMX:ident) => {
+        impl Timer<pac::$TIMX> {
+            /// Enable the break (fault) input, from one or more sources, eg a comparator monitoring
+            /// motor current; and enable the outputs. When a source goes active, hardware disables
+            /// the PWM outputs. See L44 RM, section 26.3.16: Using the break function.
+            pub fn enable_break(&mut self, sources: &[BreakSource], cfg: &BreakConfig) {
+                assert!(cfg.filter <= 0b1111);
+
+                for source in sources {
+                    cfg_if! {
+                        if #[cfg(any(feature = "l4", feature = "l5"))] {
+                            match source {
+                                BreakSource::Pin => self.regs.or2.modify(|_, w| w.bkine().set_bit()),
+                                BreakSource::Comp1 => self.regs.or2.modify(|_, w| w.bkcmp1e().set_bit()),
+                                BreakSource::Comp2 => self.regs.or2.modify(|_, w| w.bkcmp2e().set_bit()),
+                            }
+                        } else if #[cfg(any(feature = "g0", feature = "g4", feature = "h7", feature = "wb", feature = "wl"))] {
+                            match source {
+                                BreakSource::Pin => self.regs.af1.modify(|_, w| w.bkine().set_bit()),
+                                BreakSource::Comp1 => self.regs.af1.modify(|_, w| w.bkcmp1e().set_bit()),
+                                BreakSource::Comp2 => self.regs.af1.modify(|_, w| w.bkcmp2e().set_bit()),
+                                #[cfg(feature = "g4")]
+                                BreakSource::Comp3 => self.regs.af1.modify(|_, w| w.bkcmp3e().set_bit()),
+                                #[cfg(feature = "g4")]
+                                BreakSource::Comp4 => self.regs.af1.modify(|_, w| w.bkcmp4e().set_bit()),
+                                #[cfg(feature = "g4")]
+                                BreakSource::Comp5 => self.regs.af1.modify(|_, w| w.bkcmp5e().set_bit()),
+                                #[cfg(feature = "g4")]
+                                BreakSource::Comp6 => self.regs.af1.modify(|_, w| w.bkcmp6e().set_bit()),
+                                #[cfg(feature = "g4")]
+                                BreakSource::Comp7 => self.regs.af1.modify(|_, w| w.bkcmp7e().set_bit()),
+                            }
+                        } else {
+                            // The BKIN pin is the only source, and is always connected.
+                            let BreakSource::Pin = source;
+                        }
+                    }
+                }
+
+                // RM: "the LOCK bits can be written only once after reset", and once set, they
+                // write-protect BKF, BKP, BKE, and AOE; so write all of them at once. We set bits
+                // directly, since not all PACs have the BKF field.
+                // BKP: 0 means active low.
+                // F4 has no break filter.
+                #[cfg(feature = "f4")]
+                let filter = 0;
+                #[cfg(not(feature = "f4"))]
+                let filter = (cfg.filter as u32) << 16;
+
+                let bits = (cfg.lock as u32) << 8
+                    | 1 << 12 // BKE
+                    | (!cfg.polarity.bit() as u32) << 13
+                    | (cfg.automatic_output as u32) << 14
+                    | filter;
+
+                // LOCK, BKE, BKP, AOE, and BKF.
+                let mask = 0b11 << 8 | 0b111 << 12 | 0b1111 << 16;
+                self.regs.bdtr.modify(|r, w| unsafe { w.bits((r.bits() & !mask) | bits) });
+
+                self.regs.bdtr.modify(|_, w| w.moe().set_bit());
+            }
+
+            /// Returns `true` if the break has been triggered since the flag was last cleared. Reads
+            /// `TIMx_SR` register, `BIF` field.
+            pub fn break_occurred(&self) -> bool {
+                self.regs.sr.read().bif().bit_is_set()
+            }
+
+            /// Clear the break flag, and re-enable the outputs after a break. The outputs stay
+            /// disabled if the break input is still active. Sets `TIMx_SR` register, `BIF` field, and
+            /// `TIMx_BDTR` register, `MOE` field.
+            pub fn clear_break(&mut self) {
+                // Write 1s to the other flags, to leave them unchanged; see `clear_interrupt`.
+                self.regs.sr.write(|w| unsafe { w.bits(0xffff_ffff).bif().clear_bit() });
+                self.regs.bdtr.modify(|_, w| w.moe().set_bit());
+            }
+        }
+    };
+}
+
 /// Calculate values required to set the timer frequency: `PSC` and `ARR`. This can be
 /// used for initial timer setup, or changing the value later.
 fn calc_freq_vals(freq: f32, clock_speed: u32) -> Result<(u16, u16), ValueError> {
@@ -1507,7 +2424,16 @@ cfg_if! {
         feature = "wb",
         feature = "wl"
     )))]  {
-        /// Represents a Basic timer, used primarily to trigger the onboard DAC. Eg Tim6 or Tim7.
+        /// Represents a Basic timer, used primarily to trigger the onboard DAC, or DMA transfers, and
+        /// as a periodic tick. Eg Tim6 or Tim7. These only count up, and have no channels: their
+        /// outputs are the update event (interrupt and DMA request), and TRGO.
+        ///
+        /// Example, triggering DAC conversions at 44.1kHz:
+        ///
+        /// `let mut timer = BasicTimer::new(dp.TIM6, 44_100., &clock_cfg);`
+        /// `timer.enable_dac_trigger();`
+        /// `dac.set_trigger(DacChannel::C1, timer.dac_trigger());`
+        /// `timer.enable();`
         pub struct BasicTimer<R> {
             pub regs: R,
             clock_speed: u32,
@@ -1529,10 +2455,19 @@ cfg_if! {
                     R::en_reset(rcc)
                 });
 
+                // Only counter overflows generate update interrupts and DMA requests, not setting
+                // `UG`. Buffer the auto-reload value, so frequency changes apply at the next update.
+                regs.cr1.modify(|_, w| {
+                    w.urs().set_bit();
+                    w.arpe().set_bit()
+                });
+
                 // Self { regs, config, clock_speed: clocks.apb1_timer()  }
                 let mut result = Self { regs, clock_speed: clock_cfg.apb1_timer()  };
 
                 result.set_freq(freq).ok();
+                // Load the prescaler and auto-reload values.
+                result.reinitialize();
                 result
             }
 
@@ -1553,6 +2488,21 @@ cfg_if! {
                 self.regs.cr1.read().cen().bit_is_set()
             }
 
+            /// Disable the timer, and return the PAC register block. If `gate_clock` is `true`,
+            /// also disable its RCC peripheral clock.
+            pub fn free(mut self, gate_clock: bool) -> R {
+                self.disable();
+
+                if gate_clock {
+                    free(|_| {
+                        let rcc = unsafe { &(*RCC::ptr()) };
+                        R::disable_clock(rcc)
+                    });
+                }
+
+                self.regs
+            }
+
             /// Set the timer period, in seconds. Overrides the period or frequency set
             /// in the constructor.
             pub fn set_period(&mut self, time: f32) -> Result<(), ValueError> {
@@ -1609,6 +2559,62 @@ cfg_if! {
             pub fn set_mastermode(&self, mode: MasterModeSelection) {
                 self.regs.cr2.modify(|_, w| unsafe { w.mms().bits(mode as u8) });
             }
+
+            /// Output the update event on TRGO, eg to trigger DAC or ADC conversions once per
+            /// period. Basic timers only support the `Reset`, `Enable`, and `Update` master modes.
+            pub fn enable_dac_trigger(&mut self) {
+                self.set_mastermode(MasterModeSelection::Update);
+            }
+
+            /// Re-initialize the counter, and load the prescaler and auto-reload values. Sets
+            /// `EGR` register, `UG` field.
+            pub fn reinitialize(&mut self) {
+                self.regs.egr.write(|w| w.ug().set_bit());
+                self.clear_interrupt();
+            }
+
+            /// Enable the update interrupt, or the update DMA request. Sets `DIER` register, `UIE`
+            /// or `UDE` field. Other interrupts aren't available on basic timers.
+            pub fn enable_interrupt(&mut self, interrupt: TimerInterrupt) {
+                match interrupt {
+                    TimerInterrupt::Update => self.regs.dier.modify(|_, w| w.uie().set_bit()),
+                    TimerInterrupt::UpdateDma => self.regs.dier.modify(|_, w| w.ude().set_bit()),
+                    _ => panic!("Basic timers only have update interrupts and DMA requests."),
+                }
+            }
+
+            /// Disable the update interrupt, or the update DMA request.
+            pub fn disable_interrupt(&mut self, interrupt: TimerInterrupt) {
+                match interrupt {
+                    TimerInterrupt::Update => self.regs.dier.modify(|_, w| w.uie().clear_bit()),
+                    TimerInterrupt::UpdateDma => self.regs.dier.modify(|_, w| w.ude().clear_bit()),
+                    _ => panic!("Basic timers only have update interrupts and DMA requests."),
+                }
+            }
+
+            /// Clear the update interrupt flag. Place this at the top of the timer's interrupt
+            /// handler. Clears `SR` register, `UIF` field.
+            pub fn clear_interrupt(&mut self) {
+                self.regs.sr.write(|w| unsafe { w.bits(0xffff_ffff).uif().clear_bit() });
+            }
+        }
+
+        #[cfg(not(any(feature = "g0", feature = "f412")))]
+        impl BasicTimer<pac::TIM6> {
+            /// The DAC trigger selection for this timer's TRGO output. Pass it to
+            /// `Dac::set_trigger`.
+            pub fn dac_trigger(&self) -> Trigger {
+                Trigger::Tim6
+            }
+        }
+
+        #[cfg(not(any(feature = "g0", feature = "f412", feature = "f301", feature = "f302")))]
+        impl BasicTimer<pac::TIM7> {
+            /// The DAC trigger selection for this timer's TRGO output. Pass it to
+            /// `Dac::set_trigger`.
+            pub fn dac_trigger(&self) -> Trigger {
+                Trigger::Tim7
+            }
         }
     }
 }
@@ -1634,6 +2640,10 @@ make_timer!(TIM1, tim1, 2, u16);
 // todo: Some variantsl ike H7 have 4 channels on TIM1.
 #[cfg(not(any(feature = "f373")))]
 cc_2_channels!(TIM1, u16);
+#[cfg(not(any(feature = "f373")))]
+etr!(TIM1);
+#[cfg(not(any(feature = "f373")))]
+brk!(TIM1);
 
 cfg_if! {
     if #[cfg(not(any(
@@ -1644,6 +2654,7 @@ cfg_if! {
     )))] {
         make_timer!(TIM2, tim2, 1, u32);
         cc_4_channels!(TIM2, u32);
+        etr!(TIM2);
     }
 }
 
@@ -1660,6 +2671,7 @@ cfg_if! {
     )))] {
         make_timer!(TIM3, tim3, 1, u32);
         cc_4_channels!(TIM3, u32);
+        etr!(TIM3);
     }
 }
 
@@ -1680,6 +2692,7 @@ cfg_if! {
     )))] {
         make_timer!(TIM4, tim4, 1, u32);
         cc_4_channels!(TIM4, u32);
+        etr!(TIM4);
     }
 }
 
@@ -1694,6 +2707,7 @@ cfg_if! {
    ))] {
         make_timer!(TIM5, tim5, 1, u32);
         cc_4_channels!(TIM5, u32);
+        etr!(TIM5);
    }
 }
 
@@ -1708,6 +2722,8 @@ cfg_if! {
         make_timer!(TIM8, tim8, 2, u16);
         // todo: Some issues with field names or something on l562 here.
         cc_1_channel!(TIM8, u16);
+        etr!(TIM8);
+        brk!(TIM8);
     }
 }
 
@@ -1769,3 +2785,7 @@ cfg_if! {
 make_timer!(TIM20, tim20, 2, u16);
 #[cfg(any(feature = "f303"))]
 cc_4_channels!(TIM20, u16);
+#[cfg(any(feature = "f303"))]
+etr!(TIM20);
+#[cfg(any(feature = "f303"))]
+brk!(TIM20);
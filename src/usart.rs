@@ -2,24 +2,30 @@
 //! Provides APIs to configure, read, and write from
 //! USART, with blocking, nonblocking, and DMA functionality.
 
-// todo: Synchronous mode.
 // todo: Auto baud
 
 // todo: Missing some features (like additional interrupts) on the USARTv3 peripheral . (L5, G etc)
 
 use crate::{
     clocks::Clocks,
+    interrupt::InterruptPeriph,
     pac::{self, RCC},
-    util::{BaudPeriph, RccPeriph},
+    spi::SpiMode,
+    util::{free, BaudPeriph, RccPeriph},
 };
 
+#[cfg(not(feature = "f4"))]
+use crate::interrupt::Binding;
+
+use crate::gpio::OutputSpeed;
+
+pub use crate::util::BitrateError;
+
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::util::DmaPeriph;
 
 use core::ops::Deref;
 
-use cortex_m::interrupt::free;
-
 #[cfg(feature = "g0")]
 use crate::pac::dma as dma_p;
 #[cfg(any(
@@ -44,6 +50,12 @@ use embedded_hal::{
     serial::{Read, Write},
 };
 
+#[cfg(feature = "async")]
+use embedded_io_async::{ErrorKind, ErrorType};
+
+#[cfg(feature = "async")]
+use crate::asynch::{self, USART_WAKERS};
+
 use cfg_if::cfg_if;
 
 // todo: Prescaler (USART_PRESC) register on v3 (L5, G, H etc)
@@ -104,6 +116,30 @@ pub enum IrdaMode {
     LowPower,
 }
 
+#[cfg(not(feature = "f4"))]
+#[derive(Clone, Copy, PartialEq)]
+/// The length of the address used for multiprocessor communication. (USART_CR2, ADDM7)
+pub enum AddressLen {
+    /// 4-bit address, in the lower 4 bits of the address character.
+    A4,
+    /// 7-bit address, in the lower 7 bits of the address character.
+    A7,
+}
+
+#[cfg(not(any(feature = "f3", feature = "f4", feature = "l4")))]
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// The FIFO fill level that sets the TXFT or RXFT flag, and fires the associated interrupt.
+/// Sets `USART_CR3` register, `TXFTCFG` and `RXFTCFG` fields.
+pub enum FifoThreshold {
+    Eighth = 0b000,
+    Quarter = 0b001,
+    Half = 0b010,
+    ThreeQuarters = 0b011,
+    SevenEighths = 0b100,
+    Full = 0b101,
+}
+
 #[cfg(not(feature = "f4"))]
 #[derive(Clone, Copy)]
 /// The type of USART interrupt to configure. Reference the USART_ISR register.
@@ -122,6 +158,12 @@ pub enum UsartInterrupt {
     Tcbgt,
     TransmissionComplete,
     TransmitEmpty,
+    /// The TX FIFO has reached its threshold level of empty slots. FIFO mode only.
+    #[cfg(not(any(feature = "f3", feature = "l4")))]
+    TxFifoThreshold,
+    /// The RX FIFO has reached its threshold level of received words. FIFO mode only.
+    #[cfg(not(any(feature = "f3", feature = "l4")))]
+    RxFifoThreshold,
 }
 
 /// Configuration for Usart. Can be used with default::Default.
@@ -136,6 +178,9 @@ pub struct UsartConfig {
     pub parity: Parity,
     /// IrDA mode: Enables this protocol, which is used to communicate with IR devices.
     pub irda_mode: IrdaMode,
+    /// Enable the 8-word TX and RX FIFOs. Only available on G0, G4, L5, H7, WB, and WL; ignored on
+    /// other families. Defaults to disabled.
+    pub fifo: bool,
 }
 
 impl Default for UsartConfig {
@@ -146,6 +191,7 @@ impl Default for UsartConfig {
             oversampling: OverSampling::O16,
             parity: Parity::Disabled,
             irda_mode: IrdaMode::None,
+            fifo: false,
         }
     }
 }
@@ -157,11 +203,74 @@ pub struct Usart<R> {
     config: UsartConfig,
 }
 
+impl<R: InterruptPeriph> Usart<R> {
+    /// The NVIC interrupt line this peripheral uses, eg for use with `interrupt::set_priority`.
+    pub fn interrupt(&self) -> pac::Interrupt {
+        R::INTERRUPT
+    }
+}
+
 impl<R> Usart<R>
 where
     // R: Deref<Target = pac::usart1::RegisterBlock> + DmaPeriph + RccPeriph + BaudPeriph,
     R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
 {
+    /// Initialize a U[s]ART peripheral, as with `new`, returning an error instead if `baud` is
+    /// above the maximum for its kernel clock and oversampling setting, or for the TX pin's output
+    /// speed setting, or the nearest baud rate available is more than 2.5% off.
+    pub fn try_new(
+        regs: R,
+        baud: u32,
+        config: UsartConfig,
+        pin_speed: OutputSpeed,
+        clock_cfg: &Clocks,
+    ) -> Result<Self, BitrateError> {
+        let clock_freq = R::baud(clock_cfg);
+
+        let oversampling = match config.oversampling {
+            OverSampling::O16 => 16,
+            OverSampling::O8 => 8,
+        };
+
+        if baud == 0 || baud > clock_freq / oversampling {
+            return Err(BitrateError::AboveClock {
+                periph: "U[S]ART",
+                requested: baud,
+                clock: R::clock_name(),
+                clock_freq,
+            });
+        }
+        if baud > pin_speed.max_freq() {
+            return Err(BitrateError::PinSpeed {
+                periph: "U[S]ART",
+                requested: baud,
+                max: pin_speed.max_freq(),
+            });
+        }
+
+        // `USARTDIV`, as `set_baud` calculates it.
+        let usart_div = clock_freq * (16 / oversampling) / baud;
+        if usart_div > 0xffff {
+            return Err(BitrateError::BelowClock {
+                periph: "U[S]ART",
+                requested: baud,
+                clock: R::clock_name(),
+                clock_freq,
+            });
+        }
+
+        let actual = clock_freq * (16 / oversampling) / usart_div;
+        if actual.abs_diff(baud) * 1_000 > baud * 25 {
+            return Err(BitrateError::Inexact {
+                periph: "U[S]ART",
+                requested: baud,
+                actual,
+            });
+        }
+
+        Ok(Self::new(regs, baud, config, clock_cfg))
+    }
+
     /// Initialize a U[s]ART peripheral, including configuration register writes, and enabling and
     /// resetting its RCC peripheral clock. `baud` is the baud rate, in bytes-per-second.
     pub fn new(regs: R, baud: u32, config: UsartConfig, clock_cfg: &Clocks) -> Self {
@@ -208,6 +317,15 @@ where
             )
         });
 
+        // RM: "This bit can only be written when the USART is disabled (UE=0)." With the FIFOs
+        // enabled, the TXE and RXNE flags (and their interrupts) become TXFNF (TX FIFO not full)
+        // and RXFNE (RX FIFO not empty), so reads and writes work the same way.
+        #[cfg(not(any(feature = "f3", feature = "f4", feature = "l4")))]
+        result
+            .regs
+            .cr1
+            .modify(|_, w| w.fifoen().bit(result.config.fifo));
+
         // 2. Select the desired baud rate using the USART_BRR register.
         result.set_baud(baud, clock_cfg);
         // 3. Program the number of stop bits in USART_CR2.
@@ -293,6 +411,35 @@ where
         }
     }
 
+    /// Wait for any ongoing transmission to complete, disable the U[S]ART, and return the PAC
+    /// register block, eg to reconfigure its pins for other uses. If `gate_clock` is `true`, also
+    /// disable its RCC peripheral clock.
+    pub fn free(self, gate_clock: bool) -> R {
+        // RM: "In order to go into low-power mode without generating errors on the line, the TE bit
+        // must be reset before and the software must wait for the TC bit in the USART_ISR to be
+        // set before resetting the UE bit."
+        self.flush();
+        self.regs.cr1.modify(|_, w| {
+            w.te().clear_bit();
+            w.re().clear_bit()
+        });
+        self.regs.cr1.modify(|_, w| w.ue().clear_bit());
+
+        #[cfg(feature = "panic-uart")]
+        crate::panic_uart::release(&*self.regs as *const pac::usart1::RegisterBlock as usize);
+        #[cfg(feature = "logger")]
+        crate::logger::release(&*self.regs as *const pac::usart1::RegisterBlock as usize);
+
+        if gate_clock {
+            free(|_| {
+                let rcc = unsafe { &(*RCC::ptr()) };
+                R::disable_clock(rcc);
+            });
+        }
+
+        self.regs
+    }
+
     /// Transmit data, as a sequence of u8. See L44 RM, section 38.5.2: "Character transmission procedure"
     pub fn write(&mut self, data: &[u8]) {
         // 7. Write the data to send in the USART_TDR register (this clears the TXE bit). Repeat this
@@ -372,6 +519,174 @@ where
         }
     }
 
+    /// Send a break character: A frame of all 0s, including the stop bits. Sets `USART_RQR`
+    /// register, `SBKRQ` field (`USART_CR1` register, `SBK` field on F4). The break is sent after
+    /// any character currently being transmitted.
+    pub fn send_break(&mut self) {
+        cfg_if! {
+            if #[cfg(feature = "f4")] {
+                self.regs.cr1.modify(|_, w| w.sbk().set_bit());
+            } else {
+                // RM: "Setting the SBKRQ bit sends a break character. [...] The SBKF flag in the
+                // USART_ISR register is set, and is cleared by hardware once the break is sent."
+                while self.regs.isr.read().sbkf().bit_is_set() {}
+                self.regs.rqr.write(|w| w.sbkrq().set_bit());
+            }
+        }
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Enable or disable break detection. This enables LIN mode, which is required to detect breaks;
+    /// a break is detected after 11 consecutive low bits. Use the `LineBreak` interrupt, or
+    /// `break_detected`, to handle breaks. Sets `USART_CR2` register, `LINEN` and `LBDL` fields.
+    /// Note that LIN mode requires 1 stop bit, and IrDA mode disabled.
+    pub fn set_break_detection(&mut self, enabled: bool) {
+        // RM: "This bit field can only be written when the USART is disabled (UE=0)."
+        let originally_enabled = self.regs.cr1.read().ue().bit_is_set();
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.ue().clear_bit());
+            while self.regs.cr1.read().ue().bit_is_set() {}
+        }
+
+        self.regs.cr2.modify(|_, w| {
+            w.lbdl().set_bit();
+            w.linen().bit(enabled)
+        });
+
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.ue().set_bit());
+        }
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Returns `true` if a break has been detected since the flag was last cleared. Reads the
+    /// `USART_ISR` register, `LBDF` field. Clear it with `clear_interrupt(UsartInterrupt::LineBreak)`.
+    pub fn break_detected(&self) -> bool {
+        self.regs.isr.read().lbdf().bit_is_set()
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Set the receiver timeout, in bit durations, and enable it. The timeout starts after the
+    /// stop bit of each received character; when it expires, the `ReceiverTimeout` interrupt fires.
+    /// Sets `USART_RTOR` register, `RTO` field, and `USART_CR2` register, `RTOEN` field. Note that
+    /// not all U[S]ARTs support this, eg UART4 and UART5 on some MCUs.
+    pub fn set_receiver_timeout(&mut self, bit_times: u32) {
+        assert!(bit_times < (1 << 24), "Receiver timeout must be less than 2^24 bit times.");
+
+        self.regs.rtor.modify(|_, w| unsafe { w.rto().bits(bit_times) });
+        self.regs.cr2.modify(|_, w| w.rtoen().set_bit());
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Disable the receiver timeout. Clears `USART_CR2` register, `RTOEN` field.
+    pub fn disable_receiver_timeout(&mut self) {
+        self.regs.cr2.modify(|_, w| w.rtoen().clear_bit());
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Set the receiver timeout to the Modbus RTU inter-frame silence (3.5 characters) at the
+    /// current baud rate, using `modbus_rtu_silence`. After this, the `ReceiverTimeout` interrupt
+    /// marks the end of each received frame.
+    pub fn enable_modbus_rtu_framing(&mut self) {
+        self.set_receiver_timeout(modbus_rtu_silence(self.baud).frame);
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Set up multiprocessor communication with address mark wakeup, eg for RS-485 multidrop
+    /// networks. In mute mode, the receiver ignores everything until it receives an address
+    /// character (MSB set) that matches `address`; it then leaves mute mode. Received characters
+    /// with a different address put it back in mute mode. Use 9-bit words (`WordLen::W9`), so
+    /// the MSB is the address mark, and data can use all 8 lower bits.
+    ///
+    /// Sets `USART_CR1` register, `WAKE` and `MME` fields, and `USART_CR2` register, `ADD` and
+    /// `ADDM7` fields. See G4 RM, section 37.5.11: USART multiprocessor communication.
+    pub fn enable_multiprocessor(&mut self, address: u8, address_len: AddressLen) {
+        if address_len == AddressLen::A4 {
+            assert!(address < 16, "4-bit addresses must be less than 16.");
+        } else {
+            assert!(address < 128, "7-bit addresses must be less than 128.");
+        }
+
+        // RM: "This bit field can only be written when the USART is disabled (UE=0)."
+        let originally_enabled = self.regs.cr1.read().ue().bit_is_set();
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.ue().clear_bit());
+            while self.regs.cr1.read().ue().bit_is_set() {}
+        }
+
+        self.regs.cr2.modify(|_, w| unsafe {
+            w.addm7().bit(address_len == AddressLen::A7);
+            cfg_if! {
+                if #[cfg(any(feature = "f3", feature = "l4", feature = "h7", feature = "wl"))] {
+                    w.add().bits(address)
+                } else {
+                    w.add0_3().bits(address & 0b1111);
+                    w.add4_7().bits(address >> 4)
+                }
+            }
+        });
+
+        self.regs.cr1.modify(|_, w| {
+            // Address mark wakeup, instead of idle line.
+            w.wake().set_bit();
+            w.mme().set_bit()
+        });
+
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.ue().set_bit());
+        }
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Disable multiprocessor communication; the receiver processes all characters. Clears `USART_CR1`
+    /// register, `MME` field.
+    pub fn disable_multiprocessor(&mut self) {
+        self.regs.cr1.modify(|_, w| w.mme().clear_bit());
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Put the receiver in mute mode, until it receives a matching address. Sets `USART_RQR`
+    /// register, `MMRQ` field. Requires multiprocessor communication to be enabled.
+    pub fn enter_mute(&mut self) {
+        self.regs.rqr.write(|w| w.mmrq().set_bit());
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Returns `true` if the receiver is in mute mode. Reads `USART_ISR` register, `RWU` field.
+    pub fn is_muted(&self) -> bool {
+        self.regs.isr.read().rwu().bit_is_set()
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Send an address character, with the address mark (MSB) set, to wake the receivers with a
+    /// matching address. Blocks until the transmission is complete. Requires 9-bit words.
+    pub fn send_address(&mut self, address: u8) {
+        self.write_9bit(&[0x100 | address as u16]);
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Transmit 9-bit words. Only the lower 9 bits of each word are sent. Blocks until the
+    /// transmission is complete.
+    pub fn write_9bit(&mut self, data: &[u16]) {
+        for word in data {
+            while self.regs.isr.read().txe().bit_is_clear() {}
+            self.regs
+                .tdr
+                .modify(|_, w| unsafe { w.tdr().bits(*word & 0x1ff) });
+        }
+        while self.regs.isr.read().tc().bit_is_clear() {}
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Receive 9-bit words into a buffer. The MSB (bit 8) of each word is the address mark, when
+    /// using multiprocessor communication.
+    pub fn read_9bit(&mut self, buf: &mut [u16]) {
+        for word in buf {
+            while self.regs.isr.read().rxne().bit_is_clear() {}
+            *word = self.regs.rdr.read().rdr().bits();
+        }
+    }
+
     #[cfg(not(any(feature = "g0", feature = "h7", feature = "f4", feature = "l5")))]
     /// Transmit data using DMA. (L44 RM, section 38.5.15)
     /// Note that the `channel` argument is only used on F3 and L4.
@@ -491,6 +806,214 @@ where
         // controller generates an interrupt on the DMA channel interrupt vector.
     }
 
+    /// Enable synchronous master mode: The USART outputs a clock on its CK pin, and can be used
+    /// like an SPI master, with start and stop bits framing each word. Data is sent LSB first.
+    /// `last_bit_clock` outputs a clock pulse for the last data bit. Configure the CK pin in
+    /// its alternate function mode. Sets `USART_CR2` register, `CLKEN`, `CPOL`, `CPHA`, and
+    /// `LBCL` fields. See G4 RM, section 37.5.15: USART synchronous mode.
+    pub fn enable_synchronous(&mut self, mode: SpiMode, last_bit_clock: bool) {
+        // RM: "This bit field can only be written when the USART is disabled (UE=0)."
+        let originally_enabled = self.regs.cr1.read().ue().bit_is_set();
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.ue().clear_bit());
+            while self.regs.cr1.read().ue().bit_is_set() {}
+        }
+
+        // RM: "In synchronous mode, the following bits must be kept cleared:
+        // • LINEN bit in the USART_CR2 register,
+        // • SCEN, HDSEL and IREN bits in the USART_CR3 register."
+        self.regs.cr3.modify(|_, w| {
+            w.scen().clear_bit();
+            w.hdsel().clear_bit();
+            w.iren().clear_bit()
+        });
+
+        self.regs.cr2.modify(|_, w| {
+            w.linen().clear_bit();
+            w.cpol().bit(mode.polarity as u8 != 0);
+            w.cpha().bit(mode.phase as u8 != 0);
+            w.lbcl().bit(last_bit_clock);
+            w.clken().set_bit()
+        });
+
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.ue().set_bit());
+        }
+    }
+
+    /// Disable synchronous mode, and the CK pin output. Clears `USART_CR2` register, `CLKEN` field.
+    pub fn disable_synchronous(&mut self) {
+        let originally_enabled = self.regs.cr1.read().ue().bit_is_set();
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.ue().clear_bit());
+            while self.regs.cr1.read().ue().bit_is_set() {}
+        }
+
+        self.regs.cr2.modify(|_, w| w.clken().clear_bit());
+
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.ue().set_bit());
+        }
+    }
+
+    /// Full-duplex transfer in synchronous mode: Send each word in `words`, and replace it with the
+    /// word received on the same clock pulses. The clock only runs while transmitting, so this
+    /// is how to read from a device in synchronous mode.
+    pub fn transfer(&mut self, words: &mut [u8]) {
+        for word in words.iter_mut() {
+            cfg_if! {
+                if #[cfg(not(feature = "f4"))] {
+                    while self.regs.isr.read().txe().bit_is_clear() {}
+                    self.regs
+                        .tdr
+                        .modify(|_, w| unsafe { w.tdr().bits(*word as u16) });
+                    while self.regs.isr.read().rxne().bit_is_clear() {}
+                    *word = self.regs.rdr.read().rdr().bits() as u8;
+                } else {
+                    while self.regs.sr.read().txe().bit_is_clear() {}
+                    self.regs
+                        .dr
+                        .modify(|_, w| unsafe { w.dr().bits(*word as u16) });
+                    while self.regs.sr.read().rxne().bit_is_clear() {}
+                    *word = self.regs.dr.read().dr().bits() as u8;
+                }
+            }
+        }
+
+        self.flush();
+    }
+
+    /// Returns `true` if the TX and RX FIFOs are enabled. Always `false` on families without them.
+    pub fn fifo_enabled(&self) -> bool {
+        cfg_if! {
+            if #[cfg(any(feature = "f3", feature = "f4", feature = "l4"))] {
+                false
+            } else {
+                self.regs.cr1.read().fifoen().bit_is_set()
+            }
+        }
+    }
+
+    #[cfg(not(any(feature = "f3", feature = "f4", feature = "l4")))]
+    /// Set the FIFO levels that trigger the `TxFifoThreshold` and `RxFifoThreshold` interrupts.
+    /// `tx` is the fraction of the TX FIFO that's empty, and `rx` is the fraction of the RX FIFO
+    /// that's full. Sets `USART_CR3` register, `TXFTCFG` and `RXFTCFG` fields.
+    pub fn set_fifo_thresholds(&mut self, tx: FifoThreshold, rx: FifoThreshold) {
+        self.regs.cr3.modify(|_, w| unsafe {
+            w.txftcfg().bits(tx as u8);
+            w.rxftcfg().bits(rx as u8)
+        });
+    }
+
+    /// Write words from `data` until the transmitter (or its FIFO) is full, without blocking.
+    /// Returns the number of words written. Use this in a `TransmitEmpty` or `TxFifoThreshold`
+    /// interrupt handler; without FIFOs, at most one word is written.
+    pub fn write_available(&mut self, data: &[u8]) -> usize {
+        let mut written = 0;
+
+        for word in data {
+            cfg_if! {
+                if #[cfg(not(feature = "f4"))] {
+                    // TXFNF, in FIFO mode.
+                    if self.regs.isr.read().txe().bit_is_clear() {
+                        break;
+                    }
+                    self.regs
+                        .tdr
+                        .modify(|_, w| unsafe { w.tdr().bits(*word as u16) });
+                } else {
+                    if self.regs.sr.read().txe().bit_is_clear() {
+                        break;
+                    }
+                    self.regs
+                        .dr
+                        .modify(|_, w| unsafe { w.dr().bits(*word as u16) });
+                }
+            }
+            written += 1;
+        }
+
+        written
+    }
+
+    /// Read received words into `buf` until the receiver (or its FIFO) is empty, without blocking.
+    /// Returns the number of words read. Use this in a `ReadNotEmpty` or `RxFifoThreshold`
+    /// interrupt handler to drain the FIFO; without FIFOs, at most one word is read.
+    pub fn read_available(&mut self, buf: &mut [u8]) -> usize {
+        let mut read = 0;
+
+        for word in buf.iter_mut() {
+            cfg_if! {
+                if #[cfg(not(feature = "f4"))] {
+                    // RXFNE, in FIFO mode.
+                    if self.regs.isr.read().rxne().bit_is_clear() {
+                        break;
+                    }
+                    *word = self.regs.rdr.read().rdr().bits() as u8;
+                } else {
+                    if self.regs.sr.read().rxne().bit_is_clear() {
+                        break;
+                    }
+                    *word = self.regs.dr.read().dr().bits() as u8;
+                }
+            }
+            read += 1;
+        }
+
+        read
+    }
+
+    /// Read the peripheral's status flags, without modifying them. Reads the `USART_ISR`
+    /// register. (`USART_SR` on F4)
+    pub fn dump_state(&self) -> UsartState {
+        cfg_if! {
+            if #[cfg(feature = "f4")] {
+                let sr = self.regs.sr.read();
+
+                UsartState {
+                    enabled: self.regs.cr1.read().ue().bit_is_set(),
+                    overrun: sr.ore().bit_is_set(),
+                    framing_error: sr.fe().bit_is_set(),
+                    parity_error: sr.pe().bit_is_set(),
+                    noise: sr.nf().bit_is_set(),
+                    idle: sr.idle().bit_is_set(),
+                    tx_empty: sr.txe().bit_is_set(),
+                    rx_not_empty: sr.rxne().bit_is_set(),
+                    tx_complete: sr.tc().bit_is_set(),
+                    ..Default::default()
+                }
+            } else {
+                let isr = self.regs.isr.read();
+
+                #[cfg(feature = "wl")]
+                let noise = isr.ne().bit_is_set();
+                #[cfg(not(feature = "wl"))]
+                let noise = isr.nf().bit_is_set();
+
+                #[cfg(any(feature = "f3", feature = "l4"))]
+                let (rx_fifo_full, tx_fifo_empty) = (false, false);
+                #[cfg(not(any(feature = "f3", feature = "l4")))]
+                let (rx_fifo_full, tx_fifo_empty) =
+                    (isr.rxff().bit_is_set(), isr.txfe().bit_is_set());
+
+                UsartState {
+                    enabled: self.regs.cr1.read().ue().bit_is_set(),
+                    busy: isr.busy().bit_is_set(),
+                    overrun: isr.ore().bit_is_set(),
+                    framing_error: isr.fe().bit_is_set(),
+                    parity_error: isr.pe().bit_is_set(),
+                    noise,
+                    idle: isr.idle().bit_is_set(),
+                    tx_empty: isr.txe().bit_is_set(),
+                    rx_not_empty: isr.rxne().bit_is_set(),
+                    tx_complete: isr.tc().bit_is_set(),
+                    rx_fifo_full,
+                    tx_fifo_empty,
+                }
+            }
+        }
+    }
+
     /// Flush the transmit buffer.
     pub fn flush(&self) {
         #[cfg(not(feature = "f4"))]
@@ -518,9 +1041,9 @@ where
                     cfg_if! {
                         if #[cfg(any(feature = "f3", feature = "l4", feature = "h7", feature = "wl"))] {
                             w.add().bits(char)
-                        } else { // todo: Is this right, or backwards?
+                        } else {
                             w.add0_3().bits(char & 0b1111);
-                            w.add4_7().bits(char & (0b1111 << 4))
+                            w.add4_7().bits(char >> 4)
                         }
                     }
                 });
@@ -562,11 +1085,93 @@ where
             UsartInterrupt::TransmitEmpty => {
                 self.regs.cr1.modify(|_, w| w.txeie().set_bit());
             }
+            #[cfg(not(any(feature = "f3", feature = "l4")))]
+            UsartInterrupt::TxFifoThreshold => {
+                self.regs.cr3.modify(|_, w| w.txftie().set_bit());
+            }
+            #[cfg(not(any(feature = "f3", feature = "l4")))]
+            UsartInterrupt::RxFifoThreshold => {
+                self.regs.cr3.modify(|_, w| w.rxftie().set_bit());
+            }
         }
 
         self.regs.cr1.modify(|_, w| w.ue().set_bit());
     }
 
+    #[cfg(not(feature = "f4"))]
+    /// Enable a specific type of interrupt, and unmask the U[S]ART's interrupt line in the NVIC.
+    /// `irqs` is the token created by `bind_interrupts!`, and proves a handler exists for it.
+    pub fn bind_interrupt(&mut self, interrupt: UsartInterrupt, irqs: &impl Binding<R>) {
+        self.enable_interrupt(interrupt);
+        crate::interrupt::unmask(irqs);
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Disable a specific type of interrupt. Note that `FramingError` and `Overrun` share an
+    /// enable bit (`USART_CR3` register, `EIE` field), so disabling one disables both.
+    pub fn disable_interrupt(&mut self, interrupt: UsartInterrupt) {
+        match interrupt {
+            UsartInterrupt::CharDetect(_) => self.regs.cr1.modify(|_, w| w.cmie().clear_bit()),
+            UsartInterrupt::Cts => self.regs.cr3.modify(|_, w| w.ctsie().clear_bit()),
+            UsartInterrupt::EndOfBlock => self.regs.cr1.modify(|_, w| w.eobie().clear_bit()),
+            UsartInterrupt::Idle => self.regs.cr1.modify(|_, w| w.idleie().clear_bit()),
+            UsartInterrupt::FramingError | UsartInterrupt::Overrun => {
+                self.regs.cr3.modify(|_, w| w.eie().clear_bit())
+            }
+            UsartInterrupt::LineBreak => self.regs.cr2.modify(|_, w| w.lbdie().clear_bit()),
+            UsartInterrupt::ParityError => self.regs.cr1.modify(|_, w| w.peie().clear_bit()),
+            UsartInterrupt::ReadNotEmpty => self.regs.cr1.modify(|_, w| w.rxneie().clear_bit()),
+            UsartInterrupt::ReceiverTimeout => self.regs.cr1.modify(|_, w| w.rtoie().clear_bit()),
+            #[cfg(not(any(feature = "f3", feature = "l4")))]
+            UsartInterrupt::Tcbgt => self.regs.cr3.modify(|_, w| w.tcbgtie().clear_bit()),
+            UsartInterrupt::TransmissionComplete => {
+                self.regs.cr1.modify(|_, w| w.tcie().clear_bit())
+            }
+            UsartInterrupt::TransmitEmpty => self.regs.cr1.modify(|_, w| w.txeie().clear_bit()),
+            #[cfg(not(any(feature = "f3", feature = "l4")))]
+            UsartInterrupt::TxFifoThreshold => self.regs.cr3.modify(|_, w| w.txftie().clear_bit()),
+            #[cfg(not(any(feature = "f3", feature = "l4")))]
+            UsartInterrupt::RxFifoThreshold => self.regs.cr3.modify(|_, w| w.rxftie().clear_bit()),
+        }
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Check if an interrupt flag is set, eg to determine which event triggered the U[S]ART
+    /// interrupt. The flags are set whether or not the interrupt is enabled. Reads the `USART_ISR`
+    /// register. Example, parsing lines with `CharDetect(b'\n')` and `ReceiverTimeout` enabled:
+    /// `if usart.read_flag(UsartInterrupt::CharDetect(b'\n')) { usart.clear_flag(UsartInterrupt::CharDetect(b'\n')); }`
+    pub fn read_flag(&self, interrupt: UsartInterrupt) -> bool {
+        let isr = self.regs.isr.read();
+
+        match interrupt {
+            UsartInterrupt::CharDetect(_) => isr.cmf().bit_is_set(),
+            UsartInterrupt::Cts => isr.ctsif().bit_is_set(),
+            UsartInterrupt::EndOfBlock => isr.eobf().bit_is_set(),
+            UsartInterrupt::Idle => isr.idle().bit_is_set(),
+            UsartInterrupt::FramingError => isr.fe().bit_is_set(),
+            UsartInterrupt::LineBreak => isr.lbdf().bit_is_set(),
+            UsartInterrupt::Overrun => isr.ore().bit_is_set(),
+            UsartInterrupt::ParityError => isr.pe().bit_is_set(),
+            UsartInterrupt::ReadNotEmpty => isr.rxne().bit_is_set(),
+            UsartInterrupt::ReceiverTimeout => isr.rtof().bit_is_set(),
+            #[cfg(not(any(feature = "f3", feature = "l4")))]
+            UsartInterrupt::Tcbgt => isr.tcbgt().bit_is_set(),
+            UsartInterrupt::TransmissionComplete => isr.tc().bit_is_set(),
+            UsartInterrupt::TransmitEmpty => isr.txe().bit_is_set(),
+            #[cfg(not(any(feature = "f3", feature = "l4")))]
+            UsartInterrupt::TxFifoThreshold => isr.txft().bit_is_set(),
+            #[cfg(not(any(feature = "f3", feature = "l4")))]
+            UsartInterrupt::RxFifoThreshold => isr.rxft().bit_is_set(),
+        }
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Clear an interrupt flag. The same as `clear_interrupt`; provided for symmetry with
+    /// `read_flag`.
+    pub fn clear_flag(&mut self, interrupt: UsartInterrupt) {
+        self.clear_interrupt(interrupt);
+    }
+
     #[cfg(not(feature = "f4"))]
     /// Clears the interrupt pending flag for a specific type of interrupt.
     pub fn clear_interrupt(&mut self, interrupt: UsartInterrupt) {
@@ -587,10 +1192,75 @@ where
             UsartInterrupt::Tcbgt => self.regs.icr.write(|w| w.tcbgtc().set_bit()),
             UsartInterrupt::TransmissionComplete => self.regs.icr.write(|w| w.tccf().set_bit()),
             UsartInterrupt::TransmitEmpty => self.regs.rqr.write(|w| w.txfrq().set_bit()),
+            // These flags are cleared by hardware, when the FIFO level passes the threshold.
+            #[cfg(not(any(feature = "f3", feature = "l4")))]
+            UsartInterrupt::TxFifoThreshold | UsartInterrupt::RxFifoThreshold => (),
+        }
+    }
+}
+
+/// Modbus RTU silent intervals, in bit durations. These are the values to use with
+/// `Usart::set_receiver_timeout`.
+#[derive(Clone, Copy)]
+pub struct ModbusSilence {
+    /// The longest allowed gap between characters of a frame (1.5 characters). A longer gap
+    /// within a frame makes it invalid.
+    pub char: u32,
+    /// The shortest gap between frames (3.5 characters). A gap this long marks the end of a frame.
+    pub frame: u32,
+}
+
+/// Find the Modbus RTU silent intervals at a given baud rate. Per the Modbus serial line spec,
+/// a character is always 11 bits (start, 8 data bits, parity or a second stop bit, and stop).
+/// Above 19200 baud, fixed intervals of 750μs and 1750μs are used instead.
+pub fn modbus_rtu_silence(baud: u32) -> ModbusSilence {
+    if baud > 19_200 {
+        // Round up, so we never end a frame early.
+        let us_to_bits = |us: u32| ((us as u64 * baud as u64 + 999_999) / 1_000_000) as u32;
+
+        ModbusSilence {
+            char: us_to_bits(750),
+            frame: us_to_bits(1_750),
+        }
+    } else {
+        // 1.5 and 3.5 characters of 11 bits, rounded up.
+        ModbusSilence {
+            char: 17,
+            frame: 39,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Default)]
+/// A snapshot of a U[S]ART peripheral's status, from `Usart::dump_state`; eg to log when
+/// communication stalls. Flags a family doesn't have read as `false`.
+pub struct UsartState {
+    /// `CR1` register, `UE` field.
+    pub enabled: bool,
+    /// `ISR` register, `BUSY` field: Reception is in progress. Always `false` on F4.
+    pub busy: bool,
+    /// `ORE` field.
+    pub overrun: bool,
+    /// `FE` field.
+    pub framing_error: bool,
+    /// `PE` field.
+    pub parity_error: bool,
+    /// `NF` field. (`NE` on WL)
+    pub noise: bool,
+    /// `IDLE` field.
+    pub idle: bool,
+    /// `TXE` field. (`TXFNF` when the FIFO is enabled)
+    pub tx_empty: bool,
+    /// `RXNE` field. (`RXFNE` when the FIFO is enabled)
+    pub rx_not_empty: bool,
+    /// `TC` field.
+    pub tx_complete: bool,
+    /// `RXFF` field.
+    pub rx_fifo_full: bool,
+    /// `TXFE` field.
+    pub tx_fifo_empty: bool,
+}
+
 /// Serial error
 #[non_exhaustive]
 #[derive(Debug)]
@@ -708,3 +1378,172 @@ where
         Ok(())
     }
 }
+
+#[cfg(feature = "async")]
+/// Interrupt handler for async reads and writes. Masks the interrupts enabled while awaiting,
+/// and wakes the awaiting task. Set this up using the `async_interrupt!` macro.
+pub fn on_interrupt_async(regs: &pac::usart1::RegisterBlock) {
+    regs.cr1.modify(|_, w| {
+        w.txeie().clear_bit();
+        w.rxneie().clear_bit();
+        w.tcie().clear_bit()
+    });
+
+    USART_WAKERS.wake(regs as *const _ as usize);
+}
+
+#[cfg(feature = "async")]
+impl<R> Usart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Read a single word, waiting asynchronously until it's available. Requires the U[S]ART
+    /// interrupt handler to be set up with `async_interrupt!`.
+    pub async fn read_one_async(&mut self) -> Result<u8, Error> {
+        {
+            let regs = &*self.regs;
+
+            asynch::wait_for(
+                &USART_WAKERS,
+                regs as *const _ as usize,
+                || {
+                    #[cfg(not(feature = "f4"))]
+                    let isr = regs.isr.read();
+                    #[cfg(feature = "f4")]
+                    let isr = regs.sr.read();
+
+                    // RM: "An interrupt is generated if RXNEIE=1 and ORE=1 or RXNE=1"
+                    isr.rxne().bit_is_set() || isr.ore().bit_is_set()
+                },
+                || regs.cr1.modify(|_, w| w.rxneie().set_bit()),
+            )
+            .await;
+        }
+
+        cfg_if! {
+            if #[cfg(not(feature = "f4"))] {
+                if self.regs.isr.read().ore().bit_is_set() {
+                    self.regs.icr.write(|w| w.orecf().set_bit());
+                    return Err(Error::Overrun);
+                }
+            } else {
+                // RM: "It is cleared by a software sequence (an read to the USART_SR register
+                // followed by a read to the USART_DR register)."
+                if self.regs.sr.read().ore().bit_is_set() {
+                    self.regs.dr.read();
+                    return Err(Error::Overrun);
+                }
+            }
+        }
+
+        Ok(self.read_one())
+    }
+
+    /// Write a single word, waiting asynchronously until the transmit register is empty. Requires
+    /// the U[S]ART interrupt handler to be set up with `async_interrupt!`.
+    pub async fn write_one_async(&mut self, word: u8) {
+        let regs = &*self.regs;
+
+        asynch::wait_for(
+            &USART_WAKERS,
+            regs as *const _ as usize,
+            || {
+                #[cfg(not(feature = "f4"))]
+                return regs.isr.read().txe().bit_is_set();
+                #[cfg(feature = "f4")]
+                return regs.sr.read().txe().bit_is_set();
+            },
+            || regs.cr1.modify(|_, w| w.txeie().set_bit()),
+        )
+        .await;
+
+        #[cfg(not(feature = "f4"))]
+        regs.tdr.write(|w| unsafe { w.tdr().bits(word as u16) });
+        #[cfg(feature = "f4")]
+        regs.dr.write(|w| unsafe { w.dr().bits(word as u16) });
+    }
+
+    /// Wait asynchronously for any ongoing transmission to complete.
+    pub async fn flush_async(&mut self) {
+        let regs = &*self.regs;
+
+        asynch::wait_for(
+            &USART_WAKERS,
+            regs as *const _ as usize,
+            || {
+                #[cfg(not(feature = "f4"))]
+                return regs.isr.read().tc().bit_is_set();
+                #[cfg(feature = "f4")]
+                return regs.sr.read().tc().bit_is_set();
+            },
+            || regs.cr1.modify(|_, w| w.tcie().set_bit()),
+        )
+        .await;
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R> ErrorType for Usart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    type Error = Error;
+}
+
+#[cfg(feature = "async")]
+impl<R> embedded_io_async::Read for Usart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Wait for at least one word, then read any others that are immediately available.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        buf[0] = self.read_one_async().await?;
+
+        let mut i = 1;
+        while i < buf.len() {
+            #[cfg(not(feature = "f4"))]
+            let rxne = self.regs.isr.read().rxne().bit_is_set();
+            #[cfg(feature = "f4")]
+            let rxne = self.regs.sr.read().rxne().bit_is_set();
+
+            if !rxne {
+                break;
+            }
+
+            buf[i] = self.read_one();
+            i += 1;
+        }
+
+        Ok(i)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R> embedded_io_async::Write for Usart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        for word in buf {
+            self.write_one_async(*word).await;
+        }
+
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.flush_async().await;
+        Ok(())
+    }
+}
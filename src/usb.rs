@@ -78,7 +78,7 @@ unsafe impl UsbPeripheral for Peripheral {
     fn enable() {
         let rcc = unsafe { &*RCC::ptr() };
 
-        cortex_m::interrupt::free(|_| {
+        crate::util::free(|_| {
             cfg_if! {
                 if #[cfg(feature = "l4")] {
                     rcc_en_reset!(apb1, usbfs, rcc);
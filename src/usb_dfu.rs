@@ -0,0 +1,192 @@
+//! A USB DFU (Device Firmware Upgrade) runtime interface, so host tools like `dfu-util` and
+//! STM32CubeProgrammer can switch a running device into its bootloader, for updates in the field
+//! without pressing a boot button. Add `UsbDfuRuntime` alongside the device's other classes. On a
+//! `DFU_DETACH` request, it acknowledges the request, then once the status stage has been sent,
+//! writes `power::BOOTLOADER_REQUEST` to a backup register, and resets.
+//!
+//! To use the built-in bootloader, call `power::check_bootloader_request` with the same register
+//! first thing in `main`; it enters the bootloader after the reset. A custom bootloader can check
+//! the register itself instead.
+//!
+//! Requires the `usb-dfu` feature, and `usb`, `usbotg_fs`, or `usbotg_hs`.
+//!
+//! Example, with a serial port:
+//!
+//! `let mut dfu = UsbDfuRuntime::new(&usb_bus, 0);`
+//! In the USB interrupt: `usb_dev.poll(&mut [&mut serial, &mut dfu]);`
+//!
+//! See the USB DFU specification, version 1.1, section 4.1: Run-Time Descriptor Set.
+
+use core::marker::PhantomData;
+
+use usb_device::{
+    bus::{InterfaceNumber, UsbBus, UsbBusAllocator},
+    class::{ControlIn, ControlOut, UsbClass},
+    control::{Recipient, RequestType},
+    descriptor::DescriptorWriter,
+    Result,
+};
+
+use crate::power;
+
+/// Application-specific interface class.
+const CLASS_APPLICATION_SPECIFIC: u8 = 0xfe;
+const SUBCLASS_DFU: u8 = 0x01;
+const PROTOCOL_RUNTIME: u8 = 0x01;
+
+/// The DFU functional descriptor's type.
+const DESCRIPTOR_DFU_FUNCTIONAL: u8 = 0x21;
+
+/// Functional descriptor attributes: `bitWillDetach`; the device detaches and re-attaches itself
+/// on `DFU_DETACH`, instead of waiting for a bus reset. Also `bitCanUpload` and `bitCanDnload`,
+/// as supported by the built-in bootloader.
+const ATTRIBUTES: u8 = 0b1011;
+/// The longest the host waits for the device to detach, in ms.
+const DETACH_TIMEOUT_MS: u16 = 1_000;
+/// The largest data block the bootloader accepts per request, in bytes.
+const TRANSFER_SIZE: u16 = 2_048;
+/// DFU version 1.1a, with ST's DfuSe extensions, as implemented by the built-in bootloader.
+const DFU_VERSION: u16 = 0x011a;
+
+/// Class requests.
+const DFU_DETACH: u8 = 0;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_GETSTATE: u8 = 5;
+
+/// `bState` values in runtime mode.
+const STATE_APP_IDLE: u8 = 0;
+const STATE_APP_DETACH: u8 = 1;
+
+#[derive(Clone, Copy, PartialEq)]
+/// Progress of a detach requested by the host.
+enum Detach {
+    None,
+    /// `DFU_DETACH` was accepted in the current poll; its status stage is queued.
+    Requested,
+    /// The poll that accepted the request has finished; reset at the next one.
+    Acknowledged,
+}
+
+/// A DFU runtime interface, which resets into the bootloader on request from the host.
+pub struct UsbDfuRuntime<B: UsbBus> {
+    interface: InterfaceNumber,
+    /// The backup register the bootloader request is written to.
+    backup_reg: usize,
+    detach: Detach,
+    _bus: PhantomData<B>,
+}
+
+impl<B: UsbBus> UsbDfuRuntime<B> {
+    /// Allocate the interface. `backup_reg` is the backup register to write the bootloader request
+    /// to; pass the same one to `power::check_bootloader_request`.
+    pub fn new(alloc: &UsbBusAllocator<B>, backup_reg: usize) -> Self {
+        Self {
+            interface: alloc.interface(),
+            backup_reg,
+            detach: Detach::None,
+            _bus: PhantomData,
+        }
+    }
+
+    /// Returns `true` if the host has requested a detach, and the reset is pending.
+    pub fn detach_requested(&self) -> bool {
+        self.detach != Detach::None
+    }
+
+    fn state(&self) -> u8 {
+        if self.detach_requested() {
+            STATE_APP_DETACH
+        } else {
+            STATE_APP_IDLE
+        }
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for UsbDfuRuntime<B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(
+            self.interface,
+            CLASS_APPLICATION_SPECIFIC,
+            SUBCLASS_DFU,
+            PROTOCOL_RUNTIME,
+        )?;
+
+        let timeout = DETACH_TIMEOUT_MS.to_le_bytes();
+        let transfer_size = TRANSFER_SIZE.to_le_bytes();
+        let version = DFU_VERSION.to_le_bytes();
+
+        writer.write(
+            DESCRIPTOR_DFU_FUNCTIONAL,
+            &[
+                ATTRIBUTES,
+                timeout[0],
+                timeout[1],
+                transfer_size[0],
+                transfer_size[1],
+                version[0],
+                version[1],
+            ],
+        )
+    }
+
+    /// Runs at the end of each `UsbDevice::poll` that handled an endpoint event. The poll that
+    /// handles `DFU_DETACH` only queues its status stage; the next one handles the status stage
+    /// being sent, so the host has seen the request succeed before we reset. Resetting on the
+    /// poll that handles the request would drop the status stage, and the host reports an error.
+    fn poll(&mut self) {
+        match self.detach {
+            Detach::None => (),
+            Detach::Requested => self.detach = Detach::Acknowledged,
+            Detach::Acknowledged => power::reset_to_bootloader(self.backup_reg),
+        }
+    }
+
+    /// A bus reset after the request is acknowledged means the host has finished with it.
+    fn reset(&mut self) {
+        if self.detach == Detach::Acknowledged {
+            power::reset_to_bootloader(self.backup_reg);
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = xfer.request();
+
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.interface) as u16
+        {
+            return;
+        }
+
+        match req.request {
+            DFU_DETACH => {
+                // Reset from a later `poll`, after the status stage is sent.
+                if self.detach == Detach::None {
+                    self.detach = Detach::Requested;
+                }
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = xfer.request();
+
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.interface) as u16
+        {
+            return;
+        }
+
+        match req.request {
+            // `bStatus` OK, `bwPollTimeout` 0, `bState`, and no status string.
+            DFU_GETSTATUS => xfer.accept_with(&[0, 0, 0, 0, self.state(), 0]).ok(),
+            DFU_GETSTATE => xfer.accept_with(&[self.state()]).ok(),
+            _ => xfer.reject().ok(),
+        };
+    }
+}
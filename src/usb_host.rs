@@ -0,0 +1,1139 @@
+//! Minimal USB host support on the OTG_FS and OTG_HS peripherals, eg to read a USB flash drive
+//! (mass storage), or talk to an FTDI serial adapter. Supports a single full-speed or low-speed
+//! device attached directly to the port, using the embedded full-speed PHY; not hubs, high speed,
+//! or isochronous and interrupt scheduling.
+//!
+//! `UsbHost` controls the port's power, detects and resets the attached device, and runs blocking
+//! control and bulk transfers. It provides the enumeration primitives (`GET_DESCRIPTOR`,
+//! `SET_ADDRESS`, and `SET_CONFIGURATION`); class drivers, eg Bulk-Only Transport for mass
+//! storage, are built on `control_in`, `control_out`, `bulk_in`, and `bulk_out`.
+//!
+//! Transfers use the core's slave mode, by polling; interrupts don't need to be enabled. Channels
+//! 0 and 1 are reserved for control transfers; open bulk channels from 2.
+//!
+//! The USB kernel clock must be 48Mhz, eg from HSI48 or the PLL, and the D+ and D- pins set to
+//! their alternate function. Requires the `usb-host` feature. Available on F4 and H7.
+//!
+//! Example, finding an attached device's bulk endpoints:
+//!
+//! ```
+//! let mut host = UsbHost::new(dp.OTG_FS_GLOBAL, Some(vbus_en), &mut delay);
+//! host.set_port_power(true);
+//! while !host.is_connected() {}
+//!
+//! let device = host.enumerate(1, &mut delay)?;
+//! let mut config = [0; 128];
+//! let len = host.get_configuration_descriptor(1, 0, &mut config)?;
+//! host.set_configuration(1, config[5])?;
+//!
+//! let bulk = endpoints(&config[..len])
+//!     .filter(|ep| ep.ep_type == EpType::Bulk)
+//!     .find(|ep| ep.is_in())
+//!     .unwrap();
+//! let mut ch = host.open_channel(2, 1, bulk.number(), EpType::Bulk, true, bulk.max_packet);
+//!
+//! let mut buf = [0; 512];
+//! let count = host.bulk_in(&mut ch, &mut buf)?;
+//! ```
+//!
+//! See F4 RM, section 35.15.2: "Host initialization", and 35.17.4: "Host programming model".
+
+use cortex_m::delay::Delay;
+
+use crate::{
+    gpio::Pin,
+    pac::{self, RCC},
+    util::{free, RccPeriph},
+};
+
+#[cfg(feature = "h7")]
+use crate::pac::PWR;
+
+use cfg_if::cfg_if;
+
+// The core's register layout is the same on each OTG peripheral, but the PAC gives each its own
+// types, and names some fields differently; we access them by offset from its base address.
+const GAHBCFG: usize = 0x008;
+const GUSBCFG: usize = 0x00c;
+const GRSTCTL: usize = 0x010;
+const GINTSTS: usize = 0x014;
+const GRXSTSP: usize = 0x020;
+const GRXFSIZ: usize = 0x024;
+const HNPTXFSIZ: usize = 0x028;
+const HNPTXSTS: usize = 0x02c;
+const GCCFG: usize = 0x038;
+const HPTXFSIZ: usize = 0x100;
+const HCFG: usize = 0x400;
+const HFIR: usize = 0x404;
+const HFNUM: usize = 0x408;
+const HAINT: usize = 0x414;
+const HPRT: usize = 0x440;
+const PCGCCTL: usize = 0xe00;
+
+/// Per-channel registers: `HCCHARx` is at `HC_BASE + x * HC_STRIDE`.
+const HC_BASE: usize = 0x500;
+const HC_STRIDE: usize = 0x20;
+const HCCHAR: usize = 0x00;
+const HCINT: usize = 0x08;
+const HCTSIZ: usize = 0x10;
+
+/// The data FIFO; writing to `(x + 1) * FIFO_STRIDE` pushes to channel x's transmit
+/// FIFO. Reading from any channel's address pops the receive FIFO.
+const FIFO_STRIDE: usize = 0x1000;
+
+// `HPRT` bits.
+const HPRT_PCSTS: u32 = 1 << 0;
+const HPRT_PCDET: u32 = 1 << 1;
+const HPRT_PENA: u32 = 1 << 2;
+const HPRT_PENCHNG: u32 = 1 << 3;
+const HPRT_POCA: u32 = 1 << 4;
+const HPRT_POCCHNG: u32 = 1 << 5;
+const HPRT_PRST: u32 = 1 << 8;
+const HPRT_PPWR: u32 = 1 << 12;
+/// These bits are cleared by writing 1; writing 1 to `PENA` disables the port. Mask them out when
+/// modifying other bits.
+const HPRT_W1C: u32 = HPRT_PCDET | HPRT_PENA | HPRT_PENCHNG | HPRT_POCCHNG;
+
+// `HCCHARx` bits.
+const HCCHAR_EPDIR: u32 = 1 << 15;
+const HCCHAR_LSDEV: u32 = 1 << 17;
+const HCCHAR_CHDIS: u32 = 1 << 30;
+const HCCHAR_CHENA: u32 = 1 << 31;
+
+// `HCINTx` bits.
+const HCINT_XFRC: u32 = 1 << 0;
+const HCINT_CHH: u32 = 1 << 1;
+const HCINT_STALL: u32 = 1 << 3;
+const HCINT_NAK: u32 = 1 << 4;
+const HCINT_ACK: u32 = 1 << 5;
+const HCINT_BBERR: u32 = 1 << 8;
+const HCINT_FRMOR: u32 = 1 << 9;
+const HCINT_DTERR: u32 = 1 << 10;
+const HCINT_ALL: u32 = 0x7ff;
+
+/// `GINTSTS` register, `RXFLVL` field: The receive FIFO is non-empty.
+const GINTSTS_RXFLVL: u32 = 1 << 4;
+
+/// `GRXSTSP` register, `PKTSTS` field values.
+const PKTSTS_IN_DATA: u32 = 0b0010;
+
+/// FIFO sizes, in words. These fit the smallest FIFO RAM: OTG_FS's 320 words (1.25KiB). The
+/// receive FIFO holds a maximum-size packet, with status entries.
+const RX_FIFO_WORDS: u32 = 128;
+const NPTX_FIFO_WORDS: u32 = 96;
+const PTX_FIFO_WORDS: u32 = 96;
+
+/// The largest packet size supported, in bytes: The full-speed limit for control, bulk, and
+/// interrupt endpoints.
+const MAX_PACKET: usize = 64;
+
+/// Register polling iterations before giving up on the core, eg a reset, or a channel halting.
+const CORE_TIMEOUT: u32 = 1_000_000;
+/// How many times a packet NAKed by a control endpoint is retried, before returning `Nak`.
+const CONTROL_NAK_RETRIES: u32 = 10_000;
+
+// USB 2.0 spec, section 7.1.7.3 and 9.2.6.3: Port reset and set address timing, in ms.
+const RESET_MS: u32 = 15;
+const RESET_RECOVERY_MS: u32 = 10;
+const SET_ADDRESS_RECOVERY_MS: u32 = 2;
+/// Time for the core to switch to host mode after setting `FHMOD`, in ms.
+const FORCE_MODE_MS: u32 = 25;
+
+// Standard requests, and descriptor types. USB 2.0 spec, Tables 9-4 and 9-5.
+const GET_DESCRIPTOR: u8 = 6;
+const SET_ADDRESS: u8 = 5;
+const SET_CONFIGURATION: u8 = 9;
+const DESC_DEVICE: u8 = 1;
+const DESC_CONFIGURATION: u8 = 2;
+const DESC_INTERFACE: u8 = 4;
+const DESC_ENDPOINT: u8 = 5;
+
+/// Channels reserved for control transfers.
+const CONTROL_OUT_CHANNEL: u8 = 0;
+const CONTROL_IN_CHANNEL: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UsbHostError {
+    /// No device is attached, or the port isn't enabled.
+    NotConnected,
+    /// The core didn't respond in time.
+    Timeout,
+    /// The device stalled the request; eg it's unsupported.
+    Stall,
+    /// The device wasn't ready, eg there's no data to read yet. Retry later.
+    Nak,
+    /// A CRC, timeout, or bit-stuffing error on the bus.
+    Transaction,
+    /// The device sent more data than requested.
+    Babble,
+    DataToggle,
+    FrameOverrun,
+    /// A received packet doesn't fit in the buffer.
+    Overflow,
+    /// A descriptor read is too short, or malformed.
+    BadDescriptor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The attached device's speed. Sets `HCFG` register, `FSLSPCS` field.
+pub enum PortSpeed {
+    Full,
+    Low,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+/// Sets `HCCHARx` register, `EPTYP` field.
+pub enum EpType {
+    Control = 0b00,
+    Isochronous = 0b01,
+    Bulk = 0b10,
+    Interrupt = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Packet IDs. Sets `HCTSIZx` register, `DPID` field.
+enum Pid {
+    Data0 = 0b00,
+    Data1 = 0b10,
+    Setup = 0b11,
+}
+
+/// An OTG peripheral usable as a host.
+pub trait HostPeriph: RccPeriph {
+    /// The number of host channels.
+    const NUM_CHANNELS: u8;
+
+    /// The peripheral's base address.
+    fn base() -> usize;
+}
+
+macro_rules! host_periph {
+    ($periph:ident, $channels:expr) => {
+        impl HostPeriph for pac::$periph {
+            const NUM_CHANNELS: u8 = $channels;
+
+            fn base() -> usize {
+                pac::$periph::ptr() as usize
+            }
+        }
+    };
+}
+
+cfg_if! {
+    if #[cfg(feature = "f4")] {
+        host_periph!(OTG_FS_GLOBAL, 8);
+
+        #[cfg(any(
+            feature = "f405",
+            feature = "f407",
+            feature = "f427",
+            feature = "f429",
+            feature = "f446",
+            feature = "f469",
+        ))]
+        host_periph!(OTG_HS_GLOBAL, 12);
+    } else {
+        host_periph!(OTG1_HS_GLOBAL, 16);
+
+        #[cfg(not(feature = "h7b3"))]
+        host_periph!(OTG2_HS_GLOBAL, 16);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A host channel, set up for one endpoint of one device. Create with `UsbHost::open_channel`.
+/// Tracks the endpoint's data toggle.
+pub struct Channel {
+    /// The channel number. Sets `HCCHARx`, `HCINTx`, and `HCTSIZx` registers.
+    pub num: u8,
+    /// The device's address. Sets `HCCHARx` register, `DAD` field.
+    pub dev_addr: u8,
+    /// The endpoint number, without the direction bit. Sets `HCCHARx` register, `EPNUM` field.
+    pub endpoint: u8,
+    pub ep_type: EpType,
+    /// `true` for an IN endpoint. Sets `HCCHARx` register, `EPDIR` field.
+    pub dir_in: bool,
+    /// The endpoint's maximum packet size. Sets `HCCHARx` register, `MPSIZ` field.
+    pub max_packet: u16,
+    pub low_speed: bool,
+    /// The next data packet is DATA1.
+    toggle: bool,
+}
+
+impl Channel {
+    /// The `HCCHARx` register value for this channel; not enabled.
+    fn hcchar(&self) -> u32 {
+        (self.max_packet as u32 & 0x7ff)
+            | (self.endpoint as u32 & 0xf) << 11
+            | if self.dir_in { HCCHAR_EPDIR } else { 0 }
+            | if self.low_speed { HCCHAR_LSDEV } else { 0 }
+            | (self.ep_type as u32) << 18
+            | 1 << 20 // MCNT: 1 transaction per frame.
+            | (self.dev_addr as u32 & 0x7f) << 22
+    }
+
+    /// Set the data toggle, eg to DATA0 after a `SET_CONFIGURATION`, or clearing a stall.
+    pub fn reset_toggle(&mut self) {
+        self.toggle = false;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// A control request's setup packet. USB 2.0 spec, section 9.3.
+pub struct SetupPacket {
+    /// `bmRequestType`: Direction, type, and recipient.
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    /// The data stage's length, in bytes.
+    pub length: u16,
+}
+
+impl SetupPacket {
+    fn to_bytes(&self) -> [u8; 8] {
+        let value = self.value.to_le_bytes();
+        let index = self.index.to_le_bytes();
+        let length = self.length.to_le_bytes();
+        [
+            self.request_type,
+            self.request,
+            value[0],
+            value[1],
+            index[0],
+            index[1],
+            length[0],
+            length[1],
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// The fields of a device descriptor. USB 2.0 spec, Table 9-8.
+pub struct DeviceDescriptor {
+    /// `bcdUSB`
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    /// The control endpoint's maximum packet size.
+    pub max_packet_size0: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// `bcdDevice`
+    pub device_version: u16,
+    pub num_configurations: u8,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// An endpoint descriptor, with the interface it's in. USB 2.0 spec, Table 9-13.
+pub struct EndpointDescriptor {
+    pub interface: u8,
+    pub interface_class: u8,
+    pub interface_subclass: u8,
+    pub interface_protocol: u8,
+    /// `bEndpointAddress`: The endpoint number, with bit 7 set for IN endpoints.
+    pub address: u8,
+    pub ep_type: EpType,
+    pub max_packet: u16,
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    /// The endpoint number, without the direction bit.
+    pub fn number(&self) -> u8 {
+        self.address & 0xf
+    }
+
+    pub fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+}
+
+/// Iterate over the endpoints in a configuration descriptor, as read by
+/// `get_configuration_descriptor`. Stops at the first malformed descriptor.
+pub fn endpoints(config: &[u8]) -> impl Iterator<Item = EndpointDescriptor> + '_ {
+    let mut i = 0;
+    let mut interface = [0; 4];
+
+    core::iter::from_fn(move || {
+        while i + 2 <= config.len() {
+            let len = config[i] as usize;
+            if len < 2 || i + len > config.len() {
+                return None;
+            }
+            let desc = &config[i..i + len];
+            i += len;
+
+            match desc[1] {
+                DESC_INTERFACE if len >= 9 => {
+                    interface = [desc[2], desc[5], desc[6], desc[7]];
+                }
+                DESC_ENDPOINT if len >= 7 => {
+                    let ep_type = match desc[3] & 0b11 {
+                        0b00 => EpType::Control,
+                        0b01 => EpType::Isochronous,
+                        0b10 => EpType::Bulk,
+                        _ => EpType::Interrupt,
+                    };
+                    return Some(EndpointDescriptor {
+                        interface: interface[0],
+                        interface_class: interface[1],
+                        interface_subclass: interface[2],
+                        interface_protocol: interface[3],
+                        address: desc[2],
+                        ep_type,
+                        max_packet: u16::from_le_bytes([desc[4], desc[5]]) & 0x7ff,
+                        interval: desc[6],
+                    });
+                }
+                _ => (),
+            }
+        }
+        None
+    })
+}
+
+/// Represents an OTG peripheral, in host mode.
+pub struct UsbHost<R> {
+    pub regs: R,
+    /// A pin enabling an external VBUS power switch, if present. Set high to power the port.
+    vbus_en: Option<Pin>,
+    speed: PortSpeed,
+    /// The attached device's control endpoint maximum packet size.
+    max_packet_size0: u16,
+}
+
+impl<R: HostPeriph> UsbHost<R> {
+    /// Initialize the peripheral in host mode, with the port unpowered. `vbus_en` is a pin
+    /// driving the port's power switch, if present; set it to output mode first.
+    pub fn new(regs: R, vbus_en: Option<Pin>, delay: &mut Delay) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            R::en_reset(rcc);
+
+            #[cfg(feature = "h7")]
+            {
+                // Enable the USB regulator, which powers the PHY.
+                let pwr = unsafe { &(*PWR::ptr()) };
+                pwr.cr3.modify(|_, w| w.usb33den().set_bit());
+                while pwr.cr3.read().usb33rdy().bit_is_clear() {}
+            }
+        });
+
+        let mut result = Self {
+            regs,
+            vbus_en,
+            speed: PortSpeed::Full,
+            max_packet_size0: 8,
+        };
+
+        result.init(delay);
+        result
+    }
+
+    /// Reset the core, and configure it as a host. See F4 RM, section 35.15.1: "Core
+    /// initialization", and 35.15.2: "Host initialization".
+    fn init(&mut self, delay: &mut Delay) {
+        if let Some(pin) = &mut self.vbus_en {
+            pin.set_low();
+        }
+
+        // Select the embedded full-speed PHY. (This bit is read-only, and set, on OTG_FS.)
+        self.modify(GUSBCFG, 0, 1 << 6);
+
+        // "Wait for AHB master IDLE state", then soft-reset the core.
+        self.wait(GRSTCTL, 1 << 31, true);
+        self.modify(GRSTCTL, 0, 1);
+        self.wait(GRSTCTL, 1, false);
+
+        // Power up the PHY; VBUS sensing stays disabled, since the port is powered externally.
+        self.modify(GCCFG, 0, 1 << 16);
+        // Force host mode: `FHMOD`, clearing `FDMOD`. The change takes up to 25ms.
+        self.modify(GUSBCFG, 1 << 30, 1 << 29);
+        delay.delay_ms(FORCE_MODE_MS);
+
+        // Restart the PHY clock, if it was stopped.
+        self.write(PCGCCTL, 0);
+
+        self.set_frame_timing(PortSpeed::Full);
+
+        // FIFO RAM: Receive, then non-periodic transmit, then periodic transmit.
+        self.write(GRXFSIZ, RX_FIFO_WORDS);
+        self.write(HNPTXFSIZ, NPTX_FIFO_WORDS << 16 | RX_FIFO_WORDS);
+        self.write(
+            HPTXFSIZ,
+            PTX_FIFO_WORDS << 16 | (RX_FIFO_WORDS + NPTX_FIFO_WORDS),
+        );
+
+        // Flush all transmit FIFOs (`TXFNUM` = 0x10), and the receive FIFO.
+        self.write(GRSTCTL, 0x10 << 6 | 1 << 5);
+        self.wait(GRSTCTL, 1 << 5, false);
+        self.write(GRSTCTL, 1 << 4);
+        self.wait(GRSTCTL, 1 << 4, false);
+
+        for ch in 0..R::NUM_CHANNELS {
+            self.write(hc(ch, HCINT), HCINT_ALL);
+        }
+        self.write(HAINT, 0xffff_ffff);
+        self.write(GINTSTS, 0xffff_ffff);
+
+        // We poll for status: Leave the global interrupt (`GINTMSK`) disabled.
+        self.modify(GAHBCFG, 1, 0);
+    }
+
+    /// Power the port on or off: Sets `HPRT` register, `PPWR` field, and sets the VBUS enable pin,
+    /// if present.
+    pub fn set_port_power(&mut self, on: bool) {
+        if on {
+            self.modify_hprt(0, HPRT_PPWR);
+        } else {
+            self.modify_hprt(HPRT_PPWR, 0);
+        }
+
+        if let Some(pin) = &mut self.vbus_en {
+            if on {
+                pin.set_high();
+            } else {
+                pin.set_low();
+            }
+        }
+    }
+
+    /// Returns `true` if a device is attached. Reads `HPRT` register, `PCSTS` field.
+    pub fn is_connected(&self) -> bool {
+        self.read(HPRT) & HPRT_PCSTS != 0
+    }
+
+    /// Returns `true` if the port reports an overcurrent. Reads `HPRT` register, `POCA` field.
+    /// (Only set if the core's overcurrent input is connected.)
+    pub fn overcurrent(&self) -> bool {
+        self.read(HPRT) & HPRT_POCA != 0
+    }
+
+    /// Returns `true` if a device has attached since the last call, clearing the flag. Reads
+    /// `HPRT` register, `PCDET` field.
+    pub fn connect_detected(&mut self) -> bool {
+        let detected = self.read(HPRT) & HPRT_PCDET != 0;
+        if detected {
+            self.write(HPRT, (self.read(HPRT) & !HPRT_W1C) | HPRT_PCDET);
+        }
+        detected
+    }
+
+    /// The attached device's speed, as detected during the last port reset.
+    pub fn speed(&self) -> PortSpeed {
+        self.speed
+    }
+
+    /// Reset the port, and detect the attached device's speed. Afterwards, the device responds at
+    /// address 0. Sets `HPRT` register, `PRST` field.
+    pub fn reset_port(&mut self, delay: &mut Delay) -> Result<PortSpeed, UsbHostError> {
+        if !self.is_connected() {
+            return Err(UsbHostError::NotConnected);
+        }
+
+        self.pulse_reset(delay);
+
+        // `PSPD`: 0b01 for full speed, 0b10 for low speed.
+        let speed = match (self.read(HPRT) >> 17) & 0b11 {
+            0b10 => PortSpeed::Low,
+            _ => PortSpeed::Full,
+        };
+
+        // The PHY clock must match the device's speed; changing it requires another reset.
+        if speed != self.speed {
+            self.set_frame_timing(speed);
+            self.speed = speed;
+            self.pulse_reset(delay);
+        }
+
+        if self.read(HPRT) & HPRT_PENA == 0 {
+            return Err(UsbHostError::NotConnected);
+        }
+
+        self.max_packet_size0 = 8;
+        Ok(speed)
+    }
+
+    fn pulse_reset(&mut self, delay: &mut Delay) {
+        self.modify_hprt(0, HPRT_PRST);
+        delay.delay_ms(RESET_MS);
+        self.modify_hprt(HPRT_PRST, 0);
+        delay.delay_ms(RESET_RECOVERY_MS);
+
+        // Clear the enable and change flags, without disabling the port.
+        let hprt = self.read(HPRT) & !HPRT_W1C;
+        self.write(HPRT, hprt | HPRT_PENCHNG | HPRT_PCDET);
+    }
+
+    /// Set the PHY clock, and frame interval, for the device's speed. Sets `HCFG` register,
+    /// `FSLSPCS` field, and `HFIR` register.
+    fn set_frame_timing(&mut self, speed: PortSpeed) {
+        // The frame interval is 1ms, in PHY clocks: 48Mhz for full speed, and 6Mhz for low.
+        let (fslspcs, interval) = match speed {
+            PortSpeed::Full => (0b01, 48_000),
+            PortSpeed::Low => (0b10, 6_000),
+        };
+        self.modify(HCFG, 0b11, fslspcs);
+        self.write(HFIR, interval);
+    }
+
+    /// Reset the port, and enumerate the attached device: Read its device descriptor, and assign
+    /// it address `addr`. Select a configuration afterwards with `set_configuration`.
+    pub fn enumerate(
+        &mut self,
+        addr: u8,
+        delay: &mut Delay,
+    ) -> Result<DeviceDescriptor, UsbHostError> {
+        assert!(
+            (1..=127).contains(&addr),
+            "USB device address must be 1 - 127."
+        );
+
+        self.reset_port(delay)?;
+
+        // Read the first 8 bytes only, until we know the control endpoint's packet size.
+        let mut buf = [0; 18];
+        let len = self.get_descriptor(0, DESC_DEVICE, 0, &mut buf[..8])?;
+        if len < 8 {
+            return Err(UsbHostError::BadDescriptor);
+        }
+        self.max_packet_size0 = match buf[7] {
+            8 | 16 | 32 | 64 => buf[7] as u16,
+            _ => return Err(UsbHostError::BadDescriptor),
+        };
+
+        self.set_address(addr)?;
+        delay.delay_ms(SET_ADDRESS_RECOVERY_MS);
+
+        let len = self.get_descriptor(addr, DESC_DEVICE, 0, &mut buf)?;
+        if len < 18 {
+            return Err(UsbHostError::BadDescriptor);
+        }
+
+        Ok(DeviceDescriptor {
+            usb_version: u16::from_le_bytes([buf[2], buf[3]]),
+            class: buf[4],
+            subclass: buf[5],
+            protocol: buf[6],
+            max_packet_size0: buf[7],
+            vendor_id: u16::from_le_bytes([buf[8], buf[9]]),
+            product_id: u16::from_le_bytes([buf[10], buf[11]]),
+            device_version: u16::from_le_bytes([buf[12], buf[13]]),
+            num_configurations: buf[17],
+        })
+    }
+
+    /// Read a descriptor, with a standard `GET_DESCRIPTOR` request. Returns the number of bytes
+    /// read, which is up to `buf.len()`.
+    pub fn get_descriptor(
+        &mut self,
+        addr: u8,
+        desc_type: u8,
+        index: u8,
+        buf: &mut [u8],
+    ) -> Result<usize, UsbHostError> {
+        let setup = SetupPacket {
+            request_type: 0x80, // Device to host, standard, device.
+            request: GET_DESCRIPTOR,
+            value: (desc_type as u16) << 8 | index as u16,
+            index: 0,
+            length: buf.len() as u16,
+        };
+        self.control_in(addr, &setup, buf)
+    }
+
+    /// Read a configuration descriptor, with its interface and endpoint descriptors, into `buf`.
+    /// Returns the number of bytes read. Iterate over its endpoints with `endpoints`.
+    pub fn get_configuration_descriptor(
+        &mut self,
+        addr: u8,
+        index: u8,
+        buf: &mut [u8],
+    ) -> Result<usize, UsbHostError> {
+        let len = self.get_descriptor(addr, DESC_CONFIGURATION, index, buf)?;
+        if len < 9 {
+            return Err(UsbHostError::BadDescriptor);
+        }
+        // `wTotalLength`: If the buffer is too small, we only have part of it.
+        let total = u16::from_le_bytes([buf[2], buf[3]]) as usize;
+        if total > buf.len() {
+            return Err(UsbHostError::Overflow);
+        }
+        Ok(len)
+    }
+
+    /// Assign the device at address 0 a new address, with a `SET_ADDRESS` request. Wait 2ms
+    /// before using it.
+    pub fn set_address(&mut self, addr: u8) -> Result<(), UsbHostError> {
+        let setup = SetupPacket {
+            request_type: 0x00, // Host to device, standard, device.
+            request: SET_ADDRESS,
+            value: addr as u16,
+            index: 0,
+            length: 0,
+        };
+        self.control_out(0, &setup, &[])
+    }
+
+    /// Select a configuration, with a `SET_CONFIGURATION` request. `config` is its
+    /// `bConfigurationValue`, at offset 5 of its descriptor.
+    pub fn set_configuration(&mut self, addr: u8, config: u8) -> Result<(), UsbHostError> {
+        let setup = SetupPacket {
+            request_type: 0x00,
+            request: SET_CONFIGURATION,
+            value: config as u16,
+            index: 0,
+            length: 0,
+        };
+        self.control_out(addr, &setup, &[])
+    }
+
+    /// Run a control transfer with an IN data stage, eg a `GET_DESCRIPTOR`, or a vendor request.
+    /// Returns the number of bytes read.
+    pub fn control_in(
+        &mut self,
+        addr: u8,
+        setup: &SetupPacket,
+        buf: &mut [u8],
+    ) -> Result<usize, UsbHostError> {
+        let (mut ch_out, mut ch_in) = self.control_channels(addr);
+
+        self.setup_stage(&mut ch_out, setup)?;
+
+        let len = (setup.length as usize).min(buf.len());
+        ch_in.toggle = true;
+        let count = self.transfer_in(&mut ch_in, &mut buf[..len], CONTROL_NAK_RETRIES)?;
+
+        // Status stage: A zero-length OUT packet, DATA1.
+        ch_out.toggle = true;
+        self.transfer_out(&mut ch_out, &[], CONTROL_NAK_RETRIES)?;
+
+        Ok(count)
+    }
+
+    /// Run a control transfer with an OUT data stage, or none, eg a `SET_CONFIGURATION`, or a
+    /// vendor request like setting an FTDI adapter's baud rate.
+    pub fn control_out(
+        &mut self,
+        addr: u8,
+        setup: &SetupPacket,
+        data: &[u8],
+    ) -> Result<(), UsbHostError> {
+        let (mut ch_out, mut ch_in) = self.control_channels(addr);
+
+        self.setup_stage(&mut ch_out, setup)?;
+
+        if !data.is_empty() {
+            ch_out.toggle = true;
+            self.transfer_out(&mut ch_out, data, CONTROL_NAK_RETRIES)?;
+        }
+
+        // Status stage: A zero-length IN packet, DATA1.
+        ch_in.toggle = true;
+        self.transfer_in(&mut ch_in, &mut [], CONTROL_NAK_RETRIES)?;
+
+        Ok(())
+    }
+
+    /// The channels used for control transfers to a device.
+    fn control_channels(&self, addr: u8) -> (Channel, Channel) {
+        let ch = Channel {
+            num: CONTROL_OUT_CHANNEL,
+            dev_addr: addr,
+            endpoint: 0,
+            ep_type: EpType::Control,
+            dir_in: false,
+            max_packet: self.max_packet_size0,
+            low_speed: self.speed == PortSpeed::Low,
+            toggle: false,
+        };
+
+        (
+            ch,
+            Channel {
+                num: CONTROL_IN_CHANNEL,
+                dir_in: true,
+                ..ch
+            },
+        )
+    }
+
+    /// Send a setup packet. Devices must accept these, so we retry NAKs.
+    fn setup_stage(&mut self, ch: &mut Channel, setup: &SetupPacket) -> Result<(), UsbHostError> {
+        let packet = setup.to_bytes();
+        for _ in 0..CONTROL_NAK_RETRIES {
+            match self.packet_out(ch, Pid::Setup, &packet) {
+                Err(UsbHostError::Nak) => continue,
+                result => return result,
+            }
+        }
+        Err(UsbHostError::Nak)
+    }
+
+    /// Set up a channel for a device's endpoint, eg a bulk endpoint found with `endpoints`.
+    /// Channels 0 and 1 are used for control transfers. The data toggle starts at DATA0.
+    pub fn open_channel(
+        &self,
+        num: u8,
+        dev_addr: u8,
+        endpoint: u8,
+        ep_type: EpType,
+        dir_in: bool,
+        max_packet: u16,
+    ) -> Channel {
+        assert!(
+            num > CONTROL_IN_CHANNEL && num < R::NUM_CHANNELS,
+            "Invalid channel number; channels 0 and 1 are reserved for control transfers."
+        );
+        assert!(
+            max_packet > 0 && max_packet as usize <= MAX_PACKET,
+            "Maximum packet size must be 1 - 64 bytes."
+        );
+
+        Channel {
+            num,
+            dev_addr,
+            endpoint,
+            ep_type,
+            dir_in,
+            max_packet,
+            low_speed: self.speed == PortSpeed::Low,
+            toggle: false,
+        }
+    }
+
+    /// Read from a bulk (or interrupt) IN endpoint, until `buf` is full, or the device sends a
+    /// short packet. Returns the number of bytes read. Returns `Nak` if the device has no data;
+    /// retry later.
+    pub fn bulk_in(&mut self, ch: &mut Channel, buf: &mut [u8]) -> Result<usize, UsbHostError> {
+        assert!(ch.dir_in, "Bulk IN requires an IN channel.");
+        self.transfer_in(ch, buf, 0)
+    }
+
+    /// Write to a bulk (or interrupt) OUT endpoint. Sends a zero-length packet if `data` is empty.
+    /// Returns `Nak` if the device isn't ready for the first packet; retry later. Packets after
+    /// the first are retried until accepted.
+    pub fn bulk_out(&mut self, ch: &mut Channel, data: &[u8]) -> Result<(), UsbHostError> {
+        assert!(!ch.dir_in, "Bulk OUT requires an OUT channel.");
+
+        let mps = ch.max_packet as usize;
+        let (first, rest) = data.split_at(mps.min(data.len()));
+
+        self.transfer_out(ch, first, 0)?;
+        if !rest.is_empty() {
+            self.transfer_out(ch, rest, CONTROL_NAK_RETRIES)?;
+        }
+        Ok(())
+    }
+
+    /// Send data as packets, toggling DATA0 and DATA1. Each packet is retried up to
+    /// `nak_retries` times if NAKed. Sends a zero-length packet if `data` is empty.
+    fn transfer_out(
+        &mut self,
+        ch: &mut Channel,
+        data: &[u8],
+        nak_retries: u32,
+    ) -> Result<(), UsbHostError> {
+        let mps = ch.max_packet as usize;
+        let mut offset = 0;
+
+        loop {
+            let packet = &data[offset..(offset + mps).min(data.len())];
+            let mut retries = 0;
+
+            loop {
+                let pid = if ch.toggle { Pid::Data1 } else { Pid::Data0 };
+                match self.packet_out(ch, pid, packet) {
+                    Err(UsbHostError::Nak) if retries < nak_retries => retries += 1,
+                    Err(e) => return Err(e),
+                    Ok(()) => break,
+                }
+            }
+
+            ch.toggle = !ch.toggle;
+            offset += packet.len();
+
+            // A full final packet isn't followed by a zero-length one; control data stages are
+            // delimited by `wLength`, and bulk protocols by their own headers.
+            if offset >= data.len() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Receive packets into `buf`, toggling DATA0 and DATA1, until it's full, or a packet is
+    /// short. Each packet is retried up to `nak_retries` times if NAKed. Receives a single
+    /// zero-length packet if `buf` is empty. Returns the number of bytes received.
+    fn transfer_in(
+        &mut self,
+        ch: &mut Channel,
+        buf: &mut [u8],
+        nak_retries: u32,
+    ) -> Result<usize, UsbHostError> {
+        let mps = ch.max_packet as usize;
+        let mut count = 0;
+        let mut packet = [0; MAX_PACKET];
+
+        loop {
+            let mut retries = 0;
+            let len = loop {
+                let pid = if ch.toggle { Pid::Data1 } else { Pid::Data0 };
+                match self.packet_in(ch, pid, &mut packet[..mps]) {
+                    Err(UsbHostError::Nak) if retries < nak_retries => retries += 1,
+                    Err(UsbHostError::Nak) if count > 0 => return Ok(count),
+                    Err(e) => return Err(e),
+                    Ok(len) => break len,
+                }
+            };
+
+            ch.toggle = !ch.toggle;
+
+            if count + len > buf.len() {
+                return Err(UsbHostError::Overflow);
+            }
+            buf[count..count + len].copy_from_slice(&packet[..len]);
+            count += len;
+
+            if len < mps || count == buf.len() {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Send one packet. See F4 RM, section 35.17.4: "Host programming model", "Bulk and control
+    /// OUT/SETUP transactions".
+    fn packet_out(&mut self, ch: &Channel, pid: Pid, data: &[u8]) -> Result<(), UsbHostError> {
+        self.check_connected()?;
+        let words = (data.len() + 3) / 4;
+
+        // "The application must check that ... the TxFIFO has space", and the request queue.
+        self.wait_for(|s| {
+            let sts = s.read(HNPTXSTS);
+            (sts & 0xffff) as usize >= words && (sts >> 16) & 0xff > 0
+        })?;
+
+        self.start_channel(ch, pid, data.len(), 1);
+
+        let fifo = self.fifo(ch.num);
+        for chunk in data.chunks(4) {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            unsafe { fifo.write_volatile(u32::from_le_bytes(word)) };
+        }
+
+        self.finish_channel(ch.num)
+    }
+
+    /// Receive one packet, of up to `buf.len()` bytes. Returns the number of bytes received. See
+    /// F4 RM, section 35.17.4: "Host programming model", "Bulk and control IN transactions".
+    fn packet_in(&mut self, ch: &Channel, pid: Pid, buf: &mut [u8]) -> Result<usize, UsbHostError> {
+        self.check_connected()?;
+
+        // The core requests a full packet, even if we expect less.
+        self.start_channel(ch, pid, ch.max_packet as usize, 1);
+
+        let mut count = 0;
+        for _ in 0..CORE_TIMEOUT {
+            // Data arrives in the receive FIFO; pop it, or the transfer can't complete.
+            if self.read(GINTSTS) & GINTSTS_RXFLVL != 0 {
+                count += self.pop_rx_fifo(ch.num, &mut buf[count..])?;
+            }
+
+            let hcint = self.read(hc(ch.num, HCINT));
+            if hcint & HCINT_XFRC != 0 {
+                self.halt(ch.num)?;
+                return Ok(count);
+            }
+            // Any error, or a NAK.
+            if hcint & !(HCINT_CHH | HCINT_ACK) != 0 {
+                self.halt(ch.num)?;
+                return Err(hcint_error(hcint));
+            }
+        }
+
+        self.halt(ch.num).ok();
+        Err(UsbHostError::Timeout)
+    }
+
+    /// Program and enable a channel. Sets `HCTSIZx`, and `HCCHARx` registers.
+    fn start_channel(&mut self, ch: &Channel, pid: Pid, len: usize, packets: u32) {
+        self.write(hc(ch.num, HCINT), HCINT_ALL);
+        self.write(
+            hc(ch.num, HCTSIZ),
+            (len as u32 & 0x7_ffff) | packets << 19 | (pid as u32) << 29,
+        );
+
+        // `ODDFRM`: For periodic endpoints, transmit in the next frame.
+        let odd_frame = (self.read(HFNUM) & 1 == 0) as u32;
+        self.write(
+            hc(ch.num, HCCHAR),
+            ch.hcchar() | odd_frame << 29 | HCCHAR_CHENA,
+        );
+    }
+
+    /// Wait for an OUT channel's transaction to finish, and halt it.
+    fn finish_channel(&mut self, num: u8) -> Result<(), UsbHostError> {
+        for _ in 0..CORE_TIMEOUT {
+            let hcint = self.read(hc(num, HCINT));
+            if hcint & HCINT_XFRC != 0 {
+                self.halt(num)?;
+                return Ok(());
+            }
+            if hcint & !(HCINT_CHH | HCINT_ACK) != 0 {
+                self.halt(num)?;
+                return Err(hcint_error(hcint));
+            }
+        }
+
+        self.halt(num).ok();
+        Err(UsbHostError::Timeout)
+    }
+
+    /// Pop a receive FIFO entry. If it's an IN data packet for channel `num`, copy its data to
+    /// `buf`; otherwise discard it. Returns the number of bytes copied. Reads `GRXSTSP` register.
+    fn pop_rx_fifo(&mut self, num: u8, buf: &mut [u8]) -> Result<usize, UsbHostError> {
+        let status = self.read(GRXSTSP);
+        let chnum = (status & 0xf) as u8;
+        let bcnt = ((status >> 4) & 0x7ff) as usize;
+        let pktsts = (status >> 17) & 0xf;
+
+        let fifo = self.fifo(0);
+        let for_us = pktsts == PKTSTS_IN_DATA && chnum == num;
+
+        let mut copied = 0;
+        for i in 0..(bcnt + 3) / 4 {
+            let word = unsafe { fifo.read_volatile() }.to_le_bytes();
+            if for_us {
+                for byte in word.iter().take(bcnt - i * 4) {
+                    if copied == buf.len() {
+                        // Keep draining the FIFO; the transfer fails with this anyway.
+                        break;
+                    }
+                    buf[copied] = *byte;
+                    copied += 1;
+                }
+            }
+        }
+
+        if for_us && copied < bcnt {
+            return Err(UsbHostError::Overflow);
+        }
+        Ok(copied)
+    }
+
+    /// Halt a channel, if it's enabled, and wait for it to stop. Pops receive FIFO entries while
+    /// waiting, since an IN channel halting writes one. Sets `HCCHARx` register, `CHDIS` field.
+    fn halt(&mut self, num: u8) -> Result<(), UsbHostError> {
+        let hcchar = self.read(hc(num, HCCHAR));
+        if hcchar & HCCHAR_CHENA != 0 {
+            self.write(hc(num, HCCHAR), hcchar | HCCHAR_CHDIS | HCCHAR_CHENA);
+
+            let mut halted = false;
+            for _ in 0..CORE_TIMEOUT {
+                if self.read(GINTSTS) & GINTSTS_RXFLVL != 0 {
+                    self.pop_rx_fifo(num, &mut []).ok();
+                }
+                if self.read(hc(num, HCINT)) & HCINT_CHH != 0 {
+                    halted = true;
+                    break;
+                }
+            }
+            if !halted {
+                return Err(UsbHostError::Timeout);
+            }
+        }
+
+        self.write(hc(num, HCINT), HCINT_ALL);
+        Ok(())
+    }
+
+    fn check_connected(&self) -> Result<(), UsbHostError> {
+        let hprt = self.read(HPRT);
+        if hprt & HPRT_PCSTS == 0 || hprt & HPRT_PENA == 0 {
+            Err(UsbHostError::NotConnected)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Modify `HPRT`, without writing 1 to its clear-on-write bits.
+    fn modify_hprt(&mut self, clear: u32, set: u32) {
+        let hprt = self.read(HPRT) & !HPRT_W1C;
+        self.write(HPRT, (hprt & !clear) | set);
+    }
+
+    /// Poll until `f` returns `true`.
+    fn wait_for(&self, f: impl Fn(&Self) -> bool) -> Result<(), UsbHostError> {
+        for _ in 0..CORE_TIMEOUT {
+            if f(self) {
+                return Ok(());
+            }
+        }
+        Err(UsbHostError::Timeout)
+    }
+
+    /// Wait for the bits in `mask` to be all set, or all clear. Panics on timeout, since this is
+    /// only used during initialization; the core not responding means it's not clocked.
+    fn wait(&self, offset: usize, mask: u32, set: bool) {
+        self.wait_for(|s| (s.read(offset) & mask == mask) == set)
+            .expect("USB core not responding. Is its clock enabled?");
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (R::base() + offset) as *mut u32
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { self.reg(offset).read_volatile() }
+    }
+
+    fn write(&mut self, offset: usize, val: u32) {
+        unsafe { self.reg(offset).write_volatile(val) }
+    }
+
+    fn modify(&mut self, offset: usize, clear: u32, set: u32) {
+        let val = self.read(offset);
+        self.write(offset, (val & !clear) | set);
+    }
+
+    /// A channel's data FIFO.
+    fn fifo(&self, num: u8) -> *mut u32 {
+        self.reg((num as usize + 1) * FIFO_STRIDE)
+    }
+
+    /// Power the port off, gate the peripheral's clock, and return the peripheral.
+    pub fn free(mut self) -> R {
+        self.set_port_power(false);
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            R::disable_clock(rcc);
+        });
+        self.regs
+    }
+}
+
+/// The offset of a channel's register.
+fn hc(num: u8, reg: usize) -> usize {
+    HC_BASE + num as usize * HC_STRIDE + reg
+}
+
+/// The error flagged in a `HCINTx` register value. Anything else is a transaction error, `TXERR`.
+fn hcint_error(hcint: u32) -> UsbHostError {
+    if hcint & HCINT_STALL != 0 {
+        UsbHostError::Stall
+    } else if hcint & HCINT_NAK != 0 {
+        UsbHostError::Nak
+    } else if hcint & HCINT_BBERR != 0 {
+        UsbHostError::Babble
+    } else if hcint & HCINT_DTERR != 0 {
+        UsbHostError::DataToggle
+    } else if hcint & HCINT_FRMOR != 0 {
+        UsbHostError::FrameOverrun
+    } else {
+        UsbHostError::Transaction
+    }
+}
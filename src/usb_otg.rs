@@ -48,7 +48,7 @@ macro_rules! usb_peripheral {
                 let pwr = unsafe { &*PWR::ptr() };
                 let rcc = unsafe { &*RCC::ptr() };
 
-                cortex_m::interrupt::free(|_| {
+                crate::util::free(|_| {
                     // USB Regulator in BYPASS mode
                     pwr.cr3.modify(|_, w| w.usb33den().set_bit());
 
@@ -150,7 +150,7 @@ unsafe impl UsbPeripheral for USB1_ULPI {
     fn enable() {
         let rcc = unsafe { &*stm32::RCC::ptr() };
 
-        cortex_m::interrupt::free(|_| {
+        crate::util::free(|_| {
             // Enable USB peripheral
             rcc.ahb1enr.modify(|_, w| w.usb1otgen().enabled());
 
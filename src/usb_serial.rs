@@ -0,0 +1,179 @@
+//! A USB serial port (CDC-ACM), for logging and command consoles over USB without an adapter.
+//! `UsbSerial` owns the USB device and `usbd-serial` port, and buffers data in both directions,
+//! so reads and writes never block: Service it from the USB interrupt, and read and write from
+//! anywhere. It implements `fmt::Write`, for `write!` and `writeln!`.
+//!
+//! Requires the `usb-serial` feature, and `usb`, `usbotg_fs`, or `usbotg_hs`.
+//!
+//! Example, on G4:
+//!
+//! ```
+//! static mut USB_BUS: Option<UsbBusAllocator<UsbBusType>> = None;
+//! static SERIAL: Mutex<RefCell<Option<UsbSerial<UsbBusType>>>> = Mutex::new(RefCell::new(None));
+//!
+//! unsafe { USB_BUS = Some(UsbBus::new(Peripheral { regs: dp.USB })) };
+//! let serial = UsbSerial::new(unsafe { USB_BUS.as_ref().unwrap() }, UsbVidPid(0x16c0, 0x27dd), "Maker", "Logger", "1");
+//! free(|cs| SERIAL.borrow(cs).replace(Some(serial)));
+//!
+//! #[interrupt]
+//! fn USB_LP() {
+//!     free(|cs| {
+//!         access_global!(SERIAL, serial, cs);
+//!         serial.poll();
+//!     });
+//! }
+//!
+//! // Anywhere:
+//! free(|cs| {
+//!     access_global!(SERIAL, serial, cs);
+//!     writeln!(serial, "Temp: {}", temp).ok();
+//! });
+//! ```
+
+use core::fmt;
+
+use usb_device::{
+    bus::{UsbBus, UsbBusAllocator},
+    device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
+};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+use crate::util::RingBuffer;
+
+/// The USB full-speed bulk endpoint size, in bytes: The most moved to or from the port at once.
+const PACKET_SIZE: usize = 64;
+
+/// A USB serial port, with `N`-byte receive and transmit buffers.
+pub struct UsbSerial<'a, B: UsbBus, const N: usize = 256> {
+    pub device: UsbDevice<'a, B>,
+    pub serial: SerialPort<'a, B>,
+    rx: RingBuffer<N>,
+    tx: RingBuffer<N>,
+    /// Received bytes dropped because the receive buffer was full.
+    rx_dropped: usize,
+}
+
+impl<'a, B: UsbBus, const N: usize> UsbSerial<'a, B, N> {
+    /// Create the USB device, with a single CDC-ACM serial port. The strings are shown by the
+    /// host, eg in Device Manager.
+    pub fn new(
+        alloc: &'a UsbBusAllocator<B>,
+        vid_pid: UsbVidPid,
+        manufacturer: &'a str,
+        product: &'a str,
+        serial_number: &'a str,
+    ) -> Self {
+        // The port must be allocated before the device is built.
+        let serial = SerialPort::new(alloc);
+
+        let device = UsbDeviceBuilder::new(alloc, vid_pid)
+            .manufacturer(manufacturer)
+            .product(product)
+            .serial_number(serial_number)
+            .device_class(USB_CLASS_CDC)
+            .build();
+
+        Self {
+            device,
+            serial,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            rx_dropped: 0,
+        }
+    }
+
+    /// Service the USB device. Call this from the USB interrupt handler; the host polls the
+    /// device every 1ms, and enumeration fails if this isn't called promptly. Moves received bytes
+    /// to the receive buffer, and queued bytes to the port.
+    pub fn poll(&mut self) {
+        if self.device.poll(&mut [&mut self.serial]) {
+            let mut buf = [0; PACKET_SIZE];
+            while let Ok(count) = self.serial.read(&mut buf) {
+                if count == 0 {
+                    break;
+                }
+                for byte in &buf[..count] {
+                    if !self.rx.push(*byte) {
+                        self.rx_dropped += 1;
+                    }
+                }
+            }
+        }
+
+        self.send_queued();
+    }
+
+    /// Move queued bytes to the port, as far as it has room.
+    fn send_queued(&mut self) {
+        if self.device.state() != UsbDeviceState::Configured {
+            return;
+        }
+
+        while self.tx.len > 0 {
+            match self.serial.write(self.tx.front()) {
+                Ok(count) if count > 0 => self.tx.discard(count),
+                _ => break,
+            }
+        }
+
+        // Send a partial packet now, instead of waiting for it to fill. `WouldBlock` means a
+        // packet is still in flight; the next poll retries.
+        let _ = self.serial.flush();
+    }
+
+    /// Move received bytes into `buf`, oldest first. Returns the number of bytes read; 0 if none
+    /// are waiting.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut count = 0;
+        for word in buf.iter_mut() {
+            match self.rx.pop() {
+                Some(b) => *word = b,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Queue bytes to send, and start sending them. Returns the number queued, which is less than
+    /// `data.len()` if the queue is full.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let count = data.iter().take_while(|b| self.tx.push(**b)).count();
+        self.send_queued();
+        count
+    }
+
+    /// The number of received bytes waiting to be read.
+    pub fn rx_len(&self) -> usize {
+        self.rx.len
+    }
+
+    /// The number of queued bytes not yet sent.
+    pub fn tx_len(&self) -> usize {
+        self.tx.len
+    }
+
+    /// The number of received bytes dropped since this was created, because the receive buffer
+    /// was full.
+    pub fn rx_dropped(&self) -> usize {
+        self.rx_dropped
+    }
+
+    /// Returns `true` if the host has configured the device, and a terminal has the port open.
+    /// (Most terminals set DTR on opening the port.) Eg, skip logging until this is set, since
+    /// the transmit queue fills otherwise.
+    pub fn is_connected(&self) -> bool {
+        self.device.state() == UsbDeviceState::Configured && self.serial.dtr()
+    }
+}
+
+impl<'a, B: UsbBus, const N: usize> fmt::Write for UsbSerial<'a, B, N> {
+    /// Queues the string. Returns an error if the queue doesn't have room for all of it.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.write(s.as_bytes()) == s.len() {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
@@ -1,6 +1,6 @@
 //! This is an internal module that contains utility functionality used by other modules.
 
-use core::ops::Deref;
+use core::{fmt, ops::Deref};
 
 use crate::{
     clocks::Clocks,
@@ -54,6 +54,19 @@ use crate::pac::dma as dma_p;
 ))]
 use crate::pac::dma1 as dma_p;
 
+cfg_if::cfg_if! {
+    if #[cfg(feature = "critical-section")] {
+        /// Run a closure in a critical section, using the `critical-section` crate. This allows the
+        /// application to select the implementation, eg one that's safe on multi-core H7 and WB, instead
+        /// of globally disabling interrupts.
+        pub(crate) fn free<T>(f: impl FnOnce(critical_section::CriticalSection) -> T) -> T {
+            critical_section::with(f)
+        }
+    } else {
+        pub(crate) use cortex_m::interrupt::free;
+    }
+}
+
 // todo: Unable to import `paste` and `cfgif` macros directly
 
 /// Enables and resets peripheral clocks on various RCC registesr.
@@ -143,15 +156,78 @@ macro_rules! rcc_en_reset {
     };
 }
 
-// todo: This trait is currently a one-off for usart
+/// Disables (gates) peripheral clocks on various RCC registers. Uses the same syntax as
+/// `rcc_en_reset!`: The first argument is a `apb1`, `ahb2` etc to specify the reg block. The second
+/// is something like `tim1`, and the third is a `pac::RCC`.
+#[macro_export]
+macro_rules! rcc_disable {
+    (apb1, $periph:expr, $rcc:expr) => {
+        paste::paste! { cfg_if::cfg_if! {
+            if #[cfg(any(feature = "f3", feature = "f4"))] {
+                $rcc.apb1enr.modify(|_, w| w.[<$periph en>]().clear_bit());
+            } else if #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wb", feature = "wl"))] {
+                $rcc.apb1enr1.modify(|_, w| w.[<$periph en>]().clear_bit());
+            } else if #[cfg(feature = "g0")] {
+                $rcc.apbenr1.modify(|_, w| w.[<$periph en>]().clear_bit());
+            } else {  // H7
+                $rcc.apb1lenr.modify(|_, w| w.[<$periph en>]().clear_bit());
+            }
+        }}
+    };
+    (apb2, $periph:expr, $rcc:expr) => {
+        paste::paste! { cfg_if::cfg_if! {
+            if #[cfg(feature = "g0")] {
+                $rcc.apbenr2.modify(|_, w| w.[<$periph en>]().clear_bit());
+            } else {
+                $rcc.apb2enr.modify(|_, w| w.[<$periph en>]().clear_bit());
+            }
+        }}
+    };
+    (apb4, $periph:expr, $rcc:expr) => {
+        paste::paste! {
+            $rcc.apb4enr.modify(|_, w| w.[<$periph en>]().clear_bit());
+        }
+    };
+    (ahb1, $periph:expr, $rcc:expr) => {
+        paste::paste! { cfg_if::cfg_if! {
+            if #[cfg(any(feature = "f3", feature = "g0"))] {
+                $rcc.ahbenr.modify(|_, w| w.[<$periph en>]().clear_bit());
+            } else {
+                $rcc.ahb1enr.modify(|_, w| w.[<$periph en>]().clear_bit());
+            }
+        }}
+    };
+    (ahb2, $periph:expr, $rcc:expr) => {
+        paste::paste! {
+            $rcc.ahb2enr.modify(|_, w| w.[<$periph en>]().clear_bit());
+        }
+    };
+    (ahb3, $periph:expr, $rcc:expr) => {
+        paste::paste! {
+            $rcc.ahb3enr.modify(|_, w| w.[<$periph en>]().clear_bit());
+        }
+    };
+}
+
+/// A communication peripheral's kernel clock, used to set and validate its bitrate.
+// todo: This assumes the kernel clock is the APB clock; take the selectable clocks into account.
 pub trait BaudPeriph {
     fn baud(clock_cfg: &Clocks) -> u32;
+
+    /// The clock's name, for error messages.
+    fn clock_name() -> &'static str {
+        "the kernel clock"
+    }
 }
 
 impl BaudPeriph for pac::USART1 {
     fn baud(clock_cfg: &Clocks) -> u32 {
         clock_cfg.apb2()
     }
+
+    fn clock_name() -> &'static str {
+        "APB2"
+    }
 }
 
 #[cfg(not(any(feature = "wb", feature = "wl")))]
@@ -159,6 +235,10 @@ impl BaudPeriph for pac::USART2 {
     fn baud(clock_cfg: &Clocks) -> u32 {
         clock_cfg.apb1()
     }
+
+    fn clock_name() -> &'static str {
+        "APB1"
+    }
 }
 
 #[cfg(not(any(
@@ -176,6 +256,154 @@ impl BaudPeriph for pac::USART3 {
     fn baud(clock_cfg: &Clocks) -> u32 {
         clock_cfg.apb1()
     }
+
+    fn clock_name() -> &'static str {
+        "APB1"
+    }
+}
+
+// todo: On H7, SPI1 - 3 use PLL1Q by default, and SPI4 - 6 use their APB clocks.
+#[cfg(not(any(feature = "f301", feature = "h7")))]
+impl BaudPeriph for pac::SPI1 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.apb2()
+    }
+
+    fn clock_name() -> &'static str {
+        "APB2"
+    }
+}
+
+#[cfg(not(any(feature = "f3x4", feature = "h7", feature = "wb", feature = "wl")))]
+impl BaudPeriph for pac::SPI2 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.apb1()
+    }
+
+    fn clock_name() -> &'static str {
+        "APB1"
+    }
+}
+
+#[cfg(not(any(
+    feature = "f3x4",
+    feature = "f410",
+    feature = "g0",
+    feature = "h7",
+    feature = "wb",
+    feature = "wl"
+)))]
+impl BaudPeriph for pac::SPI3 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.apb1()
+    }
+
+    fn clock_name() -> &'static str {
+        "APB1"
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A requested bitrate that a communication peripheral can't produce, from its kernel clock and
+/// pin settings. Frequencies are in Hz.
+pub enum BitrateError {
+    /// The bitrate is too high for the kernel clock; or for I2C, the kernel clock is too slow for
+    /// the speed mode's timing.
+    AboveClock {
+        periph: &'static str,
+        requested: u32,
+        clock: &'static str,
+        clock_freq: u32,
+    },
+    /// The bitrate is too low for the kernel clock: Its divider doesn't go high enough.
+    BelowClock {
+        periph: &'static str,
+        requested: u32,
+        clock: &'static str,
+        clock_freq: u32,
+    },
+    /// The bitrate is above the maximum for the pins' output speed setting.
+    PinSpeed {
+        periph: &'static str,
+        requested: u32,
+        max: u32,
+    },
+    /// The nearest bitrate the divider can produce is too far from the one requested.
+    Inexact {
+        periph: &'static str,
+        requested: u32,
+        actual: u32,
+    },
+}
+
+impl fmt::Display for BitrateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::AboveClock {
+                periph,
+                requested,
+                clock,
+                clock_freq,
+            } => write!(
+                f,
+                "requested {} {}, but {} is {}",
+                Freq(requested),
+                periph,
+                clock,
+                Freq(clock_freq)
+            ),
+            Self::BelowClock {
+                periph,
+                requested,
+                clock,
+                clock_freq,
+            } => write!(
+                f,
+                "requested {} {}, below the minimum with {} at {}",
+                Freq(requested),
+                periph,
+                clock,
+                Freq(clock_freq)
+            ),
+            Self::PinSpeed {
+                periph,
+                requested,
+                max,
+            } => write!(
+                f,
+                "requested {} {}, but the pins' output speed supports up to {}",
+                Freq(requested),
+                periph,
+                Freq(max)
+            ),
+            Self::Inexact {
+                periph,
+                requested,
+                actual,
+            } => write!(
+                f,
+                "requested {} {}, but the nearest available is {}",
+                Freq(requested),
+                periph,
+                Freq(actual)
+            ),
+        }
+    }
+}
+
+/// Formats a frequency in Hz, with a unit prefix if it's a whole number of kHz or MHz.
+struct Freq(u32);
+
+impl fmt::Display for Freq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 >= 1_000_000 && self.0 % 1_000_000 == 0 {
+            write!(f, "{}MHz", self.0 / 1_000_000)
+        } else if self.0 >= 1_000 && self.0 % 1_000 == 0 {
+            write!(f, "{}kHz", self.0 / 1_000)
+        } else {
+            write!(f, "{}Hz", self.0)
+        }
+    }
 }
 
 // todo: This trait is currently a one-off for adc, and isn't currently used.
@@ -225,6 +453,8 @@ impl VrefPeriph for pac::ADC5 {
 
 pub trait RccPeriph {
     fn en_reset(rcc: &RegisterBlock);
+    /// Gate the peripheral's clock. Used when freeing a peripheral.
+    fn disable_clock(rcc: &RegisterBlock);
 }
 
 #[cfg(not(any(
@@ -242,6 +472,10 @@ impl RccPeriph for pac::TIM6 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb1, tim6, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb1, tim6, rcc);
+    }
 }
 
 #[cfg(not(any(
@@ -261,12 +495,20 @@ impl RccPeriph for pac::TIM7 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb1, tim7, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb1, tim7, rcc);
+    }
 }
 
 impl RccPeriph for pac::I2C1 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb1, i2c1, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb1, i2c1, rcc);
+    }
 }
 
 #[cfg(not(any(feature = "wb", feature = "f3x4")))]
@@ -274,6 +516,10 @@ impl RccPeriph for pac::I2C2 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb1, i2c2, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb1, i2c2, rcc);
+    }
 }
 
 #[cfg(any(feature = "h7", feature = "wb"))]
@@ -281,6 +527,10 @@ impl RccPeriph for pac::I2C3 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb1, i2c3, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb1, i2c3, rcc);
+    }
 }
 
 #[cfg(not(feature = "f301"))] // todo: Not sure what's going on  here.
@@ -288,6 +538,10 @@ impl RccPeriph for pac::SPI1 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb2, spi1, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb2, spi1, rcc);
+    }
 }
 
 #[cfg(not(any(feature = "f3x4", feature = "wb", feature = "wl")))]
@@ -295,6 +549,10 @@ impl RccPeriph for pac::SPI2 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb1, spi2, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb1, spi2, rcc);
+    }
 }
 
 #[cfg(not(any(
@@ -317,6 +575,16 @@ impl RccPeriph for pac::SPI3 {
             }
         }
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "l5")] {
+                rcc.apb1enr1.modify(|_, w| w.sp3en().clear_bit());
+            } else {
+                rcc_disable!(apb1, spi3, rcc);
+            }
+        }
+    }
 }
 
 #[cfg(feature = "h7")]
@@ -333,6 +601,16 @@ impl RccPeriph for pac::SPI4 {
             }
         }
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "l5")] {
+                rcc.apb2enr1.modify(|_, w| w.sp4en().clear_bit());
+            } else {
+                rcc_disable!(apb2, spi4, rcc);
+            }
+        }
+    }
 }
 
 #[cfg(not(any(
@@ -347,6 +625,10 @@ impl RccPeriph for pac::SAI1 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb2, sai1, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb2, sai1, rcc);
+    }
 }
 
 #[cfg(feature = "h7")]
@@ -354,26 +636,93 @@ impl RccPeriph for pac::SAI2 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb2, sai2, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb2, sai2, rcc);
+    }
 }
 
-#[cfg(feature = "h7")]
+#[cfg(all(feature = "h7", not(feature = "h7b3")))]
 impl RccPeriph for pac::SAI3 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb2, sai3, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb2, sai3, rcc);
+    }
 }
 
-#[cfg(feature = "h7")]
+#[cfg(all(feature = "h7", not(feature = "h7b3")))]
 impl RccPeriph for pac::SAI4 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb4, sai4, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb4, sai4, rcc);
+    }
+}
+
+#[cfg(all(feature = "f4", not(feature = "f410")))]
+impl RccPeriph for pac::OTG_FS_GLOBAL {
+    fn en_reset(rcc: &RegisterBlock) {
+        rcc_en_reset!(ahb2, otgfs, rcc);
+    }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(ahb2, otgfs, rcc);
+    }
+}
+
+#[cfg(any(
+    feature = "f405",
+    feature = "f407",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469",
+))]
+impl RccPeriph for pac::OTG_HS_GLOBAL {
+    fn en_reset(rcc: &RegisterBlock) {
+        rcc_en_reset!(ahb1, otghs, rcc);
+    }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(ahb1, otghs, rcc);
+    }
+}
+
+#[cfg(feature = "h7")]
+impl RccPeriph for pac::OTG1_HS_GLOBAL {
+    fn en_reset(rcc: &RegisterBlock) {
+        rcc_en_reset!(ahb1, usb1otg, rcc);
+    }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(ahb1, usb1otg, rcc);
+    }
+}
+
+#[cfg(all(feature = "h7", not(feature = "h7b3")))]
+impl RccPeriph for pac::OTG2_HS_GLOBAL {
+    fn en_reset(rcc: &RegisterBlock) {
+        rcc_en_reset!(ahb1, usb2otg, rcc);
+    }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(ahb1, usb2otg, rcc);
+    }
 }
 
 impl RccPeriph for pac::USART1 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb2, usart1, rcc);
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        rcc_disable!(apb2, usart1, rcc);
+    }
 }
 
 #[cfg(not(any(feature = "wb", feature = "wl")))]
@@ -390,6 +739,16 @@ impl RccPeriph for pac::USART2 {
             }
         }
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        cfg_if::cfg_if! {
+            if #[cfg(not(feature = "f4"))] {
+                rcc_disable!(apb1, usart2, rcc);
+            } else {
+                rcc.apb1enr.modify(|_, w| w.usart2en().clear_bit());
+            }
+        }
+    }
 }
 
 #[cfg(not(any(
@@ -415,6 +774,16 @@ impl RccPeriph for pac::USART3 {
             }
         }
     }
+
+    fn disable_clock(rcc: &RegisterBlock) {
+        cfg_if::cfg_if! {
+            if #[cfg(not(feature = "f4"))] {
+                rcc_disable!(apb1, usart3, rcc);
+            } else {
+                rcc.apb1enr.modify(|_, w| w.usart3en().clear_bit());
+            }
+        }
+    }
 }
 
 // todo: USART 4 and 5.
@@ -432,12 +801,20 @@ cfg_if::cfg_if! {
             fn en_reset(rcc: &RegisterBlock) {
                 rcc_en_reset!(apb1, dac12, rcc);
             }
+
+            fn disable_clock(rcc: &RegisterBlock) {
+                rcc_disable!(apb1, dac12, rcc);
+            }
         }
     } else if #[cfg(feature = "f3")] {
         impl RccPeriph for DAC1 {
             fn en_reset(rcc: &RegisterBlock) {
                 rcc_en_reset!(apb1, dac1, rcc);
             }
+
+            fn disable_clock(rcc: &RegisterBlock) {
+                rcc_disable!(apb1, dac1, rcc);
+            }
         }
 
         #[cfg(any(feature = "f303", feature = "f373", feature = "f3x4"))]
@@ -445,30 +822,50 @@ cfg_if::cfg_if! {
             fn en_reset(rcc: &RegisterBlock) {
                 rcc_en_reset!(apb1, dac2, rcc);
             }
+
+            fn disable_clock(rcc: &RegisterBlock) {
+                rcc_disable!(apb1, dac2, rcc);
+            }
         }
     } else if #[cfg(feature = "g4")] {
         impl RccPeriph for pac::DAC1 {
             fn en_reset(rcc: &RegisterBlock) {
                 rcc_en_reset!(ahb2, dac1, rcc);
             }
+
+            fn disable_clock(rcc: &RegisterBlock) {
+                rcc_disable!(ahb2, dac1, rcc);
+            }
         }
 
         impl RccPeriph for pac::DAC2 {
             fn en_reset(rcc: &RegisterBlock) {
                 rcc_en_reset!(ahb2, dac2, rcc);
             }
+
+            fn disable_clock(rcc: &RegisterBlock) {
+                rcc_disable!(ahb2, dac2, rcc);
+            }
         }
 
         impl RccPeriph for pac::DAC3 {
             fn en_reset(rcc: &RegisterBlock) {
                 rcc_en_reset!(ahb2, dac3, rcc);
             }
+
+            fn disable_clock(rcc: &RegisterBlock) {
+                rcc_disable!(ahb2, dac3, rcc);
+            }
         }
 
         impl RccPeriph for pac::DAC4 {
             fn en_reset(rcc: &RegisterBlock) {
                 rcc_en_reset!(ahb2, dac4, rcc);
             }
+
+            fn disable_clock(rcc: &RegisterBlock) {
+                rcc_disable!(ahb2, dac4, rcc);
+            }
         }
     } else if #[cfg(feature = "f4")] {
         // F4 only uses 1 enable, despite having 2 devices. (each with 1 channel)
@@ -476,6 +873,10 @@ cfg_if::cfg_if! {
             fn en_reset(rcc: &RegisterBlock) {
                 rcc_en_reset!(apb1, dac, rcc);
             }
+
+            fn disable_clock(rcc: &RegisterBlock) {
+                rcc_disable!(apb1, dac, rcc);
+            }
         }
     } else {
         impl RccPeriph for DAC1 {
@@ -485,6 +886,13 @@ cfg_if::cfg_if! {
                 #[cfg(not(feature = "wl"))]
                 rcc_en_reset!(apb1, dac1, rcc);
             }
+
+            fn disable_clock(rcc: &RegisterBlock) {
+                #[cfg(feature = "wl")]
+                rcc.apb1enr1.modify(|_, w| w.dac1en().clear_bit());
+                #[cfg(not(feature = "wl"))]
+                rcc_disable!(apb1, dac1, rcc);
+            }
         }
     }
 }
@@ -746,3 +1154,62 @@ impl DmaPeriph for pac::ADC2 {
 }
 
 // L4 and F3 only have DMA on ADC 1 and 2.
+
+/// A fixed-capacity queue of bytes, used by `SpiSlaveService` and `UsbSerial`.
+pub(crate) struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    /// The index of the oldest byte.
+    head: usize,
+    pub len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Add a byte to the end of the queue. Returns `false` if the queue is full.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    /// Remove the oldest byte from the queue.
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    #[cfg(feature = "usb-serial")]
+    /// The oldest bytes in the queue, up to where the buffer wraps around.
+    pub fn front(&self) -> &[u8] {
+        let end = (self.head + self.len).min(N);
+        &self.buf[self.head..end]
+    }
+
+    #[cfg(feature = "usb-serial")]
+    /// Remove the oldest `count` bytes from the queue.
+    pub fn discard(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.head = (self.head + count) % N;
+        self.len -= count;
+    }
+}
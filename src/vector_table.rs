@@ -0,0 +1,139 @@
+//! Relocate the vector table to RAM, so interrupt handlers can be changed at runtime, or fetched
+//! from faster memory than flash. Also set the Vector Table Offset Register (VTOR) directly, eg
+//! from a bootloader, before jumping to an application stored elsewhere in flash.
+//!
+//! The table has 16 entries for the initial stack pointer and the system exceptions, followed by
+//! one for each interrupt the MCU supports. Example, for an MCU with 102 interrupts:
+//!
+//! `static mut VECTORS: RamVectorTable<{ 16 + 102 }> = RamVectorTable::new();`
+//! `unsafe { VECTORS.relocate() };`
+//! `unsafe { VECTORS.set_handler(pac::Interrupt::USART1, usart1_isr) };`
+//!
+//! To place the table in CCM SRAM (F3, G4), or DTCM (H7), add a `#[link_section]` attribute to the
+//! static, with a matching section in your linker script.
+
+use cortex_m::{
+    asm::{dsb, isb},
+    interrupt::InterruptNumber,
+    peripheral::{scb::Exception, SCB},
+};
+
+use crate::util::free;
+
+/// The number of entries before the first interrupt: The initial stack pointer, and the system
+/// exception handlers.
+pub const NUM_SYSTEM_ENTRIES: usize = 16;
+
+/// The alignment the vector table must have: The table size, rounded up to the next power of
+/// two, and at least 128 bytes. (PM0214, section 4.4.4: Vector table offset register)
+pub const fn required_alignment(num_entries: usize) -> usize {
+    let size = (num_entries * 4).next_power_of_two();
+    if size < 128 {
+        128
+    } else {
+        size
+    }
+}
+
+/// Read the address of the active vector table. Reads the `VTOR` register.
+pub fn vtor() -> u32 {
+    unsafe { (*SCB::PTR).vtor.read() }
+}
+
+/// Set the address of the vector table. Panics if `addr` isn't aligned as required for a table
+/// of `num_entries` entries. Sets the `VTOR` register.
+///
+/// # Safety
+/// `addr` must point to a valid vector table, with a handler for each interrupt that may fire.
+pub unsafe fn set_vtor(addr: u32, num_entries: usize) {
+    assert!(
+        addr as usize % required_alignment(num_entries) == 0,
+        "The vector table isn't aligned to its size."
+    );
+
+    free(|_| {
+        // Make sure any writes to the table complete before the core fetches from it.
+        dsb();
+        (*SCB::PTR).vtor.write(addr);
+        dsb();
+        isb();
+    });
+}
+
+/// A vector table in RAM, with `N` entries, including the 16 system entries. The 1024-byte
+/// alignment supports tables of up to 256 entries, which covers every STM32 this HAL supports.
+#[repr(C, align(1024))]
+pub struct RamVectorTable<const N: usize> {
+    entries: [usize; N],
+}
+
+impl<const N: usize> RamVectorTable<N> {
+    /// Create an empty table. Call `relocate` to fill and activate it.
+    pub const fn new() -> Self {
+        Self { entries: [0; N] }
+    }
+
+    /// Copy the active vector table (usually the one in flash) to this table, then point `VTOR`
+    /// at it. Interrupts are disabled during the switch.
+    ///
+    /// # Safety
+    /// The active table must have at least `N` entries.
+    pub unsafe fn relocate(&'static mut self) {
+        assert!(
+            N > NUM_SYSTEM_ENTRIES && N * 4 <= 1_024,
+            "The vector table must have between 17 and 256 entries."
+        );
+
+        free(|_| {
+            let src = vtor() as *const usize;
+            for (i, entry) in self.entries.iter_mut().enumerate() {
+                core::ptr::write_volatile(entry, core::ptr::read_volatile(src.add(i)));
+            }
+
+            set_vtor(self.entries.as_ptr() as u32, N);
+        });
+    }
+
+    /// Set the handler for an interrupt. Takes effect immediately if this table is active.
+    ///
+    /// # Safety
+    /// `handler` replaces any handler defined with `#[interrupt]`, or by `bind_interrupts!`;
+    /// it must do any housekeeping those would have.
+    pub unsafe fn set_handler<I: InterruptNumber>(
+        &mut self,
+        interrupt: I,
+        handler: unsafe extern "C" fn(),
+    ) {
+        self.set_entry(NUM_SYSTEM_ENTRIES + interrupt.number() as usize, handler);
+    }
+
+    /// Set the handler for a system exception, eg `SysTick`.
+    ///
+    /// # Safety
+    /// See `set_handler`.
+    pub unsafe fn set_exception_handler(
+        &mut self,
+        exception: Exception,
+        handler: unsafe extern "C" fn(),
+    ) {
+        self.set_entry(
+            (NUM_SYSTEM_ENTRIES as isize + exception.irqn() as isize) as usize,
+            handler,
+        );
+    }
+
+    unsafe fn set_entry(&mut self, i: usize, handler: unsafe extern "C" fn()) {
+        assert!(
+            i < N,
+            "The vector table doesn't have an entry for this interrupt."
+        );
+
+        core::ptr::write_volatile(&mut self.entries[i], handler as usize);
+        dsb();
+    }
+
+    /// The address of the table, eg for passing to `set_vtor`.
+    pub fn addr(&self) -> u32 {
+        self.entries.as_ptr() as u32
+    }
+}
@@ -0,0 +1,194 @@
+//! Support for WS2812 (NeoPixel) and compatible addressable LEDs. These use a single-wire protocol
+//! at 800kHz, where each bit is a high pulse followed by a low period: Around 0.4μs high for a 0,
+//! and 0.8μs high for a 1. Data is sent as 24 bits per LED, in GRB order. A low period of at least
+//! 280μs latches the data. (Older WS2812 parts only require 50μs.)
+//!
+//! We generate this bitstream using either SPI, or timer PWM with DMA:
+//!
+//! - SPI: Each data bit is encoded as 3 SPI bits on MOSI: `110` for 1, and `100` for 0. The SPI
+//! clock must be 2.1 - 3.1Mhz; use `spi_baud_rate` to find a prescaler. Encode with `encode_spi`,
+//! then send with `Spi::write`, or `Spi::write_dma`.
+//! - Timer: Configure a timer channel for PWM at 800kHz. Encode with `encode_pwm`, which produces
+//! one CCR value per bit, then transfer them to the channel's CCR register with `write_pwm_dma`,
+//! triggered by the timer's update DMA request (`TimerInterrupt::UpdateDma`).
+//!
+//! Both encodings end with a low period that latches the data.
+
+#[cfg(not(any(feature = "f4", feature = "l5", feature = "wl")))]
+use core::ops::Deref;
+
+use crate::spi::BaudRate;
+
+#[cfg(not(any(feature = "f4", feature = "l5", feature = "wl")))]
+use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
+
+#[cfg(feature = "g0")]
+use crate::pac::dma as dma_p;
+#[cfg(any(
+    feature = "f3",
+    feature = "l4",
+    feature = "g4",
+    feature = "h7",
+    feature = "wb"
+))]
+use crate::pac::dma1 as dma_p;
+
+/// Number of SPI bits per data bit.
+const SPI_BITS_PER_BIT: usize = 3;
+
+/// Number of encoded SPI bytes per LED.
+pub const SPI_BYTES_PER_LED: usize = 24 * SPI_BITS_PER_BIT / 8;
+
+/// Number of zero bytes sent after the data using SPI, to latch it. This is 300μs at 2.4Mhz, and
+/// 230μs (Enough for older WS2812 parts, but not WS2812B) at the fastest SPI clock we allow.
+pub const SPI_RESET_BYTES: usize = 90;
+
+/// Number of zero-duty PWM periods sent after the data using a timer, to latch it. This is 300μs.
+pub const PWM_RESET_PERIODS: usize = 240;
+
+/// The lowest and highest SPI clock speeds we can use with 3-bit encoding, in Hz.
+const SPI_FREQ_MIN: u32 = 2_100_000;
+const SPI_FREQ_MAX: u32 = 3_100_000;
+
+/// An LED color.
+#[derive(Clone, Copy, Default)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// The color as sent to the LED: 24 bits, GRB order, MSB first.
+    fn bits(&self) -> u32 {
+        ((self.g as u32) << 16) | ((self.r as u32) << 8) | self.b as u32
+    }
+}
+
+/// The buffer size required to encode `num_leds` LEDs using SPI, in bytes.
+pub const fn spi_buf_len(num_leds: usize) -> usize {
+    num_leds * SPI_BYTES_PER_LED + SPI_RESET_BYTES
+}
+
+/// The buffer size required to encode `num_leds` LEDs using timer PWM, in CCR values.
+pub const fn pwm_buf_len(num_leds: usize) -> usize {
+    num_leds * 24 + PWM_RESET_PERIODS
+}
+
+/// Find the SPI prescaler that results in a clock suitable for WS2812 encoding, given the
+/// SPI peripheral's clock speed (eg `clocks.apb2()` for SPI1), in Hz. Returns `None` if there
+/// isn't one; in that case, adjust the peripheral clock.
+pub fn spi_baud_rate(pclk: u32) -> Option<BaudRate> {
+    let rates = [
+        (2, BaudRate::Div2),
+        (4, BaudRate::Div4),
+        (8, BaudRate::Div8),
+        (16, BaudRate::Div16),
+        (32, BaudRate::Div32),
+        (64, BaudRate::Div64),
+        (128, BaudRate::Div128),
+        (256, BaudRate::Div256),
+    ];
+
+    rates
+        .into_iter()
+        .find(|(div, _)| (SPI_FREQ_MIN..=SPI_FREQ_MAX).contains(&(pclk / div)))
+        .map(|(_, rate)| rate)
+}
+
+/// Encode colors for sending over SPI MOSI, including the trailing latch period. `buf` must be at least
+/// `spi_buf_len(colors.len())` bytes. Returns the number of bytes to send.
+pub fn encode_spi(colors: &[Color], buf: &mut [u8]) -> usize {
+    let len = spi_buf_len(colors.len());
+    assert!(buf.len() >= len, "WS2812 SPI buffer is too small.");
+
+    for (color, out) in colors.iter().zip(buf.chunks_exact_mut(SPI_BYTES_PER_LED)) {
+        // Build the 72-bit pattern 24 bits at a time, since each 8 data bits fill exactly 3 bytes.
+        let bits = color.bits();
+
+        for (i, byte_group) in out.chunks_exact_mut(3).enumerate() {
+            let data = (bits >> (16 - i * 8)) as u8;
+            let mut pattern: u32 = 0;
+
+            for bit in (0..8).rev() {
+                let encoded = if data & (1 << bit) != 0 { 0b110 } else { 0b100 };
+                pattern = (pattern << 3) | encoded;
+            }
+
+            byte_group.copy_from_slice(&pattern.to_be_bytes()[1..]);
+        }
+    }
+
+    for byte in &mut buf[colors.len() * SPI_BYTES_PER_LED..len] {
+        *byte = 0;
+    }
+
+    len
+}
+
+/// Encode colors as CCR values for timer PWM, including the trailing latch period. `max_duty` is
+/// the timer's auto-reload value (`Timer::get_max_duty()`), with the timer set to 800kHz. `buf` must
+/// be at least `pwm_buf_len(colors.len())` values. Returns the number of values to send.
+pub fn encode_pwm(colors: &[Color], max_duty: u16, buf: &mut [u16]) -> usize {
+    let len = pwm_buf_len(colors.len());
+    assert!(buf.len() >= len, "WS2812 PWM buffer is too small.");
+
+    // 0.83μs and 0.42μs high, of a 1.25μs period.
+    let duty_1 = (max_duty as u32 * 2 / 3) as u16;
+    let duty_0 = max_duty / 3;
+
+    for (color, out) in colors.iter().zip(buf.chunks_exact_mut(24)) {
+        let bits = color.bits();
+
+        for (i, val) in out.iter_mut().enumerate() {
+            *val = if bits & (1 << (23 - i)) != 0 {
+                duty_1
+            } else {
+                duty_0
+            };
+        }
+    }
+
+    for val in &mut buf[colors.len() * 24..len] {
+        *val = 0;
+    }
+
+    len
+}
+
+#[cfg(not(any(feature = "f4", feature = "l5", feature = "wl")))]
+/// Transfer PWM-encoded data (from `encode_pwm`) to a timer channel's CCR register, one value
+/// per update event. `ccr_addr` is the register's address, eg `&timer.regs.ccr1 as *const _ as u32`.
+/// Enable the timer's update DMA request, and the timer itself, after calling this.
+///
+/// # Safety
+/// `buf` must remain valid, and unmodified, until the transfer is complete.
+pub unsafe fn write_pwm_dma<D>(
+    buf: &[u16],
+    ccr_addr: u32,
+    channel: DmaChannel,
+    channel_cfg: ChannelCfg,
+    dma: &mut Dma<D>,
+) where
+    D: Deref<Target = dma_p::RegisterBlock>,
+{
+    #[cfg(feature = "h7")]
+    let len = buf.len() as u32;
+    #[cfg(not(feature = "h7"))]
+    let len = buf.len() as u16;
+
+    dma.cfg_channel(
+        channel,
+        ccr_addr,
+        buf.as_ptr() as u32,
+        len,
+        dma::Direction::ReadFromMem,
+        dma::DataSize::S16,
+        dma::DataSize::S16,
+        channel_cfg,
+    );
+}